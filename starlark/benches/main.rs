@@ -67,6 +67,26 @@ def bench():
 bench
 "#;
 
+// Short strings are interned, so each allocation looks its string up first.
+const SHORT_STRINGS: &str = r#"
+def bench():
+    xs = [str(i % 100) for i in range(1000)]
+    ys = {x: len(x) for x in xs}
+    if len(ys) != 100:
+        fail("Wrong answer!")
+
+bench
+"#;
+
+const KWARGS_CALL: &str = r#"
+def rule(name, srcs = [], deps = [], visibility = None, **kwargs):
+    return name
+
+def bench():
+    for i in range(1000):
+        rule(name = "x", srcs = ["x.star"], deps = [], visibility = ["PUBLIC"])
+"#;
+
 pub fn criterion_general_benchmark(c: &mut Criterion, globals: &Globals) {
     c.bench_function("empty", |b| b.iter(|| benchmark_run(globals, EMPTY)));
     c.bench_function("bubble_sort", |b| {
@@ -82,12 +102,33 @@ pub fn criterion_parsing_benchmark(c: &mut Criterion) {
 }
 
 pub fn criterion_eval_benchmark(c: &mut Criterion, globals: &Globals) {
-    c.bench_function("run_tight_loop", |b| {
+    for (name, code) in [
+        ("run_tight_loop", TIGHT_LOOP),
+        ("run_short_strings", SHORT_STRINGS),
+    ] {
+        c.bench_function(name, |b| {
+            let env = Module::new();
+            let mut eval = Evaluator::new(&env);
+            let ast =
+                AstModule::parse("benchmark.sky", code.to_owned(), &Dialect::Standard).unwrap();
+            let bench_function = eval.eval_module(ast, globals).unwrap();
+            b.iter(move || eval.eval_function(bench_function, &[], &[]).unwrap())
+        });
+    }
+    // Calls to functions in a frozen module have their named arguments resolved
+    // to parameters in advance.
+    c.bench_function("run_kwargs_call", |b| {
         let env = Module::new();
         let mut eval = Evaluator::new(&env);
         let ast =
-            AstModule::parse("benchmark.sky", TIGHT_LOOP.to_owned(), &Dialect::Standard).unwrap();
-        let bench_function = eval.eval_module(ast, globals).unwrap();
+            AstModule::parse("benchmark.sky", KWARGS_CALL.to_owned(), &Dialect::Standard).unwrap();
+        eval.eval_module(ast, globals).unwrap();
+        drop(eval);
+        let frozen = env.freeze().unwrap();
+
+        let env = Module::new();
+        let mut eval = Evaluator::new(&env);
+        let bench_function = frozen.get("bench").unwrap().owned_value(env.frozen_heap());
         b.iter(move || eval.eval_function(bench_function, &[], &[]).unwrap())
     });
 }
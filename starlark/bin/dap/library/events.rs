@@ -76,6 +76,15 @@ impl Client {
         })
     }
 
+    pub fn event_breakpoint(&self, body: BreakpointEventBody) {
+        self.event(BreakpointEvent {
+            type_: "event".to_owned(),
+            seq: 0,
+            event: "breakpoint".to_owned(),
+            body,
+        })
+    }
+
     pub fn event_output(&self, body: OutputEventBody) {
         self.event(OutputEvent {
             type_: "event".to_owned(),
@@ -15,7 +15,11 @@
  * limitations under the License.
  */
 
-pub use crate::dap::library::{events::Client, requests::DebugServer, server::DapService};
+pub use crate::dap::library::{
+    events::Client,
+    requests::{DebugServer, ExportedBreakpoint, ExportedBreakpoints},
+    server::DapService,
+};
 
 mod events;
 mod requests;
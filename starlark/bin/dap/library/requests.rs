@@ -19,6 +19,25 @@ use debugserver_types::*;
 use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
 
+/// A breakpoint, as exported by the custom `exportBreakpoints` request.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportedBreakpoint {
+    /// The path of the file.
+    pub source: String,
+    /// The 1-based line.
+    pub line: i64,
+    /// Only stop if this expression is true.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub condition: Option<String>,
+}
+
+/// The response to the custom `exportBreakpoints` request, and the arguments to
+/// `importBreakpoints`, which replaces every breakpoint with these.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ExportedBreakpoints {
+    pub breakpoints: Vec<ExportedBreakpoint>,
+}
+
 pub trait DebugServer {
     fn initialize(&self, x: InitializeRequestArguments) -> anyhow::Result<Option<Capabilities>>;
     fn set_breakpoints(
@@ -37,6 +56,11 @@ pub trait DebugServer {
     fn disconnect(&self, _x: DisconnectArguments) -> anyhow::Result<()> {
         Ok(())
     }
+    fn export_breakpoints(&self) -> anyhow::Result<ExportedBreakpoints>;
+    fn import_breakpoints(
+        &self,
+        x: ExportedBreakpoints,
+    ) -> anyhow::Result<SetBreakpointsResponseBody>;
 }
 
 pub(crate) fn dispatch(server: &impl DebugServer, r: &Request) -> Response {
@@ -84,6 +108,8 @@ pub(crate) fn dispatch(server: &impl DebugServer, r: &Request) -> Response {
         "continue" => ret_some(r, server.continue_(arg(r))),
         "evaluate" => ret_some(r, server.evaluate(arg(r))),
         "disconnect" => ret_none(r, server.disconnect(arg(r))),
+        "exportBreakpoints" => ret_some(r, server.export_breakpoints()),
+        "importBreakpoints" => ret_some(r, server.import_breakpoints(arg(r))),
         _ => ret_none(r, Err(anyhow::anyhow!("Unknown command: {}", r.command))),
     }
 }
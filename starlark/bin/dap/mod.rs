@@ -16,7 +16,7 @@
  */
 
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap},
     path::{Path, PathBuf},
     sync::{
         atomic::{AtomicUsize, Ordering},
@@ -33,11 +33,11 @@ use serde_json::{Map, Value};
 use starlark::{
     codemap::{FileSpan, Span},
     environment::Module,
-    eval::Evaluator,
+    eval::{Evaluator, PathMapping, PrintStream},
     syntax::{AstModule, Dialect},
 };
 
-use crate::eval::{dialect, globals};
+use crate::eval::{dialect, globals, PRINT_CAPACITY};
 
 mod library;
 
@@ -45,12 +45,22 @@ mod library;
 struct Backend {
     client: Client,
     file: Mutex<Option<String>>,
+    // A function in `file` to call, with no arguments, once the file has been evaluated.
+    function: Mutex<Option<String>>,
+    // How source paths map to files on disk, and the names they are reported with.
+    paths: PathMapping,
 
     // These breakpoints must all match statements as per before_stmt.
-    // Those values for which we abort the execution.
-    breakpoints: Arc<Mutex<HashMap<String, HashSet<Span>>>>,
+    // Those values for which we abort the execution, with the condition, if any.
+    breakpoints: Arc<Mutex<HashMap<String, HashMap<Span, Option<String>>>>>,
+    // The breakpoints as the client set them, including those which don't match
+    // a statement, so they can be exported.
+    requested: Mutex<BTreeMap<String, Vec<ExportedBreakpoint>>>,
     // Set while we are doing evaluate calls (>= 1 means disable)
     disable_breakpoints: Arc<AtomicUsize>,
+    // The variables with children we have shown while paused, as a path of names from
+    // the local variable. Reference `LOCALS + 1 + i` refers to element `i`.
+    variables: Mutex<Vec<Vec<String>>>,
 
     sender: Sender<Box<dyn Fn(Span, &mut Evaluator) -> Next + Send>>,
     receiver: Arc<Mutex<Receiver<Box<dyn Fn(Span, &mut Evaluator) -> Next + Send>>>>,
@@ -61,6 +71,37 @@ enum Next {
     RemainPaused,
 }
 
+/// The `variables_reference` of the local variables scope.
+const LOCALS: i64 = 2000;
+
+/// A variable as shown in the debugger: name, summary, type, and whether it has children.
+type VariableInfo = (String, String, String, bool);
+
+/// Find the children of the value at `path`, starting from the local variables,
+/// or the local variables themselves if `path` is empty.
+fn variable_children(path: &[String], eval: &Evaluator) -> Vec<VariableInfo> {
+    let locals = eval.local_variables();
+    let mut children: Vec<(String, starlark::values::Value)> = locals.into_iter().collect();
+    for name in path {
+        match children.into_iter().find(|(k, _)| k == name) {
+            None => return Vec::new(),
+            Some((_, v)) => children = v.debug_children(),
+        }
+    }
+    children.into_map(|(name, value)| {
+        let summary = if path.is_empty() {
+            match eval.value_provenance(value) {
+                None => value.debug_summary(),
+                Some(p) => format!("{}  (allocated at {})", value.debug_summary(), p.location),
+            }
+        } else {
+            value.debug_summary()
+        };
+        let has_children = !value.debug_children().is_empty();
+        (name, summary, value.get_type().to_owned(), has_children)
+    })
+}
+
 impl Backend {
     fn inject<T: 'static + Send>(
         &self,
@@ -85,56 +126,168 @@ impl Backend {
         self.inject(box move |span, eval| (Next::RemainPaused, f(span, eval)))
     }
 
-    fn execute(&self, path: &str) {
+    // Replace the breakpoints in `source`, returning whether each could be set.
+    fn set_source_breakpoints(
+        &self,
+        source: String,
+        breakpoints: Vec<ExportedBreakpoint>,
+    ) -> Vec<Breakpoint> {
+        let res = if breakpoints.is_empty() {
+            self.breakpoints.lock().unwrap().remove(&source);
+            Vec::new()
+        } else {
+            match AstModule::parse_file_mapped(Path::new(&source), &self.paths, &dialect()) {
+                Err(_) => {
+                    self.breakpoints.lock().unwrap().remove(&source);
+                    vec![breakpoint(false); breakpoints.len()]
+                }
+                Ok(ast) => {
+                    let poss: HashMap<usize, Span> = ast
+                        .stmt_locations()
+                        .iter()
+                        .map(|x| {
+                            let span = ast.file_span(*x);
+                            (span.resolve_span().begin_line, *x)
+                        })
+                        .collect();
+                    // Lines are numbered from 1, but imported breakpoints may have any line,
+                    // and those which don't match a statement can't be verified.
+                    let list = breakpoints.map(|x| match usize::try_from(x.line) {
+                        Ok(line) if line >= 1 => poss.get(&(line - 1)),
+                        _ => None,
+                    });
+                    self.breakpoints.lock().unwrap().insert(
+                        source.clone(),
+                        list.iter()
+                            .zip(&breakpoints)
+                            .filter_map(|(span, x)| span.map(|span| (*span, x.condition.clone())))
+                            .collect(),
+                    );
+                    list.map(|x| breakpoint(x.is_some()))
+                }
+            }
+        };
+        let mut requested = self.requested.lock().unwrap();
+        if breakpoints.is_empty() {
+            requested.remove(&source);
+        } else {
+            requested.insert(source, breakpoints);
+        }
+        res
+    }
+
+    fn execute(&self, path: &str, function: Option<String>) {
         let client = self.client.dupe();
         let client2 = self.client.dupe();
         let path = PathBuf::from(path);
         let breakpoints = self.breakpoints.dupe();
         let disable_breakpoints = self.disable_breakpoints.dupe();
         let receiver = self.receiver.dupe();
+        let paths = self.paths.clone();
 
         let go = move || -> anyhow::Result<String> {
             client.log(&format!("EVALUATION PREPARE: {}", path.display()));
-            let ast = AstModule::parse_file(&path, &dialect())?;
+            let ast = AstModule::parse_file_mapped(&path, &paths, &dialect())?;
             let module = Module::new();
             let globals = globals();
-            let mut eval = Evaluator::new(&module);
-            let fun = |span, eval: &mut Evaluator| {
-                let stop = if disable_breakpoints.load(Ordering::SeqCst) > 0 {
-                    false
-                } else {
-                    let breaks = breakpoints.lock().unwrap();
-                    let span_loc = eval.file_span(span);
-                    breaks
-                        .get(span_loc.file.filename())
-                        .map(|set| set.contains(&span))
-                        .unwrap_or_default()
-                };
-                if stop {
-                    client.event_stopped(StoppedEventBody {
-                        reason: "breakpoint".to_owned(),
-                        thread_id: Some(0),
-                        description: Some("Hello".to_owned()),
-                        all_threads_stopped: Some(true),
-                        preserve_focus_hint: None,
-                        text: None,
-                    });
-                    loop {
-                        let msg = receiver.lock().unwrap().recv().unwrap();
-                        match msg(span, eval) {
-                            Next::Continue => break,
-                            Next::RemainPaused => continue,
+            // Show what is printed as it happens, rather than waiting for the result
+            let output = client.dupe();
+            PrintStream::consume(
+                PRINT_CAPACITY,
+                None,
+                move |line| {
+                    output.event_output(OutputEventBody {
+                        output: format!("{}\n", line),
+                        category: Some("stdout".to_owned()),
+                        column: None,
+                        data: None,
+                        line: None,
+                        source: None,
+                        variables_reference: None,
+                    })
+                },
+                |stream| {
+                    let mut eval = Evaluator::new(&module);
+                    eval.set_print_handler(stream);
+                    // So we can show where each variable was allocated
+                    eval.enable_provenance();
+                    let fun = |span, eval: &mut Evaluator| {
+                        let stop = if disable_breakpoints.load(Ordering::SeqCst) > 0 {
+                            false
+                        } else {
+                            let condition = {
+                                let breaks = breakpoints.lock().unwrap();
+                                let span_loc = eval.file_span(span);
+                                breaks
+                                    .get(span_loc.file.filename())
+                                    .and_then(|set| set.get(&span))
+                                    .cloned()
+                            };
+                            match condition {
+                                None => false,
+                                Some(None) => true,
+                                Some(Some(condition)) => {
+                                    disable_breakpoints.fetch_add(1, Ordering::SeqCst);
+                                    let res =
+                                        AstModule::parse("condition", condition.clone(), &dialect())
+                                            .and_then(|ast| eval.eval_expression(ast))
+                                            .map(|v| v.to_bool());
+                                    disable_breakpoints.fetch_sub(1, Ordering::SeqCst);
+                                    match res {
+                                        Ok(stop) => stop,
+                                        Err(e) => {
+                                            // Stop, and say why, so the user can fix the condition
+                                            client.event_output(OutputEventBody {
+                                                output: format!(
+                                                    "Breakpoint condition `{}` failed: {:#}\n",
+                                                    condition, e
+                                                ),
+                                                category: Some("stderr".to_owned()),
+                                                column: None,
+                                                data: None,
+                                                line: None,
+                                                source: None,
+                                                variables_reference: None,
+                                            });
+                                            true
+                                        }
+                                    }
+                                }
+                            }
+                        };
+                        if stop {
+                            client.event_stopped(StoppedEventBody {
+                                reason: "breakpoint".to_owned(),
+                                thread_id: Some(0),
+                                description: Some("Hello".to_owned()),
+                                all_threads_stopped: Some(true),
+                                preserve_focus_hint: None,
+                                text: None,
+                            });
+                            loop {
+                                let msg = receiver.lock().unwrap().recv().unwrap();
+                                match msg(span, eval) {
+                                    Next::Continue => break,
+                                    Next::RemainPaused => continue,
+                                }
+                            }
                         }
+                    };
+                    eval.before_stmt(&fun);
+                    // No way to pass back success/failure to the caller
+                    client.log(&format!("EVALUATION START: {}", path.display()));
+                    let mut v = eval.eval_module(ast, &globals)?;
+                    if let Some(function) = &function {
+                        let f = module.get(function).ok_or_else(|| {
+                            anyhow::anyhow!("No function `{}` in `{}`", function, path.display())
+                        })?;
+                        v = eval.eval_function(f, &[], &[])?;
                     }
-                }
-            };
-            eval.before_stmt(&fun);
-            // No way to pass back success/failure to the caller
-            client.log(&format!("EVALUATION START: {}", path.display()));
-            let v = eval.eval_module(ast, &globals)?;
-            let s = v.to_string();
-            client.log(&format!("EVALUATION FINISHED: {}", path.display()));
-            Ok(s)
+                    let s = v.to_string();
+                    client.log(&format!("EVALUATION FINISHED: {}", path.display()));
+                    Ok(s)
+                },
+            )
         };
 
         thread::spawn(move || {
@@ -178,6 +331,7 @@ impl DebugServer for Backend {
         self.client.event_initialized(None);
         Ok(Some(Capabilities {
             supports_configuration_done_request: Some(true),
+            supports_conditional_breakpoints: Some(true),
             supports_evaluate_for_hovers: Some(true),
             supports_set_variable: Some(true),
             supports_step_in_targets_request: Some(true),
@@ -189,42 +343,15 @@ impl DebugServer for Backend {
         &self,
         x: SetBreakpointsArguments,
     ) -> anyhow::Result<SetBreakpointsResponseBody> {
-        let breakpoints = x.breakpoints.unwrap_or_default();
         let source = x.source.path.unwrap();
-
-        if breakpoints.is_empty() {
-            self.breakpoints.lock().unwrap().remove(&source);
-            Ok(SetBreakpointsResponseBody {
-                breakpoints: Vec::new(),
-            })
-        } else {
-            match AstModule::parse_file(Path::new(&source), &dialect()) {
-                Err(_) => {
-                    self.breakpoints.lock().unwrap().remove(&source);
-                    Ok(SetBreakpointsResponseBody {
-                        breakpoints: vec![breakpoint(false); breakpoints.len()],
-                    })
-                }
-                Ok(ast) => {
-                    let poss: HashMap<usize, Span> = ast
-                        .stmt_locations()
-                        .iter()
-                        .map(|x| {
-                            let span = ast.file_span(*x);
-                            (span.resolve_span().begin_line, *x)
-                        })
-                        .collect();
-                    let list = breakpoints.map(|x| poss.get(&(x.line as usize - 1)));
-                    self.breakpoints
-                        .lock()
-                        .unwrap()
-                        .insert(source, list.iter().filter_map(|x| x.copied()).collect());
-                    Ok(SetBreakpointsResponseBody {
-                        breakpoints: list.map(|x| breakpoint(x.is_some())),
-                    })
-                }
-            }
-        }
+        let breakpoints = x.breakpoints.unwrap_or_default().into_map(|x| ExportedBreakpoint {
+            source: source.clone(),
+            line: x.line,
+            condition: x.condition,
+        });
+        Ok(SetBreakpointsResponseBody {
+            breakpoints: self.set_source_breakpoints(source, breakpoints),
+        })
     }
 
     fn set_exception_breakpoints(&self, _: SetExceptionBreakpointsArguments) -> anyhow::Result<()> {
@@ -237,6 +364,11 @@ impl DebugServer for Backend {
         match args.get("program") {
             Some(Value::String(path)) => {
                 *self.file.lock().unwrap() = Some(path.to_owned());
+                // Optionally, a function to call after evaluating the program
+                *self.function.lock().unwrap() = match args.get("function") {
+                    Some(Value::String(function)) => Some(function.to_owned()),
+                    _ => None,
+                };
                 Ok(())
             }
             _ => Err(anyhow::anyhow!(
@@ -257,7 +389,7 @@ impl DebugServer for Backend {
 
     fn configuration_done(&self) -> anyhow::Result<()> {
         if let Some(path) = self.file.lock().unwrap().as_ref() {
-            self.execute(path);
+            self.execute(path, self.function.lock().unwrap().clone());
         }
         Ok(())
     }
@@ -315,7 +447,7 @@ impl DebugServer for Backend {
                 scopes: vec![Scope {
                     name: "Locals".to_owned(),
                     named_variables: Some(vars.len() as i64),
-                    variables_reference: 2000,
+                    variables_reference: LOCALS,
                     expensive: false,
                     column: None,
                     end_column: None,
@@ -328,28 +460,50 @@ impl DebugServer for Backend {
         })
     }
 
-    fn variables(&self, _: VariablesArguments) -> anyhow::Result<VariablesResponseBody> {
-        self.with_ctx(box |_, eval| {
-            let vars = eval.local_variables();
-            Ok(VariablesResponseBody {
-                variables: vars
-                    .into_iter()
-                    .map(|(name, value)| Variable {
-                        name,
-                        value: value.to_string(),
-                        type_: Some(value.get_type().to_owned()),
-                        evaluate_name: None,
-                        indexed_variables: None,
-                        named_variables: None,
-                        presentation_hint: None,
-                        variables_reference: 0,
-                    })
-                    .collect(),
-            })
+    fn variables(&self, x: VariablesArguments) -> anyhow::Result<VariablesResponseBody> {
+        let path = match x.variables_reference {
+            LOCALS => Vec::new(),
+            i => match self.variables.lock().unwrap().get((i - LOCALS - 1) as usize) {
+                None => {
+                    return Ok(VariablesResponseBody {
+                        variables: Vec::new(),
+                    });
+                }
+                Some(path) => path.clone(),
+            },
+        };
+        let vars = {
+            let path = path.clone();
+            self.with_ctx(box move |_, eval| variable_children(&path, eval))
+        };
+        let mut variables = self.variables.lock().unwrap();
+        Ok(VariablesResponseBody {
+            variables: vars.into_map(|(name, value, typ, has_children)| {
+                let variables_reference = if has_children {
+                    let mut child = path.clone();
+                    child.push(name.clone());
+                    variables.push(child);
+                    LOCALS + variables.len() as i64
+                } else {
+                    0
+                };
+                Variable {
+                    name,
+                    value,
+                    type_: Some(typ),
+                    evaluate_name: None,
+                    indexed_variables: None,
+                    named_variables: None,
+                    presentation_hint: None,
+                    variables_reference,
+                }
+            }),
         })
     }
 
     fn continue_(&self, _: ContinueArguments) -> anyhow::Result<ContinueResponseBody> {
+        // Once we resume, the paths may refer to different values
+        self.variables.lock().unwrap().clear();
         self.inject_continue();
         Ok(ContinueResponseBody::default())
     }
@@ -376,15 +530,72 @@ impl DebugServer for Backend {
             })
         })
     }
+
+    fn export_breakpoints(&self) -> anyhow::Result<ExportedBreakpoints> {
+        Ok(ExportedBreakpoints {
+            breakpoints: self
+                .requested
+                .lock()
+                .unwrap()
+                .values()
+                .flatten()
+                .cloned()
+                .collect(),
+        })
+    }
+
+    fn import_breakpoints(
+        &self,
+        x: ExportedBreakpoints,
+    ) -> anyhow::Result<SetBreakpointsResponseBody> {
+        let mut sources: BTreeMap<String, Vec<ExportedBreakpoint>> = BTreeMap::new();
+        for x in x.breakpoints {
+            sources.entry(x.source.clone()).or_default().push(x);
+        }
+        // Files which had breakpoints, but have none in the import, are cleared
+        let old = self
+            .requested
+            .lock()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        for source in old {
+            sources.entry(source).or_default();
+        }
+
+        let mut res = Vec::new();
+        for (source, breakpoints) in sources {
+            let set = self.set_source_breakpoints(source.clone(), breakpoints.clone());
+            for (x, mut set) in breakpoints.into_iter().zip(set) {
+                // Tell the client, so it shows them as if they'd been set there
+                set.line = Some(x.line);
+                set.source = Some(Source {
+                    path: Some(source.clone()),
+                    ..Source::default()
+                });
+                self.client.event_breakpoint(BreakpointEventBody {
+                    reason: "new".to_owned(),
+                    breakpoint: set.clone(),
+                });
+                res.push(set);
+            }
+        }
+        Ok(SetBreakpointsResponseBody { breakpoints: res })
+    }
 }
 
-pub fn server() {
+pub fn server(paths: PathMapping) {
     let (sender, receiver) = channel();
     DapService::run(|client| Backend {
         client,
         breakpoints: Default::default(),
+        requested: Default::default(),
         disable_breakpoints: Default::default(),
+        variables: Default::default(),
         file: Default::default(),
+        function: Default::default(),
+        paths,
         sender,
         receiver: Arc::new(Mutex::new(receiver)),
     })
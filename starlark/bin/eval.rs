@@ -18,41 +18,85 @@
 use std::{
     fs, iter,
     path::{Path, PathBuf},
+    time::Duration,
 };
 
+use anyhow::anyhow;
 use gazebo::prelude::*;
 use itertools::Either;
 use starlark::{
-    environment::{FrozenModule, Globals, Module},
-    eval::Evaluator,
+    environment::{ExportFormat, FrozenModule, Globals, Module},
+    errors::ErrorKind,
+    eval::{Evaluator, PathMapping, PrintStream},
     syntax::{AstModule, Dialect},
 };
 
-use crate::types::Message;
+use crate::{interrupt, types::Message};
+
+/// The lines printed which can wait to be written before evaluation pauses for them.
+pub const PRINT_CAPACITY: usize = 1024;
+
+/// Limits on the resources an evaluation can use.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Limits {
+    pub max_steps: Option<u64>,
+    pub timeout: Option<Duration>,
+    pub max_heap_bytes: Option<usize>,
+}
+
+impl Limits {
+    /// Apply these limits to `eval`, which evaluates code in `module`.
+    /// Must be called before the code is compiled.
+    pub fn apply<'v>(&self, module: &'v Module, eval: &mut Evaluator<'v, '_>) {
+        if let Some(steps) = self.max_steps {
+            eval.set_max_steps(steps);
+        }
+        if let Some(timeout) = self.timeout {
+            eval.set_timeout(timeout);
+        }
+        if let Some(bytes) = self.max_heap_bytes {
+            module.heap().set_allocation_limit(bytes);
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct Context {
     pub check: bool,
+    pub typecheck: bool,
     pub info: bool,
     pub run: bool,
     pub prelude: Vec<FrozenModule>,
     pub module: Option<Module>,
+    /// Print the exported symbols of each module run, in this format.
+    pub output: Option<ExportFormat>,
+    /// Fail an evaluation which prints more than this many bytes.
+    pub print_limit: Option<usize>,
+    /// Limits on each evaluation.
+    pub limits: Limits,
+    /// How files named on the command line are found, and the names they are reported with.
+    pub paths: PathMapping,
+    /// Write a heap snapshot here after evaluating each file.
+    pub heap_snapshot: Option<PathBuf>,
 }
 
 impl Context {
     pub fn new(
         check: bool,
+        typecheck: bool,
         info: bool,
         run: bool,
         prelude: &[PathBuf],
         module: bool,
+        paths: PathMapping,
     ) -> anyhow::Result<Self> {
         let globals = globals();
         let prelude = prelude.try_map(|x| {
             let env = Module::new();
 
             let mut eval = Evaluator::new(&env);
-            let module = AstModule::parse_file(x, &dialect())?;
+            eval.set_main(false);
+            let module = AstModule::parse_file_mapped(x, &paths, &dialect())?;
             eval.eval_module(module, &globals)?;
             env.freeze()
         })?;
@@ -65,14 +109,20 @@ impl Context {
 
         Ok(Self {
             check,
+            typecheck,
             info,
             run,
             prelude,
             module,
+            output: None,
+            print_limit: None,
+            limits: Limits::default(),
+            paths,
+            heap_snapshot: None,
         })
     }
 
-    fn new_module(prelude: &[FrozenModule]) -> Module {
+    pub fn new_module(prelude: &[FrozenModule]) -> Module {
         let module = Module::new();
         for p in prelude {
             module.import_public_symbols(p);
@@ -115,10 +165,11 @@ impl Context {
     }
 
     pub fn file(&self, file: &Path) -> impl Iterator<Item = Message> {
-        let filename = &file.to_string_lossy();
+        let file = self.paths.disk_path(file);
+        let filename = &self.paths.reported_name(&file);
         Self::err(
             filename,
-            fs::read_to_string(file)
+            fs::read_to_string(&file)
                 .map(|content| self.file_with_contents(filename, content))
                 .map_err(|e| e.into()),
         )
@@ -136,18 +187,64 @@ impl Context {
     }
 
     fn run(&self, file: &str, ast: AstModule) -> impl Iterator<Item = Message> {
-        let new_module;
-        let module = match self.module.as_ref() {
-            Some(module) => module,
-            None => {
-                new_module = Self::new_module(&self.prelude);
-                &new_module
-            }
+        let new_module = match self.module {
+            Some(_) => None,
+            None => Some(Self::new_module(&self.prelude)),
         };
-        let mut eval = Evaluator::new(module);
-        eval.enable_terminal_breakpoint_console();
-        let globals = globals();
-        Self::err(file, eval.eval_module(ast, &globals).map(|_| iter::empty()))
+        let module = self.module.as_ref().or(new_module.as_ref()).unwrap();
+        let res = interrupt::cancellable(|handle| {
+            PrintStream::consume(
+                PRINT_CAPACITY,
+                self.print_limit,
+                |line| eprintln!("{}", line),
+                |stream| -> anyhow::Result<()> {
+                    let mut eval = Evaluator::new(module);
+                    eval.enable_terminal_breakpoint_console();
+                    eval.set_print_handler(stream);
+                    eval.set_cancellation_handle(handle);
+                    self.limits.apply(module, &mut eval);
+                    if self.heap_snapshot.is_some() {
+                        // So the snapshot says where each value was allocated
+                        eval.enable_provenance();
+                    }
+                    let interrupted = |e: starlark::Error| match e.kind() {
+                        ErrorKind::Cancelled => match e.span() {
+                            Some(span) => anyhow!("Interrupted at {}", span),
+                            None => anyhow!("Interrupted"),
+                        },
+                        _ => e.into(),
+                    };
+                    if self.module.is_some() {
+                        // Show the value of an expression typed into the REPL
+                        if let Some(res) = eval
+                            .eval_interactive(ast, &globals())
+                            .map_err(interrupted)?
+                        {
+                            if !res.is_none() {
+                                println!("{}", res.to_repr());
+                            }
+                        }
+                        Ok(())
+                    } else {
+                        eval.eval_module(ast, &globals()).map_err(interrupted)?;
+                        if let Some(path) = &self.heap_snapshot {
+                            // We don't hold any values outside the evaluator
+                            unsafe { eval.heap_snapshot() }?.write(path)?;
+                        }
+                        Ok(())
+                    }
+                },
+            )
+        });
+        // The interactive module is never finished, so can't be exported
+        let res = res.and_then(|()| match (self.output, new_module) {
+            (Some(format), Some(module)) => {
+                println!("{}", module.freeze()?.export(format)?);
+                Ok(())
+            }
+            _ => Ok(()),
+        });
+        Self::err(file, res.map(|()| iter::empty()))
     }
 
     fn info(&self, module: &AstModule) {
@@ -158,7 +255,7 @@ impl Context {
         }
     }
 
-    fn check(&self, module: &AstModule) -> impl Iterator<Item = Message> {
+    pub fn check(&self, module: &AstModule) -> impl Iterator<Item = Message> {
         let mut globals = Vec::new();
         for x in &self.prelude {
             globals.extend(x.names());
@@ -169,7 +266,16 @@ impl Context {
             Some(globals.as_slice())
         };
 
-        module.lint(globals).into_iter().map(Message::from_lint)
+        let types = if self.typecheck {
+            module.typecheck()
+        } else {
+            Vec::new()
+        };
+        module
+            .lint(globals)
+            .into_iter()
+            .map(Message::from_lint)
+            .chain(types.into_iter().map(Message::from_type_lint))
     }
 }
 
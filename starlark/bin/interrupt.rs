@@ -0,0 +1,48 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Stop the evaluation which is running when the user presses Ctrl-C, rather than the process.
+
+use std::{process, sync::Mutex};
+
+use gazebo::prelude::*;
+use once_cell::sync::Lazy;
+use starlark::eval::CancellationHandle;
+
+// Cancels the evaluation running, if there is one.
+static RUNNING: Lazy<Mutex<Option<CancellationHandle>>> = Lazy::new(|| Mutex::new(None));
+
+/// Handle Ctrl-C by cancelling the evaluation running under [`cancellable`], or if there
+/// isn't one exiting, as the default handler would.
+pub fn install() -> anyhow::Result<()> {
+    ctrlc::set_handler(|| match &*RUNNING.lock().unwrap() {
+        Some(handle) => handle.cancel(),
+        // The conventional exit code after SIGINT
+        None => process::exit(130),
+    })?;
+    Ok(())
+}
+
+/// Run `f`, which should give the handle to its [`Evaluator`](starlark::eval::Evaluator), so
+/// pressing Ctrl-C while it runs stops the evaluation.
+pub fn cancellable<R>(f: impl FnOnce(CancellationHandle) -> R) -> R {
+    let handle = CancellationHandle::new();
+    *RUNNING.lock().unwrap() = Some(handle.dupe());
+    let res = f(handle);
+    *RUNNING.lock().unwrap() = None;
+    res
+}
@@ -25,13 +25,19 @@
 // Disagree these are good hints
 #![allow(clippy::type_complexity)]
 
-use std::{ffi::OsStr, fmt, fmt::Display, fs, path::PathBuf, sync::Arc};
+use std::{ffi::OsStr, fmt, fmt::Display, fs, path::PathBuf, sync::Arc, time::Duration};
 
 use anyhow::anyhow;
-use eval::Context;
+use eval::{dialect, globals, Context, Limits};
 use gazebo::prelude::*;
 use itertools::Either;
-use starlark::read_line::ReadLine;
+use starlark::{
+    capabilities::Capabilities,
+    environment::{ExportFormat, LibraryExtension},
+    eval::PathMapping,
+    lsp::{BazelLoadResolver, LspContext},
+    read_line::ReadLine,
+};
 use structopt::{clap::AppSettings, StructOpt};
 use walkdir::WalkDir;
 
@@ -39,7 +45,9 @@ use crate::types::{LintMessage, Message, Severity};
 
 mod dap;
 mod eval;
-mod lsp;
+mod interrupt;
+mod serve;
+mod snapshot;
 mod types;
 
 #[derive(Debug, StructOpt)]
@@ -63,15 +71,69 @@ pub struct Args {
     #[structopt(long = "dap", help = "Start a DAP server.")]
     dap: bool,
 
+    #[structopt(
+        long = "serve",
+        name = "ADDRESS",
+        help = "Serve evaluation requests as JSON over HTTP, at an address such as `:8080`."
+    )]
+    serve: Option<String>,
+
     #[structopt(long = "check", help = "Run checks and lints.")]
     check: bool,
 
+    #[structopt(
+        long = "typecheck",
+        help = "Check literal values against type annotations, with --check or --lsp."
+    )]
+    typecheck: bool,
+
     #[structopt(long = "info", help = "Show information about the code.")]
     info: bool,
 
+    #[structopt(
+        long = "capabilities",
+        help = "Print the language features, extensions and limits supported, as JSON."
+    )]
+    capabilities: bool,
+
     #[structopt(long = "json", help = "Show output as JSON lines.")]
     json: bool,
 
+    #[structopt(
+        long = "output",
+        possible_values = &["json", "toml"],
+        help = "Print the exported symbols of each file evaluated, as JSON or TOML."
+    )]
+    output: Option<String>,
+
+    #[structopt(
+        long = "max-print-bytes",
+        name = "BYTES",
+        help = "Fail an evaluation which prints more than this many bytes."
+    )]
+    max_print_bytes: Option<usize>,
+
+    #[structopt(
+        long = "max-steps",
+        name = "STEPS",
+        help = "Fail an evaluation which executes more than this many statements."
+    )]
+    max_steps: Option<u64>,
+
+    #[structopt(
+        long = "timeout",
+        name = "SECONDS",
+        help = "Fail an evaluation which runs for longer than this many seconds."
+    )]
+    timeout: Option<u64>,
+
+    #[structopt(
+        long = "max-heap-bytes",
+        name = "HEAP_BYTES",
+        help = "Fail an evaluation which allocates more than this many bytes."
+    )]
+    max_heap_bytes: Option<usize>,
+
     #[structopt(
         long = "repeat",
         help = "Number of times to repeat the execution",
@@ -96,6 +158,34 @@ pub struct Args {
     )]
     evaluate: Vec<String>,
 
+    #[structopt(
+        long = "heap-snapshot",
+        name = "SNAPSHOT",
+        help = "Write a snapshot of the live values to this file after evaluating each file."
+    )]
+    heap_snapshot: Option<PathBuf>,
+
+    #[structopt(
+        long = "analyze-heap-snapshot",
+        name = "SNAPSHOT_FILE",
+        help = "Print the values keeping the most memory alive in a heap snapshot."
+    )]
+    analyze_heap_snapshot: Option<PathBuf>,
+
+    #[structopt(
+        long = "cwd",
+        name = "DIR",
+        help = "Resolve relative file paths against this directory."
+    )]
+    cwd: Option<PathBuf>,
+
+    #[structopt(
+        long = "remap-path",
+        name = "FROM=TO",
+        help = "Report files under FROM as being under TO, e.g. a sandbox as the real checkout."
+    )]
+    remap_path: Vec<String>,
+
     #[structopt(name = "FILE", help = "Files to evaluate.")]
     // String instead of PathBuf so we can expand @file things
     files: Vec<String>,
@@ -191,6 +281,20 @@ fn drain(xs: impl Iterator<Item = Message>, json: bool, stats: &mut Stats) {
     }
 }
 
+fn path_mapping(cwd: Option<PathBuf>, remap_path: &[String]) -> anyhow::Result<PathMapping> {
+    let mut paths = PathMapping::new();
+    if let Some(cwd) = cwd {
+        paths.set_working_dir(cwd);
+    }
+    for x in remap_path {
+        match x.split_once('=') {
+            Some((from, to)) => paths.add_rewrite(from, to),
+            None => return Err(anyhow!("Expected `--remap-path FROM=TO`, got `{}`", x)),
+        }
+    }
+    Ok(paths)
+}
+
 fn interactive(ctx: &Context) -> anyhow::Result<()> {
     let mut rl = ReadLine::new();
     loop {
@@ -207,18 +311,42 @@ fn interactive(ctx: &Context) -> anyhow::Result<()> {
 
 fn main() -> anyhow::Result<()> {
     let args = Args::from_args();
+    if args.capabilities {
+        // The globals are extended by all the extensions
+        let capabilities = Capabilities::new(&dialect(), LibraryExtension::all());
+        println!("{}", capabilities.to_json());
+        return Ok(());
+    }
+    if let Some(file) = &args.analyze_heap_snapshot {
+        return snapshot::analyze(file);
+    }
     let ext = args
         .extension
         .as_ref()
         .map_or("bzl", |x| x.as_str())
         .trim_start_match('.');
+    let paths = path_mapping(args.cwd, &args.remap_path)?;
     let mut ctx = Context::new(
         args.check,
+        args.typecheck,
         args.info,
         !args.check && !args.info,
-        &expand_dirs(ext, args.prelude).collect::<Vec<_>>(),
+        &expand_dirs(ext, args.prelude.map(|x| paths.disk_path(x))).collect::<Vec<_>>(),
         args.interactive,
+        paths.clone(),
     )?;
+    ctx.output = args.output.as_deref().map(|x| match x {
+        "toml" => ExportFormat::Toml,
+        _ => ExportFormat::Json,
+    });
+    ctx.print_limit = args.max_print_bytes;
+    ctx.limits = Limits {
+        max_steps: args.max_steps,
+        timeout: args.timeout.map(Duration::from_secs),
+        max_heap_bytes: args.max_heap_bytes,
+    };
+    ctx.heap_snapshot = args.heap_snapshot;
+    interrupt::install()?;
 
     let mut stats = Stats::default();
     for _ in 0..args.repeat {
@@ -227,7 +355,8 @@ fn main() -> anyhow::Result<()> {
             drain(ctx.expression(e), args.json, &mut stats);
         }
 
-        for file in expand_dirs(ext, expand_args(args.files.clone())?) {
+        let files = expand_args(args.files.clone())?.into_map(|x| ctx.paths.disk_path(&x));
+        for file in expand_dirs(ext, files) {
             stats.increment_file();
             drain(ctx.file(&file), args.json, &mut stats);
         }
@@ -238,16 +367,28 @@ fn main() -> anyhow::Result<()> {
     }
 
     if args.lsp {
-        ctx.check = true;
-        ctx.info = false;
-        ctx.run = false;
-        lsp::server(ctx)?;
+        let context = LspContext {
+            // With a prelude, its symbols and the globals are all the names available
+            undefined_names: !ctx.prelude.is_empty(),
+            prelude: ctx.prelude,
+            typecheck: args.typecheck,
+            ..LspContext::new(dialect(), globals())
+        };
+        // Note that we must have our logging only write out to stderr.
+        eprintln!("Starting Rust Starlark server");
+        starlark::lsp::server(context, box BazelLoadResolver)?;
+        eprintln!("Stopping Rust Starlark server");
     } else if args.dap {
-        dap::server()
+        dap::server(paths)
+    } else if let Some(address) = &args.serve {
+        serve::server(&ctx, address)?;
     }
 
     if !args.json {
-        println!("{}", stats);
+        // The output is meant for other programs, so don't add to it
+        if args.output.is_none() {
+            println!("{}", stats);
+        }
         if stats.error > 0 {
             return Err(anyhow!("Failed with {} errors", stats.error));
         }
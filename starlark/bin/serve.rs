@@ -0,0 +1,450 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! A service which evaluates Starlark for other processes, with JSON over HTTP, so programs
+//! not written in Rust can run this implementation alongside them.
+//!
+//! `POST /evaluate` takes a JSON object with either a `module` or an `expression` field
+//! containing the code, an optional `filename`, and optional `globals`, an object of JSON
+//! values which the code can refer to by name. It responds with a JSON object with a `result`,
+//! a list of `diagnostics`, in the same form as `--json`, and the lines printed as `output`.
+//! The result of an expression is its value, and the result of a module is an object of its
+//! exported variables, leaving out those (such as functions) which have no JSON form.
+//! The result is `null` on error, including when more is printed than `--max-print-bytes`.
+//!
+//! The code can't `load()` other modules, or do anything else which leaves the evaluator.
+//! Each request is evaluated with the limits given by `--max-steps`, `--timeout` and
+//! `--max-heap-bytes`, or if they are not given, with the defaults below. Up to
+//! [`MAX_CONNECTIONS`] connections are read and written concurrently, but their requests are
+//! evaluated one at a time. A request whose body is bigger than [`MAX_BODY_BYTES`], or which
+//! isn't received in full within [`REQUEST_TIMEOUT`], is rejected.
+
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Sender},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use gazebo::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use starlark::{
+    environment::Module,
+    eval::{Evaluator, PrintStream},
+    syntax::AstModule,
+};
+
+use crate::{
+    eval::{dialect, globals, Context, Limits, PRINT_CAPACITY},
+    types::{LintMessage, Message},
+};
+
+/// The largest request body accepted.
+const MAX_BODY_BYTES: usize = 16 * 1024 * 1024;
+/// The most bytes of request line and headers accepted.
+const MAX_HEADER_BYTES: u64 = 64 * 1024;
+/// How long a client may take to send its whole request, however slowly it trickles in.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long to wait for a client to receive the response.
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+/// The most connections handled at once, further ones are closed straight away.
+const MAX_CONNECTIONS: usize = 64;
+
+/// The statements an evaluation may execute, unless `--max-steps` is given.
+const DEFAULT_MAX_STEPS: u64 = 100_000_000;
+/// How long an evaluation may run, unless `--timeout` is given.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+/// The bytes an evaluation may allocate, unless `--max-heap-bytes` is given.
+const DEFAULT_MAX_HEAP_BYTES: usize = 256 * 1024 * 1024;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EvaluateRequest {
+    module: Option<String>,
+    expression: Option<String>,
+    filename: Option<String>,
+    /// Kept as JSON text, so numbers too big for `f64` keep their precision.
+    #[serde(default)]
+    globals: HashMap<String, Box<RawValue>>,
+}
+
+#[derive(Serialize)]
+struct EvaluateResponse {
+    result: serde_json::Value,
+    diagnostics: Vec<LintMessage>,
+    output: Vec<String>,
+}
+
+/// A request read from a connection.
+#[derive(Debug, PartialEq)]
+enum Request {
+    /// The method, path and body.
+    Http(String, String, Vec<u8>),
+    /// The body is bigger than [`MAX_BODY_BYTES`], so wasn't read.
+    TooLarge,
+}
+
+/// A status line and body.
+type Response = (&'static str, String);
+
+/// A request to evaluate, with where to send the response.
+type Evaluation = (Vec<u8>, Sender<Response>);
+
+/// Serve requests on `address`, which may omit the host, e.g. `:8080`, to only accept
+/// connections from this machine.
+pub fn server(ctx: &Context, address: &str) -> anyhow::Result<()> {
+    let address = match address.strip_prefix(':') {
+        Some(port) => format!("127.0.0.1:{}", port),
+        None => address.to_owned(),
+    };
+    let listener = TcpListener::bind(&address)?;
+    eprintln!("Serving Starlark evaluation on {}", listener.local_addr()?);
+    let (sender, evaluations) = mpsc::channel();
+    thread::spawn(move || accept(listener, sender));
+    // The context can't be shared between threads, so evaluate here, one at a time.
+    for (body, reply) in evaluations {
+        let response = evaluate_body(ctx, &body)
+            .unwrap_or_else(|e| ("500 Internal Server Error", format!("{:#}", e)));
+        // The client may have gone away, which only affects its own request
+        let _ = reply.send(response);
+    }
+    Ok(())
+}
+
+/// Handle each connection on a thread of its own, so a slow client doesn't stop others.
+fn accept(listener: TcpListener, evaluations: Sender<Evaluation>) {
+    let active = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("Failed to accept connection: {}", e);
+                continue;
+            }
+        };
+        if active.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+            active.fetch_sub(1, Ordering::SeqCst);
+            eprintln!("Closing connection, already handling {}", MAX_CONNECTIONS);
+            continue;
+        }
+        let active = active.dupe();
+        let evaluations = evaluations.clone();
+        thread::spawn(move || {
+            // A broken connection only affects its own request
+            if let Err(e) = handle(&stream, &evaluations) {
+                eprintln!("Failed to handle request: {:#}", e);
+            }
+            active.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+}
+
+fn handle(stream: &TcpStream, evaluations: &Sender<Evaluation>) -> anyhow::Result<()> {
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    let request = read_request(Deadline {
+        stream,
+        deadline: Instant::now() + REQUEST_TIMEOUT,
+    })?;
+    let (status, body) = match route(request) {
+        Ok(body) => {
+            let (reply, response) = mpsc::channel();
+            evaluations
+                .send((body, reply))
+                .map_err(|_| anyhow!("The server stopped evaluating"))?;
+            response.recv()?
+        }
+        Err(response) => response,
+    };
+    respond(stream, status, body)
+}
+
+/// Reads from a connection, failing once `deadline` has passed, rather than only when a
+/// single read takes too long, so a client can't hold a connection by trickling its request.
+struct Deadline<'a> {
+    stream: &'a TcpStream,
+    deadline: Instant,
+}
+
+impl Read for Deadline<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "The request wasn't received in time",
+            ));
+        }
+        self.stream.set_read_timeout(Some(remaining))?;
+        let mut stream = self.stream;
+        stream.read(buf)
+    }
+}
+
+fn read_request(reader: impl Read) -> anyhow::Result<Request> {
+    let mut reader = BufReader::new(reader);
+    let mut content_length = 0;
+    let mut request_line = String::new();
+    {
+        let mut head = (&mut reader).take(MAX_HEADER_BYTES);
+        head.read_line(&mut request_line)?;
+        loop {
+            let mut header = String::new();
+            if head.read_line(&mut header)? == 0 {
+                return Err(anyhow!("Request ended before its headers"));
+            }
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse()?;
+                }
+            }
+        }
+    }
+    if content_length > MAX_BODY_BYTES {
+        return Ok(Request::TooLarge);
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    let mut words = request_line.split_whitespace();
+    let method = words.next().unwrap_or_default().to_owned();
+    let path = words.next().unwrap_or_default().to_owned();
+    Ok(Request::Http(method, path, body))
+}
+
+/// The body of a request to evaluate, or the response to any other request.
+fn route(request: Request) -> Result<Vec<u8>, Response> {
+    match request {
+        Request::Http(method, path, body) if method == "POST" && path == "/evaluate" => Ok(body),
+        Request::Http(..) => Err((
+            "404 Not Found",
+            "Only `POST /evaluate` is supported".to_owned(),
+        )),
+        Request::TooLarge => Err((
+            "413 Payload Too Large",
+            format!("The request body must be at most {} bytes", MAX_BODY_BYTES),
+        )),
+    }
+}
+
+fn evaluate_body(ctx: &Context, body: &[u8]) -> anyhow::Result<Response> {
+    Ok(match serde_json::from_slice(body) {
+        Ok(request) => match evaluate(ctx, request) {
+            Ok(response) => ("200 OK", serde_json::to_string(&response)?),
+            Err(e) => ("400 Bad Request", format!("{:#}", e)),
+        },
+        Err(e) => ("400 Bad Request", format!("Invalid request: {}", e)),
+    })
+}
+
+fn respond(stream: &TcpStream, status: &str, body: String) -> anyhow::Result<()> {
+    let content_type = if status.starts_with("200") {
+        "application/json"
+    } else {
+        "text/plain"
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    Ok(())
+}
+
+fn evaluate(ctx: &Context, request: EvaluateRequest) -> anyhow::Result<EvaluateResponse> {
+    let (code, is_module) = match (request.module, request.expression) {
+        (Some(code), None) => (code, true),
+        (None, Some(code)) => (code, false),
+        _ => return Err(anyhow!("Expected exactly one of `module` and `expression`")),
+    };
+    let filename = request
+        .filename
+        .unwrap_or_else(|| if is_module { "module" } else { "expression" }.to_owned());
+    let ast = match AstModule::parse(&filename, code, &dialect()) {
+        Ok(ast) => ast,
+        Err(e) => {
+            return Ok(EvaluateResponse {
+                result: serde_json::Value::Null,
+                diagnostics: vec![LintMessage::new(Message::from_anyhow(&filename, e))],
+                output: Vec::new(),
+            });
+        }
+    };
+    let mut diagnostics: Vec<_> = if ctx.check {
+        ctx.check(&ast).map(LintMessage::new).collect()
+    } else {
+        Vec::new()
+    };
+
+    let module = Context::new_module(&ctx.prelude);
+    for (name, value) in &request.globals {
+        let value = module.heap().alloc_from_json(value.get())?;
+        module.set(name, value);
+    }
+    let limits = Limits {
+        max_steps: ctx.limits.max_steps.or(Some(DEFAULT_MAX_STEPS)),
+        timeout: ctx.limits.timeout.or(Some(DEFAULT_TIMEOUT)),
+        max_heap_bytes: ctx.limits.max_heap_bytes.or(Some(DEFAULT_MAX_HEAP_BYTES)),
+    };
+    let output = Arc::new(Mutex::new(Vec::new()));
+    let output2 = output.dupe();
+    let result = PrintStream::consume(
+        PRINT_CAPACITY,
+        ctx.print_limit,
+        move |line| output2.lock().unwrap().push(line),
+        |stream| -> anyhow::Result<Option<String>> {
+            let mut eval = Evaluator::new(&module);
+            eval.set_print_handler(stream);
+            limits.apply(&module, &mut eval);
+            let value = eval.eval_module(ast, &globals())?;
+            if is_module {
+                Ok(None)
+            } else {
+                Ok(Some(value.to_json()?))
+            }
+        },
+    );
+    let result = result.and_then(|json| match json {
+        Some(json) => Ok(serde_json::from_str(&json)?),
+        None => exports(module, &request.globals),
+    });
+    let result = match result {
+        Ok(result) => result,
+        Err(e) => {
+            diagnostics.push(LintMessage::new(Message::from_anyhow(&filename, e)));
+            serde_json::Value::Null
+        }
+    };
+    let output = output.lock().unwrap().clone();
+    Ok(EvaluateResponse {
+        result,
+        diagnostics,
+        output,
+    })
+}
+
+/// The exported variables of a module which have a JSON form, other than the globals the
+/// request provided.
+fn exports(
+    module: Module,
+    provided: &HashMap<String, Box<RawValue>>,
+) -> anyhow::Result<serde_json::Value> {
+    let module = module.freeze()?;
+    let mut res = serde_json::Map::new();
+    for name in module.names() {
+        if provided.contains_key(name) {
+            continue;
+        }
+        if let Some(value) = module.get(name) {
+            if let Ok(json) = value.value().to_json() {
+                res.insert(name.to_owned(), serde_json::from_str(&json)?);
+            }
+        }
+    }
+    Ok(serde_json::Value::Object(res))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(request: &str) -> anyhow::Result<Request> {
+        read_request(request.as_bytes())
+    }
+
+    fn http(method: &str, path: &str, body: &[u8]) -> Request {
+        Request::Http(method.to_owned(), path.to_owned(), body.to_vec())
+    }
+
+    #[test]
+    fn test_read_request() {
+        assert_eq!(
+            read("POST /evaluate HTTP/1.1\r\nHost: x\r\ncontent-LENGTH: 4\r\n\r\n{}\r\nextra")
+                .unwrap(),
+            http("POST", "/evaluate", b"{}\r\n")
+        );
+        // Without a `Content-Length`, there is no body
+        assert_eq!(
+            read("GET / HTTP/1.1\r\n\r\n").unwrap(),
+            http("GET", "/", b"")
+        );
+        assert!(read("POST /evaluate HTTP/1.1\r\nHost: x\r\n").is_err());
+        assert!(read("POST /evaluate HTTP/1.1\r\nContent-Length: 10\r\n\r\n{}").is_err());
+        assert!(read("POST /evaluate HTTP/1.1\r\nContent-Length: x\r\n\r\n").is_err());
+        // Headers are read through a limit
+        let long = "a".repeat(MAX_HEADER_BYTES as usize);
+        assert!(read(&format!("POST /evaluate HTTP/1.1\r\nX: {}\r\n\r\n", long)).is_err());
+    }
+
+    #[test]
+    fn test_too_large() {
+        let request = read(&format!(
+            "POST /evaluate HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY_BYTES + 1
+        ))
+        .unwrap();
+        assert_eq!(request, Request::TooLarge);
+        assert_eq!(route(request).unwrap_err().0, "413 Payload Too Large");
+    }
+
+    #[test]
+    fn test_route() {
+        assert_eq!(route(http("POST", "/evaluate", b"{}")), Ok(b"{}".to_vec()));
+        for (method, path) in [("GET", "/evaluate"), ("POST", "/"), ("", "")] {
+            assert_eq!(
+                route(http(method, path, b"")).unwrap_err().0,
+                "404 Not Found"
+            );
+        }
+    }
+
+    #[test]
+    fn test_deadline() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        // Each read gets a byte well within `IO_TIMEOUT`, but the request never finishes
+        thread::spawn(move || {
+            for _ in 0..200 {
+                if client.write_all(b"P").is_err() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+        });
+        let start = Instant::now();
+        let deadline = Deadline {
+            stream: &stream,
+            deadline: start + Duration::from_millis(200),
+        };
+        assert!(read_request(deadline).is_err());
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,48 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::path::Path;
+
+use starlark::eval::HeapSnapshot;
+
+/// How many of the largest retainers to show.
+const RETAINERS: usize = 20;
+
+/// Print the values in a heap snapshot written by `--heap-snapshot` which keep the
+/// most memory alive, with what they keep alive and where they were allocated.
+pub fn analyze(file: &Path) -> anyhow::Result<()> {
+    let snapshot = HeapSnapshot::read(file)?;
+    let retained = snapshot.retained_bytes();
+    // Reading checked the first node is the roots, which isn't a value
+    println!(
+        "{} values, {} bytes",
+        snapshot.nodes.len().saturating_sub(1),
+        retained.first().copied().unwrap_or_default()
+    );
+    println!("{:>12} {:>12} {:<16} Location", "Retained", "Bytes", "Type");
+    for (i, bytes) in snapshot.largest_retainers().into_iter().take(RETAINERS) {
+        let node = &snapshot.nodes[i];
+        println!(
+            "{:>12} {:>12} {:<16} {}",
+            bytes,
+            node.bytes,
+            node.typ,
+            node.location.as_deref().unwrap_or("unknown")
+        );
+    }
+    Ok(())
+}
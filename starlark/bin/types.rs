@@ -119,6 +119,20 @@ impl Message {
             original: Some(x.original),
         }
     }
+
+    /// Convert a problem found by the type checker, which the user opted into,
+    /// so unlike other lints, serious problems are errors and the rest are warnings.
+    pub fn from_type_lint(x: Lint) -> Self {
+        let severity = if x.serious {
+            Severity::Error
+        } else {
+            Severity::Warning
+        };
+        Self {
+            severity,
+            ..Self::from_lint(x)
+        }
+    }
 }
 
 /// A JSON-deriving type that gives a stable interface to downstream types.
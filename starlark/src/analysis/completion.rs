@@ -0,0 +1,189 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use gazebo::prelude::*;
+use indexmap::IndexMap;
+
+use crate::{
+    codemap::{Pos, Span},
+    syntax::{
+        ast::{AstAssign, AstExpr, AstParameter, AstStmt, Clause, Expr, Stmt},
+        uniplate::Visit,
+        AstModule,
+    },
+};
+
+/// How a name returned by [`AstModule::names_in_scope`] was bound.
+#[derive(Debug, Clone, Copy, Dupe, PartialEq, Eq, Hash)]
+pub enum SymbolKind {
+    /// Introduced by a `load()` statement.
+    Load,
+    /// Defined by a `def` statement.
+    Function,
+    /// A parameter of an enclosing `def` or `lambda`.
+    Parameter,
+    /// Bound by an assignment, a `for` loop or a comprehension.
+    Variable,
+}
+
+fn contains(span: Span, pos: Pos) -> bool {
+    span.begin() <= pos && pos <= span.end()
+}
+
+type Names<'a> = IndexMap<&'a str, SymbolKind>;
+
+fn lvalue<'a>(x: &'a AstAssign, res: &mut Names<'a>) {
+    x.visit_lvalue(|x| {
+        res.insert(&x.0, SymbolKind::Variable);
+    })
+}
+
+fn parameters<'a>(xs: &'a [AstParameter], res: &mut Names<'a>) {
+    for x in xs {
+        if let (Some(name), _, _) = x.split() {
+            res.insert(&name.0, SymbolKind::Parameter);
+        }
+    }
+}
+
+/// Names bound by a block of statements, not descending into nested `def`s.
+fn bindings<'a>(x: &'a AstStmt, res: &mut Names<'a>) {
+    match &**x {
+        Stmt::Assign(dest, _) | Stmt::AssignModify(dest, _, _) => lvalue(dest, res),
+        Stmt::For(dest, _) => lvalue(dest, res),
+        Stmt::Def(name, ..) => {
+            res.insert(&name.0, SymbolKind::Function);
+            return;
+        }
+        Stmt::Load(load) => {
+            for (name, _) in &load.node.args {
+                res.insert(&name.0, SymbolKind::Load);
+            }
+        }
+        _ => {}
+    }
+    x.visit_stmt(|x| bindings(x, res))
+}
+
+/// Names bound by `lambda`s and comprehensions which enclose `pos`.
+fn enclosing_expr<'a>(x: &'a AstExpr, pos: Pos, res: &mut Names<'a>) {
+    if !contains(x.span, pos) {
+        return;
+    }
+    match &**x {
+        Expr::Lambda(params, _, _) => parameters(params, res),
+        Expr::ListComprehension(_, for_, clauses) | Expr::DictComprehension(_, for_, clauses) => {
+            lvalue(&for_.var, res);
+            for clause in clauses {
+                if let Clause::For(for_) = clause {
+                    lvalue(&for_.var, res);
+                }
+            }
+        }
+        _ => {}
+    }
+    x.visit_expr(|x| enclosing_expr(x, pos, res))
+}
+
+/// Names bound by `def`s (and expressions) which enclose `pos`.
+fn enclosing_stmt<'a>(x: &'a AstStmt, pos: Pos, res: &mut Names<'a>) {
+    if !contains(x.span, pos) {
+        return;
+    }
+    match &**x {
+        Stmt::Def(_, params, _, body, _) => {
+            parameters(params, res);
+            bindings(body, res);
+        }
+        _ => {}
+    }
+    x.visit_children(|x| match x {
+        Visit::Stmt(x) => enclosing_stmt(x, pos, res),
+        Visit::Expr(x) => enclosing_expr(x, pos, res),
+    })
+}
+
+impl AstModule {
+    /// The names defined within this module which are visible at a given location,
+    /// e.g. for offering completions in an editor. Includes the module-level bindings
+    /// (assignments, `def`s and `load`s), plus the parameters and local variables of
+    /// any functions and comprehensions enclosing the location.
+    /// Names defined in inner scopes are listed after those in outer scopes.
+    ///
+    /// The `line` and `column` are 0-indexed, with the column counted in characters.
+    pub fn names_in_scope(&self, line: usize, column: usize) -> Vec<(&str, SymbolKind)> {
+        let pos = self.codemap.find_pos(line, column);
+        let mut res = IndexMap::new();
+        bindings(&self.statement, &mut res);
+        enclosing_stmt(&self.statement, pos, &mut res);
+        res.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use gazebo::prelude::*;
+
+    use super::*;
+    use crate::syntax::Dialect;
+
+    fn module(x: &str) -> AstModule {
+        AstModule::parse("X", x.to_owned(), &Dialect::Extended).unwrap()
+    }
+
+    fn names(x: &AstModule, line: usize, column: usize) -> Vec<String> {
+        x.names_in_scope(line, column)
+            .map(|(name, kind)| format!("{} {:?}", name, kind))
+    }
+
+    #[test]
+    fn test_names_in_scope() {
+        let modu = module(
+            r#"
+load("test", "a")
+b = 1
+def c(d, *e, f = 2):
+    g = [h for h in d]
+    for i in e:
+        pass
+    return lambda j: j
+k = 3
+"#,
+        );
+        assert_eq!(
+            names(&modu, 9, 0),
+            &["a Load", "b Variable", "c Function", "k Variable"]
+        );
+        assert_eq!(
+            names(&modu, 6, 8),
+            &[
+                "a Load",
+                "b Variable",
+                "c Function",
+                "k Variable",
+                "d Parameter",
+                "e Parameter",
+                "f Parameter",
+                "g Variable",
+                "i Variable"
+            ]
+        );
+        assert!(names(&modu, 4, 10).contains(&"h Variable".to_owned()));
+        assert!(!names(&modu, 5, 4).contains(&"h Variable".to_owned()));
+        assert!(names(&modu, 7, 22).contains(&"j Parameter".to_owned()));
+    }
+}
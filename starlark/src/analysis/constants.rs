@@ -0,0 +1,202 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::fmt::{self, Display};
+
+use indexmap::IndexMap;
+use itertools::Itertools;
+
+use crate::{
+    codemap::FileSpan,
+    environment::{Globals, Module},
+    eval::Evaluator,
+    syntax::{
+        ast::{AssignP, AstExpr, AstLiteral, Expr, Stmt},
+        AstModule, Dialect,
+    },
+};
+
+/// A literal collection assigned to top-level variables in several modules, which could
+/// instead be defined once in a shared module and loaded by the others.
+/// Found by [`AstModule::duplicate_constants`].
+#[derive(Debug, Clone)]
+pub struct DuplicateConstant {
+    /// The collection, as it would be written in the shared module.
+    pub value: String,
+    /// The variables assigned the collection, in the order of the modules.
+    pub definitions: Vec<(FileSpan, String)>,
+    /// The bytes each copy takes on the frozen heap of its module.
+    pub bytes: usize,
+}
+
+impl DuplicateConstant {
+    /// The bytes saved by keeping only one copy.
+    pub fn savings(&self) -> usize {
+        self.bytes * (self.definitions.len() - 1)
+    }
+}
+
+impl Display for DuplicateConstant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Identical constant defined {} times ({}), ",
+            self.definitions.len(),
+            self.definitions
+                .iter()
+                .map(|(span, name)| format!("`{}` at {}", name, span))
+                .join(", "),
+        )?;
+        write!(
+            f,
+            "hoist it into a shared module and `load` it to save about {} bytes",
+            self.savings()
+        )
+    }
+}
+
+/// Is the expression a value which can be written the same way in any module.
+fn is_constant(x: &AstExpr) -> bool {
+    match &**x {
+        Expr::Literal(_) => true,
+        Expr::Minus(x) => matches!(
+            &***x,
+            Expr::Literal(AstLiteral::Int(_) | AstLiteral::Float(_))
+        ),
+        Expr::Identifier(x, ()) => matches!(x.node.as_str(), "None" | "True" | "False"),
+        Expr::Tuple(xs) | Expr::List(xs) => xs.iter().all(is_constant),
+        Expr::Dict(xs) => xs.iter().all(|(k, v)| is_constant(k) && is_constant(v)),
+        _ => false,
+    }
+}
+
+fn is_collection(x: &AstExpr) -> bool {
+    match &**x {
+        Expr::Tuple(xs) | Expr::List(xs) => !xs.is_empty(),
+        Expr::Dict(xs) => !xs.is_empty(),
+        _ => false,
+    }
+}
+
+/// The bytes on the frozen heap of a module which only assigns `value`.
+fn frozen_bytes(value: &str) -> anyhow::Result<usize> {
+    let ast = AstModule::parse("constant", format!("x = {}", value), &Dialect::Extended)?;
+    let module = Module::new();
+    let mut eval = Evaluator::new(&module);
+    eval.eval_module(ast, &Globals::standard())?;
+    drop(eval);
+    Ok(module.freeze()?.frozen_heap().allocated_bytes())
+}
+
+impl AstModule {
+    /// Find the literal collections (lists, tuples and dicts of literals) assigned to
+    /// top-level variables identically in more than one of the `modules`, such as a table
+    /// copied between the modules of a workspace. Each would take less memory if defined
+    /// once and loaded where needed. Only reports collections taking at least `min_bytes`,
+    /// as measured by evaluating and freezing them, largest savings first.
+    pub fn duplicate_constants(
+        modules: &[AstModule],
+        min_bytes: usize,
+    ) -> anyhow::Result<Vec<DuplicateConstant>> {
+        // IndexMap so collections with the same savings are in the order they are defined
+        let mut found: IndexMap<String, Vec<(usize, FileSpan, String)>> = IndexMap::new();
+        for (i, module) in modules.iter().enumerate() {
+            module.statement.visit_stmt(|x| {
+                if let Stmt::Assign(lhs, rhs) = &**x {
+                    if let AssignP::Identifier(name) = &**lhs {
+                        if is_collection(rhs) && is_constant(rhs) {
+                            found.entry(rhs.to_string()).or_default().push((
+                                i,
+                                module.file_span(name.span),
+                                name.0.clone(),
+                            ));
+                        }
+                    }
+                }
+            })
+        }
+
+        // Subtract what a module without the collection takes
+        let baseline = frozen_bytes("None")?;
+        let mut res = Vec::new();
+        for (value, definitions) in found {
+            // Copies within a single module are for its author to deal with
+            if definitions.iter().map(|x| x.0).all_equal() {
+                continue;
+            }
+            let bytes = frozen_bytes(&value)?.saturating_sub(baseline);
+            if bytes >= min_bytes {
+                res.push(DuplicateConstant {
+                    value,
+                    definitions: definitions
+                        .into_iter()
+                        .map(|(_, span, name)| (span, name))
+                        .collect(),
+                    bytes,
+                });
+            }
+        }
+        res.sort_by_key(|x| std::cmp::Reverse(x.savings()));
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use gazebo::prelude::*;
+
+    use super::*;
+
+    fn module(name: &str, x: &str) -> AstModule {
+        AstModule::parse(name, x.to_owned(), &Dialect::Extended).unwrap()
+    }
+
+    #[test]
+    fn test_duplicate_constants() {
+        let modules = [
+            module(
+                "a.bzl",
+                "PLATFORMS = ['linux', 'mac', 'windows']\nX = [1]\nY = [1]",
+            ),
+            module(
+                "b.bzl",
+                "SUPPORTED = [\n    'linux',\n    'mac',\n    'windows',\n]\ndef f(): pass",
+            ),
+            module("c.bzl", "OTHER = ['linux', 'mac']\nZ = [f()]"),
+        ];
+        let res = AstModule::duplicate_constants(&modules, 0).unwrap();
+        assert_eq!(
+            res.map(|x| (
+                x.value.as_str(),
+                x.definitions
+                    .map(|(span, name)| format!("{} {}", span, name))
+            )),
+            &[(
+                "[\"linux\", \"mac\", \"windows\"]",
+                vec![
+                    "a.bzl:1:1-10 PLATFORMS".to_owned(),
+                    "b.bzl:1:1-10 SUPPORTED".to_owned()
+                ]
+            )]
+        );
+        assert!(res[0].bytes > 0);
+        assert_eq!(res[0].savings(), res[0].bytes);
+        assert!(AstModule::duplicate_constants(&modules, res[0].bytes + 1)
+            .unwrap()
+            .is_empty());
+    }
+}
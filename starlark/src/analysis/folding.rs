@@ -0,0 +1,180 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use gazebo::prelude::*;
+
+use crate::{
+    codemap::{CodeMap, Span},
+    syntax::{
+        ast::{AstExpr, AstStmt, Expr, Stmt},
+        uniplate::Visit,
+        AstModule,
+    },
+};
+
+/// What a [`FoldingRange`] covers.
+#[derive(Debug, Clone, Copy, Dupe, PartialEq, Eq, Hash)]
+pub enum FoldingRangeKind {
+    /// A `def`, from its signature to the end of its body.
+    Function,
+    /// A list, tuple or dict literal, or a comprehension.
+    Literal,
+    /// A call, from the function to the closing bracket of its arguments.
+    Arguments,
+    /// A block of consecutive `load` statements.
+    Loads,
+}
+
+/// A range of lines which can be collapsed in an editor, as returned by
+/// [`AstModule::folding_ranges`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FoldingRange {
+    /// The 0-based line where the range starts.
+    pub start_line: usize,
+    /// The 0-based line where the range ends, inclusive.
+    pub end_line: usize,
+    /// What the range is.
+    pub kind: FoldingRangeKind,
+}
+
+struct Folds<'a> {
+    codemap: &'a CodeMap,
+    res: Vec<FoldingRange>,
+}
+
+impl Folds<'_> {
+    fn add(&mut self, start: Span, end: Span, kind: FoldingRangeKind) {
+        let start_line = self.codemap.resolve_span(start).begin_line;
+        let end = self.codemap.resolve_span(end);
+        // Statements can end at the start of the following line, after their newline
+        let end_line = if end.end_column == 0 && end.end_line > 0 {
+            end.end_line - 1
+        } else {
+            end.end_line
+        };
+        // A single line can't be folded, and a fold on the same lines as an existing one
+        // (e.g. a call whose only argument is a list) would be redundant
+        if start_line < end_line
+            && !self
+                .res
+                .iter()
+                .any(|x| x.start_line == start_line && x.end_line == end_line)
+        {
+            self.res.push(FoldingRange {
+                start_line,
+                end_line,
+                kind,
+            });
+        }
+    }
+
+    fn stmt(&mut self, x: &AstStmt) {
+        match &**x {
+            Stmt::Def(..) => self.add(x.span, x.span, FoldingRangeKind::Function),
+            Stmt::Statements(xs) => {
+                // Fold each run of adjacent `load` statements together
+                let mut run: Option<(Span, Span)> = None;
+                for x in xs {
+                    if matches!(&**x, Stmt::Load(_)) {
+                        let start = run.map_or(x.span, |(start, _)| start);
+                        run = Some((start, x.span));
+                    } else if let Some((start, end)) = run.take() {
+                        self.add(start, end, FoldingRangeKind::Loads);
+                    }
+                }
+                if let Some((start, end)) = run {
+                    self.add(start, end, FoldingRangeKind::Loads);
+                }
+            }
+            _ => {}
+        }
+        x.visit_children(|x| match x {
+            Visit::Stmt(x) => self.stmt(x),
+            Visit::Expr(x) => self.expr(x),
+        });
+    }
+
+    fn expr(&mut self, x: &AstExpr) {
+        match &**x {
+            Expr::List(_)
+            | Expr::Tuple(_)
+            | Expr::Dict(_)
+            | Expr::ListComprehension(..)
+            | Expr::DictComprehension(..) => self.add(x.span, x.span, FoldingRangeKind::Literal),
+            Expr::Call(..) => self.add(x.span, x.span, FoldingRangeKind::Arguments),
+            _ => {}
+        }
+        x.visit_expr(|x| self.expr(x));
+    }
+}
+
+impl AstModule {
+    /// The ranges of lines which can be folded: function bodies, multi-line literals,
+    /// calls whose arguments span several lines, and blocks of consecutive `load` statements.
+    /// Ranges are returned in the order they start, with outer ranges before inner ones.
+    pub fn folding_ranges(&self) -> Vec<FoldingRange> {
+        let mut res = Folds {
+            codemap: &self.codemap,
+            res: Vec::new(),
+        };
+        res.stmt(&self.statement);
+        res.res
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use gazebo::prelude::*;
+
+    use crate::syntax::{AstModule, Dialect};
+
+    #[test]
+    fn test_folding_ranges() {
+        let m = AstModule::parse(
+            "X",
+            r#"
+load("a", "a")
+load("b", "b")
+load("c", "c")
+def f(x):
+    y = [
+        1,
+        2,
+    ]
+    return g(
+        x,
+        y,
+    )
+z = {"k": (1, 2)}
+load("d", "d")
+"#
+            .to_owned(),
+            &Dialect::Extended,
+        )
+        .unwrap();
+        assert_eq!(
+            m.folding_ranges()
+                .map(|x| format!("{:?} {}-{}", x.kind, x.start_line, x.end_line)),
+            &[
+                "Loads 1-3",
+                "Function 4-12",
+                "Literal 5-8",
+                "Arguments 9-12"
+            ]
+        );
+    }
+}
@@ -0,0 +1,157 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::HashMap;
+
+use gazebo::prelude::*;
+
+use crate::{
+    analysis::bind::{self, Assigner, Bind, Scope},
+    codemap::{Pos, ResolvedSpan, Span},
+    syntax::AstModule,
+};
+
+/// Whether a [`Highlight`] reads or writes its variable.
+#[derive(Debug, Clone, Copy, Dupe, PartialEq, Eq, Hash)]
+pub enum HighlightKind {
+    /// The variable is read.
+    Read,
+    /// The variable is assigned, including by a `def`, `load`, parameter or `+=`.
+    Write,
+}
+
+/// An occurrence of a variable, as returned by [`AstModule::highlights`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Highlight {
+    /// The span of the name.
+    pub span: ResolvedSpan,
+    /// How the variable is used.
+    pub kind: HighlightKind,
+}
+
+/// An occurrence of `name`, bound in the scope with the given index,
+/// or `None` if it isn't bound in this module.
+struct Occurrence<'a> {
+    scope: Option<usize>,
+    name: &'a str,
+    span: Span,
+    kind: HighlightKind,
+}
+
+fn occurrences<'a>(
+    x: &'a Scope,
+    stack: &mut Vec<(usize, &'a HashMap<String, (Assigner, Span)>)>,
+    scopes: &mut usize,
+    res: &mut Vec<Occurrence<'a>>,
+) {
+    let index = *scopes;
+    *scopes += 1;
+    stack.push((index, &x.bound));
+    for bind in &x.inner {
+        match bind {
+            Bind::Set(_, x) => res.push(Occurrence {
+                scope: Some(index),
+                name: &x.0,
+                span: x.span,
+                kind: HighlightKind::Write,
+            }),
+            Bind::Get(x) => res.push(Occurrence {
+                scope: stack
+                    .iter()
+                    .rev()
+                    .find(|(_, bound)| bound.contains_key(&x.node))
+                    .map(|(index, _)| *index),
+                name: &x.node,
+                span: x.span,
+                kind: HighlightKind::Read,
+            }),
+            Bind::Scope(x) => occurrences(x, stack, scopes, res),
+            Bind::Flow => {}
+        }
+    }
+    stack.pop();
+}
+
+fn contains(x: Span, pos: Pos) -> bool {
+    // Include the position just after the name, where the cursor often is
+    x.begin() <= pos && pos <= x.end()
+}
+
+impl AstModule {
+    /// All the occurrences of the variable at a location, if there is one, each marked as a
+    /// read or a write. Different variables with the same name, such as a parameter and a
+    /// global, are distinguished. The result is in the order the occurrences appear.
+    ///
+    /// The `line` and `column` are 0-indexed, with the column counted in characters.
+    pub fn highlights(&self, line: usize, column: usize) -> Vec<Highlight> {
+        let pos = self.codemap.find_pos(line, column);
+        let scope = bind::scope(self);
+        let mut res = Vec::new();
+        occurrences(&scope, &mut Vec::new(), &mut 0, &mut res);
+        let (scope, name) = match res.iter().find(|x| contains(x.span, pos)) {
+            Some(x) => (x.scope, x.name),
+            None => return Vec::new(),
+        };
+        let mut res: Vec<_> = res
+            .into_iter()
+            .filter(|x| x.scope == scope && x.name == name)
+            .collect();
+        // `x += 1` both reads and writes `x`, which we report as a single write
+        res.sort_by_key(|x| (x.span.begin(), x.kind == HighlightKind::Read));
+        res.dedup_by_key(|x| x.span);
+        res.into_map(|x| Highlight {
+            span: self.codemap.resolve_span(x.span),
+            kind: x.kind,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::syntax::Dialect;
+
+    fn highlights(program: &str, line: usize, column: usize) -> Vec<String> {
+        let module = AstModule::parse("X", program.to_owned(), &Dialect::Extended).unwrap();
+        module
+            .highlights(line, column)
+            .into_map(|x| format!("{}:{} {:?}", x.span.begin_line, x.span.begin_column, x.kind))
+    }
+
+    #[test]
+    fn test_highlights() {
+        let program = r#"
+x = 1
+def f(x):
+    x += 1
+    return [x for x in [x]]
+y = x
+"#;
+        // The global
+        assert_eq!(highlights(program, 1, 0), &["1:0 Write", "5:4 Read"]);
+        assert_eq!(highlights(program, 5, 4), highlights(program, 1, 0));
+        // The parameter, from its use
+        assert_eq!(
+            highlights(program, 3, 4),
+            &["2:6 Write", "3:4 Write", "4:24 Read"]
+        );
+        // The comprehension variable
+        assert_eq!(highlights(program, 4, 12), &["4:12 Read", "4:18 Write"]);
+        // Not a variable
+        assert_eq!(highlights(program, 2, 0), Vec::<String>::new());
+    }
+}
@@ -84,14 +84,19 @@ fn match_bad_type_equality(
             if (*op == BinOp::Equal || *op == BinOp::NotEqual) && is_type_call(lhs) =>
         {
             if let Some(replacement) = lookup_type(rhs, types) {
-                res.push(LintT::new(
-                    codemap,
-                    x.span,
-                    Incompatibility::IncompatibleTypeCheck(
-                        x.to_string(),
-                        format!("{}{}type({})", lhs.node, op, replacement),
+                let replacement = format!("{}{}type({})", lhs.node, op, replacement);
+                res.push(
+                    LintT::new(
+                        codemap,
+                        x.span,
+                        Incompatibility::IncompatibleTypeCheck(x.to_string(), replacement.clone()),
+                    )
+                    .with_fix(
+                        codemap,
+                        "Use the canonical type check",
+                        vec![(x.span, replacement)],
                     ),
-                ))
+                )
             }
         }
         _ => {}
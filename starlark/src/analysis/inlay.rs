@@ -0,0 +1,408 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::collections::HashMap;
+
+use gazebo::prelude::*;
+
+use crate::{
+    codemap::CodeMap,
+    syntax::{
+        ast::{
+            Argument, Assign, AstArgument, AstExpr, AstLiteral, AstParameter, AstStmt, BinOp, Expr,
+            Parameter, Stmt,
+        },
+        uniplate::Visit,
+        AstModule,
+    },
+};
+
+/// What an [`InlayHint`] describes.
+#[derive(Debug, Clone, Copy, Dupe, PartialEq, Eq, Hash)]
+pub enum InlayHintKind {
+    /// The type of the variable being assigned, shown after its name.
+    Type,
+    /// The name of the parameter a positional argument is passed to, shown before it.
+    Parameter,
+}
+
+/// A hint to show inline in an editor, as returned by [`AstModule::inlay_hints`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InlayHint {
+    /// The 0-based line of the hint.
+    pub line: usize,
+    /// The 0-based column of the hint, in characters.
+    pub column: usize,
+    /// The type, or parameter name.
+    pub label: String,
+    /// What the label is.
+    pub kind: InlayHintKind,
+}
+
+/// The names bound in a scope, with the number of times each is bound,
+/// not descending into nested `def`s.
+fn bindings<'a>(x: &'a AstStmt, res: &mut HashMap<&'a str, usize>) {
+    match &**x {
+        Stmt::Assign(dest, _) | Stmt::AssignModify(dest, _, _) | Stmt::For(dest, _) => {
+            dest.visit_lvalue(|x| *res.entry(x.0.as_str()).or_default() += 1)
+        }
+        Stmt::Def(name, ..) => {
+            *res.entry(name.0.as_str()).or_default() += 1;
+            return;
+        }
+        Stmt::Load(load) => {
+            for (name, _) in &load.node.args {
+                *res.entry(name.0.as_str()).or_default() += 1;
+            }
+        }
+        _ => {}
+    }
+    x.visit_stmt(|x| bindings(x, res))
+}
+
+/// Is the type of an expression obvious from its syntax, so not worth a hint.
+fn is_obvious(x: &AstExpr) -> bool {
+    matches!(
+        &**x,
+        Expr::Literal(_)
+            | Expr::Tuple(_)
+            | Expr::List(_)
+            | Expr::Dict(_)
+            | Expr::ListComprehension(..)
+            | Expr::DictComprehension(..)
+            | Expr::Lambda(..)
+    )
+}
+
+/// The type named by an annotation, if it is a simple string.
+fn annotation(x: Option<&AstExpr>) -> Option<&str> {
+    match x.map(|x| &x.node) {
+        Some(Expr::Literal(AstLiteral::String(x))) => Some(&x.node),
+        _ => None,
+    }
+}
+
+fn same<'a>(x: &'a str, y: &'a str) -> Option<&'a str> {
+    if x == y {
+        Some(x)
+    } else {
+        None
+    }
+}
+
+#[derive(Default)]
+struct Scope<'a> {
+    /// How many times each name is bound in this scope.
+    bound: HashMap<&'a str, usize>,
+    /// The types of the names which are only bound once, once we have seen the binding.
+    types: HashMap<&'a str, &'a str>,
+}
+
+struct Hints<'a> {
+    codemap: &'a CodeMap,
+    /// The parameters and return type annotation of the `def`s at the top level of the
+    /// module, for those which are never rebound.
+    defs: HashMap<&'a str, (&'a [AstParameter], Option<&'a AstExpr>)>,
+    /// The module scope, followed by those of the enclosing `def`s.
+    scopes: Vec<Scope<'a>>,
+    res: Vec<InlayHint>,
+}
+
+impl<'a> Hints<'a> {
+    fn add(&mut self, line: usize, column: usize, label: &str, kind: InlayHintKind) {
+        self.res.push(InlayHint {
+            line,
+            column,
+            label: label.to_owned(),
+            kind,
+        })
+    }
+
+    fn is_bound(&self, name: &str) -> bool {
+        self.scopes.iter().any(|x| x.bound.contains_key(name))
+    }
+
+    fn lookup(&self, name: &str) -> Option<&'a str> {
+        for scope in self.scopes.iter().rev() {
+            if scope.bound.contains_key(name) {
+                return scope.types.get(name).copied();
+            }
+        }
+        match name {
+            "True" | "False" => Some("bool"),
+            "None" => Some("NoneType"),
+            _ => None,
+        }
+    }
+
+    /// The top-level `def` called `name`, if it isn't shadowed by a local.
+    fn def(&self, name: &str) -> Option<(&'a [AstParameter], Option<&'a AstExpr>)> {
+        if self.scopes[1..].iter().any(|x| x.bound.contains_key(name)) {
+            return None;
+        }
+        self.defs.get(name).copied()
+    }
+
+    fn infer(&self, x: &'a AstExpr) -> Option<&'a str> {
+        match &**x {
+            Expr::Literal(AstLiteral::Int(_)) => Some("int"),
+            Expr::Literal(AstLiteral::Float(_)) => Some("float"),
+            Expr::Literal(AstLiteral::String(_)) => Some("string"),
+            Expr::Literal(AstLiteral::Bytes(_)) => Some("bytes"),
+            Expr::Identifier(name, _) => self.lookup(&name.node),
+            Expr::Tuple(_) => Some("tuple"),
+            Expr::List(_) | Expr::ListComprehension(..) => Some("list"),
+            Expr::Dict(_) | Expr::DictComprehension(..) => Some("dict"),
+            Expr::Lambda(..) => Some("function"),
+            Expr::Not(_) => Some("bool"),
+            Expr::Minus(x) | Expr::Plus(x) => match self.infer(x)? {
+                t @ ("int" | "float") => Some(t),
+                _ => None,
+            },
+            Expr::BitNot(x) => same(self.infer(x)?, "int"),
+            Expr::If(box (_, x, y)) => same(self.infer(x)?, self.infer(y)?),
+            Expr::Op(x, op, y) => self.infer_op(x, *op, y),
+            Expr::Call(f, _) => match &***f {
+                Expr::Identifier(name, _) => self.infer_call(&name.node),
+                _ => None,
+            },
+            Expr::Slice(x, ..) => match self.infer(x)? {
+                t @ ("string" | "list" | "tuple") => Some(t),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn infer_op(&self, x: &'a AstExpr, op: BinOp, y: &'a AstExpr) -> Option<&'a str> {
+        use BinOp::*;
+        match op {
+            Equal | NotEqual | Less | Greater | LessOrEqual | GreaterOrEqual | In | NotIn => {
+                return Some("bool");
+            }
+            And | Or => return same(self.infer(x)?, self.infer(y)?),
+            _ => {}
+        }
+        let x = self.infer(x)?;
+        if op == Percent && x == "string" {
+            return Some("string");
+        }
+        match (op, x, self.infer(y)?) {
+            (Divide, "int" | "float", "int" | "float") => Some("float"),
+            (Add | Subtract | Multiply | Percent | FloorDivide, "int", "int") => Some("int"),
+            (
+                Add | Subtract | Multiply | Percent | FloorDivide,
+                "int" | "float",
+                "int" | "float",
+            ) => Some("float"),
+            (BitAnd | BitOr | BitXor | LeftShift | RightShift, "int", "int") => Some("int"),
+            (Add, t @ ("string" | "list" | "tuple"), u) => same(t, u),
+            (Multiply, t @ ("string" | "list" | "tuple"), "int")
+            | (Multiply, "int", t @ ("string" | "list" | "tuple")) => Some(t),
+            _ => None,
+        }
+    }
+
+    fn infer_call(&self, name: &str) -> Option<&'a str> {
+        if let Some((_, ret)) = self.def(name) {
+            return annotation(ret);
+        }
+        if self.is_bound(name) {
+            return None;
+        }
+        match name {
+            "str" | "repr" | "type" => Some("string"),
+            "int" | "len" | "hash" => Some("int"),
+            "float" => Some("float"),
+            "bool" | "any" | "all" | "hasattr" => Some("bool"),
+            "list" | "sorted" | "dir" => Some("list"),
+            "dict" => Some("dict"),
+            "tuple" => Some("tuple"),
+            "range" => Some("range"),
+            _ => None,
+        }
+    }
+
+    /// Parameter names for the positional arguments of a call to a known `def`.
+    fn arguments(&mut self, params: &'a [AstParameter], args: &'a [AstArgument]) {
+        let names = params.iter().map_while(|x| match &**x {
+            Parameter::Normal(name, _) | Parameter::WithDefaultValue(name, _, _) => Some(&name.0),
+            _ => None,
+        });
+        for (name, arg) in names.zip(args) {
+            let arg = match &**arg {
+                Argument::Positional(arg) => arg,
+                _ => break,
+            };
+            match &**arg {
+                Expr::Identifier(x, _) if &x.node == name => {}
+                _ => {
+                    let pos = self.codemap.resolve_span(arg.span);
+                    self.add(
+                        pos.begin_line,
+                        pos.begin_column,
+                        name,
+                        InlayHintKind::Parameter,
+                    );
+                }
+            }
+        }
+    }
+
+    fn expr(&mut self, x: &'a AstExpr) {
+        if let Expr::Call(f, args) = &**x {
+            if let Expr::Identifier(name, _) = &***f {
+                if let Some((params, _)) = self.def(&name.node) {
+                    self.arguments(params, args);
+                }
+            }
+        }
+        x.visit_expr(|x| self.expr(x))
+    }
+
+    fn stmt(&mut self, x: &'a AstStmt) {
+        match &**x {
+            Stmt::Assign(dest, rhs) => {
+                dest.visit_expr(|x| self.expr(x));
+                self.expr(rhs);
+                if let Assign::Identifier(name) = &**dest {
+                    if let Some(t) = self.infer(rhs) {
+                        let scope = self.scopes.last_mut().unwrap();
+                        if scope.bound.get(name.0.as_str()) == Some(&1) {
+                            scope.types.insert(name.0.as_str(), t);
+                        }
+                        if !is_obvious(rhs) {
+                            let pos = self.codemap.resolve_span(name.span);
+                            self.add(pos.end_line, pos.end_column, t, InlayHintKind::Type);
+                        }
+                    }
+                }
+            }
+            Stmt::Def(_, params, ret, body, _) => {
+                for p in params {
+                    p.visit_expr(|x| self.expr(x));
+                }
+                if let Some(ret) = ret {
+                    self.expr(ret);
+                }
+                let mut scope = Scope::default();
+                bindings(body, &mut scope.bound);
+                for p in params {
+                    if let (Some(name), typ, _) = p.split() {
+                        *scope.bound.entry(name.0.as_str()).or_default() += 1;
+                        let typ = match &**p {
+                            Parameter::Args(..) => Some("tuple"),
+                            Parameter::KwArgs(..) => Some("dict"),
+                            _ => annotation(typ),
+                        };
+                        if let Some(typ) = typ {
+                            scope.types.insert(name.0.as_str(), typ);
+                        }
+                    }
+                }
+                // Parameters rebound in the body don't have a fixed type
+                let bound = &scope.bound;
+                scope.types.retain(|name, _| bound[name] == 1);
+                self.scopes.push(scope);
+                self.stmt(body);
+                self.scopes.pop();
+            }
+            _ => x.visit_children(|x| match x {
+                Visit::Stmt(x) => self.stmt(x),
+                Visit::Expr(x) => self.expr(x),
+            }),
+        }
+    }
+}
+
+impl AstModule {
+    /// Hints to show inline in an editor: the types of variables where they are assigned,
+    /// as far as they can be inferred syntactically, and the names of the parameters
+    /// positional arguments are passed to, for calls to the `def`s of this module.
+    /// Types which are obvious from the assigned expression, e.g. a literal, are omitted.
+    pub fn inlay_hints(&self) -> Vec<InlayHint> {
+        let mut module = Scope::default();
+        bindings(&self.statement, &mut module.bound);
+        let mut defs = HashMap::new();
+        let top = match &*self.statement {
+            Stmt::Statements(xs) => xs.as_slice(),
+            _ => std::slice::from_ref(&self.statement),
+        };
+        for x in top {
+            if let Stmt::Def(name, params, ret, _, _) = &**x {
+                if module.bound.get(name.0.as_str()) == Some(&1) {
+                    defs.insert(name.0.as_str(), (params.as_slice(), ret.as_deref()));
+                }
+            }
+        }
+        let mut res = Hints {
+            codemap: &self.codemap,
+            defs,
+            scopes: vec![module],
+            res: Vec::new(),
+        };
+        res.stmt(&self.statement);
+        res.res
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use gazebo::prelude::*;
+
+    use super::*;
+    use crate::syntax::Dialect;
+
+    fn hints(x: &str) -> Vec<String> {
+        AstModule::parse("X", x.to_owned(), &Dialect::Extended)
+            .unwrap()
+            .inlay_hints()
+            .map(|x| format!("{}:{} {:?} {}", x.line, x.column, x.kind, x.label))
+    }
+
+    #[test]
+    fn test_inlay_hints() {
+        let res = hints(
+            r#"
+a = 1
+b = a + 2
+c = b / 2
+def f(x: "string", y, *args, z = 1) -> "string":
+    w = x + "!"
+    v = y
+    return w
+d = f("hello", a)
+e = f(x, b, c, d, z = 4)
+e = str(c) if d else repr(c)
+"#,
+        );
+        assert_eq!(
+            res,
+            &[
+                "2:1 Type int",
+                "3:1 Type float",
+                "5:5 Type string",
+                "8:6 Parameter x",
+                "8:15 Parameter y",
+                "8:1 Type string",
+                "9:9 Parameter y",
+                "9:1 Type string",
+                "10:1 Type string",
+            ]
+        );
+    }
+}
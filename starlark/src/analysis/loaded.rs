@@ -0,0 +1,116 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::{
+    codemap::ResolvedSpan,
+    syntax::{
+        ast::{AstStmt, Stmt},
+        AstModule,
+    },
+};
+
+/// A symbol bound by a `load` statement, as returned by [`AstModule::loaded_symbols`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadedSymbol<'a> {
+    /// The module being loaded from, e.g. `//pkg:file.bzl`.
+    pub module: &'a str,
+    /// The span of the module string, including the quotes.
+    pub module_span: ResolvedSpan,
+    /// The name the symbol is bound to in this module.
+    pub name: &'a str,
+    /// The span of the name the symbol is bound to.
+    pub name_span: ResolvedSpan,
+    /// The name of the symbol in the module being loaded from.
+    /// Differs from `name` for loads of the form `load("m", name = "loaded")`.
+    pub loaded: &'a str,
+}
+
+impl AstModule {
+    /// The symbols bound by `load` statements in this module, in the order they are loaded.
+    pub fn loaded_symbols(&self) -> Vec<LoadedSymbol> {
+        // Like `loads`, we only need to look at the top-level.
+        fn f<'a>(module: &'a AstModule, ast: &'a AstStmt, res: &mut Vec<LoadedSymbol<'a>>) {
+            match &ast.node {
+                Stmt::Load(load) => {
+                    let module_span = module.codemap.resolve_span(load.module.span);
+                    for (name, loaded) in &load.args {
+                        res.push(LoadedSymbol {
+                            module: &load.module.node,
+                            module_span,
+                            name: &name.0,
+                            name_span: module.codemap.resolve_span(name.span),
+                            loaded: &loaded.node,
+                        });
+                    }
+                }
+                Stmt::Statements(stmts) => {
+                    for s in stmts {
+                        f(module, s, res);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut res = Vec::new();
+        f(self, &self.statement, &mut res);
+        res
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use gazebo::prelude::*;
+
+    use crate::syntax::{AstModule, Dialect};
+
+    #[test]
+    fn test_loaded_symbols() {
+        let m = AstModule::parse(
+            "X",
+            r#"
+load("//pkg:a.bzl", "x", y = "z")
+def f():
+    pass
+load(":b.bzl", "w")
+"#
+            .to_owned(),
+            &Dialect::Extended,
+        )
+        .unwrap();
+        let res = m.loaded_symbols().map(|x| {
+            format!(
+                "{} {}:{} {} {}:{}",
+                x.module,
+                x.module_span.begin_line,
+                x.module_span.begin_column,
+                x.name,
+                x.name_span.begin_line,
+                x.name_span.begin_column,
+            )
+        });
+        assert_eq!(
+            res,
+            &[
+                "//pkg:a.bzl 1:5 x 1:20",
+                "//pkg:a.bzl 1:5 y 1:25",
+                ":b.bzl 4:5 w 4:15",
+            ]
+        );
+        assert_eq!(m.loaded_symbols()[1].loaded, "z");
+    }
+}
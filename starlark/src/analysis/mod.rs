@@ -15,18 +15,33 @@
  * limitations under the License.
  */
 
-pub use types::Lint;
+pub use completion::SymbolKind;
+pub use constants::DuplicateConstant;
+pub use folding::{FoldingRange, FoldingRangeKind};
+pub use highlight::{Highlight, HighlightKind};
+pub use inlay::{InlayHint, InlayHintKind};
+pub use loaded::LoadedSymbol;
+pub use symbols::Symbol;
+pub use types::{Lint, LintFix};
 
 use crate::{analysis::types::LintT, syntax::AstModule};
 
 mod bind;
+mod completion;
+mod constants;
 mod dubious;
 mod exported;
 mod flow;
+mod folding;
+mod highlight;
 mod incompatible;
+mod inlay;
+mod loaded;
 mod names;
 mod performance;
+mod symbols;
 mod types;
+mod typing;
 
 impl AstModule {
     /// Run a static linter over the module. If the complete set of global variables are known
@@ -49,4 +64,13 @@ impl AstModule {
         res.extend(performance::performance(self).into_iter().map(LintT::erase));
         res
     }
+
+    /// Statically check literal values against the type annotations they must match:
+    /// arguments in calls to the functions defined at the top of this module, and values
+    /// returned from functions with a return type annotation. Also reports annotations which
+    /// would be rejected at runtime. Only problems the runtime checks would also raise are
+    /// reported, and these checks are not run by [`lint`](AstModule::lint).
+    pub fn typecheck(&self) -> Vec<Lint> {
+        typing::typecheck(self).into_iter().map(LintT::erase).collect()
+    }
 }
@@ -26,9 +26,9 @@ use crate::{
         bind::{Assigner, Bind, Scope},
         types::{LintT, LintWarning},
     },
-    codemap::{CodeMap, Span},
+    codemap::{CodeMap, Pos, Span},
     syntax::{
-        ast::{Assign, AstStmt, Expr, Stmt},
+        ast::{Assign, AstLoad, AstStmt, Expr, Stmt},
         AstModule,
     },
 };
@@ -88,7 +88,46 @@ pub(crate) fn name_warnings(
     }
     inappropriate_underscore(&module.codemap, &module.statement, true, &mut res);
     use_ignored(&module.codemap, &scope, None, &mut res);
-    res
+    res.into_map(|x| {
+        let edits = match x.problem {
+            NameWarning::UnusedLoad(_) => remove_load(module, x.location.span),
+            _ => None,
+        };
+        match edits {
+            Some(edits) => x.with_fix(&module.codemap, "Remove unused load", edits),
+            None => x,
+        }
+    })
+}
+
+fn find_load(x: &AstStmt, span: Span) -> Option<(Span, &AstLoad)> {
+    match &**x {
+        Stmt::Load(load) if load.node.args.iter().any(|x| x.0.span == span) => Some((x.span, load)),
+        Stmt::Statements(xs) => xs.iter().find_map(|x| find_load(x, span)),
+        _ => None,
+    }
+}
+
+/// The edits which remove the symbol bound at `span` from its `load`, or the whole
+/// statement if it is the only symbol.
+fn remove_load(module: &AstModule, span: Span) -> Option<Vec<(Span, String)>> {
+    let (stmt, load) = find_load(&module.statement, span)?;
+    let args = &load.node.args;
+    let i = args.iter().position(|x| x.0.span == span)?;
+    let remove = if args.len() == 1 {
+        // Take the line terminator too, so we don't leave a blank line
+        let rest = &module.codemap.source()[stmt.end().get() as usize..];
+        if rest.starts_with('\n') {
+            Span::new(stmt.begin(), Pos::new(stmt.end().get() + 1))
+        } else {
+            stmt
+        }
+    } else if i == 0 {
+        Span::new(args[0].0.span.begin(), args[1].0.span.begin())
+    } else {
+        Span::new(args[i - 1].1.span.end(), args[i].1.span.end())
+    };
+    Some(vec![(remove, String::new())])
 }
 
 fn undefined_variable(
@@ -338,6 +377,36 @@ def foo():
         assert_eq!(res, &["_no2", "_no4", "_no6", "no1", "no3", "no5"]);
     }
 
+    #[test]
+    fn test_lint_fix_unused_load() {
+        let m = module(
+            r#"load("a", "x")
+load("b", "y", z = "z")
+load("c", "w", "v")
+print(z, w)
+"#,
+        );
+        let fixed = |name: &str| {
+            let lint = name_warnings(&m, None)
+                .into_iter()
+                .find(|x| matches!(&x.problem, NameWarning::UnusedLoad(x) if x == name))
+                .unwrap();
+            let mut res = m.codemap.source().to_owned();
+            for (span, text) in lint.fix.unwrap().edits.iter().rev() {
+                let begin = m.codemap.find_pos(span.begin_line, span.begin_column);
+                let end = m.codemap.find_pos(span.end_line, span.end_column);
+                res.replace_range(begin.get() as usize..end.get() as usize, text);
+            }
+            res
+        };
+        assert_eq!(
+            fixed("x"),
+            "load(\"b\", \"y\", z = \"z\")\nload(\"c\", \"w\", \"v\")\nprint(z, w)\n"
+        );
+        assert!(fixed("y").starts_with("load(\"a\", \"x\")\nload(\"b\", z = \"z\")\n"));
+        assert!(fixed("v").contains("\nload(\"c\", \"w\")\n"));
+    }
+
     #[test]
     fn test_lint_duplicate_assign() {
         let m = module(
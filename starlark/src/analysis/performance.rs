@@ -44,11 +44,19 @@ fn match_dict_copy(codemap: &CodeMap, x: &AstExpr, res: &mut Vec<LintT<Performan
     match &**x {
         Expr::Call(fun, args) if args.len() == 1 => match (&***fun, &*args[0]) {
             (Expr::Identifier(f, _), Argument::KwArgs(arg)) if f.node == "dict" => {
-                res.push(LintT::new(
-                    codemap,
-                    x.span,
-                    Performance::DictWithoutStarStar(x.to_string(), format!("dict({})", arg.node)),
-                ))
+                let replacement = format!("dict({})", arg.node);
+                res.push(
+                    LintT::new(
+                        codemap,
+                        x.span,
+                        Performance::DictWithoutStarStar(x.to_string(), replacement.clone()),
+                    )
+                    .with_fix(
+                        codemap,
+                        "Copy the dict directly",
+                        vec![(x.span, replacement)],
+                    ),
+                )
             }
             _ => {}
         },
@@ -0,0 +1,168 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use crate::{
+    analysis::SymbolKind,
+    codemap::{CodeMap, ResolvedSpan, Span},
+    syntax::{
+        ast::{AstStmt, Stmt},
+        AstModule,
+    },
+};
+
+/// A symbol defined in a module, as returned by [`AstModule::symbols`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    /// The name of the symbol.
+    pub name: String,
+    /// How the symbol was defined.
+    pub kind: SymbolKind,
+    /// The span of the entire definition, e.g. the whole `def` statement.
+    pub span: ResolvedSpan,
+    /// The span of just the name.
+    pub name_span: ResolvedSpan,
+    /// The symbols defined within this one, e.g. the parameters and locals of a `def`.
+    pub children: Vec<Symbol>,
+}
+
+struct Symbols<'a> {
+    codemap: &'a CodeMap,
+    res: Vec<Symbol>,
+}
+
+impl Symbols<'_> {
+    fn add(&mut self, name: &str, kind: SymbolKind, span: Span, name_span: Span) -> &mut Symbol {
+        self.res.push(Symbol {
+            name: name.to_owned(),
+            kind,
+            span: self.codemap.resolve_span(span),
+            name_span: self.codemap.resolve_span(name_span),
+            children: Vec::new(),
+        });
+        self.res.last_mut().unwrap()
+    }
+
+    fn stmt(&mut self, x: &AstStmt) {
+        match &**x {
+            Stmt::Assign(dest, _) => dest.visit_lvalue(|name| {
+                self.add(&name.0, SymbolKind::Variable, x.span, name.span);
+            }),
+            Stmt::For(dest, box (_, body)) => {
+                dest.visit_lvalue(|name| {
+                    self.add(&name.0, SymbolKind::Variable, x.span, name.span);
+                });
+                self.stmt(body)
+            }
+            Stmt::Def(name, params, _, body, _) => {
+                let mut inner = Symbols {
+                    codemap: self.codemap,
+                    res: Vec::new(),
+                };
+                for p in params {
+                    if let (Some(name), _, _) = p.split() {
+                        inner.add(&name.0, SymbolKind::Parameter, p.span, name.span);
+                    }
+                }
+                inner.stmt(body);
+                let children = inner.res;
+                self.add(&name.0, SymbolKind::Function, x.span, name.span).children = children;
+            }
+            Stmt::Load(load) => {
+                for (name, _) in &load.node.args {
+                    self.add(&name.0, SymbolKind::Load, x.span, name.span);
+                }
+            }
+            _ => x.visit_stmt(|x| self.stmt(x)),
+        }
+    }
+}
+
+impl AstModule {
+    /// An outline of the symbols defined by this module: the `load`s, `def`s and
+    /// assignments, in the order they appear. Each `def` contains its parameters and
+    /// the symbols defined in its body. A name assigned multiple times appears multiple times.
+    pub fn symbols(&self) -> Vec<Symbol> {
+        let mut res = Symbols {
+            codemap: &self.codemap,
+            res: Vec::new(),
+        };
+        res.stmt(&self.statement);
+        res.res
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use gazebo::prelude::*;
+
+    use super::*;
+    use crate::syntax::Dialect;
+
+    fn outline(x: &[Symbol]) -> Vec<String> {
+        x.map(|x| {
+            let children = outline(&x.children);
+            if children.is_empty() {
+                format!("{} {:?} {}", x.name, x.kind, x.name_span)
+            } else {
+                format!(
+                    "{} {:?} {} [{}]",
+                    x.name,
+                    x.kind,
+                    x.name_span,
+                    children.join(", ")
+                )
+            }
+        })
+    }
+
+    #[test]
+    fn test_symbols() {
+        let modu = AstModule::parse(
+            "X",
+            r#"
+load("test", "a", b = "c")
+d = 1
+def e(f, *args, g = 2, **kwargs):
+    h = [i for i in f]
+    def j():
+        pass
+    return h
+if d:
+    k, l = 3, 4
+    for m in []:
+        pass
+"#
+            .to_owned(),
+            &Dialect::Extended,
+        )
+        .unwrap();
+        assert_eq!(
+            outline(&modu.symbols()),
+            &[
+                "a Load 2:14-17",
+                "b Load 2:19-20",
+                "d Variable 3:1-2",
+                "e Function 4:5-6 [f Parameter 4:7-8, args Parameter 4:11-15, g Parameter 4:17-18, kwargs Parameter 4:26-32, h Variable 5:5-6, j Function 6:9-10]",
+                "k Variable 10:5-6",
+                "l Variable 10:8-9",
+                "m Variable 11:9-10",
+            ]
+        );
+        let d = &modu.symbols()[2];
+        assert_eq!(d.span.to_string(), "3:1-6");
+    }
+}
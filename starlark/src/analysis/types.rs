@@ -17,9 +17,9 @@
 
 use std::fmt::{self, Display};
 
-use gazebo::variants::VariantName;
+use gazebo::{prelude::*, variants::VariantName};
 
-use crate::codemap::{CodeMap, FileSpan, Span};
+use crate::codemap::{CodeMap, FileSpan, ResolvedSpan, Span};
 
 pub(crate) trait LintWarning: Display + VariantName {
     fn is_serious(&self) -> bool;
@@ -31,6 +31,17 @@ pub(crate) struct LintT<T> {
     pub location: FileSpan,
     pub original: String,
     pub problem: T,
+    pub fix: Option<LintFix>,
+    pub related: Vec<(FileSpan, String)>,
+}
+
+/// A suggested fix for a [`Lint`], made up of replacements in the source code.
+#[derive(Debug, Clone)]
+pub struct LintFix {
+    /// A short description of the fix, e.g. `Remove unused load`.
+    pub title: String,
+    /// The spans to replace, with their replacement text. The spans do not overlap.
+    pub edits: Vec<(ResolvedSpan, String)>,
 }
 
 /// A lint produced by [`AstModule::lint`](crate::syntax::AstModule::lint).
@@ -47,6 +58,11 @@ pub struct Lint {
     pub problem: String,
     /// The source code at [`location`](Lint::location).
     pub original: String,
+    /// A fix which can be applied automatically, if there is one.
+    pub fix: Option<LintFix>,
+    /// Other locations involved in the problem, each with a short description,
+    /// e.g. the type annotation a value fails to match.
+    pub related: Vec<(FileSpan, String)>,
 }
 
 impl Display for Lint {
@@ -68,9 +84,32 @@ impl<T: LintWarning> LintT<T> {
             original: location.file.source_span(span).to_owned(),
             location,
             problem,
+            fix: None,
+            related: Vec::new(),
         }
     }
 
+    /// Attach another location involved in the problem.
+    pub(crate) fn with_related(mut self, codemap: &CodeMap, span: Span, message: &str) -> Self {
+        self.related
+            .push((codemap.file_span(span), message.to_owned()));
+        self
+    }
+
+    /// Attach a fix, given as replacements of spans in the module.
+    pub(crate) fn with_fix(
+        mut self,
+        codemap: &CodeMap,
+        title: &str,
+        edits: Vec<(Span, String)>,
+    ) -> Self {
+        self.fix = Some(LintFix {
+            title: title.to_owned(),
+            edits: edits.into_map(|(span, x)| (codemap.resolve_span(span), x)),
+        });
+        self
+    }
+
     pub(crate) fn erase(self) -> Lint {
         Lint {
             location: self.location,
@@ -78,6 +117,8 @@ impl<T: LintWarning> LintT<T> {
             serious: self.problem.is_serious(),
             problem: self.problem.to_string(),
             original: self.original,
+            fix: self.fix,
+            related: self.related,
         }
     }
 }
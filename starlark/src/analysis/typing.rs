@@ -0,0 +1,458 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! A static check of literal values against type annotations, which reports
+//! the problems the runtime type checks would raise, without running the code.
+
+use std::collections::HashMap;
+
+use gazebo::{prelude::*, variants::VariantName};
+use thiserror::Error;
+
+use crate::{
+    analysis::types::{LintT, LintWarning},
+    codemap::CodeMap,
+    syntax::{
+        ast::{
+            Argument, AstArgument, AstExpr, AstLiteral, AstParameter, AstStmt, Expr, Parameter,
+            Stmt,
+        },
+        uniplate::Visit,
+        AstModule,
+    },
+};
+
+#[derive(Error, Debug, VariantName)]
+pub(crate) enum TypeProblem {
+    #[error("Argument `{1}` for parameter `{0}` does not match the type annotation `{2}`")]
+    IncompatibleArgument(String, String, String),
+    #[error("Returned value `{0}` does not match the return type annotation `{1}`")]
+    IncompatibleReturn(String, String),
+    #[error("Type `{0}` is not a valid type annotation")]
+    InvalidAnnotation(String),
+    #[error(r#"Found `{0}` instead of a valid type annotation. Perhaps you meant `"{1}"`?"#)]
+    UnquotedAnnotation(String, String),
+}
+
+impl LintWarning for TypeProblem {
+    fn is_serious(&self) -> bool {
+        // Invalid annotations fail when the `def` is run, so are less likely to go unnoticed
+        matches!(
+            self,
+            Self::IncompatibleArgument(..) | Self::IncompatibleReturn(..)
+        )
+    }
+}
+
+/// The builtin types which can be written `name.type`, with the name of the type.
+const BUILTIN_TYPES: &[(&str, &str)] = &[
+    ("bool", "bool"),
+    ("bytes", "bytes"),
+    ("dict", "dict"),
+    ("float", "float"),
+    ("int", "int"),
+    ("list", "list"),
+    ("str", "string"),
+    ("tuple", "tuple"),
+];
+
+/// A type annotation, as far as we can understand it statically.
+enum Ty {
+    /// Matches anything, e.g. `""`.
+    Any,
+    /// A type we can't check, e.g. a record type.
+    Unknown,
+    /// A type by name, e.g. `"int"` or `int.type`.
+    Name(String),
+    None,
+    ListOf(Box<Ty>),
+    Union(Vec<Ty>),
+    Tuple(Vec<Ty>),
+    Dict,
+    DictOf(Box<Ty>, Box<Ty>),
+}
+
+/// The shape of a literal value. Sub-values which aren't literals are `None`.
+enum Lit {
+    None,
+    Bool,
+    Int,
+    Float,
+    String,
+    Bytes,
+    List(Vec<Option<Lit>>),
+    Tuple(Vec<Option<Lit>>),
+    Dict(Vec<(Option<Lit>, Option<Lit>)>),
+}
+
+impl Lit {
+    fn new(x: &AstExpr) -> Option<Self> {
+        match &**x {
+            Expr::Literal(AstLiteral::Int(_)) => Some(Lit::Int),
+            Expr::Literal(AstLiteral::Float(_)) => Some(Lit::Float),
+            Expr::Literal(AstLiteral::String(_)) => Some(Lit::String),
+            Expr::Literal(AstLiteral::Bytes(_)) => Some(Lit::Bytes),
+            Expr::Minus(x) | Expr::Plus(x) => match &***x {
+                Expr::Literal(AstLiteral::Int(_)) => Some(Lit::Int),
+                Expr::Literal(AstLiteral::Float(_)) => Some(Lit::Float),
+                _ => None,
+            },
+            Expr::Identifier(x, _) => match x.as_str() {
+                "None" => Some(Lit::None),
+                "True" | "False" => Some(Lit::Bool),
+                _ => None,
+            },
+            Expr::List(xs) => Some(Lit::List(xs.iter().map(Lit::new).collect())),
+            Expr::Tuple(xs) => Some(Lit::Tuple(xs.iter().map(Lit::new).collect())),
+            Expr::Dict(xs) => Some(Lit::Dict(
+                xs.iter().map(|(k, v)| (Lit::new(k), Lit::new(v))).collect(),
+            )),
+            _ => None,
+        }
+    }
+
+    fn type_name(&self) -> &'static str {
+        match self {
+            Lit::None => "NoneType",
+            Lit::Bool => "bool",
+            Lit::Int => "int",
+            Lit::Float => "float",
+            Lit::String => "string",
+            Lit::Bytes => "bytes",
+            Lit::List(_) => "list",
+            Lit::Tuple(_) => "tuple",
+            Lit::Dict(_) => "dict",
+        }
+    }
+}
+
+/// Whether all of the results are true, or `None` if we can't tell.
+fn all(xs: impl IntoIterator<Item = Option<bool>>) -> Option<bool> {
+    let mut res = Some(true);
+    for x in xs {
+        match x {
+            Some(false) => return Some(false),
+            None => res = None,
+            Some(true) => {}
+        }
+    }
+    res
+}
+
+fn matches_opt(ty: &Ty, x: &Option<Lit>) -> Option<bool> {
+    matches(ty, x.as_ref()?)
+}
+
+/// Whether a literal matches a type, following `TypeCompiled`, or `None` if we can't tell.
+fn matches(ty: &Ty, x: &Lit) -> Option<bool> {
+    match (ty, x) {
+        (Ty::Any, _) => Some(true),
+        (Ty::Unknown, _) => None,
+        (Ty::Name(name), _) => Some(name == x.type_name()),
+        (Ty::None, _) => Some(matches!(x, Lit::None)),
+        (Ty::ListOf(t), Lit::List(xs)) => all(xs.iter().map(|x| matches_opt(t, x))),
+        (Ty::Union(ts), _) => {
+            let mut res = Some(false);
+            for t in ts {
+                match matches(t, x) {
+                    Some(true) => return Some(true),
+                    None => res = None,
+                    Some(false) => {}
+                }
+            }
+            res
+        }
+        (Ty::Tuple(ts), Lit::Tuple(xs)) if ts.len() == xs.len() => {
+            all(ts.iter().zip(xs).map(|(t, x)| matches_opt(t, x)))
+        }
+        (Ty::Dict, Lit::Dict(_)) => Some(true),
+        (Ty::DictOf(tk, tv), Lit::Dict(xs)) => all(xs
+            .iter()
+            .flat_map(|(k, v)| [matches_opt(tk, k), matches_opt(tv, v)])),
+        (Ty::ListOf(_) | Ty::Tuple(_) | Ty::Dict | Ty::DictOf(..), _) => Some(false),
+    }
+}
+
+fn is_wildcard(x: &str) -> bool {
+    x.is_empty() || x.starts_with('_')
+}
+
+fn builtin_type(name: &str) -> Option<&'static str> {
+    BUILTIN_TYPES
+        .iter()
+        .find(|(x, _)| *x == name)
+        .map(|(_, ty)| *ty)
+}
+
+impl Ty {
+    /// Convert an annotation, with anything we don't understand becoming `Unknown`.
+    fn new(x: &AstExpr) -> Self {
+        match &**x {
+            Expr::Literal(AstLiteral::String(s)) if is_wildcard(s) => Ty::Any,
+            Expr::Literal(AstLiteral::String(s)) => Ty::Name(s.node.clone()),
+            Expr::Identifier(name, _) if name.node == "None" => Ty::None,
+            Expr::Dot(lhs, attr) if attr.node == "type" => match &***lhs {
+                Expr::Identifier(name, _) => match builtin_type(name) {
+                    Some(ty) => Ty::Name(ty.to_owned()),
+                    None => Ty::Unknown,
+                },
+                _ => Ty::Unknown,
+            },
+            Expr::List(xs) => match xs.as_slice() {
+                [] => Ty::Unknown,
+                [t] => Ty::ListOf(box Ty::new(t)),
+                ts => Ty::Union(ts.map(Ty::new)),
+            },
+            Expr::Tuple(ts) => Ty::Tuple(ts.map(Ty::new)),
+            Expr::Dict(xs) => match xs.as_slice() {
+                [] => Ty::Dict,
+                [(k, v)] => Ty::DictOf(box Ty::new(k), box Ty::new(v)),
+                // Dicts with several keys give the types of specific keys, which we don't check
+                _ => Ty::Unknown,
+            },
+            _ => Ty::Unknown,
+        }
+    }
+}
+
+struct Checker<'a> {
+    codemap: &'a CodeMap,
+    res: Vec<LintT<TypeProblem>>,
+}
+
+impl Checker<'_> {
+    /// Report the parts of an annotation which would fail when the `def` is run.
+    fn annotation(&mut self, x: &AstExpr) {
+        match &**x {
+            Expr::Identifier(name, _) => {
+                if let Some(ty) = builtin_type(name) {
+                    self.res.push(LintT::new(
+                        self.codemap,
+                        x.span,
+                        TypeProblem::UnquotedAnnotation(name.node.clone(), ty.to_owned()),
+                    ));
+                }
+            }
+            Expr::List(xs) if xs.is_empty() => self.res.push(LintT::new(
+                self.codemap,
+                x.span,
+                TypeProblem::InvalidAnnotation(x.to_string()),
+            )),
+            Expr::List(xs) | Expr::Tuple(xs) => xs.iter().for_each(|x| self.annotation(x)),
+            Expr::Dict(xs) => {
+                for (k, v) in xs {
+                    self.annotation(k);
+                    self.annotation(v);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Check a value against an annotation, reporting a mismatch with `problem`.
+    fn check(
+        &mut self,
+        value: &AstExpr,
+        annotation: &AstExpr,
+        ty: &Ty,
+        problem: impl FnOnce(String, String) -> TypeProblem,
+    ) {
+        if let Some(lit) = Lit::new(value) {
+            if matches(ty, &lit) == Some(false) {
+                self.res.push(
+                    LintT::new(
+                        self.codemap,
+                        value.span,
+                        problem(value.to_string(), annotation.to_string()),
+                    )
+                    .with_related(
+                        self.codemap,
+                        annotation.span,
+                        "Type annotation is here",
+                    ),
+                );
+            }
+        }
+    }
+
+    /// Check the `return` statements of a `def`, not descending into nested `def`s.
+    fn returns(&mut self, x: &AstStmt, annotation: &AstExpr, ty: &Ty) {
+        match &**x {
+            Stmt::Return(Some(value)) => {
+                self.check(value, annotation, ty, TypeProblem::IncompatibleReturn)
+            }
+            Stmt::Def(..) => {}
+            _ => x.visit_stmt(|x| self.returns(x, annotation, ty)),
+        }
+    }
+
+    fn call(&mut self, args: &[AstArgument], params: &[Param]) {
+        let mut positional = true;
+        let mut index = 0;
+        for arg in args {
+            let (param, value) = match &**arg {
+                Argument::Positional(value) if positional => {
+                    let param = params.get(index).filter(|p| p.positional);
+                    index += 1;
+                    (param, value)
+                }
+                Argument::Named(name, value) => {
+                    (params.iter().find(|p| p.name == name.node), value)
+                }
+                // After `*args` we no longer know which parameter a positional argument binds
+                _ => {
+                    positional = false;
+                    continue;
+                }
+            };
+            if let Some(Param {
+                name,
+                annotation: Some((annotation, ty)),
+                ..
+            }) = param
+            {
+                self.check(value, annotation, ty, |value, ty| {
+                    TypeProblem::IncompatibleArgument((*name).to_owned(), value, ty)
+                });
+            }
+        }
+    }
+}
+
+/// A parameter of a top-level `def`.
+struct Param<'a> {
+    name: &'a str,
+    /// Whether the parameter can be passed positionally.
+    positional: bool,
+    annotation: Option<(&'a AstExpr, Ty)>,
+}
+
+fn params(params: &[AstParameter]) -> Vec<Param> {
+    let mut res = Vec::new();
+    let mut positional = true;
+    for p in params {
+        let (name, annotation) = match &**p {
+            Parameter::Normal(name, ty) | Parameter::WithDefaultValue(name, ty, _) => (name, ty),
+            // Arguments for `*args` and `**kwargs` are collected, so checking them is more
+            // complex, and after `*` the parameters are named only.
+            Parameter::NoArgs | Parameter::Args(..) | Parameter::KwArgs(..) => {
+                positional = false;
+                continue;
+            }
+        };
+        res.push(Param {
+            name: &name.0,
+            positional,
+            annotation: annotation.as_ref().map(|x| (&**x, Ty::new(x))),
+        });
+    }
+    res
+}
+
+pub(crate) fn typecheck(module: &AstModule) -> Vec<LintT<TypeProblem>> {
+    fn stmt<'a>(checker: &mut Checker, x: &'a AstStmt, defs: &HashMap<&str, Vec<Param<'a>>>) {
+        if let Stmt::Def(_, params, ret, body, _) = &**x {
+            for p in params {
+                if let Parameter::Normal(_, Some(ty))
+                | Parameter::WithDefaultValue(_, Some(ty), _)
+                | Parameter::Args(_, Some(ty))
+                | Parameter::KwArgs(_, Some(ty)) = &**p
+                {
+                    checker.annotation(ty);
+                }
+            }
+            if let Some(ret) = ret {
+                checker.annotation(ret);
+                checker.returns(body, ret, &Ty::new(ret));
+            }
+        }
+        x.visit_children(|x| match x {
+            Visit::Stmt(x) => stmt(checker, x, defs),
+            Visit::Expr(x) => expr(checker, x, defs),
+        });
+    }
+
+    fn expr(checker: &mut Checker, x: &AstExpr, defs: &HashMap<&str, Vec<Param>>) {
+        if let Expr::Call(f, args) = &**x {
+            if let Expr::Identifier(name, _) = &***f {
+                if let Some(params) = defs.get(name.as_str()) {
+                    checker.call(args, params);
+                }
+            }
+        }
+        x.visit_expr(|x| expr(checker, x, defs));
+    }
+
+    let mut checker = Checker {
+        codemap: &module.codemap,
+        res: Vec::new(),
+    };
+
+    // The parameters of each top-level `def`, whose calls we can check
+    let mut defs = HashMap::new();
+    module.statement.visit_stmt(|x| {
+        if let Stmt::Def(name, ps, ..) = &**x {
+            defs.insert(name.0.as_str(), params(ps));
+        }
+    });
+    stmt(&mut checker, &module.statement, &defs);
+    checker.res
+}
+
+#[cfg(test)]
+mod test {
+    use gazebo::prelude::*;
+
+    use super::*;
+    use crate::syntax::Dialect;
+
+    fn module(x: &str) -> AstModule {
+        AstModule::parse("bad.py", x.to_owned(), &Dialect::Extended).unwrap()
+    }
+
+    #[test]
+    fn test_typecheck() {
+        let m = module(
+            r#"
+def f(x: "int", y: [str.type], z: {"": None} = {}, *args, w: ["string", None] = None) -> bool.type:
+    def g() -> "":
+        return 1
+    if x:
+        return "no"
+    return True
+f(1, ["a", "b"], {1: None}, w = "ok")
+f("bad", ["a", 2], z = {1: 2}, w = 3)
+f(1, [], {}, 7, "unchecked")
+def h(a: [], b: int):
+    return f(a, a)
+"#,
+        );
+        let res = typecheck(&m);
+        assert_eq!(
+            res.map(|x| x.to_string()),
+            &[
+                "bad.py:6:16-20: Returned value `\"no\"` does not match the return type annotation `bool.type`",
+                "bad.py:9:3-8: Argument `\"bad\"` for parameter `x` does not match the type annotation `\"int\"`",
+                "bad.py:9:10-18: Argument `[\"a\", 2]` for parameter `y` does not match the type annotation `[str.type]`",
+                "bad.py:9:24-30: Argument `{1: 2}` for parameter `z` does not match the type annotation `{\"\": None}`",
+                "bad.py:9:36-37: Argument `3` for parameter `w` does not match the type annotation `[\"string\", None]`",
+                "bad.py:11:10-12: Type `[]` is not a valid type annotation",
+                "bad.py:11:17-20: Found `int` instead of a valid type annotation. Perhaps you meant `\"int\"`?",
+            ]
+        );
+    }
+}
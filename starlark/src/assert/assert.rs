@@ -89,12 +89,13 @@ fn assert_less_than<'v>(a: Value<'v>, b: Value<'v>) -> anyhow::Result<NoneType>
 }
 
 /// How often we garbage collection _should_ be transparent to the tests,
-/// so we run each test in three configurations.
+/// so we run each test in four configurations.
 #[derive(Clone, Copy, Dupe, Debug)]
 enum GcStrategy {
-    Never,  // Disable GC
-    Auto,   // Use the automatic heuristics (in practice, this does almost no GC)
-    Always, // GC as aggressively as we can
+    Never,        // Disable GC
+    Auto,         // Use the automatic heuristics (in practice, this does almost no GC)
+    Always,       // GC as aggressively as we can
+    Generational, // GC as aggressively as we can, with generational collection enabled
 }
 
 /// Definitions to support assert.star as used by the Go test suite
@@ -183,7 +184,7 @@ fn test_methods(builder: &mut GlobalsBuilder) {
 
     // This is only safe to call at the top-level of a Starlark module
     fn garbage_collect() -> NoneType {
-        eval.trigger_gc();
+        eval.request_gc();
         Ok(NoneType)
     }
 
@@ -247,6 +248,7 @@ impl<'a> Assert<'a> {
                 let res = f(GcStrategy::Auto);
                 f(GcStrategy::Never);
                 f(GcStrategy::Always);
+                f(GcStrategy::Generational);
                 res
             }
             Some(x) => f(x),
@@ -273,16 +275,22 @@ impl<'a> Assert<'a> {
         }
 
         let gc_always = |_, eval: &mut Evaluator| {
-            eval.trigger_gc();
+            eval.request_gc();
         };
 
         match gc {
             GcStrategy::Never => eval.disable_gc(),
             GcStrategy::Auto => {}
-            GcStrategy::Always => eval.before_stmt(&gc_always),
+            GcStrategy::Always => {
+                eval.before_stmt(&gc_always);
+            }
+            GcStrategy::Generational => {
+                eval.enable_generational_gc();
+                eval.before_stmt(&gc_always);
+            }
         }
         eval.set_loader(&loader);
-        eval.eval_module(ast, &self.globals)
+        Ok(eval.eval_module(ast, &self.globals)?)
     }
 
     fn execute_fail<'v>(
@@ -0,0 +1,111 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! A machine-readable description of what this implementation of Starlark supports, for
+//! tools which drive several implementations and need to adapt to each. See [`Capabilities`].
+
+use serde::Serialize;
+
+use crate::{
+    environment::LibraryExtension,
+    eval::{GC_THRESHOLD, MAX_CALLSTACK_RECURSION},
+    syntax::Dialect,
+    values::stack_guard::MAX_RECURSION,
+};
+
+/// The version of the [`Capabilities`] format. Fields may be added without changing it,
+/// but it will be incremented if any are removed or change meaning.
+pub const CAPABILITIES_FORMAT_VERSION: u32 = 1;
+
+/// What an evaluator configured with a [`Dialect`] and [`LibraryExtension`]s supports,
+/// serialized to JSON by `starlark --capabilities`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Capabilities {
+    /// Always [`CAPABILITIES_FORMAT_VERSION`].
+    pub format_version: u32,
+    /// The name of this implementation, `starlark-rust`.
+    pub implementation: &'static str,
+    /// The version of this crate.
+    pub version: &'static str,
+    /// The specification of the language this implementation follows. It isn't versioned,
+    /// so this is the revision of it which the implementation tracks.
+    pub spec: &'static str,
+    /// The language features enabled.
+    pub dialect: Dialect,
+    /// The builtins available beyond the standard ones, named in `snake_case`, e.g. `struct_type`.
+    pub extensions: Vec<LibraryExtension>,
+    /// The limits evaluation is subject to by default.
+    pub limits: Limits,
+}
+
+/// The default limits of evaluation, as reported in [`Capabilities`].
+#[derive(Debug, Clone, Serialize)]
+pub struct Limits {
+    /// The deepest the call stack can get before a recursion error, unless changed with
+    /// [`Evaluator::set_max_call_depth`](crate::eval::Evaluator::set_max_call_depth).
+    pub max_call_stack_depth: usize,
+    /// The deepest nested values can be compared, hashed or converted to strings.
+    pub max_value_nesting: u32,
+    /// The bytes allocated on the heap between garbage collections, unless disabled by
+    /// [`Evaluator::disable_gc`](crate::eval::Evaluator::disable_gc) or paced by
+    /// [`Evaluator::set_gc_pacing`](crate::eval::Evaluator::set_gc_pacing).
+    pub gc_threshold_bytes: usize,
+}
+
+impl Capabilities {
+    /// The capabilities of an evaluator parsing with `dialect`, whose globals were made by
+    /// [`Globals::extended_by`](crate::environment::Globals::extended_by) with `extensions`.
+    pub fn new(dialect: &Dialect, extensions: &[LibraryExtension]) -> Self {
+        Self {
+            format_version: CAPABILITIES_FORMAT_VERSION,
+            implementation: "starlark-rust",
+            version: env!("CARGO_PKG_VERSION"),
+            spec: "https://github.com/bazelbuild/starlark/blob/master/spec.md",
+            dialect: dialect.clone(),
+            extensions: extensions.to_vec(),
+            limits: Limits {
+                max_call_stack_depth: MAX_CALLSTACK_RECURSION,
+                max_value_nesting: MAX_RECURSION,
+                gc_threshold_bytes: GC_THRESHOLD,
+            },
+        }
+    }
+
+    /// The report as a single line of JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_capabilities() {
+        let json: serde_json::Value = serde_json::from_str(
+            &Capabilities::new(&Dialect::Standard, &[LibraryExtension::StructType]).to_json(),
+        )
+        .unwrap();
+        assert_eq!(json["format_version"], 1);
+        assert_eq!(json["implementation"], "starlark-rust");
+        assert_eq!(json["dialect"]["enable_def"], true);
+        assert_eq!(json["dialect"]["enable_types"], false);
+        assert_eq!(json["extensions"], serde_json::json!(["struct_type"]));
+        assert_eq!(json["limits"]["max_call_stack_depth"], 40);
+    }
+}
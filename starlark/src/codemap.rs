@@ -30,10 +30,22 @@ use std::{
 };
 
 use gazebo::prelude::*;
+use serde::{Deserialize, Serialize};
 
 /// A small, `Copy`, value representing a position in a `CodeMap`'s file.
 #[derive(
-    Copy, Clone, Dupe, Hash, Eq, PartialEq, Ord, PartialOrd, Debug, Default
+    Copy,
+    Clone,
+    Dupe,
+    Hash,
+    Eq,
+    PartialEq,
+    Ord,
+    PartialOrd,
+    Debug,
+    Default,
+    Serialize,
+    Deserialize
 )]
 pub struct Pos(u32);
 
@@ -55,7 +67,18 @@ impl Add<u32> for Pos {
 }
 
 /// A range of text within a CodeMap.
-#[derive(Copy, Dupe, Clone, Hash, Eq, PartialEq, Debug, Default)]
+#[derive(
+    Copy,
+    Dupe,
+    Clone,
+    Hash,
+    Eq,
+    PartialEq,
+    Debug,
+    Default,
+    Serialize,
+    Deserialize
+)]
 pub struct Span {
     /// The position in the codemap representing the first byte of the span.
     begin: Pos,
@@ -113,7 +136,7 @@ impl Span {
 }
 
 /// Associate a Span with a value of arbitrary type (e.g. an AST node).
-#[derive(Clone, PartialEq, Eq, Hash, Debug, Copy)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Copy, Serialize, Deserialize)]
 pub struct Spanned<T> {
     pub node: T,
     pub span: Span,
@@ -250,6 +273,23 @@ impl CodeMap {
         LineCol { line, column }
     }
 
+    /// Gets the `Pos` of a line and column, the inverse of `find_line_col`.
+    ///
+    /// The line and column are 0-indexed, with the column counted in characters.
+    /// Lines or columns beyond the end of the file (or line) are clamped to the end.
+    pub fn find_pos(&self, line: usize, column: usize) -> Pos {
+        if line >= self.0.lines.len() {
+            return self.full_span().end();
+        }
+        let line_span = self.line_span(line);
+        let text = self.source_span(line_span).trim_end_matches(&['\n', '\r'][..]);
+        let byte_col = text
+            .char_indices()
+            .nth(column)
+            .map_or(text.len(), |(i, _)| i);
+        line_span.begin() + byte_col as u32
+    }
+
     /// Gets the full source text of the file
     pub fn source(&self) -> &str {
         &self.0.source
@@ -504,6 +544,21 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_find_pos() {
+        let content = "abc\n汉语x\n";
+        let codemap = CodeMap::new("<test>".to_owned(), content.to_owned());
+        assert_eq!(codemap.find_pos(0, 0), Pos(0));
+        assert_eq!(codemap.find_pos(0, 2), Pos(2));
+        assert_eq!(codemap.find_pos(0, 10), Pos(3));
+        assert_eq!(codemap.find_pos(1, 2), Pos(10));
+        assert_eq!(codemap.find_pos(7, 0), Pos(content.len() as u32));
+        for pos in [0, 2, 4, 7, 10] {
+            let LineCol { line, column } = codemap.find_line_col(Pos(pos));
+            assert_eq!(codemap.find_pos(line, column), Pos(pos));
+        }
+    }
+
     #[test]
     fn test_line_col_span_display_point() {
         let line_col = LineCol { line: 0, column: 0 };
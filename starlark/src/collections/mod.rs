@@ -30,7 +30,7 @@ pub use crate::collections::{
 pub(crate) mod alloca;
 mod hash;
 pub(crate) mod hasher;
-mod idhasher;
+pub(crate) mod idhasher;
 pub mod small_map;
 mod small_set;
 pub(crate) mod stack;
@@ -17,7 +17,15 @@
 
 use std::{collections::HashMap, mem};
 
-use crate::{debug::inspect::to_scope_names, eval::Evaluator, syntax::AstModule, values::Value};
+use thiserror::Error;
+
+use crate::{debug::inspect::local_scope, eval::Evaluator, syntax::AstModule, values::Value};
+
+#[derive(Debug, Error)]
+enum EvaluateError {
+    #[error("Expected a single expression")]
+    NotExpression,
+}
 
 impl<'v, 'a> Evaluator<'v, 'a> {
     /// Evaluate statements in the existing context. This function is designed for debugging,
@@ -26,7 +34,35 @@ impl<'v, 'a> Evaluator<'v, 'a> {
     /// There are lots of health warnings on this code. Might not work with frozen modules, unassigned variables,
     /// nested definitions etc. It would be a bad idea to rely on the results of continued execution
     /// after evaluating stuff randomly.
+    ///
+    /// The statements are run with [`reentrant`](Evaluator::reentrant), so can be evaluated
+    /// from a `before_stmt` function or [`CallHook`](crate::eval::CallHook).
     pub fn eval_statements(&mut self, statements: AstModule) -> anyhow::Result<Value<'v>> {
+        self.reentrant(|eval| eval.eval_statements_reentrant(statements))
+    }
+
+    /// Evaluate a single expression in the existing context, e.g. the condition of a
+    /// breakpoint. This function is designed for debugging, not production use.
+    ///
+    /// It sees the same variables as [`eval_statements`](Evaluator::eval_statements), but
+    /// the module variables are put back how they were afterwards, and garbage collection
+    /// is only disabled while the expression is evaluated, so evaluation can carry on as
+    /// normal. Values the expression modifies, e.g. with `xs.append(1)`, stay modified.
+    pub fn eval_expression(&mut self, expression: AstModule) -> anyhow::Result<Value<'v>> {
+        if !expression.is_expression() {
+            return Err(EvaluateError::NotExpression.into());
+        }
+        let disable_gc = self.disable_gc;
+        let snapshot = self.module_env.snapshot();
+        let res = self.eval_statements(expression);
+        let restored = self.module_env.restore(snapshot);
+        self.module_env.discard_snapshot(snapshot);
+        self.disable_gc = disable_gc;
+        restored?;
+        res
+    }
+
+    fn eval_statements_reentrant(&mut self, statements: AstModule) -> anyhow::Result<Value<'v>> {
         // We are doing a lot of funky stuff here. It's amazing anything works, so let's not push our luck with GC.
         self.disable_gc();
 
@@ -57,15 +93,10 @@ impl<'v, 'a> Evaluator<'v, 'a> {
         }
 
         // Push all local variables into the module
-        let locals = self
-            .call_stack
-            .to_function_values()
-            .into_iter()
-            .rev()
-            .find_map(to_scope_names);
-        if let Some(names) = &locals {
-            for (name, (slot, _binding_id)) in &names.mp {
-                if let Some(value) = self.current_frame.get_slot(*slot) {
+        let locals = local_scope(self);
+        if let Some((names, position)) = locals {
+            for (name, slot) in names.live_names(position) {
+                if let Some(value) = self.current_frame.get_slot(slot) {
                     self.module_env.set(name, value)
                 }
             }
@@ -78,10 +109,11 @@ impl<'v, 'a> Evaluator<'v, 'a> {
 
         // Now put the Module back how it was before we started, as best we can
         // and move things into locals if that makes sense
-        if let Some(names) = &locals {
-            for (name, (slot, _binding_id)) in &names.mp {
+        if let Some((names, position)) = locals {
+            // Of locals sharing a slot, only the one live here was moved over
+            for (name, slot) in names.live_names(position) {
                 if let Some(value) = self.module_env.get(name) {
-                    self.current_frame.set_slot(*slot, value)
+                    self.current_frame.set_slot(slot, value)
                 }
             }
             for (name, slot) in self.module_env.names().all_names() {
@@ -93,7 +125,7 @@ impl<'v, 'a> Evaluator<'v, 'a> {
             }
         }
 
-        res
+        Ok(res?)
     }
 }
 
@@ -110,6 +142,34 @@ mod tests {
             let ast = AstModule::parse("interactive", code, &Dialect::Extended)?;
             eval.eval_statements(ast)
         }
+
+        fn debug_expression(code: String) -> Value<'v> {
+            let ast = AstModule::parse("interactive", code, &Dialect::Extended)?;
+            let disable_gc = eval.disable_gc;
+            let res = eval.eval_expression(ast)?;
+            assert_eq!(disable_gc, eval.disable_gc);
+            Ok(res)
+        }
+    }
+
+    #[test]
+    fn test_debug_expression() {
+        let mut a = assert::Assert::new();
+        a.globals_add(debugger);
+        a.pass(
+            r#"
+x = 7
+def f(y):
+    return debug_expression("x + y")
+assert_eq(f(4), 11)
+def g(x):
+    return debug_expression("x")
+assert_eq(g(1), 1)
+# The local `x` was only visible to the expression.
+assert_eq(x, 7)
+"#,
+        );
+        a.fail("debug_expression('x = 1')", "Expected a single expression");
     }
 
     #[test]
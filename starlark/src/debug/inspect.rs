@@ -16,6 +16,7 @@
  */
 
 use crate::{
+    codemap::Span,
     collections::SmallMap,
     eval::{Def, Evaluator, FrozenDef, ScopeNames},
     values::{Value, ValueLike},
@@ -29,6 +30,16 @@ pub(crate) fn to_scope_names<'v>(x: Value<'v>) -> Option<&'v ScopeNames> {
     }
 }
 
+/// The names of the innermost `def` on the call-stack, with the position it has reached,
+/// if it is calling another function.
+pub(crate) fn local_scope<'v>(eval: &Evaluator<'v, '_>) -> Option<(&'v ScopeNames, Option<Span>)> {
+    eval.call_stack
+        .to_function_values_and_positions()
+        .into_iter()
+        .rev()
+        .find_map(|(x, position)| Some((to_scope_names(x)?, position)))
+}
+
 impl<'v, 'a> Evaluator<'v, 'a> {
     /// Obtain the local variables currently in scope. When at top-level these will be
     /// [`Module`](crate::environment::Module) variables, otherwise local definitions. The precise number of variables
@@ -40,12 +51,11 @@ impl<'v, 'a> Evaluator<'v, 'a> {
 
 fn inspect_local_variables<'v>(eval: &Evaluator<'v, '_>) -> Option<SmallMap<String, Value<'v>>> {
     // First we find the first entry on the call_stack which contains a Def (and thus has locals)
-    let xs = eval.call_stack.to_function_values();
-    let names = xs.into_iter().rev().find_map(to_scope_names)?;
+    let (names, position) = local_scope(eval)?;
     let mut res = SmallMap::new();
-    for (name, (slot, _binding_id)) in &names.mp {
-        if let Some(v) = eval.current_frame.get_slot(*slot) {
-            res.insert(name.clone(), v);
+    for (name, slot) in names.live_names(position) {
+        if let Some(v) = eval.current_frame.get_slot(slot) {
+            res.insert(name.to_owned(), v);
         }
     }
     Some(res)
@@ -66,8 +76,10 @@ mod tests {
     use gazebo::prelude::*;
 
     use crate::{
-        self as starlark, assert, collections::SmallMap, environment::GlobalsBuilder,
-        values::dict::Dict,
+        self as starlark, assert,
+        collections::SmallMap,
+        environment::GlobalsBuilder,
+        values::{dict::Dict, Heap},
     };
 
     #[starlark_module]
@@ -122,4 +134,17 @@ assert_eq(debug_inspect_variables(), {"root": 12, "f": f, "_ignore": [True]})
 "#,
         );
     }
+
+    #[test]
+    fn test_debug_children() {
+        let heap = Heap::new();
+        let x = heap.alloc((1, "test"));
+        assert_eq!(x.debug_summary(), r#"(1, "test")"#);
+        assert_eq!(
+            x.debug_children()
+                .map(|(k, v)| format!("{} = {}", k, v.to_repr())),
+            &["0 = 1", r#"1 = "test""#]
+        );
+        assert!(heap.alloc(1).debug_children().is_empty());
+    }
 }
@@ -28,14 +28,14 @@ use crate::{
         symbol_map::{Symbol, SymbolMap},
         SmallMap,
     },
-    eval::{Arguments, Evaluator},
+    eval::{Arguments, Coercions, Evaluator},
     stdlib,
     values::{
         docs,
         docs::{DocItem, DocString, DocStringKind},
         function::{NativeAttribute, NativeCallableRawDocs},
         structs::FrozenStruct,
-        types::function::{NativeFunction, NativeMethod},
+        types::function::{BatchedFunction, NativeBatchedFunction, NativeFunction, NativeMethod},
         AllocFrozenValue, FrozenHeap, FrozenHeapRef, FrozenStringValue, FrozenValue, Heap, Value,
         ValueLike,
     },
@@ -56,6 +56,7 @@ struct GlobalsData {
     heap: FrozenHeapRef,
     variables: SymbolMap<FrozenValue>,
     docstring: Option<String>,
+    coercions: Coercions,
 }
 
 #[derive(Debug)]
@@ -78,6 +79,8 @@ pub struct GlobalsBuilder {
     struct_fields: Option<SmallMap<FrozenStringValue, FrozenValue>>,
     // The raw docstring for this module
     docstring: Option<String>,
+    // Coercions applied to arguments of native functions
+    coercions: Coercions,
 }
 
 /// Used to build a [`Methods`] value.
@@ -142,6 +145,16 @@ impl Globals {
             .collect()
     }
 
+    /// Get the attributes of the global `name`, e.g. the members of a struct
+    /// added with [`GlobalsBuilder::struct_`]. Returns [`None`] if `name` is not defined.
+    pub fn attribute_names(&self, name: &str) -> Option<Vec<String>> {
+        self.get(name).map(|x| x.dir_attr())
+    }
+
+    pub(crate) fn coercions(&self) -> Coercions {
+        self.0.coercions
+    }
+
     pub(crate) fn heap(&self) -> &FrozenHeapRef {
         &self.0.heap
     }
@@ -206,6 +219,7 @@ impl GlobalsBuilder {
             variables: SymbolMap::new(),
             struct_fields: None,
             docstring: None,
+            coercions: Coercions::default(),
         }
     }
 
@@ -225,9 +239,7 @@ impl GlobalsBuilder {
     /// all those defined in [`LibraryExtension`].
     pub fn extended_by(extensions: &[LibraryExtension]) -> Self {
         let mut res = Self::standard();
-        for x in extensions {
-            x.add(&mut res);
-        }
+        LibraryExtension::add_all(extensions, &mut res);
         res
     }
 
@@ -245,6 +257,15 @@ impl GlobalsBuilder {
         self.set(name, FrozenStruct::new(fields));
     }
 
+    /// Enable [`Coercions`] for arguments to native functions, when evaluating
+    /// with the resulting [`Globals`]. By default no coercions apply.
+    /// They apply to every native function the evaluation calls, including those from
+    /// other [`Globals`], and not to calls of these functions from evaluations with
+    /// other [`Globals`].
+    pub fn set_coercions(&mut self, coercions: Coercions) {
+        self.coercions = coercions;
+    }
+
     /// A fluent API for modifying [`GlobalsBuilder`] and returning the result.
     pub fn with(mut self, f: impl FnOnce(&mut Self)) -> Self {
         f(&mut self);
@@ -263,6 +284,7 @@ impl GlobalsBuilder {
             heap: self.heap.into_ref(),
             variables: self.variables,
             docstring: self.docstring,
+            coercions: self.coercions,
         }))
     }
 
@@ -306,6 +328,18 @@ impl GlobalsBuilder {
         )
     }
 
+    /// Set a native function whose calls in list comprehensions are batched,
+    /// see [`BatchedFunction`].
+    pub fn set_batched_function(&mut self, name: &str, function: impl BatchedFunction) {
+        self.set(
+            name,
+            NativeBatchedFunction {
+                function: box function,
+                name: name.to_owned(),
+            },
+        )
+    }
+
     /// Allocate a value using the same underlying heap as the [`GlobalsBuilder`],
     /// only intended for values that are referred to by those which are passed
     /// to [`set`](GlobalsBuilder::set).
@@ -52,4 +52,8 @@ pub(crate) enum EnvironmentError {
     ModuleSymbolIsNotExported(String),
     #[error("No imports are available, you tried `{0}` (no call to `Evaluator.set_loader`)")]
     NoImportsAvailable(String),
+    #[error("Module snapshot was discarded, or taken of another module")]
+    SnapshotNotFound,
+    #[error("Can't export {0}, the module symbols {1} have no {0} representation")]
+    CannotExport(ExportFormat, String),
 }
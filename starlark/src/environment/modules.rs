@@ -23,6 +23,7 @@
 use std::{
     cell::{Cell, RefCell},
     collections::HashMap,
+    fmt::{self, Debug},
     mem,
     sync::Arc,
     time::{Duration, Instant},
@@ -30,21 +31,24 @@ use std::{
 
 use derive_more::Display;
 use gazebo::{any::AnyLifetime, prelude::*};
+use indexmap::IndexMap;
 use itertools::Itertools;
 
 use crate::{
+    codemap::FileSpan,
     environment::{
         names::{FrozenNames, MutableNames},
         slots::{FrozenSlots, ModuleSlotId, MutableSlots},
         EnvironmentError,
     },
-    errors::did_you_mean::did_you_mean,
+    errors::{did_you_mean::did_you_mean, Diagnostic},
+    eval::runtime::provenance::freeze_error_provenance,
     syntax::ast::Visibility,
     values::{
         docs,
         docs::{DocItem, DocString, DocStringKind},
         Freezer, FrozenHeap, FrozenHeapRef, FrozenValue, Heap, OwnedFrozenValue, SimpleValue,
-        StarlarkValue, Value,
+        StarlarkValue, Trace, Tracer, Value,
     },
 };
 
@@ -111,7 +115,13 @@ pub struct Module {
     // you can inject the wrong values in, so make sure slots aren't
     // exported.
     slots: MutableSlots<'static>,
+    // Saved states, see `snapshot`. As with `slots`, really `ModuleSnapshotData<'v>`.
+    snapshots: RefCell<Vec<ModuleSnapshotData<'static>>>,
     docstring: RefCell<Option<String>>,
+    // Run on the exported values by `freeze`, see `on_freeze`.
+    freeze_hooks: RefCell<FreezeHooks>,
+    // Where each variable was first assigned by an evaluation, to report freeze hook errors.
+    definitions: RefCell<HashMap<String, FileSpan>>,
     /// Module evaluation duration:
     /// * evaluation of the top-level statements
     /// * optimizations during that evaluation
@@ -120,6 +130,28 @@ pub struct Module {
     eval_duration: Cell<Duration>,
 }
 
+/// Identifies a state of a [`Module`] saved with [`snapshot`](Module::snapshot).
+#[derive(Debug, Clone, Copy, Dupe, PartialEq, Eq)]
+pub struct ModuleSnapshot(usize);
+
+type FreezeHook = Box<dyn for<'v> Fn(&str, Value<'v>, &'v Heap) -> anyhow::Result<Value<'v>>>;
+
+#[derive(Default)]
+struct FreezeHooks(Vec<FreezeHook>);
+
+impl Debug for FreezeHooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "FreezeHooks({})", self.0.len())
+    }
+}
+
+#[derive(Debug)]
+struct ModuleSnapshotData<'v> {
+    names: IndexMap<String, (ModuleSlotId, Visibility)>,
+    slots: Vec<Option<Value<'v>>>,
+    docstring: Option<String>,
+}
+
 impl FrozenModule {
     /// Get value, exported or private by name.
     #[doc(hidden)] // TODO(nga): Buck2 depends on this function
@@ -148,6 +180,14 @@ impl FrozenModule {
         self.module.0.names()
     }
 
+    /// Number of bytes kept alive by this module, including those of the modules it loaded,
+    /// see [`FrozenHeapRef::retained_bytes`]. Use
+    /// [`frozen_heap().allocated_bytes()`](FrozenHeapRef::allocated_bytes) for the bytes
+    /// allocated by this module alone.
+    pub fn retained_bytes(&self) -> usize {
+        self.heap.retained_bytes()
+    }
+
     /// Obtain the [`FrozenHeapRef`] which owns the storage of all values defined in this module.
     pub fn frozen_heap(&self) -> &FrozenHeapRef {
         &self.heap
@@ -184,6 +224,77 @@ impl FrozenModule {
             members,
         }
     }
+
+    /// Serialize the exported symbols of the module, those which don't start with an
+    /// underscore, as a JSON object or TOML table, e.g. to generate a configuration file.
+    /// Fails, listing the symbols, if any have no representation in the format,
+    /// such as functions, or `None` in TOML.
+    pub fn export(&self, format: ExportFormat) -> anyhow::Result<String> {
+        let mut values = Vec::new();
+        let mut unrepresentable = Vec::new();
+        for name in self
+            .names()
+            .filter(|n| Module::default_visibility(n) == Visibility::Public)
+        {
+            if let Some(value) = self.get(name) {
+                match value.value().to_json() {
+                    Ok(json) => values.push((name, json)),
+                    Err(_) => unrepresentable.push(name),
+                }
+            }
+        }
+
+        let res = match format {
+            ExportFormat::Json => {
+                // Build the object by hand to keep the symbols in the order they were defined
+                let mut res = "{".to_owned();
+                for (i, (name, json)) in values.iter().enumerate() {
+                    if i != 0 {
+                        res.push(',');
+                    }
+                    res.push_str(&serde_json::to_string(name)?);
+                    res.push(':');
+                    res.push_str(json);
+                }
+                res.push('}');
+                res
+            }
+            ExportFormat::Toml => {
+                let mut res = toml::value::Table::new();
+                for (name, json) in values {
+                    let json: serde_json::Value = serde_json::from_str(&json)?;
+                    match toml::Value::try_from(json) {
+                        Ok(x) => {
+                            res.insert(name.to_owned(), x);
+                        }
+                        Err(_) => unrepresentable.push(name),
+                    }
+                }
+                toml::to_string(&toml::Value::Table(res))?
+            }
+        };
+        if unrepresentable.is_empty() {
+            Ok(res)
+        } else {
+            Err(EnvironmentError::CannotExport(
+                format,
+                unrepresentable
+                    .iter()
+                    .map(|x| format!("`{}`", x))
+                    .join(", "),
+            )
+            .into())
+        }
+    }
+}
+
+/// A format for [`FrozenModule::export`].
+#[derive(Debug, Clone, Copy, Dupe, PartialEq, Eq, Display)]
+pub enum ExportFormat {
+    #[display(fmt = "JSON")]
+    Json,
+    #[display(fmt = "TOML")]
+    Toml,
 }
 
 impl FrozenModuleData {
@@ -253,7 +364,10 @@ impl Module {
             frozen_heap: FrozenHeap::new(),
             names: MutableNames::new(),
             slots: MutableSlots::new(),
+            snapshots: RefCell::new(Vec::new()),
             docstring: RefCell::new(None),
+            freeze_hooks: RefCell::new(FreezeHooks::default()),
+            definitions: RefCell::new(HashMap::new()),
             eval_duration: Cell::new(Duration::ZERO),
         }
     }
@@ -277,6 +391,72 @@ impl Module {
         unsafe { transmute!(&'v MutableSlots<'static>, &'v MutableSlots<'v>, &self.slots) }
     }
 
+    fn snapshots<'v>(&'v self) -> &'v RefCell<Vec<ModuleSnapshotData<'v>>> {
+        // As for `slots`.
+        unsafe {
+            transmute!(
+                &'v RefCell<Vec<ModuleSnapshotData<'static>>>,
+                &'v RefCell<Vec<ModuleSnapshotData<'v>>>,
+                &self.snapshots
+            )
+        }
+    }
+
+    /// Save the variables of the module, so they can be put back with
+    /// [`restore`](Module::restore), e.g. to undo an evaluation which failed part way through.
+    /// Snapshots form a stack, each one stays available until it, or one taken before it, is
+    /// discarded.
+    ///
+    /// Only the bindings are saved, not the heap: changes made to a value which existed when
+    /// the snapshot was taken, e.g. appending to a list or setting a field of a record, are
+    /// not undone by [`restore`](Module::restore), so only bindings are rolled back. Values
+    /// allocated after the snapshot are freed by the next garbage collection once nothing
+    /// refers to them.
+    pub fn snapshot(&self) -> ModuleSnapshot {
+        let mut snapshots = self.snapshots().borrow_mut();
+        snapshots.push(ModuleSnapshotData {
+            names: self.names.snapshot(),
+            slots: self.slots().get_slots_mut().clone(),
+            docstring: self.docstring.borrow().clone(),
+        });
+        ModuleSnapshot(snapshots.len() - 1)
+    }
+
+    /// Put the variables of the module back to how they were when `snapshot` was taken.
+    /// Discards any snapshots taken after it, but `snapshot` itself can be restored again.
+    /// Must not be called while evaluating code in this module.
+    ///
+    /// Variables defined after the snapshot become unassigned, but keep their slots, since
+    /// a function defined after the snapshot may still be reachable, e.g. from a list which
+    /// existed before it, and reading them raises an error rather than another value.
+    pub fn restore(&self, snapshot: ModuleSnapshot) -> anyhow::Result<()> {
+        let mut snapshots = self.snapshots().borrow_mut();
+        let data = snapshots
+            .get(snapshot.0)
+            .ok_or(EnvironmentError::SnapshotNotFound)?;
+        self.names.restore(data.names.clone());
+        let mut slots = self.slots().get_slots_mut();
+        let len = slots.len();
+        *slots = data.slots.clone();
+        slots.resize(len, None);
+        drop(slots);
+        *self.docstring.borrow_mut() = data.docstring.clone();
+        snapshots.truncate(snapshot.0 + 1);
+        Ok(())
+    }
+
+    /// Discard `snapshot`, and any taken after it, keeping the current state of the module.
+    pub fn discard_snapshot(&self, snapshot: ModuleSnapshot) {
+        self.snapshots().borrow_mut().truncate(snapshot.0);
+    }
+
+    /// The values held by snapshots are roots for garbage collection.
+    pub(crate) fn trace_snapshots<'v>(&'v self, tracer: &Tracer<'v>) {
+        for snapshot in self.snapshots().borrow_mut().iter_mut() {
+            snapshot.slots.trace(tracer);
+        }
+    }
+
     /// Get value, exported or private by name.
     pub(crate) fn get_any_visibility<'v>(&'v self, name: &str) -> Option<(Value<'v>, Visibility)> {
         let (slot, vis) = self.names.get_name(name)?;
@@ -294,11 +474,63 @@ impl Module {
             })
     }
 
+    /// Add a function called by [`freeze`](Module::freeze) with the name and value of each
+    /// exported variable, before anything is frozen. It returns the value to export instead,
+    /// which may be allocated on the given [`Heap`], or an error to make `freeze` fail, e.g.
+    /// to reject mutable values or names which break a convention. Errors are reported at the
+    /// location where the variable was first assigned, if it was assigned by an evaluation.
+    /// Several functions can be added, and are called in the order they were added.
+    pub fn on_freeze(
+        &self,
+        f: impl for<'v> Fn(&str, Value<'v>, &'v Heap) -> anyhow::Result<Value<'v>> + 'static,
+    ) {
+        self.freeze_hooks.borrow_mut().0.push(box f);
+    }
+
+    /// Record where the variables assigned by an evaluation were first defined.
+    pub(crate) fn record_definitions(&self, definitions: Vec<(&str, FileSpan)>) {
+        let mut res = self.definitions.borrow_mut();
+        for (name, span) in definitions {
+            if !res.contains_key(name) {
+                res.insert(name.to_owned(), span);
+            }
+        }
+    }
+
+    fn run_freeze_hooks(&self) -> anyhow::Result<()> {
+        let hooks = self.freeze_hooks.borrow();
+        if hooks.0.is_empty() {
+            return Ok(());
+        }
+        for (name, (slot, vis)) in self.names.snapshot() {
+            if vis == Visibility::Private {
+                continue;
+            }
+            if let Some(mut value) = self.slots().get_slot(slot) {
+                for hook in &hooks.0 {
+                    value = hook(&name, value, &self.heap).map_err(|e| {
+                        match self.definitions.borrow().get(&name) {
+                            Some(span) => Diagnostic::new(e, span.span, span.file.dupe()),
+                            None => e,
+                        }
+                    })?;
+                }
+                self.slots().set_slot(slot, value);
+            }
+        }
+        Ok(())
+    }
+
     /// Freeze the environment, all its value will become immutable afterwards.
+    /// Fails if a function added with [`on_freeze`](Module::on_freeze) returns an error.
     pub fn freeze(self) -> anyhow::Result<FrozenModule> {
+        self.run_freeze_hooks()?;
         let Module {
             names,
             slots,
+            snapshots: _,
+            freeze_hooks: _,
+            definitions: _,
             frozen_heap,
             heap,
             docstring,
@@ -310,7 +542,9 @@ impl Module {
         // slot-index in the code, and we don't walk into them, so don't know if
         // they are used.
         let freezer = Freezer::new(frozen_heap);
-        let slots = slots.freeze(&freezer)?;
+        let slots = slots
+            .freeze(&freezer, &names)
+            .map_err(|e| freeze_error_provenance(e, &heap))?;
         let rest = FrozenModuleRef(Arc::new(FrozenModuleData {
             names: names.freeze(),
             slots,
@@ -411,3 +645,143 @@ where
     FrozenModule: Send + Sync,
 {
 }
+
+#[test]
+fn test_retained_bytes() {
+    let a = Module::new();
+    a.set("x", a.heap().alloc_str(&"x".repeat(1000)));
+    let a = a.freeze().unwrap();
+    let b = Module::new();
+    b.import_public_symbols(&a);
+    b.set("y", b.heap().alloc(1));
+    let b = b.freeze().unwrap();
+    let c = Module::new();
+    c.import_public_symbols(&a);
+    c.import_public_symbols(&b);
+    let c = c.freeze().unwrap();
+
+    assert!(a.retained_bytes() >= 1000);
+    assert_eq!(a.retained_bytes(), a.frozen_heap().allocated_bytes());
+    assert_eq!(
+        b.retained_bytes(),
+        b.frozen_heap().allocated_bytes() + a.retained_bytes()
+    );
+    // `a` is only counted once, although `c` references it directly and through `b`.
+    assert_eq!(
+        c.retained_bytes(),
+        c.frozen_heap().allocated_bytes() + b.retained_bytes()
+    );
+}
+
+#[test]
+fn test_snapshot_restore() {
+    use crate::{
+        environment::Globals,
+        eval::Evaluator,
+        syntax::{AstModule, Dialect},
+    };
+
+    let parse = |x: &str| AstModule::parse("test.star", x.to_owned(), &Dialect::Extended).unwrap();
+    let globals = Globals::standard();
+    let module = Module::new();
+    let mut eval = Evaluator::new(&module);
+    eval.eval_module(parse("x = 1\nxs = []"), &globals).unwrap();
+    let snapshot = module.snapshot();
+    assert!(eval
+        .eval_module(parse("x = 2\ny = 3\nxs.append(x)\nfail('bad')"), &globals)
+        .is_err());
+    assert_eq!(module.get("y").unwrap().unpack_int(), Some(3));
+
+    module.restore(snapshot).unwrap();
+    assert_eq!(module.get("x").unwrap().unpack_int(), Some(1));
+    assert!(module.get("y").is_none());
+    // Changes to existing values are not undone.
+    assert_eq!(module.get("xs").unwrap().to_str(), "[2]");
+
+    // The snapshot survives garbage collection, and can be restored again.
+    eval.eval_module(parse("x = [str(i) for i in range(10)]"), &globals)
+        .unwrap();
+    unsafe { eval.garbage_collect() };
+    module.restore(snapshot).unwrap();
+    let res = eval.eval_module(parse("x + 1"), &globals).unwrap();
+    assert_eq!(res.unpack_int(), Some(2));
+
+    module.discard_snapshot(snapshot);
+    assert!(module.restore(snapshot).is_err());
+}
+
+#[test]
+fn test_snapshot_restore_escaped_def() {
+    use crate::{
+        environment::Globals,
+        eval::Evaluator,
+        syntax::{AstModule, Dialect},
+    };
+
+    let parse = |x: &str| AstModule::parse("test.star", x.to_owned(), &Dialect::Extended).unwrap();
+    let globals = Globals::standard();
+    let module = Module::new();
+    let mut eval = Evaluator::new(&module);
+    eval.eval_module(parse("xs = []"), &globals).unwrap();
+    let snapshot = module.snapshot();
+    eval.eval_module(parse("z = 1\ndef g(): return z\nxs.append(g)"), &globals)
+        .unwrap();
+    module.restore(snapshot).unwrap();
+
+    // `g` survives in `xs`, but the `z` it reads is gone.
+    let err = eval.eval_module(parse("xs[0]()"), &globals).unwrap_err();
+    assert!(err.to_string().contains("referenced before assignment"));
+    // A variable defined after the restore doesn't take the slot of `z`.
+    let err = eval
+        .eval_module(parse("w = 2\nxs[0]()"), &globals)
+        .unwrap_err();
+    assert!(err.to_string().contains("referenced before assignment"));
+    assert_eq!(module.get("w").unwrap().unpack_int(), Some(2));
+}
+
+#[test]
+fn test_on_freeze() {
+    use crate::{
+        environment::Globals,
+        eval::Evaluator,
+        syntax::{AstModule, Dialect},
+    };
+
+    let eval_module = |program: &str| {
+        let module = Module::new();
+        let mut eval = Evaluator::new(&module);
+        let ast = AstModule::parse("test.star", program.to_owned(), &Dialect::Extended).unwrap();
+        eval.eval_module(ast, &Globals::standard()).unwrap();
+        drop(eval);
+        module
+    };
+
+    // Post-process the exported values, leaving private ones alone.
+    let module = eval_module("x = 1\n_y = 2\ndef f(): pass");
+    module.on_freeze(|_, value, heap| Ok(value.unpack_int().map_or(value, |i| heap.alloc(i * 10))));
+    let module = module.freeze().unwrap();
+    assert_eq!(module.get("x").unwrap().value().unpack_int(), Some(10));
+    assert_eq!(
+        module
+            .get_any_visibility("_y")
+            .unwrap()
+            .0
+            .value()
+            .unpack_int(),
+        Some(2)
+    );
+
+    // Reject mutable values, pointing at where they were defined.
+    let module = eval_module("x = 1\nif True:\n  xs = []\nxs.append(x)");
+    module.on_freeze(|name, value, _| {
+        if value.get_type() == "list" {
+            Err(anyhow::anyhow!("`{}` is mutable", name))
+        } else {
+            Ok(value)
+        }
+    });
+    let err = module.freeze().unwrap_err();
+    let err = err.downcast_ref::<Diagnostic>().unwrap();
+    assert_eq!(err.message.to_string(), "`xs` is mutable");
+    assert_eq!(err.span.as_ref().unwrap().resolve_span().begin_line, 2);
+}
@@ -15,7 +15,10 @@
  * limitations under the License.
  */
 
-use std::{cell::RefCell, convert::TryInto, iter::Iterator};
+use std::{
+    cell::{Cell, RefCell},
+    iter::Iterator,
+};
 
 use indexmap::map::IndexMap;
 
@@ -38,19 +41,26 @@ use crate::{environment::slots::ModuleSlotId, syntax::ast::Visibility};
 /// fresh slots at the end, and bind them to the names in the comprehension.
 /// On an unscope, we do the reverse, putting things back to how they were
 /// before (apart from the total) number of slots required.
+///
+/// Slots are never reused, even once their name is hidden or dropped by
+/// [`restore`](MutableNames::restore), since code compiled while the name existed
+/// may still refer to the slot. The second field is the number of slots allocated.
 #[derive(Debug)]
-pub(crate) struct MutableNames(RefCell<IndexMap<String, (ModuleSlotId, Visibility)>>);
+pub(crate) struct MutableNames(
+    RefCell<IndexMap<String, (ModuleSlotId, Visibility)>>,
+    Cell<u32>,
+);
 
 #[derive(Debug)]
 pub(crate) struct FrozenNames(IndexMap<String, (ModuleSlotId, Visibility)>);
 
 impl MutableNames {
     pub fn new() -> Self {
-        Self(RefCell::new(IndexMap::new()))
+        Self(RefCell::new(IndexMap::new()), Cell::new(0))
     }
 
     pub fn slot_count(&self) -> u32 {
-        self.0.borrow().len().try_into().unwrap()
+        self.1.get()
     }
 
     /// Try and go back from a slot to a name.
@@ -80,7 +90,8 @@ impl MutableNames {
                 *slot
             }
             None => {
-                let slot = ModuleSlotId::new(x.len().try_into().unwrap());
+                let slot = ModuleSlotId::new(self.1.get());
+                self.1.set(self.1.get() + 1);
                 x.insert(name.to_owned(), (slot, vis));
                 slot
             }
@@ -112,6 +123,15 @@ impl MutableNames {
             .collect()
     }
 
+    /// Copy the current names, to go back to them with [`restore`](MutableNames::restore).
+    pub(crate) fn snapshot(&self) -> IndexMap<String, (ModuleSlotId, Visibility)> {
+        self.0.borrow().clone()
+    }
+
+    pub(crate) fn restore(&self, names: IndexMap<String, (ModuleSlotId, Visibility)>) {
+        *self.0.borrow_mut() = names;
+    }
+
     pub fn freeze(self) -> FrozenNames {
         FrozenNames(self.0.into_inner())
     }
@@ -19,7 +19,10 @@ use std::cell::{RefCell, RefMut};
 
 use gazebo::prelude::*;
 
-use crate::values::{Freezer, FrozenValue, Value};
+use crate::{
+    environment::names::MutableNames,
+    values::{FreezeError, FreezeStep, Freezer, FrozenValue, Value},
+};
 
 #[derive(Clone, Copy, Dupe, Debug, PartialEq, Eq)]
 pub(crate) struct ModuleSlotId(pub(crate) u32);
@@ -72,11 +75,30 @@ impl<'v> MutableSlots<'v> {
         }
     }
 
-    pub(crate) fn freeze(self, freezer: &Freezer) -> anyhow::Result<FrozenSlots> {
+    /// Freeze the slots, using `names` to say which variable held a value which can't be.
+    pub(crate) fn freeze(
+        self,
+        freezer: &Freezer,
+        names: &MutableNames,
+    ) -> anyhow::Result<FrozenSlots> {
         let slots = self
             .0
             .into_inner()
-            .try_map(|x| x.into_try_map(|x| x.freeze(freezer)))?;
+            .into_iter()
+            .enumerate()
+            .map(|(i, x)| {
+                x.into_try_map(|x| {
+                    FreezeError::add_step(x.freeze(freezer), || {
+                        let slot = ModuleSlotId::new(i as u32);
+                        FreezeStep::Variable(
+                            names
+                                .get_slot(slot)
+                                .unwrap_or_else(|| format!("<slot {}>", i)),
+                        )
+                    })
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
         Ok(FrozenSlots(slots))
     }
 }
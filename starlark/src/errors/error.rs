@@ -0,0 +1,188 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::fmt::{self, Debug, Display, Formatter};
+
+use crate::{
+    codemap::FileSpan,
+    environment::EnvironmentError,
+    errors::{Diagnostic, Frame},
+    eval::{CallStackError, EvaluatorError, LoadError},
+    stdlib::funcs::FailError,
+    values::HeapError,
+};
+
+/// What sort of problem caused an [`Error`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// A problem found before evaluation started, e.g. a reference to an undefined variable.
+    Static,
+    /// The Starlark code called `fail()`.
+    Fail,
+    /// A `load()` statement couldn't be resolved.
+    Load,
+    /// The evaluation exceeded a limit, e.g. on steps, time, recursion depth or memory.
+    Limit,
+    /// The evaluation was stopped with a
+    /// [`CancellationHandle`](crate::eval::CancellationHandle).
+    Cancelled,
+    /// Any other error while evaluating, e.g. a type error or a failing native function.
+    Runtime,
+}
+
+/// An error from evaluating a module, giving access to its kind, location and call stack,
+/// so hosts can present it however they like. The [`Display`] instance renders it the same
+/// way as the underlying [`Diagnostic`].
+///
+/// Converts into an [`anyhow::Error`] with `?`, which is the underlying error, so existing
+/// code can still downcast it to a [`Diagnostic`].
+///
+/// If a loaded module fails to evaluate, the error describes the failure in that module.
+pub struct Error {
+    kind: ErrorKind,
+    error: anyhow::Error,
+}
+
+impl Error {
+    pub(crate) fn new_kind(kind: ErrorKind, error: anyhow::Error) -> Self {
+        Self { kind, error }
+    }
+
+    /// Wrap an error raised while evaluating, working out its kind from the underlying error.
+    pub(crate) fn new(error: anyhow::Error) -> Self {
+        let kind = Self::classify(
+            error
+                .downcast_ref::<Diagnostic>()
+                .map_or(&error, |d| &d.message),
+        );
+        Self { kind, error }
+    }
+
+    fn classify(message: &anyhow::Error) -> ErrorKind {
+        if message.is::<FailError>() {
+            ErrorKind::Fail
+        } else if message.is::<LoadError>()
+            || matches!(
+                message.downcast_ref::<EnvironmentError>(),
+                Some(EnvironmentError::NoImportsAvailable(_))
+            )
+        {
+            ErrorKind::Load
+        } else if message.is::<CallStackError>() || message.is::<HeapError>() {
+            ErrorKind::Limit
+        } else {
+            match message.downcast_ref::<EvaluatorError>() {
+                Some(EvaluatorError::Cancelled) => ErrorKind::Cancelled,
+                Some(EvaluatorError::StepLimitExceeded(_) | EvaluatorError::DeadlineExceeded) => {
+                    ErrorKind::Limit
+                }
+                _ => ErrorKind::Runtime,
+            }
+        }
+    }
+
+    fn diagnostic(&self) -> Option<&Diagnostic> {
+        self.error.downcast_ref::<Diagnostic>()
+    }
+
+    /// What sort of problem caused the error.
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// The underlying error, without the location or call stack.
+    pub fn message(&self) -> &anyhow::Error {
+        self.diagnostic().map_or(&self.error, |d| &d.message)
+    }
+
+    /// The location of the expression or statement which failed, if known.
+    pub fn span(&self) -> Option<&FileSpan> {
+        self.diagnostic().and_then(|d| d.span.as_ref())
+    }
+
+    /// The functions being called when the error occurred, outermost first, including
+    /// native functions. Empty if the error didn't happen inside a function call.
+    pub fn call_stack(&self) -> &[Frame] {
+        self.diagnostic().map_or(&[], |d| &d.call_stack)
+    }
+
+    /// Convert to the underlying [`anyhow::Error`], usually a [`Diagnostic`].
+    pub fn into_anyhow(self) -> anyhow::Error {
+        self.error
+    }
+}
+
+impl From<Error> for anyhow::Error {
+    fn from(e: Error) -> Self {
+        e.error
+    }
+}
+
+impl Debug for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&self.error, f)
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Display::fmt(&self.error, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        environment::{Globals, Module},
+        eval::Evaluator,
+        syntax::{AstModule, Dialect},
+    };
+
+    fn eval(program: &str) -> Error {
+        let ast = AstModule::parse("test.star", program.to_owned(), &Dialect::Extended).unwrap();
+        let module = Module::new();
+        let mut eval = Evaluator::new(&module);
+        eval.eval_module(ast, &Globals::standard()).unwrap_err()
+    }
+
+    #[test]
+    fn test_error_kind() {
+        assert_eq!(eval("x = y").kind(), ErrorKind::Static);
+        assert_eq!(eval("fail('bad')").kind(), ErrorKind::Fail);
+        assert_eq!(eval("load('a.star', 'a')").kind(), ErrorKind::Load);
+        assert_eq!(eval("def f(): f()\nf()").kind(), ErrorKind::Limit);
+        assert_eq!(eval("1 + 'a'").kind(), ErrorKind::Runtime);
+    }
+
+    #[test]
+    fn test_error_data() {
+        let err = eval("def f(x):\n  fail(x)\ndef g():\n  f('bad')\ng()");
+        assert_eq!(err.message().to_string(), "fail: bad");
+        assert_eq!(err.span().unwrap().resolve_span().begin_line, 1);
+        assert_eq!(
+            err.call_stack()
+                .iter()
+                .map(|x| x.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["test.star.g", "test.star.f", "fail"]
+        );
+        let err = anyhow::Error::from(err);
+        assert!(err.is::<Diagnostic>());
+    }
+}
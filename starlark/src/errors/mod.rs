@@ -15,10 +15,10 @@
  * limitations under the License.
  */
 
-//! Error types used by Starlark, mostly [`Diagnostic`].
+//! Error types used by Starlark, mostly [`Diagnostic`], and [`Error`] returned by evaluation.
 
 use std::{
-    error::Error,
+    error::Error as StdError,
     fmt::{self, Display, Formatter},
 };
 
@@ -27,10 +27,13 @@ use annotate_snippets::{
     snippet::{Annotation, AnnotationType, Slice, Snippet, SourceAnnotation},
 };
 
-pub use crate::analysis::Lint;
+pub use crate::analysis::{Lint, LintFix};
 use crate::codemap::{CodeMap, FileSpan, Span};
 
 pub(crate) mod did_you_mean;
+mod error;
+
+pub use error::{Error, ErrorKind};
 
 /// An error plus its origination location and call stack.
 ///
@@ -50,7 +53,7 @@ pub struct Diagnostic {
 }
 
 /// A frame of the call-stack.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Frame {
     /// The name of the entry on the call-stack.
     pub name: String,
@@ -68,8 +71,8 @@ impl Display for Frame {
     }
 }
 
-impl Error for Diagnostic {
-    fn source(&self) -> Option<&(dyn Error + 'static)> {
+impl StdError for Diagnostic {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
         // We do have an underlying source (namely `self.message`), but if we return
         // it then `anyhow` will print it with `{:#}`, and we already print it in our
         // `Display`, which would cause it to appear twice.
@@ -33,9 +33,10 @@ use crate::{
             compiler::expr::write_exprs,
             instr_arg::{ArgPopsStack, ArgPopsStack1},
             instr_impl::{
-                InstrCall, InstrCallFrozen, InstrCallFrozenDef, InstrCallFrozenDefPos,
-                InstrCallFrozenNative, InstrCallFrozenNativePos, InstrCallFrozenPos,
-                InstrCallMethod, InstrCallMethodPos, InstrCallPos,
+                InstrCall, InstrCallFrozen, InstrCallFrozenDef, InstrCallFrozenDefNamed,
+                InstrCallFrozenDefPos, InstrCallFrozenNative, InstrCallFrozenNativePos,
+                InstrCallFrozenPos, InstrCallMethod, InstrCallMethodPos, InstrCallPos,
+                InstrTailCallPos,
             },
             writer::BcWriter,
         },
@@ -137,7 +138,21 @@ impl Spanned<CallCompiled> {
                     bc.write_instr::<InstrCallFrozenDefPos>(span, (npops, fun, span));
                 }
                 Either::Right(args) => {
-                    bc.write_instr::<InstrCallFrozenDef>(span, (fun, args));
+                    // If we can, work out which parameter each named argument goes to now,
+                    // rather than looking the names up on every call.
+                    let resolved = match (args.args, args.kwargs) {
+                        (false, false) => fun
+                            .as_ref()
+                            .parameters
+                            .resolve_names(args.pos() as usize, &args.names),
+                        _ => None,
+                    };
+                    match resolved {
+                        Some(resolved) => {
+                            bc.write_instr::<InstrCallFrozenDefNamed>(span, (fun, args, resolved));
+                        }
+                        None => bc.write_instr::<InstrCallFrozenDef>(span, (fun, args)),
+                    }
                 }
             }
         } else if let Some(fun) = FrozenValueTyped::<NativeFunction>::new(fun) {
@@ -161,6 +176,27 @@ impl Spanned<CallCompiled> {
         }
     }
 
+    /// Write a call in tail position, returning its result from the function. Only calls with
+    /// positional arguments are supported, otherwise returns `false` and writes nothing.
+    pub(crate) fn write_tail_call_bc(&self, bc: &mut BcWriter) -> bool {
+        let span = self.span;
+        match self.node {
+            CallCompiled::Call(box (ref f, ref args)) => match args.pos_only() {
+                Some(pos) => {
+                    f.write_bc(bc);
+                    write_exprs(pos, bc);
+                    bc.write_instr::<InstrTailCallPos>(
+                        span,
+                        (ArgPopsStack1, ArgPopsStack(pos.len() as u32), span),
+                    );
+                    true
+                }
+                None => false,
+            },
+            CallCompiled::Method(..) => false,
+        }
+    }
+
     pub(crate) fn write_bc(&self, bc: &mut BcWriter) {
         let span = self.span;
         match self.node {
@@ -131,6 +131,14 @@ impl Spanned<StmtCompiled> {
         }
     }
 
+    /// Write `return f(...)` as a tail call, returning `false` if `expr` isn't a suitable call.
+    fn write_tail_call(expr: &Spanned<ExprCompiled>, bc: &mut BcWriter) -> bool {
+        match expr.node {
+            ExprCompiled::Call(ref call) => call.write_tail_call_bc(bc),
+            _ => false,
+        }
+    }
+
     fn write_bc_inner(&self, compiler: &StmtCompileContext, bc: &mut BcWriter) {
         let span = self.span;
         match self.node {
@@ -138,6 +146,8 @@ impl Spanned<StmtCompiled> {
             StmtCompiled::Return(ref expr) => {
                 if expr.is_none() {
                     bc.write_instr::<InstrReturnNone>(span, ());
+                } else if compiler.tail_calls && Self::write_tail_call(expr, bc) {
+                    // The call returns.
                 } else {
                     expr.write_bc(bc);
                     bc.write_instr::<InstrReturn>(span, ());
@@ -160,6 +170,10 @@ impl Spanned<StmtCompiled> {
                 over.write_bc(bc);
                 bc.write_for(span, |bc| {
                     assign.write_bc(bc);
+                    // A loop doing nothing still takes a step per iteration
+                    if compiler.has_before_stmt && body.is_empty() {
+                        bc.write_instr::<InstrBeforeStmt>(span, span);
+                    }
                     body.write_bc(compiler, bc);
                 });
             }
@@ -514,6 +514,30 @@ impl BcInstrArg for Symbol {
     }
 }
 
+impl BcInstrArg for Box<[u32]> {
+    fn fmt_append(param: &Self, _ip: BcAddr, f: &mut dyn Write) -> fmt::Result {
+        write!(f, " [")?;
+        for (i, v) in param.iter().enumerate() {
+            if i != 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", v)?;
+        }
+        write!(f, "]")?;
+        Ok(())
+    }
+
+    fn visit_jump_addr(_param: &Self, _consumer: &mut dyn FnMut(BcAddrOffset)) {}
+
+    fn pops_stack(_param: &Self) -> u32 {
+        0
+    }
+
+    fn pushes_stack(_param: &Self) -> u32 {
+        0
+    }
+}
+
 impl BcInstrArg for Box<[FrozenValue]> {
     fn fmt_append(param: &Self, _ip: BcAddr, f: &mut dyn Write) -> fmt::Result {
         write!(f, " [")?;
@@ -49,10 +49,14 @@ use crate::{
         Arguments, Def, Evaluator, FrozenDef, ParametersSpec,
     },
     values::{
+        bigint,
         dict::Dict,
         function::NativeFunction,
         list::List,
-        string::interpolation::{format_one, percent_s_one},
+        string::{
+            interpolation::{format_one, percent_s_one},
+            rope::StarlarkRope,
+        },
         typed::FrozenValueTyped,
         typing::TypeCompiled,
         FrozenRef, FrozenStringValue, FrozenValue, Heap, StarlarkValue, Value,
@@ -523,11 +527,12 @@ impl InstrNoFlowAddSpanImpl for InstrSetArrayIndexImpl {
 
     #[inline(always)]
     fn run_with_args<'v>(
-        _eval: &mut Evaluator<'v, '_>,
+        eval: &mut Evaluator<'v, '_>,
         _stack: &mut BcStackPtr<'v, '_>,
         (): &(),
         [value, array, index]: [Value<'v>; 3],
     ) -> Result<(), anyhow::Error> {
+        eval.heap().write_barrier(array);
         array.set_at(index, value)
     }
 }
@@ -539,11 +544,12 @@ impl InstrNoFlowAddSpanImpl for InstrArrayIndexSetImpl {
 
     #[inline(always)]
     fn run_with_args<'v>(
-        _eval: &mut Evaluator<'v, '_>,
+        eval: &mut Evaluator<'v, '_>,
         _stack: &mut BcStackPtr<'v, '_>,
         (): &(),
         [array, index, value]: [Value<'v>; 3],
     ) -> Result<(), anyhow::Error> {
+        eval.heap().write_barrier(array);
         array.set_at(index, value)
     }
 }
@@ -570,11 +576,12 @@ impl InstrNoFlowAddSpanImpl for InstrSetObjectFieldImpl {
     type Arg = Symbol;
 
     fn run_with_args<'v>(
-        _eval: &mut Evaluator<'v, '_>,
+        eval: &mut Evaluator<'v, '_>,
         _stack: &mut BcStackPtr<'v, '_>,
         symbol: &Symbol,
         [v, o]: [Value<'v>; 2],
     ) -> Result<(), anyhow::Error> {
+        eval.heap().write_barrier(o);
         o.set_attr(symbol.as_str(), v)
     }
 }
@@ -585,11 +592,12 @@ impl InstrNoFlowAddSpanImpl for InstrObjectSetFieldImpl {
     type Arg = Symbol;
 
     fn run_with_args<'v>(
-        _eval: &mut Evaluator<'v, '_>,
+        eval: &mut Evaluator<'v, '_>,
         _stack: &mut BcStackPtr<'v, '_>,
         symbol: &Symbol,
         [o, v]: [Value<'v>; 2],
     ) -> Result<(), anyhow::Error> {
+        eval.heap().write_barrier(o);
         o.set_attr(symbol.as_str(), v)
     }
 }
@@ -673,8 +681,8 @@ impl InstrUnOpImpl for InstrMinusImpl {
 
 impl InstrUnOpImpl for InstrBitNotImpl {
     #[inline(always)]
-    fn eval<'v>(v: Value<'v>, _heap: &'v Heap) -> Result<Value<'v>, anyhow::Error> {
-        Ok(Value::new_int(!v.to_int()?))
+    fn eval<'v>(v: Value<'v>, heap: &'v Heap) -> Result<Value<'v>, anyhow::Error> {
+        bigint::bit_not(v, heap)
     }
 }
 
@@ -721,6 +729,18 @@ impl<I: InstrUnOpImpl> InstrNoFlowAddSpanImpl for InstrUnOpWrapper<I> {
     }
 }
 
+/// Arithmetic on two ints is the most common case, so do it without dispatching through
+/// `StarlarkValue`. Returns [`None`] if either value is not an int, or the result overflows,
+/// in which case the general implementation is used, which promotes to a big integer.
+#[inline(always)]
+fn int_bin_op<'v>(
+    l: Value<'v>,
+    r: Value<'v>,
+    op: fn(i32, i32) -> Option<i32>,
+) -> Option<Value<'v>> {
+    op(l.unpack_int()?, r.unpack_int()?).map(Value::new_int)
+}
+
 pub(crate) struct InstrAddImpl;
 pub(crate) struct InstrAddAssignImpl;
 pub(crate) struct InstrSubImpl;
@@ -754,17 +774,12 @@ pub(crate) type InstrNotIn = InstrBinOp<InstrNotInImpl>;
 impl InstrBinOpImpl for InstrAddImpl {
     #[inline(always)]
     fn eval<'v>(l: Value<'v>, r: Value<'v>, heap: &'v Heap) -> Result<Value<'v>, anyhow::Error> {
+        if let Some(v) = int_bin_op(l, r, i32::checked_add) {
+            return Ok(v);
+        }
         // Addition of string is super common and pretty cheap, so have a special case for it.
-        if let Some(ls) = l.unpack_str() {
-            if let Some(rs) = r.unpack_str() {
-                if ls.is_empty() {
-                    return Ok(r);
-                } else if rs.is_empty() {
-                    return Ok(l);
-                } else {
-                    return Ok(heap.alloc_str_concat(ls, rs));
-                }
-            }
+        if let Some(v) = StarlarkRope::concat(l, r, heap) {
+            return Ok(v);
         }
 
         l.add(r, heap)
@@ -781,6 +796,9 @@ impl InstrBinOpImpl for InstrAddAssignImpl {
 impl InstrBinOpImpl for InstrSubImpl {
     #[inline(always)]
     fn eval<'v>(v0: Value<'v>, v1: Value<'v>, heap: &'v Heap) -> Result<Value<'v>, anyhow::Error> {
+        if let Some(v) = int_bin_op(v0, v1, i32::checked_sub) {
+            return Ok(v);
+        }
         v0.sub(v1, heap)
     }
 }
@@ -788,6 +806,9 @@ impl InstrBinOpImpl for InstrSubImpl {
 impl InstrBinOpImpl for InstrMultiplyImpl {
     #[inline(always)]
     fn eval<'v>(v0: Value<'v>, v1: Value<'v>, heap: &'v Heap) -> Result<Value<'v>, anyhow::Error> {
+        if let Some(v) = int_bin_op(v0, v1, i32::checked_mul) {
+            return Ok(v);
+        }
         v0.mul(v1, heap)
     }
 }
@@ -815,36 +836,36 @@ impl InstrBinOpImpl for InstrDivideImpl {
 
 impl InstrBinOpImpl for InstrBitAndImpl {
     #[inline(always)]
-    fn eval<'v>(v0: Value<'v>, v1: Value<'v>, _heap: &'v Heap) -> Result<Value<'v>, anyhow::Error> {
-        v0.bit_and(v1)
+    fn eval<'v>(v0: Value<'v>, v1: Value<'v>, heap: &'v Heap) -> Result<Value<'v>, anyhow::Error> {
+        v0.bit_and(v1, heap)
     }
 }
 
 impl InstrBinOpImpl for InstrBitOrImpl {
     #[inline(always)]
-    fn eval<'v>(v0: Value<'v>, v1: Value<'v>, _heap: &'v Heap) -> Result<Value<'v>, anyhow::Error> {
-        v0.bit_or(v1)
+    fn eval<'v>(v0: Value<'v>, v1: Value<'v>, heap: &'v Heap) -> Result<Value<'v>, anyhow::Error> {
+        v0.bit_or(v1, heap)
     }
 }
 
 impl InstrBinOpImpl for InstrBitXorImpl {
     #[inline(always)]
-    fn eval<'v>(v0: Value<'v>, v1: Value<'v>, _heap: &'v Heap) -> Result<Value<'v>, anyhow::Error> {
-        v0.bit_xor(v1)
+    fn eval<'v>(v0: Value<'v>, v1: Value<'v>, heap: &'v Heap) -> Result<Value<'v>, anyhow::Error> {
+        v0.bit_xor(v1, heap)
     }
 }
 
 impl InstrBinOpImpl for InstrLeftShiftImpl {
     #[inline(always)]
-    fn eval<'v>(v0: Value<'v>, v1: Value<'v>, _heap: &'v Heap) -> Result<Value<'v>, anyhow::Error> {
-        v0.left_shift(v1)
+    fn eval<'v>(v0: Value<'v>, v1: Value<'v>, heap: &'v Heap) -> Result<Value<'v>, anyhow::Error> {
+        v0.left_shift(v1, heap)
     }
 }
 
 impl InstrBinOpImpl for InstrRightShiftImpl {
     #[inline(always)]
-    fn eval<'v>(v0: Value<'v>, v1: Value<'v>, _heap: &'v Heap) -> Result<Value<'v>, anyhow::Error> {
-        v0.right_shift(v1)
+    fn eval<'v>(v0: Value<'v>, v1: Value<'v>, heap: &'v Heap) -> Result<Value<'v>, anyhow::Error> {
+        v0.right_shift(v1, heap)
     }
 }
 
@@ -913,7 +934,16 @@ pub(crate) struct InstrCompare<I: InstrCompareImpl>(marker::PhantomData<I>);
 impl<I: InstrCompareImpl> InstrBinOpImpl for InstrCompare<I> {
     #[inline(always)]
     fn eval<'v>(v0: Value<'v>, v1: Value<'v>, _heap: &'v Heap) -> Result<Value<'v>, anyhow::Error> {
-        Ok(Value::new_bool(I::eval_compare(v0.compare(v1)?)))
+        // Comparing ints or strings is most common, so do it without dispatching through
+        // `StarlarkValue`.
+        let ordering = if let (Some(l), Some(r)) = (v0.unpack_int(), v1.unpack_int()) {
+            l.cmp(&r)
+        } else if let (Some(l), Some(r)) = (v0.unpack_str(), v1.unpack_str()) {
+            l.cmp(r)
+        } else {
+            v0.compare(v1)?
+        };
+        Ok(Value::new_bool(I::eval_compare(ordering)))
     }
 }
 
@@ -1402,6 +1432,42 @@ impl BcInstr for InstrReturn {
     }
 }
 
+/// `return f(...)` with positional arguments. If `f` is the function being executed, the
+/// arguments are handed to `DefGen::invoke_raw` to restart it, otherwise `f` is called as
+/// usual.
+pub(crate) struct InstrTailCallPos;
+
+impl BcInstr for InstrTailCallPos {
+    type Pop<'v> = ();
+    type Push<'v> = ();
+    type Arg = (ArgPopsStack1, ArgPopsStack, Span);
+
+    #[inline(always)]
+    fn run<'v, 'b>(
+        eval: &mut Evaluator<'v, '_>,
+        stack: &mut BcStackPtr<'v, '_>,
+        ip: BcPtrAddr<'b>,
+        (_pop1, npops, span): &Self::Arg,
+    ) -> InstrControl<'v, 'b> {
+        let arguments = stack.pop_args_pos(*npops);
+        let f = stack.pop();
+        let is_current = eval
+            .call_stack
+            .top_function()
+            .map_or(false, |x| x.ptr_eq(f));
+        if is_current {
+            eval.tail_call_args.clear();
+            eval.tail_call_args.extend_from_slice(arguments.pos);
+            eval.tail_call = true;
+            return InstrControl::Return(Value::new_none());
+        }
+        match f.invoke(Some(*span), arguments, eval) {
+            Ok(v) => InstrControl::Return(v),
+            Err(e) => InstrControl::Err(Bc::wrap_error_for_instr_ptr(ip, e, eval)),
+        }
+    }
+}
+
 pub(crate) struct InstrDefImpl;
 pub(crate) type InstrDef = InstrNoFlow<InstrDefImpl>;
 
@@ -1555,6 +1621,7 @@ pub(crate) struct InstrCallImpl;
 pub(crate) struct InstrCallPosImpl;
 pub(crate) struct InstrCallFrozenGenericImpl<F: BcFrozenCallable>(marker::PhantomData<F>);
 pub(crate) struct InstrCallFrozenGenericPosImpl<F: BcFrozenCallable>(marker::PhantomData<F>);
+pub(crate) struct InstrCallFrozenDefNamedImpl;
 pub(crate) struct InstrCallMethodImpl;
 pub(crate) struct InstrCallMethodPosImpl;
 
@@ -1564,6 +1631,7 @@ pub(crate) type InstrCallFrozenDef =
     InstrNoFlowAddSpan<InstrCallFrozenGenericImpl<FrozenValueTyped<'static, FrozenDef>>>;
 pub(crate) type InstrCallFrozenDefPos =
     InstrNoFlowAddSpan<InstrCallFrozenGenericPosImpl<FrozenValueTyped<'static, FrozenDef>>>;
+pub(crate) type InstrCallFrozenDefNamed = InstrNoFlowAddSpan<InstrCallFrozenDefNamedImpl>;
 pub(crate) type InstrCallFrozenNative =
     InstrNoFlowAddSpan<InstrCallFrozenGenericImpl<FrozenValueTyped<'static, NativeFunction>>>;
 pub(crate) type InstrCallFrozenNativePos =
@@ -1643,6 +1711,29 @@ impl<F: BcFrozenCallable> InstrNoFlowAddSpanImpl for InstrCallFrozenGenericPosIm
     }
 }
 
+impl InstrNoFlowAddSpanImpl for InstrCallFrozenDefNamedImpl {
+    type Pop<'v> = ();
+    type Push<'v> = Value<'v>;
+    // The function, the arguments, and the parameter index for each named argument.
+    type Arg = (
+        FrozenValueTyped<'static, FrozenDef>,
+        ArgsCompiledValueBc,
+        Box<[u32]>,
+    );
+
+    #[inline(always)]
+    fn run_with_args<'v>(
+        eval: &mut Evaluator<'v, '_>,
+        stack: &mut BcStackPtr<'v, '_>,
+        (fun, args, resolved): &Self::Arg,
+        _pops: (),
+    ) -> Result<Value<'v>, anyhow::Error> {
+        let arguments = stack.pop_args(args);
+        fun.as_ref()
+            .invoke_resolved(fun.to_value(), args.span, arguments, resolved, eval)
+    }
+}
+
 impl InstrNoFlowAddSpanImpl for InstrCallMethodImpl {
     type Pop<'v> = ();
     type Push<'v> = Value<'v>;
@@ -1734,8 +1825,7 @@ impl InstrNoFlowImpl for InstrBeforeStmtImpl {
         span: &Span,
         (): (),
     ) -> Result<(), EvalException> {
-        before_stmt(*span, eval);
-        Ok(())
+        expr_throw(before_stmt(*span, eval), *span, eval)
     }
 }
 
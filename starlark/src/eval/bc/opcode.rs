@@ -105,12 +105,14 @@ pub(crate) enum BcOpcode {
     CallPos,
     CallFrozenDef,
     CallFrozenDefPos,
+    CallFrozenDefNamed,
     CallFrozenNative,
     CallFrozenNativePos,
     CallFrozen,
     CallFrozenPos,
     CallMethod,
     CallMethodPos,
+    TailCallPos,
     Def,
     PossibleGc,
     BeforeStmt,
@@ -15,13 +15,18 @@
  * limitations under the License.
  */
 
-use std::{collections::HashMap, convert::TryInto, iter, mem};
+use std::{
+    cmp,
+    collections::{HashMap, HashSet},
+    convert::TryInto,
+    mem, slice,
+};
 
-use gazebo::dupe::Dupe;
+use gazebo::{dupe::Dupe, prelude::*};
 use indexmap::map::IndexMap;
 
 use crate::{
-    codemap::CodeMap,
+    codemap::{CodeMap, Span},
     environment::{names::MutableNames, slots::ModuleSlotId, EnvironmentError, Globals, Module},
     errors::{did_you_mean::did_you_mean, Diagnostic},
     eval::runtime::slots::LocalSlotId,
@@ -32,7 +37,7 @@ use crate::{
             ForClauseP, ParameterP, Stmt, StmtP, Visibility,
         },
         payload_map::AstPayloadFunction,
-        uniplate::VisitMut,
+        uniplate::{Visit, VisitMut},
     },
     values::{FrozenRef, FrozenValue},
 };
@@ -47,6 +52,8 @@ pub(crate) struct Scope<'a> {
     unscopes: Vec<Unscope>,
     codemap: CodeMap,
     globals: FrozenRef<Globals>,
+    // Values shadowing `globals`, see `Evaluator::set_overrides`.
+    overrides: HashMap<String, FrozenValue>,
     pub(crate) errors: Vec<anyhow::Error>,
 }
 
@@ -59,6 +66,9 @@ struct UnscopeBinding {
     ///
     /// When we pop the comprehension scope, we restore the mapping from this value.
     undo: Option<(LocalSlotId, BindingId)>,
+    /// The slot of the comprehension variable, which is dead after the comprehension.
+    slot: LocalSlotId,
+    binding_id: BindingId,
 }
 
 #[derive(Default)]
@@ -67,13 +77,25 @@ struct Unscope(HashMap<String, UnscopeBinding>);
 #[derive(Default, Debug)]
 pub(crate) struct ScopeNames {
     /// Slots this scope uses, including for parameters and `parent`.
-    /// Indexed by [`LocalSlotId`], values are variable names.
+    /// Indexed by [`LocalSlotId`], values are variable names, or for a slot shared
+    /// by locals which are live at different times, the name of the first.
     pub used: Vec<String>,
     /// The names that are in this scope
     pub mp: HashMap<String, (LocalSlotId, BindingId)>,
     /// Slots to copy from the parent. (index in parent, index in child).
     /// Module-level identifiers are not copied over, to avoid excess copying.
     pub parent: Vec<(LocalSlotId, LocalSlotId)>,
+    /// Slots of comprehension variables which are no longer live, and can be reused
+    /// by later comprehension variables of the same name, so `used` names every
+    /// variable in the slot. Lowest last.
+    ///
+    /// Other locals keep their own slot unless they are sure to be assigned before they
+    /// are read, see [`shared_slots`]: reading one before it is assigned must fail, rather
+    /// than see whatever another variable left in a shared slot.
+    free: Vec<LocalSlotId>,
+    /// The locals which share their slot with others, with the span of the statements
+    /// where each is live, so debuggers only show the one in the slot.
+    pub shared: HashMap<String, Span>,
 }
 
 impl ScopeNames {
@@ -102,13 +124,59 @@ impl ScopeNames {
         slot
     }
 
+    /// Add a local which is live only within `span`, sharing the slot of the local `first`,
+    /// which must have been added already, unless it is `first`.
+    fn add_shared(
+        &mut self,
+        name: &str,
+        binding_id: BindingId,
+        first: &str,
+        span: Span,
+    ) -> LocalSlotId {
+        let slot = match self.mp.get(first) {
+            Some((slot, _)) if name != first => *slot,
+            _ => self.next_slot(name),
+        };
+        let old = self.mp.insert(name.to_owned(), (slot, binding_id));
+        assert!(old.is_none());
+        self.shared.insert(name.to_owned(), span);
+        slot
+    }
+
+    /// The locals in scope at `position`, where the function has got to, if known.
+    /// Of the locals sharing a slot, only the one live at `position` is included.
+    pub(crate) fn live_names(
+        &self,
+        position: Option<Span>,
+    ) -> impl Iterator<Item = (&str, LocalSlotId)> + '_ {
+        self.mp
+            .iter()
+            .filter_map(move |(name, (slot, _))| match self.shared.get(name) {
+                Some(span) if !position.map_or(false, |x| span.contains(x)) => None,
+                _ => Some((name.as_str(), *slot)),
+            })
+    }
+
+    /// Add a comprehension variable. If `reuse` is set, the variable may be given the slot
+    /// of a dead variable of the same name, so it must be assigned before it can be read,
+    /// and not captured.
     fn add_scoped(
         &mut self,
         name: &str,
         binding_id: BindingId,
+        reuse: bool,
         unscope: &mut Unscope,
     ) -> LocalSlotId {
-        let slot = self.next_slot(name);
+        let used = &self.used;
+        let reused = if reuse {
+            self.free
+                .iter()
+                .rposition(|x| used[x.0 as usize] == name)
+                .map(|i| self.free.remove(i))
+        } else {
+            None
+        };
+        let slot = reused.unwrap_or_else(|| self.next_slot(name));
         let undo = match self.mp.get_mut(name) {
             Some(v) => {
                 let old = *v;
@@ -121,12 +189,19 @@ impl ScopeNames {
             }
         };
         let name = name.to_owned();
-        assert!(unscope.0.insert(name, UnscopeBinding { undo }).is_none());
+        let binding = UnscopeBinding {
+            undo,
+            slot,
+            binding_id,
+        };
+        assert!(unscope.0.insert(name, binding).is_none());
         slot
     }
 
-    fn unscope(&mut self, unscope: Unscope) {
-        for (name, UnscopeBinding { undo }) in unscope.0 {
+    fn unscope(&mut self, unscope: Unscope, dead: impl IntoIterator<Item = LocalSlotId>) {
+        self.free.extend(dead);
+        self.free.sort_by_key(|x| cmp::Reverse(x.0));
+        for (name, UnscopeBinding { undo, .. }) in unscope.0 {
             match undo {
                 None => {
                     self.mp.remove(&name);
@@ -168,6 +243,8 @@ impl<'a> Scope<'a> {
         mut scope_data: ScopeData,
         code: &mut CstStmt,
         globals: FrozenRef<Globals>,
+        overrides: HashMap<String, FrozenValue>,
+        share_slots: bool,
         codemap: CodeMap,
     ) -> Self {
         // Not really important, sanity check
@@ -194,7 +271,7 @@ impl<'a> Scope<'a> {
         }
 
         // Here we traverse the AST second time to collect scopes of defs
-        Self::collect_defines_recursively(&mut scope_data, code);
+        Self::collect_defines_recursively(&mut scope_data, code, share_slots);
         let mut scope = Self {
             scope_data,
             module,
@@ -203,6 +280,7 @@ impl<'a> Scope<'a> {
             unscopes: Vec::new(),
             codemap,
             globals,
+            overrides,
             errors: Vec::new(),
         };
         scope.resolve_idents(code);
@@ -220,12 +298,24 @@ impl<'a> Scope<'a> {
         (self.module.slot_count(), scope, self.scope_data)
     }
 
+    /// Collect the locals of a `def` or `lambda`. If `share_slots` is set, locals of a `def`
+    /// which are live at different times share a slot, see [`shared_slots`].
     fn collect_defines_in_def(
         scope_data: &mut ScopeData,
         scope_id: ScopeId,
         params: &mut [CstParameter],
         body: Option<&mut CstStmt>,
+        share_slots: bool,
     ) {
+        let shared = match &body {
+            Some(body) if share_slots => {
+                let names = params
+                    .iter()
+                    .filter_map(|p| p.split().0.map(|x| x.0.as_str()));
+                shared_slots(&names.collect::<Vec<_>>(), body)
+            }
+            _ => HashMap::new(),
+        };
         let params = params.iter_mut().filter_map(|p| match &mut p.node {
             ParameterP::Normal(n, ..) => Some(n),
             ParameterP::WithDefaultValue(n, ..) => Some(n),
@@ -248,32 +338,48 @@ impl<'a> Scope<'a> {
             Stmt::collect_defines(code, InLoop::No, scope_data, &mut locals);
         }
         for (name, binding_id) in locals.into_iter() {
-            let slot = scope_data.mut_scope(scope_id).add_name(name, binding_id);
+            let scope = scope_data.mut_scope(scope_id);
+            let slot = match shared.get(name) {
+                Some((first, span)) => scope.add_shared(name, binding_id, first, *span),
+                None => scope.add_name(name, binding_id),
+            };
             let binding = scope_data.mut_binding(binding_id);
             let old_slot = mem::replace(&mut binding.slot, Some(Slot::Local(slot)));
             assert!(old_slot.is_none());
         }
     }
 
-    fn collect_defines_recursively(scope_data: &mut ScopeData, code: &mut CstStmt) {
+    fn collect_defines_recursively(
+        scope_data: &mut ScopeData,
+        code: &mut CstStmt,
+        share_slots: bool,
+    ) {
         if let StmtP::Def(_name, params, _ret, suite, scope_id) = &mut code.node {
             // Here we traverse the AST twice: once for this def scope,
             // second time below for nested defs.
-            Self::collect_defines_in_def(scope_data, *scope_id, params, Some(suite));
+            Self::collect_defines_in_def(scope_data, *scope_id, params, Some(suite), share_slots);
         }
 
         code.visit_children_mut(&mut |visit| match visit {
-            VisitMut::Expr(e) => Self::collect_defines_recursively_in_expr(scope_data, e),
-            VisitMut::Stmt(s) => Self::collect_defines_recursively(scope_data, s),
+            VisitMut::Expr(e) => {
+                Self::collect_defines_recursively_in_expr(scope_data, e, share_slots)
+            }
+            VisitMut::Stmt(s) => Self::collect_defines_recursively(scope_data, s, share_slots),
         });
     }
 
-    fn collect_defines_recursively_in_expr(scope_data: &mut ScopeData, code: &mut CstExpr) {
+    fn collect_defines_recursively_in_expr(
+        scope_data: &mut ScopeData,
+        code: &mut CstExpr,
+        share_slots: bool,
+    ) {
         if let ExprP::Lambda(params, _expr, scope_id) = &mut code.node {
-            Self::collect_defines_in_def(scope_data, *scope_id, params, None);
+            Self::collect_defines_in_def(scope_data, *scope_id, params, None, share_slots);
         }
 
-        code.visit_expr_mut(|e| Self::collect_defines_recursively_in_expr(scope_data, e));
+        code.visit_expr_mut(|e| {
+            Self::collect_defines_recursively_in_expr(scope_data, e, share_slots)
+        });
     }
 
     fn resolve_idents(&mut self, code: &mut CstStmt) {
@@ -345,6 +451,7 @@ impl<'a> Scope<'a> {
             r.extend(scope.mp.keys().cloned());
         }
         r.extend(self.module_bindings.keys().cloned());
+        r.extend(self.overrides.keys().cloned());
         r.extend(self.globals.names());
         r
     }
@@ -370,7 +477,11 @@ impl<'a> Scope<'a> {
         *resolved_ident = Some(match self.get_name(ident) {
             None => {
                 // Must be a global, since we know all variables
-                match self.globals.get_frozen(ident) {
+                let global = match self.overrides.get(ident.as_str()) {
+                    Some(v) => Some(*v),
+                    None => self.globals.get_frozen(ident),
+                };
+                match global {
                     None => {
                         self.errors.push(self.variable_not_found_err(ident));
                         return;
@@ -395,13 +506,26 @@ impl<'a> Scope<'a> {
 
         // Add identifiers to compr scope
 
+        // A lambda might capture a comprehension variable, which would then need its own slot
+        let mut lambda = false;
+        let mut find_lambda = |x: &CstExpr| lambda = lambda || contains_lambda(x);
+        for x in exprs.iter() {
+            find_lambda(x);
+        }
+        for clause in clauses.iter() {
+            match clause {
+                ClauseP::For(for_clause) => for_clause.visit_expr(&mut find_lambda),
+                ClauseP::If(cond) => find_lambda(cond),
+            }
+        }
+
         self.add_compr(
-            iter::once(&mut first_for.var).chain(clauses.iter_mut().filter_map(
-                |clause| match clause {
-                    ClauseP::For(for_clause) => Some(&mut for_clause.var),
-                    ClauseP::If(..) => None,
-                },
-            )),
+            &mut first_for.var,
+            clauses.iter_mut().filter_map(|clause| match clause {
+                ClauseP::For(for_clause) => Some(&mut for_clause.var),
+                ClauseP::If(..) => None,
+            }),
+            !lambda,
         );
 
         // Now resolve idents in compr scope
@@ -444,16 +568,29 @@ impl<'a> Scope<'a> {
         self.unscopes.push(Unscope::default());
     }
 
-    fn add_compr<'x>(&mut self, var: impl IntoIterator<Item = &'x mut CstAssign>) {
+    /// Add the variables of a comprehension, given the variables of the first `for` clause
+    /// and those of the remaining clauses. The variables of the first `for` are assigned
+    /// before anything in the comprehension can read them, so if none of the variables
+    /// will be captured, they can reuse the slots of dead comprehension variables with
+    /// the same names.
+    fn add_compr<'x>(
+        &mut self,
+        first: &'x mut CstAssign,
+        rest: impl IntoIterator<Item = &'x mut CstAssign>,
+        reuse: bool,
+    ) {
         let scope_id = self.top_scope_id();
         let mut locals = IndexMap::new();
-        for var in var {
+        Assign::collect_defines_lvalue(first, InLoop::Yes, &mut self.scope_data, &mut locals);
+        let first_len = locals.len();
+        for var in rest {
             Assign::collect_defines_lvalue(var, InLoop::Yes, &mut self.scope_data, &mut locals);
         }
-        for (name, binding_id) in locals.into_iter() {
+        for (i, (name, binding_id)) in locals.into_iter().enumerate() {
             let slot = self.scope_data.mut_scope(scope_id).add_scoped(
                 name,
                 binding_id,
+                reuse && i < first_len,
                 self.unscopes.last_mut().unwrap(),
             );
             let binding = self.scope_data.mut_binding(binding_id);
@@ -462,9 +599,22 @@ impl<'a> Scope<'a> {
     }
 
     fn exit_compr(&mut self) {
-        self.scope_data
-            .mut_scope(self.top_scope_id())
-            .unscope(self.unscopes.pop().unwrap());
+        let unscope = self.unscopes.pop().unwrap();
+        // The comprehension variables can't be referenced after the comprehension,
+        // unless they were captured by a lambda.
+        let dead: Vec<LocalSlotId> = unscope
+            .0
+            .values()
+            .filter(|x| {
+                matches!(
+                    self.scope_data.get_binding(x.binding_id).captured,
+                    Captured::No
+                )
+            })
+            .map(|x| x.slot)
+            .collect();
+        let scope_id = self.top_scope_id();
+        self.scope_data.mut_scope(scope_id).unscope(unscope, dead);
     }
 
     fn get_name(&mut self, name: &str) -> Option<(Slot, BindingId)> {
@@ -495,6 +645,172 @@ impl<'a> Scope<'a> {
     }
 }
 
+fn contains_lambda(x: &CstExpr) -> bool {
+    let mut res = matches!(x.node, ExprP::Lambda(..));
+    x.visit_expr(|x| res = res || contains_lambda(x));
+    res
+}
+
+/// The names referenced by some code, whether read or assigned.
+#[derive(Default)]
+struct NameRefs<'a> {
+    /// Referenced by the code itself, in order, possibly more than once.
+    local: Vec<&'a str>,
+    /// Referenced inside a `def` or `lambda` in the code, which might capture them.
+    nested: Vec<&'a str>,
+}
+
+impl<'a> NameRefs<'a> {
+    fn add(&mut self, name: &'a str, nested: bool) {
+        if nested {
+            self.nested.push(name)
+        } else {
+            self.local.push(name)
+        }
+    }
+
+    fn params(&mut self, params: &'a [CstParameter], nested: bool) {
+        for param in params {
+            let (name, typ, default) = param.split();
+            if let Some(name) = name {
+                self.add(&name.0, true);
+            }
+            for x in typ.into_iter().chain(default) {
+                self.expr(x, nested);
+            }
+        }
+    }
+
+    fn assign(&mut self, assign: &'a CstAssign, nested: bool) {
+        assign.visit_lvalue(|x| self.add(&x.0, nested));
+        assign.visit_expr(|x| self.expr(x, nested));
+    }
+
+    fn stmt(&mut self, stmt: &'a CstStmt, nested: bool) {
+        match &stmt.node {
+            StmtP::Def(name, params, ret, body, _) => {
+                self.add(&name.0, nested);
+                self.params(params, nested);
+                if let Some(ret) = ret {
+                    self.expr(ret, nested);
+                }
+                self.stmt(body, true);
+            }
+            StmtP::Assign(lhs, rhs) | StmtP::AssignModify(lhs, _, rhs) => {
+                self.assign(lhs, nested);
+                self.expr(rhs, nested);
+            }
+            StmtP::For(lhs, box (over, body)) => {
+                self.assign(lhs, nested);
+                self.expr(over, nested);
+                self.stmt(body, nested);
+            }
+            StmtP::Load(load) => {
+                for (name, _) in &load.node.args {
+                    self.add(&name.0, nested);
+                }
+            }
+            _ => stmt.visit_children(|x| match x {
+                Visit::Stmt(x) => self.stmt(x, nested),
+                Visit::Expr(x) => self.expr(x, nested),
+            }),
+        }
+    }
+
+    fn expr(&mut self, expr: &'a CstExpr, nested: bool) {
+        match &expr.node {
+            ExprP::Identifier(name, _) => self.add(&name.node, nested),
+            ExprP::Lambda(params, body, _) => {
+                self.params(params, nested);
+                self.expr(body, true);
+            }
+            ExprP::ListComprehension(_, first_for, clauses)
+            | ExprP::DictComprehension(_, first_for, clauses) => {
+                first_for.var.visit_lvalue(|x| self.add(&x.0, nested));
+                for clause in clauses {
+                    if let ClauseP::For(for_clause) = clause {
+                        for_clause.var.visit_lvalue(|x| self.add(&x.0, nested));
+                    }
+                }
+                expr.visit_expr(|x| self.expr(x, nested));
+            }
+            _ => expr.visit_expr(|x| self.expr(x, nested)),
+        }
+    }
+}
+
+/// Find the locals of a `def` which can share a slot, because they are never live at the
+/// same time, such as the temporaries of generated code. Returns the name of the first local
+/// in the slot, and the span of the statements where the local is live, for each local
+/// which shares a slot with another.
+///
+/// A local qualifies if it is first referenced by a statement at the top of the body which
+/// assigns it without otherwise referencing it, so it is always assigned before it is read,
+/// and it is neither a parameter nor referenced in a nested `def` or `lambda`, which might
+/// capture it. It is live from that statement to the last statement which references it,
+/// which includes the whole of any loop that does.
+fn shared_slots(params: &[&str], body: &CstStmt) -> HashMap<String, (String, Span)> {
+    let stmts = match &body.node {
+        StmtP::Statements(xs) => xs.as_slice(),
+        _ => slice::from_ref(body),
+    };
+    let refs = stmts.map(|x| {
+        let mut refs = NameRefs::default();
+        refs.stmt(x, false);
+        refs
+    });
+    let nested: HashSet<&str> = refs.iter().flat_map(|x| x.nested.iter().copied()).collect();
+    // The first and last statements which reference each name
+    let mut live: IndexMap<&str, (usize, usize)> = IndexMap::new();
+    for (i, x) in refs.iter().enumerate() {
+        for name in &x.local {
+            live.entry(*name).or_insert((i, i)).1 = i;
+        }
+    }
+
+    // The locals in each slot, and the last statement where one is live
+    let mut slots: Vec<(Vec<(&str, Span)>, usize)> = Vec::new();
+    for (name, (first, last)) in live {
+        if params.contains(&name) || nested.contains(name) || !assigns_first(&stmts[first], name) {
+            continue;
+        }
+        let span = stmts[first].span.merge(stmts[last].span);
+        match slots.iter_mut().find(|x| x.1 < first) {
+            Some(slot) => {
+                slot.0.push((name, span));
+                slot.1 = last;
+            }
+            None => slots.push((vec![(name, span)], last)),
+        }
+    }
+
+    let mut res = HashMap::new();
+    for (locals, _) in slots {
+        if locals.len() > 1 {
+            let first = locals[0].0;
+            for (name, span) in locals {
+                res.insert(name.to_owned(), (first.to_owned(), span));
+            }
+        }
+    }
+    res
+}
+
+/// Whether `stmt` assigns `name` without otherwise referencing it.
+fn assigns_first(stmt: &CstStmt, name: &str) -> bool {
+    match &stmt.node {
+        StmtP::Assign(lhs, rhs) => {
+            let mut assigned = false;
+            lhs.visit_lvalue(|x| assigned = assigned || x.0 == name);
+            let mut refs = NameRefs::default();
+            lhs.visit_expr(|x| refs.expr(x, false));
+            refs.expr(rhs, false);
+            assigned && !refs.local.contains(&name) && !refs.nested.contains(&name)
+        }
+        _ => false,
+    }
+}
+
 /// While performing analysis.
 #[derive(Copy, Clone, Dupe)]
 enum InLoop {
@@ -787,7 +1103,7 @@ pub(crate) type CstLoad = AstLoadP<CstPayload>;
 
 #[cfg(test)]
 mod test {
-    use std::fmt::Write;
+    use std::{collections::HashMap, fmt::Write};
 
     use crate::{
         environment::{names::MutableNames, Globals},
@@ -816,6 +1132,8 @@ mod test {
             scope_data,
             &mut cst,
             FrozenRef::new(Globals::empty()),
+            HashMap::new(),
+            true,
             ast.codemap,
         );
         assert!(scope.errors.is_empty());
@@ -957,6 +1275,21 @@ def f():
         )
     }
 
+    #[test]
+    fn def_shared_slots() {
+        // `a` is dead when `c` is assigned, so they share a slot, but not with `b`,
+        // which is assigned while `a` is read
+        t(
+            "\
+def f():
+    a = 1
+    b = a
+    c = b
+    return c",
+            "0:m=0 1:l=0 2:l=1 3:l=0 | f:0 a:1 b:2 a:1 c:3 b:2 c:3",
+        )
+    }
+
     #[test]
     fn existing_module_with_names() {
         let module = MutableNames::new();
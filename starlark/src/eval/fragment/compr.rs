@@ -23,16 +23,21 @@ use crate::{
     codemap::{Span, Spanned},
     eval::{
         compiler::{
-            scope::{CstExpr, CstPayload},
+            scope::{CstExpr, CstPayload, ResolvedIdent},
             Compiler,
         },
         fragment::{
+            call::{ArgsCompiledValue, CallCompiled},
             expr::ExprCompiled,
             known::list_to_tuple,
             stmt::{AssignCompiledValue, OptimizeOnFreezeContext},
         },
     },
-    syntax::ast::{ClauseP, ForClauseP},
+    syntax::ast::{ArgumentP, ClauseP, ExprP, ForClauseP},
+    values::{
+        function::{BatchCall, NativeBatchedFunction},
+        FrozenValue, ValueLike,
+    },
 };
 
 impl Compiler<'_, '_, '_> {
@@ -42,11 +47,50 @@ impl Compiler<'_, '_, '_> {
         for_: ForClauseP<CstPayload>,
         clauses: Vec<ClauseP<CstPayload>>,
     ) -> ExprCompiled {
+        if let Some(function) = batched_function(&x, &for_, &clauses) {
+            return self.batched_list_comprehension(function, x, for_, clauses);
+        }
         let clauses = compile_clauses(for_, clauses, self);
         let x = self.expr(x);
         ExprCompiled::Compr(ComprCompiled::List(box x, clauses))
     }
 
+    /// Compile `[f(a, b) for ...]`, where `f` is a batched function, as a single call
+    /// with the arguments of every iteration, `batch_f([(a, b) for ...])`.
+    fn batched_list_comprehension(
+        &mut self,
+        function: FrozenValue,
+        x: CstExpr,
+        for_: ForClauseP<CstPayload>,
+        clauses: Vec<ClauseP<CstPayload>>,
+    ) -> ExprCompiled {
+        let span = x.span;
+        let args = match x.node {
+            ExprP::Call(_, args) => args,
+            _ => unreachable!("checked by batched_function"),
+        };
+        let clauses = compile_clauses(for_, clauses, self);
+        let args = args.into_map(|x| self.expr(x.node.into_expr()));
+        let args = ExprCompiled::tuple(args, self.eval.module_env.frozen_heap());
+        let calls = ExprCompiled::Compr(ComprCompiled::List(
+            box Spanned { span, node: args },
+            clauses,
+        ));
+        let batch = self
+            .eval
+            .module_env
+            .frozen_heap()
+            .alloc_simple(BatchCall { function });
+        CallCompiled::call(
+            span,
+            ExprCompiled::Value(batch),
+            ArgsCompiledValue {
+                pos_named: vec![Spanned { span, node: calls }],
+                ..ArgsCompiledValue::default()
+            },
+        )
+    }
+
     pub fn dict_comprehension(
         &mut self,
         k: CstExpr,
@@ -61,6 +105,54 @@ impl Compiler<'_, '_, '_> {
     }
 }
 
+/// If a list comprehension element is a call to a batched function which can be batched,
+/// return that function. The batched calls are only made after every iteration has run,
+/// so nothing else may be called in the arguments, the conditions or the nested loops.
+/// The first `for` is evaluated before any iteration, so can call anything.
+fn batched_function(
+    x: &CstExpr,
+    for_: &ForClauseP<CstPayload>,
+    clauses: &[ClauseP<CstPayload>],
+) -> Option<FrozenValue> {
+    let (fun, args) = match &x.node {
+        ExprP::Call(fun, args) => (fun, args),
+        _ => return None,
+    };
+    let function = match &fun.node {
+        ExprP::Identifier(_, Some(ResolvedIdent::Global(function))) => *function,
+        _ => return None,
+    };
+    function.downcast_ref::<NativeBatchedFunction>()?;
+
+    let mut exprs = Vec::new();
+    for arg in args {
+        match &arg.node {
+            ArgumentP::Positional(x) => exprs.push(x),
+            _ => return None,
+        }
+    }
+    for_.var.visit_expr(|x| exprs.push(x));
+    for clause in clauses {
+        clause.visit_expr(|x| exprs.push(x));
+    }
+    if exprs.iter().any(|x| contains_call(x)) {
+        None
+    } else {
+        Some(function)
+    }
+}
+
+fn contains_call(x: &CstExpr) -> bool {
+    match &x.node {
+        ExprP::Call(..) => true,
+        x => {
+            let mut res = false;
+            x.visit_expr(|x| res = res || contains_call(x));
+            res
+        }
+    }
+}
+
 /// Peel the final if's from clauses, and return them (in the order they started), plus the next for you get to
 fn compile_ifs(
     clauses: &mut Vec<ClauseP<CstPayload>>,
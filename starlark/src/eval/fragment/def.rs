@@ -21,6 +21,7 @@ use std::{
     cell::UnsafeCell,
     collections::HashMap,
     fmt::{self, Display, Write},
+    intrinsics::unlikely,
     mem, ptr,
 };
 
@@ -415,6 +416,14 @@ impl Compiler<'_, '_, '_> {
         let file = self.codemap.file_span(suite.span);
         let function_name = format!("{}.{}", file.file.filename(), name);
 
+        // Restored once the body, including any nested functions, is compiled
+        let has_before_stmt = self.has_before_stmt;
+        if let Some(skip) = self.eval.skip_before_stmt {
+            if has_before_stmt && skip(file.file.filename(), name) {
+                self.has_before_stmt = false;
+            }
+        }
+
         // The parameters run in the scope of the parent, so compile them with the outer
         // scope
         let params = params.into_map(|x| self.parameter(x));
@@ -440,6 +449,7 @@ impl Compiler<'_, '_, '_> {
             stmt_compile_context: self.compile_context(),
             globals: self.globals,
         });
+        self.has_before_stmt = has_before_stmt;
 
         ExprCompiled::Def(DefCompiled {
             function_name,
@@ -455,7 +465,7 @@ impl Compiler<'_, '_, '_> {
 #[derive(Derivative)]
 #[derivative(Debug)]
 pub(crate) struct DefGen<V> {
-    parameters: ParametersSpec<V>, // The parameters, **kwargs etc including defaults (which are evaluated afresh each time)
+    pub(crate) parameters: ParametersSpec<V>, // The parameters, **kwargs etc including defaults (which are evaluated afresh each time)
     parameter_captures: Vec<u32>,  // Indices of parameters, which are captured in nested defs
     parameter_types: Vec<(u32, String, V, TypeCompiled)>, // The types of the parameters (sparse indexed array, (0, argm T) implies parameter 0 named arg must have type T)
     return_type: Option<(V, TypeCompiled)>, // The return type annotation for the function
@@ -615,11 +625,13 @@ where
         args: Arguments<'v, '_>,
         eval: &mut Evaluator<'v, '_>,
     ) -> anyhow::Result<Value<'v>> {
-        let local_slots = self.def_info.scope_names.used.len() as u32;
-        alloca_frame(eval, local_slots, self.bc().max_stack_size, |eval| {
-            let slots = eval.current_frame.locals();
-            self.parameters.collect_inline(args, slots, eval.heap())?;
-            eval.with_call_stack(me, location, |eval| self.invoke_raw(eval))
+        eval.with_call_hooks(me, location, args, |args, eval| {
+            let local_slots = self.def_info.scope_names.used.len() as u32;
+            alloca_frame(eval, local_slots, self.bc().max_stack_size, |eval| {
+                let slots = eval.current_frame.locals();
+                self.parameters.collect_inline(args, slots, eval.heap())?;
+                eval.with_call_stack(me, location, |eval| self.invoke_raw(eval))
+            })
         })
     }
 
@@ -640,12 +652,72 @@ where
         }
     }
 
+    /// Invoke the function from a call site whose named arguments were resolved
+    /// to parameters when it was compiled, see [`ParametersSpec::resolve_names`].
+    pub(crate) fn invoke_resolved(
+        &self,
+        me: Value<'v>,
+        location: Span,
+        args: Arguments<'v, '_>,
+        resolved: &[u32],
+        eval: &mut Evaluator<'v, '_>,
+    ) -> anyhow::Result<Value<'v>> {
+        eval.with_call_hooks(me, Some(location), args, |args, eval| {
+            let local_slots = self.def_info.scope_names.used.len() as u32;
+            alloca_frame(eval, local_slots, self.bc().max_stack_size, |eval| {
+                let slots = eval.current_frame.locals();
+                self.parameters
+                    .collect_resolved(args.pos, args.named, resolved, slots, eval.heap())?;
+                eval.with_call_stack(me, Some(location), |eval| self.invoke_raw(eval))
+            })
+        })
+    }
+
     /// Invoke the function, assuming that:
     /// * the frame has been allocated and stored in `eval.current_frame`
     /// * the arguments have been collected into the frame
     fn invoke_raw(&self, eval: &mut Evaluator<'v, '_>) -> anyhow::Result<Value<'v>> {
         // println!("invoking {}", self.def.stmt.name.node);
 
+        loop {
+            let ret = self.invoke_body(eval)?;
+            if unlikely(eval.tail_call) {
+                // The body ended with a call to itself, so run it again with the new arguments.
+                eval.tail_call = false;
+                let args = mem::take(&mut eval.tail_call_args);
+                let slots = eval.current_frame.locals();
+                for slot in slots {
+                    slot.set(None);
+                }
+                let arguments = Arguments {
+                    pos: &args,
+                    named: &[],
+                    names: &[],
+                    args: None,
+                    kwargs: None,
+                };
+                self.parameters
+                    .collect_inline(arguments, slots, eval.heap())?;
+                eval.tail_call_args = args;
+                continue;
+            }
+            if eval.check_types() {
+                // Slightly ugly: by the time we check the return type, we no longer
+                // have the location of the return statement, so the "blame" is attached
+                // to the caller, rather than the return statement. Fixing it requires
+                // either passing the type down (ugly) or passing the location back
+                // (ugly and fiddly). Both also imply some runtime cost. If types take off,
+                // worth revisiting.
+                if let Some((tv, t)) = &self.return_type {
+                    ret.check_type_compiled(tv.to_value(), t, None)?
+                }
+            }
+            return Ok(ret);
+        }
+    }
+
+    /// Run the body once, see [`invoke_raw`](DefGen::invoke_raw).
+    fn invoke_body(&self, eval: &mut Evaluator<'v, '_>) -> anyhow::Result<Value<'v>> {
         if eval.check_types() {
             for (i, arg_name, ty, ty2) in &self.parameter_types {
                 match eval.current_frame.get_slot(LocalSlotId::new(*i)) {
@@ -681,24 +753,7 @@ where
         let res = eval.with_function_context(self.module.load_relaxed(), self.def_info, |eval| {
             self.bc().run(eval)
         });
-
-        let ret = match res {
-            Err(EvalException(e)) => return Err(e),
-            Ok(v) => v,
-        };
-
-        if eval.check_types() {
-            // Slightly ugly: by the time we check the return type, we no longer
-            // have the location of the return statement, so the "blame" is attached
-            // to the caller, rather than the return statement. Fixing it requires
-            // either passing the type down (ugly) or passing the location back
-            // (ugly and fiddly). Both also imply some runtime cost. If types take off,
-            // worth revisiting.
-            if let Some((tv, t)) = &self.return_type {
-                ret.check_type_compiled(tv.to_value(), t, None)?
-            }
-        }
-        Ok(ret)
+        res.map_err(|EvalException(e)| e)
     }
 
     pub(crate) fn dump_debug(&self) -> String {
@@ -38,11 +38,15 @@ use crate::{
         runtime::slots::LocalSlotId,
         FrozenDef,
     },
-    syntax::ast::{AstExprP, AstLiteral, AstPayload, AstString, BinOp, ExprP, StmtP},
+    syntax::{
+        ast::{AstExprP, AstLiteral, AstPayload, AstString, BinOp, ExprP, StmtP},
+        lexer::TokenInt,
+    },
     values::{
         function::BoundMethodGen,
         string::{interpolation::parse_percent_s_one, StarlarkStr},
         types::{
+            bigint::{self, StarlarkBigInt},
             bool::StarlarkBool,
             dict::Dict,
             float::StarlarkFloat,
@@ -105,7 +109,7 @@ impl ExprUnOp {
         match self {
             ExprUnOp::Minus => v.minus(heap),
             ExprUnOp::Plus => v.plus(heap),
-            ExprUnOp::BitNot => Ok(Value::new_int(!v.to_int()?)),
+            ExprUnOp::BitNot => bigint::bit_not(v, heap),
         }
     }
 }
@@ -136,11 +140,11 @@ impl ExprBinOp {
             ExprBinOp::Percent => a.percent(b, heap),
             ExprBinOp::Divide => a.div(b, heap),
             ExprBinOp::FloorDivide => a.floor_div(b, heap),
-            ExprBinOp::BitAnd => a.bit_and(b),
-            ExprBinOp::BitOr => a.bit_or(b),
-            ExprBinOp::BitXor => a.bit_xor(b),
-            ExprBinOp::LeftShift => a.left_shift(b),
-            ExprBinOp::RightShift => a.right_shift(b),
+            ExprBinOp::BitAnd => a.bit_and(b, heap),
+            ExprBinOp::BitOr => a.bit_or(b, heap),
+            ExprBinOp::BitXor => a.bit_xor(b, heap),
+            ExprBinOp::LeftShift => a.left_shift(b, heap),
+            ExprBinOp::RightShift => a.right_shift(b, heap),
         }
     }
 }
@@ -615,6 +619,8 @@ impl ExprCompiled {
             Some(ExprCompiled::Value(heap.alloc_float(*v)))
         } else if let Some(v) = v.downcast_ref::<Range>() {
             Some(ExprCompiled::Value(heap.alloc(*v)))
+        } else if let Some(v) = StarlarkBigInt::from_value(v) {
+            Some(ExprCompiled::Value(heap.alloc(v.clone())))
         } else if let Some(v) = List::from_value(v) {
             // When spec-safe function returned a non-frozen list,
             // we try to convert that list to a list of constants instruction.
@@ -841,9 +847,13 @@ fn eval_equals(l: Spanned<ExprCompiled>, r: Spanned<ExprCompiled>) -> ExprCompil
 impl AstLiteral {
     fn compile(&self, heap: &FrozenHeap) -> FrozenValue {
         match self {
-            AstLiteral::Int(i) => FrozenValue::new_int(i.node),
+            AstLiteral::Int(i) => match &i.node {
+                TokenInt::I32(i) => FrozenValue::new_int(*i),
+                TokenInt::BigInt(i) => StarlarkBigInt::alloc_frozen((**i).clone(), heap),
+            },
             AstLiteral::Float(f) => heap.alloc(f.node),
             AstLiteral::String(x) => heap.alloc(x.node.as_str()),
+            AstLiteral::Bytes(x) => heap.alloc(x.node.clone()),
         }
     }
 }
@@ -17,7 +17,17 @@
 
 //! Compile and evaluate module top-level statements.
 
+use std::{
+    collections::{HashMap, HashSet},
+    hash::{Hash, Hasher},
+    time::Instant,
+};
+
+use gazebo::prelude::*;
+
 use crate::{
+    codemap::Span,
+    collections::StarlarkHasher,
     environment::EnvironmentError,
     eval::{
         bc::frame::alloca_frame,
@@ -26,11 +36,58 @@ use crate::{
             scope::{CstLoad, CstStmt, ScopeId, Slot},
             Compiler, EvalException,
         },
+        runtime::{
+            checkpoint::{CheckpointError, CheckpointRestorer, CheckpointSaver},
+            file_loader::LoadChainEntry,
+        },
+        Checkpoint, LoadError, LoadEvent,
     },
     syntax::ast::StmtP,
     values::Value,
 };
 
+/// The module variables a top-level statement binds, and whether it is a `load` or `def`,
+/// whose values can't be checkpointed, so are bound again by running it when resuming.
+fn stmt_bindings(stmt: &CstStmt) -> (bool, Vec<String>) {
+    fn assigned(x: &CstStmt, res: &mut Vec<String>) {
+        match &x.node {
+            StmtP::Assign(dest, _) | StmtP::AssignModify(dest, _, _) | StmtP::For(dest, _) => {
+                dest.visit_lvalue(|x| res.push(x.0.clone()))
+            }
+            StmtP::Def(name, ..) => {
+                res.push(name.0.clone());
+                // The assignments in the body are local
+                return;
+            }
+            StmtP::Load(load) => res.extend(load.node.args.iter().map(|(x, _)| x.0.clone())),
+            _ => {}
+        }
+        x.visit_stmt(|x| assigned(x, res));
+    }
+
+    let mut res = Vec::new();
+    assigned(stmt, &mut res);
+    (matches!(stmt.node, StmtP::Load(_) | StmtP::Def(..)), res)
+}
+
+/// The `load` and `def` statements before statement `end` which are run again when resuming
+/// a checkpoint taken before `end`: those which bind variables no later statement before
+/// `end` binds again. Running any other would overwrite a restored variable.
+fn rerunnable(bindings: &[(bool, Vec<String>)], end: usize) -> HashSet<usize> {
+    let mut last = HashMap::new();
+    for (i, (_, names)) in bindings[..end].iter().enumerate() {
+        for name in names {
+            last.insert(name.as_str(), i);
+        }
+    }
+    bindings[..end]
+        .iter()
+        .enumerate()
+        .filter(|(i, (rerun, names))| *rerun && names.iter().all(|x| last[x.as_str()] == *i))
+        .map(|(i, _)| i)
+        .collect()
+}
+
 impl<'v> Compiler<'v, '_, '_> {
     fn eval_load(&mut self, load: CstLoad) -> Result<(), EvalException> {
         let name = load.node.module.node;
@@ -43,7 +100,35 @@ impl<'v> Compiler<'v, '_, '_> {
                     self.eval,
                 ));
             }
-            Some(loader) => expr_throw(loader.load(&name), load.span, self.eval)?,
+            Some(loader) => {
+                let entry = LoadChainEntry::enter(&name, &self.eval.file_span(load.span));
+                if entry.is_cycle() {
+                    return Err(add_span_to_expr_error(
+                        LoadError::cycle(&name).into(),
+                        load.span,
+                        self.eval,
+                    ));
+                }
+                #[cfg(feature = "tracing")]
+                let _span = tracing::info_span!("starlark_load", module = name.as_str()).entered();
+                let res = match self.eval.load_logger {
+                    None => loader.load(&name),
+                    Some(logger) => {
+                        let start = Instant::now();
+                        let res = loader.load(&name);
+                        logger.log(LoadEvent {
+                            module: name.clone(),
+                            location: self.eval.file_span(load.span).to_string(),
+                            symbols: load.node.args.iter().map(|x| x.1.node.clone()).collect(),
+                            details: loader.describe_load(&name),
+                            duration: start.elapsed(),
+                            error: res.as_ref().err().map(|e| format!("{:#}", e)),
+                        });
+                        res
+                    }
+                };
+                expr_throw(res, load.span, self.eval)?
+            }
         };
 
         for (our_name, their_name) in load.node.args {
@@ -93,13 +178,149 @@ impl<'v> Compiler<'v, '_, '_> {
         }
     }
 
+    /// A hash of the module's code and the names of its globals, so we can check a
+    /// checkpoint is resumed with the same ones. Must be stable between processes.
+    fn checkpoint_hash(&self) -> u64 {
+        let mut hasher = StarlarkHasher::new();
+        self.codemap.source().hash(&mut hasher);
+        let mut names = self.globals.names();
+        names.sort();
+        names.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Save the module variables, except those which can't be saved but will be bound
+    /// again by rerunning the statements in `rerun`.
+    fn checkpoint(
+        &self,
+        statement: usize,
+        hash: u64,
+        rerun: &HashSet<String>,
+    ) -> anyhow::Result<Checkpoint> {
+        let module = self.eval.module_env;
+        let mut saver = CheckpointSaver::default();
+        let mut variables = Vec::new();
+        for (name, slot) in module.names().all_names() {
+            if let Some(value) = module.slots().get_slot(slot) {
+                match saver.save(value) {
+                    Some(x) => variables.push((name, x)),
+                    None if rerun.contains(&name) => {}
+                    None => {
+                        return Err(CheckpointError::Unsupported(name, value.get_type()).into());
+                    }
+                }
+            }
+        }
+        Ok(Checkpoint {
+            statement,
+            hash,
+            values: saver.finish(),
+            variables,
+        })
+    }
+
+    fn restore(
+        &mut self,
+        checkpoint: &Checkpoint,
+        hash: u64,
+        span: Span,
+    ) -> Result<(), EvalException> {
+        if checkpoint.hash != hash {
+            return Err(add_span_to_expr_error(
+                CheckpointError::HashMismatch.into(),
+                span,
+                self.eval,
+            ));
+        }
+        let mut restorer = CheckpointRestorer::new(
+            &checkpoint.values,
+            self.eval.heap(),
+            self.eval.module_env.frozen_heap(),
+        );
+        for (name, value) in &checkpoint.variables {
+            let slot = match self.eval.module_env.names().get_name(name) {
+                Some((slot, _vis)) => slot,
+                None => {
+                    return Err(add_span_to_expr_error(
+                        CheckpointError::UnknownVariable(name.clone()).into(),
+                        span,
+                        self.eval,
+                    ));
+                }
+            };
+            let value = expr_throw(restorer.restore(*value), span, self.eval)?;
+            self.eval.set_slot_module(slot, value);
+        }
+        Ok(())
+    }
+
+    /// Evaluate the top-level statements one at a time, offering a checkpoint before each.
+    /// When resuming, the checkpointed variables are restored first, then statements before
+    /// the checkpoint are skipped, apart from the `load` and `def` statements which are
+    /// [`rerunnable`], whose values can't be checkpointed, and which may refer to the restored
+    /// variables, e.g. in defaults.
+    fn eval_top_level_stmts_checkpointed(
+        &mut self,
+        stmt: CstStmt,
+        local_count: u32,
+    ) -> Result<Value<'v>, EvalException> {
+        let span = stmt.span;
+        let stmts = match stmt.node {
+            StmtP::Statements(stmts) => stmts,
+            _ => vec![stmt],
+        };
+        let resume = self.eval.resume.take();
+        let start = resume.as_ref().map_or(0, |x| x.statement);
+        let len = stmts.len();
+        if start > len {
+            return Err(add_span_to_expr_error(
+                CheckpointError::StatementOutOfRange(start, len).into(),
+                span,
+                self.eval,
+            ));
+        }
+        let hash = self.checkpoint_hash();
+        if let Some(resume) = &resume {
+            self.restore(resume, hash, span)?;
+        }
+
+        let bindings = stmts.map(stmt_bindings);
+        let rerun_on_resume = rerunnable(&bindings, start);
+        let mut last = Value::new_none();
+        for (i, stmt) in stmts.into_iter().enumerate() {
+            if i < start && !rerun_on_resume.contains(&i) {
+                continue;
+            }
+            if i >= start {
+                if let Some(checkpointer) = self.eval.checkpointer {
+                    if checkpointer.want_checkpoint(i) {
+                        let rerun: HashSet<String> = rerunnable(&bindings, i)
+                            .into_iter()
+                            .flat_map(|j| bindings[j].1.iter().cloned())
+                            .collect();
+                        let res = self
+                            .checkpoint(i, hash, &rerun)
+                            .and_then(|x| checkpointer.checkpoint(x));
+                        expr_throw(res, stmt.span, self.eval)?;
+                    }
+                }
+            }
+            last = self.eval_top_level_stmt(stmt, local_count)?;
+        }
+        Ok(last)
+    }
+
     pub(crate) fn eval_module(
         &mut self,
         stmt: CstStmt,
         local_count: u32,
     ) -> Result<Value<'v>, EvalException> {
         self.enter_scope(ScopeId::module());
-        let value = self.eval_top_level_stmt(stmt, local_count)?;
+        let value = if self.eval.checkpointer.is_some() || self.eval.resume.is_some() {
+            self.eval_top_level_stmts_checkpointed(stmt, local_count)?
+        } else {
+            self.eval_top_level_stmt(stmt, local_count)?
+        };
         self.exit_scope();
         assert!(self.locals.is_empty());
         Ok(value)
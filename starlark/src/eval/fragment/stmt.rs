@@ -23,7 +23,7 @@
 //! Bazel's .bzl files) or the BUILD file dialect (i.e. used to interpret
 //! Bazel's BUILD file). The BUILD dialect does not allow `def` statements.
 
-use std::mem;
+use std::time::Instant;
 
 use anyhow::anyhow;
 use gazebo::prelude::*;
@@ -39,12 +39,14 @@ use crate::{
         },
         fragment::{expr::ExprCompiled, known::list_to_tuple, small_vec_1::SmallVec1},
         runtime::{
-            evaluator::{Evaluator, GC_THRESHOLD},
+            evaluator::{Evaluator, EvaluatorError},
             slots::LocalSlotId,
         },
     },
     syntax::ast::{AssignOp, AssignP, StmtP},
-    values::{list::List, FrozenHeap, FrozenValue, Heap, Value, ValueError},
+    values::{
+        list::List, string::rope::StarlarkRope, FrozenHeap, FrozenValue, Heap, Value, ValueError,
+    },
 };
 
 #[derive(Clone, Debug)]
@@ -78,6 +80,7 @@ pub(crate) enum StmtCompiled {
 pub(crate) struct StmtCompileContext {
     pub(crate) has_before_stmt: bool,
     pub(crate) bc_profile: bool,
+    pub(crate) tail_calls: bool,
 }
 
 pub(crate) struct OptimizeOnFreezeContext<'a> {
@@ -438,23 +441,40 @@ impl Compiler<'_, '_, '_> {
 }
 
 // This function should be called before every meaningful statement.
-// The purposes are GC, profiling and debugging.
+// The purposes are GC, profiling, debugging, cancellation and limiting the steps,
+// time and memory taken.
 //
-// This function is called only if `before_stmt` is set before compilation start.
-pub(crate) fn before_stmt(span: Span, eval: &mut Evaluator) {
-    assert!(
-        !eval.before_stmt.is_empty(),
-        "this code should not be called if `before_stmt` is set"
-    );
-    let fs = mem::take(&mut eval.before_stmt);
-    for f in &fs {
-        f(span, eval)
-    }
-    let added = mem::replace(&mut eval.before_stmt, fs);
-    assert!(
-        added.is_empty(),
-        "`before_stmt` cannot be modified during evaluation"
-    );
+// This function is called only if `before_stmt`, `max_steps`, `deadline`, `cancellation`
+// or the heap's allocation limit is set before compilation start.
+pub(crate) fn before_stmt(span: Span, eval: &mut Evaluator) -> anyhow::Result<()> {
+    if let Some(max_steps) = eval.max_steps {
+        if eval.steps >= max_steps {
+            return Err(EvaluatorError::StepLimitExceeded(max_steps).into());
+        }
+        eval.steps += 1;
+    }
+    if let Some(deadline) = eval.deadline {
+        if Instant::now() >= deadline {
+            return Err(EvaluatorError::DeadlineExceeded.into());
+        }
+    }
+    if let Some(cancellation) = &eval.cancellation {
+        if cancellation.is_cancelled() {
+            return Err(EvaluatorError::Cancelled.into());
+        }
+    }
+    eval.heap().check_allocation_limit(0)?;
+    if eval.suppress_hooks != 0 {
+        return Ok(());
+    }
+    // The functions may add or remove functions, so after each one, continue from the
+    // first function added after it.
+    let mut i = 0;
+    while let Some((handle, f)) = eval.before_stmt.get(i).copied() {
+        f(span, eval);
+        i = eval.before_stmt.partition_point(|x| x.0 <= handle);
+    }
+    Ok(())
 }
 
 // There are two requirements to perform a GC:
@@ -497,7 +517,7 @@ pub(crate) fn possible_gc(eval: &mut Evaluator) {
         unsafe {
             eval.garbage_collect()
         }
-        eval.next_gc_level = eval.heap().allocated_bytes() + GC_THRESHOLD;
+        eval.next_gc_level = eval.heap().allocated_bytes() + eval.gc_threshold();
     }
 }
 
@@ -508,17 +528,10 @@ pub(crate) fn add_assign<'v>(
     rhs: Value<'v>,
     heap: &'v Heap,
 ) -> anyhow::Result<Value<'v>> {
-    // Addition of strings is super common, so have a special case
-    if let Some(ls) = lhs.unpack_str() {
-        if let Some(rs) = rhs.unpack_str() {
-            if ls.is_empty() {
-                return Ok(rhs);
-            } else if rs.is_empty() {
-                return Ok(lhs);
-            } else {
-                return Ok(heap.alloc_str_concat(ls, rs));
-            }
-        }
+    // Addition of strings is super common, so have a special case,
+    // which avoids copying `lhs` when appending to a long string in a loop.
+    if let Some(v) = StarlarkRope::concat(lhs, rhs, heap) {
+        return Ok(v);
     }
 
     // The Starlark spec says list += mutates, while nothing else does.
@@ -538,6 +551,7 @@ pub(crate) fn add_assign<'v>(
             // If the value is None, that must mean its a FrozenList, thus turn it into an immutable error
             let list = List::from_value_mut(lhs)?
                 .ok_or_else(|| anyhow!(ValueError::CannotMutateImmutableValue))?;
+            heap.write_barrier(lhs);
             if lhs.ptr_eq(rhs) {
                 list.double(heap);
             } else {
@@ -556,6 +570,7 @@ impl Compiler<'_, '_, '_> {
         StmtCompileContext {
             has_before_stmt: self.has_before_stmt,
             bc_profile: self.bc_profile,
+            tail_calls: self.eval.tail_calls,
         }
     }
 
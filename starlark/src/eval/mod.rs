@@ -25,13 +25,35 @@ pub(crate) use fragment::def::{Def, FrozenDef};
 use gazebo::{cast, prelude::*};
 pub use runtime::{
     arguments::{Arguments, ParametersParser, ParametersSpec},
-    evaluator::Evaluator,
-    file_loader::{FileLoader, ReturnFileLoader},
+    async_native::{eval_async, set_async_eval_threads, AsyncEval},
+    call_hook::CallHook,
+    cancellation::CancellationHandle,
+    checkpoint::{Checkpoint, CheckpointValue, Checkpointer},
+    coercions::Coercions,
+    evaluator::{BeforeStmtHandle, Evaluator},
+    file_loader::{
+        AsyncFileLoader, AsyncLoader, CachedLoader, FileLoader, LoadError, LoadEvent, LoadLogger,
+        LoadStep, PathMapping, ReturnFileLoader,
+    },
+    heap_snapshot::{HeapSnapshot, HeapSnapshotNode},
+    parallel::{ModuleLimits, ParallelEval},
+    pinned::PinnedValue,
+    print_stream::PrintStream,
+    profile_mode::ProfileMode,
+    provenance::ValueProvenance,
+    stats::EvalStats,
+    stepper::{eval_stepped, StepResult, Stepper},
+};
+pub(crate) use runtime::{
+    call_stack::{CallStackError, MAX_CALLSTACK_RECURSION},
+    evaluator::{EvaluatorError, GC_THRESHOLD},
 };
 
+pub use crate::stdlib::PrintHandler;
 use crate::{
     collections::symbol_map::Symbol,
-    environment::Globals,
+    environment::{FrozenModule, Globals, Module},
+    errors::{Error, ErrorKind},
     eval::{
         compiler::{
             scope::{CompilerAstMap, Scope, ScopeData},
@@ -52,13 +74,62 @@ mod runtime;
 mod tests;
 
 impl<'v, 'a> Evaluator<'v, 'a> {
+    /// Evaluate an [`AstModule`] in a new [`Module`](crate::environment::Module), so `async`
+    /// native functions suspend the evaluation rather than blocking the thread polling the
+    /// result. Returns the frozen module. `load()` statements are resolved by `loader`, and
+    /// fail if it is `None`. Use [`eval_async`] to set up the [`Evaluator`] differently.
+    ///
+    /// The evaluation runs on a pooled OS thread, which it holds until it finishes, including
+    /// while a native function awaits, so one thread is used per evaluation in flight. At most
+    /// [`set_async_eval_threads`] (by default 64) run at once, and further evaluations wait
+    /// for one of them to finish.
+    pub fn eval_module_async(
+        ast: AstModule,
+        globals: Globals,
+        loader: Option<Box<dyn FileLoader + Send>>,
+    ) -> AsyncEval<FrozenModule> {
+        eval_async(move || {
+            let module = Module::new();
+            let mut eval = Evaluator::new(&module);
+            if let Some(loader) = &loader {
+                eval.set_loader(&**loader);
+            }
+            eval.eval_module(ast, &globals)?;
+            drop(eval);
+            module.freeze()
+        })
+    }
+
+    /// Evaluate one input to a REPL or notebook cell against the in-scope
+    /// [`Module`](crate::environment::Module), like [`eval_module`](Evaluator::eval_module),
+    /// but returning the value of the last statement only if it is an expression, e.g. `Some`
+    /// for `x = 1; x + 1` and `None` for `x = 1`. Variables defined by earlier inputs remain
+    /// available, so the same module can be used for the whole session. If a statement fails,
+    /// the variables assigned by those before it keep their new values.
+    pub fn eval_interactive(
+        &mut self,
+        ast: AstModule,
+        globals: &Globals,
+    ) -> Result<Option<Value<'v>>, Error> {
+        let trailing_expression = ast.ends_with_expression();
+        let res = self.eval_module(ast, globals)?;
+        Ok(if trailing_expression { Some(res) } else { None })
+    }
+
     /// Evaluate an [`AstModule`] with this [`Evaluator`], modifying the in-scope
-    /// [`Module`](crate::environment::Module) as appropriate.
-    pub fn eval_module(&mut self, ast: AstModule, globals: &Globals) -> anyhow::Result<Value<'v>> {
+    /// [`Module`](crate::environment::Module) as appropriate. On failure the [`Error`] gives
+    /// the kind of problem, its location and the call stack.
+    pub fn eval_module(&mut self, ast: AstModule, globals: &Globals) -> Result<Value<'v>, Error> {
         let start = Instant::now();
+        #[cfg(feature = "tracing")]
+        let _span =
+            tracing::info_span!("starlark_eval_module", file = ast.codemap.filename()).entered();
 
+        self.module_env
+            .record_definitions(ast.top_level_assignments());
         let AstModule { codemap, statement } = ast;
 
+        self.coercions = globals.coercions();
         let globals = self.module_env.frozen_heap().alloc_any(globals.dupe());
 
         let mut scope_data = ScopeData::new();
@@ -77,6 +148,9 @@ impl<'v, 'a> Evaluator<'v, 'a> {
             scope_data,
             &mut statement,
             globals,
+            mem::take(&mut self.overrides),
+            // Locals sharing slots would confuse a debugger stopped between statements
+            self.before_stmt.is_empty(),
             codemap.dupe(),
         );
 
@@ -84,7 +158,7 @@ impl<'v, 'a> Evaluator<'v, 'a> {
         scope.errors.truncate(1);
         if let Some(e) = scope.errors.pop() {
             // Static errors, reported even if the branch is not hit
-            return Err(e);
+            return Err(Error::new_kind(ErrorKind::Static, e));
         }
 
         let span = statement.span;
@@ -124,12 +198,18 @@ impl<'v, 'a> Evaluator<'v, 'a> {
             globals,
             codemap: codemap.dupe(),
             constants: Constants::new(),
-            has_before_stmt: !self.before_stmt.is_empty(),
+            has_before_stmt: !self.before_stmt.is_empty()
+                || self.max_steps.is_some()
+                || self.deadline.is_some()
+                || self.cancellation.is_some()
+                || self.heap().allocation_limit().is_some(),
             bc_profile: self.bc_profile.enabled(),
             eval: self,
         };
 
+        self.stats.start();
         let res = compiler.eval_module(statement, local_count);
+        self.stats.stop();
 
         // Clean up the world, putting everything back
         self.call_stack.pop();
@@ -142,7 +222,7 @@ impl<'v, 'a> Evaluator<'v, 'a> {
         self.module_env.add_eval_duration(start.elapsed());
 
         // Return the result of evaluation
-        res.map_err(|e| e.0)
+        res.map_err(|e| Error::new(e.0))
     }
 
     /// Evaluate a function stored in a [`Value`], passing in `positional` and `named` arguments.
@@ -34,8 +34,8 @@ use crate::{
     },
     eval::Evaluator,
     values::{
-        dict::Dict, docs, docs::DocString, Freezer, FrozenValue, Heap, StringValue, Trace, Tracer,
-        UnpackValue, Value, ValueError, ValueLike,
+        dict::Dict, docs, docs::DocString, Freezer, FrozenStringValue, FrozenValue, Heap,
+        StringValue, Trace, Tracer, UnpackValue, Value, ValueError, ValueLike,
     },
 };
 
@@ -291,6 +291,31 @@ impl<V> ParametersSpec<V> {
         }
     }
 
+    /// Resolve the named arguments of a call site with `pos` positional arguments
+    /// (and no `*args` or `**kwargs`) to the indices of the parameters they fill,
+    /// so they don't have to be looked up by name on every call.
+    ///
+    /// Returns `None` if the call needs the general [`collect`](ParametersSpec::collect),
+    /// e.g. because some arguments would end up in `*args` or `**kwargs`, or because
+    /// a name clashes with a positional argument (which is an error).
+    pub(crate) fn resolve_names(
+        &self,
+        pos: usize,
+        names: &[(Symbol, FrozenStringValue)],
+    ) -> Option<Box<[u32]>> {
+        if pos > self.positional {
+            return None;
+        }
+        // Names are unique at a call site, so they resolve to distinct parameters
+        names
+            .iter()
+            .map(|(name, _)| match self.names.get(name) {
+                Some(i) if *i >= pos => (*i).try_into().ok(),
+                _ => None,
+            })
+            .collect()
+    }
+
     /// Iterate over the parameters
     ///
     /// Returns an iterator over (parameter index, name, kind)
@@ -522,15 +547,50 @@ impl<'v, V: ValueLike<'v>> ParametersSpec<V> {
 
         // We have moved parameters into all the relevant slots, so need to finalise things.
         // We need to set default values and error if any required values are missing
+        self.collect_defaults(next_position, slots)?;
+
+        // Now set the kwargs/args slots, if they are requested, and fail it they are absent but used
+        // Note that we deliberately give warnings about missing parameters _before_ giving warnings
+        // about unexpected extra parameters, so if a user mis-spells an argument they get a better error.
+        if let Some(args_pos) = self.args {
+            slots[args_pos].set(Some(heap.alloc_tuple(&star_args)));
+        } else if unlikely(!star_args.is_empty()) {
+            return Err(FunctionError::ExtraPositionalParameters {
+                count: star_args.len(),
+                function: self.signature(),
+            }
+            .into());
+        }
+
+        if let Some(kwargs_pos) = self.kwargs {
+            slots[kwargs_pos].set(Some(kwargs.alloc(heap)));
+        } else if let Some(kwargs) = kwargs.kwargs {
+            return Err(FunctionError::ExtraNamedParameters {
+                names: kwargs.keys().map(|x| x.as_str().to_owned()).collect(),
+                function: self.signature(),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Set the default values of the unfilled parameters from index `from` onwards,
+    /// failing if any of them are required.
+    #[inline(always)]
+    fn collect_defaults(
+        &self,
+        from: usize,
+        slots: &[Cell<Option<Value<'v>>>],
+    ) -> anyhow::Result<()> {
         let kinds = &self.kinds;
         // This code is very hot, and setting up iterators was a noticeable bottleneck.
-        for index in next_position..kinds.len() {
+        for index in from..kinds.len() {
             // The number of locals must be at least the number of parameters, see `collect`
             // which reserves `max(_, kinds.len())`.
             let slot = unsafe { slots.get_unchecked(index) };
             let def = unsafe { kinds.get_unchecked(index) };
 
-            // We know that up to next_position got filled positionally, so we don't need to check those
+            // We know that up to `from` got filled positionally, so we don't need to check those
             if slot.get().is_some() {
                 continue;
             }
@@ -548,28 +608,35 @@ impl<'v, V: ValueLike<'v>> ParametersSpec<V> {
                 _ => {}
             }
         }
+        Ok(())
+    }
 
-        // Now set the kwargs/args slots, if they are requested, and fail it they are absent but used
-        // Note that we deliberately give warnings about missing parameters _before_ giving warnings
-        // about unexpected extra parameters, so if a user mis-spells an argument they get a better error.
+    /// Like [`collect`](ParametersSpec::collect), but for a call site without `*args` or
+    /// `**kwargs`, whose named arguments were resolved by
+    /// [`resolve_names`](ParametersSpec::resolve_names) when it was compiled.
+    #[inline(always)]
+    pub(crate) fn collect_resolved(
+        &self,
+        pos: &[Value<'v>],
+        named: &[Value<'v>],
+        resolved: &[u32],
+        slots: &[Cell<Option<Value<'v>>>],
+        heap: &'v Heap,
+    ) -> anyhow::Result<()> {
+        // We might do unchecked stuff later on, so make sure we have as many slots as we expect
+        assert!(slots.len() >= self.kinds.len());
+        for (v, s) in pos.iter().zip(slots.iter()) {
+            s.set(Some(*v));
+        }
+        for (v, i) in named.iter().zip(resolved) {
+            slots[*i as usize].set(Some(*v));
+        }
+        self.collect_defaults(pos.len(), slots)?;
         if let Some(args_pos) = self.args {
-            slots[args_pos].set(Some(heap.alloc_tuple(&star_args)));
-        } else if unlikely(!star_args.is_empty()) {
-            return Err(FunctionError::ExtraPositionalParameters {
-                count: star_args.len(),
-                function: self.signature(),
-            }
-            .into());
+            slots[args_pos].set(Some(heap.alloc_tuple(&[])));
         }
-
         if let Some(kwargs_pos) = self.kwargs {
-            slots[kwargs_pos].set(Some(kwargs.alloc(heap)));
-        } else if let Some(kwargs) = kwargs.kwargs {
-            return Err(FunctionError::ExtraNamedParameters {
-                names: kwargs.keys().map(|x| x.as_str().to_owned()).collect(),
-                function: self.signature(),
-            }
-            .into());
+            slots[kwargs_pos].set(Some(heap.alloc(Dict::default())));
         }
         Ok(())
     }
@@ -992,6 +1059,51 @@ impl Arguments<'_, '_> {
             )?)),
         }
     }
+
+    /// Like [`check_required`](Arguments::check_required), but applying the
+    /// [`Coercions`](crate::eval::Coercions) of the [`Evaluator`] if the parameter doesn't match.
+    /// Used by functions defined with `#[starlark_module]`.
+    pub fn check_required_coerced<'v, T: UnpackValue<'v>>(
+        eval: &Evaluator<'v, '_>,
+        name: &str,
+        x: Option<Value<'v>>,
+    ) -> anyhow::Result<T> {
+        let x = x.ok_or_else(|| ValueError::MissingRequired(name.to_owned()))?;
+        Self::check_coerced(eval, name, x)
+    }
+
+    /// Like [`check_optional`](Arguments::check_optional), but applying the
+    /// [`Coercions`](crate::eval::Coercions) of the [`Evaluator`] if the parameter doesn't match.
+    /// Used by functions defined with `#[starlark_module]`.
+    pub fn check_optional_coerced<'v, T: UnpackValue<'v>>(
+        eval: &Evaluator<'v, '_>,
+        name: &str,
+        x: Option<Value<'v>>,
+    ) -> anyhow::Result<Option<T>> {
+        match x {
+            None => Ok(None),
+            Some(x) => Ok(Some(Self::check_coerced(eval, name, x)?)),
+        }
+    }
+
+    fn check_coerced<'v, T: UnpackValue<'v>>(
+        eval: &Evaluator<'v, '_>,
+        name: &str,
+        x: Value<'v>,
+    ) -> anyhow::Result<T> {
+        if let Some(v) = T::unpack_value(x) {
+            return Ok(v);
+        }
+        if let Some(v) = eval
+            .coercions
+            .coerce(x, eval.heap())
+            .and_then(T::unpack_value)
+        {
+            return Ok(v);
+        }
+        // Fails, but gives the type a chance to produce a more precise error.
+        T::unpack_named_param(x, name)
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,412 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Evaluate with native functions which are `async`, without blocking the caller's thread.
+//!
+//! The interpreter can't suspend part way through a call, so an asynchronous evaluation runs
+//! on a thread of its own, taken from a bounded pool. When a native function awaits, its
+//! future is passed back to the [`AsyncEval`] future, which polls it on behalf of the
+//! evaluation thread, so it runs in the caller's executor (with access to its reactor) while
+//! the evaluation thread waits.
+
+use std::{
+    any::Any,
+    cell::RefCell,
+    collections::VecDeque,
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    sync::{Arc, Condvar, Mutex},
+    task::{Context, Poll, Wake, Waker},
+    thread::{self, Thread},
+};
+
+use gazebo::prelude::*;
+use once_cell::sync::Lazy;
+use thiserror::Error;
+
+type NativeFuture = Pin<Box<dyn Future<Output = anyhow::Result<Box<dyn Any + Send>>> + Send>>;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// The default for [`set_async_eval_threads`].
+const DEFAULT_ASYNC_EVAL_THREADS: usize = 64;
+
+#[derive(Debug, Error)]
+enum AsyncError {
+    #[error("The asynchronous evaluation was abandoned while a native function was awaiting")]
+    Abandoned,
+}
+
+/// State shared between an [`AsyncEval`] and the thread running its evaluation.
+#[derive(Default)]
+struct Shared {
+    state: Mutex<State>,
+    // Notified when `result` is set, or the evaluation is abandoned.
+    ready: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    // A native function's future, waiting to be polled by the `AsyncEval`.
+    pending: Option<NativeFuture>,
+    // The result of the last future, waiting to be taken by the evaluation thread.
+    result: Option<anyhow::Result<Box<dyn Any + Send>>>,
+    // The evaluation thread has finished.
+    finished: bool,
+    // The `AsyncEval` was dropped before the evaluation finished.
+    abandoned: bool,
+    // Wakes the task polling the `AsyncEval`.
+    waker: Option<Waker>,
+}
+
+impl State {
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+thread_local! {
+    // Set on the thread running an asynchronous evaluation.
+    static DRIVER: RefCell<Option<Arc<Shared>>> = RefCell::new(None);
+}
+
+/// The threads running asynchronous evaluations, each running one at a time. Threads are
+/// started as evaluations are queued, up to `max`, and then wait for the next one.
+struct Pool {
+    state: Mutex<PoolState>,
+    // Notified when a job is queued.
+    queued: Condvar,
+}
+
+struct PoolState {
+    queue: VecDeque<Job>,
+    // The threads started, and how many of them are waiting for a job.
+    threads: usize,
+    idle: usize,
+    max: usize,
+}
+
+static POOL: Lazy<Pool> = Lazy::new(|| Pool {
+    state: Mutex::new(PoolState {
+        queue: VecDeque::new(),
+        threads: 0,
+        idle: 0,
+        max: DEFAULT_ASYNC_EVAL_THREADS,
+    }),
+    queued: Condvar::new(),
+});
+
+impl Pool {
+    fn spawn(&'static self, job: Job) {
+        let mut state = self.state.lock().unwrap();
+        state.queue.push_back(job);
+        if state.queue.len() > state.idle && state.threads < state.max {
+            state.threads += 1;
+            thread::spawn(move || self.work());
+        } else {
+            self.queued.notify_one();
+        }
+    }
+
+    fn work(&self) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            match state.queue.pop_front() {
+                Some(job) => {
+                    drop(state);
+                    // Jobs catch their own panics.
+                    job();
+                    state = self.state.lock().unwrap();
+                }
+                None => {
+                    state.idle += 1;
+                    state = self.queued.wait(state).unwrap();
+                    state.idle -= 1;
+                }
+            }
+        }
+    }
+}
+
+/// Set the maximum number of threads running asynchronous evaluations at once, by default 64.
+/// Each evaluation holds its thread until it finishes, including while a native function
+/// awaits, so once that many are in flight, further evaluations wait for one to finish
+/// before they start. An evaluation must therefore not await the result of one started
+/// after it. Lowering the limit doesn't stop threads which have already started.
+pub fn set_async_eval_threads(max: usize) {
+    assert!(max > 0, "asynchronous evaluations need at least one thread");
+    POOL.state.lock().unwrap().max = max;
+}
+
+// Marks the evaluation as finished when its job ends.
+struct Finish(Arc<Shared>);
+
+impl Drop for Finish {
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock().unwrap();
+        state.finished = true;
+        state.wake();
+    }
+}
+
+/// A [`Future`] for an evaluation running on a thread of its own, created by [`eval_async`] or
+/// [`Evaluator::eval_module_async`](crate::eval::Evaluator::eval_module_async).
+/// The evaluation only makes progress through `async` native functions while this future is
+/// being polled. If it is dropped, the native function waiting fails with an error, so the
+/// evaluation finishes soon after.
+pub struct AsyncEval<R> {
+    shared: Arc<Shared>,
+    // The result of `f`, or its panic, set before the evaluation is marked finished.
+    output: Arc<Mutex<Option<thread::Result<anyhow::Result<R>>>>>,
+}
+
+/// Run `f` on a pooled thread, allowing any [`Evaluator`](crate::eval::Evaluator) it uses to
+/// call `async` native functions without blocking the thread polling the result.
+/// The thread is held until `f` returns, including while a native function awaits, and
+/// at most [`set_async_eval_threads`] run at once, so later calls wait for a thread.
+/// The values of an evaluation can't be sent between threads, so `f` should create the
+/// [`Module`](crate::environment::Module) and return something which can, such as the
+/// [`FrozenModule`](crate::environment::FrozenModule).
+pub fn eval_async<R: Send + 'static>(
+    f: impl FnOnce() -> anyhow::Result<R> + Send + 'static,
+) -> AsyncEval<R> {
+    let shared = Arc::new(Shared::default());
+    let output = Arc::new(Mutex::new(None));
+    let driver = shared.dupe();
+    let result = output.dupe();
+    POOL.spawn(Box::new(move || {
+        let _finish = Finish(driver.dupe());
+        DRIVER.with(|x| *x.borrow_mut() = Some(driver));
+        let res = panic::catch_unwind(AssertUnwindSafe(f));
+        DRIVER.with(|x| *x.borrow_mut() = None);
+        *result.lock().unwrap() = Some(res);
+    }));
+    AsyncEval { shared, output }
+}
+
+impl<R> Future for AsyncEval<R> {
+    type Output = anyhow::Result<R>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut pending = {
+            let mut state = self.shared.state.lock().unwrap();
+            if state.finished {
+                drop(state);
+                return Poll::Ready(match self.output.lock().unwrap().take().unwrap() {
+                    Ok(res) => res,
+                    Err(e) => panic::resume_unwind(e),
+                });
+            }
+            state.waker = Some(cx.waker().clone());
+            match state.pending.take() {
+                None => return Poll::Pending,
+                Some(pending) => pending,
+            }
+        };
+        // Poll without the lock, the future may take a while
+        let res = pending.as_mut().poll(cx);
+        let mut state = self.shared.state.lock().unwrap();
+        match res {
+            Poll::Ready(res) => {
+                state.result = Some(res);
+                self.shared.ready.notify_one();
+            }
+            Poll::Pending => state.pending = Some(pending),
+        }
+        // Either the future will wake us, or the evaluation thread will when it next awaits
+        Poll::Pending
+    }
+}
+
+impl<R> Drop for AsyncEval<R> {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.abandoned = true;
+        state.pending = None;
+        self.shared.ready.notify_one();
+    }
+}
+
+// Wakes a thread parked in `block_on`.
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark()
+    }
+}
+
+// Poll the future to completion on this thread.
+fn block_on<F: Future>(fut: F) -> F::Output {
+    let mut fut = Box::pin(fut);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        match fut.as_mut().poll(&mut cx) {
+            Poll::Ready(res) => return res,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+/// Wait for the result of `fut`. On the thread of an [`eval_async`], the future is polled by
+/// the [`AsyncEval`], otherwise it is polled on this thread, blocking until it completes.
+pub(crate) fn await_native<T: Send + 'static>(
+    fut: impl Future<Output = anyhow::Result<T>> + Send + 'static,
+) -> anyhow::Result<T> {
+    let fut: NativeFuture = Box::pin(async move {
+        let res = fut.await?;
+        Ok(box res as Box<dyn Any + Send>)
+    });
+    let res = match DRIVER.with(|x| x.borrow().as_ref().map(|x| x.dupe())) {
+        None => block_on(fut),
+        Some(shared) => {
+            let mut state = shared.state.lock().unwrap();
+            if state.abandoned {
+                return Err(AsyncError::Abandoned.into());
+            }
+            state.pending = Some(fut);
+            state.wake();
+            loop {
+                if let Some(res) = state.result.take() {
+                    break res;
+                }
+                if state.abandoned {
+                    return Err(AsyncError::Abandoned.into());
+                }
+                state = shared.ready.wait(state).unwrap();
+            }
+        }
+    };
+    Ok(*res?.downcast::<T>().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        future::Future,
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    use gazebo::prelude::*;
+
+    use super::{block_on, DEFAULT_ASYNC_EVAL_THREADS, POOL};
+    use crate as starlark;
+    use crate::{
+        environment::{FrozenModule, Globals, GlobalsBuilder, Module},
+        eval::{Evaluator, FileLoader, LoadError},
+        syntax::{AstModule, Dialect},
+    };
+
+    struct MapLoader(HashMap<String, FrozenModule>);
+
+    impl FileLoader for MapLoader {
+        fn load(&self, path: &str) -> anyhow::Result<FrozenModule> {
+            match self.0.get(path) {
+                Some(module) => Ok(module.dupe()),
+                None => Err(LoadError::not_found(path).into()),
+            }
+        }
+    }
+
+    /// Pending the first time it is polled, so the caller has to be woken.
+    struct YieldOnce(bool);
+
+    impl Future for YieldOnce {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            if self.0 {
+                Poll::Ready(())
+            } else {
+                self.0 = true;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+
+    #[starlark_module]
+    fn async_functions(builder: &mut GlobalsBuilder) {
+        async fn double(x: i32) -> i32 {
+            YieldOnce(false).await;
+            Ok(x * 2)
+        }
+    }
+
+    fn globals() -> Globals {
+        GlobalsBuilder::standard().with(async_functions).build()
+    }
+
+    fn parse(content: &str) -> AstModule {
+        AstModule::parse("a.star", content.to_owned(), &Dialect::Standard).unwrap()
+    }
+
+    #[test]
+    fn test_eval_module_async() {
+        let ast = parse("x = double(double(5))");
+        let module = block_on(Evaluator::eval_module_async(ast, globals(), None)).unwrap();
+        assert_eq!(module.get("x").unwrap().value().unpack_int(), Some(20));
+    }
+
+    #[test]
+    fn test_eval_module_async_load() {
+        let lib = Module::new();
+        lib.set("y", lib.heap().alloc(3));
+        let modules = hashmap! {"lib.star".to_owned() => lib.freeze().unwrap()};
+        let loader = MapLoader(modules);
+
+        let ast = parse("load('lib.star', 'y')\nx = double(y)");
+        let res = Evaluator::eval_module_async(ast, globals(), Some(Box::new(loader)));
+        let module = block_on(res).unwrap();
+        assert_eq!(module.get("x").unwrap().value().unpack_int(), Some(6));
+
+        // Without a loader, `load()` fails.
+        let ast = parse("load('lib.star', 'y')");
+        assert!(block_on(Evaluator::eval_module_async(ast, globals(), None)).is_err());
+    }
+
+    #[test]
+    fn test_eval_module_async_pool() {
+        // More evaluations than threads, so some wait for a thread until earlier ones finish.
+        let n = DEFAULT_ASYNC_EVAL_THREADS * 2;
+        let evals = (0..n)
+            .map(|i| {
+                let ast = parse(&format!("x = double({})", i));
+                Evaluator::eval_module_async(ast, globals(), None)
+            })
+            .collect::<Vec<_>>();
+        for (i, eval) in evals.into_iter().enumerate() {
+            let module = block_on(eval).unwrap();
+            let x = module.get("x").unwrap().value().unpack_int();
+            assert_eq!(x, Some(i as i32 * 2));
+        }
+        assert!(POOL.state.lock().unwrap().threads <= DEFAULT_ASYNC_EVAL_THREADS);
+    }
+
+    #[test]
+    fn test_await_native_sync() {
+        let module = Module::new();
+        let mut eval = Evaluator::new(&module);
+        let res = eval.eval_module(parse("double(4)"), &globals()).unwrap();
+        assert_eq!(res.unpack_int(), Some(8));
+    }
+}
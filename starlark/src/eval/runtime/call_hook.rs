@@ -0,0 +1,56 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Observe every function call made by an evaluation.
+
+use crate::{
+    codemap::FileSpan,
+    eval::{Arguments, Evaluator},
+    values::Value,
+};
+
+/// Functions called on entry to and exit from every function call, whether the function is
+/// written in Starlark or Rust, including calls made by native functions such as `sorted`.
+/// Useful for tracers, call-graph profilers, or checking a function may be called at all.
+/// Add one with [`Evaluator::add_call_hook`].
+///
+/// Calls the compiler turns into operations aren't seen, i.e. `len(x)` and `type(x)`, and
+/// calls to builtins with constant arguments which were evaluated during compilation.
+pub trait CallHook<'v> {
+    /// Called before `function` is called with `args`. The `location` is that of the call,
+    /// or [`None`] if the function was called from Rust. Returning an error fails the call
+    /// without running the function, calling [`exit`](CallHook::exit) with the error on
+    /// those hooks which were already entered.
+    fn enter(
+        &self,
+        _function: Value<'v>,
+        _args: &Arguments<'v, '_>,
+        _location: Option<&FileSpan>,
+        _eval: &mut Evaluator<'v, '_>,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Called after `function` returns, with its result, or the error it failed with.
+    fn exit(
+        &self,
+        _function: Value<'v>,
+        _location: Option<&FileSpan>,
+        _result: Result<Value<'v>, &anyhow::Error>,
+        _eval: &mut Evaluator<'v, '_>,
+    ) {
+    }
+}
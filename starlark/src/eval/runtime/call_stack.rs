@@ -24,15 +24,17 @@
 // * When an exception happens, decorate it with the call stack on the way back
 //   up, in eval_call.
 
-use std::{fmt, fmt::Debug, intrinsics::unlikely};
+use std::{fmt, fmt::Debug, intrinsics::unlikely, iter};
 
 use gazebo::prelude::*;
+use itertools::Itertools;
+use thiserror::Error;
 
 use crate::{
     codemap::{FileSpan, Span},
     errors::Frame,
     eval::fragment::def::DefInfo,
-    values::{ControlError, FrozenRef, Trace, Tracer, Value},
+    values::{FrozenRef, Trace, Tracer, Value},
 };
 
 // A value akin to Frame, but can be created cheaply, since it doesn't resolve
@@ -46,6 +48,14 @@ struct CheapFrame<'v> {
 }
 
 impl CheapFrame<'_> {
+    fn empty() -> Self {
+        CheapFrame {
+            function: Value::new_none(),
+            file: None,
+            span: Span::default(),
+        }
+    }
+
     fn location(&self) -> Option<FileSpan> {
         self.file.map(|file| FileSpan {
             file: file.codemap.dupe(),
@@ -71,29 +81,34 @@ impl Debug for CheapFrame<'_> {
     }
 }
 
+#[derive(Debug, Error)]
+pub(crate) enum CallStackError {
+    #[error("Call stack overflow, recursion limit of {0} exceeded")]
+    RecursionLimit(usize),
+    #[error("Call stack overflow, recursion limit of {0} exceeded, in the cycle `{1}`")]
+    RecursionCycle(usize, String),
+}
+
 /// Starlark call stack.
 #[derive(Debug)]
 pub(crate) struct CallStack<'v> {
     count: usize,
-    stack: [CheapFrame<'v>; MAX_CALLSTACK_RECURSION],
+    // The length is the maximum depth.
+    stack: Box<[CheapFrame<'v>]>,
 }
 
 impl<'v> Default for CallStack<'v> {
     fn default() -> Self {
         Self {
             count: 0,
-            stack: [CheapFrame {
-                function: Value::new_none(),
-                file: None,
-                span: Span::default(),
-            }; MAX_CALLSTACK_RECURSION],
+            stack: vec![CheapFrame::empty(); MAX_CALLSTACK_RECURSION].into_boxed_slice(),
         }
     }
 }
 
 // At 50 we see the C stack overflowing, so limit to 40 (which seems quite
 // low...)
-const MAX_CALLSTACK_RECURSION: usize = 40;
+pub(crate) const MAX_CALLSTACK_RECURSION: usize = 40;
 
 unsafe impl<'v> Trace<'v> for CallStack<'v> {
     fn trace(&mut self, tracer: &Tracer<'v>) {
@@ -118,8 +133,8 @@ impl<'v> CallStack<'v> {
         span: Span,
         file: Option<FrozenRef<DefInfo>>,
     ) -> anyhow::Result<()> {
-        if unlikely(self.count >= MAX_CALLSTACK_RECURSION) {
-            return Err(ControlError::TooManyRecursionLevel.into());
+        if unlikely(self.count >= self.stack.len()) {
+            return Err(self.recursion_error(function));
         }
         self.stack[self.count] = CheapFrame {
             function,
@@ -130,6 +145,56 @@ impl<'v> CallStack<'v> {
         Ok(())
     }
 
+    /// The error when pushing `function` would exceed the maximum depth. If `function` is
+    /// already on the stack we report the cycle of calls, which is usually the culprit.
+    #[cold]
+    fn recursion_error(&self, function: Value<'v>) -> anyhow::Error {
+        // The first frame is the module, which can't be part of a cycle.
+        // The maximum depth is at least 1, so any error is raised with the module pushed.
+        let stack = &self.stack[1..self.count];
+        match stack.iter().rposition(|x| x.function.ptr_eq(function)) {
+            None => CallStackError::RecursionLimit(self.stack.len()).into(),
+            Some(i) => {
+                let cycle = stack[i..]
+                    .iter()
+                    .map(|x| x.function)
+                    .chain(iter::once(function))
+                    .map(|x| x.to_repr())
+                    .join(" -> ");
+                CallStackError::RecursionCycle(self.stack.len(), cycle).into()
+            }
+        }
+    }
+
+    /// Identify each frame by its function, file and call site, cheaply, without
+    /// resolving the locations as [`to_diagnostic_frames`](CallStack::to_diagnostic_frames) does.
+    pub(crate) fn frame_keys(&self) -> impl Iterator<Item = (usize, usize, Span)> + '_ {
+        self.stack[0..self.count].iter().map(|x| {
+            let file = x.file.map_or(0, |x| &*x as *const DefInfo as usize);
+            (x.function.ptr_value(), file, x.span)
+        })
+    }
+
+    /// The maximum depth of the stack.
+    pub(crate) fn max_depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// Change the maximum depth of the stack, which must be at least 1, for the module,
+    /// and at least the current depth.
+    pub(crate) fn set_max_depth(&mut self, depth: usize) {
+        assert!(depth >= 1, "Can't limit the call stack to 0 frames");
+        assert!(
+            depth >= self.count,
+            "Can't limit the call stack to {} frames, it already has {}",
+            depth,
+            self.count
+        );
+        let mut stack = self.stack[0..self.count].to_vec();
+        stack.resize(depth, CheapFrame::empty());
+        self.stack = stack.into_boxed_slice();
+    }
+
     /// Remove the top element from the stack. Called after `push`.
     pub(crate) fn pop(&mut self) {
         debug_assert!(self.count >= 1);
@@ -137,6 +202,15 @@ impl<'v> CallStack<'v> {
         self.count -= 1;
     }
 
+    /// The function at the top of the stack, i.e. the one currently executing.
+    pub(crate) fn top_function(&self) -> Option<Value<'v>> {
+        if self.count == 0 {
+            None
+        } else {
+            Some(self.stack[self.count - 1].function)
+        }
+    }
+
     /// The location at the top of the stack. May be `None` if
     /// either there the stack is empty, or the top of the stack lacks location
     /// information (e.g. called from Rust).
@@ -153,8 +227,14 @@ impl<'v> CallStack<'v> {
         self.stack[1..self.count].map(CheapFrame::to_frame)
     }
 
-    /// List the entries on the stack as values
-    pub(crate) fn to_function_values(&self) -> Vec<Value<'v>> {
-        self.stack[1..self.count].map(|x| x.function)
+    /// List the entries on the stack as values, with the position each function has
+    /// reached, where it called the function above it, or [`None`] for the top.
+    pub(crate) fn to_function_values_and_positions(&self) -> Vec<(Value<'v>, Option<Span>)> {
+        (1..self.count)
+            .map(|i| {
+                let position = self.stack[i + 1..self.count].first().map(|x| x.span);
+                (self.stack[i].function, position)
+            })
+            .collect()
     }
 }
@@ -0,0 +1,51 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Cancel an evaluation from another thread.
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use gazebo::prelude::*;
+
+/// A handle to cancel an evaluation, which is cheap to clone and can be sent to
+/// other threads, e.g. to stop when the user presses Ctrl-C, or when the request the
+/// evaluation is for is abandoned. Set it with
+/// [`Evaluator::set_cancellation_handle`](crate::eval::Evaluator::set_cancellation_handle),
+/// then after [`cancel`](CancellationHandle::cancel) the evaluation fails with an error
+/// before its next statement or function call.
+#[derive(Debug, Clone, Dupe, Default)]
+pub struct CancellationHandle(Arc<AtomicBool>);
+
+impl CancellationHandle {
+    /// A handle which hasn't been cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel the evaluations using this handle, or any clone of it.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed)
+    }
+
+    /// Has [`cancel`](CancellationHandle::cancel) been called.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
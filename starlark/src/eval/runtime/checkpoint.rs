@@ -0,0 +1,337 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Checkpoints of module evaluation, taken between top-level statements.
+
+use std::collections::{HashMap, HashSet};
+
+use num_bigint::BigInt;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    collections::SmallMap,
+    values::{
+        bigint::StarlarkBigInt,
+        bytes::StarlarkBytes,
+        dict::{Dict, FrozenDict},
+        float::StarlarkFloat,
+        list::ListRef,
+        set::{FrozenSet, Set},
+        stack_guard,
+        tuple::Tuple,
+        FrozenHeap, FrozenValue, Heap, UnpackValue, Value, ValueIdentity, ValueLike,
+    },
+};
+
+#[derive(Debug, Error)]
+pub(crate) enum CheckpointError {
+    #[error(
+        "Can't checkpoint variable `{0}`, values of type `{1}` can't be saved unless last bound by a top-level `load` or `def`"
+    )]
+    Unsupported(String, &'static str),
+    #[error(
+        "Checkpoint resumes at statement {0}, but the module only has {1} top-level statements"
+    )]
+    StatementOutOfRange(usize, usize),
+    #[error("Checkpoint restores variable `{0}`, which the module doesn't define")]
+    UnknownVariable(String),
+    #[error("Checkpoint was taken with different code or globals")]
+    HashMismatch,
+    #[error("Checkpoint refers to value {0}, which isn't saved before the value referring to it")]
+    BadReference(usize),
+}
+
+/// A value saved in a [`Checkpoint`]. Only plain data can be saved. Values refer to the
+/// values they contain by their index in [`Checkpoint::values`], which is always lower
+/// than their own. Lists, dicts and sets which were frozen, e.g. loaded from another module,
+/// are saved as distinct variants, and restored frozen, along with everything they contain.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum CheckpointValue {
+    None,
+    Bool(bool),
+    Int(i32),
+    BigInt(BigInt),
+    Float(f64),
+    String(String),
+    Bytes(Vec<u8>),
+    List(Vec<usize>),
+    Tuple(Vec<usize>),
+    Dict(Vec<(usize, usize)>),
+    Set(Vec<usize>),
+    FrozenList(Vec<usize>),
+    FrozenDict(Vec<(usize, usize)>),
+    FrozenSet(Vec<usize>),
+}
+
+/// Saves values for a [`Checkpoint`], each once, however many values refer to it.
+#[derive(Default)]
+pub(crate) struct CheckpointSaver<'v> {
+    values: Vec<CheckpointValue>,
+    saved: HashMap<ValueIdentity<'v>, usize>,
+    /// The values whose contents are being saved, to detect cycles.
+    saving: HashSet<ValueIdentity<'v>>,
+}
+
+impl<'v> CheckpointSaver<'v> {
+    /// Save a value, returning its index, or [`None`] if it (or anything it contains)
+    /// isn't plain data, or it contains itself.
+    pub(crate) fn save(&mut self, x: Value<'v>) -> Option<usize> {
+        // Values may be very deep.
+        let _guard = stack_guard::stack_guard().ok()?;
+        if let Some(id) = self.saved.get(&x.identity()) {
+            return Some(*id);
+        }
+        if !self.saving.insert(x.identity()) {
+            return None;
+        }
+        let res = self.save_contents(x);
+        self.saving.remove(&x.identity());
+        let id = self.values.len();
+        self.values.push(res?);
+        self.saved.insert(x.identity(), id);
+        Some(id)
+    }
+
+    fn save_contents(&mut self, x: Value<'v>) -> Option<CheckpointValue> {
+        Some(if x.is_none() {
+            CheckpointValue::None
+        } else if let Some(x) = x.unpack_bool() {
+            CheckpointValue::Bool(x)
+        } else if let Some(x) = x.unpack_int() {
+            CheckpointValue::Int(x)
+        } else if let Some(x) = x.downcast_ref::<StarlarkBigInt>() {
+            CheckpointValue::BigInt(x.get().clone())
+        } else if let Some(x) = StarlarkFloat::unpack_value(x) {
+            CheckpointValue::Float(x.0)
+        } else if let Some(x) = x.unpack_str() {
+            CheckpointValue::String(x.to_owned())
+        } else if let Some(x) = x.downcast_ref::<StarlarkBytes>() {
+            CheckpointValue::Bytes(x.as_bytes().to_vec())
+        } else if let Some(xs) = ListRef::from_value(x) {
+            let xs = xs.iter().map(|x| self.save(x)).collect::<Option<_>>()?;
+            if x.unpack_frozen().is_some() {
+                CheckpointValue::FrozenList(xs)
+            } else {
+                CheckpointValue::List(xs)
+            }
+        } else if let Some(xs) = Tuple::from_value(x) {
+            CheckpointValue::Tuple(xs.iter().map(|x| self.save(x)).collect::<Option<_>>()?)
+        } else if let Some(xs) = Dict::from_value(x) {
+            let xs = xs
+                .iter()
+                .map(|(k, v)| Some((self.save(k)?, self.save(v)?)))
+                .collect::<Option<_>>()?;
+            if x.unpack_frozen().is_some() {
+                CheckpointValue::FrozenDict(xs)
+            } else {
+                CheckpointValue::Dict(xs)
+            }
+        } else if let Some(xs) = Set::from_value(x) {
+            let xs = xs.iter().map(|x| self.save(x)).collect::<Option<_>>()?;
+            if x.unpack_frozen().is_some() {
+                CheckpointValue::FrozenSet(xs)
+            } else {
+                CheckpointValue::Set(xs)
+            }
+        } else {
+            return None;
+        })
+    }
+
+    pub(crate) fn finish(self) -> Vec<CheckpointValue> {
+        self.values
+    }
+}
+
+/// Allocates the values saved in a [`Checkpoint`], each once.
+pub(crate) struct CheckpointRestorer<'c, 'v> {
+    values: &'c [CheckpointValue],
+    heap: &'v Heap,
+    /// Where frozen containers, and everything they contain, are allocated.
+    frozen_heap: &'v FrozenHeap,
+    restored: Vec<Option<Value<'v>>>,
+    /// Values restored within frozen containers, which must be frozen themselves.
+    restored_frozen: Vec<Option<FrozenValue>>,
+}
+
+impl<'c, 'v> CheckpointRestorer<'c, 'v> {
+    pub(crate) fn new(
+        values: &'c [CheckpointValue],
+        heap: &'v Heap,
+        frozen_heap: &'v FrozenHeap,
+    ) -> Self {
+        Self {
+            values,
+            heap,
+            frozen_heap,
+            restored: vec![None; values.len()],
+            restored_frozen: vec![None; values.len()],
+        }
+    }
+
+    /// Allocate the value with index `id`, or return it if it was already allocated.
+    pub(crate) fn restore(&mut self, id: usize) -> anyhow::Result<Value<'v>> {
+        self.restore_within(id, self.values.len())
+    }
+
+    /// Like [`restore`](CheckpointRestorer::restore), but `id` must be lower than `bound`,
+    /// so a malformed checkpoint can't make us loop.
+    fn restore_within(&mut self, id: usize, bound: usize) -> anyhow::Result<Value<'v>> {
+        let _guard = stack_guard::stack_guard()?;
+        if id >= bound {
+            return Err(CheckpointError::BadReference(id).into());
+        }
+        if let Some(res) = self.restored[id] {
+            return Ok(res);
+        }
+        let heap = self.heap;
+        let values = self.values;
+        let res = match &values[id] {
+            CheckpointValue::None => Value::new_none(),
+            CheckpointValue::Bool(x) => Value::new_bool(*x),
+            CheckpointValue::Int(x) => Value::new_int(*x),
+            CheckpointValue::BigInt(x) => heap.alloc(x.clone()),
+            CheckpointValue::Float(x) => heap.alloc(StarlarkFloat(*x)),
+            CheckpointValue::String(x) => heap.alloc_str(x),
+            CheckpointValue::Bytes(x) => heap.alloc(x.as_slice()),
+            CheckpointValue::List(xs) => heap.alloc_list(&self.restore_all(xs, id)?),
+            CheckpointValue::Tuple(xs) => heap.alloc_tuple(&self.restore_all(xs, id)?),
+            CheckpointValue::Dict(xs) => {
+                let mut res = SmallMap::with_capacity(xs.len());
+                for (k, v) in xs {
+                    let k = self.restore_within(*k, id)?.get_hashed()?;
+                    res.insert_hashed(k, self.restore_within(*v, id)?);
+                }
+                heap.alloc(Dict::new(res))
+            }
+            CheckpointValue::Set(xs) => {
+                let mut res = Set::new();
+                for x in self.restore_all(xs, id)? {
+                    res.insert_hashed(x.get_hashed()?);
+                }
+                heap.alloc(res)
+            }
+            CheckpointValue::FrozenList(_)
+            | CheckpointValue::FrozenDict(_)
+            | CheckpointValue::FrozenSet(_) => self.restore_frozen_within(id, bound)?.to_value(),
+        };
+        self.restored[id] = Some(res);
+        Ok(res)
+    }
+
+    fn restore_all(&mut self, xs: &[usize], bound: usize) -> anyhow::Result<Vec<Value<'v>>> {
+        xs.iter().map(|x| self.restore_within(*x, bound)).collect()
+    }
+
+    /// Like [`restore_within`](CheckpointRestorer::restore_within), but allocates the value
+    /// on the frozen heap, for the contents of frozen containers.
+    fn restore_frozen_within(&mut self, id: usize, bound: usize) -> anyhow::Result<FrozenValue> {
+        let _guard = stack_guard::stack_guard()?;
+        if id >= bound {
+            return Err(CheckpointError::BadReference(id).into());
+        }
+        if let Some(res) = self.restored_frozen[id] {
+            return Ok(res);
+        }
+        let heap = self.frozen_heap;
+        let values = self.values;
+        let res = match &values[id] {
+            CheckpointValue::None => FrozenValue::new_none(),
+            CheckpointValue::Bool(x) => FrozenValue::new_bool(*x),
+            CheckpointValue::Int(x) => FrozenValue::new_int(*x),
+            CheckpointValue::BigInt(x) => heap.alloc(x.clone()),
+            CheckpointValue::Float(x) => heap.alloc(*x),
+            CheckpointValue::String(x) => heap.alloc(x.as_str()),
+            CheckpointValue::Bytes(x) => heap.alloc(x.clone()),
+            CheckpointValue::List(xs) | CheckpointValue::FrozenList(xs) => {
+                heap.alloc_list(&self.restore_all_frozen(xs, id)?)
+            }
+            CheckpointValue::Tuple(xs) => heap.alloc_tuple(&self.restore_all_frozen(xs, id)?),
+            CheckpointValue::Dict(xs) | CheckpointValue::FrozenDict(xs) => {
+                let mut res = SmallMap::with_capacity(xs.len());
+                for (k, v) in xs {
+                    let k = self.restore_frozen_within(*k, id)?.get_hashed()?;
+                    res.insert_hashed(k, self.restore_frozen_within(*v, id)?);
+                }
+                heap.alloc(FrozenDict::new(res))
+            }
+            CheckpointValue::Set(xs) | CheckpointValue::FrozenSet(xs) => {
+                let mut res = SmallMap::with_capacity(xs.len());
+                for x in self.restore_all_frozen(xs, id)? {
+                    res.insert_hashed(x.get_hashed()?, ());
+                }
+                heap.alloc(FrozenSet::new(res))
+            }
+        };
+        self.restored_frozen[id] = Some(res);
+        Ok(res)
+    }
+
+    fn restore_all_frozen(
+        &mut self,
+        xs: &[usize],
+        bound: usize,
+    ) -> anyhow::Result<Vec<FrozenValue>> {
+        xs.iter()
+            .map(|x| self.restore_frozen_within(*x, bound))
+            .collect()
+    }
+}
+
+/// The state of a module part way through its evaluation, taken just before one of its
+/// top-level statements, which can be saved (e.g. with `serde_json`) and passed to
+/// [`Evaluator::resume_from`](crate::eval::Evaluator::resume_from) in a fresh process to
+/// continue the evaluation from that statement.
+///
+/// **Checkpoints are only taken between top-level statements**, never inside a function or a
+/// top-level `for` loop, so a module whose time is spent in one long loop can't be
+/// checkpointed part way through it. To allow that, split the work into several top-level
+/// statements, e.g. one loop per batch.
+///
+/// A checkpoint holds the module's variables. Those which aren't plain data, such as
+/// functions, can only be checkpointed if they were bound by a top-level `load` or `def`
+/// which is the last statement to bind them before the checkpoint, as those statements are
+/// run again when resuming, after the other variables are restored. Otherwise, e.g. for a
+/// variable rebound to a `lambda` after its `def`, taking the checkpoint fails. Defaults of
+/// such a `def` are evaluated again, with the restored variables. A value referred to by
+/// several variables, or from several places, is saved once, and is shared again after
+/// resuming, but a value which contains itself can't be saved. Frozen lists, dicts and sets,
+/// e.g. aliases of loaded values, are still frozen after resuming.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// The index of the top-level statement to resume from.
+    pub statement: usize,
+    /// A hash of the module's code and the names of its globals, which must match when
+    /// resuming. Modules it loads aren't included.
+    pub hash: u64,
+    /// The saved values.
+    pub values: Vec<CheckpointValue>,
+    /// The saved module variables, with the index of their value in `values`.
+    pub variables: Vec<(String, usize)>,
+}
+
+/// Decides when to take [`Checkpoint`]s during the evaluation of a module, and stores them,
+/// see [`set_checkpointer`](crate::eval::Evaluator::set_checkpointer).
+pub trait Checkpointer {
+    /// Whether to take a checkpoint before the top-level statement with index `statement`.
+    /// Typically true when enough time has passed since the last checkpoint.
+    fn want_checkpoint(&self, statement: usize) -> bool;
+
+    /// Store a checkpoint. An error aborts the evaluation.
+    fn checkpoint(&self, checkpoint: Checkpoint) -> anyhow::Result<()>;
+}
@@ -0,0 +1,113 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use gazebo::prelude::*;
+
+use crate::values::{float::StarlarkFloat, tuple::Tuple, Heap, Value};
+
+/// Opt-in rules for converting arguments to native functions when they don't have the
+/// expected type, configured for a [`Globals`](crate::environment::Globals) with
+/// [`GlobalsBuilder::set_coercions`](crate::environment::GlobalsBuilder::set_coercions).
+///
+/// Coercions apply to all native functions and methods called while evaluating a module
+/// with those globals, and are only tried after an argument fails to unpack as given.
+/// By default no coercions are enabled, matching the Starlark specification.
+///
+/// The coercions belong to the evaluation, not to the function: a native function defined
+/// in other globals, e.g. one exported by a loaded module, or a method of a value created
+/// elsewhere, uses the coercions of the globals passed to
+/// [`eval_module`](crate::eval::Evaluator::eval_module) for the module calling it.
+#[derive(Debug, Clone, Copy, Dupe, Default, PartialEq, Eq)]
+pub struct Coercions {
+    /// Accept an `int` where a `float` is expected.
+    pub int_to_float: bool,
+    /// Accept a `tuple` where a `list` is expected, passing a fresh list with the same elements.
+    pub tuple_to_list: bool,
+}
+
+impl Coercions {
+    /// Enable every coercion.
+    pub fn all() -> Self {
+        Self {
+            int_to_float: true,
+            tuple_to_list: true,
+        }
+    }
+
+    /// Convert `x` using the first enabled coercion which applies to it, if any.
+    pub(crate) fn coerce<'v>(self, x: Value<'v>, heap: &'v Heap) -> Option<Value<'v>> {
+        if self.int_to_float {
+            if let Some(x) = x.unpack_int() {
+                return Some(heap.alloc(StarlarkFloat(x as f64)));
+            }
+        }
+        if self.tuple_to_list {
+            if let Some(x) = Tuple::from_value(x) {
+                return Some(heap.alloc_list(x.content()));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        self as starlark,
+        assert::Assert,
+        environment::GlobalsBuilder,
+        eval::Coercions,
+        values::{float::StarlarkFloat, list::ListRef},
+    };
+
+    #[starlark_module]
+    fn functions(builder: &mut GlobalsBuilder) {
+        fn half(x: StarlarkFloat) -> StarlarkFloat {
+            Ok(StarlarkFloat(x.0 / 2.0))
+        }
+
+        fn length(xs: &ListRef) -> i32 {
+            Ok(xs.len() as i32)
+        }
+    }
+
+    #[test]
+    fn test_coercions() {
+        let mut a = Assert::new();
+        a.globals_add(functions);
+        a.fail("half(3)", "Type of parameter `x` doesn't match");
+        a.fail("length((1, 2))", "Type of parameter `xs` doesn't match");
+
+        a.globals_add(|x| {
+            x.set_coercions(Coercions::all());
+            functions(x)
+        });
+        a.eq("1.5", "half(3)");
+        a.eq("2", "length((1, 2))");
+        a.fail("half('3')", "Type of parameter `x` doesn't match");
+
+        a.globals_add(|x| {
+            x.set_coercions(Coercions {
+                int_to_float: true,
+                ..Coercions::default()
+            });
+            functions(x)
+        });
+        a.eq("1.5", "half(3)");
+        a.fail("length((1, 2))", "Type of parameter `xs` doesn't match");
+    }
+}
@@ -0,0 +1,104 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Record which statements were executed.
+
+use std::collections::{hash_map::Entry, BTreeSet, HashMap, HashSet};
+
+use gazebo::prelude::*;
+
+use crate::{
+    codemap::{CodeMap, Span},
+    eval::runtime::stmt_profile::FileId,
+};
+
+// When coverage is not enabled, we want this to be small and cheap
+pub(crate) struct Coverage(Option<Box<CoverageData>>);
+
+#[derive(Default)]
+struct CoverageData {
+    // The statements executed in each file
+    files: HashMap<FileId, (CodeMap, HashSet<Span>)>,
+}
+
+impl Coverage {
+    pub(crate) fn new() -> Self {
+        Self(None)
+    }
+
+    pub(crate) fn enable(&mut self) {
+        self.0 = Some(box CoverageData::default())
+    }
+
+    pub(crate) fn before_stmt(&mut self, span: Span, codemap: &CodeMap) {
+        if let Some(box data) = &mut self.0 {
+            let spans = match data.files.entry(FileId::new(codemap)) {
+                Entry::Occupied(x) => &mut x.into_mut().1,
+                Entry::Vacant(x) => &mut x.insert((codemap.dupe(), HashSet::new())).1,
+            };
+            spans.insert(span);
+        }
+    }
+
+    // None = not applicable because not enabled
+    pub(crate) fn lines(&self) -> Option<HashMap<String, BTreeSet<usize>>> {
+        let data = self.0.as_ref()?;
+        let mut res: HashMap<String, BTreeSet<usize>> = HashMap::new();
+        for (codemap, spans) in data.files.values() {
+            // Several files may have the same name, e.g. if a file was evaluated twice
+            let lines = res.entry(codemap.filename().to_owned()).or_default();
+            for span in spans {
+                lines.insert(codemap.resolve_span(*span).begin_line + 1);
+            }
+        }
+        Some(res)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use crate::{
+        environment::{Globals, Module},
+        eval::Evaluator,
+        syntax::{AstModule, Dialect},
+    };
+
+    #[test]
+    fn test_coverage() {
+        let module = Module::new();
+        let globals = Globals::standard();
+        let mut eval = Evaluator::new(&module);
+        assert!(eval.coverage().is_err());
+        eval.enable_coverage();
+        let program = "\
+def f(x):
+    if x:
+        return 1
+    return 2
+f(False)
+";
+        eval.eval_module(
+            AstModule::parse("cover.star", program.to_owned(), &Dialect::Standard).unwrap(),
+            &globals,
+        )
+        .unwrap();
+        let coverage = eval.coverage().unwrap();
+        assert_eq!(coverage.len(), 1);
+        assert_eq!(coverage["cover.star"], BTreeSet::from([1, 2, 4, 5]));
+    }
+}
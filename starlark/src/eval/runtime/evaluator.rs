@@ -17,12 +17,15 @@
 
 use std::{
     cell::Cell,
-    intrinsics::unlikely,
+    collections::{BTreeSet, HashMap},
+    future::Future,
+    intrinsics::{likely, unlikely},
     mem::{self, MaybeUninit},
     path::Path,
+    time::{Duration, Instant},
 };
 
-use gazebo::{any::AnyLifetime, cast};
+use gazebo::{any::AnyLifetime, cast, dupe::Dupe};
 use thiserror::Error;
 
 use crate::{
@@ -34,14 +37,26 @@ use crate::{
         bc::frame::BcFrame,
         fragment::def::DefInfo,
         runtime::{
+            async_native,
             bc_profile::BcProfile,
             call_stack::CallStack,
+            cancellation::CancellationHandle,
+            checkpoint::{Checkpoint, Checkpointer},
+            coercions::Coercions,
+            coverage::Coverage,
             flame_profile::FlameProfile,
+            gc_pacer::GcPacer,
             heap_profile::{HeapProfile, HeapProfileFormat},
+            heap_snapshot::HeapSnapshot,
+            pinned::{PinnedValue, Pins},
+            profile_mode::ProfileMode,
+            provenance::{Provenance, ValueProvenance},
             slots::LocalSlotId,
+            stats::{EvalStats, Stats},
+            stepper,
             stmt_profile::StmtProfile,
         },
-        FileLoader,
+        Arguments, CallHook, FileLoader, LoadLogger,
     },
     stdlib::{
         breakpoint::{BreakpointConsole, RealBreakpointConsole},
@@ -49,10 +64,16 @@ use crate::{
     },
     values::{
         recursive_repr_guard::ReprStackReleaseMemoryOnDrop, value_captured_get, FrozenHeap,
-        FrozenRef, Heap, Trace, Tracer, Value, ValueCaptured, ValueLike,
+        FrozenRef, FrozenValue, Heap, OwnedFrozenValue, Trace, Tracer, Value, ValueCaptured,
+        ValueLike,
     },
 };
 
+/// Identifies a function added with [`Evaluator::before_stmt`], so it can be removed with
+/// [`Evaluator::remove_before_stmt`].
+#[derive(Debug, Clone, Copy, Dupe, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BeforeStmtHandle(usize);
+
 #[derive(Error, Debug)]
 #[allow(clippy::enum_variant_names)]
 pub(crate) enum EvaluatorError {
@@ -64,6 +85,22 @@ pub(crate) enum EvaluatorError {
     FlameProfilingNotEnabled,
     #[error("Can't call `write_bc_profile` unless you first call `enable_bc_profile`.")]
     BcProfilingNotEnabled,
+    #[error("Can't call `coverage` unless you first call `enable_coverage`.")]
+    CoverageNotEnabled,
+    #[error("Can't call `stats` unless you first call `enable_stats`.")]
+    StatsNotEnabled,
+    #[error("Can't call `write_profile` unless you first call `enable_profiling`.")]
+    ProfilingNotEnabled,
+    #[error("Can't call `heap_snapshot` after `enable_heap_profile`, as it collects garbage.")]
+    HeapSnapshotWithHeapProfile,
+    #[error("`{0}` is not available in deterministic evaluation, it may differ between runs")]
+    NotDeterministic(&'static str),
+    #[error("Evaluation exceeded the limit of {0} steps")]
+    StepLimitExceeded(u64),
+    #[error("Evaluation exceeded its deadline")]
+    DeadlineExceeded,
+    #[error("Evaluation was cancelled")]
+    Cancelled,
 }
 
 /// Number of bytes to allocate between GC's.
@@ -81,6 +118,33 @@ pub struct Evaluator<'v, 'a> {
     pub(crate) current_frame: BcFrame<'v>,
     // How we deal with a `load` function.
     pub(crate) loader: Option<&'a dyn FileLoader>,
+    // Where to report each `load`, usually `None`.
+    pub(crate) load_logger: Option<&'a dyn LoadLogger>,
+    // Called around every function call, usually empty.
+    call_hooks: Vec<&'a dyn CallHook<'v>>,
+    // How many `reentrant` calls are in progress, during which hooks are not called.
+    pub(crate) suppress_hooks: u32,
+    // Is the module the top-level program, rather than being evaluated for a `load`.
+    pub(crate) is_main: bool,
+    // How to convert arguments to native functions, taken from the `Globals`.
+    pub(crate) coercions: Coercions,
+    // Where to save checkpoints of the module evaluation, usually `None`.
+    pub(crate) checkpointer: Option<&'a dyn Checkpointer>,
+    // The checkpoint the next module evaluation resumes from.
+    pub(crate) resume: Option<Checkpoint>,
+    // Globals shadowed for the next module evaluation, see `set_overrides`.
+    pub(crate) overrides: HashMap<String, FrozenValue>,
+    // The seed of the identifiers made by `ids.next`, `None` until the first is made.
+    pub(crate) ids_seed: Option<u64>,
+    // Whether builtins whose result may differ between runs are refused.
+    deterministic: bool,
+    // Whether `return f(...)` calling the current function reuses its frame.
+    pub(crate) tail_calls: bool,
+    // Set by a tail call, with its arguments in `tail_call_args`, until the function restarts.
+    pub(crate) tail_call: bool,
+    pub(crate) tail_call_args: Vec<Value<'v>>,
+    // How many identifiers `ids.next` has made with each prefix.
+    pub(crate) ids: HashMap<String, u64>,
     // `DefInfo` of currently executed function or module.
     pub(crate) def_info: FrozenRef<DefInfo>,
     // Make leak sanitizer happy.
@@ -98,10 +162,34 @@ pub struct Evaluator<'v, 'a> {
     pub(crate) verbose_gc: bool,
     // Size of the heap when we should next perform a GC.
     pub(crate) next_gc_level: usize,
-    // Extra functions to run on each statement, usually empty
-    pub(crate) before_stmt: Vec<&'a dyn Fn(Span, &mut Evaluator<'v, 'a>)>,
+    // Paces GC towards a pause target, if one was set.
+    gc_pacer: Option<GcPacer>,
+    // Extra functions to run on each statement, usually empty, ordered by handle
+    pub(crate) before_stmt: Vec<(BeforeStmtHandle, &'a dyn Fn(Span, &mut Evaluator<'v, 'a>))>,
+    // The handle to give the next function added to `before_stmt`
+    next_before_stmt: usize,
+    // Functions compiled without calling `before_stmt`, given the file and function name
+    pub(crate) skip_before_stmt: Option<&'a dyn Fn(&str, &str) -> bool>,
+    // The number of statements which may be executed, usually `None` for no limit
+    pub(crate) max_steps: Option<u64>,
+    // The number of statements executed so far, only counted if `max_steps` is set
+    pub(crate) steps: u64,
+    // When to stop the evaluation, usually `None` for no limit
+    pub(crate) deadline: Option<Instant>,
+    // Checked before each statement and call, usually `None`
+    pub(crate) cancellation: Option<CancellationHandle>,
     // Used for line profiling
     stmt_profile: StmtProfile,
+    // Which statements were executed, if enabled.
+    coverage: Coverage,
+    // The profile chosen with `enable_profiling`, if any.
+    profile_mode: Option<ProfileMode>,
+    // Counts of statements, calls and time, usually disabled.
+    pub(crate) stats: Stats,
+    // Values pinned by the host, which are roots for garbage collection.
+    pins: Pins<'v>,
+    // Records which statement allocated each value
+    provenance: Provenance<'v>,
     // Bytecode profile.
     pub(crate) bc_profile: BcProfile,
     // Used for stack-like allocation
@@ -127,6 +215,8 @@ unsafe impl<'v> Trace<'v> for Evaluator<'v, '_> {
     fn trace(&mut self, tracer: &Tracer<'v>) {
         let mut roots = self.module_env.slots().get_slots_mut();
         roots.trace(tracer);
+        self.module_env.trace_snapshots(tracer);
+        self.pins.trace(tracer);
         self.current_frame.trace(tracer);
         self.call_stack.trace(tracer);
         self.flame_profile.trace(tracer);
@@ -145,18 +235,44 @@ impl<'v, 'a> Evaluator<'v, 'a> {
             module_variables: None,
             current_frame: BcFrame::default(),
             loader: None,
+            load_logger: None,
+            call_hooks: Vec::new(),
+            suppress_hooks: 0,
+            is_main: true,
+            coercions: Coercions::default(),
+            checkpointer: None,
+            resume: None,
+            overrides: HashMap::new(),
+            ids_seed: None,
+            deterministic: false,
+            tail_calls: false,
+            tail_call: false,
+            tail_call_args: Vec::new(),
+            ids: HashMap::new(),
             extra: None,
             extra_v: None,
             next_gc_level: GC_THRESHOLD,
+            gc_pacer: None,
             disable_gc: false,
             alloca: Alloca::new(),
             _repr_stack_release_memory_on_drop: ReprStackReleaseMemoryOnDrop,
             heap_profile: HeapProfile::new(),
             stmt_profile: StmtProfile::new(),
+            coverage: Coverage::new(),
+            profile_mode: None,
+            stats: Stats::new(),
+            pins: Pins::default(),
+            provenance: Provenance::new(),
             bc_profile: BcProfile::new(),
             flame_profile: FlameProfile::new(),
             heap_or_flame_profile: false,
             before_stmt: Vec::new(),
+            next_before_stmt: 0,
+            skip_before_stmt: None,
+            max_steps: None,
+            steps: 0,
+            deadline: None,
+            cancellation: None,
             def_info: DefInfo::empty(), // Will be replaced before it is used
             string_pool: StringPool::default(),
             breakpoint_handler: None,
@@ -177,6 +293,39 @@ impl<'v, 'a> Evaluator<'v, 'a> {
         self.verbose_gc = true;
     }
 
+    /// Collect garbage by generations from now onwards: values which survive a collection
+    /// are tenured, and are only copied again by an occasional major collection, which
+    /// speeds up long evaluations with many long-lived values. Cannot be disabled.
+    ///
+    /// Native values which store a [`Value`] in themselves after they were allocated must
+    /// call [`Heap::write_barrier`](crate::values::Heap::write_barrier) when doing so.
+    pub fn enable_generational_gc(&mut self) {
+        self.heap().enable_generational_gc();
+    }
+
+    /// Pace garbage collections, choosing the bytes allocated between minor collections
+    /// from how fast previous collections were, so each takes about `target`. Useful for
+    /// embedders which would rather have many short pauses than a few long ones, e.g. when
+    /// evaluating interactively. Enables [generational collection](Evaluator::enable_generational_gc).
+    ///
+    /// This only changes when collections happen: collection isn't incremental, and major
+    /// collections, which copy every live value, take as long as that takes. The pauses can
+    /// be observed with [`enable_stats`](Evaluator::enable_stats).
+    pub fn set_gc_pacing(&mut self, target: Duration) {
+        self.enable_generational_gc();
+        let pacer = GcPacer::new(target);
+        self.next_gc_level = self.heap().allocated_bytes() + pacer.slice_bytes();
+        self.gc_pacer = Some(pacer);
+    }
+
+    /// Bytes to allocate before the next garbage collection.
+    pub(crate) fn gc_threshold(&self) -> usize {
+        match &self.gc_pacer {
+            Some(pacer) => pacer.slice_bytes(),
+            None => GC_THRESHOLD,
+        }
+    }
+
     /// Set the [`FileLoader`] used to resolve `load()` statements.
     /// A list of all load statements can be obtained through
     /// [`AstModule::loads`](crate::syntax::AstModule::loads).
@@ -184,6 +333,166 @@ impl<'v, 'a> Evaluator<'v, 'a> {
         self.loader = Some(loader);
     }
 
+    /// Report every `load()` statement executed by this evaluation to a [`LoadLogger`],
+    /// useful when debugging why a module was resolved to the wrong file.
+    pub fn set_load_logger(&mut self, logger: &'a dyn LoadLogger) {
+        self.load_logger = Some(logger);
+    }
+
+    /// Call the [`CallHook`] on entry to and exit from every function call. Several hooks can
+    /// be added, entered in the order they were added and exited in the reverse order.
+    /// Every call is slower once a hook is added, even one which does nothing.
+    pub fn add_call_hook(&mut self, hook: &'a dyn CallHook<'v>) {
+        self.call_hooks.push(hook);
+    }
+
+    pub(crate) fn has_call_hooks(&self) -> bool {
+        !self.call_hooks.is_empty()
+    }
+
+    /// Call `function` with `args` by running `call`, surrounded by the [`CallHook`]s.
+    #[inline(always)]
+    pub(crate) fn with_call_hooks<'x>(
+        &mut self,
+        function: Value<'v>,
+        span: Option<Span>,
+        args: Arguments<'v, 'x>,
+        call: impl FnOnce(Arguments<'v, 'x>, &mut Self) -> anyhow::Result<Value<'v>>,
+    ) -> anyhow::Result<Value<'v>> {
+        if likely(self.call_hooks.is_empty() && !self.stats.enabled()) {
+            call(args, self)
+        } else {
+            self.with_call_hooks_slow(function, span, args, call)
+        }
+    }
+
+    #[cold]
+    #[inline(never)]
+    fn with_call_hooks_slow<'x>(
+        &mut self,
+        function: Value<'v>,
+        span: Option<Span>,
+        args: Arguments<'v, 'x>,
+        call: impl FnOnce(Arguments<'v, 'x>, &mut Self) -> anyhow::Result<Value<'v>>,
+    ) -> anyhow::Result<Value<'v>> {
+        let previous = self.stats.enter(function);
+        let res = if self.suppress_hooks != 0 || self.call_hooks.is_empty() {
+            call(args, self)
+        } else {
+            self.run_call_hooks(function, span, args, call)
+        };
+        self.stats.exit(previous);
+        res
+    }
+
+    fn run_call_hooks<'x>(
+        &mut self,
+        function: Value<'v>,
+        span: Option<Span>,
+        args: Arguments<'v, 'x>,
+        call: impl FnOnce(Arguments<'v, 'x>, &mut Self) -> anyhow::Result<Value<'v>>,
+    ) -> anyhow::Result<Value<'v>> {
+        let location = span.map(|span| self.file_span(span));
+        // Index, since the hooks may add more hooks
+        let mut entered = 0;
+        let mut res = Ok(());
+        while let Some(hook) = self.call_hooks.get(entered).copied() {
+            res = hook.enter(function, &args, location.as_ref(), self);
+            if res.is_err() {
+                break;
+            }
+            entered += 1;
+        }
+        let res = res.and_then(|()| call(args, self));
+        for hook in self.call_hooks[..entered].to_vec().into_iter().rev() {
+            hook.exit(function, location.as_ref(), res.as_ref().copied(), self);
+        }
+        res
+    }
+
+    /// Record whether the module being evaluated is the top-level program (the default),
+    /// or is being evaluated because another module loaded it, as reported by `module_ctx()`
+    /// (see [`LibraryExtension::ModuleCtx`](crate::environment::LibraryExtension::ModuleCtx)).
+    pub fn set_main(&mut self, main: bool) {
+        self.is_main = main;
+    }
+
+    /// Offer a [`Checkpoint`] to a [`Checkpointer`] before each top-level statement of the
+    /// module, so a long evaluation can later be continued with
+    /// [`resume_from`](Evaluator::resume_from).
+    pub fn set_checkpointer(&mut self, checkpointer: &'a dyn Checkpointer) {
+        self.checkpointer = Some(checkpointer);
+    }
+
+    /// Make the next call to [`eval_module`](Evaluator::eval_module) continue from a
+    /// [`Checkpoint`] rather than running the module from the start.
+    /// The module must be evaluated with the same code and globals as when the checkpoint
+    /// was taken, in a fresh [`Module`], or evaluation fails. The checkpointed variables are
+    /// restored, then those top-level `load` and `def` statements before the checkpoint whose
+    /// variables no later statement rebinds are run again, see [`Checkpoint`].
+    pub fn resume_from(&mut self, checkpoint: Checkpoint) {
+        self.resume = Some(checkpoint);
+    }
+
+    /// Shadow the given globals for the next call to [`eval_module`](Evaluator::eval_module)
+    /// only, without rebuilding the [`Globals`](crate::environment::Globals), e.g. to replace
+    /// a global function with a mock in tests. Variables of the module still take precedence,
+    /// as they do over globals, and nothing is added to the module. Functions defined by that
+    /// evaluation keep using the overrides when called later.
+    ///
+    /// Modules it loads are evaluated by the [`FileLoader`], usually with their own
+    /// [`Evaluator`], so don't see the overrides unless the loader sets them too.
+    pub fn set_overrides(&mut self, overrides: &[(&str, OwnedFrozenValue)]) {
+        for (name, value) in overrides {
+            // Safe because the module's frozen heap now keeps the value alive.
+            let value = unsafe { value.owned_frozen_value(self.module_env.frozen_heap()) };
+            self.overrides.insert((*name).to_owned(), value);
+        }
+    }
+
+    /// Set the seed of the identifiers made by `ids.next`
+    /// (see [`LibraryExtension::Ids`](crate::environment::LibraryExtension::Ids)).
+    /// By default the seed is derived from the file name of the module, so each module makes
+    /// the same identifiers every time it is evaluated, different to those of other modules.
+    pub fn set_ids_seed(&mut self, seed: u64) {
+        self.ids_seed = Some(seed);
+    }
+
+    /// Guarantee that evaluating the same code with the same inputs behaves identically on every
+    /// run, byte for byte, as build systems need for hermetic builds. Hashing is always stable,
+    /// and dictionaries always iterate in insertion order, but in this mode builtins whose
+    /// results may differ between runs, such as `debug()` (which can show memory addresses) and
+    /// `breakpoint()` (which is interactive), fail with an error instead.
+    /// Identifiers made by `ids.next` depend on the file name unless
+    /// [`set_ids_seed`](Evaluator::set_ids_seed) is called, so should be given a seed if files
+    /// are evaluated at different paths.
+    pub fn enable_deterministic(&mut self) {
+        self.deterministic = true;
+    }
+
+    /// Eliminate self-recursive tail calls in functions compiled after this call: a `def`
+    /// whose body contains `return f(x, y)`, where `f` evaluates to the function itself and
+    /// all the arguments are positional, restarts with the new arguments instead of making a
+    /// nested call. Such recursion then runs in constant stack space, and isn't limited by
+    /// [`set_max_call_depth`](Evaluator::set_max_call_depth).
+    ///
+    /// Eliminated calls don't appear in stack traces: an error reports the function once, at
+    /// the location of the call which started the chain of tail calls. Nor are they reported
+    /// to [`CallHook`]s or profilers, which see only the outer call.
+    pub fn enable_tail_calls(&mut self) {
+        self.tail_calls = true;
+    }
+
+    /// Fail if the builtin `name` may not be called, because
+    /// [`enable_deterministic`](Evaluator::enable_deterministic) was called.
+    pub(crate) fn check_deterministic(&self, name: &'static str) -> anyhow::Result<()> {
+        if self.deterministic {
+            Err(EvaluatorError::NotDeterministic(name).into())
+        } else {
+            Ok(())
+        }
+    }
+
     /// Enable profiling, allowing [`Evaluator::write_heap_profile`] to be used.
     /// Has the side effect of disabling garbage-collection.
     ///
@@ -219,6 +528,84 @@ impl<'v, 'a> Evaluator<'v, 'a> {
         self.before_stmt(&|span, eval| eval.stmt_profile.before_stmt(span, &eval.def_info.codemap));
     }
 
+    /// Record which statements are executed, allowing [`Evaluator::coverage`] to be used.
+    /// Must be called _before_ execution. Functions from loaded modules were compiled by
+    /// another [`Evaluator`], so their statements are only recorded if it also had a
+    /// [`before_stmt`](Evaluator::before_stmt) function, or coverage enabled.
+    pub fn enable_coverage(&mut self) {
+        self.coverage.enable();
+        self.before_stmt(&|span, eval| eval.coverage.before_stmt(span, &eval.def_info.codemap));
+    }
+
+    /// The lines, counting from 1, of the statements executed in each file, keyed by file name.
+    /// A statement spanning several lines is recorded as its first line.
+    /// Only valid if [`enable_coverage`](Evaluator::enable_coverage) was called before
+    /// execution began.
+    pub fn coverage(&self) -> anyhow::Result<HashMap<String, BTreeSet<usize>>> {
+        self.coverage
+            .lines()
+            .ok_or_else(|| EvaluatorError::CoverageNotEnabled.into())
+    }
+
+    /// Count the statements and calls executed, and the time spent in them, allowing
+    /// [`Evaluator::stats`] to be used. Must be called _before_ execution. Statements in
+    /// functions from loaded modules are only counted if their [`Evaluator`] had a
+    /// [`before_stmt`](Evaluator::before_stmt) function, but calls to them are always counted.
+    pub fn enable_stats(&mut self) {
+        self.stats.enable();
+        self.before_stmt(&|_, eval| eval.stats.before_stmt());
+    }
+
+    /// Statistics about the evaluations done so far with this [`Evaluator`], including the
+    /// current state of its heap. Only valid if [`enable_stats`](Evaluator::enable_stats) was
+    /// called before execution began.
+    pub fn stats(&self) -> anyhow::Result<EvalStats> {
+        self.stats
+            .stats(self.heap())
+            .ok_or_else(|| EvaluatorError::StatsNotEnabled.into())
+    }
+
+    /// Pause before every statement until the [`Stepper`](crate::eval::Stepper) running this
+    /// evaluation calls [`step`](crate::eval::Stepper::step). Must be called _before_
+    /// execution, from within [`eval_stepped`](crate::eval::eval_stepped), otherwise it has no
+    /// effect. Sets a [`CancellationHandle`] if there isn't one, so dropping the
+    /// [`Stepper`](crate::eval::Stepper) stops the evaluation.
+    pub fn enable_stepping(&mut self) {
+        let cancellation = self
+            .cancellation
+            .get_or_insert_with(CancellationHandle::new);
+        if stepper::register(cancellation) {
+            self.before_stmt(&|span, eval| stepper::pause(span, eval));
+        }
+    }
+
+    /// Record the statement and call-stack which allocated each value, allowing
+    /// [`Evaluator::value_provenance`] to be used, and making the error from
+    /// [`Module::freeze`](crate::environment::Module::freeze) say where a value which
+    /// can't be frozen was allocated. Must be called _before_ execution.
+    /// Has the side effect of disabling automatic garbage-collection, and makes every statement
+    /// slower, so should only be used when debugging. The provenance of values which survive
+    /// an explicit [`garbage_collect`](Evaluator::garbage_collect) is kept.
+    pub fn enable_provenance(&mut self) {
+        self.provenance.enable();
+        // The provenance is recovered from the order of the values on the heap.
+        self.disable_gc = true;
+        self.before_stmt(&|span, eval| {
+            let location = eval.file_span(span);
+            let heap = eval.heap();
+            eval.provenance.record(location, &eval.call_stack, heap);
+        });
+    }
+
+    /// Find where a value was allocated. Only available if
+    /// [`enable_provenance`](Evaluator::enable_provenance) was called before execution began,
+    /// and only for values allocated by a statement on the current heap.
+    /// The first lookup after more values have been allocated walks the heap to index them,
+    /// later lookups are cheap.
+    pub fn value_provenance(&self, value: Value<'v>) -> Option<ValueProvenance> {
+        self.provenance.lookup(value, self.heap())
+    }
+
     /// Enable bytecode profiling, allowing [`Evaluator::write_bytecode_profile`] to be used.
     pub fn enable_bytecode_profile(&mut self) {
         self.bc_profile.enable_1();
@@ -289,6 +676,37 @@ impl<'v, 'a> Evaluator<'v, 'a> {
             .unwrap_or_else(|| Err(EvaluatorError::FlameProfilingNotEnabled.into()))
     }
 
+    /// Enable the profile given by `mode`, chosen at runtime, e.g. from a command line flag,
+    /// allowing [`Evaluator::write_profile`] to be used. Equivalent to calling the `enable_`
+    /// function for that mode, e.g. [`enable_heap_profile`](Evaluator::enable_heap_profile).
+    /// Must be called _before_ execution, and at most once.
+    pub fn enable_profiling(&mut self, mode: ProfileMode) {
+        match mode {
+            ProfileMode::Heap | ProfileMode::HeapFlame => self.enable_heap_profile(),
+            ProfileMode::Statement => self.enable_stmt_profile(),
+            ProfileMode::TimeFlame => self.enable_flame_profile(),
+            ProfileMode::Bytecode => self.enable_bytecode_profile(),
+            ProfileMode::BytecodePairs => self.enable_bytecode_pairs_profile(),
+        }
+        self.profile_mode = Some(mode);
+    }
+
+    /// Write the profile enabled with [`enable_profiling`](Evaluator::enable_profiling) to a
+    /// file, in the format of the `write_` function for that mode, e.g.
+    /// [`write_heap_profile`](Evaluator::write_heap_profile).
+    pub fn write_profile<P: AsRef<Path>>(&self, filename: P) -> anyhow::Result<()> {
+        match self.profile_mode {
+            None => Err(EvaluatorError::ProfilingNotEnabled.into()),
+            Some(ProfileMode::Heap) => self.write_heap_profile(filename),
+            Some(ProfileMode::HeapFlame) => self.write_heap_flame_profile(filename),
+            Some(ProfileMode::Statement) => self.write_stmt_profile(filename),
+            Some(ProfileMode::TimeFlame) => self.write_flame_profile(filename),
+            Some(ProfileMode::Bytecode | ProfileMode::BytecodePairs) => {
+                self.write_bytecode_profile(filename)
+            }
+        }
+    }
+
     /// Enable interactive `breakpoint()`. When enabled, `breakpoint()`
     /// reads commands from stdin and write to stdout.
     /// When disabled (default), `breakpoint()` function results in error.
@@ -311,9 +729,118 @@ impl<'v, 'a> Evaluator<'v, 'a> {
     /// A list of all possible statements can be obtained in advance by
     /// [`AstModule::stmt_locations`](crate::syntax::AstModule::stmt_locations).
     ///
+    /// Several functions can be added, and are called in the order they were added.
+    /// The result can be passed to [`remove_before_stmt`](Evaluator::remove_before_stmt)
+    /// to stop calling `f`.
+    ///
     /// This function may have no effect is called mid evaluation.
-    pub fn before_stmt(&mut self, f: &'a dyn Fn(Span, &mut Evaluator<'v, 'a>)) {
-        self.before_stmt.push(f)
+    pub fn before_stmt(&mut self, f: &'a dyn Fn(Span, &mut Evaluator<'v, 'a>)) -> BeforeStmtHandle {
+        let handle = BeforeStmtHandle(self.next_before_stmt);
+        self.next_before_stmt += 1;
+        self.before_stmt.push((handle, f));
+        handle
+    }
+
+    /// Run `f`, which evaluates more code with this evaluator while it is part way through an
+    /// evaluation, e.g. a [`before_stmt`](Evaluator::before_stmt) function or [`CallHook`]
+    /// running [`eval_statements`](Evaluator::eval_statements) to inspect the program, or a
+    /// native function calling back into Starlark.
+    ///
+    /// While `f` runs no `before_stmt` functions or [`CallHook`]s are called, so a hook doesn't
+    /// observe (or pause in) its own evaluation, although step, time and allocation limits still
+    /// apply. Afterwards the module being executed is restored, so the outer evaluation
+    /// continues as before. Calls may be nested.
+    pub fn reentrant<R>(&mut self, f: impl FnOnce(&mut Self) -> R) -> R {
+        self.suppress_hooks += 1;
+        let module_variables = self.module_variables;
+        let def_info = self.def_info;
+        let res = f(self);
+        self.module_variables = module_variables;
+        self.def_info = def_info;
+        self.suppress_hooks -= 1;
+        res
+    }
+
+    /// Stop calling a function added with [`before_stmt`](Evaluator::before_stmt), returning
+    /// `false` if it had already been removed. Can be called at any time, including from within
+    /// a `before_stmt` function. Statements are still compiled to call the functions, so
+    /// removing them all doesn't make the rest of the evaluation faster.
+    pub fn remove_before_stmt(&mut self, handle: BeforeStmtHandle) -> bool {
+        match self.before_stmt.binary_search_by_key(&handle, |x| x.0) {
+            Ok(i) => {
+                self.before_stmt.remove(i);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Compile the functions for which `f` returns `true`, given the file name and the
+    /// function name, without calling the [`before_stmt`](Evaluator::before_stmt) functions
+    /// for their statements, or those of the functions defined inside them.
+    /// Use it so trusted library code, which is most of what runs, isn't slowed down by
+    /// debugging or profiling the rest. The statements skipped aren't counted by
+    /// [`set_max_steps`](Evaluator::set_max_steps), and don't check the
+    /// [`set_deadline`](Evaluator::set_deadline) or cancellation, though calls still check
+    /// cancellation. Must be called _before_ the functions are compiled.
+    pub fn skip_before_stmt(&mut self, f: &'a dyn Fn(&str, &str) -> bool) {
+        self.skip_before_stmt = Some(f);
+    }
+
+    /// Limit the number of statements executed, failing evaluation with an error once it
+    /// would exceed `steps`. Each statement counts every time it is executed, so a loop counts
+    /// the statements in its body once per iteration (or the loop itself, if the body is only
+    /// `pass`). The count is kept across every call to evaluate code with this [`Evaluator`].
+    /// Functions from loaded modules were compiled by another [`Evaluator`], so their
+    /// statements are only counted if it also had a limit or a
+    /// [`before_stmt`](Evaluator::before_stmt) function.
+    /// Use it to stop untrusted code running for too long. Must be called _before_ execution.
+    pub fn set_max_steps(&mut self, steps: u64) {
+        self.max_steps = Some(steps);
+    }
+
+    /// The number of statements executed so far. Only counted if
+    /// [`set_max_steps`](Evaluator::set_max_steps) was called before execution began.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Limit how deep the call stack can get, failing evaluation with a recursion error
+    /// (including the call stack) if a call would exceed `depth` frames. The module itself
+    /// counts as one frame. The default is 40, a higher limit may overflow the native stack
+    /// unless the evaluation is run on a thread with a larger stack.
+    /// Must be called _before_ execution. Panics if `depth` is 0, as even the module
+    /// couldn't run.
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.call_stack.set_max_depth(depth);
+    }
+
+    /// The maximum depth of the call stack, see
+    /// [`set_max_call_depth`](Evaluator::set_max_call_depth).
+    pub fn max_call_depth(&self) -> usize {
+        self.call_stack.max_depth()
+    }
+
+    /// Fail evaluation with an error once it is still running at `deadline`.
+    /// The time is checked before each statement, the same points at which
+    /// [`set_max_steps`](Evaluator::set_max_steps) counts, so a single call to a slow native
+    /// function isn't interrupted, but evaluation stops as soon as it returns.
+    /// Must be called _before_ execution.
+    pub fn set_deadline(&mut self, deadline: Instant) {
+        self.deadline = Some(deadline);
+    }
+
+    /// Fail evaluation with an error once it has run for longer than `timeout`, counting
+    /// from now, see [`set_deadline`](Evaluator::set_deadline).
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.set_deadline(Instant::now() + timeout)
+    }
+
+    /// Fail evaluation with an error before the next statement or function call once
+    /// [`CancellationHandle::cancel`] is called on `handle`, e.g. from another thread.
+    /// Must be called _before_ execution.
+    pub fn set_cancellation_handle(&mut self, handle: CancellationHandle) {
+        self.cancellation = Some(handle);
     }
 
     /// Set the handler invoked when `print` function is used.
@@ -334,6 +861,18 @@ impl<'v, 'a> Evaluator<'v, 'a> {
         true
     }
 
+    /// Wait for the result of `fut`, used by an `async fn` in a
+    /// [`#[starlark_module]`](macro@crate::starlark_module). If the evaluation was started by
+    /// [`eval_module_async`](Evaluator::eval_module_async) or
+    /// [`eval_async`](crate::eval::eval_async), the future is polled by the caller's executor
+    /// while the evaluation is suspended, otherwise it blocks this thread until it completes.
+    pub fn await_native<T: Send + 'static>(
+        &mut self,
+        fut: impl Future<Output = anyhow::Result<T>> + Send + 'static,
+    ) -> anyhow::Result<T> {
+        async_native::await_native(fut)
+    }
+
     /// Called to add an entry to the call stack, by the function being invoked.
     /// Called for all types of function, including those written in Rust.
     #[inline(always)]
@@ -352,11 +891,20 @@ impl<'v, 'a> Evaluator<'v, 'a> {
             })
         }
 
+        if let Some(cancellation) = &self.cancellation {
+            if cancellation.is_cancelled() {
+                return Err(EvaluatorError::Cancelled.into());
+            }
+        }
+        self.heap().check_allocation_limit(0)?;
         self.call_stack.push(
             function,
             span.unwrap_or_default(),
             span.map(|_| self.def_info),
         )?;
+        // Only renders the function if the span is enabled.
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!("starlark_call", function = %function.to_repr()).entered();
         if unlikely(self.heap_or_flame_profile) {
             self.heap_profile.record_call_enter(function, self.heap());
             self.flame_profile.record_call_enter(function);
@@ -502,8 +1050,9 @@ impl<'v, 'a> Evaluator<'v, 'a> {
 
     pub(crate) fn set_slot_local_captured(&mut self, slot: LocalSlotId, value: Value<'v>) {
         match self.current_frame.get_slot(slot) {
-            Some(value_captured) => {
-                let value_captured = value_captured
+            Some(captured) => {
+                self.heap().write_barrier(captured);
+                let value_captured = captured
                     .downcast_ref::<ValueCaptured>()
                     .expect("not a ValueCaptured");
                 value_captured.set(value);
@@ -527,8 +1076,31 @@ impl<'v, 'a> Evaluator<'v, 'a> {
         self.current_frame.set_slot(slot, value_captured);
     }
 
-    /// Cause a GC to be triggered next time it's possible.
-    pub(crate) fn trigger_gc(&mut self) {
+    /// Keep `value`, and everything it refers to, alive across garbage collections until
+    /// [`unpin`](Evaluator::unpin) is called. Use it for values held by the host, e.g. in a
+    /// native function's state or a Rust container, which the collector can't otherwise see.
+    /// Garbage collection may move the value, so read it back with
+    /// [`pinned`](Evaluator::pinned) rather than keeping the original [`Value`].
+    pub fn pin(&mut self, value: Value<'v>) -> PinnedValue {
+        self.pins.pin(value)
+    }
+
+    /// The current location of a value pinned with [`pin`](Evaluator::pin), or [`None`] if it
+    /// has been unpinned. After unpinning, the handle may be reused for another value.
+    pub fn pinned(&self, pin: PinnedValue) -> Option<Value<'v>> {
+        self.pins.get(pin)
+    }
+
+    /// Stop keeping a value pinned with [`pin`](Evaluator::pin) alive, returning it, or
+    /// [`None`] if it had already been unpinned.
+    pub fn unpin(&mut self, pin: PinnedValue) -> Option<Value<'v>> {
+        self.pins.unpin(pin)
+    }
+
+    /// Ask for a garbage collection the next time it's safe, which is before the next statement
+    /// at the top level of a module, rather than waiting for enough memory to be allocated.
+    /// Has no effect if GC is disabled with [`disable_gc`](Evaluator::disable_gc).
+    pub fn request_gc(&mut self) {
         // We will GC next time we can, since the threshold is if 0 or more bytes are allocated
         self.next_gc_level = 0;
     }
@@ -538,13 +1110,45 @@ impl<'v, 'a> Evaluator<'v, 'a> {
     /// and using them will lead to a segfault.
     /// Do not call during Starlark evaluation.
     pub unsafe fn garbage_collect(&mut self) {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!(
+            "starlark_gc",
+            before = self.heap().allocated_bytes(),
+            after = tracing::field::Empty
+        )
+        .entered();
         if self.verbose_gc {
             eprintln!(
                 "Starlark: allocated bytes: {}, starting GC...",
                 self.heap().allocated_bytes()
             );
         }
+        // The bytes a collection goes through: a minor collection only copies the
+        // values allocated since the previous one.
+        let minor = self.heap().next_gc_is_minor();
+        let bytes = if minor {
+            self.heap().young_allocated_bytes()
+        } else {
+            self.heap().allocated_bytes()
+        };
+        self.provenance.before_gc(self.heap());
+        let start = Instant::now();
         self.heap().garbage_collect(|tracer| self.trace(tracer));
+        let elapsed = start.elapsed();
+        self.provenance.after_gc();
+        self.stats.gc(elapsed, minor);
+        if let Some(pacer) = &mut self.gc_pacer {
+            pacer.record(bytes, elapsed);
+            if self.verbose_gc && elapsed > pacer.target() {
+                eprintln!(
+                    "Starlark: GC took {:?}, over the target of {:?}.",
+                    elapsed,
+                    pacer.target()
+                );
+            }
+        }
+        #[cfg(feature = "tracing")]
+        _span.record("after", &self.heap().allocated_bytes());
         if self.verbose_gc {
             eprintln!(
                 "Starlark: GC complete. Allocated bytes: {}.",
@@ -553,6 +1157,27 @@ impl<'v, 'a> Evaluator<'v, 'a> {
         }
     }
 
+    /// Collect garbage, recording a [`HeapSnapshot`] of the values which are still alive:
+    /// their types, sizes and references, and where they were allocated if
+    /// [`enable_provenance`](Evaluator::enable_provenance) was called before execution began.
+    ///
+    /// The same restrictions apply as to [`garbage_collect`](Evaluator::garbage_collect),
+    /// and this can't be used together with [`enable_heap_profile`](Evaluator::enable_heap_profile).
+    pub unsafe fn heap_snapshot(&mut self) -> anyhow::Result<HeapSnapshot> {
+        if self.heap_profile.is_enabled() {
+            return Err(EvaluatorError::HeapSnapshotWithHeapProfile.into());
+        }
+        let locations = self.provenance.locations(self.heap());
+        self.provenance.before_gc(self.heap());
+        let start = Instant::now();
+        let snapshot = self
+            .heap()
+            .garbage_collect_snapshot(|tracer| self.trace(tracer), &locations);
+        self.stats.gc(start.elapsed(), false);
+        self.provenance.after_gc();
+        Ok(snapshot)
+    }
+
     /// Note that the `Drop` for the `T` will not be called. That's safe if there is no `Drop`,
     /// or you call it yourself.
     #[inline(always)]
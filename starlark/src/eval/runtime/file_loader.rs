@@ -18,17 +18,232 @@
 //! Define variants of the evaluation function with different support
 //! for the `load(...)` statement.
 
-use std::collections::HashMap;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::{self, Display},
+    future::Future,
+    path::{Path, PathBuf},
+    pin::Pin,
+    time::Duration,
+};
 
-use anyhow::anyhow;
 use gazebo::prelude::*;
+use serde::Serialize;
+use thiserror::Error;
 
-use crate::environment::FrozenModule;
+use crate::{codemap::FileSpan, environment::FrozenModule, eval::runtime::async_native};
 
 /// A trait for turning a `path` given by a `load()` statement into a [`FrozenModule`].
 pub trait FileLoader {
     /// Open the file given by the load statement `path`.
     fn load(&self, path: &str) -> anyhow::Result<FrozenModule>;
+
+    /// Describe how the last call to [`load`](FileLoader::load) with `path` was resolved,
+    /// e.g. which resolver was used, the resolved file and whether it came from a cache.
+    /// Only called when a [`LoadLogger`] is set, with the results placed in
+    /// [`LoadEvent::details`].
+    fn describe_load(&self, _path: &str) -> Vec<(String, String)> {
+        Vec::new()
+    }
+}
+
+/// A variant of [`FileLoader`] which resolves modules asynchronously, e.g. by fetching them
+/// over the network. Use it as a [`FileLoader`] by wrapping it in [`AsyncLoader`].
+pub trait AsyncFileLoader {
+    /// Open the file given by the load statement `path`.
+    fn load(
+        &self,
+        path: &str,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<FrozenModule>> + Send + 'static>>;
+}
+
+/// Adapts an [`AsyncFileLoader`] to a [`FileLoader`]. When evaluating under
+/// [`eval_async`](crate::eval::eval_async) the future returned by the loader is polled by the
+/// [`AsyncEval`](crate::eval::AsyncEval), so it runs in the caller's executor, otherwise the
+/// evaluating thread blocks until the future completes.
+pub struct AsyncLoader<L>(pub L);
+
+impl<L: AsyncFileLoader> FileLoader for AsyncLoader<L> {
+    fn load(&self, path: &str) -> anyhow::Result<FrozenModule> {
+        async_native::await_native(self.0.load(path))
+    }
+}
+
+/// Wraps a [`FileLoader`], caching the modules it returns by path, so each is only
+/// evaluated once.
+pub struct CachedLoader<L> {
+    loader: L,
+    cache: RefCell<HashMap<String, FrozenModule>>,
+}
+
+impl<L: FileLoader> CachedLoader<L> {
+    /// Cache the modules returned by `loader`.
+    pub fn new(loader: L) -> Self {
+        Self {
+            loader,
+            cache: RefCell::new(HashMap::new()),
+        }
+    }
+}
+
+impl<L: FileLoader> FileLoader for CachedLoader<L> {
+    fn load(&self, path: &str) -> anyhow::Result<FrozenModule> {
+        if let Some(module) = self.cache.borrow().get(path) {
+            return Ok(module.dupe());
+        }
+        // Not borrowed while loading, as the module may load others through this loader.
+        let module = self.loader.load(path)?;
+        self.cache.borrow_mut().insert(path.to_owned(), module.dupe());
+        Ok(module)
+    }
+
+    fn describe_load(&self, path: &str) -> Vec<(String, String)> {
+        self.loader.describe_load(path)
+    }
+}
+
+/// One step in a chain of `load()` statements, see [`LoadError`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LoadStep {
+    /// The module requested.
+    pub module: String,
+    /// The location of the `load()` statement.
+    pub location: String,
+}
+
+impl Display for LoadStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}` loaded at {}", self.module, self.location)
+    }
+}
+
+fn display_chain(chain: &[LoadStep]) -> String {
+    let mut res = String::new();
+    if !chain.is_empty() {
+        res.push_str(", load chain:");
+        for step in chain {
+            res.push_str("\n  ");
+            res.push_str(&step.to_string());
+        }
+    }
+    res
+}
+
+/// Structured errors a [`FileLoader`] can return, recording the chain of `load()` statements
+/// which led to the failing one, outermost first, so diagnostics show which file requested
+/// the module. The chain is captured when the error is created, from the `load()` statements
+/// being evaluated on the current thread, including those of modules evaluated by a
+/// [`FileLoader`] while resolving an outer `load()`.
+#[derive(Debug, Error)]
+pub enum LoadError {
+    /// The requested module doesn't exist.
+    #[error("Module `{module}` not found{}", display_chain(.chain))]
+    NotFound {
+        module: String,
+        chain: Vec<LoadStep>,
+    },
+    /// The requested module is already being loaded, so loading it again would never finish.
+    #[error("Module `{module}` is part of a `load()` cycle{}", display_chain(.chain))]
+    Cycle {
+        module: String,
+        chain: Vec<LoadStep>,
+    },
+}
+
+// Each step is paired with the name of the file containing the `load()` statement.
+thread_local!(static LOAD_CHAIN: RefCell<Vec<(String, LoadStep)>> = RefCell::new(Vec::new()));
+
+impl LoadError {
+    /// The module `path` doesn't exist.
+    pub fn not_found(path: &str) -> Self {
+        Self::NotFound {
+            module: path.to_owned(),
+            chain: Self::current_chain(),
+        }
+    }
+
+    /// The module `path` is already being loaded.
+    pub fn cycle(path: &str) -> Self {
+        Self::Cycle {
+            module: path.to_owned(),
+            chain: Self::current_chain(),
+        }
+    }
+
+    /// The `load()` statements currently being evaluated on this thread, outermost first.
+    pub fn current_chain() -> Vec<LoadStep> {
+        LOAD_CHAIN.with(|x| x.borrow().iter().map(|(_, step)| step.clone()).collect())
+    }
+
+    /// The chain of `load()` statements which led to the error, outermost first.
+    pub fn chain(&self) -> &[LoadStep] {
+        match self {
+            Self::NotFound { chain, .. } | Self::Cycle { chain, .. } => chain,
+        }
+    }
+}
+
+/// Records a `load()` statement as being evaluated until dropped.
+pub(crate) struct LoadChainEntry(());
+
+impl LoadChainEntry {
+    pub(crate) fn enter(module: &str, location: &FileSpan) -> Self {
+        LOAD_CHAIN.with(|x| {
+            x.borrow_mut().push((
+                location.file.filename().to_owned(),
+                LoadStep {
+                    module: module.to_owned(),
+                    location: location.to_string(),
+                },
+            ))
+        });
+        Self(())
+    }
+
+    /// Whether the module being loaded is one of the files already being evaluated
+    /// on this thread, either a module requested by an outer `load()`, or a file containing one.
+    pub(crate) fn is_cycle(&self) -> bool {
+        LOAD_CHAIN.with(|x| {
+            let chain = x.borrow();
+            let ((file, step), outer) = chain.split_last().unwrap();
+            *file == step.module
+                || outer
+                    .iter()
+                    .any(|(file, outer)| *file == step.module || outer.module == step.module)
+        })
+    }
+}
+
+impl Drop for LoadChainEntry {
+    fn drop(&mut self) {
+        LOAD_CHAIN.with(|x| x.borrow_mut().pop());
+    }
+}
+
+/// A record of a single `load()` statement, passed to a [`LoadLogger`].
+#[derive(Debug, Clone, Serialize)]
+pub struct LoadEvent {
+    /// The module requested, e.g. `"foo.star"` in `load("foo.star", "x")`.
+    pub module: String,
+    /// The location of the `load()` statement.
+    pub location: String,
+    /// The symbols requested from the module.
+    pub symbols: Vec<String>,
+    /// Key/value pairs describing the resolution, as returned by [`FileLoader::describe_load`].
+    pub details: Vec<(String, String)>,
+    /// The time spent in [`FileLoader::load`].
+    pub duration: Duration,
+    /// The error message, if the load failed.
+    pub error: Option<String>,
+}
+
+/// Receives a [`LoadEvent`] for each `load()` statement executed by an
+/// [`Evaluator`](crate::eval::Evaluator), see
+/// [`set_load_logger`](crate::eval::Evaluator::set_load_logger).
+pub trait LoadLogger {
+    /// Called after each `load()`, whether it succeeded or not.
+    fn log(&self, event: LoadEvent);
 }
 
 /// [`FileLoader`] that looks up modules by name from a [`HashMap`].
@@ -44,10 +259,285 @@ impl<'a> FileLoader for ReturnFileLoader<'a> {
     fn load(&self, path: &str) -> anyhow::Result<FrozenModule> {
         match self.modules.get(path) {
             Some(v) => Ok((*v).dupe()),
-            None => Err(anyhow!(
-                "ReturnFileLoader does not know the module `{}`",
-                path
-            )),
+            None => Err(LoadError::not_found(path).into()),
+        }
+    }
+}
+
+/// Maps between the paths of files on disk and the names reported for them in diagnostics,
+/// call stacks and debugger sources. Useful when evaluating in a temporary sandbox which mirrors
+/// the user's checkout, so messages refer to the checkout rather than the sandbox.
+///
+/// A [`FileLoader`] can use [`disk_path`](PathMapping::disk_path) to resolve the paths given to
+/// `load()`, and [`AstModule::parse_file_mapped`](crate::syntax::AstModule::parse_file_mapped)
+/// to parse them under their reported name.
+#[derive(Debug, Clone, Default)]
+pub struct PathMapping {
+    working_dir: Option<PathBuf>,
+    rewrites: Vec<(PathBuf, PathBuf)>,
+}
+
+impl PathMapping {
+    /// A mapping which leaves all paths unchanged.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Resolve relative paths against `dir`, rather than the working directory of the process.
+    pub fn set_working_dir(&mut self, dir: impl Into<PathBuf>) {
+        self.working_dir = Some(dir.into());
+    }
+
+    /// Report files on disk under `from` as being under `to`.
+    /// Rewrites are tried in the order they were added, and the first that matches is used.
+    pub fn add_rewrite(&mut self, from: impl Into<PathBuf>, to: impl Into<PathBuf>) {
+        self.rewrites.push((from.into(), to.into()));
+    }
+
+    fn join_working_dir(&self, path: &Path) -> PathBuf {
+        match &self.working_dir {
+            Some(dir) if path.is_relative() => dir.join(path),
+            _ => path.to_owned(),
+        }
+    }
+
+    /// The file on disk for `path`, which is either a reported name, or a path relative to the
+    /// working directory, as given to `load()` or on a command line.
+    pub fn disk_path(&self, path: &Path) -> PathBuf {
+        for (from, to) in &self.rewrites {
+            if let Ok(rest) = path.strip_prefix(to) {
+                return self.join_working_dir(&from.join(rest));
+            }
+        }
+        self.join_working_dir(path)
+    }
+
+    /// The name to report for the file at `path` on disk.
+    pub fn reported_name(&self, path: &Path) -> String {
+        let path = self.join_working_dir(path);
+        for (from, to) in &self.rewrites {
+            if let Ok(rest) = path.strip_prefix(from) {
+                return to.join(rest).to_string_lossy().into_owned();
+            }
+        }
+        path.to_string_lossy().into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use anyhow::anyhow;
+
+    use super::*;
+    use crate::{
+        environment::{Globals, Module},
+        errors::Diagnostic,
+        eval::Evaluator,
+        syntax::{AstModule, Dialect},
+    };
+
+    struct Loader(FrozenModule);
+
+    impl FileLoader for Loader {
+        fn load(&self, path: &str) -> anyhow::Result<FrozenModule> {
+            if path == "a.star" {
+                Ok(self.0.dupe())
+            } else {
+                Err(anyhow!("No such module"))
+            }
+        }
+
+        fn describe_load(&self, path: &str) -> Vec<(String, String)> {
+            vec![("resolved".to_owned(), format!("/root/{}", path))]
         }
     }
+
+    #[derive(Default)]
+    struct Logger(RefCell<Vec<LoadEvent>>);
+
+    impl LoadLogger for Logger {
+        fn log(&self, event: LoadEvent) {
+            self.0.borrow_mut().push(event)
+        }
+    }
+
+    #[test]
+    fn test_load_logger() {
+        let a = Module::new();
+        a.set("x", a.heap().alloc(1));
+        let loader = Loader(a.freeze().unwrap());
+        let logger = Logger::default();
+
+        let module = Module::new();
+        let mut eval = Evaluator::new(&module);
+        eval.set_loader(&loader);
+        eval.set_load_logger(&logger);
+        let ast = AstModule::parse(
+            "test.star",
+            "load('a.star', 'x')\nload('b.star', y = 'x')".to_owned(),
+            &Dialect::Standard,
+        )
+        .unwrap();
+        assert!(eval.eval_module(ast, &Globals::standard()).is_err());
+        drop(eval);
+
+        let events = logger.0.into_inner();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].module, "a.star");
+        assert_eq!(events[0].location, "test.star:1:1-20");
+        assert_eq!(events[0].symbols, vec!["x".to_owned()]);
+        assert_eq!(
+            events[0].details,
+            vec![("resolved".to_owned(), "/root/a.star".to_owned())]
+        );
+        assert_eq!(events[0].error, None);
+        assert_eq!(events[1].module, "b.star");
+        assert_eq!(events[1].error.as_deref(), Some("No such module"));
+    }
+
+    // Evaluates modules from source, loading their dependencies with itself.
+    struct SourceLoader(HashMap<&'static str, &'static str>);
+
+    impl FileLoader for SourceLoader {
+        fn load(&self, path: &str) -> anyhow::Result<FrozenModule> {
+            let source = match self.0.get(path) {
+                Some(source) => *source,
+                None => return Err(LoadError::not_found(path).into()),
+            };
+            let ast = AstModule::parse(path, source.to_owned(), &Dialect::Standard)?;
+            let module = Module::new();
+            let mut eval = Evaluator::new(&module);
+            eval.set_loader(self);
+            eval.eval_module(ast, &Globals::standard())?;
+            drop(eval);
+            module.freeze()
+        }
+    }
+
+    struct ReadyLoader(FrozenModule);
+
+    impl AsyncFileLoader for ReadyLoader {
+        fn load(
+            &self,
+            _path: &str,
+        ) -> Pin<Box<dyn Future<Output = anyhow::Result<FrozenModule>> + Send + 'static>> {
+            Box::pin(std::future::ready(Ok(self.0.dupe())))
+        }
+    }
+
+    #[test]
+    fn test_load_error_chain() {
+        let loader = SourceLoader(hashmap! {
+            "a.star" => "load('b.star', 'b')\na = b",
+            "b.star" => "\nload('c.star', 'c')\nb = c",
+        });
+        let err = loader.load("a.star").unwrap_err();
+        let diagnostic = err.downcast_ref::<Diagnostic>().unwrap();
+        let load_err = diagnostic.message.downcast_ref::<LoadError>().unwrap();
+        assert!(matches!(load_err, LoadError::NotFound { module, .. } if module == "c.star"));
+        assert_eq!(
+            load_err.chain(),
+            &[
+                LoadStep {
+                    module: "b.star".to_owned(),
+                    location: "a.star:1:1-20".to_owned(),
+                },
+                LoadStep {
+                    module: "c.star".to_owned(),
+                    location: "b.star:2:1-20".to_owned(),
+                },
+            ]
+        );
+        assert!(
+            load_err.to_string().starts_with(
+                "Module `c.star` not found, load chain:\n  `b.star` loaded at a.star:1:1-20"
+            ),
+            "{}",
+            load_err
+        );
+        // The chain is only recorded while the `load()` is being evaluated.
+        assert!(LoadError::current_chain().is_empty());
+    }
+
+    #[test]
+    fn test_load_cycle() {
+        let loader = SourceLoader(hashmap! {
+            "a.star" => "load('b.star', 'b')\na = b",
+            "b.star" => "load('a.star', 'a')\nb = a",
+            "c.star" => "load('c.star', 'c')\nc = 1",
+        });
+        let err = loader.load("a.star").unwrap_err();
+        let diagnostic = err.downcast_ref::<Diagnostic>().unwrap();
+        let load_err = diagnostic.message.downcast_ref::<LoadError>().unwrap();
+        assert!(matches!(load_err, LoadError::Cycle { module, .. } if module == "a.star"));
+        assert_eq!(
+            load_err.to_string(),
+            "Module `a.star` is part of a `load()` cycle, load chain:\n  \
+            `b.star` loaded at a.star:1:1-20\n  \
+            `a.star` loaded at b.star:1:1-20"
+        );
+
+        let err = loader.load("c.star").unwrap_err();
+        let diagnostic = err.downcast_ref::<Diagnostic>().unwrap();
+        let load_err = diagnostic.message.downcast_ref::<LoadError>().unwrap();
+        assert!(matches!(load_err, LoadError::Cycle { module, .. } if module == "c.star"));
+    }
+
+    #[test]
+    fn test_cached_loader() {
+        let loader = CachedLoader::new(SourceLoader(hashmap! {
+            "lib.star" => "x = [1]",
+        }));
+        let x = loader.load("lib.star").unwrap().get("x").unwrap();
+        let again = loader.load("lib.star").unwrap().get("x").unwrap();
+        assert!(again.value().ptr_eq(x.value()));
+    }
+
+    #[test]
+    fn test_async_loader() {
+        let a = Module::new();
+        a.set("x", a.heap().alloc(1));
+        let loader = AsyncLoader(ReadyLoader(a.freeze().unwrap()));
+
+        let module = Module::new();
+        let mut eval = Evaluator::new(&module);
+        eval.set_loader(&loader);
+        let ast = AstModule::parse(
+            "test.star",
+            "load('a.star', 'x')\nx".to_owned(),
+            &Dialect::Standard,
+        )
+        .unwrap();
+        let res = eval.eval_module(ast, &Globals::standard()).unwrap();
+        assert_eq!(res.unpack_int(), Some(1));
+    }
+
+    #[test]
+    fn test_path_mapping() {
+        let mut paths = PathMapping::new();
+        assert_eq!(paths.disk_path(Path::new("a.star")), Path::new("a.star"));
+        assert_eq!(paths.reported_name(Path::new("a.star")), "a.star");
+
+        paths.set_working_dir("/tmp/sandbox/pkg");
+        paths.add_rewrite("/tmp/sandbox", "/home/user/repo");
+        assert_eq!(
+            paths.disk_path(Path::new("a.star")),
+            Path::new("/tmp/sandbox/pkg/a.star")
+        );
+        assert_eq!(
+            paths.disk_path(Path::new("/home/user/repo/lib/b.star")),
+            Path::new("/tmp/sandbox/lib/b.star")
+        );
+        assert_eq!(
+            paths.reported_name(Path::new("a.star")),
+            "/home/user/repo/pkg/a.star"
+        );
+        assert_eq!(
+            paths.reported_name(Path::new("/tmp/sandbox/lib/b.star")),
+            "/home/user/repo/lib/b.star"
+        );
+        assert_eq!(paths.reported_name(Path::new("/etc/c.star")), "/etc/c.star");
+    }
 }
@@ -0,0 +1,115 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Pace garbage collections,
+//! see [`Evaluator::set_gc_pacing`](crate::eval::Evaluator::set_gc_pacing).
+//!
+//! With generational collection, the work done by a minor collection grows with
+//! the bytes allocated since the previous one, so collecting after fewer bytes gives
+//! shorter pauses. The pacer measures how many bytes a collection gets through per second,
+//! and from that how many bytes may be allocated before the next collection. Only the
+//! threshold is paced: each collection still runs to completion, however long it takes.
+
+use std::{cmp, time::Duration};
+
+use crate::eval::runtime::evaluator::GC_THRESHOLD;
+
+/// Fewest bytes allocated between collections, however slow they are, so the
+/// evaluation still makes progress between them.
+const MIN_SLICE_BYTES: usize = 16 * 1024;
+
+/// Weight of the latest collection in the measured rate, so one slow collection
+/// doesn't shrink the slices too much.
+const RATE_WEIGHT: f64 = 0.25;
+
+pub(crate) struct GcPacer {
+    /// How long a collection should take.
+    target: Duration,
+    /// Bytes a collection gets through per second, once measured.
+    bytes_per_sec: Option<f64>,
+}
+
+impl GcPacer {
+    pub(crate) fn new(target: Duration) -> Self {
+        Self {
+            target,
+            bytes_per_sec: None,
+        }
+    }
+
+    pub(crate) fn target(&self) -> Duration {
+        self.target
+    }
+
+    /// Record a collection through `bytes`, which took `elapsed`.
+    pub(crate) fn record(&mut self, bytes: usize, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        if bytes == 0 || secs == 0.0 {
+            return;
+        }
+        let rate = bytes as f64 / secs;
+        self.bytes_per_sec = Some(match self.bytes_per_sec {
+            None => rate,
+            Some(old) => old + (rate - old) * RATE_WEIGHT,
+        });
+    }
+
+    /// Bytes which may be allocated before the next collection, so it takes about half
+    /// the target, leaving room for the work which doesn't depend on the bytes allocated.
+    pub(crate) fn slice_bytes(&self) -> usize {
+        match self.bytes_per_sec {
+            None => GC_THRESHOLD,
+            Some(rate) => {
+                let bytes = (rate * self.target.as_secs_f64() / 2.0) as usize;
+                cmp::max(MIN_SLICE_BYTES, bytes)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::eval::runtime::{
+        evaluator::GC_THRESHOLD,
+        gc_pacer::{GcPacer, MIN_SLICE_BYTES},
+    };
+
+    #[test]
+    fn test_slice_bytes() {
+        let mut pacer = GcPacer::new(Duration::from_millis(2));
+        assert_eq!(GC_THRESHOLD, pacer.slice_bytes());
+        // 1MB per millisecond, so half of 2 milliseconds.
+        pacer.record(1_000_000, Duration::from_millis(1));
+        let bytes = pacer.slice_bytes();
+        assert!((999_000..=1_001_000).contains(&bytes), "{}", bytes);
+        // Very slow collections still leave room to evaluate.
+        let mut pacer = GcPacer::new(Duration::from_micros(1));
+        pacer.record(1000, Duration::from_secs(1));
+        assert_eq!(MIN_SLICE_BYTES, pacer.slice_bytes());
+    }
+
+    #[test]
+    fn test_slow_collection_is_smoothed() {
+        let mut pacer = GcPacer::new(Duration::from_millis(2));
+        pacer.record(1_000_000, Duration::from_millis(1));
+        pacer.record(1_000_000, Duration::from_millis(1000));
+        let bytes = pacer.slice_bytes();
+        assert!(bytes > 500_000 && bytes < 1_000_000, "{}", bytes);
+    }
+}
@@ -50,11 +50,11 @@ pub(crate) struct HeapProfile {
 }
 
 /// A type which is either drop or non-drop.
-trait MaybeDrop: Debug + Sync + Send + 'static {}
+pub(crate) trait MaybeDrop: Debug + Sync + Send + 'static {}
 
 /// Type which has `Drop`.
 #[derive(AnyLifetime, Debug, Trace)]
-struct NeedsDrop;
+pub(crate) struct NeedsDrop;
 impl Drop for NeedsDrop {
     fn drop(&mut self) {
         // Just make this type `Drop`.
@@ -65,7 +65,7 @@ impl Drop for NeedsDrop {
 
 /// Type which doesn't have `Drop`.
 #[derive(AnyLifetime, Debug, Trace)]
-struct NoDrop;
+pub(crate) struct NoDrop;
 
 impl MaybeDrop for NeedsDrop {}
 impl MaybeDrop for NoDrop {}
@@ -173,6 +173,10 @@ impl HeapProfile {
         self.enabled = true;
     }
 
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
     pub(crate) fn record_call_enter<'v>(&self, function: Value<'v>, heap: &'v Heap) {
         if self.enabled {
             let time = Instant::now();
@@ -0,0 +1,383 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! A graph of the live values on a [`Heap`](crate::values::Heap), and working out
+//! which of them keep the most memory alive.
+
+use std::{fs, path::Path};
+
+use anyhow::Context;
+use gazebo::prelude::*;
+use thiserror::Error;
+
+use crate::eval::runtime::csv::CsvWriter;
+
+#[derive(Debug, Error)]
+enum HeapSnapshotError {
+    #[error("Heap snapshot line {0} is malformed")]
+    Malformed(usize),
+    #[error("Heap snapshot refers to value {0}, which it does not contain")]
+    UnknownReference(usize),
+}
+
+/// A value in a [`HeapSnapshot`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeapSnapshotNode {
+    /// The type of the value, as given by `type()`.
+    pub typ: String,
+    /// The memory used by the value itself, as in [`HeapSummary`](crate::values::HeapSummary).
+    pub bytes: usize,
+    /// The values this value refers to, as indices into [`HeapSnapshot::nodes`].
+    /// References to frozen values are not included, as they live on another heap.
+    pub references: Vec<usize>,
+    /// Where the value was allocated, if
+    /// [`Evaluator::enable_provenance`](crate::eval::Evaluator::enable_provenance) was called.
+    pub location: Option<String>,
+}
+
+/// A graph of the values on a heap which were alive when the snapshot was taken,
+/// obtained with [`Evaluator::heap_snapshot`](crate::eval::Evaluator::heap_snapshot).
+///
+/// The first node is not a value, but stands for the evaluator: it has type `(roots)`,
+/// no size, and refers to every value the evaluator keeps alive directly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeapSnapshot {
+    /// The values, with the roots first.
+    pub nodes: Vec<HeapSnapshotNode>,
+}
+
+impl HeapSnapshot {
+    /// The type of the first node, which refers to the values the evaluator keeps alive.
+    pub const ROOTS: &'static str = "(roots)";
+
+    /// Write the snapshot as a `.csv` file, with one line per node, giving its index,
+    /// type, size, the space-separated indices of the nodes it refers to, and its location.
+    pub fn write<P: AsRef<Path>>(&self, filename: P) -> anyhow::Result<()> {
+        let filename = filename.as_ref();
+        fs::write(filename, self.to_csv())
+            .with_context(|| format!("When writing heap snapshot file `{}`", filename.display()))
+    }
+
+    /// Read a snapshot written by [`write`](HeapSnapshot::write). Fails unless the first
+    /// node is the roots.
+    pub fn read<P: AsRef<Path>>(filename: P) -> anyhow::Result<Self> {
+        let filename = filename.as_ref();
+        let csv = fs::read_to_string(filename)
+            .with_context(|| format!("When reading heap snapshot file `{}`", filename.display()))?;
+        Self::from_csv(&csv)
+    }
+
+    pub(crate) fn to_csv(&self) -> String {
+        let mut csv = CsvWriter::new(["Id", "Type", "Bytes", "References", "Location"]);
+        for (i, node) in self.nodes.iter().enumerate() {
+            csv.write_value(i);
+            csv.write_value(node.typ.as_str());
+            csv.write_value(node.bytes);
+            csv.write_display(
+                node.references
+                    .iter()
+                    .map(|x| x.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            );
+            // Last, as the file name may contain commas.
+            csv.write_display(node.location.as_deref().unwrap_or_default());
+            csv.finish_row();
+        }
+        csv.finish()
+    }
+
+    pub(crate) fn from_csv(csv: &str) -> anyhow::Result<Self> {
+        let mut nodes = Vec::new();
+        // The first line is the header.
+        for (i, line) in csv.lines().enumerate().skip(1) {
+            let malformed = || HeapSnapshotError::Malformed(i + 1);
+            let mut columns = line.splitn(5, ',');
+            let mut column = || columns.next().ok_or_else(malformed);
+            if column()?.parse::<usize>().ok() != Some(nodes.len()) {
+                return Err(malformed().into());
+            }
+            let typ = column()?.trim_matches('"').to_owned();
+            let bytes = column()?.parse().map_err(|_| malformed())?;
+            let references = column()?
+                .split_whitespace()
+                .map(|x| x.parse().map_err(|_| malformed()))
+                .collect::<Result<_, _>>()?;
+            let location = Some(column()?).filter(|x| !x.is_empty()).map(str::to_owned);
+            nodes.push(HeapSnapshotNode {
+                typ,
+                bytes,
+                references,
+                location,
+            });
+        }
+        // The roots are on the line after the header.
+        if nodes.first().map(|x| x.typ.as_str()) != Some(Self::ROOTS) {
+            return Err(HeapSnapshotError::Malformed(2).into());
+        }
+        for node in &nodes {
+            if let Some(x) = node.references.iter().find(|x| **x >= nodes.len()) {
+                return Err(HeapSnapshotError::UnknownReference(*x).into());
+            }
+        }
+        Ok(Self { nodes })
+    }
+
+    /// For each node, the number of bytes which would be freed if nothing referred to it:
+    /// its own size, plus that of every value only reachable through it.
+    /// The first node is taken to be the roots, whatever its type.
+    pub fn retained_bytes(&self) -> Vec<usize> {
+        let idom = self.dominators();
+        let mut retained = self.nodes.map(|x| x.bytes);
+        // A node's dominator is always visited before it, so walk back from the end.
+        for &x in self.reverse_postorder().iter().rev() {
+            if x != 0 {
+                retained[idom[x]] += retained[x];
+            }
+        }
+        retained
+    }
+
+    /// The nodes retaining the most memory, as `(index, retained bytes)`, largest first,
+    /// not including the roots.
+    pub fn largest_retainers(&self) -> Vec<(usize, usize)> {
+        let mut res: Vec<_> = self
+            .retained_bytes()
+            .into_iter()
+            .enumerate()
+            .skip(1)
+            .collect();
+        res.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        res
+    }
+
+    /// The nodes reachable from the roots, with every node before all those it refers to,
+    /// other than by a cycle.
+    fn reverse_postorder(&self) -> Vec<usize> {
+        if self.nodes.is_empty() {
+            return Vec::new();
+        }
+        let mut visited = vec![false; self.nodes.len()];
+        let mut postorder = Vec::with_capacity(self.nodes.len());
+        // Each entry is a node, and how many of its references have been visited.
+        let mut stack = vec![(0, 0)];
+        visited[0] = true;
+        while let Some((x, i)) = stack.last_mut() {
+            match self.nodes[*x].references.get(*i) {
+                Some(&y) => {
+                    *i += 1;
+                    if !visited[y] {
+                        visited[y] = true;
+                        stack.push((y, 0));
+                    }
+                }
+                None => {
+                    postorder.push(*x);
+                    stack.pop();
+                }
+            }
+        }
+        postorder.reverse();
+        postorder
+    }
+
+    /// The immediate dominator of each node: the closest node every path from the roots
+    /// to it must go through. Uses "A Simple, Fast Dominance Algorithm" by Cooper,
+    /// Harvey and Kennedy. Nodes which are not reachable are their own dominator.
+    fn dominators(&self) -> Vec<usize> {
+        let order = self.reverse_postorder();
+        let mut rank = vec![usize::MAX; self.nodes.len()];
+        for (i, x) in order.iter().enumerate() {
+            rank[*x] = i;
+        }
+        let mut predecessors = vec![Vec::new(); self.nodes.len()];
+        for (x, node) in self.nodes.iter().enumerate() {
+            for y in &node.references {
+                predecessors[*y].push(x);
+            }
+        }
+
+        let mut idom: Vec<usize> = (0..self.nodes.len()).collect();
+        if self.nodes.is_empty() {
+            return idom;
+        }
+        let mut defined = vec![false; self.nodes.len()];
+        defined[0] = true;
+        let intersect = |idom: &[usize], mut a: usize, mut b: usize| {
+            while a != b {
+                while rank[a] > rank[b] {
+                    a = idom[a];
+                }
+                while rank[b] > rank[a] {
+                    b = idom[b];
+                }
+            }
+            a
+        };
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &x in order.iter().skip(1) {
+                let mut new = None;
+                for &p in &predecessors[x] {
+                    if defined[p] {
+                        new = Some(match new {
+                            None => p,
+                            Some(new) => intersect(&idom, p, new),
+                        });
+                    }
+                }
+                // Every reachable node other than the roots has a reachable predecessor
+                // earlier in the order, so has been given a dominator by now.
+                let new = new.unwrap();
+                if !defined[x] || idom[x] != new {
+                    idom[x] = new;
+                    defined[x] = true;
+                    changed = true;
+                }
+            }
+        }
+        idom
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        environment::{Globals, Module},
+        eval::Evaluator,
+        syntax::{AstModule, Dialect},
+    };
+
+    fn node(typ: &str, bytes: usize, references: Vec<usize>) -> HeapSnapshotNode {
+        HeapSnapshotNode {
+            typ: typ.to_owned(),
+            bytes,
+            references,
+            location: None,
+        }
+    }
+
+    #[test]
+    fn test_retained_bytes() {
+        // The roots keep 1 and 2 alive. 3 is shared between them, 4 is only reachable
+        // through 2, and 4 and 5 refer to each other.
+        let snapshot = HeapSnapshot {
+            nodes: vec![
+                node(HeapSnapshot::ROOTS, 0, vec![1, 2]),
+                node("list", 10, vec![3]),
+                node("dict", 20, vec![3, 4]),
+                node("string", 100, vec![]),
+                node("list", 30, vec![5]),
+                node("list", 40, vec![4]),
+            ],
+        };
+        assert_eq!(snapshot.retained_bytes(), vec![200, 10, 90, 100, 70, 40]);
+        assert_eq!(
+            snapshot.largest_retainers(),
+            vec![(3, 100), (2, 90), (4, 70), (5, 40), (1, 10)]
+        );
+    }
+
+    #[test]
+    fn test_csv_round_trip() {
+        let mut snapshot = HeapSnapshot {
+            nodes: vec![
+                node(HeapSnapshot::ROOTS, 0, vec![1]),
+                node("list", 10, vec![2, 2]),
+                node("string", 20, vec![]),
+            ],
+        };
+        snapshot.nodes[2].location = Some("a,b.star:1:5-8".to_owned());
+        let csv = snapshot.to_csv();
+        assert_eq!(
+            csv,
+            "\
+Id,Type,Bytes,References,Location
+0,\"(roots)\",0,1,
+1,\"list\",10,2 2,
+2,\"string\",20,,a,b.star:1:5-8
+"
+        );
+        assert_eq!(HeapSnapshot::from_csv(&csv).unwrap(), snapshot);
+        assert!(HeapSnapshot::from_csv("Id\n0,\"(roots)\",0,7,\n").is_err());
+    }
+
+    #[test]
+    fn test_no_roots() {
+        for csv in [
+            "",
+            "Id,Type,Bytes,References,Location\n",
+            "Id\n0,\"list\",10,,\n",
+        ] {
+            let err = HeapSnapshot::from_csv(csv).unwrap_err();
+            assert_eq!(err.to_string(), "Heap snapshot line 2 is malformed");
+        }
+        // A snapshot built by hand may still be empty.
+        let empty = HeapSnapshot { nodes: Vec::new() };
+        assert_eq!(empty.retained_bytes(), Vec::<usize>::new());
+        assert_eq!(empty.largest_retainers(), Vec::<(usize, usize)>::new());
+    }
+
+    #[test]
+    fn test_heap_snapshot() {
+        let ast = AstModule::parse(
+            "x.star",
+            "\
+x = [[1], 'x' * 100]
+y = {'a': x}
+"
+            .to_owned(),
+            &Dialect::Extended,
+        )
+        .unwrap();
+        let module = Module::new();
+        let mut eval = Evaluator::new(&module);
+        eval.enable_provenance();
+        eval.eval_module(ast, &Globals::standard()).unwrap();
+        let snapshot = unsafe { eval.heap_snapshot() }.unwrap();
+
+        assert_eq!(snapshot.nodes[0].typ, HeapSnapshot::ROOTS);
+        let strings: Vec<_> = snapshot
+            .nodes
+            .iter()
+            .filter(|x| x.typ == "string")
+            .collect();
+        assert_eq!(strings.len(), 1);
+        assert!(strings[0].bytes > 100);
+        assert!(strings[0]
+            .location
+            .as_ref()
+            .unwrap()
+            .starts_with("x.star:1:"));
+        // `x` keeps the string alive, `y` refers to `x`, but so does the module.
+        let (largest, retained) = snapshot.largest_retainers()[0];
+        assert_eq!(snapshot.nodes[largest].typ, "list");
+        assert!(retained > strings[0].bytes);
+        assert_eq!(
+            HeapSnapshot::from_csv(&snapshot.to_csv()).unwrap(),
+            snapshot
+        );
+        // The values survive the garbage collection.
+        assert!(module
+            .get("y")
+            .unwrap()
+            .to_repr()
+            .starts_with(r#"{"a": [[1], "xxx"#));
+    }
+}
@@ -16,12 +16,27 @@
  */
 
 pub(crate) mod arguments;
+pub(crate) mod async_native;
 pub(crate) mod bc_profile;
+pub(crate) mod call_hook;
 pub(crate) mod call_stack;
+pub(crate) mod cancellation;
+pub(crate) mod checkpoint;
+pub(crate) mod coercions;
+pub(crate) mod coverage;
 pub(crate) mod csv;
 pub(crate) mod evaluator;
 pub(crate) mod file_loader;
 pub(crate) mod flame_profile;
+pub(crate) mod gc_pacer;
 pub(crate) mod heap_profile;
+pub(crate) mod heap_snapshot;
+pub(crate) mod parallel;
+pub(crate) mod pinned;
+pub(crate) mod print_stream;
+pub(crate) mod profile_mode;
+pub(crate) mod provenance;
 pub(crate) mod slots;
+pub(crate) mod stats;
+pub(crate) mod stepper;
 pub(crate) mod stmt_profile;
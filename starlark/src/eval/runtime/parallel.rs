@@ -0,0 +1,397 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Evaluate a set of modules which `load()` each other, evaluating modules whose
+//! dependencies are all available in parallel.
+
+use std::{
+    any::Any,
+    collections::{HashMap, VecDeque},
+    mem,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::Duration,
+};
+
+use gazebo::prelude::*;
+use thiserror::Error;
+
+use crate::{
+    environment::{FrozenModule, Globals, Module},
+    errors::ErrorKind,
+    eval::{Evaluator, ReturnFileLoader},
+    syntax::AstModule,
+};
+
+#[derive(Debug, Error)]
+enum ParallelEvalError {
+    #[error("Module `{0}` loads `{1}`, which was not added to the evaluation")]
+    UnknownModule(String, String),
+    #[error("Modules form a `load()` cycle, involving {}", .0.map(|x| format!("`{}`", x)).join(", "))]
+    Cycle(Vec<String>),
+    #[error("Module `{0}` exceeded its limits")]
+    LimitExceeded(String),
+}
+
+/// Limits on the resources used to evaluate a module, see
+/// [`ParallelEval::set_module_limits`]. A limit of [`None`] means no limit.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ModuleLimits {
+    /// The statements the module may execute, see
+    /// [`Evaluator::set_max_steps`].
+    pub max_steps: Option<u64>,
+    /// The bytes the module may allocate, see
+    /// [`Heap::set_allocation_limit`](crate::values::Heap::set_allocation_limit).
+    pub max_allocated_bytes: Option<usize>,
+    /// How long the module may take to evaluate, see [`Evaluator::set_timeout`].
+    pub timeout: Option<Duration>,
+}
+
+/// Evaluates a set of modules, each of which may `load()` others in the set, on a pool of
+/// threads. A module is evaluated once all the modules it loads have been evaluated and frozen,
+/// so modules which don't depend on each other are evaluated at the same time, and each
+/// [`FrozenModule`] is evaluated once and shared by all the modules which load it.
+///
+/// ```
+/// use starlark::environment::Globals;
+/// use starlark::eval::ParallelEval;
+/// use starlark::syntax::{AstModule, Dialect};
+///
+/// let parse = |name: &str, content: &str| {
+///     AstModule::parse(name, content.to_owned(), &Dialect::Standard).unwrap()
+/// };
+/// let mut eval = ParallelEval::new(Globals::standard(), 2);
+/// eval.add_module("a.star", parse("a.star", "a = 1"));
+/// eval.add_module("b.star", parse("b.star", "load('a.star', 'a')\nb = a + 1"));
+/// eval.add_module("c.star", parse("c.star", "load('a.star', 'a')\nc = a + 2"));
+/// let modules = eval.eval().unwrap();
+/// assert_eq!(modules["c.star"].get("c").unwrap().value().unpack_int(), Some(3));
+/// ```
+pub struct ParallelEval {
+    globals: Globals,
+    modules: HashMap<String, (AstModule, Option<ModuleLimits>)>,
+    default_limits: ModuleLimits,
+    threads: usize,
+}
+
+// The progress of an evaluation, shared between the worker threads.
+struct State {
+    // Modules whose dependencies have all been evaluated.
+    ready: VecDeque<String>,
+    // Modules still to be evaluated, with the number of their dependencies still to be evaluated.
+    waiting: HashMap<String, (AstModule, ModuleLimits, usize)>,
+    // For each module, the modules which load it.
+    dependents: HashMap<String, Vec<String>>,
+    // For each module, the modules it loads.
+    loads: HashMap<String, Vec<String>>,
+    done: HashMap<String, FrozenModule>,
+    // Number of modules currently being evaluated.
+    running: usize,
+    error: Option<anyhow::Error>,
+    // A panic from evaluating a module, to be resumed on the calling thread.
+    panic: Option<Box<dyn Any + Send>>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+impl ParallelEval {
+    /// Evaluate modules with the given [`Globals`], evaluating up to `threads` modules at once.
+    pub fn new(globals: Globals, threads: usize) -> Self {
+        Self {
+            globals,
+            modules: HashMap::new(),
+            default_limits: ModuleLimits::default(),
+            threads: threads.max(1),
+        }
+    }
+
+    /// Add a module to evaluate. The `name` is the path other modules use to `load()` it.
+    pub fn add_module(&mut self, name: &str, ast: AstModule) {
+        self.modules.insert(name.to_owned(), (ast, None));
+    }
+
+    /// Set the limits for the modules without limits of their own. By default there are none.
+    pub fn set_default_limits(&mut self, limits: ModuleLimits) {
+        self.default_limits = limits;
+    }
+
+    /// Set the limits for evaluating the module `name`, which must have been added, instead of
+    /// the [default limits](ParallelEval::set_default_limits). Each module is evaluated with
+    /// its own [`Evaluator`] and heap, so only what the module itself does counts, not the
+    /// modules it loads. If a module goes over a limit, the error names it.
+    pub fn set_module_limits(&mut self, name: &str, limits: ModuleLimits) {
+        if let Some((_, x)) = self.modules.get_mut(name) {
+            *x = Some(limits);
+        }
+    }
+
+    /// Evaluate all the added modules, returning them frozen, keyed by name. If any module
+    /// fails, no further modules are started and the first error is returned.
+    pub fn eval(self) -> anyhow::Result<HashMap<String, FrozenModule>> {
+        let mut loads = HashMap::with_capacity(self.modules.len());
+        let mut dependents: HashMap<String, Vec<String>> = HashMap::new();
+        for (name, (ast, _)) in &self.modules {
+            let mut deps = ast.loads().into_map(|x| x.to_owned());
+            deps.sort();
+            deps.dedup();
+            for dep in &deps {
+                if !self.modules.contains_key(dep) {
+                    return Err(ParallelEvalError::UnknownModule(name.clone(), dep.clone()).into());
+                }
+                dependents
+                    .entry(dep.clone())
+                    .or_default()
+                    .push(name.clone());
+            }
+            loads.insert(name.clone(), deps);
+        }
+
+        let mut ready = VecDeque::new();
+        let mut waiting = HashMap::with_capacity(self.modules.len());
+        for (name, (ast, limits)) in self.modules {
+            let count = loads[&name].len();
+            if count == 0 {
+                ready.push_back(name.clone());
+            }
+            waiting.insert(name, (ast, limits.unwrap_or(self.default_limits), count));
+        }
+        let threads = self.threads.min(waiting.len());
+
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                ready,
+                waiting,
+                dependents,
+                loads,
+                done: HashMap::new(),
+                running: 0,
+                error: None,
+                panic: None,
+            }),
+            changed: Condvar::new(),
+        });
+        let workers = (0..threads).map(|_| {
+            let shared = shared.dupe();
+            let globals = self.globals.dupe();
+            thread::spawn(move || worker(&shared, &globals))
+        });
+        for worker in workers.collect::<Vec<_>>() {
+            worker.join().unwrap();
+        }
+
+        let mut state = shared.state.lock().unwrap();
+        if let Some(e) = state.panic.take() {
+            panic::resume_unwind(e);
+        }
+        if let Some(e) = state.error.take() {
+            return Err(e);
+        }
+        if !state.waiting.is_empty() {
+            let mut cycle = state.waiting.keys().cloned().collect::<Vec<_>>();
+            cycle.sort();
+            return Err(ParallelEvalError::Cycle(cycle).into());
+        }
+        Ok(mem::take(&mut state.done))
+    }
+}
+
+fn worker(shared: &Shared, globals: &Globals) {
+    let mut state = shared.state.lock().unwrap();
+    loop {
+        if state.error.is_some() || state.panic.is_some() {
+            return;
+        }
+        let name = match state.ready.pop_front() {
+            Some(name) => name,
+            // Nothing left to start, and nothing running which could make more ready.
+            None if state.running == 0 => return,
+            None => {
+                state = shared.changed.wait(state).unwrap();
+                continue;
+            }
+        };
+        let (ast, limits, _) = state.waiting.remove(&name).unwrap();
+        let deps = state.loads[&name].map(|x| (x.clone(), state.done[x].dupe()));
+        state.running += 1;
+        drop(state);
+
+        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+            eval_one(&name, ast, limits, globals, &deps)
+        }));
+
+        state = shared.state.lock().unwrap();
+        state.running -= 1;
+        match res {
+            Err(e) => state.panic = Some(e),
+            Ok(Ok(module)) => {
+                state.done.insert(name.clone(), module);
+                for dependent in state.dependents.remove(&name).unwrap_or_default() {
+                    let count = &mut state.waiting.get_mut(&dependent).unwrap().2;
+                    *count -= 1;
+                    if *count == 0 {
+                        state.ready.push_back(dependent);
+                    }
+                }
+            }
+            Ok(Err(e)) => {
+                if state.error.is_none() {
+                    state.error = Some(e);
+                }
+            }
+        }
+        shared.changed.notify_all();
+    }
+}
+
+fn eval_one(
+    name: &str,
+    ast: AstModule,
+    limits: ModuleLimits,
+    globals: &Globals,
+    deps: &[(String, FrozenModule)],
+) -> anyhow::Result<FrozenModule> {
+    let modules = deps.iter().map(|(k, v)| (k.as_str(), v)).collect();
+    let loader = ReturnFileLoader { modules: &modules };
+    let module = Module::new();
+    let mut eval = Evaluator::new(&module);
+    eval.set_loader(&loader);
+    if let Some(steps) = limits.max_steps {
+        eval.set_max_steps(steps);
+    }
+    if let Some(bytes) = limits.max_allocated_bytes {
+        module.heap().set_allocation_limit(bytes);
+    }
+    if let Some(timeout) = limits.timeout {
+        eval.set_timeout(timeout);
+    }
+    if let Err(e) = eval.eval_module(ast, globals) {
+        return Err(match e.kind() {
+            ErrorKind::Limit => e
+                .into_anyhow()
+                .context(ParallelEvalError::LimitExceeded(name.to_owned())),
+            _ => e.into(),
+        });
+    }
+    drop(eval);
+    module.freeze()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::syntax::Dialect;
+
+    fn parse(name: &str, content: &str) -> AstModule {
+        AstModule::parse(name, content.to_owned(), &Dialect::Standard).unwrap()
+    }
+
+    fn eval(modules: &[(&str, &str)]) -> anyhow::Result<HashMap<String, FrozenModule>> {
+        let mut eval = ParallelEval::new(Globals::standard(), 4);
+        for (name, content) in modules {
+            eval.add_module(name, parse(name, content));
+        }
+        eval.eval()
+    }
+
+    #[test]
+    fn test_parallel_eval() {
+        let modules = eval(&[
+            ("a.star", "a = [1]"),
+            ("b.star", "load('a.star', 'a')\nb = a + [2]"),
+            ("c.star", "load('a.star', 'a')\nc = a + [3]"),
+            (
+                "d.star",
+                "load('b.star', 'b')\nload('c.star', 'c')\nd = b + c",
+            ),
+            ("e.star", "e = 5"),
+        ])
+        .unwrap();
+        assert_eq!(modules.len(), 5);
+        assert_eq!(
+            modules["d.star"].get("d").unwrap().value().to_str(),
+            "[1, 2, 1, 3]"
+        );
+    }
+
+    #[test]
+    fn test_parallel_eval_errors() {
+        let err = eval(&[("a.star", "load('b.star', 'b')")]).unwrap_err();
+        assert!(err.to_string().contains("not added"), "{}", err);
+
+        let err = eval(&[
+            ("a.star", "load('b.star', 'b')\na = 1"),
+            ("b.star", "load('a.star', 'a')\nb = 1"),
+            ("c.star", "c = 1"),
+        ])
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Modules form a `load()` cycle, involving `a.star`, `b.star`"
+        );
+
+        let err =
+            eval(&[("a.star", "fail('bad')"), ("b.star", "load('a.star', 'a')")]).unwrap_err();
+        assert!(format!("{:#}", err).contains("bad"), "{:#}", err);
+    }
+
+    #[test]
+    fn test_parallel_eval_limits() {
+        let modules = [
+            ("a.star", "a = 1"),
+            (
+                "b.star",
+                "load('a.star', 'a')\ndef f():\n  for x in range(100):\n    pass\nb = f()",
+            ),
+            ("c.star", "c = 3"),
+        ];
+        let new = || {
+            let mut eval = ParallelEval::new(Globals::standard(), 4);
+            for (name, content) in modules {
+                eval.add_module(name, parse(name, content));
+            }
+            eval
+        };
+
+        let mut eval = new();
+        eval.set_default_limits(ModuleLimits {
+            max_steps: Some(1000),
+            ..ModuleLimits::default()
+        });
+        eval.set_module_limits(
+            "b.star",
+            ModuleLimits {
+                max_steps: Some(10),
+                ..ModuleLimits::default()
+            },
+        );
+        let err = eval.eval().unwrap_err();
+        assert_eq!(err.to_string(), "Module `b.star` exceeded its limits");
+        assert!(format!("{:#}", err).contains("10"), "{:#}", err);
+
+        let mut eval = new();
+        eval.set_default_limits(ModuleLimits {
+            max_steps: Some(10),
+            ..ModuleLimits::default()
+        });
+        eval.set_module_limits("b.star", ModuleLimits::default());
+        assert_eq!(eval.eval().unwrap().len(), 3);
+    }
+}
@@ -0,0 +1,104 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Values kept alive by the [`Evaluator`](crate::eval::Evaluator) on behalf of host code,
+//! see [`Evaluator::pin`](crate::eval::Evaluator::pin).
+
+use gazebo::prelude::*;
+
+use crate::values::{Trace, Tracer, Value};
+
+/// Identifies a value pinned with [`Evaluator::pin`](crate::eval::Evaluator::pin).
+#[derive(Debug, Clone, Copy, Dupe, PartialEq, Eq, Hash)]
+pub struct PinnedValue(usize);
+
+/// The pinned values, which are roots for garbage collection.
+#[derive(Default)]
+pub(crate) struct Pins<'v> {
+    values: Vec<Option<Value<'v>>>,
+    // Indices in `values` which are `None`, to reuse.
+    free: Vec<usize>,
+}
+
+impl<'v> Pins<'v> {
+    pub(crate) fn pin(&mut self, value: Value<'v>) -> PinnedValue {
+        match self.free.pop() {
+            Some(i) => {
+                self.values[i] = Some(value);
+                PinnedValue(i)
+            }
+            None => {
+                self.values.push(Some(value));
+                PinnedValue(self.values.len() - 1)
+            }
+        }
+    }
+
+    pub(crate) fn get(&self, pin: PinnedValue) -> Option<Value<'v>> {
+        *self.values.get(pin.0)?
+    }
+
+    pub(crate) fn unpin(&mut self, pin: PinnedValue) -> Option<Value<'v>> {
+        let value = self.values.get_mut(pin.0)?.take()?;
+        self.free.push(pin.0);
+        Some(value)
+    }
+
+    pub(crate) fn trace(&mut self, tracer: &Tracer<'v>) {
+        self.values.trace(tracer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        environment::{Globals, Module},
+        eval::Evaluator,
+        syntax::{AstModule, Dialect},
+    };
+
+    #[test]
+    fn test_pin() {
+        let module = Module::new();
+        let mut eval = Evaluator::new(&module);
+        let ast = AstModule::parse(
+            "pin.star",
+            "[str(i) for i in range(100)]".to_owned(),
+            &Dialect::Standard,
+        )
+        .unwrap();
+        let xs = eval.eval_module(ast, &Globals::standard()).unwrap();
+        let pin = eval.pin(xs);
+        let other = eval.pin(module.heap().alloc("other"));
+        assert_eq!(eval.unpin(other).unwrap().unpack_str(), Some("other"));
+
+        // Without the pin, `xs` would be freed, since nothing else refers to it.
+        unsafe { eval.garbage_collect() };
+        let xs = eval.pinned(pin).unwrap();
+        assert_eq!(xs.length().unwrap(), 100);
+        assert_eq!(
+            xs.at(module.heap().alloc(99), module.heap())
+                .unwrap()
+                .unpack_str(),
+            Some("99")
+        );
+
+        assert!(eval.unpin(pin).is_some());
+        assert!(eval.pinned(pin).is_none());
+        assert!(eval.unpin(pin).is_none());
+    }
+}
@@ -0,0 +1,175 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Stream the output of `print` to another thread as it is produced.
+
+use std::{
+    cell::Cell,
+    panic,
+    sync::mpsc::{sync_channel, Receiver, SyncSender},
+    thread,
+};
+
+use thiserror::Error;
+
+use crate::stdlib::PrintHandler;
+
+#[derive(Debug, Error)]
+enum PrintStreamError {
+    #[error("Printed more than the limit of {0} bytes")]
+    LimitExceeded(usize),
+    #[error("The printed output is no longer being read")]
+    Disconnected,
+}
+
+/// A [`PrintHandler`] which sends each line printed to a [`Receiver`], usually on another
+/// thread, so large output is passed on while the evaluation continues, rather than
+/// buffered until it finishes. Text containing newlines, such as from `pprint`, is split,
+/// so each string received is a single line, without its newline.
+///
+/// At most `capacity` lines can wait to be received. Beyond that, `print` blocks until the
+/// receiver catches up, so a slow consumer slows the evaluation down rather than letting
+/// the output grow without bound. The total printed can also be limited, after which
+/// `print` fails, and with it the evaluation.
+pub struct PrintStream {
+    sender: SyncSender<String>,
+    limit: Option<usize>,
+    printed: Cell<usize>,
+}
+
+impl PrintStream {
+    /// A stream holding at most `capacity` lines which haven't been received,
+    /// and the receiving end. Set it with
+    /// [`Evaluator::set_print_handler`](crate::eval::Evaluator::set_print_handler).
+    pub fn new(capacity: usize) -> (Self, Receiver<String>) {
+        let (sender, receiver) = sync_channel(capacity);
+        (
+            Self {
+                sender,
+                limit: None,
+                printed: Cell::new(0),
+            },
+            receiver,
+        )
+    }
+
+    /// Fail once more than `bytes` have been printed in total, counting a newline per line.
+    pub fn with_limit(mut self, bytes: usize) -> Self {
+        self.limit = Some(bytes);
+        self
+    }
+
+    /// The bytes printed so far, counting a newline per line.
+    pub fn printed(&self) -> usize {
+        self.printed.get()
+    }
+
+    /// Call `f` with a stream, passing each line printed to `consumer`, which runs on a
+    /// separate thread. Returns once `consumer` has been passed every line.
+    pub fn consume<R>(
+        capacity: usize,
+        limit: Option<usize>,
+        mut consumer: impl FnMut(String) + Send + 'static,
+        f: impl FnOnce(&PrintStream) -> R,
+    ) -> R {
+        let (stream, receiver) = Self::new(capacity);
+        let stream = match limit {
+            Some(limit) => stream.with_limit(limit),
+            None => stream,
+        };
+        let thread = thread::spawn(move || {
+            for line in receiver {
+                consumer(line)
+            }
+        });
+        let res = f(&stream);
+        // Close the channel, so the consumer finishes once it's received everything
+        drop(stream);
+        if let Err(e) = thread.join() {
+            panic::resume_unwind(e)
+        }
+        res
+    }
+}
+
+impl PrintHandler for PrintStream {
+    fn println(&self, text: &str) -> anyhow::Result<()> {
+        for line in text.split('\n') {
+            let printed = self.printed.get() + line.len() + 1;
+            if let Some(limit) = self.limit {
+                if printed > limit {
+                    return Err(PrintStreamError::LimitExceeded(limit).into());
+                }
+            }
+            self.printed.set(printed);
+            self.sender
+                .send(line.to_owned())
+                .map_err(|_| PrintStreamError::Disconnected)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use gazebo::prelude::*;
+
+    use super::*;
+    use crate::{
+        environment::{Globals, Module},
+        eval::Evaluator,
+        syntax::{AstModule, Dialect},
+    };
+
+    fn run(code: &str, limit: Option<usize>) -> (anyhow::Result<()>, Vec<String>) {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let lines2 = lines.dupe();
+        let res = PrintStream::consume(
+            1,
+            limit,
+            move |line| lines2.lock().unwrap().push(line),
+            |stream| {
+                let module = Module::new();
+                let mut eval = Evaluator::new(&module);
+                eval.set_print_handler(stream);
+                let ast = AstModule::parse("test.star", code.to_owned(), &Dialect::Standard)?;
+                eval.eval_module(ast, &Globals::standard())?;
+                Ok(())
+            },
+        );
+        let lines = lines.lock().unwrap().clone();
+        (res, lines)
+    }
+
+    #[test]
+    fn test_print_stream() {
+        let (res, lines) = run("[print(i) for i in range(100)]\nprint('a\\nb')", None);
+        res.unwrap();
+        assert_eq!(lines.len(), 102);
+        assert_eq!(lines[99], "99");
+        assert_eq!(&lines[100..], &["a", "b"]);
+    }
+
+    #[test]
+    fn test_print_stream_limit() {
+        let (res, lines) = run("[print('x' * 10) for i in range(100)]", Some(50));
+        assert!(format!("{:#}", res.unwrap_err()).contains("limit of 50 bytes"));
+        assert_eq!(lines, vec!["xxxxxxxxxx".to_owned(); 4]);
+    }
+}
@@ -0,0 +1,122 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::str::FromStr;
+
+use derive_more::Display;
+use gazebo::prelude::*;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+enum ProfileModeError {
+    #[error("Unknown profile mode `{0}`, expected one of {}", ProfileMode::ALL.map(|x| format!("`{}`", x)).join(", "))]
+    Unknown(String),
+}
+
+/// A kind of profile, chosen at runtime with
+/// [`Evaluator::enable_profiling`](crate::eval::Evaluator::enable_profiling), and written with
+/// [`Evaluator::write_profile`](crate::eval::Evaluator::write_profile). Parses from, and
+/// displays as, the names used by command line tools, e.g. `heap` or `time-flame`.
+#[derive(Debug, Clone, Copy, Dupe, PartialEq, Eq, Hash, Display)]
+pub enum ProfileMode {
+    /// Time and allocations per function, as a `.csv` file, see
+    /// [`enable_heap_profile`](crate::eval::Evaluator::enable_heap_profile).
+    #[display(fmt = "heap")]
+    Heap,
+    /// Allocations per call stack, as input to `flamegraph.pl`.
+    #[display(fmt = "heap-flame")]
+    HeapFlame,
+    /// Time per statement, as a `.csv` file, see
+    /// [`enable_stmt_profile`](crate::eval::Evaluator::enable_stmt_profile).
+    #[display(fmt = "stmt")]
+    Statement,
+    /// Time per call stack, as input to `flamegraph.pl`, see
+    /// [`enable_flame_profile`](crate::eval::Evaluator::enable_flame_profile).
+    #[display(fmt = "time-flame")]
+    TimeFlame,
+    /// Bytecode instructions executed, as a `.csv` file, see
+    /// [`enable_bytecode_profile`](crate::eval::Evaluator::enable_bytecode_profile).
+    #[display(fmt = "bytecode")]
+    Bytecode,
+    /// Pairs of consecutive bytecode instructions executed, as a `.csv` file, see
+    /// [`enable_bytecode_pairs_profile`](crate::eval::Evaluator::enable_bytecode_pairs_profile).
+    #[display(fmt = "bytecode-pairs")]
+    BytecodePairs,
+}
+
+impl ProfileMode {
+    /// Every profile mode.
+    pub const ALL: [ProfileMode; 6] = [
+        ProfileMode::Heap,
+        ProfileMode::HeapFlame,
+        ProfileMode::Statement,
+        ProfileMode::TimeFlame,
+        ProfileMode::Bytecode,
+        ProfileMode::BytecodePairs,
+    ];
+}
+
+impl FromStr for ProfileMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|x| x.to_string() == s)
+            .ok_or_else(|| ProfileModeError::Unknown(s.to_owned()).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{
+        environment::{Globals, Module},
+        eval::Evaluator,
+        syntax::{AstModule, Dialect},
+    };
+
+    #[test]
+    fn test_profile_mode_parse() {
+        for mode in ProfileMode::ALL {
+            assert_eq!(mode.to_string().parse::<ProfileMode>().unwrap(), mode);
+        }
+        assert!("cpu".parse::<ProfileMode>().is_err());
+    }
+
+    #[test]
+    fn test_write_profile() {
+        let dir = std::env::temp_dir();
+        for mode in ProfileMode::ALL {
+            let module = Module::new();
+            let mut eval = Evaluator::new(&module);
+            assert!(eval.write_profile(dir.join("unused")).is_err());
+            eval.enable_profiling(mode);
+            let program = "def f(x):\n  return [x]\nf(1)";
+            let ast = AstModule::parse("prof.star", program.to_owned(), &Dialect::Standard);
+            eval.eval_module(ast.unwrap(), &Globals::standard())
+                .unwrap();
+            let file = dir.join(format!("starlark-{}-{}.prof", mode, std::process::id()));
+            eval.write_profile(&file).unwrap();
+            // Removing fails if the profile wasn't written.
+            fs::remove_file(&file).unwrap();
+        }
+    }
+}
@@ -0,0 +1,316 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Record where values were allocated, for debugging.
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::{self, Display},
+    sync::Arc,
+};
+
+use derive_more::Display;
+use gazebo::{any::AnyLifetime, prelude::*};
+
+use crate::{
+    codemap::{FileSpan, Span},
+    errors::Frame,
+    eval::runtime::call_stack::CallStack,
+    values::{FreezeError, Heap, SimpleValue, StarlarkValue, Value, ValueLike, WeakValue},
+};
+
+/// Where a [`Value`] was allocated, as recorded when
+/// [`Evaluator::enable_provenance`](crate::eval::Evaluator::enable_provenance) has been called.
+#[derive(Debug, Clone)]
+pub struct ValueProvenance {
+    /// The statement that was executing when the value was allocated.
+    pub location: FileSpan,
+    /// The call-stack at the time of the allocation. Shared between the statements
+    /// of a single call.
+    pub call_stack: Arc<[Frame]>,
+}
+
+impl Display for ValueProvenance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.location)?;
+        for x in self.call_stack.iter().rev() {
+            write!(f, "\n* {}", x)?;
+        }
+        Ok(())
+    }
+}
+
+/// Allocated on the heap before each statement, so that walking the heap in order
+/// tells us which statement each value was allocated by. We need one in both the
+/// drop and non-drop arenas, see `HeapProfile`. This is the one in the drop arena,
+/// and holds the provenance itself, so it can still be found when
+/// [`Module::freeze`](crate::environment::Module::freeze) fails, after the evaluator has gone.
+#[derive(Debug, Display, AnyLifetime)]
+#[display(fmt = "ProvenanceRecord")]
+struct ProvenanceRecord(ValueProvenance);
+
+impl SimpleValue for ProvenanceRecord {}
+
+impl<'v> StarlarkValue<'v> for ProvenanceRecord {
+    starlark_type!("provenance_record");
+}
+
+/// The marker in the non-drop arena, with the index of the [`ProvenanceRecord`]
+/// allocated with it.
+#[derive(Debug, Display, AnyLifetime)]
+#[display(fmt = "ProvenanceMarker")]
+struct ProvenanceMarker(usize);
+
+impl SimpleValue for ProvenanceMarker {}
+
+impl<'v> StarlarkValue<'v> for ProvenanceMarker {
+    starlark_type!("provenance_marker");
+}
+
+pub(crate) struct Provenance<'v> {
+    enabled: bool,
+    records: usize,
+    /// The frames of the last record, as function, file and call site, so the
+    /// call-stack is only resolved again when they change.
+    frames: Vec<(usize, usize, Span)>,
+    call_stack: Arc<[Frame]>,
+    /// The provenance of the values which survived a garbage collection, which drops the
+    /// records the heap is walked for. The weak references follow the values as they move.
+    survivors: Vec<(WeakValue<'v>, ValueProvenance)>,
+    /// The provenance of each value on the heap, by [`Value::ptr_value`], built by walking
+    /// the heap on the first lookup after it grew, to the size recorded here, or after
+    /// a garbage collection.
+    index: RefCell<(Option<usize>, HashMap<usize, ValueProvenance>)>,
+}
+
+impl<'v> Provenance<'v> {
+    pub(crate) fn new() -> Self {
+        Self {
+            enabled: false,
+            records: 0,
+            frames: Vec::new(),
+            call_stack: Arc::from(Vec::new()),
+            survivors: Vec::new(),
+            index: RefCell::new((None, HashMap::new())),
+        }
+    }
+
+    pub(crate) fn enable(&mut self) {
+        self.enabled = true;
+    }
+
+    pub(crate) fn record(&mut self, location: FileSpan, call_stack: &CallStack<'v>, heap: &Heap) {
+        if self.enabled {
+            if !call_stack.frame_keys().eq(self.frames.iter().copied()) {
+                self.frames = call_stack.frame_keys().collect();
+                self.call_stack = call_stack.to_diagnostic_frames().into();
+            }
+            heap.alloc_simple(ProvenanceRecord(ValueProvenance {
+                location,
+                call_stack: self.call_stack.dupe(),
+            }));
+            heap.alloc_simple(ProvenanceMarker(self.records));
+            self.records += 1;
+        }
+    }
+
+    pub(crate) fn lookup(&self, value: Value<'v>, heap: &'v Heap) -> Option<ValueProvenance> {
+        if !self.enabled || value.unpack_frozen().is_some() {
+            return None;
+        }
+        let mut index = self.index.borrow_mut();
+        // Between garbage collections the heap only grows, so it has the same values if
+        // it has the same size.
+        let allocated = heap.allocated_bytes();
+        if index.0 != Some(allocated) {
+            let mut values = HashMap::new();
+            for (x, provenance) in &self.survivors {
+                if let Some(x) = x.get() {
+                    values.insert(x.ptr_value(), provenance.clone());
+                }
+            }
+            for_each_provenance(heap, |_, x, provenance| {
+                if let Some(x) = x {
+                    values.insert(x.ptr_value(), provenance.clone());
+                }
+            });
+            *index = (Some(allocated), values);
+        }
+        index.1.get(&value.ptr_value()).cloned()
+    }
+
+    /// Where each value on the heap was allocated, by its address.
+    pub(crate) fn locations(&self, heap: &Heap) -> HashMap<usize, String> {
+        let mut res = HashMap::new();
+        if self.enabled {
+            // Format each record once, not once per value
+            let mut locations: HashMap<*const ValueProvenance, String> = HashMap::new();
+            for (x, provenance) in &self.survivors {
+                if let Some(address) = x.get().and_then(|x| x.0.unpack_ptr()) {
+                    let location = locations
+                        .entry(provenance as *const _)
+                        .or_insert_with(|| provenance.location.to_string());
+                    res.insert(address as *const _ as usize, location.clone());
+                }
+            }
+            for_each_provenance(heap, |address, x, provenance| {
+                if x.is_some() {
+                    let location = locations
+                        .entry(provenance as *const _)
+                        .or_insert_with(|| provenance.location.to_string());
+                    res.insert(address, location.clone());
+                }
+            });
+        }
+        res
+    }
+
+    /// Called before garbage collection, which drops the records, to keep the provenance
+    /// of the values which survive it.
+    pub(crate) fn before_gc(&mut self, heap: &'v Heap) {
+        if self.enabled {
+            let survivors = &mut self.survivors;
+            for_each_provenance(heap, |_, x, provenance| {
+                if let Some(x) = x {
+                    survivors.push((heap.weak(x), provenance.clone()));
+                }
+            });
+        }
+    }
+
+    /// Called after garbage collection, to forget the values which didn't survive it.
+    pub(crate) fn after_gc(&mut self) {
+        if self.enabled {
+            self.survivors.retain(|(x, _)| x.is_alive());
+            self.records = 0;
+            self.frames.clear();
+            *self.index.get_mut() = (None, HashMap::new());
+        }
+    }
+}
+
+/// Call `f` with the address, value and provenance of each value on `heap` allocated
+/// after a record, in a single walk over the heap. The value is [`None`] if it has been
+/// overwritten, e.g. by freezing.
+fn for_each_provenance<'v>(
+    heap: &'v Heap,
+    mut f: impl FnMut(usize, Option<Value<'v>>, &'v ValueProvenance),
+) {
+    // The drop arena comes first, so we have every record before the first marker
+    let mut records = Vec::new();
+    let mut index = None;
+    heap.for_each_ordered_address(|address, x| {
+        if let Some(record) = x.and_then(|x| x.downcast_ref::<ProvenanceRecord>()) {
+            records.push(&record.0);
+            index = Some(records.len() - 1);
+        } else if let Some(marker) = x.and_then(|x| x.downcast_ref::<ProvenanceMarker>()) {
+            index = Some(marker.0);
+        } else if let Some(provenance) = index.and_then(|i| records.get(i)) {
+            f(address, x, provenance);
+        }
+    });
+}
+
+/// If `error` is a [`FreezeError`], add the provenance of the value which couldn't be frozen,
+/// if it was recorded. Freezing overwrites values, so this must look at the address.
+pub(crate) fn freeze_error_provenance(error: anyhow::Error, heap: &Heap) -> anyhow::Error {
+    match error.downcast::<FreezeError>() {
+        Ok(mut e) => {
+            let address = e.address();
+            let mut res = None;
+            for_each_provenance(heap, |x, _, provenance| {
+                if res.is_none() && x == address {
+                    res = Some(provenance.clone());
+                }
+            });
+            if let Some(provenance) = res {
+                e.set_provenance(provenance);
+            }
+            e.into()
+        }
+        Err(e) => e,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        environment::{Globals, Module},
+        eval::Evaluator,
+        syntax::{AstModule, Dialect},
+        values::Value,
+    };
+
+    #[test]
+    fn test_value_provenance() {
+        let ast = AstModule::parse(
+            "x.star",
+            "\
+def f():
+    return [1]
+x = {1: 2}
+y = f()
+z = str(x)
+"
+            .to_owned(),
+            &Dialect::Extended,
+        )
+        .unwrap();
+
+        let globals = Globals::standard();
+        let module = Module::new();
+        let mut eval = Evaluator::new(&module);
+        eval.enable_provenance();
+        eval.eval_module(ast, &globals).unwrap();
+
+        let provenance = |name| {
+            let p = eval.value_provenance(module.get(name).unwrap()).unwrap();
+            (p.location.resolve_span().begin_line, p.call_stack.len())
+        };
+        // from drop heap
+        assert_eq!(provenance("x"), (2, 0));
+        assert_eq!(provenance("y"), (1, 1));
+        // from non-drop heap
+        assert_eq!(provenance("z"), (4, 0));
+        assert!(eval.value_provenance(Value::new_int(1)).is_none());
+    }
+
+    #[test]
+    fn test_value_provenance_after_gc() {
+        let parse =
+            |code: &str| AstModule::parse("x.star", code.to_owned(), &Dialect::Extended).unwrap();
+        let globals = Globals::standard();
+        let module = Module::new();
+        let mut eval = Evaluator::new(&module);
+        eval.enable_provenance();
+        eval.eval_module(parse("x = {1: 2}\ny = [str(x)]"), &globals)
+            .unwrap();
+        unsafe { eval.garbage_collect() };
+        eval.eval_module(parse("\n\nz = [1]"), &globals).unwrap();
+
+        let line = |name| {
+            let p = eval.value_provenance(module.get(name).unwrap()).unwrap();
+            p.location.resolve_span().begin_line
+        };
+        // The values which survived the collection keep their provenance.
+        assert_eq!(line("x"), 0);
+        assert_eq!(line("y"), 1);
+        // Values allocated after it get theirs as before.
+        assert_eq!(line("z"), 2);
+    }
+}
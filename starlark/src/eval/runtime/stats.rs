@@ -0,0 +1,226 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Count what an evaluation did, for [`Evaluator::stats`](crate::eval::Evaluator::stats).
+
+use std::{
+    cmp,
+    collections::HashMap,
+    mem,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    eval::{Def, FrozenDef},
+    values::{Heap, Value},
+};
+
+/// Statistics about the evaluations done by an [`Evaluator`](crate::eval::Evaluator),
+/// see [`Evaluator::stats`](crate::eval::Evaluator::stats).
+#[derive(Debug, Clone, Default)]
+pub struct EvalStats {
+    /// Statements executed, counted like
+    /// [`set_max_steps`](crate::eval::Evaluator::set_max_steps).
+    pub statements: u64,
+    /// Calls to functions defined in Starlark, with `def` or `lambda`.
+    pub starlark_calls: u64,
+    /// Calls to functions implemented in Rust.
+    pub native_calls: u64,
+    /// For each type, the number of values currently on the heap and their total size in bytes.
+    pub allocations: HashMap<String, (usize, usize)>,
+    /// The most bytes allocated on the heap at any point, see
+    /// [`Heap::peak_allocated_bytes`].
+    pub peak_heap_bytes: usize,
+    /// Time spent evaluating Starlark code, excluding the time in native functions.
+    pub starlark_time: Duration,
+    /// Time spent in native functions, excluding the time in any Starlark functions they call.
+    pub native_time: Duration,
+    /// Garbage collections performed.
+    pub gc_count: u64,
+    /// Time spent collecting garbage, which is included in neither `starlark_time`
+    /// nor `native_time`.
+    pub gc_time: Duration,
+    /// The longest a single garbage collection took.
+    pub max_gc_pause: Duration,
+    /// The longest a single minor garbage collection took, see
+    /// [`Evaluator::set_gc_pacing`](crate::eval::Evaluator::set_gc_pacing).
+    pub max_minor_gc_pause: Duration,
+}
+
+// When stats are not enabled, we want this to be small and cheap
+pub(crate) struct Stats(Option<Box<StatsData>>);
+
+struct StatsData {
+    stats: EvalStats,
+    // Whether time is currently being spent in native code.
+    native: bool,
+    // When the current stretch of native or Starlark time started, if evaluating.
+    since: Option<Instant>,
+}
+
+impl Stats {
+    pub(crate) fn new() -> Self {
+        Self(None)
+    }
+
+    pub(crate) fn enable(&mut self) {
+        self.0 = Some(box StatsData {
+            stats: EvalStats::default(),
+            native: false,
+            since: None,
+        })
+    }
+
+    pub(crate) fn enabled(&self) -> bool {
+        self.0.is_some()
+    }
+
+    pub(crate) fn before_stmt(&mut self) {
+        if let Some(data) = &mut self.0 {
+            data.stats.statements += 1;
+        }
+    }
+
+    // Add the time since the last switch to whichever of native or Starlark code was running,
+    // then record that `native` code is now running, returning what was running before.
+    fn switch(data: &mut StatsData, native: bool) -> bool {
+        let now = Instant::now();
+        if let Some(since) = data.since {
+            let elapsed = now - since;
+            if data.native {
+                data.stats.native_time += elapsed;
+            } else {
+                data.stats.starlark_time += elapsed;
+            }
+        }
+        data.since = Some(now);
+        mem::replace(&mut data.native, native)
+    }
+
+    /// Start timing a module evaluation.
+    pub(crate) fn start(&mut self) {
+        if let Some(data) = &mut self.0 {
+            data.native = false;
+            data.since = Some(Instant::now());
+        }
+    }
+
+    /// Stop timing a module evaluation.
+    pub(crate) fn stop(&mut self) {
+        if let Some(data) = &mut self.0 {
+            Self::switch(data, false);
+            data.since = None;
+        }
+    }
+
+    /// Record a call to `function`, returning what was running before, to pass to
+    /// [`exit`](Stats::exit).
+    pub(crate) fn enter(&mut self, function: Value) -> bool {
+        match &mut self.0 {
+            None => false,
+            Some(data) => {
+                // Functions defined in Starlark are `Def` while the module is being evaluated,
+                // and `FrozenDef` once it has been frozen.
+                let native = function.downcast_ref::<Def>().is_none()
+                    && function.downcast_ref::<FrozenDef>().is_none();
+                if native {
+                    data.stats.native_calls += 1;
+                } else {
+                    data.stats.starlark_calls += 1;
+                }
+                Self::switch(data, native)
+            }
+        }
+    }
+
+    pub(crate) fn exit(&mut self, native: bool) {
+        if let Some(data) = &mut self.0 {
+            Self::switch(data, native);
+        }
+    }
+
+    /// Record a garbage collection which took `elapsed`, and just finished.
+    pub(crate) fn gc(&mut self, elapsed: Duration, minor: bool) {
+        if let Some(data) = &mut self.0 {
+            data.stats.gc_count += 1;
+            data.stats.gc_time += elapsed;
+            data.stats.max_gc_pause = cmp::max(data.stats.max_gc_pause, elapsed);
+            if minor {
+                data.stats.max_minor_gc_pause = cmp::max(data.stats.max_minor_gc_pause, elapsed);
+            }
+            // Don't count the collection as evaluation time.
+            if let Some(since) = &mut data.since {
+                *since += elapsed;
+            }
+        }
+    }
+
+    // None = not applicable because not enabled
+    pub(crate) fn stats(&self, heap: &Heap) -> Option<EvalStats> {
+        let data = self.0.as_ref()?;
+        let mut stats = data.stats.clone();
+        if let Some(since) = data.since {
+            // Called from within an evaluation, e.g. by a native function.
+            let elapsed = since.elapsed();
+            if data.native {
+                stats.native_time += elapsed;
+            } else {
+                stats.starlark_time += elapsed;
+            }
+        }
+        stats.allocations = heap.allocated_summary().summary;
+        stats.peak_heap_bytes = heap.peak_allocated_bytes();
+        Some(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        environment::{Globals, Module},
+        eval::Evaluator,
+        syntax::{AstModule, Dialect},
+    };
+
+    #[test]
+    fn test_stats() {
+        let module = Module::new();
+        let globals = Globals::standard();
+        let mut eval = Evaluator::new(&module);
+        assert!(eval.stats().is_err());
+        eval.enable_stats();
+        let program = "\
+def f(x):
+    return str(x)
+xs = [f(x) for x in range(10)]
+ys = sorted(xs, key = f)
+";
+        eval.eval_module(
+            AstModule::parse("stats.star", program.to_owned(), &Dialect::Standard).unwrap(),
+            &globals,
+        )
+        .unwrap();
+        let stats = eval.stats().unwrap();
+        // The `def`, the two assignments and the `return` run by each call to `f`.
+        assert!(stats.statements >= 3 + 20, "{:?}", stats);
+        assert_eq!(stats.starlark_calls, 20);
+        // `range`, `sorted` and the calls to `str`.
+        assert!(stats.native_calls >= 22, "{:?}", stats);
+        assert!(stats.peak_heap_bytes >= module.heap().allocated_bytes());
+        assert!(stats.allocations.contains_key("list"));
+    }
+}
@@ -0,0 +1,331 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Evaluate one statement at a time, under the control of the host.
+//!
+//! The interpreter can't suspend part way through a module, so a stepped evaluation runs on a
+//! thread of its own, which waits before each statement until the [`Stepper`] lets it continue.
+//! Values can't be sent between threads, so while it waits the host inspects the evaluation by
+//! sending functions to run on the evaluation thread.
+
+use std::{
+    any::Any,
+    cell::RefCell,
+    panic,
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, JoinHandle},
+};
+
+use gazebo::prelude::*;
+use thiserror::Error;
+
+use crate::{
+    codemap::{FileSpan, Span},
+    eval::{CancellationHandle, Evaluator},
+};
+
+type Inspect = Box<dyn for<'v, 'a> FnOnce(&mut Evaluator<'v, 'a>) -> Box<dyn Any + Send> + Send>;
+
+#[derive(Debug, Error)]
+enum StepperError {
+    #[error("The stepped evaluation is not paused before a statement")]
+    NotPaused,
+    #[error("The stepped evaluation has already finished")]
+    Finished,
+}
+
+/// State shared between a [`Stepper`] and the thread running its evaluation.
+#[derive(Default)]
+struct Shared {
+    state: Mutex<State>,
+    // Notified whenever `State` changes.
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    // Where the evaluation is paused, before running the statement.
+    paused: Option<FileSpan>,
+    // Set by the `Stepper` to let the paused evaluation run the statement.
+    resume: bool,
+    // A function to run on the paused evaluation thread.
+    inspect: Option<Inspect>,
+    // The result of the last `inspect`, waiting to be taken by the `Stepper`.
+    result: Option<Box<dyn Any + Send>>,
+    // The evaluation thread has finished.
+    finished: bool,
+    // The `Stepper` was dropped before the evaluation finished.
+    abandoned: bool,
+    // Cancels the evaluation if the `Stepper` is dropped.
+    cancellation: Option<CancellationHandle>,
+}
+
+thread_local! {
+    // Set on the thread running a stepped evaluation.
+    static STEPPER: RefCell<Option<Arc<Shared>>> = RefCell::new(None);
+}
+
+// Marks the evaluation as finished when the thread ends, even if it panics.
+struct Finish(Arc<Shared>);
+
+impl Drop for Finish {
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock().unwrap();
+        state.finished = true;
+        state.paused = None;
+        self.0.changed.notify_all();
+    }
+}
+
+/// What happened after a call to [`Stepper::step`].
+#[derive(Debug)]
+pub enum StepResult<R> {
+    /// The evaluation is paused before running the statement at this location.
+    Paused(FileSpan),
+    /// The evaluation finished, with the result of the function passed to [`eval_stepped`].
+    Finished(anyhow::Result<R>),
+}
+
+/// An evaluation running on its own thread one statement at a time, created by
+/// [`eval_stepped`]. Each [`Evaluator`] in the evaluation which called
+/// [`enable_stepping`](Evaluator::enable_stepping) pauses before every statement, until
+/// [`step`](Stepper::step) is called. While paused, [`inspect`](Stepper::inspect) runs code
+/// against the [`Evaluator`], e.g. to look at its
+/// [`local_variables`](Evaluator::local_variables) or [`call_stack`](Evaluator::call_stack).
+///
+/// If the `Stepper` is dropped before the evaluation finishes, the evaluation is cancelled.
+pub struct Stepper<R> {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<anyhow::Result<R>>>,
+}
+
+/// Run `f` on a new thread, pausing before each statement run by an
+/// [`Evaluator`] which calls [`enable_stepping`](Evaluator::enable_stepping), so the
+/// host can drive it with the returned [`Stepper`]. Nothing runs until the first call to
+/// [`step`](Stepper::step).
+/// The values of an evaluation can't be sent between threads, so `f` should create the
+/// [`Module`](crate::environment::Module) and return something which can, such as the
+/// [`FrozenModule`](crate::environment::FrozenModule).
+pub fn eval_stepped<R: Send + 'static>(
+    f: impl FnOnce() -> anyhow::Result<R> + Send + 'static,
+) -> Stepper<R> {
+    let shared = Arc::new(Shared::default());
+    let stepper = shared.dupe();
+    let thread = thread::spawn(move || {
+        let _finish = Finish(stepper.dupe());
+        // Wait for the first step before running anything.
+        {
+            let mut state = stepper.state.lock().unwrap();
+            while !state.resume && !state.abandoned {
+                state = stepper.changed.wait(state).unwrap();
+            }
+            state.resume = false;
+        }
+        STEPPER.with(|x| *x.borrow_mut() = Some(stepper));
+        f()
+    });
+    Stepper {
+        shared,
+        thread: Some(thread),
+    }
+}
+
+impl<R> Stepper<R> {
+    /// Run the evaluation until it is about to run the next statement, or finishes.
+    /// Once it has finished, further calls return an error.
+    pub fn step(&mut self) -> anyhow::Result<StepResult<R>> {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.finished && self.thread.is_none() {
+            return Err(StepperError::Finished.into());
+        }
+        state.paused = None;
+        state.resume = true;
+        self.shared.changed.notify_all();
+        loop {
+            if let Some(span) = &state.paused {
+                return Ok(StepResult::Paused(span.clone()));
+            }
+            if state.finished {
+                drop(state);
+                return Ok(StepResult::Finished(
+                    match self.thread.take().unwrap().join() {
+                        Ok(res) => res,
+                        Err(e) => panic::resume_unwind(e),
+                    },
+                ));
+            }
+            state = self.shared.changed.wait(state).unwrap();
+        }
+    }
+
+    /// Where the evaluation is paused, or `None` if it hasn't started or has finished.
+    pub fn location(&self) -> Option<FileSpan> {
+        self.shared.state.lock().unwrap().paused.clone()
+    }
+
+    /// Run `f` on the evaluation thread with the paused [`Evaluator`], returning its result.
+    /// Fails if the evaluation isn't paused. Any code `f` evaluates with the [`Evaluator`]
+    /// doesn't pause, see [`Evaluator::reentrant`].
+    pub fn inspect<T: Send + 'static>(
+        &mut self,
+        f: impl for<'v, 'a> FnOnce(&mut Evaluator<'v, 'a>) -> T + Send + 'static,
+    ) -> anyhow::Result<T> {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.paused.is_none() {
+            return Err(StepperError::NotPaused.into());
+        }
+        let f: Inspect = box move |eval: &mut Evaluator| -> Box<dyn Any + Send> { box f(eval) };
+        state.inspect = Some(f);
+        self.shared.changed.notify_all();
+        loop {
+            if let Some(res) = state.result.take() {
+                return Ok(*res.downcast::<T>().unwrap());
+            }
+            if state.finished {
+                // The evaluation thread panicked while running `f`.
+                return Err(StepperError::Finished.into());
+            }
+            state = self.shared.changed.wait(state).unwrap();
+        }
+    }
+}
+
+impl<R> Drop for Stepper<R> {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.abandoned = true;
+        if let Some(cancellation) = &state.cancellation {
+            cancellation.cancel();
+        }
+        self.shared.changed.notify_all();
+    }
+}
+
+/// Called on the evaluation thread by [`Evaluator::enable_stepping`], returning `false` if
+/// the thread isn't running a stepped evaluation.
+pub(crate) fn register(cancellation: &CancellationHandle) -> bool {
+    STEPPER.with(|x| match &*x.borrow() {
+        None => false,
+        Some(shared) => {
+            let mut state = shared.state.lock().unwrap();
+            if state.abandoned {
+                cancellation.cancel();
+            }
+            state.cancellation = Some(cancellation.dupe());
+            true
+        }
+    })
+}
+
+/// Wait before the statement at `span` until the [`Stepper`] resumes the evaluation, running
+/// any functions it sends in the meantime.
+pub(crate) fn pause(span: Span, eval: &mut Evaluator) {
+    let shared = match STEPPER.with(|x| x.borrow().as_ref().map(|x| x.dupe())) {
+        None => return,
+        Some(shared) => shared,
+    };
+    let mut state = shared.state.lock().unwrap();
+    state.paused = Some(eval.file_span(span));
+    shared.changed.notify_all();
+    loop {
+        if state.resume || state.abandoned {
+            state.resume = false;
+            return;
+        }
+        if let Some(f) = state.inspect.take() {
+            // Run without the lock, the function may take a while
+            drop(state);
+            let res = eval.reentrant(f);
+            state = shared.state.lock().unwrap();
+            state.result = Some(res);
+            shared.changed.notify_all();
+            continue;
+        }
+        state = shared.changed.wait(state).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        environment::{Globals, Module},
+        syntax::{AstModule, Dialect},
+    };
+
+    fn paused_line<R>(res: anyhow::Result<StepResult<R>>) -> usize {
+        match res.unwrap() {
+            StepResult::Paused(span) => span.resolve_span().begin_line,
+            StepResult::Finished(_) => panic!("Expected the evaluation to pause"),
+        }
+    }
+
+    fn stepped(program: &'static str) -> Stepper<i32> {
+        eval_stepped(move || {
+            let module = Module::new();
+            let mut eval = Evaluator::new(&module);
+            eval.enable_stepping();
+            let ast = AstModule::parse("step.star", program.to_owned(), &Dialect::Extended)?;
+            let res = eval.eval_module(ast, &Globals::standard())?;
+            Ok(res.unpack_int().unwrap())
+        })
+    }
+
+    fn variable(stepper: &mut Stepper<i32>, name: &'static str) -> Option<String> {
+        stepper
+            .inspect(move |eval| eval.local_variables().get(name).map(|x| x.to_repr()))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_step() {
+        let mut stepper = stepped("x = 1\ny = x + 1\ny * 2");
+        assert!(stepper.location().is_none());
+        assert!(stepper.inspect(|_| ()).is_err());
+        assert_eq!(paused_line(stepper.step()), 0);
+        assert_eq!(variable(&mut stepper, "x"), None);
+        assert_eq!(paused_line(stepper.step()), 1);
+        assert_eq!(variable(&mut stepper, "x").as_deref(), Some("1"));
+        assert_eq!(stepper.location().unwrap().resolve_span().begin_line, 1);
+        assert_eq!(paused_line(stepper.step()), 2);
+        assert_eq!(variable(&mut stepper, "y").as_deref(), Some("2"));
+        match stepper.step().unwrap() {
+            StepResult::Finished(res) => assert_eq!(res.unwrap(), 4),
+            StepResult::Paused(_) => panic!("Expected the evaluation to finish"),
+        }
+        assert!(stepper.step().is_err());
+    }
+
+    #[test]
+    fn test_step_into_function() {
+        let mut stepper = stepped("def f(x):\n  y = x * 2\n  return y\nf(3)");
+        // The `def`, then the call, then the body of `f`.
+        assert_eq!(paused_line(stepper.step()), 0);
+        assert_eq!(paused_line(stepper.step()), 3);
+        assert_eq!(paused_line(stepper.step()), 1);
+        assert_eq!(variable(&mut stepper, "x").as_deref(), Some("3"));
+        assert_eq!(stepper.inspect(|eval| eval.call_stack().len()).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_step_drop() {
+        let mut stepper = stepped("x = 1\nwhile True:\n  x += 1");
+        assert_eq!(paused_line(stepper.step()), 0);
+        // Dropping cancels the evaluation rather than leaving it running.
+        drop(stepper);
+    }
+}
@@ -40,12 +40,12 @@ pub(crate) struct StmtProfile(Option<Box<StmtProfileData>>);
 // somewhat delving into internal details.
 // Remains unique because we take a reference to the CodeMap.
 #[derive(Debug, Hash, PartialEq, Eq, Clone, Copy, Dupe)]
-struct FileId(*const crate::codemap::CodeMapData);
+pub(crate) struct FileId(*const crate::codemap::CodeMapData);
 
 impl FileId {
     const EMPTY: FileId = FileId(ptr::null());
 
-    fn new(codemap: &CodeMap) -> Self {
+    pub(crate) fn new(codemap: &CodeMap) -> Self {
         Self(Arc::as_ptr(codemap.get_ptr()))
     }
 }
@@ -17,7 +17,14 @@
 
 //! Basic expression tests.
 
-use crate::{assert, assert::Assert, environment::GlobalsBuilder, values::OwnedFrozenValue};
+use crate::{
+    assert,
+    assert::Assert,
+    environment::{Globals, GlobalsBuilder, Module},
+    eval::Evaluator,
+    syntax::{AstModule, Dialect},
+    values::OwnedFrozenValue,
+};
 
 #[test]
 fn arithmetic_test() {
@@ -28,6 +35,41 @@ fn arithmetic_test() {
     assert::is_true("(5 % 2 == 1)");
 }
 
+#[test]
+fn arithmetic_mixed_types_test() {
+    // The operands are parameters, so the operations aren't folded at compile time.
+    let ops = r#"
+def add(x, y): return x + y
+def sub(x, y): return x - y
+def mul(x, y): return x * y
+def lt(x, y): return x < y
+def ge(x, y): return x >= y
+"#;
+    assert::pass(&format!(
+        "{}{}",
+        ops,
+        r#"
+assert_eq(add(1, 2), 3)
+assert_eq(add(1, 2.5), 3.5)
+assert_eq(add("a", "b"), "ab")
+assert_eq(sub(1, 3), -2)
+assert_eq(sub(1.5, 1), 0.5)
+assert_eq(mul(3, 4), 12)
+assert_eq(mul(2, [1]), [1, 1])
+assert_eq(mul("ab", 2), "abab")
+assert_true(lt(1, 2))
+assert_true(lt(1, 1.5))
+assert_false(lt("b", "a"))
+assert_true(ge("b", "ab"))
+assert_true(ge(2.0, 2))
+assert_eq(add(2147483647, 1), 2147483648)
+assert_eq(sub(-2147483647, 2), -2147483649)
+assert_eq(mul(65536, 65536), 4294967296)
+"#
+    ));
+    assert::fail(&format!("{}lt(1, 'a')", ops), "not supported");
+}
+
 #[test]
 fn bitwise_test() {
     assert::all_true(
@@ -49,9 +91,9 @@ fn bitwise_test() {
 "#,
     );
 
-    // For now, we report negative shift amounts as integer overflow
-    assert::fail("1 << -13", "overflow");
-    assert::fail("1 >> -13", "overflow");
+    // Negative shift amounts are reported as in Go
+    assert::fail("1 << -13", "negative shift count: -13");
+    assert::fail("1 >> -13", "negative shift count: -13");
 }
 
 #[test]
@@ -224,3 +266,22 @@ x[y]
         "not hashable",
     );
 }
+
+#[test]
+fn test_eval_interactive() {
+    let module = Module::new();
+    let globals = Globals::standard();
+    let mut eval = Evaluator::new(&module);
+    let mut input = |code: &str| {
+        let ast = AstModule::parse("<repl>", code.to_owned(), &Dialect::Extended).unwrap();
+        eval.eval_interactive(ast, &globals)
+            .map(|x| x.map(|x| x.to_string()))
+    };
+    assert_eq!(input("x = 1").unwrap(), None);
+    assert_eq!(input("def f(y): return x + y").unwrap(), None);
+    assert_eq!(input("f(2)").unwrap().as_deref(), Some("3"));
+    assert_eq!(input("x = 10; x * 2").unwrap().as_deref(), Some("20"));
+    assert_eq!(input("if x:\n  x").unwrap(), None);
+    assert!(input("y = 5\nfail('bad')").is_err());
+    assert_eq!(input("[x, y]").unwrap().as_deref(), Some("[10, 5]"));
+}
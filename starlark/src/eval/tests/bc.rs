@@ -84,3 +84,58 @@ fn test_spec_exec_list() {
         "def test(): return list((10, 20))",
     )
 }
+
+#[test]
+fn test_call_frozen_def_named() {
+    let program = r#"
+def f(a, b = 1, *args, c, **kwargs):
+    return (a, b, args, c, kwargs)
+
+def test(x):
+    return f(x, c = 2)
+"#;
+    let mut a = Assert::new();
+    let def = a
+        .module("instrs.star", program)
+        .get("test")
+        .unwrap()
+        .downcast::<FrozenDef>()
+        .unwrap();
+    assert!(def.bc().instrs.opcodes().contains(&BcOpcode::CallFrozenDefNamed));
+}
+
+#[test]
+fn test_call_frozen_def_named_eval() {
+    let mut a = Assert::new();
+    a.module(
+        "f.star",
+        r#"
+def f(a, b = 1, *args, c, **kwargs):
+    return (a, b, args, c, kwargs)
+
+def resolved(x):
+    return f(x, c = 2)
+def resolved_all(x):
+    return f(c = 3, b = x, a = 4)
+def missing(x):
+    return f(x, b = 2)
+def extra(x):
+    return f(x, c = 2, d = 3)
+def repeated(x):
+    return f(x, a = 2, c = 3)
+"#,
+    );
+    a.pass(
+        r#"
+load("f.star", "resolved", "resolved_all", "extra")
+assert_eq(resolved(0), (0, 1, (), 2, {}))
+assert_eq(resolved_all(0), (4, 0, (), 3, {}))
+assert_eq(extra(0), (0, 1, (), 2, {"d": 3}))
+"#,
+    );
+    a.fail("load('f.star', 'missing')\nmissing(0)", "Missing parameter `c`");
+    a.fail(
+        "load('f.star', 'repeated')\nrepeated(0)",
+        "occurs both explicitly and in **kwargs",
+    );
+}
@@ -15,12 +15,16 @@
  * limitations under the License.
  */
 
-use std::cell::Cell;
+use std::{cell::Cell, thread, time::Duration};
+
+use gazebo::prelude::*;
 
 use crate::{
+    codemap::FileSpan,
     environment::{Globals, Module},
-    eval::Evaluator,
+    eval::{Arguments, CallHook, CancellationHandle, Evaluator},
     syntax::{AstModule, Dialect},
+    values::Value,
 };
 
 #[test]
@@ -44,3 +48,213 @@ f()            # 2
     evaluator.eval_module(ast, &globals).unwrap();
     assert_eq!(4, counter.get());
 }
+
+#[test]
+fn max_steps() {
+    let program = "\
+x = 1
+def f():
+  return x + 1
+f()
+";
+    let run = |max_steps, program: &str| {
+        let module = Module::new();
+        let mut evaluator = Evaluator::new(&module);
+        evaluator.set_max_steps(max_steps);
+        let ast = AstModule::parse("a.star", program.to_owned(), &Dialect::Extended).unwrap();
+        let res = evaluator.eval_module(ast, &Globals::standard()).map(|_| ());
+        (res, evaluator.steps())
+    };
+
+    let (res, steps) = run(4, program);
+    res.unwrap();
+    assert_eq!(4, steps);
+
+    let (res, steps) = run(3, program);
+    assert!(format!("{:#}", res.unwrap_err()).contains("exceeded the limit of 3 steps"));
+    assert_eq!(3, steps);
+
+    // Loops which do nothing are still counted
+    let (res, steps) = run(1000, "for x in range(1000000000): pass");
+    assert!(res.is_err());
+    assert_eq!(1000, steps);
+}
+
+#[test]
+fn timeout() {
+    let run = |timeout, program: &str| {
+        let module = Module::new();
+        let mut evaluator = Evaluator::new(&module);
+        evaluator.set_timeout(timeout);
+        let ast = AstModule::parse("a.star", program.to_owned(), &Dialect::Extended).unwrap();
+        evaluator.eval_module(ast, &Globals::standard()).map(|_| ())
+    };
+
+    run(Duration::from_secs(60), "for x in range(10): pass").unwrap();
+    let res = run(
+        Duration::from_millis(10),
+        "for x in range(1000000000): pass",
+    );
+    assert!(format!("{:#}", res.unwrap_err()).contains("exceeded its deadline"));
+}
+
+#[test]
+fn cancellation() {
+    let run = |handle: CancellationHandle, program: &str| {
+        let module = Module::new();
+        let mut evaluator = Evaluator::new(&module);
+        evaluator.set_cancellation_handle(handle);
+        let ast = AstModule::parse("a.star", program.to_owned(), &Dialect::Extended).unwrap();
+        evaluator.eval_module(ast, &Globals::standard()).map(|_| ())
+    };
+
+    let handle = CancellationHandle::new();
+    run(handle.dupe(), "for x in range(10): pass").unwrap();
+
+    // Cancelled from another thread during evaluation
+    let canceller = thread::spawn({
+        let handle = handle.dupe();
+        move || {
+            thread::sleep(Duration::from_millis(10));
+            handle.cancel()
+        }
+    });
+    let res = run(handle.dupe(), "for x in range(1000000000): pass");
+    assert!(format!("{:#}", res.unwrap_err()).contains("was cancelled"));
+    canceller.join().unwrap();
+
+    // Once cancelled, evaluation fails straight away
+    let res = run(handle, "x = 1");
+    assert!(format!("{:#}", res.unwrap_err()).contains("was cancelled"));
+}
+
+#[test]
+fn skip_before_stmt() {
+    let program = "\
+def lib(x):
+  y = x
+  return y
+def user():
+  return lib(1)
+user()
+";
+    let run = |skip: bool| {
+        let module = Module::new();
+        let mut evaluator = Evaluator::new(&module);
+        let counter = Cell::new(0);
+        let before_stmt = |_span, _eval: &mut Evaluator<'_, '_>| {
+            counter.set(counter.get() + 1);
+        };
+        let skip_lib = |file: &str, name: &str| file == "a.star" && name == "lib";
+        evaluator.before_stmt(&before_stmt);
+        if skip {
+            evaluator.skip_before_stmt(&skip_lib);
+        }
+        let ast = AstModule::parse("a.star", program.to_owned(), &Dialect::Extended).unwrap();
+        evaluator.eval_module(ast, &Globals::standard()).unwrap();
+        counter.get()
+    };
+    assert_eq!(6, run(false));
+    assert_eq!(4, run(true));
+}
+
+#[test]
+fn allocation_limit() {
+    let run = |program: &str| {
+        let module = Module::new();
+        module.heap().set_allocation_limit(1_000_000);
+        let mut evaluator = Evaluator::new(&module);
+        let ast = AstModule::parse("a.star", program.to_owned(), &Dialect::Extended).unwrap();
+        evaluator
+            .eval_module(ast, &Globals::standard())
+            .map_err(|e| format!("{:#}", e))
+            .map(|_| ())
+    };
+
+    run("x = [1] * 1000").unwrap();
+    // Checked before allocating
+    let res = run("x = [1] * 1000000000");
+    assert!(res.unwrap_err().contains("Allocation budget exceeded"));
+    // Checked between statements
+    let res = run("x = []\nfor i in range(1000000):\n  x.append(str(i))");
+    assert!(res.unwrap_err().contains("Allocation budget exceeded"));
+}
+
+#[test]
+fn remove_before_stmt() {
+    let module = Module::new();
+    let mut evaluator = Evaluator::new(&module);
+    let first = Cell::new(0);
+    let second = Cell::new(0);
+    let first_handle = Cell::new(None);
+    // Removes itself after the second statement
+    let remove_first = |_span, eval: &mut Evaluator<'_, '_>| {
+        first.set(first.get() + 1);
+        if first.get() == 2 {
+            assert!(eval.remove_before_stmt(first_handle.get().unwrap()));
+        }
+    };
+    let count_second = |_span, _eval: &mut Evaluator<'_, '_>| {
+        second.set(second.get() + 1);
+    };
+    first_handle.set(Some(evaluator.before_stmt(&remove_first)));
+    let second_handle = evaluator.before_stmt(&count_second);
+
+    let ast = AstModule::parse(
+        "a.star",
+        "x = 1\ny = 2\nz = 3\nw = 4".to_owned(),
+        &Dialect::Extended,
+    )
+    .unwrap();
+    evaluator.eval_module(ast, &Globals::standard()).unwrap();
+    assert_eq!(2, first.get());
+    // Still called after the first was removed
+    assert_eq!(4, second.get());
+    assert!(!evaluator.remove_before_stmt(first_handle.get().unwrap()));
+    assert!(evaluator.remove_before_stmt(second_handle));
+}
+
+#[test]
+fn reentrant() {
+    // Counts the calls made by the program.
+    struct Calls(Cell<usize>);
+
+    impl<'v> CallHook<'v> for Calls {
+        fn enter(
+            &self,
+            _function: Value<'v>,
+            _args: &Arguments<'v, '_>,
+            _location: Option<&FileSpan>,
+            _eval: &mut Evaluator<'v, '_>,
+        ) -> anyhow::Result<()> {
+            self.0.set(self.0.get() + 1);
+            Ok(())
+        }
+    }
+
+    let counter = Cell::new(0);
+    // Evaluating more statements doesn't call this function again, or the call hook.
+    let before_stmt = |_span, eval: &mut Evaluator<'_, '_>| {
+        counter.set(counter.get() + 1);
+        let ast = AstModule::parse("interactive", "str(1)".to_owned(), &Dialect::Extended).unwrap();
+        let res = eval.reentrant(|eval| eval.eval_statements(ast)).unwrap();
+        assert_eq!(res.unpack_str(), Some("1"));
+    };
+    let calls = Calls(Cell::new(0));
+    let module = Module::new();
+    let globals = Globals::standard();
+    let mut evaluator = Evaluator::new(&module);
+    evaluator.before_stmt(&before_stmt);
+    evaluator.add_call_hook(&calls);
+
+    let program = "\
+x = 1
+def f():
+  return x + 1
+f()
+";
+    let ast = AstModule::parse("a.star", program.to_owned(), &Dialect::Extended).unwrap();
+    evaluator.eval_module(ast, &globals).unwrap();
+    assert_eq!(4, counter.get());
+    assert_eq!(1, calls.0.get());
+}
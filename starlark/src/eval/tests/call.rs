@@ -17,7 +17,20 @@
 
 //! Test call expression and parameter binding.
 
-use crate::{assert, assert::Assert};
+use std::cell::RefCell;
+
+use anyhow::anyhow;
+
+use crate::{
+    assert,
+    assert::Assert,
+    codemap::FileSpan,
+    environment::{Globals, Module},
+    errors::ErrorKind,
+    eval::{Arguments, CallHook, Evaluator},
+    syntax::{AstModule, Dialect},
+    values::Value,
+};
 
 #[test]
 fn funcall_test() {
@@ -63,6 +76,10 @@ def rec6(): rec2()
     // Recursion limit
     assert::fail(&f("rec1()"), "recursion");
     assert::fail(&f("rec2()"), "recursion");
+    // The cycle is reported, starting from whichever function hit the limit,
+    // so contains every call in the cycle
+    assert::fail(&f("rec1()"), "`assert.bzl.rec1 -> assert.bzl.rec1`");
+    assert::fail(&f("rec2()"), "assert.bzl.rec6 -> assert.bzl.rec2");
     // multiple argument with the same name should not be allowed
     assert::fail("def f(a, a=2): pass", "duplicated parameter");
     // Invalid order of parameter
@@ -234,3 +251,136 @@ f(1)
         "Missing parameter `y`",
     );
 }
+
+#[test]
+fn test_max_call_depth() {
+    let run = |depth, n: i32| {
+        let module = Module::new();
+        let mut eval = Evaluator::new(&module);
+        eval.set_max_call_depth(depth);
+        assert_eq!(eval.max_call_depth(), depth);
+        let program = format!(
+            "def f(n):\n  if n > 0:\n    f(n - 1)\ndef g(n): f(n)\ng({})",
+            n
+        );
+        let ast = AstModule::parse("a.star", program, &Dialect::Extended).unwrap();
+        eval.eval_module(ast, &Globals::standard()).map(|_| ())
+    };
+
+    // The module, `g`, then `f` for each of 0 to `n`
+    run(6, 3).unwrap();
+    let err = run(6, 4).unwrap_err();
+    assert!(format!("{:#}", err).contains("recursion limit of 6 exceeded"));
+    assert_eq!(err.kind(), ErrorKind::Limit);
+    assert_eq!(err.call_stack().len(), 5);
+    assert!(err.call_stack()[0].name.contains('g'));
+
+    // Only the module can run
+    run(1, 0).unwrap_err();
+    let module = Module::new();
+    let mut eval = Evaluator::new(&module);
+    eval.set_max_call_depth(1);
+    let ast = AstModule::parse("a.star", "x = 1".to_owned(), &Dialect::Extended).unwrap();
+    eval.eval_module(ast, &Globals::standard()).unwrap();
+}
+
+#[test]
+#[should_panic(expected = "Can't limit the call stack to 0 frames")]
+fn test_max_call_depth_zero() {
+    let module = Module::new();
+    Evaluator::new(&module).set_max_call_depth(0);
+}
+
+#[test]
+fn test_call_hook() {
+    // Records each call, and refuses to call `fail`
+    #[derive(Default)]
+    struct Tracer(RefCell<Vec<String>>);
+
+    impl<'v> CallHook<'v> for Tracer {
+        fn enter(
+            &self,
+            function: Value<'v>,
+            args: &Arguments<'v, '_>,
+            location: Option<&FileSpan>,
+            _eval: &mut Evaluator<'v, '_>,
+        ) -> anyhow::Result<()> {
+            let location = location.map_or("native".to_owned(), |x| x.to_string());
+            self.0.borrow_mut().push(format!(
+                "enter {} {} {}",
+                function,
+                args.pos.len(),
+                location
+            ));
+            if function.to_str() == "fail" {
+                return Err(anyhow!("Calling `fail` is not allowed"));
+            }
+            Ok(())
+        }
+
+        fn exit(
+            &self,
+            function: Value<'v>,
+            _location: Option<&FileSpan>,
+            result: Result<Value<'v>, &anyhow::Error>,
+            _eval: &mut Evaluator<'v, '_>,
+        ) {
+            let result = result.map_or("error".to_owned(), |x| x.to_str());
+            self.0
+                .borrow_mut()
+                .push(format!("exit {} {}", function, result));
+        }
+    }
+
+    let tracer = Tracer::default();
+    let module = Module::new();
+    let mut eval = Evaluator::new(&module);
+    eval.add_call_hook(&tracer);
+    let program = "def f(x): return str(x)\nf([1, 2])\nsorted([[3]], key = f)\nfail('x')";
+    let ast = AstModule::parse("a.star", program.to_owned(), &Dialect::Extended).unwrap();
+    let err = eval.eval_module(ast, &Globals::standard()).unwrap_err();
+    assert!(format!("{:#}", err).contains("Calling `fail` is not allowed"));
+    assert_eq!(
+        tracer.0.into_inner(),
+        vec![
+            "enter a.star.f 1 a.star:2:1-10",
+            "enter str 1 a.star:1:18-24",
+            "exit str [1, 2]",
+            "exit a.star.f [1, 2]",
+            "enter sorted 1 a.star:3:1-23",
+            "enter a.star.f 1 native",
+            "enter str 1 a.star:1:18-24",
+            "exit str [3]",
+            "exit a.star.f [3]",
+            "exit sorted [[3]]",
+            "enter fail 1 a.star:4:1-10",
+        ]
+    );
+}
+
+#[test]
+fn test_tail_calls() {
+    let run = |tail_calls: bool, program: &str| {
+        let module = Module::new();
+        let mut eval = Evaluator::new(&module);
+        if tail_calls {
+            eval.enable_tail_calls();
+        }
+        let ast = AstModule::parse("a.star", program.to_owned(), &Dialect::Extended).unwrap();
+        eval.eval_module(ast, &Globals::standard())
+            .map(|x| x.to_string())
+    };
+
+    let total = "def total(n, acc):\n  if n == 0:\n    return acc\n  return total(n - 1, acc + n)\ntotal(1000, 0)";
+    assert!(run(false, total).is_err());
+    assert_eq!(run(true, total).unwrap(), "500500");
+
+    // Calls to other functions, or with named arguments, are made as usual.
+    let other = "def f(n): return g(n)\ndef g(n): return n if n > 1 else f(n = n + 1)\nf(0)";
+    assert_eq!(run(true, other).unwrap(), "2");
+
+    // The eliminated calls don't appear in the stack.
+    let fails = "def f(n, xs):\n  if n == 0:\n    return xs[1]\n  return f(n - 1, xs)\nf(100, [])";
+    let err = run(true, fails).unwrap_err();
+    assert_eq!(err.call_stack().len(), 1);
+}
@@ -0,0 +1,218 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use std::{cell::RefCell, collections::HashMap};
+
+use gazebo::prelude::*;
+
+use crate::{
+    environment::{FrozenModule, Globals, Module},
+    eval::{Checkpoint, Checkpointer, Evaluator, ReturnFileLoader},
+    syntax::{AstModule, Dialect},
+};
+
+/// Checkpoints before statement `at`, and records which statements it was asked about.
+struct Recorder {
+    at: usize,
+    asked: RefCell<Vec<usize>>,
+    saved: RefCell<Option<String>>,
+}
+
+impl Recorder {
+    fn new(at: usize) -> Self {
+        Self {
+            at,
+            asked: RefCell::new(Vec::new()),
+            saved: RefCell::new(None),
+        }
+    }
+}
+
+impl Checkpointer for Recorder {
+    fn want_checkpoint(&self, statement: usize) -> bool {
+        self.asked.borrow_mut().push(statement);
+        statement == self.at
+    }
+
+    fn checkpoint(&self, checkpoint: Checkpoint) -> anyhow::Result<()> {
+        *self.saved.borrow_mut() = Some(serde_json::to_string(&checkpoint)?);
+        Ok(())
+    }
+}
+
+fn eval(program: &str, checkpointer: &Recorder, resume: Option<Checkpoint>) -> anyhow::Result<()> {
+    let module = Module::new();
+    let mut eval = Evaluator::new(&module);
+    eval.set_checkpointer(checkpointer);
+    if let Some(resume) = resume {
+        eval.resume_from(resume);
+    }
+    let ast = AstModule::parse("a.star", program.to_owned(), &Dialect::Extended)?;
+    eval.eval_module(ast, &Globals::extended())?;
+    Ok(())
+}
+
+#[test]
+fn test_checkpoint_resume() {
+    let program = r#"
+n = 0
+def inc(x):
+    return x + 1
+a = inc(1)
+b = [a, (a, "s"), {"k": None, 1: 2.5}]
+c = inc(a)
+assert_eq(b, [2, (2, "s"), {"k": None, 1: 2.5}])
+assert_eq(c, 3)
+"#;
+    let first = Recorder::new(4);
+    eval(program, &first, None).unwrap();
+    assert_eq!(*first.asked.borrow(), vec![0, 1, 2, 3, 4, 5, 6]);
+    let saved = first.saved.borrow().clone().unwrap();
+    let checkpoint: Checkpoint = serde_json::from_str(&saved).unwrap();
+    assert_eq!(checkpoint.statement, 4);
+    assert_eq!(
+        checkpoint.variables.map(|x| x.0.as_str()),
+        vec!["n", "a", "b"]
+    );
+
+    // Resuming skips straight to statement 4, with `inc` defined again
+    let second = Recorder::new(usize::MAX);
+    eval(program, &second, Some(checkpoint)).unwrap();
+    assert_eq!(*second.asked.borrow(), vec![4, 5, 6]);
+}
+
+#[test]
+fn test_checkpoint_unsupported() {
+    let program = "f = lambda: 1\nx = f()";
+    let err = eval(program, &Recorder::new(1), None).unwrap_err();
+    assert!(
+        format!("{:#}", err).contains("Can't checkpoint variable `f`, values of type `function`"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_checkpoint_resume_shared() {
+    let program = r#"
+D = [1]
+def f(x = D):
+    return x
+a = []
+b = a
+c = (1 << 100, b"xy", set([1, 2]), a)
+a.append(1)
+assert_eq(b, [1])
+assert_eq(c, (1 << 100, b"xy", set([1, 2]), [1]))
+assert_eq(f(), [1])
+D.append(2)
+assert_eq(f(), [1, 2])
+"#;
+    let first = Recorder::new(5);
+    eval(program, &first, None).unwrap();
+    let saved = first.saved.borrow().clone().unwrap();
+    let checkpoint: Checkpoint = serde_json::from_str(&saved).unwrap();
+
+    // `f` is defined again with the restored `D` as its default, and `a` and `b` are still
+    // the same list
+    let second = Recorder::new(usize::MAX);
+    eval(program, &second, Some(checkpoint)).unwrap();
+    assert_eq!(*second.asked.borrow(), vec![5, 6, 7, 8, 9, 10]);
+}
+
+#[test]
+fn test_checkpoint_resume_rebound_def() {
+    let program = r#"
+def f():
+    return "def"
+f = 1
+x = f + 1
+assert_eq(x, 2)
+"#;
+    let first = Recorder::new(2);
+    eval(program, &first, None).unwrap();
+    let saved = first.saved.borrow().clone().unwrap();
+    let checkpoint: Checkpoint = serde_json::from_str(&saved).unwrap();
+
+    // The `def` isn't run again, as it would overwrite the restored `f = 1`
+    let second = Recorder::new(usize::MAX);
+    eval(program, &second, Some(checkpoint)).unwrap();
+    assert_eq!(*second.asked.borrow(), vec![2, 3]);
+}
+
+#[test]
+fn test_checkpoint_rebound_lambda() {
+    // Running the `def` again wouldn't restore `f`, so the checkpoint can't be taken
+    let program = "def f():\n    return 1\nf = lambda: 2\nx = f()";
+    let err = eval(program, &Recorder::new(2), None).unwrap_err();
+    assert!(
+        format!("{:#}", err).contains("Can't checkpoint variable `f`, values of type `function`"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_checkpoint_resume_frozen() {
+    let lib = Module::new();
+    let xs = lib.heap().alloc(vec![1]);
+    lib.set("xs", xs);
+    let lib = lib.freeze().unwrap();
+    let modules: HashMap<&str, &FrozenModule> = hashmap! {"lib.star" => &lib};
+    let loader = ReturnFileLoader { modules: &modules };
+    let eval = |checkpointer: &Recorder, resume: Option<Checkpoint>| {
+        let module = Module::new();
+        let mut eval = Evaluator::new(&module);
+        eval.set_loader(&loader);
+        eval.set_checkpointer(checkpointer);
+        if let Some(resume) = resume {
+            eval.resume_from(resume);
+        }
+        let program = "load('lib.star', 'xs')\nys = xs\nn = len(ys)\nys.append(2)";
+        let ast = AstModule::parse("a.star", program.to_owned(), &Dialect::Extended).unwrap();
+        eval.eval_module(ast, &Globals::extended())
+            .map(|_| ())
+            .unwrap_err()
+    };
+
+    let first = Recorder::new(2);
+    let err = eval(&first, None);
+    assert!(format!("{:#}", err).contains("Immutable"), "{}", err);
+    let saved = first.saved.borrow().clone().unwrap();
+    let checkpoint: Checkpoint = serde_json::from_str(&saved).unwrap();
+
+    // `ys` is restored frozen, so mutating it still fails
+    let second = Recorder::new(usize::MAX);
+    let err = eval(&second, Some(checkpoint));
+    assert_eq!(*second.asked.borrow(), vec![2, 3]);
+    assert!(format!("{:#}", err).contains("Immutable"), "{}", err);
+}
+
+#[test]
+fn test_checkpoint_changed_code() {
+    let first = Recorder::new(1);
+    eval("x = 1\ny = x", &first, None).unwrap();
+    let saved = first.saved.borrow().clone().unwrap();
+    let checkpoint: Checkpoint = serde_json::from_str(&saved).unwrap();
+
+    let err = eval("x = 2\ny = x", &Recorder::new(usize::MAX), Some(checkpoint)).unwrap_err();
+    assert!(
+        format!("{:#}", err).contains("Checkpoint was taken with different code or globals"),
+        "{}",
+        err
+    );
+}
@@ -17,7 +17,14 @@
 
 //! Test dict and list comprehension.
 
-use crate::assert;
+use std::sync::{Arc, Mutex};
+
+use crate::{
+    assert,
+    assert::Assert,
+    eval::{Evaluator, FrozenDef},
+    values::{function::BatchedFunction, Value},
+};
 
 // comprehensions should work whether they are at the root, or under a def
 // but these are actually quite different locations semantically, so test both
@@ -113,3 +120,124 @@ assert_eq(res, [1,2])
         "variable `x` referenced before assignment",
     );
 }
+
+#[test]
+fn test_reuse_slots() {
+    // Later comprehensions reuse the slots of earlier comprehension variables with the
+    // same name, so each slot has the name of every variable in it
+    let mut a = assert::Assert::new();
+    let def = a
+        .module(
+            "slots.star",
+            r#"
+def test():
+    a = [x for x in range(3)]
+    b = [x * 2 for x in a]
+    c = {x: v for x, v in zip(a, b)}
+    d = [y for y in b]
+    return (a, b, c, d)
+"#,
+        )
+        .get("test")
+        .unwrap()
+        .downcast::<FrozenDef>()
+        .unwrap();
+    assert_eq!(
+        def.def_info.scope_names.used,
+        &["a", "b", "c", "d", "x", "v", "y"]
+    );
+    a.is_true(
+        r#"
+load("slots.star", "test")
+test() == ([0, 1, 2], [0, 2, 4], {0: 0, 1: 2, 2: 4}, [0, 2, 4])
+"#,
+    );
+
+    // Even if a later comprehension reads a variable too early
+    assert::fail(
+        "_ = [y for y in [1]]\n[1 for y in [1] for z in w for w in [2]]",
+        "variable `w` referenced before assignment",
+    );
+    // Or captures its variables
+    check_comp(&[
+        "_ = [y for y in [1]]",
+        "[(lambda: y)() for y in [2, 3]] == [2, 3]",
+    ]);
+}
+
+#[test]
+fn test_share_slots() {
+    // Temporaries which are dead before the next is assigned all share one slot,
+    // so the frame doesn't grow with the length of the function
+    let mut program = "def test():\n    acc = 0\n".to_owned();
+    for i in 0..300 {
+        program += &format!("    t{i} = acc + {i}\n    acc = t{i}\n", i = i);
+    }
+    program += "    return acc\n";
+    let mut a = assert::Assert::new();
+    let def = a
+        .module("slots.star", &program)
+        .get("test")
+        .unwrap()
+        .downcast::<FrozenDef>()
+        .unwrap();
+    assert_eq!(def.def_info.scope_names.used, &["acc", "t0"]);
+    a.eq("44850", "load('slots.star', 'test')\ntest()");
+}
+
+#[test]
+fn test_batched_function() {
+    /// Doubles an int, recording the number of calls in each batch.
+    struct Double(Arc<Mutex<Vec<usize>>>);
+
+    impl BatchedFunction for Double {
+        fn call<'v>(
+            &self,
+            args: &[Value<'v>],
+            _eval: &mut Evaluator<'v, '_>,
+        ) -> anyhow::Result<Value<'v>> {
+            self.0.lock().unwrap().push(1);
+            Ok(Value::new_int(args[0].unpack_int().unwrap() * 2))
+        }
+
+        fn call_batch<'v>(
+            &self,
+            args: &[Vec<Value<'v>>],
+            _eval: &mut Evaluator<'v, '_>,
+        ) -> anyhow::Result<Vec<Value<'v>>> {
+            self.0.lock().unwrap().push(args.len());
+            Ok(args
+                .iter()
+                .map(|args| Value::new_int(args[0].unpack_int().unwrap() * 2))
+                .collect())
+        }
+    }
+
+    let batches = Arc::new(Mutex::new(Vec::new()));
+    let mut a = Assert::new();
+    a.globals_add(|builder| builder.set_batched_function("double", Double(batches.clone())));
+    let check = |program: &str, expected: &str, batch: usize| {
+        a.eq(expected, program);
+        let mut batches = batches.lock().unwrap();
+        assert!(!batches.is_empty());
+        assert!(batches.iter().all(|x| *x == batch), "{:?}", batches);
+        batches.clear();
+    };
+
+    check("[double(x) for x in [1, 2, 3] if x != 2]", "[2, 6]", 2);
+    check(
+        "def f(xs):\n  return [double(x + y) for x in xs for y in xs[:x]]\nf([1, 2])",
+        "[4, 6, 8]",
+        3,
+    );
+    check("double(4)", "8", 1);
+    // Anything else called in an iteration could observe the order of the calls.
+    check("[double(int(x)) for x in ['1', '2']]", "[2, 4]", 1);
+    check("[double(x) for x in [1, 2] if str(x)]", "[2, 4]", 1);
+    // Only comprehensions are batched, not `for` statements.
+    check(
+        "def f(xs):\n  res = []\n  for x in xs:\n    res.append(double(x))\n  return res\nf([1, 2])",
+        "[2, 4]",
+        1,
+    );
+}
@@ -145,6 +145,77 @@ fn test_go() {
     ));
 }
 
+#[test]
+fn test_go_int() {
+    // We skip int.star above, so these are the cases about the integer arithmetic described
+    // in the module docs of `values/types/int.rs`, with the results and error messages of the
+    // Go implementation, in the same format.
+    let assert = Assert::new();
+    assert.conformance(
+        r#"
+load("assert.star", "assert")
+
+# floored division
+assert.eq(100 // 7, 14)
+assert.eq(100 // -7, -15)
+assert.eq(-100 // 7, -15)
+assert.eq(-100 // -7, 14)
+assert.eq(98 // 7, 14)
+assert.eq(98 // -7, -14)
+assert.eq(-98 // 7, -14)
+assert.eq(-98 // -7, 14)
+assert.eq(-7 // 2, -4)
+assert.eq(7 // 2.0, 3.0)
+
+# remainder
+assert.eq(100 % 7, 2)
+assert.eq(100 % -7, -5)
+assert.eq(-100 % 7, 5)
+assert.eq(-100 % -7, -2)
+assert.eq(98 % 7, 0)
+assert.eq(98 % -7, 0)
+assert.eq(-7.0 % 2, 1.0)
+assert.eq(7 / 2, 3.5)
+
+# results outside 32 bits
+assert.eq((-2147483647 - 1) // -1, 2147483648)
+assert.eq((-2147483647 - 1) % -1, 0)
+assert.eq(1 << 31, 2147483648)
+assert.eq((1 << 70) // (1 << 69), 2)
+assert.eq(-(1 << 70) % 3, 2)
+
+# shifts
+assert.eq(1 << 2, 4)
+assert.eq(1 << 3, 8)
+assert.eq(-1 << 3, -8)
+assert.eq(16 >> 2, 4)
+assert.eq(-16 >> 2, -4)
+assert.eq(-1 >> 40, -1)
+assert.eq(1 >> 40, 0)
+assert.eq(1 << 511, 2 * (1 << 510))
+assert.eq((1 << 511) >> 1000, 0)
+"#,
+    );
+    // `assert.fails` and `###` don't check the message, so check each error has Go's.
+    for (code, msg) in [
+        ("1 // 0", "floored division by zero"),
+        ("1 // 0.0", "floored division by zero"),
+        ("(1 << 70) // 0", "floored division by zero"),
+        ("1 % 0", "integer modulo by zero"),
+        ("(1 << 70) % 0", "integer modulo by zero"),
+        ("1.0 % 0", "floating-point modulo by zero"),
+        ("1 / 0", "floating-point division by zero"),
+        ("1.0 / 0.0", "floating-point division by zero"),
+        ("1 << -1", "negative shift count: -1"),
+        ("2 >> -1", "negative shift count: -1"),
+        ("(1 << 70) << -1", "negative shift count: -1"),
+        ("1 << 512", "shift count too large: 512"),
+        ("(1 << 70) << 600", "shift count too large: 600"),
+    ] {
+        assert::fail(code, msg);
+    }
+}
+
 #[test]
 fn test_in_range() {
     // Go Starlark considers this a type error (I think that is a mistake)
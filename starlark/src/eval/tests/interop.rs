@@ -20,6 +20,7 @@
 use std::{
     cell::RefCell,
     collections::HashMap,
+    hash::Hasher,
     sync::{Arc, Mutex},
 };
 
@@ -30,7 +31,8 @@ use crate as starlark;
 use crate::{
     assert,
     assert::Assert,
-    environment::{GlobalsBuilder, Module},
+    collections::StarlarkHasher,
+    environment::{ExportFormat, GlobalsBuilder, Module},
     eval::Evaluator,
     syntax::{AstModule, Dialect},
     values::{any::StarlarkAny, none::NoneType, Freeze, StarlarkValue, Value},
@@ -187,6 +189,39 @@ fn test_load_symbols_extra() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_export() -> anyhow::Result<()> {
+    let module = assert::pass_module(
+        r#"
+name = "app"
+ports = [80, 443]
+db = {"host": "localhost", "replicas": 2}
+_private = len
+"#,
+    );
+    assert_eq!(
+        module.export(ExportFormat::Json)?,
+        r#"{"name":"app","ports":[80,443],"db":{"host":"localhost","replicas":2}}"#
+    );
+    assert_eq!(
+        toml::from_str::<toml::Value>(&module.export(ExportFormat::Toml)?)?,
+        toml::from_str::<toml::Value>(
+            "name = 'app'\nports = [80, 443]\n[db]\nhost = 'localhost'\nreplicas = 2"
+        )?
+    );
+
+    let module = assert::pass_module("f = len\nx = 1\ny = None");
+    assert_eq!(
+        module.export(ExportFormat::Json).unwrap_err().to_string(),
+        "Can't export JSON, the module symbols `f` have no JSON representation"
+    );
+    assert_eq!(
+        module.export(ExportFormat::Toml).unwrap_err().to_string(),
+        "Can't export TOML, the module symbols `f`, `y` have no TOML representation"
+    );
+    Ok(())
+}
+
 #[test]
 fn test_repr_str() {
     #[derive(AnyLifetime, Debug, Display)]
@@ -268,6 +303,9 @@ mod value_of {
                 .join(" + ");
             Ok((*v, repr))
         }
+        fn sum_int_list(v: ListOf<i32>) -> (i32, i32) {
+            Ok((v.len() as i32, v.iter().sum()))
+        }
         fn with_int_dict(v: DictOf<i32, i32>) -> (Value<'v>, String) {
             let repr = v
                 .to_dict()
@@ -337,6 +375,12 @@ mod value_of {
         a.fail("with_int_list(1)", BAD);
         a.fail("with_int_list([1, 'foo'])", BAD);
         a.fail("with_int_list([[]])", BAD);
+        a.fail(
+            "with_int_list([1, 'foo'])",
+            "expected `list of int`, actual `list with item 1 of type string`",
+        );
+        a.eq("(3, 6)", "sum_int_list([1, 2, 3])");
+        a.eq("(0, 0)", "sum_int_list([])");
 
         a.eq(
             "([[1, 2], [3]], '1, 2 + 3')",
@@ -356,6 +400,14 @@ mod value_of {
         a.fail(r#"with_int_dict(1)"#, BAD);
         a.fail(r#"with_int_dict({1: "str"})"#, BAD);
         a.fail(r#"with_int_dict({1: {}})"#, BAD);
+        a.fail(
+            r#"with_int_dict({1: 2, 3: "str"})"#,
+            "actual `dict with value of type string for key 3`",
+        );
+        a.fail(
+            r#"with_int_dict({"x": 2})"#,
+            "actual `dict with key of type string`",
+        );
 
         let expected = r#"({1: [2, 3], 4: [5]}, "1: 2, 3 + 4: 5")"#;
         let test = r#"with_list_dict({1: [2, 3], 4: [5]})"#;
@@ -433,6 +485,173 @@ fn test_derive_attrs() {
     a.eq("example.nested.foo", "\"bar\"");
 }
 
+#[test]
+fn test_derive_attrs_equals_display() {
+    #[derive(Debug, Clone, StarlarkAttrs)]
+    #[starlark(display)]
+    struct Point {
+        x: i32,
+        label: String,
+        #[starlark(skip)]
+        _cache: Option<u32>,
+    }
+    starlark_simple_value!(Point);
+    impl<'v> StarlarkValue<'v> for Point {
+        starlark_type!("point");
+        starlark_attrs!(equals);
+    }
+
+    fn point(x: i32, cache: u32) -> Point {
+        Point {
+            x,
+            label: "p".to_owned(),
+            _cache: Some(cache),
+        }
+    }
+
+    let mut a = Assert::new();
+    a.globals_add(|gb| {
+        gb.set("p1", point(1, 10));
+        gb.set("p1_again", point(1, 20));
+        gb.set("p2", point(2, 10));
+    });
+    a.eq("repr(p1)", "'Point(x=1, label=\"p\")'");
+    a.is_true("p1 == p1_again");
+    a.is_true("p1 != p2");
+    a.is_true("p1 != 1");
+}
+
+#[test]
+fn test_derive_enum() {
+    #[derive(Debug, PartialEq, StarlarkEnum)]
+    enum OptLevel {
+        Debug,
+        FastRelease,
+        #[starlark(rename = "small")]
+        Size,
+    }
+
+    #[starlark_module]
+    fn module(builder: &mut GlobalsBuilder) {
+        fn opt_level(x: OptLevel) -> OptLevel {
+            Ok(match x {
+                OptLevel::Debug => OptLevel::FastRelease,
+                x => x,
+            })
+        }
+    }
+
+    assert_eq!(OptLevel::values(), &["debug", "fast_release", "small"]);
+    assert_eq!(OptLevel::Size.as_str(), "small");
+
+    let mut a = Assert::new();
+    a.globals_add(module);
+    a.eq("'fast_release'", "opt_level('debug')");
+    a.eq("'small'", "opt_level('small')");
+    a.fail(
+        "opt_level('size')",
+        "one of `debug`, `fast_release`, `small`",
+    );
+    a.fail("opt_level(1)", "one of `debug`, `fast_release`, `small`");
+}
+
+#[test]
+fn test_derive_unpack_value() {
+    #[derive(Debug, UnpackValue)]
+    struct Config<'v> {
+        name: &'v str,
+        port: Option<i32>,
+        #[starlark(default)]
+        tags: Vec<String>,
+    }
+
+    #[starlark_module]
+    fn module(builder: &mut GlobalsBuilder) {
+        fn describe(config: Config<'v>) -> String {
+            Ok(format!(
+                "{} {:?} {:?}",
+                config.name, config.port, config.tags
+            ))
+        }
+    }
+
+    let mut a = Assert::new();
+    a.globals_add(module);
+    a.eq("'a None []'", "describe({'name': 'a'})");
+    a.eq(
+        "'a Some(80) [\"x\"]'",
+        "describe({'name': 'a', 'port': 80, 'tags': ['x']})",
+    );
+    a.eq("'a None []'", "describe(struct(name = 'a', port = None))");
+    a.fail("describe({'port': 80})", "Missing field `name`");
+    a.fail(
+        "describe({'name': 'a', 'port': '80'})",
+        "Type of field `port` doesn't match",
+    );
+    a.fail(
+        "describe({'name': 'a', 'prot': 80})",
+        "Unknown field `prot`",
+    );
+    a.fail("describe([])", "Expected `dict or struct`");
+}
+
+#[test]
+fn test_std_conversions() {
+    use std::{
+        collections::{BTreeMap, HashMap},
+        path::PathBuf,
+        time::Duration,
+    };
+
+    use either::Either;
+
+    type Ten = (i32, i32, i32, i32, i32, i32, i32, i32, i32, String);
+
+    #[starlark_module]
+    fn module(builder: &mut GlobalsBuilder) {
+        fn reverse3(x: (i32, String, bool)) -> (bool, String, i32) {
+            Ok((x.2, x.1, x.0))
+        }
+        fn ten(x: Ten) -> Ten {
+            Ok(x)
+        }
+        fn sorted(x: HashMap<String, i32>) -> BTreeMap<String, i32> {
+            Ok(x.into_iter().collect())
+        }
+        fn nones(x: Vec<Option<i32>>) -> Vec<Option<i32>> {
+            Ok(x)
+        }
+        fn parent(x: PathBuf) -> PathBuf {
+            Ok(x.parent().unwrap().to_owned())
+        }
+        fn double(x: Duration) -> Duration {
+            Ok(x * 2)
+        }
+        fn either(x: bool) -> Either<i32, String> {
+            Ok(if x {
+                Either::Left(1)
+            } else {
+                Either::Right("no".to_owned())
+            })
+        }
+    }
+
+    let mut a = Assert::new();
+    a.globals_add(module);
+    a.eq("(True, 'a', 1)", "reverse3((1, 'a', True))");
+    a.eq(
+        "(1, 2, 3, 4, 5, 6, 7, 8, 9, 'x')",
+        "ten((1, 2, 3, 4, 5, 6, 7, 8, 9, 'x'))",
+    );
+    a.eq("['a', 'b']", "list(sorted({'b': 2, 'a': 1}))");
+    a.eq("[1, None]", "nones([1, None])");
+    a.eq("'a/b'", "parent('a/b/c')");
+    a.eq("3.0", "double(1.5)");
+    a.eq("[1, 'no']", "[either(True), either(False)]");
+    a.fail("reverse3((1, 'a'))", "tuple (int, str, bool)");
+    a.fail("double(-1)", "non-negative number of seconds");
+}
+
 #[test]
 fn test_eval_function() {
     let fun = assert::pass(
@@ -450,3 +669,99 @@ fun
         .unwrap();
     assert_eq!(v.unpack_str(), Some("(8, \"hello\", 1)"))
 }
+
+#[test]
+fn test_freeze_error_path() {
+    use crate::values::{FreezeError, Freezer, NoSimpleValue, Trace};
+
+    #[derive(Debug, Trace, AnyLifetime, Display)]
+    #[display(fmt = "unfreezable")]
+    struct Unfreezable;
+
+    impl<'v> StarlarkValue<'v> for Unfreezable {
+        starlark_type!("unfreezable");
+    }
+
+    impl Freeze for Unfreezable {
+        type Frozen = NoSimpleValue;
+        fn freeze(self, _freezer: &Freezer) -> anyhow::Result<Self::Frozen> {
+            Err(anyhow::anyhow!("It won't freeze"))
+        }
+    }
+
+    #[starlark_module]
+    fn module(builder: &mut GlobalsBuilder) {
+        fn unfreezable() -> Value<'v> {
+            Ok(heap.alloc_complex(Unfreezable))
+        }
+    }
+
+    let freeze = |code: &str, provenance: bool| {
+        let globals = GlobalsBuilder::extended().with(module).build();
+        let module = Module::new();
+        let mut eval = Evaluator::new(&module);
+        if provenance {
+            eval.enable_provenance();
+        }
+        let ast = AstModule::parse("test.star", code.to_owned(), &Dialect::Extended).unwrap();
+        eval.eval_module(ast, &globals).unwrap();
+        drop(eval);
+        module.freeze().unwrap_err()
+    };
+
+    let err = freeze(
+        "x = 1\ncfg = {'handlers': [1, struct(callback = unfreezable())]}",
+        true,
+    );
+    let e = err.downcast_ref::<FreezeError>().unwrap();
+    assert_eq!(e.path(), "cfg[\"handlers\"][1].callback");
+    assert_eq!(e.type_name(), "unfreezable");
+    assert_eq!(
+        e.provenance().unwrap().location.resolve_span().begin_line,
+        1
+    );
+    let message = err.to_string();
+    assert!(message.contains("It won't freeze"), "{}", message);
+    assert!(message.contains("allocated at test.star:2"), "{}", message);
+
+    // A value inside a function is reached through its type
+    let err = freeze("def f(x = (1, unfreezable())):\n    pass", false);
+    let e = err.downcast_ref::<FreezeError>().unwrap();
+    assert_eq!(e.path(), "f.<function>[1]");
+    assert!(e.provenance().is_none());
+}
+
+#[test]
+fn test_debug_summary_in_errors() {
+    // A native value with a `repr` too long to show in an error message.
+    #[derive(Debug, Display)]
+    #[display(fmt = "table({})", "\"row, \".repeat(self.0)")]
+    struct Table(usize);
+    starlark_simple_value!(Table);
+    impl<'v> StarlarkValue<'v> for Table {
+        starlark_type!("table");
+
+        fn debug_summary(&self) -> Option<String> {
+            Some(format!("table of {} rows", self.0))
+        }
+
+        fn write_hash(&self, hasher: &mut StarlarkHasher) -> anyhow::Result<()> {
+            hasher.write_usize(self.0);
+            Ok(())
+        }
+    }
+
+    let mut a = Assert::new();
+    a.globals_add(|gb| gb.set("table", Table(10000)));
+    for (program, msg) in [
+        (
+            "def f(x: int.type): pass\nf(table)",
+            "Value `table of 10000 rows` of type `table` does not match",
+        ),
+        ("{}[table]", "Key `table of 10000 rows` was not found"),
+        ("{1: 2}.pop(table)", "Key `table of 10000 rows` not found"),
+    ] {
+        let err = a.fail(program, msg);
+        assert!(!err.to_string().contains("row, "), "{}", err);
+    }
+}
@@ -0,0 +1,205 @@
+/*
+ * Copyright 2018 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Evaluations which share only frozen data can't observe or modify each other.
+//! See the "Isolation" section of `docs/heaps.md`.
+
+use std::thread;
+
+use gazebo::prelude::*;
+
+use crate::{
+    environment::{FrozenModule, Globals, Module},
+    eval::{Evaluator, ReturnFileLoader},
+    syntax::{AstModule, Dialect},
+};
+
+const SHARED: &str = r#"
+xs = [1, 2]
+d = {"a": [3]}
+def f(x = []):
+    x.append(1)
+    return len(x)
+"#;
+
+const SHARED_REPR: &str = r#"[[1, 2], {"a": [3]}]"#;
+
+/// Statements a tenant might run against the shared module. Many of them must fail,
+/// but none of them may change what the shared module (or another tenant) sees.
+/// Each `N` is replaced by a random value.
+const OPS: &[&str] = &[
+    "xs.append(N)",
+    "xs[N] = N",
+    "xs += [N]",
+    "xs.pop()",
+    "xs.clear()",
+    "xs.remove(N)",
+    "xs.extend(xs)",
+    "d[N] = N",
+    "d['a'].append(N)",
+    "d['a'] += [N]",
+    "d.clear()",
+    "d.pop('a')",
+    "d.update({N: N})",
+    "d.setdefault(N, N)",
+    "f()",
+    "f([])",
+    "ys = xs + [N]",
+    "ys = list(xs)\nys.append(N)",
+    "ys = dict(d)\nys['a'] = N",
+    "ys = [xs, d]",
+    "g = d['a']\ng.append(N)",
+    "mine.append(xs)",
+    "mine.append(N)",
+    "mine.append(f())",
+    "mine[N] = d",
+];
+
+/// The random values substituted into [`OPS`].
+const VALUES: &[&str] = &["0", "1", "-1", "'a'", "'b'", "None", "[]", "[1]", "(1, 2)"];
+
+fn shared() -> FrozenModule {
+    let module = Module::new();
+    let mut eval = Evaluator::new(&module);
+    let ast = AstModule::parse("shared.star", SHARED.to_owned(), &Dialect::Extended).unwrap();
+    eval.eval_module(ast, &Globals::standard()).unwrap();
+    drop(eval);
+    module.freeze().unwrap()
+}
+
+fn shared_repr(shared: &FrozenModule) -> String {
+    let xs = shared.get("xs").unwrap();
+    let d = shared.get("d").unwrap();
+    format!("[{}, {}]", xs.value().to_repr(), d.value().to_repr())
+}
+
+/// Run a tenant script which loads the shared module, returning the tenant's frozen module,
+/// regardless of whether the script failed.
+fn tenant(shared: &FrozenModule, ops: &[impl AsRef<str>]) -> FrozenModule {
+    let mut code = "load('shared.star', 'xs', 'd', 'f')\nmine = []\n".to_owned();
+    for op in ops {
+        code.push_str(op.as_ref());
+        code.push('\n');
+    }
+    let modules = hashmap! {"shared.star" => shared};
+    let loader = ReturnFileLoader { modules: &modules };
+    let module = Module::new();
+    let mut eval = Evaluator::new(&module);
+    eval.set_loader(&loader);
+    let ast = AstModule::parse("tenant.star", code, &Dialect::Extended).unwrap();
+    // Most adversarial scripts fail, which is fine, the module is still usable.
+    let _ = eval.eval_module(ast, &Globals::standard());
+    drop(eval);
+    module.freeze().unwrap()
+}
+
+/// A small deterministic generator, so failures are reproducible.
+struct Lcg(u64);
+
+impl Lcg {
+    fn below(&mut self, n: usize) -> usize {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        ((self.0 >> 33) as usize) % n
+    }
+
+    /// A random sequence of operations. Each is run as its own statement, so a failing
+    /// operation stops the rest, so sequences are short.
+    fn ops(&mut self) -> Vec<String> {
+        let len = self.below(6) + 1;
+        (0..len)
+            .map(|_| {
+                let op = OPS[self.below(OPS.len())];
+                let mut res = String::new();
+                for (i, part) in op.split('N').enumerate() {
+                    if i != 0 {
+                        res.push_str(VALUES[self.below(VALUES.len())]);
+                    }
+                    res.push_str(part);
+                }
+                res
+            })
+            .collect()
+    }
+}
+
+fn mine_repr(tenant: &FrozenModule) -> String {
+    tenant.get("mine").unwrap().value().to_repr()
+}
+
+#[test]
+fn test_isolation_each_op() {
+    let shared = shared();
+    for op in OPS {
+        for value in VALUES {
+            let op = op.replace('N', value);
+            tenant(&shared, &[&op]);
+            assert_eq!(shared_repr(&shared), SHARED_REPR, "After `{}`", op);
+        }
+    }
+}
+
+#[test]
+fn test_isolation_fuzz() {
+    let shared = shared();
+    let mut rng = Lcg(42);
+    for _ in 0..500 {
+        let ops = rng.ops();
+        tenant(&shared, &ops);
+        assert_eq!(shared_repr(&shared), SHARED_REPR, "After {:?}", ops);
+    }
+}
+
+#[test]
+fn test_isolation_threads() {
+    // Tenants on several threads run random sequences at the same time. Each must leave
+    // the shared module unchanged, and end up exactly as if it had run alone.
+    let shared = shared();
+    let threads = (0..4u64)
+        .map(|seed| {
+            let shared = shared.dupe();
+            thread::spawn(move || {
+                let mut rng = Lcg(seed);
+                (0..100)
+                    .map(|_| {
+                        let ops = rng.ops();
+                        let res = mine_repr(&tenant(&shared, &ops));
+                        assert_eq!(shared_repr(&shared), SHARED_REPR, "After {:?}", ops);
+                        (ops, res)
+                    })
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect::<Vec<_>>();
+    for t in threads {
+        for (ops, res) in t.join().unwrap() {
+            assert_eq!(mine_repr(&tenant(&shared, &ops)), res, "After {:?}", ops);
+        }
+    }
+}
+
+#[test]
+fn test_isolation_heap_lifetime() {
+    // A tenant which captures shared values must keep the shared heap alive
+    // (via its `FrozenHeapRef`) after every other handle to it has been dropped.
+    let shared = shared();
+    let res = tenant(&shared, &["mine.append(xs)\nmine.append(d)"]);
+    drop(shared);
+    assert_eq!(res.get("mine").unwrap().value().to_repr(), SHARED_REPR);
+}
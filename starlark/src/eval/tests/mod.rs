@@ -35,13 +35,17 @@ mod basic;
 mod bc;
 mod before_stmt;
 mod call;
+mod checkpoint;
 mod comprehension;
 mod def;
 mod docstring;
 mod go;
 mod interop;
+mod isolation;
 mod opt;
 mod runtime;
+#[cfg(feature = "tracing")]
+mod spans;
 mod type_is;
 
 #[test]
@@ -20,6 +20,7 @@
 use std::{
     mem,
     sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
 };
 
 use derive_more::Display;
@@ -29,8 +30,10 @@ use crate as starlark;
 use crate::{
     assert,
     assert::Assert,
-    environment::GlobalsBuilder,
-    values::{any::StarlarkAny, FrozenHeap, Heap},
+    environment::{Globals, GlobalsBuilder, Module},
+    eval::Evaluator,
+    syntax::{AstModule, Dialect},
+    values::{any::StarlarkAny, none::NoneType, FrozenHeap, Heap},
 };
 
 #[test]
@@ -45,6 +48,80 @@ assert_eq(y, str(x))
     );
 }
 
+#[test]
+fn test_garbage_collect_mutated_old_values() {
+    // Values stored into containers which survived earlier collections
+    // must be kept alive, including by generational collection.
+    assert::pass(
+        r#"
+xs = []
+d = {}
+s = set()
+ws = list(range(20))[2:18]
+def counter():
+    n = []
+    def inc():
+        n.append(str(len(n)))
+        return n
+    return inc
+inc = counter()
+xs.append(str(1))
+xs.extend([str(2)])
+xs.insert(0, str(0))
+xs[0] = str(3)
+d["a"] = str(4)
+d.setdefault("b", str(5))
+d.update({"c": str(6)})
+s.add(str(7))
+ws.pop()
+ws[0] = str(8)
+inc()
+garbage_collect()
+assert_eq(xs, ["3", "1", "2"])
+assert_eq(d, {"a": "4", "b": "5", "c": "6"})
+assert_eq(s, set(["7"]))
+assert_eq(ws, ["8"] + list(range(3, 17)))
+assert_eq(inc(), ["0", "1"])
+    "#,
+    );
+}
+
+#[test]
+fn test_garbage_collect_reassigned_captured_variable() {
+    #[starlark_module]
+    fn helpers(builder: &mut GlobalsBuilder) {
+        // Only safe because the caller keeps nothing on its stack, and the module
+        // keeps nothing in its own locals.
+        fn collect() -> NoneType {
+            unsafe { eval.garbage_collect() };
+            Ok(NoneType)
+        }
+    }
+
+    let module = Module::new();
+    let mut eval = Evaluator::new(&module);
+    eval.enable_generational_gc();
+    // The first collection is a major one, which tenures the cell holding `x`,
+    // and the second is a minor one, which only finds the new value of `x`
+    // through the write barrier.
+    let program = r#"
+def outer():
+    x = str(1)
+    def inner():
+        return x
+    collect()
+    x = str(42)
+    collect()
+    return inner
+inner = outer()
+inner()
+"#;
+    let ast = AstModule::parse("a.star", program.to_owned(), &Dialect::Standard).unwrap();
+    let globals = GlobalsBuilder::standard().with(helpers).build();
+    let res = eval.eval_module(ast, &globals).unwrap();
+    assert_eq!(res.unpack_str(), Some("42"));
+}
+
 #[test]
 fn test_deallocation() {
     // Check that we really do deallocate values we create
@@ -211,7 +288,7 @@ fn test_display_debug() {
     assert_eq!(val.to_str(), "([1, 2], \"test\", True)");
     assert_eq!(
         format!("{:?}", val),
-        "Value(TupleGen { content: [Value(ListGen(List { content: Cell { value: ValueType(Value(Array { len: 2, capacity: 4, iter_count: 0, content: [Value(1), Value(2)] })) } })), Value(\"test\"), Value(StarlarkBool(true))] })"
+        "Value(TupleGen { content: [Value(ListGen(List { content: Cell { value: Value(Array { len: 2, capacity: 4, iter_count: 0, content: [Value(1), Value(2)] }) } })), Value(\"test\"), Value(StarlarkBool(true))] })"
     );
     let v = heap.alloc("test");
     assert_eq!(format!("{}", v), "\"test\"");
@@ -228,3 +305,86 @@ fn test_display_debug() {
     assert_eq!(format!("{:?}", v), "FrozenValue(\"test\")");
     assert_eq!(format!("{:#?}", v), "FrozenValue(\n    \"test\",\n)");
 }
+
+#[test]
+fn test_request_gc() {
+    #[starlark_module]
+    fn helpers(builder: &mut GlobalsBuilder) {
+        fn request_gc() -> NoneType {
+            eval.request_gc();
+            Ok(NoneType)
+        }
+    }
+
+    // Not enough garbage to trigger a GC by itself.
+    let run = |request: bool| {
+        let module = Module::new();
+        let mut eval = Evaluator::new(&module);
+        let program = format!(
+            "x = [str(i) for i in range(1000)]\nx = None\n{}\ny = 1",
+            if request { "request_gc()" } else { "" }
+        );
+        let ast = AstModule::parse("a.star", program, &Dialect::Extended).unwrap();
+        let globals = GlobalsBuilder::extended().with(helpers).build();
+        eval.eval_module(ast, &globals).unwrap();
+        (
+            module.heap().allocated_bytes(),
+            module.heap().peak_allocated_bytes(),
+        )
+    };
+    let (allocated, peak) = run(false);
+    assert_eq!(allocated, peak);
+    let (allocated, peak) = run(true);
+    assert!(allocated < peak / 2, "{} < {} / 2", allocated, peak);
+}
+
+#[test]
+fn test_gc_pacing() {
+    let target = Duration::from_millis(50);
+    let module = Module::new();
+    let mut eval = Evaluator::new(&module);
+    eval.enable_stats();
+    eval.set_gc_pacing(target);
+    let mut program = "xs = []\n".to_owned();
+    for _ in 0..200 {
+        program.push_str("xs.append([str(i) for i in range(200)])\n");
+        program.push_str("garbage = [str(i) for i in range(2000)]\n");
+    }
+    program.push_str("len(xs) == 200 and xs[0][199] == '199' and xs[199][0] == '0'");
+    let ast = AstModule::parse("a.star", program, &Dialect::Standard).unwrap();
+    let res = eval.eval_module(ast, &Globals::standard()).unwrap();
+    assert!(res.unpack_bool().unwrap());
+    let stats = eval.stats().unwrap();
+    // Values kept alive by the earlier statements survive the collections.
+    assert!(stats.gc_count > 1, "{:?}", stats);
+    // Only minor collections are paced, major ones take as long as they need.
+    assert!(stats.max_minor_gc_pause > Duration::ZERO, "{:?}", stats);
+    assert!(stats.max_minor_gc_pause <= target, "{:?}", stats);
+}
+
+#[test]
+fn test_set_overrides() {
+    let parse = |x: &str| AstModule::parse("test.star", x.to_owned(), &Dialect::Extended).unwrap();
+    let globals = Globals::standard();
+    let mocks = Module::new();
+    let mut eval = Evaluator::new(&mocks);
+    eval.eval_module(parse("def fake(x):\n  return 42"), &globals)
+        .unwrap();
+    drop(eval);
+    let fake = mocks.freeze().unwrap().get("fake").unwrap();
+
+    let module = Module::new();
+    let mut eval = Evaluator::new(&module);
+    eval.set_overrides(&[("len", fake)]);
+    let res = eval
+        .eval_module(parse("def f(x):\n  return len(x)\nf([1, 2])"), &globals)
+        .unwrap();
+    assert_eq!(res.unpack_int(), Some(42));
+    // The next evaluation sees the real `len`, but `f` keeps the override.
+    let res = eval
+        .eval_module(parse("f([1, 2]) + len([1, 2])"), &globals)
+        .unwrap();
+    assert_eq!(res.unpack_int(), Some(44));
+    drop(eval);
+    assert!(module.get("len").is_none());
+}
@@ -0,0 +1,143 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Test of the `tracing` spans emitted with the `tracing` feature.
+
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    sync::{Arc, Mutex},
+};
+
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+    Event, Metadata, Subscriber,
+};
+
+use crate::{
+    environment::{FrozenModule, Globals, Module},
+    eval::{Evaluator, ReturnFileLoader},
+    syntax::{AstModule, Dialect},
+};
+
+/// A span which was created, with its name and the fields recorded on it, as `name=value`.
+#[derive(Debug, Clone)]
+struct RecordedSpan {
+    name: &'static str,
+    fields: Vec<String>,
+}
+
+/// Records every span created while it is the default subscriber.
+#[derive(Clone, Default)]
+struct SpanRecorder(Arc<Mutex<Vec<RecordedSpan>>>);
+
+struct FieldVisitor<'a>(&'a mut Vec<String>);
+
+impl Visit for FieldVisitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0.push(format!("{}={:?}", field.name(), value));
+    }
+}
+
+impl Subscriber for SpanRecorder {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &Attributes) -> Id {
+        let mut spans = self.0.lock().unwrap();
+        let mut fields = Vec::new();
+        span.record(&mut FieldVisitor(&mut fields));
+        spans.push(RecordedSpan {
+            name: span.metadata().name(),
+            fields,
+        });
+        // Ids must be non-zero
+        Id::from_u64(spans.len() as u64)
+    }
+
+    fn record(&self, span: &Id, values: &Record) {
+        let mut spans = self.0.lock().unwrap();
+        let span = &mut spans[span.into_u64() as usize - 1];
+        values.record(&mut FieldVisitor(&mut span.fields));
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, _event: &Event) {}
+
+    fn enter(&self, _span: &Id) {}
+
+    fn exit(&self, _span: &Id) {}
+}
+
+#[test]
+fn test_tracing_spans() {
+    let lib = Module::new();
+    {
+        let mut eval = Evaluator::new(&lib);
+        let ast = AstModule::parse(
+            "lib.star",
+            "def f():\n    return 1".to_owned(),
+            &Dialect::Extended,
+        )
+        .unwrap();
+        eval.eval_module(ast, &Globals::standard()).unwrap();
+    }
+    let lib = lib.freeze().unwrap();
+    let modules: HashMap<&str, &FrozenModule> = hashmap! {"lib.star" => &lib};
+    let loader = ReturnFileLoader { modules: &modules };
+
+    let recorder = SpanRecorder::default();
+    tracing::subscriber::with_default(recorder.clone(), || {
+        let module = Module::new();
+        let mut eval = Evaluator::new(&module);
+        eval.set_loader(&loader);
+        let program = "load('lib.star', 'f')\ndef g():\n    return f()\ng()";
+        let ast = AstModule::parse("a.star", program.to_owned(), &Dialect::Extended).unwrap();
+        eval.eval_module(ast, &Globals::standard()).unwrap();
+        unsafe { eval.garbage_collect() };
+    });
+
+    let spans = recorder.0.lock().unwrap().clone();
+    let find = |name: &str| {
+        spans
+            .iter()
+            .filter(|x| x.name == name)
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+    let eval_module = find("starlark_eval_module");
+    assert_eq!(eval_module.len(), 1);
+    assert_eq!(eval_module[0].fields, vec!["file=\"a.star\""]);
+    let load = find("starlark_load");
+    assert_eq!(load.len(), 1);
+    assert_eq!(load[0].fields, vec!["module=\"lib.star\""]);
+    // One span for each of `g` and `f`.
+    let calls = find("starlark_call");
+    assert_eq!(calls.len(), 2);
+    assert!(calls
+        .iter()
+        .all(|x| x.fields.len() == 1 && x.fields[0].starts_with("function=")));
+    // The bytes allocated after the collection are recorded once it is done.
+    let gc = find("starlark_gc");
+    assert_eq!(gc.len(), 1);
+    assert_eq!(gc[0].fields.len(), 2);
+    assert!(gc[0].fields[0].starts_with("before="));
+    assert!(gc[0].fields[1].starts_with("after="));
+}
@@ -404,14 +404,18 @@ mod macros;
 
 pub use starlark_derive::starlark_module;
 
+pub use crate::errors::Error;
+
 pub(crate) mod analysis;
 pub mod assert;
+pub mod capabilities;
 pub mod codemap;
 pub mod collections;
 mod debug;
 pub mod environment;
 pub mod errors;
 pub mod eval;
+pub mod lsp;
 pub mod read_line;
 mod stdlib;
 pub mod syntax;
@@ -0,0 +1,2179 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! A [Language Server Protocol](https://microsoft.github.io/language-server-protocol/) server
+//! for Starlark, communicating over stdin/stdout, as started by [`server`]. Embedders whose
+//! Starlark has its own builtins can run it with their [`Globals`], so completions, hovers and
+//! checks know about their functions.
+//!
+//! Based on the reference lsp-server example at <https://github.com/rust-analyzer/lsp-server/blob/master/examples/goto_def.rs>.
+
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    ffi::OsStr,
+    fs, iter,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context as _};
+use gazebo::prelude::*;
+use itertools::Itertools;
+use lsp_server::{Connection, Message, Notification, Request, RequestId, Response};
+use lsp_types::{
+    notification::{
+        DidChangeConfiguration, DidChangeTextDocument, DidChangeWorkspaceFolders,
+        DidCloseTextDocument, DidOpenTextDocument, LogMessage, PublishDiagnostics,
+    },
+    request::{
+        CodeActionRequest, CodeLensRequest, Completion, DocumentHighlightRequest,
+        DocumentSymbolRequest, FoldingRangeRequest, Formatting, GotoDefinition, HoverRequest,
+        OnTypeFormatting, RangeFormatting, WorkspaceSymbol,
+    },
+    CodeAction, CodeActionKind, CodeActionOrCommand, CodeActionParams,
+    CodeActionProviderCapability, CodeActionResponse, CodeLens, CodeLensOptions, CodeLensParams,
+    Command, CompletionItem, CompletionItemKind, CompletionOptions, CompletionParams,
+    CompletionResponse, CompletionTextEdit, Diagnostic, DiagnosticRelatedInformation,
+    DiagnosticSeverity, DidChangeConfigurationParams, DidChangeTextDocumentParams,
+    DidChangeWorkspaceFoldersParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
+    DocumentFormattingParams, DocumentHighlight, DocumentHighlightKind, DocumentHighlightParams,
+    DocumentOnTypeFormattingOptions, DocumentOnTypeFormattingParams, DocumentRangeFormattingParams,
+    DocumentSymbol, DocumentSymbolParams, DocumentSymbolResponse, FoldingRange,
+    FoldingRangeKind as LspFoldingRangeKind, FoldingRangeParams, FoldingRangeProviderCapability,
+    GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverContents, HoverParams,
+    HoverProviderCapability, InitializeParams, Location, LogMessageParams, MarkupContent,
+    MarkupKind, MessageType, NumberOrString, OneOf, Position, PublishDiagnosticsParams, Range,
+    ServerCapabilities, SymbolInformation, SymbolKind as LspSymbolKind, TextDocumentIdentifier,
+    TextDocumentSyncCapability, TextDocumentSyncKind, TextEdit, Url, WorkspaceEdit,
+    WorkspaceFolder, WorkspaceFoldersServerCapabilities, WorkspaceServerCapabilities,
+    WorkspaceSymbolParams,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use walkdir::WalkDir;
+
+use crate::{
+    codemap::ResolvedSpan,
+    environment::{FrozenModule, Globals},
+    errors::{Diagnostic as StarlarkDiagnostic, Lint, LintFix},
+    syntax::{
+        AstModule, Dialect, FoldingRangeKind, HighlightKind, InlayHintKind, Symbol, SymbolKind,
+    },
+    values::{
+        dict::Dict,
+        docs::{DocItem, Param},
+        Heap, Value,
+    },
+};
+
+/// Maps the module strings written in `load()` statements to files on disk, so the server can
+/// follow loads for diagnostics and go-to-definition.
+pub trait LoadResolver {
+    /// Resolve `path`, as written in a `load()` in `current_file`, to the file it refers to.
+    /// `folder` is the workspace folder the editor opened which contains `current_file`, if any.
+    /// Each folder of a multi-root workspace is resolved separately.
+    fn resolve_load(
+        &self,
+        path: &str,
+        current_file: &Path,
+        folder: Option<&Path>,
+    ) -> anyhow::Result<PathBuf>;
+
+    /// The paths which could be written in a `load()` in `current_file`, starting with
+    /// `prefix`, the part of the path typed so far, offered as completions.
+    /// By default there are none.
+    fn complete_load(
+        &self,
+        _prefix: &str,
+        _current_file: &Path,
+        _folder: Option<&Path>,
+    ) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+/// The nearest directory containing `dir` with a `WORKSPACE` or `WORKSPACE.bazel` file,
+/// or failing that the workspace folder.
+fn workspace_root<'a>(dir: &'a Path, folder: Option<&'a Path>) -> Option<&'a Path> {
+    dir.ancestors()
+        .find(|x| x.join("WORKSPACE").exists() || x.join("WORKSPACE.bazel").exists())
+        .or(folder)
+}
+
+/// The subdirectories and Starlark files in `dir`, as names with whether they are a directory,
+/// sorted by name. Hidden entries are left out.
+fn directory_entries(dir: &Path) -> Vec<(String, bool)> {
+    let mut res: Vec<_> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|e| {
+                let e = e.ok()?;
+                let name = e.file_name().into_string().ok()?;
+                let is_dir = e.file_type().ok()?.is_dir();
+                let is_starlark = e
+                    .path()
+                    .extension()
+                    .map_or(false, |ext| EXTENSIONS.iter().any(|x| OsStr::new(x) == ext));
+                if name.starts_with('.') || !(is_dir || is_starlark) {
+                    None
+                } else {
+                    Some((name, is_dir))
+                }
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    res.sort();
+    res
+}
+
+/// Resolves Bazel-style labels. `//pkg:file.bzl` is relative to the workspace root, which is the
+/// nearest enclosing directory with a `WORKSPACE` or `WORKSPACE.bazel` file, or failing that
+/// the workspace folder, while `:file.bzl` and plain paths are relative to the directory of
+/// the current file.
+pub struct BazelLoadResolver;
+
+impl LoadResolver for BazelLoadResolver {
+    fn resolve_load(
+        &self,
+        path: &str,
+        current_file: &Path,
+        folder: Option<&Path>,
+    ) -> anyhow::Result<PathBuf> {
+        let current_dir = current_file.parent().unwrap_or_else(|| Path::new(""));
+        if path.starts_with('@') {
+            Err(anyhow!(
+                "Can't resolve `{}`, external repositories are not supported",
+                path
+            ))
+        } else if let Some(label) = path.strip_prefix("//") {
+            let root = workspace_root(current_dir, folder).ok_or_else(|| {
+                anyhow!(
+                    "Can't resolve `{}`, no `WORKSPACE` file found above `{}`",
+                    path,
+                    current_file.display()
+                )
+            })?;
+            let (package, target) = label.split_once(':').unwrap_or(("", label));
+            Ok(root.join(package).join(target))
+        } else {
+            Ok(current_dir.join(path.strip_prefix(':').unwrap_or(path)))
+        }
+    }
+
+    /// Directories complete to packages, e.g. `//pkg`, and files to targets, e.g. `//pkg:file.bzl`,
+    /// or for relative paths, to `dir/` and `dir/file.bzl`.
+    fn complete_load(
+        &self,
+        prefix: &str,
+        current_file: &Path,
+        folder: Option<&Path>,
+    ) -> Vec<String> {
+        let current_dir = current_file.parent().unwrap_or_else(|| Path::new(""));
+        let mut res = Vec::new();
+        if prefix.starts_with('@') {
+            return res;
+        } else if let Some(label) = prefix.strip_prefix("//") {
+            let root = match workspace_root(current_dir, folder) {
+                Some(root) => root,
+                None => return res,
+            };
+            // Once there's a `:`, only the files of the package are left
+            let (package, files_only) = match label.split_once(':') {
+                Some((package, _)) => (package, true),
+                None => (label.rsplit_once('/').map_or("", |x| x.0), false),
+            };
+            for (name, is_dir) in directory_entries(&root.join(package)) {
+                if !is_dir {
+                    res.push(format!("//{}:{}", package, name));
+                } else if !files_only {
+                    res.push(if package.is_empty() {
+                        format!("//{}", name)
+                    } else {
+                        format!("//{}/{}", package, name)
+                    });
+                }
+            }
+        } else if prefix.starts_with(':') {
+            for (name, is_dir) in directory_entries(current_dir) {
+                if !is_dir {
+                    res.push(format!(":{}", name));
+                }
+            }
+        } else {
+            let dir = prefix.rsplit_once('/').map_or("", |x| x.0);
+            for (name, is_dir) in directory_entries(&current_dir.join(dir)) {
+                let path = if dir.is_empty() {
+                    name
+                } else {
+                    format!("{}/{}", dir, name)
+                };
+                res.push(if is_dir { format!("{}/", path) } else { path });
+            }
+        }
+        res.retain(|x| x.starts_with(prefix));
+        res
+    }
+}
+
+/// The extensions of the files we index for workspace symbols.
+const EXTENSIONS: &[&str] = &["bzl", "star", "sky", "bxl"];
+
+/// The commands our code lenses invoke, which the client must implement. Both take the URI
+/// of the file, and optionally the name of a function in it to call with no arguments.
+const RUN_COMMAND: &str = "starlark.run";
+const DEBUG_COMMAND: &str = "starlark.debug";
+
+/// The Starlark the [`server`] works with. It should match the Starlark being edited,
+/// so use the same [`Dialect`] and [`Globals`] the files are evaluated with.
+pub struct LspContext {
+    /// How to parse the files.
+    pub dialect: Dialect,
+    /// The builtins available to the files, offered as completions, with their documentation
+    /// shown on hover.
+    pub globals: Globals,
+    /// Modules whose public symbols are also available to every file.
+    pub prelude: Vec<FrozenModule>,
+    /// Report names which aren't defined in the file, the [`globals`](LspContext::globals) or
+    /// the [`prelude`](LspContext::prelude). Only useful if those hold everything available.
+    pub undefined_names: bool,
+    /// Also report the problems found by [`AstModule::typecheck`].
+    pub typecheck: bool,
+}
+
+impl LspContext {
+    /// Serve files of the given dialect, with the given builtins and no prelude.
+    pub fn new(dialect: Dialect, globals: Globals) -> Self {
+        Self {
+            dialect,
+            globals,
+            prelude: Vec::new(),
+            undefined_names: false,
+            typecheck: false,
+        }
+    }
+}
+
+/// The most recent contents of an open document.
+struct Document {
+    text: String,
+    /// The last version of the document which parsed successfully, used for completions
+    /// when the current text is mid-edit and doesn't parse.
+    ast: Option<AstModule>,
+    /// The diagnostics for the current text which have fixes, offered as code actions.
+    fixes: Vec<(Diagnostic, LintFix)>,
+}
+
+/// The `textDocument/inlayHint` request, which is newer than our version of `lsp_types`.
+enum InlayHintRequest {}
+
+impl lsp_types::request::Request for InlayHintRequest {
+    type Params = InlayHintParams;
+    type Result = Vec<InlayHint>;
+    const METHOD: &'static str = "textDocument/inlayHint";
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct InlayHintParams {
+    text_document: TextDocumentIdentifier,
+    range: Range,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct InlayHint {
+    position: Position,
+    label: String,
+    /// 1 for a type, 2 for a parameter.
+    kind: u32,
+    padding_left: bool,
+    padding_right: bool,
+}
+
+/// The severity a user gave a lint in the `starlark.lints` setting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum LintSeverity {
+    Error,
+    Warning,
+    Info,
+    Hint,
+    Off,
+}
+
+/// The `starlark` section of the workspace settings.
+#[derive(Debug, Default, Deserialize)]
+struct Settings {
+    /// Overrides of the severity of lints, by their name, e.g. `unused-load`.
+    #[serde(default)]
+    lints: HashMap<String, LintSeverity>,
+}
+
+struct Backend {
+    connection: Connection,
+    context: LspContext,
+    documents: RefCell<HashMap<Url, Document>>,
+    /// Documents which have changed since they were last validated, with their latest version.
+    /// We validate once no more messages are waiting, so a burst of edits is only parsed and
+    /// linted once.
+    changed: RefCell<HashMap<Url, Option<i64>>>,
+    resolver: Box<dyn LoadResolver>,
+    settings: RefCell<Settings>,
+    /// The workspace folders open in the editor, of which there may be several.
+    folders: RefCell<Vec<PathBuf>>,
+}
+
+/// The range of a span, counting characters as Unicode scalar values, like the codemap.
+/// Convert it with [`range_to_lsp`] before sending it to the client.
+fn to_range(x: ResolvedSpan) -> Range {
+    Range::new(
+        Position::new(x.begin_line as u32, x.begin_column as u32),
+        Position::new(x.end_line as u32, x.end_column as u32),
+    )
+}
+
+/// LSP counts the characters of a line in UTF-16 code units, but the rest of the server
+/// counts Unicode scalar values, so we convert positions as they arrive and leave.
+/// This is the position in `text` of a `position` received from the client.
+fn from_lsp(text: &str, position: Position) -> Position {
+    let line = text.lines().nth(position.line as usize).unwrap_or_default();
+    let mut units = 0;
+    let mut chars = 0;
+    for c in line.chars() {
+        if units >= position.character as usize {
+            break;
+        }
+        units += c.len_utf16();
+        chars += 1;
+    }
+    // Positions beyond the end of the line are kept beyond it.
+    let beyond = (position.character as usize).saturating_sub(units);
+    Position::new(position.line, (chars + beyond) as u32)
+}
+
+/// The position to send to the client for a `position` in `text`, the inverse of
+/// [`from_lsp`].
+fn to_lsp(text: &str, position: Position) -> Position {
+    let line = text.lines().nth(position.line as usize).unwrap_or_default();
+    let mut units = 0;
+    let mut chars = 0;
+    for c in line.chars().take(position.character as usize) {
+        units += c.len_utf16();
+        chars += 1;
+    }
+    let beyond = (position.character as usize).saturating_sub(chars);
+    Position::new(position.line, (units + beyond) as u32)
+}
+
+/// The range to send to the client for a `range` in `text`.
+fn range_to_lsp(text: &str, range: Range) -> Range {
+    Range::new(to_lsp(text, range.start), to_lsp(text, range.end))
+}
+
+/// A diagnostic for `text`, with its ranges converted to send to the client.
+/// Related information in other files is left alone, as we don't have their text.
+fn diagnostic_to_lsp(text: &str, uri: &Url, mut diag: Diagnostic) -> Diagnostic {
+    diag.range = range_to_lsp(text, diag.range);
+    for x in diag.related_information.iter_mut().flatten() {
+        if &x.location.uri == uri {
+            x.location.range = range_to_lsp(text, x.location.range);
+        }
+    }
+    diag
+}
+
+/// A diagnostic for a file which failed to parse.
+fn error_diagnostic(x: anyhow::Error) -> Diagnostic {
+    let (range, message) = match x.downcast_ref::<StarlarkDiagnostic>() {
+        Some(StarlarkDiagnostic {
+            message,
+            span: Some(span),
+            ..
+        }) => (to_range(span.resolve_span()), format!("{:#}", message)),
+        _ => (Range::default(), format!("{:#}", x)),
+    };
+    Diagnostic::new(
+        range,
+        Some(DiagnosticSeverity::Error),
+        Some(NumberOrString::String("error".to_owned())),
+        None,
+        message,
+        None,
+        None,
+    )
+}
+
+/// A diagnostic for a lint, which is only shown as information unless it's serious.
+/// The problems found by the type checker were asked for, so are warnings or errors.
+fn lint_diagnostic(x: Lint, typecheck: bool) -> Diagnostic {
+    let severity = match (x.serious, typecheck) {
+        (true, true) => DiagnosticSeverity::Error,
+        (true, false) | (false, true) => DiagnosticSeverity::Warning,
+        (false, false) => DiagnosticSeverity::Information,
+    };
+    // Files are parsed with the document URI as their filename
+    let related: Vec<_> = x
+        .related
+        .into_iter()
+        .filter_map(|(span, message)| {
+            Some(DiagnosticRelatedInformation {
+                location: Location::new(
+                    Url::parse(span.file.filename()).ok()?,
+                    to_range(span.resolve_span()),
+                ),
+                message,
+            })
+        })
+        .collect();
+    Diagnostic::new(
+        to_range(x.location.resolve_span()),
+        Some(severity),
+        Some(NumberOrString::String(x.short_name)),
+        None,
+        x.problem,
+        if related.is_empty() {
+            None
+        } else {
+            Some(related)
+        },
+        None,
+    )
+}
+
+/// Render the documentation of a builtin as Markdown, with its signature if it's a function.
+fn hover_docs(name: &str, item: &DocItem) -> String {
+    let (docs, signature) = match item {
+        DocItem::Function(x) => {
+            let params = x.params.iter().map(|x| match x {
+                Param::Arg {
+                    name,
+                    typ,
+                    default_value,
+                    ..
+                } => {
+                    let mut res = name.clone();
+                    if let Some(typ) = typ {
+                        res.push_str(&format!(": {}", typ.raw_type));
+                    }
+                    if let Some(default) = default_value {
+                        res.push_str(&format!(" = {}", default));
+                    }
+                    res
+                }
+                Param::NoArgs => "*".to_owned(),
+                Param::Args { name, .. } => format!("*{}", name),
+                Param::Kwargs { name, .. } => format!("**{}", name),
+            });
+            let mut signature = format!("def {}({})", name, params.join(", "));
+            if let Some(typ) = &x.ret.typ {
+                signature.push_str(&format!(" -> {}", typ.raw_type));
+            }
+            (&x.docs, signature)
+        }
+        DocItem::Object(x) => (&x.docs, name.to_owned()),
+        DocItem::Module(x) => (&x.docs, name.to_owned()),
+    };
+    let mut res = format!("```python\n{}\n```", signature);
+    if let Some(docs) = docs {
+        res.push_str(&format!("\n\n{}", docs.summary));
+        if let Some(details) = &docs.details {
+            res.push_str(&format!("\n\n{}", details));
+        }
+    }
+    res
+}
+
+fn code_lens(title: &str, command: &str, range: Range, arguments: &[&str]) -> CodeLens {
+    CodeLens {
+        range,
+        command: Some(Command {
+            title: title.to_owned(),
+            command: command.to_owned(),
+            arguments: Some(
+                arguments
+                    .iter()
+                    .map(|x| serde_json::Value::String((*x).to_owned()))
+                    .collect(),
+            ),
+        }),
+        data: None,
+    }
+}
+
+fn load_diagnostic(span: ResolvedSpan, message: String) -> Diagnostic {
+    Diagnostic::new(
+        to_range(span),
+        Some(DiagnosticSeverity::Warning),
+        Some(NumberOrString::String("unresolved-load".to_owned())),
+        None,
+        message,
+        None,
+        None,
+    )
+}
+
+fn contains(x: Range, position: Position) -> bool {
+    x.start <= position && position <= x.end
+}
+
+fn overlaps(x: Range, y: Range) -> bool {
+    x.start <= y.end && y.start <= x.end
+}
+
+fn to_completion_kind(x: SymbolKind) -> CompletionItemKind {
+    match x {
+        SymbolKind::Load => CompletionItemKind::Module,
+        SymbolKind::Function => CompletionItemKind::Function,
+        SymbolKind::Parameter | SymbolKind::Variable => CompletionItemKind::Variable,
+    }
+}
+
+fn to_symbol_kind(x: SymbolKind) -> LspSymbolKind {
+    match x {
+        SymbolKind::Load => LspSymbolKind::Module,
+        SymbolKind::Function => LspSymbolKind::Function,
+        SymbolKind::Parameter | SymbolKind::Variable => LspSymbolKind::Variable,
+    }
+}
+
+// The `deprecated` field is itself deprecated, but we still have to fill it in.
+#[allow(deprecated)]
+fn to_document_symbol(text: &str, x: Symbol) -> DocumentSymbol {
+    DocumentSymbol {
+        name: x.name,
+        detail: None,
+        kind: to_symbol_kind(x.kind),
+        tags: None,
+        deprecated: None,
+        range: range_to_lsp(text, to_range(x.span)),
+        selection_range: range_to_lsp(text, to_range(x.name_span)),
+        children: if x.children.is_empty() {
+            None
+        } else {
+            Some(
+                x.children
+                    .into_iter()
+                    .map(|x| to_document_symbol(text, x))
+                    .collect(),
+            )
+        },
+    }
+}
+
+#[allow(deprecated)]
+fn to_symbol_information(text: &str, x: Symbol, uri: &Url) -> SymbolInformation {
+    SymbolInformation {
+        name: x.name,
+        kind: to_symbol_kind(x.kind),
+        tags: None,
+        deprecated: None,
+        location: Location::new(uri.clone(), range_to_lsp(text, to_range(x.name_span))),
+        container_name: None,
+    }
+}
+
+fn to_folders(xs: Vec<WorkspaceFolder>) -> Vec<PathBuf> {
+    xs.into_iter()
+        .filter_map(|x| x.uri.to_file_path().ok())
+        .collect()
+}
+
+fn completion_item(label: &str, kind: CompletionItemKind) -> CompletionItem {
+    CompletionItem {
+        label: label.to_owned(),
+        kind: Some(kind),
+        ..CompletionItem::default()
+    }
+}
+
+fn is_ident_char(x: char) -> bool {
+    x.is_alphanumeric() || x == '_'
+}
+
+/// If the text before the cursor is an attribute access, e.g. `foo.ba`,
+/// return the text preceding the `.`, e.g. `foo`.
+fn attribute_receiver(before: &str) -> Option<&str> {
+    let before = before.trim_end_matches(is_ident_char);
+    let receiver = before.strip_suffix('.')?;
+    Some(receiver.trim_end())
+}
+
+/// The identifier under the cursor, if any. Attribute names, like the `foo` in `x.foo`,
+/// don't count.
+fn identifier_at(text: &str, position: Position) -> Option<&str> {
+    let line = text.lines().nth(position.line as usize)?;
+    let offset = line
+        .char_indices()
+        .nth(position.character as usize)
+        .map_or(line.len(), |(i, _)| i);
+    let start = line[..offset].trim_end_matches(is_ident_char).len();
+    let end = line.len() - line[offset..].trim_start_matches(is_ident_char).len();
+    if start == end || line[..start].ends_with('.') {
+        None
+    } else {
+        Some(&line[start..end])
+    }
+}
+
+/// The byte offset of a position in `text`, clamped to the end of its line.
+/// Like the rest of the server, counts characters as Unicode scalar values, so positions
+/// from the client must be converted with [`from_lsp`] first.
+fn position_offset(text: &str, position: Position) -> usize {
+    let mut offset = 0;
+    for (i, line) in text.split_inclusive('\n').enumerate() {
+        if i == position.line as usize {
+            let line = line.strip_suffix('\n').unwrap_or(line);
+            return offset
+                + line
+                    .char_indices()
+                    .nth(position.character as usize)
+                    .map_or(line.len(), |(i, _)| i);
+        }
+        offset += line.len();
+    }
+    offset
+}
+
+/// The position of a byte offset in `text`, the inverse of [`position_offset`].
+fn offset_position(text: &str, offset: usize) -> Position {
+    end_position(&text[..offset])
+}
+
+/// Where the cursor is in a `load()`, if it's in one of its strings.
+#[derive(Debug, PartialEq)]
+enum LoadArgument {
+    /// The module being loaded, with what has been typed of it so far, and where it starts.
+    Module(String, Position),
+    /// A symbol to load from the module, with what has been typed of the symbol so far,
+    /// and where it starts.
+    Symbol(String, String, Position),
+}
+
+/// Work out if the cursor is in a string in a `load()`. Works on the text, rather than the
+/// AST, since the code being typed rarely parses.
+fn load_argument(text: &str, position: Position) -> Option<LoadArgument> {
+    let offset = position_offset(text, position);
+    let (open, _) = scan_brackets(&text[..offset]);
+    let paren = position_offset(text, *open.last()?);
+    let before = text[..paren].trim_end().strip_suffix("load")?;
+    if before.ends_with(is_ident_char) || !text[paren..].starts_with('(') {
+        return None;
+    }
+    // Walk the arguments, noting the module and the string we end up in
+    let mut module = None;
+    let mut argument = 0;
+    let mut string: Option<(char, usize)> = None;
+    let mut chars = text[paren + 1..offset].char_indices();
+    while let Some((i, c)) = chars.next() {
+        let i = paren + 1 + i;
+        match string {
+            Some((quote, start)) if c == quote => {
+                if argument == 0 {
+                    module = Some(text[start..i].to_owned());
+                }
+                string = None;
+            }
+            Some(_) if c == '\\' => {
+                chars.next();
+            }
+            Some(_) => {}
+            None if c == '"' || c == '\'' => string = Some((c, i + 1)),
+            None if c == ',' => argument += 1,
+            None => {}
+        }
+    }
+    let (_, start) = string?;
+    let typed = text[start..offset].to_owned();
+    let start = offset_position(text, start);
+    if argument == 0 {
+        Some(LoadArgument::Module(typed, start))
+    } else {
+        Some(LoadArgument::Symbol(module?, typed, start))
+    }
+}
+
+/// The position just after the last character of `text`.
+fn end_position(text: &str) -> Position {
+    let line = text.matches('\n').count();
+    let last = &text[text.rfind('\n').map_or(0, |x| x + 1)..];
+    Position::new(line as u32, last.chars().count() as u32)
+}
+
+/// Scan `text` for brackets, skipping those in strings and comments. Returns the positions
+/// of the brackets still open at the end, innermost last, and for each line the number of
+/// brackets open at its start, or `None` if it starts inside a string.
+fn scan_brackets(text: &str) -> (Vec<Position>, Vec<Option<usize>>) {
+    let chars: Vec<char> = text.chars().collect();
+    let mut open = Vec::new();
+    let mut lines = vec![Some(0)];
+    // The quote and whether it's tripled
+    let mut string: Option<(char, bool)> = None;
+    let mut comment = false;
+    let mut pos = Position::new(0, 0);
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let triple = chars[i..].starts_with(&[c, c, c]);
+        let mut len = 1;
+        match string {
+            _ if comment => comment = c != '\n',
+            Some((quote, tripled)) => {
+                if c == '\\' {
+                    len = 2;
+                } else if c == quote && (!tripled || triple) {
+                    string = None;
+                    if tripled {
+                        len = 3;
+                    }
+                } else if c == '\n' && !tripled {
+                    // An unterminated string, which the parser will complain about
+                    string = None;
+                }
+            }
+            None => match c {
+                '#' => comment = true,
+                '"' | '\'' => {
+                    string = Some((c, triple));
+                    if triple {
+                        len = 3;
+                    }
+                }
+                '(' | '[' | '{' => open.push(pos),
+                ')' | ']' | '}' => {
+                    open.pop();
+                }
+                _ => {}
+            },
+        }
+        for c in &chars[i..(i + len).min(chars.len())] {
+            if *c == '\n' {
+                pos = Position::new(pos.line + 1, 0);
+                lines.push(if string.is_some() {
+                    None
+                } else {
+                    Some(open.len())
+                });
+            } else {
+                pos.character += 1;
+            }
+        }
+        i += len;
+    }
+    (open, lines)
+}
+
+fn indentation(line: &str) -> usize {
+    line.chars().take_while(|c| *c == ' ' || *c == '\t').count()
+}
+
+/// Replace the indentation of a line, if it differs.
+fn reindent(line: u32, text: &str, indent: usize) -> Option<TextEdit> {
+    let old = indentation(text);
+    if old == indent && text.starts_with(&" ".repeat(indent)) {
+        return None;
+    }
+    Some(TextEdit {
+        range: Range::new(Position::new(line, 0), Position::new(line, old as u32)),
+        new_text: " ".repeat(indent),
+    })
+}
+
+/// Where the lines inside a bracket should start: aligned after the bracket if it is
+/// followed by anything on its line, otherwise one level in from that line.
+fn continuation_indent(lines: &[&str], open: Position, tab: usize) -> usize {
+    let line = lines[open.line as usize];
+    let after: String = line.chars().skip(open.character as usize + 1).collect();
+    let after = after.split('#').next().unwrap_or_default();
+    if after.trim().is_empty() {
+        indentation(line) + tab
+    } else {
+        open.character as usize + 1
+    }
+}
+
+/// Indent a new line: one level in after a `:`, one level out after a statement which ends
+/// a block such as `return`, and inside brackets as a continuation of the line above.
+fn indent_new_line(text: &str, line: u32, tab: usize) -> Option<TextEdit> {
+    let lines: Vec<&str> = text.lines().collect();
+    let before = &text[..position_offset(text, Position::new(line, 0))];
+    let (open, depths) = scan_brackets(before);
+    let indent = if let Some(open) = open.last() {
+        continuation_indent(&lines, *open, tab)
+    } else {
+        let previous = (0..(line as usize).min(lines.len()))
+            .rev()
+            .find(|i| !lines[*i].trim().is_empty())?;
+        // The line the statement ending on the previous line started on
+        let start = (0..=previous)
+            .rev()
+            .find(|i| depths.get(*i).copied().flatten() == Some(0))
+            .unwrap_or(previous);
+        let code = lines[previous]
+            .split('#')
+            .next()
+            .unwrap_or_default()
+            .trim_end();
+        let first_word = lines[start]
+            .split(|c| !is_ident_char(c))
+            .find(|x| !x.is_empty());
+        let indent = indentation(lines[start]);
+        if code.ends_with(':') {
+            indent + tab
+        } else if matches!(first_word, Some("return" | "pass" | "break" | "continue")) {
+            indent.saturating_sub(tab)
+        } else {
+            indent
+        }
+    };
+    reindent(
+        line,
+        lines.get(line as usize).copied().unwrap_or_default(),
+        indent,
+    )
+}
+
+/// Once an `else:` or `elif ...:` is complete, align it with its `if`.
+fn align_else(text: &str, line: u32) -> Option<TextEdit> {
+    let lines: Vec<&str> = text.lines().collect();
+    let current = *lines.get(line as usize)?;
+    let trimmed = current.trim();
+    if !(trimmed == "else:" || trimmed.starts_with("elif ") && trimmed.ends_with(':')) {
+        return None;
+    }
+    // Only an `if` indented no further than a statement between it and us can match
+    let mut limit = indentation(current);
+    for x in lines[..line as usize].iter().rev() {
+        let indent = indentation(x);
+        if x.trim().is_empty() || indent > limit {
+            continue;
+        }
+        let x = x.trim_start();
+        if x.starts_with("if ") || x.starts_with("elif ") {
+            return reindent(line, current, indent);
+        }
+        limit = indent.checked_sub(1)?;
+    }
+    None
+}
+
+/// Once a bracket which spans several lines is closed, align the lines inside it, and put
+/// the closing bracket, if it starts its line, back at the indentation of the opening line.
+fn align_brackets(text: &str, closed: Position, tab: usize) -> Vec<TextEdit> {
+    let lines: Vec<&str> = text.lines().collect();
+    let (open, depths) = scan_brackets(&text[..position_offset(text, closed)]);
+    let depth = Some(open.len());
+    let open = match open.last() {
+        Some(open) if open.line < closed.line && (closed.line as usize) < lines.len() => *open,
+        _ => return Vec::new(),
+    };
+    let indent = continuation_indent(&lines, open, tab);
+    let mut res = Vec::new();
+    for line in open.line + 1..=closed.line {
+        let text = lines[line as usize];
+        let before: String = text.chars().take(closed.character as usize).collect();
+        let edit = if line == closed.line && before.trim().is_empty() {
+            reindent(line, text, indentation(lines[open.line as usize]))
+        } else if depths[line as usize] == depth && !text.trim().is_empty() {
+            reindent(line, text, indent)
+        } else {
+            None
+        };
+        res.extend(edit);
+    }
+    res
+}
+
+/// The logic implementations of stuff
+impl Backend {
+    fn server_capabilities() -> ServerCapabilities {
+        ServerCapabilities {
+            text_document_sync: Some(TextDocumentSyncCapability::Kind(
+                TextDocumentSyncKind::Incremental,
+            )),
+            completion_provider: Some(CompletionOptions {
+                trigger_characters: Some(vec![
+                    ".".to_owned(),
+                    "\"".to_owned(),
+                    "'".to_owned(),
+                    "/".to_owned(),
+                    ":".to_owned(),
+                ]),
+                ..CompletionOptions::default()
+            }),
+            document_symbol_provider: Some(OneOf::Left(true)),
+            document_formatting_provider: Some(OneOf::Left(true)),
+            document_range_formatting_provider: Some(OneOf::Left(true)),
+            document_on_type_formatting_provider: Some(DocumentOnTypeFormattingOptions {
+                first_trigger_character: "\n".to_owned(),
+                more_trigger_character: Some(vec![
+                    ":".to_owned(),
+                    ")".to_owned(),
+                    "]".to_owned(),
+                    "}".to_owned(),
+                ]),
+            }),
+            code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
+            definition_provider: Some(OneOf::Left(true)),
+            hover_provider: Some(HoverProviderCapability::Simple(true)),
+            document_highlight_provider: Some(OneOf::Left(true)),
+            folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+            code_lens_provider: Some(CodeLensOptions {
+                resolve_provider: Some(false),
+            }),
+            workspace_symbol_provider: Some(OneOf::Left(true)),
+            workspace: Some(WorkspaceServerCapabilities {
+                workspace_folders: Some(WorkspaceFoldersServerCapabilities {
+                    supported: Some(true),
+                    change_notifications: Some(OneOf::Left(true)),
+                }),
+                file_operations: None,
+            }),
+            ..ServerCapabilities::default()
+        }
+    }
+
+    fn parse(&self, uri: &Url, text: String) -> anyhow::Result<AstModule> {
+        AstModule::parse(uri.as_str(), text, &self.context.dialect)
+    }
+
+    fn validate(&self, uri: Url, version: Option<i64>, text: String) {
+        let (ast, lints, diags) = match self.parse(&uri, text.clone()) {
+            Ok(ast) => {
+                let lints = self.check(&ast);
+                let diags = self.load_diagnostics(&uri, &ast);
+                (Some(ast), lints, diags)
+            }
+            Err(e) => (None, Vec::new(), vec![error_diagnostic(e)]),
+        };
+        let mut diags: Vec<_> = diags
+            .into_iter()
+            .filter_map(|x| self.configure(x))
+            .map(|x| diagnostic_to_lsp(&text, &uri, x))
+            .collect();
+        self.update_document(&uri, &text, ast);
+        let mut fixes = Vec::new();
+        for (mut x, typecheck) in lints {
+            let fix = x.fix.take();
+            let diag = match self.configure(lint_diagnostic(x, typecheck)) {
+                Some(diag) => diagnostic_to_lsp(&text, &uri, diag),
+                None => continue,
+            };
+            if let Some(fix) = fix {
+                fixes.push((diag.clone(), fix));
+            }
+            diags.push(diag);
+        }
+        if let Some(doc) = self.documents.borrow_mut().get_mut(&uri) {
+            doc.fixes = fixes;
+        }
+        self.publish_diagnostics(uri, diags, version)
+    }
+
+    /// The lints for a document, each with whether it came from the type checker.
+    fn check(&self, ast: &AstModule) -> Vec<(Lint, bool)> {
+        let names = if self.context.undefined_names {
+            let mut names = self.context.globals.names();
+            for module in &self.context.prelude {
+                names.extend(module.names().map(str::to_owned));
+            }
+            Some(names)
+        } else {
+            None
+        };
+        let names = names.as_ref().map(|x| x.map(|x| x.as_str()));
+        let mut res = ast.lint(names.as_deref()).into_map(|x| (x, false));
+        if self.context.typecheck {
+            res.extend(ast.typecheck().into_iter().map(|x| (x, true)));
+        }
+        res
+    }
+
+    /// Apply the severity the user configured for the lint, or `None` if they turned it off.
+    fn configure(&self, mut diag: Diagnostic) -> Option<Diagnostic> {
+        let name = match &diag.code {
+            Some(NumberOrString::String(name)) => name,
+            _ => return Some(diag),
+        };
+        diag.severity = match self.settings.borrow().lints.get(name) {
+            None => return Some(diag),
+            Some(LintSeverity::Off) => return None,
+            Some(LintSeverity::Error) => Some(DiagnosticSeverity::Error),
+            Some(LintSeverity::Warning) => Some(DiagnosticSeverity::Warning),
+            Some(LintSeverity::Info) => Some(DiagnosticSeverity::Information),
+            Some(LintSeverity::Hint) => Some(DiagnosticSeverity::Hint),
+        };
+        Some(diag)
+    }
+
+    fn update_document(&self, uri: &Url, text: &str, ast: Option<AstModule>) {
+        let mut documents = self.documents.borrow_mut();
+        match documents.get_mut(uri) {
+            Some(doc) => {
+                doc.text = text.to_owned();
+                if ast.is_some() {
+                    doc.ast = ast;
+                }
+            }
+            None => {
+                documents.insert(
+                    uri.clone(),
+                    Document {
+                        text: text.to_owned(),
+                        ast,
+                        fixes: Vec::new(),
+                    },
+                );
+            }
+        }
+    }
+
+    /// The innermost workspace folder containing `file`, if any.
+    fn folder_of(&self, file: &Path) -> Option<PathBuf> {
+        self.folders
+            .borrow()
+            .iter()
+            .filter(|folder| file.starts_with(folder))
+            .max_by_key(|folder| folder.components().count())
+            .cloned()
+    }
+
+    /// The contents of `file`, preferring those of an open document, which may have unsaved
+    /// changes, to what is on disk.
+    fn read_file(&self, uri: &Url, file: &Path) -> std::io::Result<String> {
+        match self.documents.borrow().get(uri) {
+            Some(doc) => Ok(doc.text.clone()),
+            None => fs::read_to_string(file),
+        }
+    }
+
+    /// Resolve and parse the module loaded as `path` from `current_file`.
+    fn load_module(&self, path: &str, current_file: &Path) -> anyhow::Result<(Url, AstModule)> {
+        let folder = self.folder_of(current_file);
+        let file = self
+            .resolver
+            .resolve_load(path, current_file, folder.as_deref())?;
+        let uri = Url::from_file_path(&file).map_err(|()| {
+            anyhow!(
+                "Can't resolve `{}`, `{}` is not an absolute path",
+                path,
+                file.display()
+            )
+        })?;
+        let text = self
+            .read_file(&uri, &file)
+            .with_context(|| format!("Can't read `{}`, loaded as `{}`", file.display(), path))?;
+        let ast = self
+            .parse(&uri, text)
+            .with_context(|| format!("Can't parse `{}`, loaded as `{}`", file.display(), path))?;
+        Ok((uri, ast))
+    }
+
+    /// Warnings for `load()` statements whose module can't be resolved, or which load symbols
+    /// the module doesn't export.
+    fn load_diagnostics(&self, uri: &Url, ast: &AstModule) -> Vec<Diagnostic> {
+        let current_file = match uri.to_file_path() {
+            Ok(x) => x,
+            Err(_) => return Vec::new(),
+        };
+        let mut res = Vec::new();
+        // The exports of each module, or `None` if it couldn't be loaded
+        let mut modules: HashMap<&str, Option<Vec<String>>> = HashMap::new();
+        for x in ast.loaded_symbols() {
+            let exports = modules.entry(x.module).or_insert_with(|| {
+                match self.load_module(x.module, &current_file) {
+                    Ok((_, module)) => Some(
+                        module
+                            .exported_symbols()
+                            .into_iter()
+                            .map(|(_, name)| name.to_owned())
+                            .collect(),
+                    ),
+                    Err(e) => {
+                        res.push(load_diagnostic(x.module_span, format!("{:#}", e)));
+                        None
+                    }
+                }
+            });
+            if let Some(exports) = exports {
+                if !exports.iter().any(|name| name == x.loaded) {
+                    res.push(load_diagnostic(
+                        x.name_span,
+                        format!("Module `{}` does not export `{}`", x.module, x.loaded),
+                    ));
+                }
+            }
+        }
+        res
+    }
+
+    /// Go to the module of a `load()`, or to the definition of a loaded symbol. Definitions
+    /// within the current file are not yet supported.
+    fn goto_definition(&self, params: GotoDefinitionParams) -> Option<GotoDefinitionResponse> {
+        let uri = params.text_document_position_params.text_document.uri;
+        let position = params.text_document_position_params.position;
+        let current_file = uri.to_file_path().ok()?;
+        let (module, symbol) = {
+            let documents = self.documents.borrow();
+            let doc = documents.get(&uri)?;
+            let ast = doc.ast.as_ref()?;
+            let position = from_lsp(&doc.text, position);
+            let loaded = ast.loaded_symbols();
+            match loaded
+                .iter()
+                .find(|x| contains(to_range(x.module_span), position))
+            {
+                Some(x) => (x.module.to_owned(), None),
+                None => {
+                    let identifier = identifier_at(&doc.text, position);
+                    let x = loaded.iter().find(|x| {
+                        contains(to_range(x.name_span), position) || Some(x.name) == identifier
+                    })?;
+                    (x.module.to_owned(), Some(x.loaded.to_owned()))
+                }
+            }
+        };
+        let (target, ast) = self.load_module(&module, &current_file).ok()?;
+        let range = symbol
+            .and_then(|symbol| {
+                ast.exported_symbols()
+                    .into_iter()
+                    .find(|(_, name)| *name == symbol)
+                    .map(|(span, _)| {
+                        range_to_lsp(ast.codemap.source(), to_range(span.resolve_span()))
+                    })
+            })
+            .unwrap_or_default();
+        Some(GotoDefinitionResponse::Scalar(Location::new(target, range)))
+    }
+
+    /// The attributes available on the expression ending `receiver`, as far as we can tell
+    /// syntactically. We know about literals of builtin types, and about globals.
+    fn attribute_completions(&self, receiver: &str) -> Vec<CompletionItem> {
+        let heap = Heap::new();
+        let value: Option<Value> = match receiver.chars().last() {
+            Some('"') | Some('\'') => Some(heap.alloc_str("")),
+            Some(']') => Some(heap.alloc_list(&[])),
+            Some('}') => Some(heap.alloc(Dict::default())),
+            _ => None,
+        };
+        let names = match value {
+            Some(value) => value.dir_attr(),
+            None => {
+                let ident = &receiver[receiver.trim_end_matches(is_ident_char).len()..];
+                self.context
+                    .globals
+                    .attribute_names(ident)
+                    .unwrap_or_default()
+            }
+        };
+        names
+            .iter()
+            .map(|x| completion_item(x, CompletionItemKind::Method))
+            .collect()
+    }
+
+    fn document_symbols(&self, params: DocumentSymbolParams) -> DocumentSymbolResponse {
+        let documents = self.documents.borrow();
+        let symbols = match documents.get(&params.text_document.uri) {
+            Some(Document {
+                text,
+                ast: Some(ast),
+                ..
+            }) => ast
+                .symbols()
+                .into_iter()
+                .map(|x| to_document_symbol(text, x))
+                .collect(),
+            _ => Vec::new(),
+        };
+        DocumentSymbolResponse::Nested(symbols)
+    }
+
+    /// The reads and writes of the variable under the cursor. If the document doesn't
+    /// currently parse, we highlight nothing.
+    /// The documentation of the builtin under the cursor, unless a variable of the same name
+    /// defined in the file is in scope there.
+    fn hover(&self, params: HoverParams) -> Option<Hover> {
+        let documents = self.documents.borrow();
+        let position = params.text_document_position_params;
+        let doc = documents.get(&position.text_document.uri)?;
+        let position = from_lsp(&doc.text, position.position);
+        let name = identifier_at(&doc.text, position)?;
+        if let Some(ast) = &doc.ast {
+            let scope = ast.names_in_scope(position.line as usize, position.character as usize);
+            if scope.iter().any(|(x, _)| *x == name) {
+                return None;
+            }
+        }
+        let item = self
+            .context
+            .globals
+            .member_documentation()
+            .remove(name)
+            .flatten()?;
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: hover_docs(name, &item),
+            }),
+            range: None,
+        })
+    }
+
+    fn document_highlights(&self, params: DocumentHighlightParams) -> Vec<DocumentHighlight> {
+        let documents = self.documents.borrow();
+        let uri = params.text_document_position_params.text_document.uri;
+        let (text, ast) = match documents.get(&uri) {
+            Some(doc) => match self.parse(&uri, doc.text.clone()) {
+                Ok(ast) => (&doc.text, ast),
+                Err(_) => return Vec::new(),
+            },
+            None => return Vec::new(),
+        };
+        let Position { line, character } =
+            from_lsp(text, params.text_document_position_params.position);
+        ast.highlights(line as usize, character as usize)
+            .into_iter()
+            .map(|x| DocumentHighlight {
+                range: range_to_lsp(text, to_range(x.span)),
+                kind: Some(match x.kind {
+                    HighlightKind::Read => DocumentHighlightKind::Read,
+                    HighlightKind::Write => DocumentHighlightKind::Write,
+                }),
+            })
+            .collect()
+    }
+
+    /// The top-level definitions whose names contain the query, ignoring case, in the files of
+    /// every workspace folder and every open document. A file in several (nested) folders is
+    /// only searched once.
+    fn workspace_symbols(&self, params: WorkspaceSymbolParams) -> Vec<SymbolInformation> {
+        let query = params.query.to_lowercase();
+        let mut files: Vec<PathBuf> = self
+            .documents
+            .borrow()
+            .keys()
+            .filter_map(|uri| uri.to_file_path().ok())
+            .collect();
+        for folder in self.folders.borrow().iter() {
+            files.extend(
+                WalkDir::new(folder)
+                    .into_iter()
+                    .filter_map(|e| e.ok())
+                    .filter(|e| {
+                        e.path()
+                            .extension()
+                            .map_or(false, |ext| EXTENSIONS.iter().any(|x| OsStr::new(x) == ext))
+                    })
+                    .map(|e| e.into_path()),
+            );
+        }
+        let mut seen = HashSet::new();
+        let mut res = Vec::new();
+        for file in files {
+            if !seen.insert(file.clone()) {
+                continue;
+            }
+            let uri = match Url::from_file_path(&file) {
+                Ok(uri) => uri,
+                Err(()) => continue,
+            };
+            let ast = match self
+                .read_file(&uri, &file)
+                .ok()
+                .and_then(|text| self.parse(&uri, text).ok())
+            {
+                Some(ast) => ast,
+                None => continue,
+            };
+            for x in ast.symbols() {
+                if x.kind != SymbolKind::Load && x.name.to_lowercase().contains(&query) {
+                    res.push(to_symbol_information(ast.codemap.source(), x, &uri));
+                }
+            }
+        }
+        res
+    }
+
+    /// Reformat the whole document, replacing it with a single edit.
+    /// If the document doesn't currently parse, we leave it alone.
+    fn folding_ranges(&self, params: FoldingRangeParams) -> Option<Vec<FoldingRange>> {
+        let documents = self.documents.borrow();
+        let ast = documents.get(&params.text_document.uri)?.ast.as_ref()?;
+        Some(
+            ast.folding_ranges()
+                .into_iter()
+                .map(|x| FoldingRange {
+                    start_line: x.start_line as u32,
+                    start_character: None,
+                    end_line: x.end_line as u32,
+                    end_character: None,
+                    kind: match x.kind {
+                        FoldingRangeKind::Loads => Some(LspFoldingRangeKind::Imports),
+                        _ => Some(LspFoldingRangeKind::Region),
+                    },
+                })
+                .collect(),
+        )
+    }
+
+    fn formatting(&self, params: DocumentFormattingParams) -> Option<Vec<TextEdit>> {
+        let documents = self.documents.borrow();
+        let uri = params.text_document.uri;
+        let text = &documents.get(&uri)?.text;
+        let ast = self.parse(&uri, text.clone()).ok()?;
+        let new_text = ast.format();
+        if &new_text == text {
+            return None;
+        }
+        Some(vec![TextEdit {
+            range: Range::new(Position::new(0, 0), to_lsp(text, end_position(text))),
+            new_text,
+        }])
+    }
+
+    /// Reformat the top-level statements which overlap the range, leaving the rest
+    /// of the document alone.
+    fn range_formatting(&self, params: DocumentRangeFormattingParams) -> Option<Vec<TextEdit>> {
+        let documents = self.documents.borrow();
+        let uri = params.text_document.uri;
+        let text = &documents.get(&uri)?.text;
+        let ast = self.parse(&uri, text.clone()).ok()?;
+        let Range { start, end } = params.range;
+        // A range ending at the start of a line doesn't include that line
+        let end_line = if end.character == 0 && end.line > start.line {
+            end.line
+        } else {
+            end.line + 1
+        };
+        let (lines, new_text) = ast.format_lines(start.line as usize..end_line as usize)?;
+        let old_text = text
+            .split_inclusive('\n')
+            .skip(lines.start)
+            .take(lines.len())
+            .collect::<String>();
+        if new_text == old_text {
+            return None;
+        }
+        // The last line might not end with a newline, in which case replace up to the end
+        let end = if lines.end > text.matches('\n').count() {
+            to_lsp(text, end_position(text))
+        } else {
+            Position::new(lines.end as u32, 0)
+        };
+        Some(vec![TextEdit {
+            range: Range::new(Position::new(lines.start as u32, 0), end),
+            new_text,
+        }])
+    }
+
+    /// Fix up the indentation around what was just typed. This works on the text rather than
+    /// the AST, since code being typed rarely parses.
+    fn on_type_formatting(&self, params: DocumentOnTypeFormattingParams) -> Option<Vec<TextEdit>> {
+        let documents = self.documents.borrow();
+        let position = params.text_document_position;
+        let text = &documents.get(&position.text_document.uri)?.text;
+        // The edits only change indentation, which is the same in UTF-16.
+        let position = from_lsp(text, position.position);
+        let tab = params.options.tab_size as usize;
+        let res = match params.ch.as_str() {
+            "\n" => indent_new_line(text, position.line, tab)
+                .into_iter()
+                .collect(),
+            ":" => align_else(text, position.line).into_iter().collect(),
+            _ => {
+                let closed = Position::new(position.line, position.character.checked_sub(1)?);
+                align_brackets(text, closed, tab)
+            }
+        };
+        Some(res)
+    }
+
+    /// Quick fixes for the diagnostics which overlap the range.
+    /// Lenses to run or debug the whole file, at the top of the file, and to run or debug each
+    /// test function (a top-level `def` whose name starts with `test_`), above its definition.
+    fn code_lens(&self, params: CodeLensParams) -> Option<Vec<CodeLens>> {
+        let documents = self.documents.borrow();
+        let doc = documents.get(&params.text_document.uri)?;
+        let uri = params.text_document.uri.as_str();
+        let mut res = vec![
+            code_lens("Run file", RUN_COMMAND, Range::default(), &[uri]),
+            code_lens("Debug file", DEBUG_COMMAND, Range::default(), &[uri]),
+        ];
+        if let Some(ast) = &doc.ast {
+            for x in ast.symbols() {
+                if x.kind == SymbolKind::Function && x.name.starts_with("test_") {
+                    let range = range_to_lsp(&doc.text, to_range(x.span));
+                    let args = [uri, x.name.as_str()];
+                    res.push(code_lens("Run test", RUN_COMMAND, range, &args));
+                    res.push(code_lens("Debug test", DEBUG_COMMAND, range, &args));
+                }
+            }
+        }
+        Some(res)
+    }
+
+    fn code_action(&self, params: CodeActionParams) -> CodeActionResponse {
+        let documents = self.documents.borrow();
+        let uri = params.text_document.uri;
+        let (text, fixes) = match documents.get(&uri) {
+            Some(doc) => (&doc.text, &doc.fixes),
+            None => return Vec::new(),
+        };
+        fixes
+            .iter()
+            .filter(|(diag, _)| overlaps(diag.range, params.range))
+            .map(|(diag, fix)| {
+                let edits = fix
+                    .edits
+                    .iter()
+                    .map(|(span, new_text)| {
+                        TextEdit::new(range_to_lsp(text, to_range(*span)), new_text.clone())
+                    })
+                    .collect();
+                CodeActionOrCommand::CodeAction(CodeAction {
+                    title: fix.title.clone(),
+                    kind: Some(CodeActionKind::QUICKFIX),
+                    diagnostics: Some(vec![diag.clone()]),
+                    edit: Some(WorkspaceEdit::new(
+                        iter::once((uri.clone(), edits)).collect(),
+                    )),
+                    is_preferred: Some(true),
+                    ..CodeAction::default()
+                })
+            })
+            .collect()
+    }
+
+    /// The types of assigned variables, and the parameters that arguments are passed to,
+    /// within the range. If the document doesn't currently parse, we offer no hints.
+    fn inlay_hints(&self, params: InlayHintParams) -> Vec<InlayHint> {
+        let documents = self.documents.borrow();
+        let uri = params.text_document.uri;
+        let (text, ast) = match documents.get(&uri) {
+            Some(doc) => match self.parse(&uri, doc.text.clone()) {
+                Ok(ast) => (&doc.text, ast),
+                Err(_) => return Vec::new(),
+            },
+            None => return Vec::new(),
+        };
+        let Range { start, end } = params.range;
+        ast.inlay_hints()
+            .into_iter()
+            .filter_map(|x| {
+                let position = to_lsp(text, Position::new(x.line as u32, x.column as u32));
+                if position < start || position > end {
+                    return None;
+                }
+                Some(match x.kind {
+                    InlayHintKind::Type => InlayHint {
+                        position,
+                        label: format!(": {}", x.label),
+                        kind: 1,
+                        padding_left: false,
+                        padding_right: false,
+                    },
+                    InlayHintKind::Parameter => InlayHint {
+                        position,
+                        label: format!("{}:", x.label),
+                        kind: 2,
+                        padding_left: false,
+                        padding_right: true,
+                    },
+                })
+            })
+            .collect()
+    }
+
+    /// Completions for the module or symbols of a `load()`, replacing what has been typed of
+    /// the string so far.
+    fn load_completions(
+        &self,
+        uri: &Url,
+        text: &str,
+        argument: LoadArgument,
+        end: Position,
+    ) -> Vec<CompletionItem> {
+        let current_file = match uri.to_file_path() {
+            Ok(x) => x,
+            Err(_) => return Vec::new(),
+        };
+        let item = |label: &str, kind, start| CompletionItem {
+            text_edit: Some(CompletionTextEdit::Edit(TextEdit::new(
+                range_to_lsp(text, Range::new(start, end)),
+                label.to_owned(),
+            ))),
+            ..completion_item(label, kind)
+        };
+        match argument {
+            LoadArgument::Module(typed, start) => {
+                let folder = self.folder_of(&current_file);
+                self.resolver
+                    .complete_load(&typed, &current_file, folder.as_deref())
+                    .iter()
+                    .map(|x| item(x, CompletionItemKind::File, start))
+                    .collect()
+            }
+            LoadArgument::Symbol(module, typed, start) => {
+                let ast = match self.load_module(&module, &current_file) {
+                    Ok((_, ast)) => ast,
+                    Err(_) => return Vec::new(),
+                };
+                ast.exported_symbols()
+                    .into_iter()
+                    .filter(|(_, name)| name.starts_with(&typed))
+                    .map(|(_, name)| item(name, CompletionItemKind::Variable, start))
+                    .collect()
+            }
+        }
+    }
+
+    fn completion(&self, params: CompletionParams) -> CompletionResponse {
+        let uri = params.text_document_position.text_document.uri;
+        let documents = self.documents.borrow();
+        let doc = match documents.get(&uri) {
+            Some(doc) => doc,
+            None => return CompletionResponse::Array(Vec::new()),
+        };
+        let position = from_lsp(&doc.text, params.text_document_position.position);
+        let Position { line, character } = position;
+        if let Some(argument) = load_argument(&doc.text, position) {
+            return CompletionResponse::Array(
+                self.load_completions(&uri, &doc.text, argument, position),
+            );
+        }
+        // The characters which trigger completions in `load()` strings mean nothing elsewhere
+        let trigger = params.context.and_then(|x| x.trigger_character);
+        if trigger.map_or(false, |x| x != ".") {
+            return CompletionResponse::Array(Vec::new());
+        }
+        let before: String = doc
+            .text
+            .lines()
+            .nth(line as usize)
+            .unwrap_or_default()
+            .chars()
+            .take(character as usize)
+            .collect();
+        if let Some(receiver) = attribute_receiver(&before) {
+            return CompletionResponse::Array(self.attribute_completions(receiver));
+        }
+
+        let mut res = Vec::new();
+        if let Some(ast) = &doc.ast {
+            for (name, kind) in ast.names_in_scope(line as usize, character as usize) {
+                res.push(completion_item(name, to_completion_kind(kind)));
+            }
+        }
+        for module in &self.context.prelude {
+            for name in module.names() {
+                res.push(completion_item(name, CompletionItemKind::Variable));
+            }
+        }
+        for name in self.context.globals.names() {
+            res.push(completion_item(&name, CompletionItemKind::Function));
+        }
+        CompletionResponse::Array(res)
+    }
+
+    fn did_open(&self, params: DidOpenTextDocumentParams) {
+        self.changed.borrow_mut().remove(&params.text_document.uri);
+        self.validate(
+            params.text_document.uri,
+            Some(params.text_document.version as i64),
+            params.text_document.text,
+        )
+    }
+
+    fn did_change(&self, params: DidChangeTextDocumentParams) {
+        let uri = params.text_document.uri;
+        if let Some(doc) = self.documents.borrow_mut().get_mut(&uri) {
+            // Each change applies to the result of the previous one,
+            // and a change without a range replaces the whole document.
+            for change in params.content_changes {
+                match change.range {
+                    None => doc.text = change.text,
+                    Some(range) => {
+                        let start = position_offset(&doc.text, from_lsp(&doc.text, range.start));
+                        let end = position_offset(&doc.text, from_lsp(&doc.text, range.end));
+                        doc.text.replace_range(start..end, &change.text);
+                    }
+                }
+            }
+        }
+        self.changed
+            .borrow_mut()
+            .insert(uri, Some(params.text_document.version as i64));
+    }
+
+    fn did_change_configuration(&self, params: DidChangeConfigurationParams) {
+        // Clients may send just our section, or all the settings
+        let settings = params
+            .settings
+            .get("starlark")
+            .unwrap_or(&params.settings)
+            .clone();
+        match serde_json::from_value(settings) {
+            Ok(settings) => *self.settings.borrow_mut() = settings,
+            Err(e) => {
+                self.log_message(
+                    MessageType::Warning,
+                    &format!("Invalid Starlark settings: {}", e),
+                );
+                return;
+            }
+        }
+        // The severities of diagnostics may have changed, so recompute them all
+        let mut changed = self.changed.borrow_mut();
+        for uri in self.documents.borrow().keys() {
+            changed.entry(uri.clone()).or_insert(None);
+        }
+    }
+
+    fn did_change_workspace_folders(&self, params: DidChangeWorkspaceFoldersParams) {
+        {
+            let mut folders = self.folders.borrow_mut();
+            for x in params.event.removed {
+                if let Ok(path) = x.uri.to_file_path() {
+                    folders.retain(|folder| *folder != path);
+                }
+            }
+            folders.extend(to_folders(params.event.added));
+        }
+        // Loads may now resolve differently
+        let mut changed = self.changed.borrow_mut();
+        for uri in self.documents.borrow().keys() {
+            changed.entry(uri.clone()).or_insert(None);
+        }
+    }
+
+    /// Validate every document which has changed since it was last validated.
+    fn validate_changed(&self) {
+        let changed: Vec<_> = self.changed.borrow_mut().drain().collect();
+        for (uri, version) in changed {
+            let text = match self.documents.borrow().get(&uri) {
+                Some(doc) => doc.text.clone(),
+                None => continue,
+            };
+            self.validate(uri, version, text);
+        }
+    }
+
+    fn did_close(&self, params: DidCloseTextDocumentParams) {
+        self.changed.borrow_mut().remove(&params.text_document.uri);
+        self.documents.borrow_mut().remove(&params.text_document.uri);
+        self.publish_diagnostics(params.text_document.uri, Vec::new(), None)
+    }
+}
+
+/// The library style pieces
+impl Backend {
+    fn send_notification(&self, x: Notification) {
+        self.connection
+            .sender
+            .send(Message::Notification(x))
+            .unwrap()
+    }
+
+    fn send_response(&self, x: Response) {
+        self.connection.sender.send(Message::Response(x)).unwrap()
+    }
+
+    fn log_message(&self, typ: MessageType, message: &str) {
+        self.send_notification(new_notification::<LogMessage>(LogMessageParams {
+            typ,
+            message: message.to_owned(),
+        }))
+    }
+
+    fn publish_diagnostics(&self, uri: Url, diags: Vec<Diagnostic>, version: Option<i64>) {
+        self.send_notification(new_notification::<PublishDiagnostics>(
+            PublishDiagnosticsParams::new(uri, diags, version.map(|i| i as i32)),
+        ));
+    }
+
+    fn main_loop(&self, params: InitializeParams) -> anyhow::Result<()> {
+        // Clients which predate multi-root workspaces only send the root
+        *self.folders.borrow_mut() = match params.workspace_folders {
+            Some(folders) => to_folders(folders),
+            None => params
+                .root_uri
+                .and_then(|x| x.to_file_path().ok())
+                .into_iter()
+                .collect(),
+        };
+        self.log_message(MessageType::Info, "Starlark server initialised");
+        for msg in &self.connection.receiver {
+            match msg {
+                Message::Request(req) => {
+                    if self.connection.handle_shutdown(&req)? {
+                        return Ok(());
+                    }
+                    // Requests must see the result of every change sent before them
+                    self.validate_changed();
+                    if let Some(params) = as_request::<Completion>(&req) {
+                        self.send_response(new_response(req.id, self.completion(params)))
+                    } else if let Some(params) = as_request::<DocumentSymbolRequest>(&req) {
+                        self.send_response(new_response(req.id, self.document_symbols(params)))
+                    } else if let Some(params) = as_request::<Formatting>(&req) {
+                        self.send_response(new_response(req.id, self.formatting(params)))
+                    } else if let Some(params) = as_request::<RangeFormatting>(&req) {
+                        self.send_response(new_response(req.id, self.range_formatting(params)))
+                    } else if let Some(params) = as_request::<OnTypeFormatting>(&req) {
+                        self.send_response(new_response(req.id, self.on_type_formatting(params)))
+                    } else if let Some(params) = as_request::<CodeActionRequest>(&req) {
+                        self.send_response(new_response(req.id, self.code_action(params)))
+                    } else if let Some(params) = as_request::<CodeLensRequest>(&req) {
+                        self.send_response(new_response(req.id, self.code_lens(params)))
+                    } else if let Some(params) = as_request::<FoldingRangeRequest>(&req) {
+                        self.send_response(new_response(req.id, self.folding_ranges(params)))
+                    } else if let Some(params) = as_request::<GotoDefinition>(&req) {
+                        self.send_response(new_response(req.id, self.goto_definition(params)))
+                    } else if let Some(params) = as_request::<InlayHintRequest>(&req) {
+                        self.send_response(new_response(req.id, self.inlay_hints(params)))
+                    } else if let Some(params) = as_request::<HoverRequest>(&req) {
+                        self.send_response(new_response(req.id, self.hover(params)))
+                    } else if let Some(params) = as_request::<DocumentHighlightRequest>(&req) {
+                        self.send_response(new_response(req.id, self.document_highlights(params)))
+                    } else if let Some(params) = as_request::<WorkspaceSymbol>(&req) {
+                        self.send_response(new_response(req.id, self.workspace_symbols(params)))
+                    }
+                    // Currently don't handle any other requests
+                }
+                Message::Notification(x) => {
+                    if let Some(params) = as_notification::<DidOpenTextDocument>(&x) {
+                        self.did_open(params)
+                    } else if let Some(params) = as_notification::<DidChangeTextDocument>(&x) {
+                        self.did_change(params)
+                    } else if let Some(params) = as_notification::<DidCloseTextDocument>(&x) {
+                        self.did_close(params)
+                    } else if let Some(params) = as_notification::<DidChangeConfiguration>(&x) {
+                        self.did_change_configuration(params)
+                    } else if let Some(params) = as_notification::<DidChangeWorkspaceFolders>(&x) {
+                        self.did_change_workspace_folders(params)
+                    }
+                    if self.connection.receiver.is_empty() {
+                        self.validate_changed();
+                    }
+                }
+                Message::Response(_) => {
+                    // Don't expect any of these
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Run the server over stdin/stdout until the client exits it, serving Starlark as described
+/// by `context`, and following `load()`s with `resolver`. Nothing is logged, so the caller
+/// can log to stderr around it, but must not write to stdout.
+pub fn server(context: LspContext, resolver: Box<dyn LoadResolver>) -> anyhow::Result<()> {
+    let (connection, io_threads) = Connection::stdio();
+    // Run the server and wait for the two threads to end (typically by trigger LSP Exit event).
+    let mut server_capabilities = serde_json::to_value(&Backend::server_capabilities())?;
+    // Our version of `lsp_types` doesn't know about inlay hints
+    server_capabilities["inlayHintProvider"] = serde_json::Value::Bool(true);
+    let initialization_params = connection.initialize(server_capabilities)?;
+    let initialization_params = serde_json::from_value(initialization_params)?;
+    Backend {
+        connection,
+        context,
+        documents: RefCell::new(HashMap::new()),
+        changed: RefCell::new(HashMap::new()),
+        resolver,
+        settings: RefCell::new(Settings::default()),
+        folders: RefCell::new(Vec::new()),
+    }
+    .main_loop(initialization_params)?;
+    io_threads.join()?;
+    Ok(())
+}
+
+fn as_notification<T>(x: &Notification) -> Option<T::Params>
+where
+    T: lsp_types::notification::Notification,
+    T::Params: DeserializeOwned,
+{
+    if x.method == T::METHOD {
+        let params = serde_json::from_value(x.params.clone()).unwrap_or_else(|err| {
+            panic!(
+                "Invalid notification\nMethod: {}\n error: {}",
+                x.method, err
+            )
+        });
+        Some(params)
+    } else {
+        None
+    }
+}
+
+fn as_request<T>(x: &Request) -> Option<T::Params>
+where
+    T: lsp_types::request::Request,
+    T::Params: DeserializeOwned,
+{
+    if x.method == T::METHOD {
+        let params = serde_json::from_value(x.params.clone()).unwrap_or_else(|err| {
+            panic!(
+                "Invalid request\nMethod: {}\n error: {}",
+                x.method, err
+            )
+        });
+        Some(params)
+    } else {
+        None
+    }
+}
+
+fn new_response<T: Serialize>(id: RequestId, result: T) -> Response {
+    Response::new_ok(id, result)
+}
+
+fn new_notification<T>(params: T::Params) -> Notification
+where
+    T: lsp_types::notification::Notification,
+{
+    Notification {
+        method: T::METHOD.to_owned(),
+        params: serde_json::to_value(&params).unwrap(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use lsp_types::{
+        CompletionContext, CompletionTriggerKind, PartialResultParams,
+        TextDocumentContentChangeEvent, TextDocumentItem, TextDocumentPositionParams,
+        VersionedTextDocumentIdentifier, WorkDoneProgressParams,
+    };
+    use starlark_derive::starlark_module;
+
+    use super::*;
+    use crate as starlark;
+    use crate::environment::GlobalsBuilder;
+
+    fn apply(text: &str, edits: Vec<TextEdit>) -> String {
+        let mut lines: Vec<String> = text.split('\n').map(str::to_owned).collect();
+        for edit in edits.into_iter().rev() {
+            let line = &mut lines[edit.range.start.line as usize];
+            let rest: String = line
+                .chars()
+                .skip(edit.range.end.character as usize)
+                .collect();
+            *line = format!("{}{}", edit.new_text, rest);
+        }
+        lines.join("\n")
+    }
+
+    #[test]
+    fn test_on_type_formatting() {
+        let new_line =
+            |text: &str, line| apply(text, indent_new_line(text, line, 4).into_iter().collect());
+        assert_eq!(new_line("def foo():\n", 1), "def foo():\n    ");
+        assert_eq!(
+            new_line("def foo():\n    return 1\n    \n", 2),
+            "def foo():\n    return 1\n\n"
+        );
+        assert_eq!(new_line("x = foo(a,\n", 1), "x = foo(a,\n        ");
+        assert_eq!(new_line("x = foo(\n", 1), "x = foo(\n    ");
+        assert_eq!(
+            new_line("def f():\n    x = foo(a,\n        b)\n", 3),
+            "def f():\n    x = foo(a,\n        b)\n    "
+        );
+
+        let text = "x = foo(a,\n  b, '(',\n c,\n  )";
+        assert_eq!(
+            apply(text, align_brackets(text, Position::new(3, 2), 4)),
+            "x = foo(a,\n        b, '(',\n        c,\n)"
+        );
+
+        let text = "def f():\n    if a:\n        x = 1\n        else:";
+        assert_eq!(
+            apply(text, align_else(text, 3).into_iter().collect()),
+            "def f():\n    if a:\n        x = 1\n    else:"
+        );
+    }
+
+    #[test]
+    fn test_load_argument() {
+        let text = "load(\"//foo:bar.bzl\", \"ab\", x = 'y')\nfoo(\"x\")";
+        let at = |line, character| load_argument(text, Position::new(line, character));
+        assert_eq!(
+            at(0, 12),
+            Some(LoadArgument::Module(
+                "//foo:".to_owned(),
+                Position::new(0, 6)
+            ))
+        );
+        assert_eq!(
+            at(0, 24),
+            Some(LoadArgument::Symbol(
+                "//foo:bar.bzl".to_owned(),
+                "a".to_owned(),
+                Position::new(0, 23)
+            ))
+        );
+        assert_eq!(
+            at(0, 33),
+            Some(LoadArgument::Symbol(
+                "//foo:bar.bzl".to_owned(),
+                String::new(),
+                Position::new(0, 33)
+            ))
+        );
+        // Between the arguments, and in a call which isn't a `load()`
+        assert_eq!(at(0, 21), None);
+        assert_eq!(at(1, 6), None);
+    }
+
+    #[test]
+    fn test_utf16_positions() {
+        // The emoji is one Unicode scalar value, but two UTF-16 code units.
+        let text = "x = '\u{1F600}' + y\nz";
+        assert_eq!(from_lsp(text, Position::new(0, 7)), Position::new(0, 6));
+        assert_eq!(to_lsp(text, Position::new(0, 6)), Position::new(0, 7));
+        assert_eq!(from_lsp(text, Position::new(0, 13)), Position::new(0, 12));
+        assert_eq!(to_lsp(text, Position::new(0, 12)), Position::new(0, 13));
+        // Other lines, and positions beyond the end of the line, are unaffected.
+        assert_eq!(from_lsp(text, Position::new(1, 1)), Position::new(1, 1));
+        assert_eq!(from_lsp(text, Position::new(0, 20)), Position::new(0, 19));
+        assert_eq!(to_lsp(text, Position::new(0, 19)), Position::new(0, 20));
+
+        // Replacing `y` after the emoji, as a client would send it.
+        let start = position_offset(text, from_lsp(text, Position::new(0, 11)));
+        let end = position_offset(text, from_lsp(text, Position::new(0, 12)));
+        let mut new_text = text.to_owned();
+        new_text.replace_range(start..end, "w");
+        assert_eq!(new_text, "x = '\u{1F600}' + w\nz");
+    }
+
+    /// A server for `context`, with the connection its client would read the messages from.
+    fn backend(context: LspContext) -> (Backend, Connection) {
+        let (connection, client) = Connection::memory();
+        let backend = Backend {
+            connection,
+            context,
+            documents: RefCell::new(HashMap::new()),
+            changed: RefCell::new(HashMap::new()),
+            resolver: box BazelLoadResolver,
+            settings: RefCell::new(Settings::default()),
+            folders: RefCell::new(Vec::new()),
+        };
+        (backend, client)
+    }
+
+    fn test_uri() -> Url {
+        Url::parse("file:///test.star").unwrap()
+    }
+
+    fn open(backend: &Backend, text: &str) {
+        backend.did_open(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem::new(
+                test_uri(),
+                "starlark".to_owned(),
+                1,
+                text.to_owned(),
+            ),
+        });
+    }
+
+    /// The labels and kinds of the completions offered at a position in the open document,
+    /// with the character which triggered them, if any.
+    fn completions(
+        backend: &Backend,
+        line: u32,
+        character: u32,
+        trigger: Option<&str>,
+    ) -> Vec<(String, CompletionItemKind)> {
+        let res = backend.completion(CompletionParams {
+            text_document_position: TextDocumentPositionParams::new(
+                TextDocumentIdentifier::new(test_uri()),
+                Position::new(line, character),
+            ),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+            context: Some(CompletionContext {
+                trigger_kind: match trigger {
+                    Some(_) => CompletionTriggerKind::TriggerCharacter,
+                    None => CompletionTriggerKind::Invoked,
+                },
+                trigger_character: trigger.map(str::to_owned),
+            }),
+        });
+        match res {
+            CompletionResponse::Array(items) => items
+                .into_iter()
+                .map(|x| (x.label, x.kind.unwrap()))
+                .collect(),
+            CompletionResponse::List(list) => list
+                .items
+                .into_iter()
+                .map(|x| (x.label, x.kind.unwrap()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_completion() {
+        let (backend, _client) = backend(LspContext::new(Dialect::Extended, Globals::standard()));
+        open(
+            &backend,
+            "x = 1\ndef f(param):\n    return param\ns = \"abc\".up\n",
+        );
+        let has = |items: &[(String, CompletionItemKind)], name: &str, kind| {
+            items.iter().any(|(x, k)| x == name && *k == kind)
+        };
+
+        // Inside the body of `f`, after `return `
+        let items = completions(&backend, 2, 11, None);
+        assert!(has(&items, "len", CompletionItemKind::Function));
+        assert!(has(&items, "x", CompletionItemKind::Variable));
+        assert!(has(&items, "f", CompletionItemKind::Function));
+        assert!(has(&items, "param", CompletionItemKind::Variable));
+
+        // Parameters are only offered within their function.
+        let items = completions(&backend, 0, 0, None);
+        assert!(has(&items, "x", CompletionItemKind::Variable));
+        assert!(!items.iter().any(|(x, _)| x == "param"));
+
+        // After the `.`, and after part of the attribute name, only the methods of a string.
+        for (character, trigger) in [(10, Some(".")), (12, None)] {
+            let items = completions(&backend, 3, character, trigger);
+            assert!(has(&items, "upper", CompletionItemKind::Method));
+            assert!(has(&items, "startswith", CompletionItemKind::Method));
+            assert!(!items.iter().any(|(x, _)| x == "len" || x == "x"));
+        }
+
+        // Other trigger characters are only for `load()` strings.
+        assert_eq!(completions(&backend, 2, 11, Some("/")), Vec::new());
+    }
+
+    #[starlark_module]
+    fn shout_globals(builder: &mut GlobalsBuilder) {
+        /// Shout the text.
+        fn shout(x: &str) -> String {
+            Ok(x.to_uppercase())
+        }
+    }
+
+    #[test]
+    fn test_custom_globals() {
+        let mut context = LspContext::new(
+            Dialect::Extended,
+            GlobalsBuilder::standard().with(shout_globals).build(),
+        );
+        context.undefined_names = true;
+        let (backend, client) = backend(context);
+        open(&backend, "x = shout('hi')\ny = whisper('hi')\n");
+
+        // Only the name which isn't one of the globals is reported.
+        let diagnostics: Vec<_> = client
+            .receiver
+            .try_iter()
+            .filter_map(|x| match x {
+                Message::Notification(x) if x.method == PublishDiagnostics::METHOD => Some(
+                    serde_json::from_value::<PublishDiagnosticsParams>(x.params)
+                        .unwrap()
+                        .diagnostics,
+                ),
+                _ => None,
+            })
+            .flatten()
+            .collect();
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].message.contains("`whisper`"));
+        assert_eq!(diagnostics[0].range.start, Position::new(1, 4));
+
+        assert!(completions(&backend, 1, 4, None)
+            .contains(&("shout".to_owned(), CompletionItemKind::Function)));
+
+        let hover = backend
+            .hover(HoverParams {
+                text_document_position_params: TextDocumentPositionParams::new(
+                    TextDocumentIdentifier::new(test_uri()),
+                    Position::new(0, 6),
+                ),
+                work_done_progress_params: WorkDoneProgressParams::default(),
+            })
+            .unwrap();
+        match hover.contents {
+            HoverContents::Markup(x) => {
+                assert!(x.value.contains("def shout(x"));
+                assert!(x.value.contains("Shout the text."));
+            }
+            x => panic!("Unexpected hover: {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_did_change() {
+        let (backend, client) = backend(LspContext::new(Dialect::Extended, Globals::standard()));
+        // The versions of the diagnostics published since last called
+        let published = || {
+            client.receiver.try_iter().filter_map(|x| match x {
+                Message::Notification(x) if x.method == PublishDiagnostics::METHOD => {
+                    serde_json::from_value::<PublishDiagnosticsParams>(x.params)
+                        .unwrap()
+                        .version
+                }
+                _ => None,
+            })
+        };
+        let uri = test_uri;
+        let text = |backend: &Backend| backend.documents.borrow()[&uri()].text.clone();
+        let change =
+            |range: Option<(u32, u32, u32, u32)>, text: &str| TextDocumentContentChangeEvent {
+                range: range.map(|(l1, c1, l2, c2)| {
+                    Range::new(Position::new(l1, c1), Position::new(l2, c2))
+                }),
+                range_length: None,
+                text: text.to_owned(),
+            };
+        let did_change = |version, content_changes| {
+            backend.did_change(DidChangeTextDocumentParams {
+                text_document: VersionedTextDocumentIdentifier::new(uri(), version),
+                content_changes,
+            })
+        };
+
+        open(&backend, "x = 1\ny = 2\n");
+        assert_eq!(published().collect::<Vec<_>>(), vec![1]);
+
+        // Each change applies to the text left by the one before,
+        // including after the whole document is replaced.
+        did_change(
+            2,
+            vec![
+                change(Some((0, 4, 0, 5)), "10"),
+                change(Some((1, 0, 1, 1)), "z"),
+                change(None, "a = 1\n"),
+                change(Some((1, 0, 1, 0)), "b = a\n"),
+            ],
+        );
+        assert_eq!(text(&backend), "a = 1\nb = a\n");
+        did_change(3, vec![change(Some((0, 0, 0, 1)), "c")]);
+        assert_eq!(text(&backend), "c = 1\nb = a\n");
+
+        // Nothing is validated until asked, and then only once, for the latest version.
+        assert_eq!(published().count(), 0);
+        backend.validate_changed();
+        assert_eq!(published().collect::<Vec<_>>(), vec![3]);
+        assert_eq!(text(&backend), "c = 1\nb = a\n");
+        backend.validate_changed();
+        assert_eq!(published().count(), 0);
+    }
+}
@@ -97,7 +97,7 @@ fn cmd_variables(eval: &mut Evaluator, rl: &mut dyn BreakpointConsole) -> anyhow
     }
 
     for (name, value) in eval.local_variables() {
-        rl.println(&format!("* {} = {}", name, truncate(value.to_string(), 80)))
+        rl.println(&format!("* {} = {}", name, truncate(value.debug_summary(), 80)))
     }
     Ok(Next::Again)
 }
@@ -205,6 +205,7 @@ const BREAKPOINT_HIT_MESSAGE: &str = "BREAKPOINT HIT! :resume to continue, :help
 #[starlark_module]
 pub fn global(builder: &mut GlobalsBuilder) {
     fn breakpoint() -> NoneType {
+        eval.check_deterministic("breakpoint")?;
         {
             let mut guard = BREAKPOINT_MUTEX.lock().unwrap();
             if *guard == State::Allow {
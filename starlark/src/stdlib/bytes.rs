@@ -0,0 +1,65 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Methods for the `bytes` type.
+
+use crate as starlark;
+use crate::{
+    environment::MethodsBuilder,
+    values::{
+        bytes::{Encoding, StarlarkBytes},
+        Value,
+    },
+};
+
+#[starlark_module]
+pub(crate) fn bytes_methods(builder: &mut MethodsBuilder) {
+    /// bytes.elems: returns an iterable of the byte values of a bytes.
+    ///
+    /// `B.elems()` returns an iterable value containing the
+    /// sequence of integer byte values, each in the range 0 to 255.
+    ///
+    /// Examples:
+    ///
+    /// ```
+    /// # starlark::assert::all_true(r#"
+    /// list(b"abc".elems()) == [97, 98, 99]
+    /// # "#);
+    /// ```
+    fn elems(this: &StarlarkBytes) -> Value<'v> {
+        Ok(heap.alloc_list_iter(this.as_bytes().iter().map(|x| Value::new_int(*x as i32))))
+    }
+
+    /// bytes.decode: convert bytes to a string.
+    ///
+    /// `B.decode(encoding="utf-8")` decodes the bytes B using the given encoding, which may be
+    /// `"utf-8"`, `"ascii"` or `"latin-1"`. It is an error if the bytes are not valid in that
+    /// encoding.
+    ///
+    /// Examples:
+    ///
+    /// ```
+    /// # starlark::assert::all_true(r#"
+    /// b"hello".decode() == "hello"
+    /// b"caf\xc3\xa9".decode("utf-8") == "café"
+    /// b"caf\xe9".decode("latin-1") == "café"
+    /// # "#);
+    /// ```
+    fn decode(this: &StarlarkBytes, ref encoding: Option<&str>) -> String {
+        Encoding::new(encoding.unwrap_or("utf-8"))?.decode(this.as_bytes())
+    }
+}
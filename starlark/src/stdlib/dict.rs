@@ -173,8 +173,8 @@ pub(crate) fn dict_methods(registry: &mut MethodsBuilder) {
                     mem::drop(me);
                     Err(anyhow!(
                         "Key `{}` not found in dictionary `{}`",
-                        key.to_repr(),
-                        this.to_repr()
+                        key.debug_summary(),
+                        this.debug_summary()
                     ))
                 }
             },
@@ -254,6 +254,7 @@ pub(crate) fn dict_methods(registry: &mut MethodsBuilder) {
     /// # )"#)
     /// ```
     fn setdefault(this: Value, ref key: Value, ref default: Option<Value>) -> Value<'v> {
+        heap.write_barrier(this);
         let mut this = Dict::from_value_mut(this)?.unwrap();
         let key = key.get_hashed()?;
         if let Some(r) = this.get_hashed(key) {
@@ -305,6 +306,7 @@ pub(crate) fn dict_methods(registry: &mut MethodsBuilder) {
             pairs
         };
 
+        heap.write_barrier(this);
         let mut this = Dict::from_value_mut(this)?.unwrap();
         if let Some(pairs) = pairs {
             if let Some(dict) = Dict::from_value(pairs) {
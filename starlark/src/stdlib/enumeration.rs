@@ -24,6 +24,17 @@ use crate::{
 
 #[starlark_module]
 pub fn global(builder: &mut GlobalsBuilder) {
+    /// Create an enumeration type from a fixed set of distinct values.
+    ///
+    /// Calling the resulting type with one of those values returns the corresponding enum value,
+    /// and any other value is an error. Enum values can be tested for membership with `in`.
+    ///
+    /// ```
+    /// # starlark::assert::is_true(r#"
+    /// Colors = enum("Red", "Green")
+    /// Colors("Red") in Colors
+    /// # "#);
+    /// ```
     fn r#enum(args: Vec<Value>) -> Value<'v> {
         // Every Value must either be a field or a value (the type)
         EnumType::new(args, heap)
@@ -97,6 +108,13 @@ assert_eq(str(x), "\"option1\"")
             r#"
 enum_type = enum("option1","option2")
 repr(enum_type) # Check it is finite
+"#,
+        );
+        assert::all_true(
+            r#"
+enum("a", "b")("a") in enum("a", "b")
+not (enum("a", "b")("a") in enum("a", "c"))
+not ("a" in enum("a", "b"))
 "#,
         );
     }
@@ -18,6 +18,7 @@
 use std::{
     collections::HashSet,
     fmt::{self, Display},
+    hash::{Hash, Hasher},
 };
 
 use gazebo::{
@@ -27,17 +28,20 @@ use gazebo::{
     prelude::*,
 };
 use itertools::Itertools;
+use num_bigint::BigInt;
+use num_traits::Signed;
 
 use crate::{
     self as starlark,
     codemap::Span,
-    collections::symbol_map::Symbol,
+    collections::{symbol_map::Symbol, StarlarkHasher},
     environment::GlobalsBuilder,
     eval::{Arguments, Evaluator},
+    stdlib::LibraryExtension,
     values::{
-        dict::Dict, function::FUNCTION_TYPE, none::NoneType, tuple::Tuple, Freeze, Freezer,
-        FrozenStringValue, FrozenValue, StarlarkValue, StringValue, StringValueLike, Trace, Value,
-        ValueLike,
+        dict::Dict, function::FUNCTION_TYPE, none::NoneType, structs::StructBuilder, tuple::Tuple,
+        Freeze, Freezer, FrozenStringValue, FrozenValue, StarlarkValue, StringValue,
+        StringValueLike, Trace, Value, ValueLike,
     },
 };
 
@@ -101,6 +105,7 @@ pub fn debug(builder: &mut GlobalsBuilder) {
     /// Print the value with full debug formatting. The result may not be stable over time,
     /// mostly intended for debugging purposes.
     fn debug(ref val: Value) -> String {
+        eval.check_deterministic("debug")?;
         Ok(format!("{:?}", val))
     }
 }
@@ -137,11 +142,21 @@ impl fmt::Display for PrintWrapper<'_, '_> {
 }
 
 /// Invoked from `print` or `pprint` to print a value.
+///
+/// Implemented by closures, so output can be captured with e.g.
+/// `|text: &str| { lines.borrow_mut().push(text.to_owned()); Ok(()) }`, using a
+/// [`RefCell`](std::cell::RefCell) or similar for any state which changes.
 pub trait PrintHandler {
     /// If this function returns error, evaluation fails with this error.
     fn println(&self, text: &str) -> anyhow::Result<()>;
 }
 
+impl<F: Fn(&str) -> anyhow::Result<()>> PrintHandler for F {
+    fn println(&self, text: &str) -> anyhow::Result<()> {
+        self(text)
+    }
+}
+
 pub(crate) struct StderrPrintHandler;
 
 impl PrintHandler for StderrPrintHandler {
@@ -172,6 +187,106 @@ pub fn pprint(builder: &mut GlobalsBuilder) {
     }
 }
 
+#[starlark_module]
+pub fn module_ctx(builder: &mut GlobalsBuilder) {
+    /// Information about the module whose code is running: `path` is its file name,
+    /// and `main` is whether it is the top-level program. Code in a `def` belongs to the
+    /// module where the `def` was written, so a loaded function is never `main`.
+    fn module_ctx() -> Value<'v> {
+        // If we are running a `def` from a loaded module, we have its module variables
+        let main = eval.is_main && eval.module_variables.is_none();
+        let mut s = StructBuilder::new(heap);
+        s.add("path", eval.def_info.codemap.filename());
+        s.add("main", main);
+        Ok(heap.alloc(s.build()))
+    }
+}
+
+/// Add the `debug` struct with the members of each of `extensions`, which may be
+/// [`CallStack`](LibraryExtension::CallStack) and [`Provenance`](LibraryExtension::Provenance).
+/// They share the struct, and adding a struct again replaces it, so it is added once with
+/// the members of all of them.
+pub(crate) fn debug_struct(builder: &mut GlobalsBuilder, extensions: &[LibraryExtension]) {
+    builder.struct_("debug", |builder| {
+        for x in extensions {
+            match x {
+                LibraryExtension::CallStack => callstack_members(builder),
+                LibraryExtension::Provenance => provenance_members(builder),
+                _ => unreachable!("`{:?}` doesn't add to the `debug` struct", x),
+            }
+        }
+    });
+}
+
+#[starlark_module]
+fn callstack_members(builder: &mut GlobalsBuilder) {
+    /// Return the current call-stack as a list of structs with fields `name`, `file` and `line`,
+    /// outermost first. Each entry gives the function that was running and the position it had
+    /// reached, with the module top-level named `<module>`. The `debug.callstack()` call itself
+    /// is not included. `file` and `line` are `None` for positions inside native functions.
+    fn callstack() -> Value<'v> {
+        let frames = eval.call_stack();
+        // Frame `i` records where its function was called from, which is the position
+        // reached by the function of frame `i - 1`. The final frame is `callstack` itself.
+        let res = frames.iter().enumerate().map(|(i, frame)| {
+            let name = match i {
+                0 => "<module>",
+                _ => frames[i - 1].name.as_str(),
+            };
+            let mut s = StructBuilder::new(heap);
+            s.add("name", name);
+            match &frame.location {
+                Some(loc) => {
+                    s.add("file", loc.file.filename());
+                    s.add("line", (loc.resolve_span().begin_line + 1) as i32);
+                }
+                None => {
+                    s.add("file", NoneType);
+                    s.add("line", NoneType);
+                }
+            }
+            heap.alloc(s.build())
+        });
+        Ok(heap.alloc_list_iter(res))
+    }
+}
+
+#[starlark_module]
+fn provenance_members(builder: &mut GlobalsBuilder) {
+    /// Describe the statement and call-stack which allocated a value.
+    /// Returns `None` if the value was not allocated by a statement, or if provenance
+    /// tracking was not enabled with `Evaluator::enable_provenance`.
+    fn provenance(ref val: Value) -> Option<String> {
+        Ok(eval.value_provenance(val).map(|x| x.to_string()))
+    }
+}
+
+pub fn ids(builder: &mut GlobalsBuilder) {
+    builder.struct_("ids", ids_members);
+}
+
+#[starlark_module]
+fn ids_members(builder: &mut GlobalsBuilder) {
+    /// Return a new identifier starting with `prefix`, unique within this evaluation, made
+    /// from the prefix, the seed and the number of identifiers made with that prefix so far.
+    fn next(ref prefix: &str) -> String {
+        let seed = match eval.ids_seed {
+            Some(seed) => seed,
+            None => {
+                let mut hasher = StarlarkHasher::new();
+                eval.def_info.codemap.filename().hash(&mut hasher);
+                let seed = hasher.finish();
+                eval.ids_seed = Some(seed);
+                seed
+            }
+        };
+        let count = eval.ids.entry(prefix.to_owned()).or_insert(0);
+        let id = format!("{}_{:08x}_{}", prefix, seed as u32, count);
+        *count += 1;
+        Ok(id)
+    }
+}
+
 #[starlark_module]
 pub fn json(builder: &mut GlobalsBuilder) {
     fn json(ref x: Value) -> String {
@@ -181,7 +296,7 @@ pub fn json(builder: &mut GlobalsBuilder) {
 
 #[starlark_module]
 pub fn abs(builder: &mut GlobalsBuilder) {
-    fn abs(ref x: i32) -> i32 {
+    fn abs(ref x: BigInt) -> BigInt {
         Ok(x.abs())
     }
 }
@@ -283,7 +398,12 @@ mod tests {
 
     use gazebo::prelude::*;
 
-    use crate::{assert, assert::Assert, stdlib::PrintHandler};
+    use crate::{
+        assert,
+        assert::Assert,
+        environment::Globals,
+        stdlib::{LibraryExtension, PrintHandler},
+    };
 
     #[test]
     fn test_filter() {
@@ -358,7 +478,7 @@ assert_eq(
         assert::pass(
             r#"assert_eq(
                 debug([1,2]),
-                "Value(ListGen(List { content: Cell { value: ValueType(Value(Array { len: 2, capacity: 2, iter_count: 0, content: [Value(1), Value(2)] })) } }))"
+                "Value(ListGen(List { content: Cell { value: Value(Array { len: 2, capacity: 2, iter_count: 0, content: [Value(1), Value(2)] }) } }))"
                 )"#,
         );
     }
@@ -395,4 +515,131 @@ assert_eq(dedupe([a,b,a]), [a,b])
         a.pass("print('hw')");
         assert_eq!("hw", s_copy.borrow().as_str());
     }
+
+    #[test]
+    fn test_print_closure() {
+        let lines = RefCell::new(Vec::new());
+        let print_handler = |text: &str| -> anyhow::Result<()> {
+            lines.borrow_mut().push(text.to_owned());
+            Ok(())
+        };
+        let mut a = Assert::new();
+        a.set_print_handler(&print_handler);
+        a.pass("print('a', 1)\nprint([2])");
+        assert_eq!(*lines.borrow(), ["a 1", "[2]"]);
+    }
+
+    #[test]
+    fn test_module_ctx() {
+        let mut a = Assert::new();
+        a.module(
+            "lib",
+            r#"
+def ctx():
+    return module_ctx()
+"#,
+        );
+        a.pass(
+            r#"
+load("lib", "ctx")
+assert_eq(module_ctx().path, "assert.bzl")
+assert_eq(module_ctx().main, True)
+assert_eq(ctx().path, "lib.bzl")
+assert_eq(ctx().main, False)
+"#,
+        );
+        a.setup_eval(|eval| eval.set_main(false));
+        a.is_true("not module_ctx().main");
+    }
+
+    #[test]
+    fn test_callstack() {
+        let mut a = Assert::new();
+        a.globals_add(|x| LibraryExtension::CallStack.add(x));
+        a.pass(
+            r#"
+def where():
+    return debug.callstack()[-2]
+
+def rule():
+    return where()
+
+loc = rule()
+assert_eq(loc.name, "rule")
+assert_eq(loc.file, "assert.bzl")
+assert_eq(loc.line, 6)
+stack = debug.callstack()
+assert_eq(len(stack), 1)
+assert_eq(stack[0].name, "<module>")
+assert_eq(stack[0].line, 13)
+assert_eq([x.name for x in (lambda: debug.callstack())()], ["<module>", "lambda"])
+"#,
+        );
+    }
+
+    #[test]
+    fn test_debug_provenance() {
+        let mut a = Assert::new();
+        a.globals_add(|x| LibraryExtension::Provenance.add(x));
+        a.setup_eval(|eval| eval.enable_provenance());
+        a.pass(
+            r#"
+def f():
+    return [2]
+x = [1]
+assert_true(debug.provenance(x).startswith("assert.bzl:4:"))
+# The values allocated since the last lookup are found too
+y = f()
+assert_true(debug.provenance(y).startswith("assert.bzl:3:"))
+assert_eq(debug.provenance(1), None)
+assert_false(hasattr(debug, "callstack"))
+"#,
+        );
+    }
+
+    #[test]
+    fn test_debug_struct() {
+        // Each extension adding to the `debug` struct keeps the members of the others.
+        let mut a = Assert::new();
+        a.globals(Globals::extended_by(&[
+            LibraryExtension::CallStack,
+            LibraryExtension::Provenance,
+        ]));
+        a.pass(
+            r#"
+assert_eq(len(debug.callstack()), 1)
+assert_eq(debug.provenance(1), None)
+"#,
+        );
+    }
+
+    #[test]
+    fn test_ids() {
+        let mut a = Assert::new();
+        a.pass(
+            r#"
+x = ids.next("tmp")
+assert_eq(x, ids.next("tmp")[:-1] + "0")
+assert_ne(x, ids.next("tmp"))
+assert_true(x.startswith("tmp_"))
+assert_eq(ids.next("rule")[-2:], "_0")
+"#,
+        );
+        // Evaluations with the same seed make the same identifiers, whatever the file
+        a.setup_eval(|eval| eval.set_ids_seed(0x1234));
+        a.eq("ids.next('x')", "'x_00001234_0'");
+        a.eq(
+            "[ids.next('x') for _ in range(2)]",
+            "['x_00001234_0', 'x_00001234_1']",
+        );
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let mut a = Assert::new();
+        a.setup_eval(|eval| eval.enable_deterministic());
+        a.fail("debug([1])", "not available in deterministic evaluation");
+        a.fail("breakpoint()", "not available in deterministic evaluation");
+        a.eq("ids.next('x')[:2]", "'x_'");
+    }
 }
@@ -18,9 +18,15 @@
 //! A module with the standard function and constants that are by default in all
 //! dialect of Starlark
 
-use std::{cmp::Ordering, num::NonZeroI32};
+use std::{
+    cmp::Ordering,
+    num::{IntErrorKind, NonZeroI32},
+};
 
 use anyhow::anyhow;
+use num_bigint::BigInt;
+use num_traits::FromPrimitive;
+use thiserror::Error;
 
 use crate::{
     self as starlark,
@@ -28,12 +34,27 @@ use crate::{
     environment::GlobalsBuilder,
     eval::Arguments,
     values::{
-        bool::BOOL_TYPE, dict::Dict, float::StarlarkFloat, int::INT_TYPE, list::List,
-        none::NoneType, num::Num, range::Range, string::STRING_TYPE, tuple::Tuple, Heap, Value,
-        ValueError, ValueLike,
+        bigint::StarlarkBigInt,
+        bool::BOOL_TYPE,
+        bytes::{StarlarkBytes, BYTES_TYPE},
+        dict::Dict,
+        float::StarlarkFloat,
+        int::INT_TYPE,
+        list::List,
+        none::NoneType,
+        num::Num,
+        range::Range,
+        string::STRING_TYPE,
+        tuple::Tuple,
+        Heap, Value, ValueError, ValueLike,
     },
 };
 
+/// The error raised by the `fail()` function.
+#[derive(Debug, Error)]
+#[error("fail:{0}")]
+pub(crate) struct FailError(String);
+
 fn unpack_pair<'v>(pair: Value<'v>, heap: &'v Heap) -> anyhow::Result<(Value<'v>, Value<'v>)> {
     pair.with_iterator(heap, |it| {
         if let Some(first) = it.next() {
@@ -45,7 +66,7 @@ fn unpack_pair<'v>(pair: Value<'v>, heap: &'v Heap) -> anyhow::Result<(Value<'v>
         }
         Err(anyhow!(
             "Found a non-pair element in the positional argument of dict(): {}",
-            pair.to_repr(),
+            pair.debug_summary(),
         ))
     })?
 }
@@ -76,7 +97,7 @@ pub(crate) fn global_functions(builder: &mut GlobalsBuilder) {
                 None => x.collect_repr(&mut s),
             }
         }
-        Err(anyhow!("fail:{}", s))
+        Err(FailError(s).into())
     }
 
     /// [any](
@@ -172,6 +193,31 @@ pub(crate) fn global_functions(builder: &mut GlobalsBuilder) {
         }
     }
 
+    /// bytes: construct a bytes value.
+    ///
+    /// `bytes(x)` converts its argument to bytes. A string is encoded as UTF-8,
+    /// bytes are returned unchanged, and any other iterable must contain integers
+    /// in the range 0 to 255, giving the value of each byte.
+    ///
+    /// ```
+    /// # starlark::assert::all_true(r#"
+    /// bytes("hello") == b"hello"
+    /// bytes(b"hello") == b"hello"
+    /// bytes([104, 105]) == b"hi"
+    /// # "#);
+    /// ```
+    #[starlark(type(BYTES_TYPE))]
+    #[starlark(speculative_exec_safe)]
+    fn bytes(ref x: Value) -> Value<'v> {
+        if let Some(s) = x.unpack_str() {
+            Ok(heap.alloc(s.as_bytes()))
+        } else if StarlarkBytes::from_value(x).is_some() {
+            Ok(x)
+        } else {
+            Ok(heap.alloc(StarlarkBytes::collect_ints(x, heap)?))
+        }
+    }
+
     /// [chr](
     /// https://github.com/google/skylark/blob/a0e5de7e63b47e716cca7226662a4c95d47bf873/doc/spec.md#bool
     /// ): returns a string encoding a codepoint.
@@ -355,6 +401,8 @@ pub(crate) fn global_functions(builder: &mut GlobalsBuilder) {
         let a = a.unwrap();
         if let Some(f) = a.unpack_num().map(|n| n.as_float()) {
             Ok(f)
+        } else if let Some(i) = StarlarkBigInt::from_value(a) {
+            i.to_f64()
         } else if let Some(s) = a.unpack_str() {
             match s.parse::<f64>() {
                 Ok(f) => {
@@ -469,8 +517,7 @@ pub(crate) fn global_functions(builder: &mut GlobalsBuilder) {
     ///
     /// If x is a string, it is interpreted like a string literal;
     /// an optional base prefix (`0`, `0b`, `0B`, `0x`, `0X`) determines which
-    /// base to use. The string may specify an arbitrarily large integer,
-    /// whereas true integer literals are restricted to 64 bits.
+    /// base to use. The string may specify an arbitrarily large integer.
     /// If a non-zero `base` argument is provided, the string is interpreted
     /// in that base and no base prefix is permitted; the base argument may
     /// specified by name.
@@ -492,14 +539,13 @@ pub(crate) fn global_functions(builder: &mut GlobalsBuilder) {
     /// int(3.14) == 3
     /// int(-12345.6789) == -12345
     /// int(2e9) == 2000000000
+    /// int(1e20) == 100000000000000000000
+    /// int('123456789012345678901234567890') == 123456789012345678901234567890
     /// # "#);
     /// # starlark::assert::fail(r#"
     /// int("hello")   # error: not a valid number
     /// # "#, "not a valid number");
     /// # starlark::assert::fail(r#"
-    /// int(1e100)   # error: overflow
-    /// # "#, "cannot convert float to integer");
-    /// # starlark::assert::fail(r#"
     /// int(float("nan"))   # error: cannot convert NaN to int
     /// # "#, "cannot convert float to integer");
     /// # starlark::assert::fail(r#"
@@ -508,9 +554,9 @@ pub(crate) fn global_functions(builder: &mut GlobalsBuilder) {
     /// ```
     #[starlark(type(INT_TYPE))]
     #[starlark(speculative_exec_safe)]
-    fn int(ref a: Option<Value>, base: Option<Value>) -> i32 {
+    fn int(ref a: Option<Value>, base: Option<Value>) -> Value<'v> {
         if a.is_none() {
-            return Ok(0);
+            return Ok(Value::new_int(0));
         }
         let a = a.unwrap();
         if let Some(s) = a.unpack_str() {
@@ -565,30 +611,41 @@ pub(crate) fn global_functions(builder: &mut GlobalsBuilder) {
                 }
                 _ => s,
             };
-            match i32::from_str_radix(s, base) {
-                Ok(i) => Ok(sign * i),
-                Err(x) => Err(anyhow!(
-                    "{} is not a valid number in base {}: {}",
-                    a.to_repr(),
-                    base,
-                    x,
-                )),
-            }
+            let i = match i32::from_str_radix(s, base) {
+                Ok(i) => BigInt::from(i),
+                Err(x) => match x.kind() {
+                    // The digits are valid, but too many for an `i32`.
+                    IntErrorKind::PosOverflow | IntErrorKind::NegOverflow => {
+                        BigInt::parse_bytes(s.as_bytes(), base).unwrap()
+                    }
+                    _ => {
+                        return Err(anyhow!(
+                            "{} is not a valid number in base {}: {}",
+                            a.to_repr(),
+                            base,
+                            x,
+                        ));
+                    }
+                },
+            };
+            Ok(StarlarkBigInt::alloc(i * sign, heap))
         } else if let Some(base) = base {
             Err(anyhow!(
                 "int() cannot convert non-string with explicit base '{}'",
                 base.to_repr()
             ))
         } else if let Some(Num::Float(f)) = a.unpack_num() {
-            match Num::from(f.trunc()).as_int() {
-                Some(i) => Ok(i),
+            match BigInt::from_f64(f.trunc()) {
+                Some(i) => Ok(StarlarkBigInt::alloc(i, heap)),
                 None => Err(anyhow!(
                     "int() cannot convert float to integer: {}",
                     a.to_repr()
                 )),
             }
+        } else if StarlarkBigInt::from_value(a).is_some() {
+            Ok(a)
         } else {
-            Ok(a.to_int()?)
+            Ok(Value::new_int(a.to_int()?))
         }
     }
 
@@ -971,6 +1028,11 @@ pub(crate) fn global_functions(builder: &mut GlobalsBuilder) {
         if a.unpack_str().is_some() {
             // Special case that can avoid reallocating, but is equivalent.
             Ok(a)
+        } else if let Some(b) = StarlarkBytes::from_value(a) {
+            // Bytes are decoded as UTF-8, replacing any invalid sequences.
+            Ok(eval
+                .heap()
+                .alloc(String::from_utf8_lossy(b.as_bytes()).as_ref()))
         } else {
             let mut s = eval.string_pool.alloc();
             a.collect_repr(&mut s);
@@ -52,6 +52,7 @@ pub(crate) fn list_methods(builder: &mut MethodsBuilder) {
     /// # "#);
     /// ```
     fn append(this: Value, ref el: Value) -> NoneType {
+        heap.write_barrier(this);
         let this = List::from_value_mut(this)?.unwrap();
         this.push(el, heap);
         Ok(NoneType)
@@ -101,6 +102,7 @@ pub(crate) fn list_methods(builder: &mut MethodsBuilder) {
     /// ```
     fn extend(this: Value, ref other: Value) -> NoneType {
         let res = List::from_value_mut(this)?.unwrap();
+        heap.write_barrier(this);
         if this.ptr_eq(other) {
             // If the types alias, we can't borrow the `other` for iteration.
             // But we can do something smarter to double the elements
@@ -184,6 +186,7 @@ pub(crate) fn list_methods(builder: &mut MethodsBuilder) {
     /// # "#);
     /// ```
     fn insert(this: Value, ref index: i32, ref el: Value) -> NoneType {
+        heap.write_barrier(this);
         let this = List::from_value_mut(this)?.unwrap();
         let index = convert_index(this.len() as i32, index);
         this.insert(index, el, heap);
@@ -219,6 +222,8 @@ pub(crate) fn list_methods(builder: &mut MethodsBuilder) {
             None => None,
         };
 
+        // Removing from a list sliced from a frozen list gives it a new array.
+        heap.write_barrier(this);
         let this = List::from_value_mut(this)?.unwrap();
         let index = index.unwrap_or_else(|| (this.len() as i32) - 1);
         if index < 0 || index >= this.len() as i32 {
@@ -278,6 +283,7 @@ pub(crate) fn list_methods(builder: &mut MethodsBuilder) {
         };
         {
             // now mutate it with no further value calls
+            heap.write_barrier(this);
             let this = List::from_value_mut(this)?.unwrap();
             this.remove(position);
             Ok(NoneType)
@@ -18,16 +18,21 @@
 //! A module with the standard function and constants that are by default in all
 //! dialect of Starlark
 
+use serde::Serialize;
+
 use crate::environment::GlobalsBuilder;
 
 pub(crate) mod breakpoint;
+pub(crate) mod bytes;
 pub(crate) mod dict;
 pub(crate) mod enumeration;
 pub(crate) mod extra;
-mod funcs;
+pub(crate) mod funcs;
 use gazebo::prelude::*;
 pub(crate) mod list;
+pub(crate) mod namedtuple;
 pub(crate) mod record;
+pub(crate) mod set;
 pub(crate) mod string;
 pub(crate) mod structs;
 pub(crate) mod util;
@@ -44,7 +49,8 @@ pub(crate) fn standard_environment() -> GlobalsBuilder {
 }
 
 /// The extra library definitions available in this Starlark implementation, but not in the standard.
-#[derive(PartialEq, Eq, Copy, Clone, Dupe)]
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Dupe, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum LibraryExtension {
     /// Definitions to support the `struct` type, the `struct()` constructor.
     StructType,
@@ -52,6 +58,10 @@ pub enum LibraryExtension {
     RecordType,
     /// Definitions to support the `enum` type, the `enum()` constructor.
     EnumType,
+    /// Definitions to support the `set` type, the `set()` constructor.
+    SetType,
+    /// Definitions to support the `namedtuple` type, the `namedtuple()` constructor.
+    NamedTupleType,
     /// A function `map(f, xs)` which applies `f` to each element of `xs` and returns the result.
     Map,
     /// A function `filter(f, xs)` which applies `f` to each element of `xs` and returns those for which `f` returns `True`.
@@ -65,6 +75,13 @@ pub enum LibraryExtension {
     /// Add a function `debug(x)` which shows the Rust [`Debug`](std::fmt::Debug) representation of a value.
     /// Useful when debugging, but the output should not be considered stable.
     Debug,
+    /// Add a struct `debug` with a function `debug.provenance(x)` which describes where a
+    /// value was allocated, if enabled with
+    /// [`Evaluator::enable_provenance`](crate::eval::Evaluator::enable_provenance).
+    /// Not included in [`all`](LibraryExtension::all), since the struct replaces the `debug()`
+    /// function of [`Debug`](LibraryExtension::Debug). With
+    /// [`CallStack`](LibraryExtension::CallStack) as well, the struct has both functions.
+    Provenance,
     /// Add a function `print(x)` which prints to stderr.
     Print,
     /// Add a function `pprint(x)` which pretty-prints to stderr.
@@ -75,7 +92,25 @@ pub enum LibraryExtension {
     Json,
     /// Add a function `abs()` which will take the absolute value of an int.
     Abs,
-    // Make sure if you add anything new, you add it to `all` below.
+    /// Add a function `module_ctx()` returning a struct with fields `path`, the file name of the
+    /// module whose code is running, and `main`, whether that module is the top-level program
+    /// rather than one that was loaded (see [`Evaluator::set_main`](crate::eval::Evaluator::set_main)).
+    ModuleCtx,
+    /// Add a struct `debug` with a function `debug.callstack()` returning the current call-stack
+    /// as a list of structs with fields `name`, `file` and `line`. Useful for libraries which want
+    /// to report where a value was defined. Not included in [`all`](LibraryExtension::all),
+    /// since it exposes the call-stack to user code and replaces the `debug()` function
+    /// of [`Debug`](LibraryExtension::Debug). With [`Provenance`](LibraryExtension::Provenance)
+    /// as well, the struct has both functions.
+    CallStack,
+    /// Add a struct `ids` with a function `ids.next(prefix)` returning a new identifier starting
+    /// with `prefix`, unique within the evaluation, such as `"tmp_1a2b3c4d_0"`. The identifiers
+    /// are deterministic, depending only on the seed (see
+    /// [`Evaluator::set_ids_seed`](crate::eval::Evaluator::set_ids_seed)) and the order of
+    /// the calls, so code generators can make the same names each time they are run.
+    Ids,
+    // Make sure if you add anything new (except `CallStack` and `Provenance`), you add it to
+    // `all` below.
 }
 
 impl LibraryExtension {
@@ -83,11 +118,41 @@ impl LibraryExtension {
     pub fn all() -> &'static [Self] {
         use LibraryExtension::*;
         &[
-            StructType, RecordType, EnumType, Map, Filter, Partial, Dedupe, Debug, Print, Pprint,
-            Breakpoint, Json, Abs,
+            StructType,
+            RecordType,
+            EnumType,
+            SetType,
+            NamedTupleType,
+            Map,
+            Filter,
+            Partial,
+            Dedupe,
+            Debug,
+            Print,
+            Pprint,
+            Breakpoint,
+            Json,
+            Abs,
+            ModuleCtx,
+            Ids,
         ]
     }
 
+    /// Add a list of extensions to a [`GlobalsBuilder`]. Unlike calling [`add`](LibraryExtension::add)
+    /// for each, those sharing the `debug` struct add a single struct with all their members.
+    pub fn add_all(extensions: &[Self], builder: &mut GlobalsBuilder) {
+        use LibraryExtension::*;
+        let (debug, rest): (Vec<Self>, Vec<Self>) = extensions
+            .iter()
+            .partition(|x| matches!(x, CallStack | Provenance));
+        for x in rest {
+            x.add(builder);
+        }
+        if !debug.is_empty() {
+            extra::debug_struct(builder, &debug);
+        }
+    }
+
     /// Add a specific extension to a [`GlobalsBuilder`].
     pub fn add(self, builder: &mut GlobalsBuilder) {
         use LibraryExtension::*;
@@ -95,16 +160,22 @@ impl LibraryExtension {
             StructType => structs::global(builder),
             RecordType => record::global(builder),
             EnumType => enumeration::global(builder),
+            SetType => set::global(builder),
+            NamedTupleType => namedtuple::global(builder),
             Map => extra::map(builder),
             Filter => extra::filter(builder),
             Partial => extra::partial(builder),
             Dedupe => extra::dedupe(builder),
             Debug => extra::debug(builder),
+            Provenance => extra::debug_struct(builder, &[Provenance]),
             Print => extra::print(builder),
             Pprint => extra::pprint(builder),
             Breakpoint => breakpoint::global(builder),
             Json => extra::json(builder),
             Abs => extra::abs(builder),
+            ModuleCtx => extra::module_ctx(builder),
+            CallStack => extra::debug_struct(builder, &[CallStack]),
+            Ids => extra::ids(builder),
         }
     }
 }
@@ -0,0 +1,41 @@
+/*
+ * Copyright 2018 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+//! Implementation of `namedtuple` function.
+use crate as starlark;
+use crate::{
+    environment::GlobalsBuilder,
+    values::{namedtuple::NamedTupleType, StringValue},
+};
+
+#[starlark_module]
+pub fn global(builder: &mut GlobalsBuilder) {
+    /// Creates a named tuple type, with the given field names.
+    ///
+    /// Calling the resulting type with the field values, given either positionally or by name,
+    /// creates a named tuple. Its elements can be accessed by index or as attributes.
+    ///
+    /// ```
+    /// # starlark::assert::is_true(r#"
+    /// Point = namedtuple("x", "y")
+    /// p = Point(1, y=2)
+    /// p.x == 1 and p[1] == 2
+    /// # "#);
+    /// ```
+    fn namedtuple(args: Vec<StringValue>) -> NamedTupleType<'v> {
+        NamedTupleType::new(args)
+    }
+}
@@ -31,6 +31,21 @@ use crate::{
 
 #[starlark_module]
 pub fn global(builder: &mut GlobalsBuilder) {
+    /// Creates a record type, with the given field names and types.
+    ///
+    /// Each keyword argument is a field, whose value is either a type or a `field()`
+    /// giving the type and a default. Calling the resulting type creates a record,
+    /// checking the value of every field against its type.
+    ///
+    /// Examples:
+    ///
+    /// ```
+    /// # starlark::assert::is_true(r#"
+    /// IpAddress = record(host=str.type, port=field(int.type, 80))
+    /// rec = IpAddress(host="localhost")
+    /// rec.host == "localhost" and rec.port == 80
+    /// # "#);
+    /// ```
     fn record(kwargs: SmallMap<String, Value>) -> RecordType<'v> {
         // Every Value must either be a field or a value (the type)
         let mut mp = SmallMap::with_capacity(kwargs.len());
@@ -106,6 +121,13 @@ rec_type(host="localhost", port=80, mask=255)
 "#,
             &["extra named", "mask"],
         );
+        assert::fails(
+            r#"
+rec_type = record(host=str.type, port=field(int.type, 80))
+rec_type(host="localhost", port="80")
+"#,
+            &["`\"80\"`", "`int`", "`port`"],
+        );
         assert::pass(
             r#"
 rec_type = record(host=str.type, port=int.type)
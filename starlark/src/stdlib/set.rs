@@ -0,0 +1,245 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Implementation of the `set` function and the methods for the `set` type.
+
+use anyhow::anyhow;
+use gazebo::cell::ARef;
+
+use crate as starlark;
+use crate::{
+    environment::{GlobalsBuilder, MethodsBuilder},
+    values::{none::NoneType, set::Set, Heap, Value, ValueError},
+};
+
+/// Collect the elements of an iterable into a new set.
+fn to_set<'v>(x: Value<'v>, heap: &'v Heap) -> anyhow::Result<Set<'v>> {
+    if let Some(x) = Set::from_value(x) {
+        return Ok((*x).clone());
+    }
+    let mut res = Set::new();
+    x.with_iterator(heap, |it| {
+        for x in it {
+            res.insert_hashed(x.get_hashed()?);
+        }
+        Ok::<_, anyhow::Error>(())
+    })??;
+    Ok(res)
+}
+
+#[starlark_module]
+pub fn global(builder: &mut GlobalsBuilder) {
+    /// set: construct a set.
+    ///
+    /// `set(x)` returns a new set containing the unique elements of the iterable `x`,
+    /// in the order they were first seen. With no argument, `set()` returns a new empty set.
+    ///
+    /// ```
+    /// # starlark::assert::all_true(r#"
+    /// len(set()) == 0
+    /// list(set([3, 1, 3, 2])) == [3, 1, 2]
+    /// # "#);
+    /// ```
+    #[starlark(type(Set::TYPE))]
+    fn set(ref a: Option<Value>) -> Set<'v> {
+        match a {
+            None => Ok(Set::new()),
+            Some(a) => to_set(a, heap),
+        }
+    }
+}
+
+#[starlark_module]
+pub(crate) fn set_methods(builder: &mut MethodsBuilder) {
+    /// set.add: add an element to a set.
+    ///
+    /// `S.add(x)` adds `x` to the set S, if it is not already present, and returns `None`.
+    /// It fails if the set is frozen or has active iterators.
+    ///
+    /// ```
+    /// # starlark::assert::is_true(r#"
+    /// x = set([1])
+    /// x.add(2)
+    /// x.add(1)
+    /// x == set([1, 2])
+    /// # "#);
+    /// ```
+    fn add(this: Value, ref x: Value) -> NoneType {
+        let x = x.get_hashed()?;
+        heap.write_barrier(this);
+        Set::from_value_mut(this)?.unwrap().insert_hashed(x);
+        Ok(NoneType)
+    }
+
+    /// set.clear: remove all the elements of a set.
+    ///
+    /// ```
+    /// # starlark::assert::is_true(r#"
+    /// x = set([1, 2])
+    /// x.clear()
+    /// len(x) == 0
+    /// # "#);
+    /// ```
+    fn clear(this: Value) -> NoneType {
+        Set::from_value_mut(this)?.unwrap().clear();
+        Ok(NoneType)
+    }
+
+    /// set.difference: the elements of a set which are not in an iterable.
+    ///
+    /// ```
+    /// # starlark::assert::all_true(r#"
+    /// set([1, 2, 3]).difference([2]) == set([1, 3])
+    /// # "#);
+    /// ```
+    #[starlark(speculative_exec_safe)]
+    fn difference(this: ARef<Set>, ref other: Value) -> Set<'v> {
+        let other = to_set(other, heap)?;
+        Ok(this.filter(|x| !other.contains_hashed(x)))
+    }
+
+    /// set.discard: remove an element from a set, if present.
+    ///
+    /// `S.discard(x)` removes `x` from the set S and returns `None`. Unlike `remove`,
+    /// it is not an error if `x` is not in the set.
+    ///
+    /// ```
+    /// # starlark::assert::is_true(r#"
+    /// x = set([1, 2])
+    /// x.discard(2)
+    /// x.discard(3)
+    /// x == set([1])
+    /// # "#);
+    /// ```
+    fn discard(this: Value, ref x: Value) -> NoneType {
+        let x = x.get_hashed()?;
+        Set::from_value_mut(this)?.unwrap().remove_hashed(x);
+        Ok(NoneType)
+    }
+
+    /// set.intersection: the elements of a set which are also in an iterable.
+    ///
+    /// ```
+    /// # starlark::assert::all_true(r#"
+    /// set([1, 2, 3]).intersection([3, 2, 4]) == set([2, 3])
+    /// # "#);
+    /// ```
+    #[starlark(speculative_exec_safe)]
+    fn intersection(this: ARef<Set>, ref other: Value) -> Set<'v> {
+        let other = to_set(other, heap)?;
+        Ok(this.filter(|x| other.contains_hashed(x)))
+    }
+
+    /// set.issubset: is every element of a set in an iterable.
+    ///
+    /// ```
+    /// # starlark::assert::all_true(r#"
+    /// set([1, 2]).issubset([1, 2, 3])
+    /// not set([1, 4]).issubset([1, 2, 3])
+    /// # "#);
+    /// ```
+    #[starlark(speculative_exec_safe)]
+    fn issubset(this: ARef<Set>, ref other: Value) -> bool {
+        Ok(this.is_subset(&to_set(other, heap)?))
+    }
+
+    /// set.issuperset: is every element of an iterable in a set.
+    ///
+    /// ```
+    /// # starlark::assert::all_true(r#"
+    /// set([1, 2, 3]).issuperset([1, 2])
+    /// not set([1, 2, 3]).issuperset([1, 4])
+    /// # "#);
+    /// ```
+    #[starlark(speculative_exec_safe)]
+    fn issuperset(this: ARef<Set>, ref other: Value) -> bool {
+        Ok(to_set(other, heap)?.is_subset(&this))
+    }
+
+    /// set.pop: remove and return the most recently added element of a set.
+    ///
+    /// It fails if the set is empty, frozen or has active iterators.
+    ///
+    /// ```
+    /// # starlark::assert::is_true(r#"
+    /// x = set([1, 2])
+    /// x.pop() == 2 and x == set([1])
+    /// # "#);
+    /// ```
+    fn pop(this: Value) -> Value<'v> {
+        match Set::from_value_mut(this)?.unwrap().pop() {
+            Some(x) => Ok(x),
+            None => Err(anyhow!("pop: set is empty")),
+        }
+    }
+
+    /// set.remove: remove an element from a set.
+    ///
+    /// `S.remove(x)` removes `x` from the set S and returns `None`.
+    /// It fails if `x` is not in the set, or the set is frozen or has active iterators.
+    ///
+    /// ```
+    /// # starlark::assert::is_true(r#"
+    /// x = set([1, 2])
+    /// x.remove(2)
+    /// x == set([1])
+    /// # "#);
+    /// ```
+    fn remove(this: Value, ref x: Value) -> NoneType {
+        let hashed = x.get_hashed()?;
+        if Set::from_value_mut(this)?.unwrap().remove_hashed(hashed) {
+            Ok(NoneType)
+        } else {
+            Err(ValueError::KeyNotFound(x.debug_summary()).into())
+        }
+    }
+
+    /// set.union: the elements of a set, followed by those of an iterable.
+    ///
+    /// ```
+    /// # starlark::assert::all_true(r#"
+    /// set([1, 2]).union([2, 3]) == set([1, 2, 3])
+    /// # "#);
+    /// ```
+    #[starlark(speculative_exec_safe)]
+    fn union(this: ARef<Set>, ref other: Value) -> Set<'v> {
+        let mut res = (*this).clone();
+        for x in to_set(other, heap)?.iter_hashed() {
+            res.insert_hashed(x);
+        }
+        Ok(res)
+    }
+
+    /// set.update: add all the elements of an iterable to a set.
+    ///
+    /// ```
+    /// # starlark::assert::is_true(r#"
+    /// x = set([1])
+    /// x.update([2, 3])
+    /// x == set([1, 2, 3])
+    /// # "#);
+    /// ```
+    fn update(this: Value, ref other: Value) -> NoneType {
+        let other = to_set(other, heap)?;
+        heap.write_barrier(this);
+        let mut this = Set::from_value_mut(this)?.unwrap();
+        for x in other.iter_hashed() {
+            this.insert_hashed(x);
+        }
+        Ok(NoneType)
+    }
+}
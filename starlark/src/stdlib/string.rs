@@ -28,6 +28,7 @@ use crate::{
     eval::Arguments,
     stdlib::string::fast_string::convert_str_indices,
     values::{
+        bytes::Encoding,
         none::NoneOr,
         string::{fast_string, interpolation},
         tuple::Tuple,
@@ -268,6 +269,26 @@ pub(crate) fn string_methods(builder: &mut MethodsBuilder) {
         }
     }
 
+    /// string.encode: convert a string to bytes.
+    ///
+    /// `S.encode(encoding="utf-8")` encodes the string S using the given encoding, which may be
+    /// `"utf-8"`, `"ascii"` or `"latin-1"`. It is an error if the string contains characters
+    /// which can't be represented in that encoding.
+    ///
+    /// Examples:
+    ///
+    /// ```
+    /// # starlark::assert::all_true(r#"
+    /// "hello".encode() == b"hello"
+    /// "café".encode("utf-8") == b"caf\xc3\xa9"
+    /// "café".encode("latin-1") == b"caf\xe9"
+    /// # "#);
+    /// ```
+    #[starlark(speculative_exec_safe)]
+    fn encode(this: &str, ref encoding: Option<&str>) -> Vec<u8> {
+        Encoding::new(encoding.unwrap_or("utf-8"))?.encode(this)
+    }
+
     /// [string.find](
     /// https://github.com/google/skylark/blob/3705afa472e466b8b061cce44b47c9ddc6db696d/doc/spec.md#string·find
     /// ): find a substring in a string.
@@ -25,6 +25,17 @@ use crate::{
 
 #[starlark_module]
 pub fn global(builder: &mut GlobalsBuilder) {
+    /// Creates a struct, whose fields are given by the keyword arguments.
+    ///
+    /// Fields are accessed as attributes, and are listed by `dir()`. Two structs are equal
+    /// if they have the same fields with equal values.
+    ///
+    /// ```
+    /// # starlark::assert::is_true(r#"
+    /// s = struct(host="localhost", port=80)
+    /// s.port == 80 and dir(s) == ["host", "port", "to_json"]
+    /// # "#);
+    /// ```
     #[starlark(type(Struct::TYPE))]
     fn r#struct(args: Arguments<'v, '_>) -> Struct<'v> {
         args.no_positional_args(heap)?;
@@ -24,9 +24,13 @@ use std::{
 
 use derivative::Derivative;
 use gazebo::prelude::*;
+use serde::{Deserialize, Serialize};
 use static_assertions::assert_eq_size;
 
-use crate::codemap::{CodeMap, Pos, Span, Spanned};
+use crate::{
+    codemap::{CodeMap, Pos, Span, Spanned},
+    syntax::lexer::TokenInt,
+};
 
 /// Payload types attached to AST nodes.
 pub trait AstPayload: Debug {
@@ -71,8 +75,9 @@ pub type AstAssignIdent = AstAssignIdentP<AstNoPayload>;
 pub type AstArgument = AstArgumentP<AstNoPayload>;
 pub type AstString = Spanned<String>;
 pub type AstParameter = AstParameterP<AstNoPayload>;
-pub type AstInt = Spanned<i32>;
+pub type AstInt = Spanned<TokenInt>;
 pub type AstFloat = Spanned<f64>;
+pub type AstBytes = Spanned<Vec<u8>>;
 pub type AstLoad = AstLoadP<AstNoPayload>;
 pub type AstStmt = AstStmtP<AstNoPayload>;
 
@@ -109,7 +114,8 @@ pub(crate) trait ToAst: Sized {
 
 impl<T> ToAst for T {}
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(bound = "P: AstPayload<IdentPayload = (), IdentAssignPayload = (), DefPayload = ()>")]
 pub enum ArgumentP<P: AstPayload> {
     Positional(AstExprP<P>),
     Named(AstString, AstExprP<P>),
@@ -117,7 +123,8 @@ pub enum ArgumentP<P: AstPayload> {
     KwArgs(AstExprP<P>),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(bound = "P: AstPayload<IdentPayload = (), IdentAssignPayload = (), DefPayload = ()>")]
 pub enum ParameterP<P: AstPayload> {
     Normal(AstAssignIdentP<P>, Option<Box<AstExprP<P>>>),
     WithDefaultValue(
@@ -130,14 +137,16 @@ pub enum ParameterP<P: AstPayload> {
     KwArgs(AstAssignIdentP<P>, Option<Box<AstExprP<P>>>),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum AstLiteral {
     Int(AstInt),
     Float(AstFloat),
     String(AstString),
+    Bytes(AstBytes),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(bound = "P: AstPayload<IdentPayload = (), IdentAssignPayload = (), DefPayload = ()>")]
 pub enum ExprP<P: AstPayload> {
     Tuple(Vec<AstExprP<P>>),
     Dot(Box<AstExprP<P>>, AstString),
@@ -169,7 +178,8 @@ pub enum ExprP<P: AstPayload> {
 }
 
 /// In some places e.g. AssignModify, the Tuple case is not allowed.
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(bound = "P: AstPayload<IdentPayload = (), IdentAssignPayload = (), DefPayload = ()>")]
 pub enum AssignP<P: AstPayload> {
     // We use Tuple for both Tuple and List,
     // as these have the same semantics in Starlark.
@@ -180,30 +190,34 @@ pub enum AssignP<P: AstPayload> {
 }
 
 /// Identifier in assign position.
-#[derive(Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(bound = "P: AstPayload<IdentPayload = (), IdentAssignPayload = (), DefPayload = ()>")]
 pub struct AssignIdentP<P: AstPayload>(pub String, pub P::IdentAssignPayload);
 
 /// `load` statement.
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(bound = "P: AstPayload<IdentPayload = (), IdentAssignPayload = (), DefPayload = ()>")]
 pub struct LoadP<P: AstPayload> {
     pub module: AstString,
     pub args: Vec<(AstAssignIdentP<P>, AstString)>,
     pub visibility: Visibility,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(bound = "P: AstPayload<IdentPayload = (), IdentAssignPayload = (), DefPayload = ()>")]
 pub struct ForClauseP<P: AstPayload> {
     pub var: AstAssignP<P>,
     pub over: AstExprP<P>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(bound = "P: AstPayload<IdentPayload = (), IdentAssignPayload = (), DefPayload = ()>")]
 pub enum ClauseP<P: AstPayload> {
     For(ForClauseP<P>),
     If(AstExprP<P>),
 }
 
-#[derive(Debug, Clone, Copy, Dupe, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Dupe, Eq, PartialEq, Serialize, Deserialize)]
 pub enum BinOp {
     Or,
     And,
@@ -228,7 +242,7 @@ pub enum BinOp {
     RightShift,
 }
 
-#[derive(Debug, Clone, Copy, Dupe, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Dupe, PartialEq, Eq, Serialize, Deserialize)]
 pub enum AssignOp {
     Add,         // +=
     Subtract,    // -=
@@ -243,13 +257,14 @@ pub enum AssignOp {
     RightShift,  // >>=
 }
 
-#[derive(Debug, Copy, Clone, Dupe, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Dupe, Eq, PartialEq, Serialize, Deserialize)]
 pub enum Visibility {
     Private,
     Public,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(bound = "P: AstPayload<IdentPayload = (), IdentAssignPayload = (), DefPayload = ()>")]
 pub enum StmtP<P: AstPayload> {
     Break,
     Continue,
@@ -338,7 +353,7 @@ impl Display for AssignOp {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match *self {
             AssignOp::Add => f.write_str(" += "),
-            AssignOp::Subtract => f.write_str(" += "),
+            AssignOp::Subtract => f.write_str(" -= "),
             AssignOp::Multiply => f.write_str(" *= "),
             AssignOp::Divide => f.write_str(" /= "),
             AssignOp::FloorDivide => f.write_str(" //= "),
@@ -387,12 +402,29 @@ fn fmt_string_literal(f: &mut Formatter<'_>, s: &str) -> fmt::Result {
     f.write_str("\"")
 }
 
+pub(crate) fn fmt_bytes_literal(f: &mut Formatter<'_>, s: &[u8]) -> fmt::Result {
+    f.write_str("b\"")?;
+    for x in s {
+        match x {
+            b'\n' => f.write_str("\\n")?,
+            b'\t' => f.write_str("\\t")?,
+            b'\r' => f.write_str("\\r")?,
+            b'"' => f.write_str("\\\"")?,
+            b'\\' => f.write_str("\\\\")?,
+            b' '..=b'~' => write!(f, "{}", *x as char)?,
+            x => write!(f, "\\x{:02x}", x)?,
+        }
+    }
+    f.write_str("\"")
+}
+
 impl Display for AstLiteral {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
             AstLiteral::Int(i) => write!(f, "{}", &i.node),
             AstLiteral::Float(n) => write!(f, "{}", &n.node),
             AstLiteral::String(s) => fmt_string_literal(f, &s.node),
+            AstLiteral::Bytes(s) => fmt_bytes_literal(f, &s.node),
         }
     }
 }
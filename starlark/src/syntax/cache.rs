@@ -0,0 +1,204 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Serialize parsed modules, so they can be cached between runs rather than parsed again.
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    fs,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    codemap::CodeMap,
+    syntax::{
+        ast::{AstModule, AstStmt},
+        Dialect,
+    },
+};
+
+/// Written at the start of serialized modules, the format changes between versions.
+const HEADER: &[u8] = concat!("starlark-ast-", env!("CARGO_PKG_VERSION"), "\n").as_bytes();
+
+#[derive(Debug, Error)]
+enum AstCacheError {
+    #[error("Not a module serialized by this version of starlark")]
+    WrongHeader,
+}
+
+#[derive(Serialize)]
+struct SerializeModule<'a> {
+    filename: &'a str,
+    source: &'a str,
+    statement: &'a AstStmt,
+}
+
+#[derive(Deserialize)]
+struct DeserializeModule {
+    filename: String,
+    source: String,
+    statement: AstStmt,
+}
+
+impl AstModule {
+    /// Serialize the module, including its source, so it can be restored with
+    /// [`from_bytes`](AstModule::from_bytes) without parsing it again.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut res = HEADER.to_vec();
+        bincode::serialize_into(
+            &mut res,
+            &SerializeModule {
+                filename: self.codemap.filename(),
+                source: self.codemap.source(),
+                statement: &self.statement,
+            },
+        )
+        .expect("serializing to a Vec can't fail");
+        res
+    }
+
+    /// Restore a module serialized with [`to_bytes`](AstModule::to_bytes). Fails if the
+    /// bytes were written by a different version of this crate.
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        let bytes = bytes
+            .strip_prefix(HEADER)
+            .ok_or(AstCacheError::WrongHeader)?;
+        let module: DeserializeModule = bincode::deserialize(bytes)?;
+        Ok(AstModule {
+            codemap: CodeMap::new(module.filename, module.source),
+            statement: module.statement,
+        })
+    }
+}
+
+/// Parses modules, keeping the results in a directory keyed by a hash of the file name,
+/// contents and [`Dialect`], so unchanged files don't need parsing again on the next run.
+/// Entries which can't be read, e.g. because they were written by another version of this
+/// crate, are ignored and replaced. Nothing is ever removed from the directory.
+pub struct AstCache {
+    dir: PathBuf,
+}
+
+impl AstCache {
+    /// Cache parsed modules in `dir`, which is created if it doesn't exist.
+    pub fn new(dir: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    fn entry(&self, filename: &str, content: &str, dialect: &Dialect) -> PathBuf {
+        let mut hasher = DefaultHasher::new();
+        HEADER.hash(&mut hasher);
+        filename.hash(&mut hasher);
+        content.hash(&mut hasher);
+        dialect.hash(&mut hasher);
+        self.dir.join(format!("{:016x}.ast", hasher.finish()))
+    }
+
+    /// Like [`AstModule::parse`], but reusing the result of an earlier parse of the same
+    /// content if there is one.
+    pub fn parse(
+        &self,
+        filename: &str,
+        content: String,
+        dialect: &Dialect,
+    ) -> anyhow::Result<AstModule> {
+        let entry = self.entry(filename, &content, dialect);
+        if let Ok(bytes) = fs::read(&entry) {
+            if let Ok(module) = AstModule::from_bytes(&bytes) {
+                if module.codemap.source() == content {
+                    return Ok(module);
+                }
+            }
+        }
+        let module = AstModule::parse(filename, content, dialect)?;
+        // Failing to write the cache only makes the next run slower.
+        let _ignored = fs::write(&entry, module.to_bytes());
+        Ok(module)
+    }
+
+    /// Like [`AstModule::parse_file`], but reusing the result of an earlier parse of the same
+    /// content if there is one.
+    pub fn parse_file(&self, path: &Path, dialect: &Dialect) -> anyhow::Result<AstModule> {
+        let content = fs::read_to_string(path)?;
+        self.parse(&path.to_string_lossy(), content, dialect)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        environment::{Globals, Module},
+        eval::Evaluator,
+    };
+
+    fn eval(ast: AstModule) -> String {
+        let module = Module::new();
+        let mut eval = Evaluator::new(&module);
+        eval.eval_module(ast, &Globals::standard())
+            .unwrap()
+            .to_string()
+    }
+
+    const PROGRAM: &str = r#"
+def f(x, *args, y = 1, **kwargs):
+    return [x + y, args, kwargs]
+z = {k: v for k, v in [(1, 2.5)] if k}
+f(-1, "a", y = 2, w = z)[0:3]
+"#;
+
+    #[test]
+    fn test_bytes_roundtrip() {
+        let ast = AstModule::parse("a.star", PROGRAM.to_owned(), &Dialect::Extended).unwrap();
+        let bytes = ast.to_bytes();
+        let restored = AstModule::from_bytes(&bytes).unwrap();
+        assert_eq!(
+            restored.statement.node.to_string(),
+            ast.statement.node.to_string()
+        );
+        assert_eq!(restored.codemap.filename(), "a.star");
+        assert_eq!(eval(restored), "[1, (\"a\",), {\"w\": {1: 2.5}}]");
+
+        assert!(AstModule::from_bytes(&bytes[1..]).is_err());
+    }
+
+    #[test]
+    fn test_ast_cache() {
+        let dir = std::env::temp_dir().join(format!("starlark-ast-cache-{}", std::process::id()));
+        let cache = AstCache::new(&dir).unwrap();
+        let parse = || {
+            cache
+                .parse("a.star", PROGRAM.to_owned(), &Dialect::Extended)
+                .unwrap()
+        };
+        parse();
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        assert_eq!(eval(parse()), "[1, (\"a\",), {\"w\": {1: 2.5}}]");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        cache
+            .parse("a.star", "1".to_owned(), &Dialect::Extended)
+            .unwrap();
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
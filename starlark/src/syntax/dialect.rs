@@ -16,6 +16,7 @@
  */
 
 use gazebo::prelude::*;
+use serde::Serialize;
 use thiserror::Error;
 
 use crate::{
@@ -36,10 +37,12 @@ enum DialectError {
     KeywordOnlyArguments,
     #[error("type annotations are not allowed in this dialect")]
     Types,
+    #[error("bytes literals are not allowed in this dialect")]
+    Bytes,
 }
 
 /// Starlark language features to enable, e.g. [`Standard`](Dialect::Standard) to follow the Starlark standard.
-#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize)]
 pub struct Dialect {
     /// Are `def` statements permitted.
     /// Enabled in both [`Standard`](Dialect::Standard) and [`Extended`](Dialect::Extended).
@@ -66,6 +69,9 @@ pub struct Dialect {
     /// Are `for`, `if` and other statements allowed at the top level.
     /// Only enabled in [`Extended`](Dialect::Extended).
     pub enable_top_level_stmt: bool,
+    /// Are `b"..."` bytes literals permitted, as per the proposed addition to the standard.
+    /// Enabled in both [`Standard`](Dialect::Standard) and [`Extended`](Dialect::Extended).
+    pub enable_bytes: bool,
 }
 
 // These are morally enumerations, so give them enumeration-like names
@@ -82,6 +88,7 @@ impl Dialect {
         enable_tabs: true,
         enable_load_reexport: true, // But they plan to change it
         enable_top_level_stmt: false,
+        enable_bytes: true,
     };
 
     /// A superset of [`Standard`](Dialect::Standard), including extra features (types, top-level statements etc).
@@ -94,6 +101,7 @@ impl Dialect {
         enable_tabs: true,
         enable_load_reexport: true,
         enable_top_level_stmt: true,
+        enable_bytes: true,
     };
 }
 
@@ -114,6 +122,18 @@ impl Dialect {
         }
     }
 
+    pub(crate) fn check_bytes<T>(
+        &self,
+        codemap: &CodeMap,
+        x: Spanned<T>,
+    ) -> anyhow::Result<Spanned<T>> {
+        if self.enable_bytes {
+            Ok(x)
+        } else {
+            err(codemap, x.span, DialectError::Bytes)
+        }
+    }
+
     pub(crate) fn check_def<T>(
         &self,
         codemap: &CodeMap,
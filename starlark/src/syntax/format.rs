@@ -0,0 +1,879 @@
+/*
+ * Copyright 2018 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Pretty-print a module, in the style of
+//! [buildifier](https://github.com/bazelbuild/buildtools/tree/master/buildifier).
+
+use std::{ops::Range, slice};
+
+use crate::{
+    codemap::{CodeMap, Pos, Span},
+    syntax::{
+        ast::{
+            Argument, Assign, AstArgument, AstAssign, AstExpr, AstLiteral, AstParameter, AstStmt,
+            AstString, BinOp, Clause, Expr, ForClause, Parameter, Stmt,
+        },
+        AstModule, Dialect,
+    },
+};
+
+const INDENT: &str = "    ";
+
+/// Operator precedence, following the grammar. Higher binds tighter.
+mod prec {
+    pub(super) const TEST: u8 = 1; // `lambda` and `x if c else y`
+    pub(super) const OR: u8 = 2;
+    pub(super) const AND: u8 = 3;
+    pub(super) const NOT: u8 = 4;
+    pub(super) const COMPARE: u8 = 5;
+    pub(super) const BIT_OR: u8 = 6;
+    pub(super) const BIT_XOR: u8 = 7;
+    pub(super) const BIT_AND: u8 = 8;
+    pub(super) const SHIFT: u8 = 9;
+    pub(super) const ARITH: u8 = 10;
+    pub(super) const PRODUCT: u8 = 11;
+    pub(super) const UNARY: u8 = 12;
+    pub(super) const PRIMARY: u8 = 13;
+}
+
+fn bin_op_prec(op: BinOp) -> u8 {
+    match op {
+        BinOp::Or => prec::OR,
+        BinOp::And => prec::AND,
+        BinOp::Equal
+        | BinOp::NotEqual
+        | BinOp::Less
+        | BinOp::Greater
+        | BinOp::LessOrEqual
+        | BinOp::GreaterOrEqual
+        | BinOp::In
+        | BinOp::NotIn => prec::COMPARE,
+        BinOp::BitOr => prec::BIT_OR,
+        BinOp::BitXor => prec::BIT_XOR,
+        BinOp::BitAnd => prec::BIT_AND,
+        BinOp::LeftShift | BinOp::RightShift => prec::SHIFT,
+        BinOp::Add | BinOp::Subtract => prec::ARITH,
+        BinOp::Multiply | BinOp::Percent | BinOp::Divide | BinOp::FloorDivide => prec::PRODUCT,
+    }
+}
+
+fn expr_prec(x: &Expr) -> u8 {
+    match x {
+        Expr::Lambda(..) | Expr::If(..) => prec::TEST,
+        Expr::Not(..) => prec::NOT,
+        Expr::Op(_, op, _) => bin_op_prec(*op),
+        Expr::Minus(..) | Expr::Plus(..) | Expr::BitNot(..) => prec::UNARY,
+        // We always print tuples with brackets
+        _ => prec::PRIMARY,
+    }
+}
+
+/// A `#` comment in the source.
+struct Comment {
+    pos: Pos,
+    line: usize,
+    text: String,
+}
+
+/// Find all the comments, skipping over anything that looks like one inside a string.
+fn comments(codemap: &CodeMap) -> Vec<Comment> {
+    let source = codemap.source();
+    let mut res = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '#' => {
+                let end = source[i..].find('\n').map_or(source.len(), |x| i + x);
+                let pos = Pos::new(i as u32);
+                res.push(Comment {
+                    pos,
+                    line: codemap.find_line(pos),
+                    text: source[i..end].trim_end().to_owned(),
+                });
+                while chars.peek().map_or(false, |(j, _)| *j < end) {
+                    chars.next();
+                }
+            }
+            '\'' | '"' => {
+                let triple = c.to_string().repeat(3);
+                let quote = if source[i..].starts_with(&triple) {
+                    triple
+                } else {
+                    c.to_string()
+                };
+                for _ in 1..quote.len() {
+                    chars.next();
+                }
+                while let Some((j, d)) = chars.next() {
+                    if d == '\\' {
+                        chars.next();
+                    } else if source[j..].starts_with(&quote) {
+                        for _ in 1..quote.len() {
+                            chars.next();
+                        }
+                        break;
+                    } else if d == '\n' && quote.len() == 1 {
+                        // Unterminated string, which the parser would have rejected
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    res
+}
+
+struct Printer<'a> {
+    codemap: &'a CodeMap,
+    comments: Vec<Comment>,
+    /// Comments before this index have been printed.
+    next_comment: usize,
+    /// The last line of the original source we printed something from.
+    last_line: Option<usize>,
+    out: String,
+    indent: usize,
+}
+
+impl<'a> Printer<'a> {
+    fn line(&self, pos: Pos) -> usize {
+        self.codemap.find_line(pos)
+    }
+
+    fn write_indent(&mut self) {
+        for _ in 0..self.indent {
+            self.out.push_str(INDENT);
+        }
+    }
+
+    /// Called before printing something which started at `line` in the original,
+    /// preserving (at most one) blank line before it.
+    fn blank_lines(&mut self, line: usize) {
+        if let Some(last) = self.last_line {
+            if (last + 1..line).any(|i| self.codemap.source_line(i).trim().is_empty()) {
+                self.out.push('\n');
+            }
+        }
+    }
+
+    /// Print the comments which appear on lines before `line`.
+    fn leading_comments(&mut self, line: usize) {
+        while let Some(c) = self.comments.get(self.next_comment) {
+            if c.line >= line {
+                break;
+            }
+            let (c_line, text) = (c.line, c.text.clone());
+            self.blank_lines(c_line);
+            self.write_indent();
+            self.out.push_str(&text);
+            self.out.push('\n');
+            self.last_line = Some(c_line);
+            self.next_comment += 1;
+        }
+    }
+
+    /// Print a comment on `line` (if there is one) at the end of the current line.
+    fn trailing_comment(&mut self, line: usize) {
+        if let Some(c) = self.comments.get(self.next_comment) {
+            if c.line == line {
+                self.out.push_str("  ");
+                self.out.push_str(&c.text);
+                self.next_comment += 1;
+            }
+        }
+    }
+
+    /// Are there any comments strictly within `span`.
+    fn has_comments_within(&self, span: Span) -> bool {
+        self.comments
+            .get(self.next_comment)
+            .map_or(false, |c| c.pos < span.end())
+    }
+
+    fn stmts(&mut self, x: &AstStmt) {
+        match &x.node {
+            Stmt::Statements(xs) => {
+                for x in xs {
+                    self.stmts(x)
+                }
+            }
+            _ => self.stmt(x),
+        }
+    }
+
+    /// Print an indented block, e.g. the body of a `def`.
+    fn suite(&mut self, x: &AstStmt) {
+        self.indent += 1;
+        self.stmts(x);
+        self.indent -= 1;
+    }
+
+    fn stmt(&mut self, x: &AstStmt) {
+        let begin_line = self.line(x.span.begin());
+        self.leading_comments(begin_line);
+        self.blank_lines(begin_line);
+        self.write_indent();
+        match &x.node {
+            Stmt::Def(..) | Stmt::If(..) | Stmt::IfElse(..) | Stmt::For(..) => {
+                self.compound(x);
+                return;
+            }
+            _ => {}
+        }
+        if self.has_comments_within(x.span) {
+            // We can't move comments around inside a statement, so leave it as it is.
+            self.out.push_str(self.codemap.source_span(x.span));
+            while self
+                .comments
+                .get(self.next_comment)
+                .map_or(false, |c| c.pos < x.span.end())
+            {
+                self.next_comment += 1;
+            }
+        } else {
+            self.simple(x);
+        }
+        let end_line = self.line(x.span.end());
+        self.trailing_comment(end_line);
+        self.out.push('\n');
+        self.last_line = Some(end_line);
+    }
+
+    fn simple(&mut self, x: &AstStmt) {
+        match &x.node {
+            Stmt::Break => self.out.push_str("break"),
+            Stmt::Continue => self.out.push_str("continue"),
+            Stmt::Pass => self.out.push_str("pass"),
+            Stmt::Return(None) => self.out.push_str("return"),
+            Stmt::Return(Some(e)) => {
+                self.out.push_str("return ");
+                self.expr(e, prec::TEST);
+            }
+            Stmt::Expression(e) => self.expr(e, prec::TEST),
+            Stmt::Assign(lhs, rhs) => {
+                self.assign(lhs, true);
+                self.out.push_str(" = ");
+                self.expr(rhs, prec::TEST);
+            }
+            Stmt::AssignModify(lhs, op, rhs) => {
+                self.assign(lhs, true);
+                self.out.push_str(&op.to_string());
+                self.expr(rhs, prec::TEST);
+            }
+            Stmt::Load(load) => {
+                self.out.push_str("load");
+                let exploded = self.exploded(x.span.begin(), load.node.module.span);
+                let items = Some(Item::Literal(&load.node.module))
+                    .into_iter()
+                    .chain(load.node.args.iter().map(|(ours, theirs)| {
+                        if ours.node.0 == theirs.node {
+                            Item::Literal(theirs)
+                        } else {
+                            Item::LoadNamed(&ours.node.0, theirs)
+                        }
+                    }))
+                    .collect::<Vec<_>>();
+                self.items("(", &items, ")", exploded);
+            }
+            Stmt::Statements(_)
+            | Stmt::Def(..)
+            | Stmt::If(..)
+            | Stmt::IfElse(..)
+            | Stmt::For(..) => unreachable!("not a simple statement"),
+        }
+    }
+
+    /// Print a statement with a body, the indentation has already been written.
+    fn compound(&mut self, x: &AstStmt) {
+        let begin_line = self.line(x.span.begin());
+        match &x.node {
+            Stmt::Def(name, params, ret, body, _) => {
+                self.out.push_str("def ");
+                self.out.push_str(&name.node.0);
+                let exploded = match params.first() {
+                    Some(p) => self.exploded(x.span.begin(), p.span),
+                    None => false,
+                };
+                let items = params.iter().map(Item::Parameter).collect::<Vec<_>>();
+                self.items("(", &items, ")", exploded);
+                if let Some(ret) = ret {
+                    self.out.push_str(" -> ");
+                    self.expr(ret, prec::TEST);
+                }
+                self.header_end(begin_line);
+                self.suite(body);
+            }
+            Stmt::For(var, box (over, body)) => {
+                self.out.push_str("for ");
+                self.assign(var, true);
+                self.out.push_str(" in ");
+                self.expr(over, prec::TEST);
+                self.header_end(begin_line);
+                self.suite(body);
+            }
+            Stmt::If(cond, box body) => {
+                self.out.push_str("if ");
+                self.expr(cond, prec::TEST);
+                self.header_end(begin_line);
+                self.suite(body);
+            }
+            Stmt::IfElse(cond, box (then, els)) => {
+                self.out.push_str("if ");
+                self.expr(cond, prec::TEST);
+                self.header_end(begin_line);
+                self.suite(then);
+                self.else_(els);
+            }
+            _ => unreachable!("not a compound statement"),
+        }
+    }
+
+    /// Print the `else` branch of an `if`, turning `else: if` into `elif`.
+    fn else_(&mut self, x: &AstStmt) {
+        let (cond, then, els) = match &x.node {
+            Stmt::If(cond, box then) => (cond, then, None),
+            Stmt::IfElse(cond, box (then, els)) => (cond, then, Some(els)),
+            _ => {
+                self.write_indent();
+                self.out.push_str("else:\n");
+                self.suite(x);
+                return;
+            }
+        };
+        let begin_line = self.line(x.span.begin());
+        self.leading_comments(begin_line);
+        self.write_indent();
+        self.out.push_str("elif ");
+        self.expr(cond, prec::TEST);
+        self.header_end(begin_line);
+        self.suite(then);
+        if let Some(els) = els {
+            self.else_(els);
+        }
+    }
+
+    fn header_end(&mut self, line: usize) {
+        self.out.push(':');
+        self.trailing_comment(line);
+        self.out.push('\n');
+        // Don't preserve blank lines at the start of a block.
+        self.last_line = None;
+    }
+
+    /// Should a bracketed list be printed one item per line. We preserve the
+    /// choice made by the author: if the first item was on a different line to the
+    /// start of the expression, it is.
+    fn exploded(&self, begin: Pos, first: Span) -> bool {
+        self.line(begin) != self.line(first.begin())
+    }
+
+    fn items(&mut self, open: &str, items: &[Item], close: &str, exploded: bool) {
+        self.out.push_str(open);
+        if exploded && !items.is_empty() {
+            self.out.push('\n');
+            self.indent += 1;
+            for x in items {
+                self.write_indent();
+                self.item(x);
+                self.out.push_str(",\n");
+            }
+            self.indent -= 1;
+            self.write_indent();
+        } else {
+            for (i, x) in items.iter().enumerate() {
+                if i != 0 {
+                    self.out.push_str(", ");
+                }
+                self.item(x);
+            }
+            if let [Item::TupleElem(_)] = items {
+                self.out.push(',');
+            }
+        }
+        self.out.push_str(close);
+    }
+
+    fn item(&mut self, x: &Item) {
+        match x {
+            Item::Expr(x) | Item::TupleElem(x) => self.expr(x, prec::TEST),
+            Item::DictEntry(k, v) => {
+                self.expr(k, prec::TEST);
+                self.out.push_str(": ");
+                self.expr(v, prec::TEST);
+            }
+            Item::Argument(x) => match &x.node {
+                Argument::Positional(e) => self.expr(e, prec::TEST),
+                Argument::Named(name, e) => {
+                    self.out.push_str(&name.node);
+                    self.out.push_str(" = ");
+                    self.expr(e, prec::TEST);
+                }
+                Argument::Args(e) => {
+                    self.out.push('*');
+                    self.expr(e, prec::TEST);
+                }
+                Argument::KwArgs(e) => {
+                    self.out.push_str("**");
+                    self.expr(e, prec::TEST);
+                }
+            },
+            Item::Parameter(x) => {
+                let (prefix, name, typ, default) = match &x.node {
+                    Parameter::Normal(name, typ) => ("", name, typ, None),
+                    Parameter::WithDefaultValue(name, typ, default) => {
+                        ("", name, typ, Some(default))
+                    }
+                    Parameter::NoArgs => {
+                        self.out.push('*');
+                        return;
+                    }
+                    Parameter::Args(name, typ) => ("*", name, typ, None),
+                    Parameter::KwArgs(name, typ) => ("**", name, typ, None),
+                };
+                self.out.push_str(prefix);
+                self.out.push_str(&name.node.0);
+                if let Some(typ) = typ {
+                    self.out.push_str(": ");
+                    self.expr(typ, prec::TEST);
+                }
+                if let Some(default) = default {
+                    self.out.push_str(" = ");
+                    self.expr(default, prec::TEST);
+                }
+            }
+            Item::Literal(x) => self.string(x.span),
+            Item::LoadNamed(name, x) => {
+                self.out.push_str(name);
+                self.out.push_str(" = ");
+                self.string(x.span);
+            }
+        }
+    }
+
+    /// Print a string literal, preferring double quotes.
+    fn string(&mut self, span: Span) {
+        let s = self.codemap.source_span(span);
+        let (prefix, rest) = match s.strip_prefix('r') {
+            Some(rest) => ("r", rest),
+            None => ("", s),
+        };
+        let simple = rest.len() >= 2
+            && rest.starts_with('\'')
+            && !rest.starts_with("'''")
+            && !rest.contains(&['"', '\\'][..]);
+        if simple {
+            self.out.push_str(prefix);
+            self.out.push('"');
+            self.out.push_str(&rest[1..rest.len() - 1]);
+            self.out.push('"');
+        } else {
+            self.out.push_str(s);
+        }
+    }
+
+    /// Print an assignment target. At the top-level, tuples don't need brackets.
+    fn assign(&mut self, x: &AstAssign, top: bool) {
+        match &x.node {
+            Assign::Tuple(xs) => {
+                if !top {
+                    self.out.push('(');
+                }
+                for (i, x) in xs.iter().enumerate() {
+                    if i != 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.assign(x, false);
+                }
+                if xs.len() == 1 {
+                    self.out.push(',');
+                }
+                if !top {
+                    self.out.push(')');
+                }
+            }
+            Assign::ArrayIndirection(box (e, i)) => {
+                self.expr(e, prec::PRIMARY);
+                self.out.push('[');
+                self.expr(i, prec::TEST);
+                self.out.push(']');
+            }
+            Assign::Dot(e, name) => {
+                self.expr(e, prec::PRIMARY);
+                self.out.push('.');
+                self.out.push_str(&name.node);
+            }
+            Assign::Identifier(name) => self.out.push_str(&name.node.0),
+        }
+    }
+
+    fn for_clause(&mut self, x: &ForClause) {
+        self.out.push_str(" for ");
+        self.assign(&x.var, true);
+        self.out.push_str(" in ");
+        self.expr(&x.over, prec::OR);
+    }
+
+    fn clauses(&mut self, for_: &ForClause, clauses: &[Clause]) {
+        self.for_clause(for_);
+        for x in clauses {
+            match x {
+                Clause::For(x) => self.for_clause(x),
+                Clause::If(x) => {
+                    self.out.push_str(" if ");
+                    self.expr(x, prec::OR);
+                }
+            }
+        }
+    }
+
+    /// Print an expression, adding brackets if it binds less tightly than `min_prec`.
+    fn expr(&mut self, x: &AstExpr, min_prec: u8) {
+        let brackets = expr_prec(&x.node) < min_prec;
+        if brackets {
+            self.out.push('(');
+        }
+        match &x.node {
+            Expr::Tuple(xs) => {
+                let items = xs.iter().map(Item::TupleElem).collect::<Vec<_>>();
+                let exploded = match xs.first() {
+                    Some(first) => self.exploded(x.span.begin(), first.span),
+                    None => false,
+                };
+                self.items("(", &items, ")", exploded)
+            }
+            Expr::Dot(e, name) => {
+                self.expr(e, prec::PRIMARY);
+                self.out.push('.');
+                self.out.push_str(&name.node);
+            }
+            Expr::Call(f, args) => {
+                self.expr(f, prec::PRIMARY);
+                let exploded = match args.first() {
+                    Some(first) => self.exploded(f.span.end(), first.span),
+                    None => false,
+                };
+                let items = args.iter().map(Item::Argument).collect::<Vec<_>>();
+                self.items("(", &items, ")", exploded)
+            }
+            Expr::ArrayIndirection(box (e, i)) => {
+                self.expr(e, prec::PRIMARY);
+                self.out.push('[');
+                self.expr(i, prec::TEST);
+                self.out.push(']');
+            }
+            Expr::Slice(e, i1, i2, i3) => {
+                self.expr(e, prec::PRIMARY);
+                self.out.push('[');
+                if let Some(i1) = i1 {
+                    self.expr(i1, prec::TEST);
+                }
+                self.out.push(':');
+                if let Some(i2) = i2 {
+                    self.expr(i2, prec::TEST);
+                }
+                if let Some(i3) = i3 {
+                    self.out.push(':');
+                    self.expr(i3, prec::TEST);
+                }
+                self.out.push(']');
+            }
+            Expr::Identifier(name, _) => self.out.push_str(&name.node),
+            Expr::Lambda(params, body, _) => {
+                self.out.push_str("lambda");
+                for (i, p) in params.iter().enumerate() {
+                    self.out.push_str(if i == 0 { " " } else { ", " });
+                    self.item(&Item::Parameter(p));
+                }
+                self.out.push_str(": ");
+                self.expr(body, prec::TEST);
+            }
+            Expr::Literal(AstLiteral::String(_)) => self.string(x.span),
+            // Keep numbers as written, e.g. hex or exponent notation.
+            Expr::Literal(_) => self.out.push_str(self.codemap.source_span(x.span)),
+            Expr::Not(e) => {
+                self.out.push_str("not ");
+                self.expr(e, prec::NOT);
+            }
+            Expr::Minus(e) => {
+                self.out.push('-');
+                self.expr(e, prec::UNARY);
+            }
+            Expr::Plus(e) => {
+                self.out.push('+');
+                self.expr(e, prec::UNARY);
+            }
+            Expr::BitNot(e) => {
+                self.out.push('~');
+                self.expr(e, prec::UNARY);
+            }
+            Expr::Op(l, op, r) => {
+                let p = bin_op_prec(*op);
+                if p == prec::COMPARE {
+                    // Comparisons don't chain
+                    self.expr(l, p + 1);
+                } else {
+                    self.expr(l, p);
+                }
+                self.out.push_str(&op.to_string());
+                self.expr(r, p + 1);
+            }
+            Expr::If(box (cond, then, els)) => {
+                self.expr(then, prec::OR);
+                self.out.push_str(" if ");
+                self.expr(cond, prec::OR);
+                self.out.push_str(" else ");
+                self.expr(els, prec::TEST);
+            }
+            Expr::List(xs) => {
+                let items = xs.iter().map(Item::Expr).collect::<Vec<_>>();
+                let exploded = match xs.first() {
+                    Some(first) => self.exploded(x.span.begin(), first.span),
+                    None => false,
+                };
+                self.items("[", &items, "]", exploded)
+            }
+            Expr::Dict(xs) => {
+                let items = xs
+                    .iter()
+                    .map(|(k, v)| Item::DictEntry(k, v))
+                    .collect::<Vec<_>>();
+                let exploded = match xs.first() {
+                    Some((first, _)) => self.exploded(x.span.begin(), first.span),
+                    None => false,
+                };
+                self.items("{", &items, "}", exploded)
+            }
+            Expr::ListComprehension(e, for_, clauses) => {
+                self.out.push('[');
+                self.expr(e, prec::TEST);
+                self.clauses(for_, clauses);
+                self.out.push(']');
+            }
+            Expr::DictComprehension(box (k, v), for_, clauses) => {
+                self.out.push('{');
+                self.expr(k, prec::TEST);
+                self.out.push_str(": ");
+                self.expr(v, prec::TEST);
+                self.clauses(for_, clauses);
+                self.out.push('}');
+            }
+        }
+        if brackets {
+            self.out.push(')');
+        }
+    }
+}
+
+/// Something which appears in a bracketed, comma-separated list.
+enum Item<'a> {
+    Expr(&'a AstExpr),
+    TupleElem(&'a AstExpr),
+    DictEntry(&'a AstExpr, &'a AstExpr),
+    Argument(&'a AstArgument),
+    Parameter(&'a AstParameter),
+    Literal(&'a AstString),
+    LoadNamed(&'a str, &'a AstString),
+}
+
+/// A top-level statement, along with the comments and blank lines before it.
+struct Chunk {
+    /// The lines it occupied in the original.
+    lines: Range<usize>,
+    /// Where it is in the formatted output.
+    text: Range<usize>,
+}
+
+impl AstModule {
+    /// Print the module, returning `None` if the result wouldn't parse.
+    fn print(&self) -> Option<(String, Vec<Chunk>)> {
+        let mut printer = Printer {
+            codemap: &self.codemap,
+            comments: comments(&self.codemap),
+            next_comment: 0,
+            last_line: None,
+            out: String::new(),
+            indent: 0,
+        };
+        let stmts = match &self.statement.node {
+            Stmt::Statements(xs) => xs.as_slice(),
+            _ => slice::from_ref(&self.statement),
+        };
+        let mut chunks = Vec::with_capacity(stmts.len());
+        let mut next_line = 0;
+        for x in stmts {
+            let start = printer.out.len();
+            printer.stmts(x);
+            let end_line = last_line(&self.codemap, x) + 1;
+            chunks.push(Chunk {
+                lines: next_line..end_line,
+                text: start..printer.out.len(),
+            });
+            next_line = end_line;
+        }
+        printer.leading_comments(usize::MAX);
+        let res = printer.out;
+        AstModule::parse(self.codemap.filename(), res.clone(), &Dialect::Extended).ok()?;
+        Some((res, chunks))
+    }
+
+    /// Format the module in a standard style, in the spirit of
+    /// [buildifier](https://github.com/bazelbuild/buildtools/tree/master/buildifier):
+    /// four space indentation, double-quoted strings, one statement per line and a
+    /// single space around binary operators and keyword arguments.
+    /// Comments and single blank lines are preserved. Bracketed lists are
+    /// printed with one item per line if the first item was on a new line in the original.
+    ///
+    /// If the result would not parse (which would be a bug), the original source is returned.
+    pub fn format(&self) -> String {
+        match self.print() {
+            Some((res, _)) => res,
+            None => self.codemap.source().to_owned(),
+        }
+    }
+
+    /// Format only the top-level statements which overlap the 0-based lines `lines`,
+    /// e.g. a block of code which has just been pasted, in the same style as
+    /// [`format`](AstModule::format).
+    /// Returns the (0-based, end exclusive) range of lines to replace, along with the
+    /// text to replace them with, which will always end with a newline.
+    /// Returns `None` if there are no such statements.
+    pub fn format_lines(&self, lines: Range<usize>) -> Option<(Range<usize>, String)> {
+        let (res, chunks) = self.print()?;
+        let overlaps = |x: &&Chunk| {
+            x.lines.start < lines.end.max(lines.start + 1) && lines.start < x.lines.end
+        };
+        let first = chunks.iter().find(overlaps)?;
+        let last = chunks.iter().rev().find(overlaps)?;
+        Some((
+            first.lines.start..last.lines.end,
+            res[first.text.start..last.text.end].to_owned(),
+        ))
+    }
+}
+
+/// The last line of a statement. We can't use the end of its span, since
+/// for a statement with a body that includes any newlines after the body.
+fn last_line(codemap: &CodeMap, x: &AstStmt) -> usize {
+    match &x.node {
+        Stmt::Statements(xs) if !xs.is_empty() => last_line(codemap, xs.last().unwrap()),
+        Stmt::Def(_, _, _, body, _) | Stmt::If(_, body) => last_line(codemap, body),
+        Stmt::IfElse(_, box (_, body)) | Stmt::For(_, box (_, body)) => last_line(codemap, body),
+        _ => codemap.find_line(x.span.end()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn format(x: &str) -> String {
+        AstModule::parse("X", x.to_owned(), &Dialect::Extended)
+            .unwrap()
+            .format()
+    }
+
+    #[test]
+    fn test_format() {
+        let before = r#"
+# A leading comment
+load('a.star', 'b', c='d')
+
+
+x=1+2*3 # trailing
+y = (1+2)*3
+z = [ 1,2,
+  3]
+w = {
+  'a':1, "b" : (2,)}
+def f(a,b:int=1,*args,**kwargs)->str:
+
+    # About the loop
+    for i,j in zip(a,b):
+        if i: pass
+        elif not j: continue
+        else:
+            a -= i[1:] if j else i[::2]
+    return lambda x,y: x
+g(1,
+  key = "it's")
+h(
+  1, 2)
+# The end
+"#;
+        let after = r#"# A leading comment
+load("a.star", "b", c = "d")
+
+x = 1 + 2 * 3  # trailing
+y = (1 + 2) * 3
+z = [1, 2, 3]
+w = {
+    "a": 1,
+    "b": (2,),
+}
+def f(a, b: int = 1, *args, **kwargs) -> str:
+    # About the loop
+    for i, j in zip(a, b):
+        if i:
+            pass
+        elif not j:
+            continue
+        else:
+            a -= i[1:] if j else i[::2]
+    return lambda x, y: x
+g(1, key = "it's")
+h(
+    1,
+    2,
+)
+# The end
+"#;
+        assert_eq!(format(before), after);
+        assert_eq!(format(after), after);
+    }
+
+    #[test]
+    fn test_format_lines() {
+        let modu = AstModule::parse(
+            "X",
+            "x=1\n\ndef f():\n  return 2\ny=[1,\n  2]\n".to_owned(),
+            &Dialect::Extended,
+        )
+        .unwrap();
+        assert_eq!(
+            modu.format_lines(3..4),
+            Some((1..4, "\ndef f():\n    return 2\n".to_owned()))
+        );
+        assert_eq!(modu.format_lines(0..0), Some((0..1, "x = 1\n".to_owned())));
+        assert_eq!(modu.format_lines(0..5), Some((0..6, modu.format())));
+        assert_eq!(modu.format_lines(10..12), None);
+    }
+
+    #[test]
+    fn test_format_comments_in_strings() {
+        let before = "x = '# not a comment' # a comment\ny = \"\"\"\n# nor this\"\"\"\n";
+        assert_eq!(
+            format(before),
+            "x = \"# not a comment\"  # a comment\ny = \"\"\"\n# nor this\"\"\"\n"
+        );
+    }
+
+    #[test]
+    fn test_format_comment_within_statement() {
+        let before = "x = [\n  1, # one\n  2]\n";
+        assert_eq!(format(before), before);
+    }
+}
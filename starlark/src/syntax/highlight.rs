@@ -0,0 +1,243 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Classify the tokens of Starlark source for syntax highlighting.
+
+use std::{collections::HashSet, ops::Range};
+
+use gazebo::prelude::*;
+
+use crate::{
+    codemap::CodeMap,
+    environment::Globals,
+    errors::Diagnostic,
+    syntax::{
+        lexer::{Lexer, Token},
+        Dialect,
+    },
+};
+
+/// The kind of a token, as returned by [`tokenize_for_highlight`].
+#[derive(Debug, Clone, Copy, Dupe, PartialEq, Eq, Hash)]
+pub enum TokenClass {
+    /// A keyword, e.g. `def` or `for`.
+    Keyword,
+    /// A string literal, including its quotes.
+    String,
+    /// An integer or float literal.
+    Number,
+    /// A comment, from the `#` to the end of the line.
+    Comment,
+    /// An operator, e.g. `+` or `==`.
+    Operator,
+    /// A bracket, `,`, `:` or `;`.
+    Punctuation,
+    /// A name defined by the globals, e.g. `len` or `None`, unless it follows a `.`.
+    Builtin,
+    /// The name of a function being defined or called, other than a builtin.
+    Function,
+    /// Any other name, e.g. a variable or attribute.
+    Identifier,
+    /// Text which isn't valid Starlark, e.g. a reserved word or an unfinished string.
+    Error,
+}
+
+fn token_class(token: &Token) -> Option<TokenClass> {
+    match token {
+        Token::Indent | Token::Dedent | Token::Newline | Token::Tabs => None,
+        Token::And
+        | Token::Else
+        | Token::Load
+        | Token::Break
+        | Token::For
+        | Token::Not
+        | Token::Continue
+        | Token::If
+        | Token::Or
+        | Token::Def
+        | Token::In
+        | Token::Pass
+        | Token::Elif
+        | Token::Return
+        | Token::Lambda => Some(TokenClass::Keyword),
+        Token::String(_) | Token::Bytes(_) => Some(TokenClass::String),
+        Token::Int(_) | Token::Float(_) => Some(TokenClass::Number),
+        Token::Identifier(_) => Some(TokenClass::Identifier),
+        Token::Comma
+        | Token::Semicolon
+        | Token::Colon
+        | Token::OpeningSquare
+        | Token::OpeningCurly
+        | Token::OpeningRound
+        | Token::ClosingSquare
+        | Token::ClosingCurly
+        | Token::ClosingRound => Some(TokenClass::Punctuation),
+        Token::Error | Token::Reserved | Token::RawSingleQuote | Token::RawDoubleQuote => {
+            Some(TokenClass::Error)
+        }
+        _ => Some(TokenClass::Operator),
+    }
+}
+
+/// Add the comments in `source[range]`, which contains no tokens.
+fn comments(source: &str, range: Range<usize>, res: &mut Vec<(Range<usize>, TokenClass)>) {
+    let mut pos = range.start;
+    while let Some(start) = source[pos..range.end].find('#') {
+        let start = pos + start;
+        let end = source[start..range.end]
+            .find('\n')
+            .map_or(range.end, |x| start + x);
+        res.push((start..end, TokenClass::Comment));
+        pos = end;
+    }
+}
+
+/// Split `source` into the byte ranges of its tokens, with the class of each, in order,
+/// so tools can highlight Starlark the same way it is parsed. Whitespace is left out.
+/// Names are classified without resolving variables, so a local variable called `len`
+/// is still a [`TokenClass::Builtin`] if `len` is one of the `globals`.
+/// Invalid code doesn't stop the tokenizing, the invalid parts are [`TokenClass::Error`].
+pub fn tokenize_for_highlight(
+    source: &str,
+    dialect: &Dialect,
+    globals: &Globals,
+) -> Vec<(Range<usize>, TokenClass)> {
+    let codemap = CodeMap::new(String::new(), source.to_owned());
+    let mut tokens = Vec::new();
+    for lexeme in Lexer::new(source, dialect, codemap) {
+        match lexeme {
+            Ok((start, token, end)) => tokens.push((start..end, token)),
+            Err(e) => {
+                if let Some(span) = e.downcast_ref::<Diagnostic>().and_then(|d| d.span.as_ref()) {
+                    let range = span.span.begin().get() as usize..span.span.end().get() as usize;
+                    tokens.push((range, Token::Error));
+                }
+            }
+        }
+    }
+    // After some errors, such as an unfinished string, the lexer carries on from inside it
+    let mut end = 0;
+    tokens.retain(|(range, _)| {
+        let keep = range.start >= end;
+        if keep {
+            end = range.end.max(end);
+        }
+        keep
+    });
+
+    let globals: HashSet<String> = globals.names().into_iter().collect();
+    let mut res = Vec::with_capacity(tokens.len());
+    let mut pos = 0;
+    for (i, (range, token)) in tokens.iter().enumerate() {
+        comments(source, pos..range.start, &mut res);
+        pos = range.end;
+        if range.is_empty() {
+            continue;
+        }
+        let class = match token_class(token) {
+            None => continue,
+            Some(TokenClass::Identifier) => {
+                let previous = i.checked_sub(1).map(|i| &tokens[i].1);
+                let called = matches!(tokens.get(i + 1), Some((_, Token::OpeningRound)));
+                let name = &source[range.clone()];
+                match previous {
+                    Some(Token::Def) => TokenClass::Function,
+                    Some(Token::Dot) if called => TokenClass::Function,
+                    Some(Token::Dot) => TokenClass::Identifier,
+                    _ if globals.contains(name) => TokenClass::Builtin,
+                    _ if called => TokenClass::Function,
+                    _ => TokenClass::Identifier,
+                }
+            }
+            Some(class) => class,
+        };
+        res.push((range.clone(), class));
+    }
+    comments(source, pos..source.len(), &mut res);
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn highlight(source: &str) -> Vec<(&str, TokenClass)> {
+        tokenize_for_highlight(source, &Dialect::Extended, &Globals::standard())
+            .into_map(|(range, class)| (&source[range], class))
+    }
+
+    #[test]
+    fn test_tokenize_for_highlight() {
+        use TokenClass::*;
+        assert_eq!(
+            highlight(
+                "def f(x):  # A comment\n    return len(x.y) + g(1.5, '#') if x.z() else None\n"
+            ),
+            &[
+                ("def", Keyword),
+                ("f", Function),
+                ("(", Punctuation),
+                ("x", Identifier),
+                (")", Punctuation),
+                (":", Punctuation),
+                ("# A comment", Comment),
+                ("return", Keyword),
+                ("len", Builtin),
+                ("(", Punctuation),
+                ("x", Identifier),
+                (".", Operator),
+                ("y", Identifier),
+                (")", Punctuation),
+                ("+", Operator),
+                ("g", Function),
+                ("(", Punctuation),
+                ("1.5", Number),
+                (",", Punctuation),
+                ("'#'", String),
+                (")", Punctuation),
+                ("if", Keyword),
+                ("x", Identifier),
+                (".", Operator),
+                ("z", Function),
+                ("(", Punctuation),
+                (")", Punctuation),
+                ("else", Keyword),
+                ("None", Builtin),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_for_highlight_errors() {
+        use TokenClass::*;
+        assert_eq!(
+            highlight("x = [ # one\n  1, # two\n]\nwhile 'abc"),
+            &[
+                ("x", Identifier),
+                ("=", Operator),
+                ("[", Punctuation),
+                ("# one", Comment),
+                ("1", Number),
+                (",", Punctuation),
+                ("# two", Comment),
+                ("]", Punctuation),
+                ("while", Error),
+                ("'abc", Error),
+            ]
+        );
+    }
+}
@@ -19,6 +19,8 @@ use std::{char, collections::VecDeque, fmt, fmt::Display};
 
 use gazebo::dupe::Dupe;
 use logos::Logos;
+use num_bigint::BigInt;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 use crate::{
@@ -28,7 +30,7 @@ use crate::{
         cursors::{CursorBytes, CursorChars},
         dialect::Dialect,
     },
-    values::StarlarkValue,
+    values::{bytes::StarlarkBytes, StarlarkValue},
 };
 
 #[derive(Error, Debug)]
@@ -47,8 +49,6 @@ pub(crate) enum LexemeError {
     ReservedKeyword(String),
     #[error("Parse error: integer cannot have leading 0, got `{0}`")]
     StartsZero(String),
-    #[error("Parse error: integer overflow, must fit in 32 bits, got `{0}`")]
-    IntOverflow(String),
 }
 
 type Lexeme = anyhow::Result<(usize, Token, usize)>;
@@ -268,6 +268,27 @@ impl<'a> Lexer<'a> {
         Ok(())
     }
 
+    // We have seen a '\\' character in a bytes literal. Like `escape`, but hex and octal escapes
+    // denote a single byte, rather than the UTF-8 encoding of a character.
+    fn escape_byte(it: &mut CursorChars, res: &mut Vec<u8>) -> Result<(), ()> {
+        match it.next() {
+            Some('x') => res.push(Self::escape_char(it, 2, 2, 16)? as u8),
+            Some(c @ '0'..='7') => {
+                it.unnext(c);
+                let c = Self::escape_char(it, 1, 3, 8)?;
+                res.push(u8::try_from(u32::from(c)).map_err(|_| ())?)
+            }
+            Some(c) => {
+                it.unnext(c);
+                let mut s = String::new();
+                Self::escape(it, &mut s)?;
+                res.extend_from_slice(s.as_bytes());
+            }
+            None => return Err(()),
+        }
+        Ok(())
+    }
+
     // String parsing is a hot-spot, so parameterise by a `stop` function which gets
     // specialised for each variant
     fn string(&mut self, triple: bool, raw: bool, mut stop: impl FnMut(char) -> bool) -> Lexeme {
@@ -377,6 +398,75 @@ impl<'a> Lexer<'a> {
         )
     }
 
+    // Bytes literals are rare, so unlike `string` there is no fast path.
+    fn bytes(&mut self, triple: bool, raw: bool, mut stop: impl FnMut(char) -> bool) -> Lexeme {
+        let string_start = self.lexer.span().start;
+        let mut string_end = self.lexer.span().end;
+
+        let mut it = CursorChars::new_offset(self.lexer.remainder(), if triple { 2 } else { 0 });
+        let mut res = Vec::new();
+        let mut buf = [0; 4];
+        while let Some(c) = it.next() {
+            if stop(c) {
+                self.lexer.bump(it.pos());
+                if triple {
+                    res.truncate(res.len() - 2);
+                }
+                return Ok((string_start, Token::Bytes(res), string_end + it.pos()));
+            }
+            match c {
+                '\n' if !triple => {
+                    string_end -= 1;
+                    break;
+                }
+                '\r' => {}
+                '\\' => {
+                    if raw {
+                        match it.next() {
+                            Some(c) => {
+                                if c != '\'' && c != '"' {
+                                    res.push(b'\\');
+                                }
+                                res.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                            }
+                            _ => break, // Out of chars
+                        }
+                    } else {
+                        let pos = it.pos();
+                        if Self::escape_byte(&mut it, &mut res).is_err() {
+                            return self.err_span(
+                                LexemeError::InvalidEscapeSequence(
+                                    self.lexer.remainder()[pos..it.pos()].to_owned(),
+                                ),
+                                string_end + pos - 1,
+                                string_end + it.pos(),
+                            );
+                        }
+                    }
+                }
+                c => res.extend_from_slice(c.encode_utf8(&mut buf).as_bytes()),
+            }
+        }
+
+        // We ran out of characters
+        self.err_span(
+            LexemeError::UnfinishedStringLiteral,
+            string_start,
+            string_end + it.pos(),
+        )
+    }
+
+    // Lex a string or bytes literal, where the quote prefix has already been consumed.
+    fn quoted(&mut self, triple: bool, stop: impl FnMut(char) -> bool) -> Lexeme {
+        let prefix = self.lexer.slice();
+        let raw = prefix.contains('r');
+        if prefix.contains('b') {
+            self.bytes(triple, raw, stop)
+        } else {
+            self.string(triple, raw, stop)
+        }
+    }
+
     pub fn next(&mut self) -> Option<Lexeme> {
         loop {
             // Note that this function doesn't always return - a few branches use `continue`
@@ -418,32 +508,19 @@ impl<'a> Lexer<'a> {
                         }
                         Token::Reserved => Some(self.err_now(LexemeError::ReservedKeyword)),
                         Token::Error => Some(self.err_now(LexemeError::InvalidInput)),
-                        Token::Int(radix) => {
-                            let mut s = self.lexer.slice();
-                            if radix == 10 {
-                                if s.len() > 1 && &s[0..1] == "0" {
-                                    return Some(self.err_now(LexemeError::StartsZero));
-                                }
-                            } else {
-                                // Skip the 0x prefix
-                                s = &s[2..];
-                            }
-                            match i32::from_str_radix(s, radix as u32) {
-                                Ok(i) => {
-                                    let span = self.lexer.span();
-                                    Some(Ok((span.start, Token::Int(i), span.end)))
-                                }
-                                Err(_) => {
-                                    // Because we validated the characters going in, it must have been an overflow
-                                    Some(self.err_now(LexemeError::IntOverflow))
-                                }
+                        Token::Int(i) => {
+                            // Decimal literals can't start with 0, but `0x1` etc. are fine.
+                            let s = self.lexer.slice().as_bytes();
+                            if s.len() > 1 && s[0] == b'0' && s[1].is_ascii_digit() {
+                                return Some(self.err_now(LexemeError::StartsZero));
                             }
+                            let span = self.lexer.span();
+                            Some(Ok((span.start, Token::Int(i), span.end)))
                         }
                         Token::RawDoubleQuote => {
-                            let raw = self.lexer.span().len() == 2;
                             if self.lexer.remainder().starts_with("\"\"") {
                                 let mut qs = 0;
-                                Some(self.string(true, raw, |c| {
+                                Some(self.quoted(true, |c| {
                                     if c == '\"' {
                                         qs += 1;
                                         qs == 3
@@ -453,14 +530,13 @@ impl<'a> Lexer<'a> {
                                     }
                                 }))
                             } else {
-                                Some(self.string(false, raw, |c| c == '\"'))
+                                Some(self.quoted(false, |c| c == '\"'))
                             }
                         }
                         Token::RawSingleQuote => {
-                            let raw = self.lexer.span().len() == 2;
                             if self.lexer.remainder().starts_with("''") {
                                 let mut qs = 0;
-                                Some(self.string(true, raw, |c| {
+                                Some(self.quoted(true, |c| {
                                     if c == '\'' {
                                         qs += 1;
                                         qs == 3
@@ -470,7 +546,7 @@ impl<'a> Lexer<'a> {
                                     }
                                 }))
                             } else {
-                                Some(self.string(false, raw, |c| c == '\''))
+                                Some(self.quoted(false, |c| c == '\''))
                             }
                         }
                         Token::OpeningCurly | Token::OpeningRound | Token::OpeningSquare => {
@@ -512,9 +588,15 @@ pub enum Token {
     // things ourselves
     #[token("'")]
     #[token("r'")]
+    #[token("b'")]
+    #[token("rb'")]
+    #[token("br'")]
     RawSingleQuote,
     #[token("\"")]
     #[token("r\"")]
+    #[token("b\"")]
+    #[token("rb\"")]
+    #[token("br\"")]
     RawDoubleQuote,
 
     #[regex(
@@ -527,11 +609,11 @@ pub enum Token {
     , |lex| lex.slice().to_owned())]
     Identifier(String), // An identifier
 
-    #[regex("[0-9]+", |_| 10)]
-    #[regex("0[xX][A-Fa-f0-9]+", |_| 16)]
-    #[regex("0[bB][01]+", |_| 2)]
-    #[regex("0[oO][0-7]+", |_| 8)]
-    Int(i32), // An integer literal (123, 0x1, 0b1011, 0o755, ...)
+    #[regex("[0-9]+", |lex| TokenInt::from_str_radix(lex.slice(), 10))]
+    #[regex("0[xX][A-Fa-f0-9]+", |lex| TokenInt::from_str_radix(&lex.slice()[2..], 16))]
+    #[regex("0[bB][01]+", |lex| TokenInt::from_str_radix(&lex.slice()[2..], 2))]
+    #[regex("0[oO][0-7]+", |lex| TokenInt::from_str_radix(&lex.slice()[2..], 8))]
+    Int(TokenInt), // An integer literal (123, 0x1, 0b1011, 0o755, ...)
 
     #[regex("[0-9]+\\.[0-9]*([eE][-+]?[0-9]+)?", |lex| lex.slice().parse::<f64>())]
     #[regex("[0-9]+[eE][-+]?[0-9]+", |lex| lex.slice().parse::<f64>())]
@@ -539,6 +621,7 @@ pub enum Token {
     Float(f64), // A float literal (3.14, .3, 1e6, 0.)
 
     String(String), // A string literal
+    Bytes(Vec<u8>), // A bytes literal
 
     // Keywords
     #[token("and")]
@@ -660,6 +743,34 @@ pub enum Token {
     ClosingRound,
 }
 
+/// The value of an integer literal, which may be too large for an `i32`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TokenInt {
+    I32(i32),
+    /// Only used for literals which don't fit in an `i32`.
+    BigInt(Box<BigInt>),
+}
+
+impl TokenInt {
+    /// Parse digits (without any prefix or sign) in the given radix.
+    pub fn from_str_radix(s: &str, radix: u32) -> Option<TokenInt> {
+        match i32::from_str_radix(s, radix) {
+            Ok(i) => Some(TokenInt::I32(i)),
+            // The characters are validated going in, so it must have been an overflow.
+            Err(_) => BigInt::parse_bytes(s.as_bytes(), radix).map(|x| TokenInt::BigInt(box x)),
+        }
+    }
+}
+
+impl Display for TokenInt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenInt::I32(i) => write!(f, "{}", i),
+            TokenInt::BigInt(i) => write!(f, "{}", i),
+        }
+    }
+}
+
 impl Token {
     /// Used for testing
     pub(crate) fn unlex(&self) -> String {
@@ -675,6 +786,7 @@ impl Token {
                 x.as_str().collect_json(&mut res).unwrap();
                 res
             }
+            Token::Bytes(x) => StarlarkBytes::new(x.clone()).to_string(),
             _ => {
                 let s = self.to_string();
                 // Out display is often: keyword 'lambda'
@@ -760,6 +872,7 @@ impl Display for Token {
             Token::Int(i) => write!(f, "integer literal '{}'", i),
             Token::Float(n) => write!(f, "float literal '{}'", n),
             Token::String(s) => write!(f, "string literal '{}'", s),
+            Token::Bytes(s) => write!(f, "bytes literal '{}'", String::from_utf8_lossy(s)),
             Token::RawSingleQuote => write!(f, "starting '"),
             Token::RawDoubleQuote => write!(f, "starting \""),
             Token::Tabs => Ok(()),
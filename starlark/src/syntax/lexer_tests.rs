@@ -23,6 +23,10 @@ fn test_int_lit() {
     assert_eq!(assert::lex("0x7F 0x7d"), "127 125 \n");
     assert_eq!(assert::lex("0B1011 0b1010"), "11 10 \n");
     assert_eq!(assert::lex("0o755 0O753"), "493 491 \n");
+    assert_eq!(
+        assert::lex("2147483648 0x10000000000000000"),
+        "2147483648 18446744073709551616 \n"
+    );
     // Starlark requires us to ban leading zeros (confusion with implicit octal)
     assert::parse_fail("x = !01!");
 }
@@ -143,6 +147,17 @@ fn test_string_escape() {
     assert::parse_fail("test 'more !\\x0!");
 }
 
+#[test]
+fn test_bytes_lit() {
+    assert_eq!(
+        assert::lex(r#"b'abc' b"\x00\xff" rb'\x00' br"\"" b'\377' b'é' b rb br"#),
+        r#"b"abc" b"\x00\xff" b"\\x00" b"\"" b"\xff" b"\xc3\xa9" b rb br "#.to_owned() + "\n"
+    );
+    assert_eq!(assert::lex("b'''a\nb'''"), "b\"a\\nb\" \n");
+    assert::parse_fail("test b'!\\777!'");
+    assert::parse_fail("test !b'unfinished!");
+}
+
 #[test]
 fn test_simple_example() {
     assert_eq!(
@@ -266,10 +281,6 @@ fn test_lexer_error_messages() {
         "an + 'invalid escape !\\x3 ! character'",
         "invalid string escape sequence `x3 `",
     );
-    f(
-        "large_int = !1238989456723879! + 8",
-        "integer overflow, must fit in 32 bits, got `1238989456723879`",
-    );
     f(
         "leading_zero = !003! + 8",
         "integer cannot have leading 0, got `003`",
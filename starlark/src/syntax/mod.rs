@@ -18,7 +18,14 @@
 //! The AST of Starlark as [`AstModule`], along with a [`parse`](AstModule::parse) function.
 
 pub use ast::AstModule;
+pub use cache::AstCache;
 pub use dialect::Dialect;
+pub use highlight::{tokenize_for_highlight, TokenClass};
+
+pub use crate::analysis::{
+    DuplicateConstant, FoldingRange, FoldingRangeKind, Highlight, HighlightKind, InlayHint,
+    InlayHintKind, LoadedSymbol, Symbol, SymbolKind,
+};
 
 #[cfg(test)]
 mod grammar_tests;
@@ -28,8 +35,11 @@ mod lexer_tests;
 mod testcases;
 
 pub(crate) mod ast;
+mod cache;
 pub(crate) mod cursors;
 mod dialect;
+mod format;
+mod highlight;
 pub(crate) mod lexer;
 pub(crate) mod payload_map;
 pub(crate) mod validate;
@@ -24,6 +24,7 @@ use lalrpop_util as lu;
 use crate::{
     codemap::{CodeMap, FileSpan, Pos, Span},
     errors::Diagnostic,
+    eval::PathMapping,
     syntax::{
         ast::{AstModule, AstStmt, Stmt},
         dialect::Dialect,
@@ -110,6 +111,18 @@ impl AstModule {
         Self::parse(&path.to_string_lossy(), content, dialect)
     }
 
+    /// Parse a file stored on disk, reading it from [`disk_path`](PathMapping::disk_path) and
+    /// reporting it as [`reported_name`](PathMapping::reported_name).
+    pub fn parse_file_mapped(
+        path: &Path,
+        paths: &PathMapping,
+        dialect: &Dialect,
+    ) -> anyhow::Result<Self> {
+        let path = paths.disk_path(path);
+        let content = fs::read_to_string(&path)?;
+        Self::parse(&paths.reported_name(&path), content, dialect)
+    }
+
     /// Parse a Starlark module to produce an [`AstModule`], or an error if there are syntax errors.
     /// The `filename` is for error messages only, and does not have to be a valid file.
     /// The [`Dialect`] selects which Starlark constructs are valid.
@@ -155,6 +168,59 @@ impl AstModule {
         loads
     }
 
+    /// The variables assigned at the top level of the module, outside of any `def`, with the
+    /// location of each assignment, in the order they appear.
+    pub(crate) fn top_level_assignments(&self) -> Vec<(&str, FileSpan)> {
+        fn f<'a>(ast: &'a AstStmt, codemap: &CodeMap, res: &mut Vec<(&'a str, FileSpan)>) {
+            match &ast.node {
+                Stmt::Assign(dest, _) | Stmt::AssignModify(dest, _, _) | Stmt::For(dest, _) => {
+                    dest.visit_lvalue(|x| res.push((&x.node.0, codemap.file_span(x.span))))
+                }
+                Stmt::Def(name, ..) => {
+                    res.push((&name.node.0, codemap.file_span(name.span)));
+                    // The assignments in the body are local
+                    return;
+                }
+                Stmt::Load(load) => {
+                    for (name, _) in &load.node.args {
+                        res.push((&name.node.0, codemap.file_span(name.span)));
+                    }
+                }
+                _ => {}
+            }
+            ast.visit_stmt(|x| f(x, codemap, res));
+        }
+
+        let mut res = Vec::new();
+        f(&self.statement, &self.codemap, &mut res);
+        res
+    }
+
+    /// Whether the last statement of the module is an expression, whose value is returned by
+    /// [`eval_module`](crate::eval::Evaluator::eval_module).
+    pub(crate) fn ends_with_expression(&self) -> bool {
+        let mut last = &self.statement;
+        while let Stmt::Statements(stmts) = &last.node {
+            match stmts.last() {
+                Some(stmt) => last = stmt,
+                None => return false,
+            }
+        }
+        matches!(last.node, Stmt::Expression(_))
+    }
+
+    /// Whether the module is a single expression.
+    pub(crate) fn is_expression(&self) -> bool {
+        let mut stmt = &self.statement;
+        while let Stmt::Statements(stmts) = &stmt.node {
+            match stmts.as_slice() {
+                [x] => stmt = x,
+                _ => return false,
+            }
+        }
+        matches!(stmt.node, Stmt::Expression(_))
+    }
+
     /// Look up a [`Span`] contained in this module to a [`FileSpan`].
     pub fn file_span(&self, x: Span) -> FileSpan {
         self.codemap.file_span(x)
@@ -17,6 +17,8 @@
 
 //! This mod defines utilities to easily create Rust values as Starlark values.
 
+use either::Either;
+
 use crate::values::{
     layout::Value, FrozenHeap, FrozenStringValue, FrozenValue, Heap, StringValue, UnpackValue,
     ValueOf,
@@ -56,6 +58,19 @@ where
     }
 }
 
+impl<'v, L, R> AllocValue<'v> for Either<L, R>
+where
+    L: AllocValue<'v>,
+    R: AllocValue<'v>,
+{
+    fn alloc_value(self, heap: &'v Heap) -> Value<'v> {
+        match self {
+            Either::Left(x) => x.alloc_value(heap),
+            Either::Right(x) => x.alloc_value(heap),
+        }
+    }
+}
+
 /// Trait for things that can be allocated on a [`FrozenHeap`] producing a [`FrozenValue`].
 pub trait AllocFrozenValue {
     fn alloc_frozen_value(self, heap: &FrozenHeap) -> FrozenValue;
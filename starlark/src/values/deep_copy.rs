@@ -0,0 +1,360 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Copy values from one [`Heap`] to another, see [`Value::deep_copy_to`].
+
+use std::collections::{HashMap, HashSet};
+
+use either::Either;
+use thiserror::Error;
+
+use crate::{
+    collections::Hashed,
+    values::{
+        bigint::StarlarkBigInt,
+        bytes::StarlarkBytes,
+        dict::Dict,
+        float::StarlarkFloat,
+        list::List,
+        namedtuple::{NamedTuple, NamedTupleType},
+        set::Set,
+        stack_guard,
+        structs::{Struct, StructBuilder},
+        tuple::Tuple,
+        Heap, Value, ValueIdentity,
+    },
+};
+
+#[derive(Debug, Error)]
+enum DeepCopyError {
+    #[error("Can't copy a value of type `{0}` to another heap")]
+    Unsupported(&'static str),
+    #[error(
+        "Can't copy a value of type `{0}` which contains itself other than through a list or dict"
+    )]
+    Cycle(&'static str),
+}
+
+/// A list element or dict value which couldn't be copied yet, as it refers back to a tuple
+/// or struct whose copy wasn't allocated yet, so is copied once everything else has been.
+struct Deferred<'v, 'v2> {
+    container: Value<'v2>,
+    /// The list index or dict key.
+    index: Value<'v2>,
+    value: Value<'v>,
+}
+
+/// Copies values to a heap, remembering the copy of each value, so values which are
+/// shared, or refer to each other in a cycle, are copied once.
+///
+/// Lists and dicts are recorded before their contents are copied, as the contents may
+/// refer back to them. Tuples and structs can only be allocated once their contents
+/// are copied, so while they are being copied a value which refers back to one is pending,
+/// as is any tuple or struct containing a pending value. The nearest list or dict slot
+/// containing a pending value gets a placeholder, which is replaced by a copy of the value
+/// once everything else has been copied.
+struct DeepCopier<'v, 'v2> {
+    heap: &'v2 Heap,
+    copied: HashMap<ValueIdentity<'v>, Value<'v2>>,
+    /// The tuples and structs whose contents are being copied.
+    in_progress: HashSet<ValueIdentity<'v>>,
+    deferred: Vec<Deferred<'v, 'v2>>,
+}
+
+impl<'v, 'v2> DeepCopier<'v, 'v2> {
+    /// Copy `x`, and everything it refers to, filling in every placeholder.
+    fn copy_all(&mut self, x: Value<'v>) -> anyhow::Result<Value<'v2>> {
+        let res = self.copy_now(x)?;
+        // Everything has been copied, so every value deferred has a copy.
+        while let Some(Deferred {
+            container,
+            index,
+            value,
+        }) = self.deferred.pop()
+        {
+            container.set_at(index, self.copy_now(value)?)?;
+        }
+        Ok(res)
+    }
+
+    /// Copy `x`, which can't refer back to a tuple or struct being copied.
+    fn copy_now(&mut self, x: Value<'v>) -> anyhow::Result<Value<'v2>> {
+        match self.copy(x)? {
+            Some(res) => Ok(res),
+            None => Err(DeepCopyError::Cycle(x.get_type()).into()),
+        }
+    }
+
+    /// Copy `x`, the element `index` of `container`, using a placeholder if `x` is pending.
+    fn copy_or_defer(
+        &mut self,
+        x: Value<'v>,
+        container: Value<'v2>,
+        index: Value<'v2>,
+    ) -> anyhow::Result<Value<'v2>> {
+        match self.copy(x)? {
+            Some(res) => Ok(res),
+            None => {
+                self.deferred.push(Deferred {
+                    container,
+                    index,
+                    value: x,
+                });
+                Ok(Value::new_none())
+            }
+        }
+    }
+
+    /// Copy `x`, or return `None` if it is pending, as it refers back to a tuple or struct
+    /// being copied.
+    fn copy(&mut self, x: Value<'v>) -> anyhow::Result<Option<Value<'v2>>> {
+        // Values may be very deep.
+        let _guard = stack_guard::stack_guard()?;
+        if let Some(res) = self.copied.get(&x.identity()) {
+            return Ok(Some(*res));
+        }
+        if self.in_progress.contains(&x.identity()) {
+            return Ok(None);
+        }
+        let heap = self.heap;
+        let res = if x.is_none() {
+            Value::new_none()
+        } else if let Some(b) = x.unpack_bool() {
+            Value::new_bool(b)
+        } else if let Some(i) = x.unpack_int() {
+            Value::new_int(i)
+        } else if let Some(s) = x.unpack_str() {
+            heap.alloc_str(s)
+        } else if let Some(f) = x.downcast_ref::<StarlarkFloat>() {
+            heap.alloc(f.0)
+        } else if let Some(b) = x.downcast_ref::<StarlarkBigInt>() {
+            heap.alloc_simple(b.clone())
+        } else if let Some(b) = x.downcast_ref::<StarlarkBytes>() {
+            heap.alloc_simple(b.clone())
+        } else if let Some(xs) = List::from_value(x) {
+            let res = heap.alloc_list(&[]);
+            self.copied.insert(x.identity(), res);
+            let mut content = Vec::with_capacity(xs.len());
+            for (i, x) in xs.iter().enumerate() {
+                content.push(self.copy_or_defer(x, res, Value::new_int(i as i32))?);
+            }
+            List::from_value_mut(res)?.unwrap().extend(content, heap);
+            res
+        } else if let Some(d) = Dict::from_value(x) {
+            let res = heap.alloc(Dict::default());
+            self.copied.insert(x.identity(), res);
+            let mut content = Vec::with_capacity(d.len());
+            for (k, v) in d.iter_hashed() {
+                // Keys are hashed by their contents, which don't change, and can't
+                // contain the dict, so can't refer back to anything being copied.
+                let k = Hashed::new_unchecked(k.hash(), self.copy_now(*k.key())?);
+                let v = self.copy_or_defer(v, res, *k.key())?;
+                content.push((k, v));
+            }
+            let mut res_dict = Dict::from_value_mut(res)?.unwrap();
+            for (k, v) in content {
+                res_dict.insert_hashed(k, v);
+            }
+            res
+        } else if let Some(xs) = Tuple::from_value(x) {
+            match self.copy_contents(x, xs.iter())? {
+                Some(content) => heap.alloc_tuple(&content),
+                None => return Ok(None),
+            }
+        } else if let Some(xs) = NamedTuple::from_value(x) {
+            let typ = self.copy_now(xs.typ())?;
+            match self.copy_contents(x, xs.iter())? {
+                Some(content) => heap.alloc_complex(NamedTuple::new(typ, content)),
+                None => return Ok(None),
+            }
+        } else if let Some(typ) = NamedTupleType::from_value(x) {
+            heap.alloc_complex(match typ {
+                Either::Left(typ) => typ.copy_to(heap),
+                Either::Right(typ) => typ.copy_to(heap),
+            })
+        } else if let Some(xs) = Set::from_value(x) {
+            let mut res = Set::new();
+            for k in xs.iter_hashed() {
+                res.insert_hashed(Hashed::new_unchecked(k.hash(), self.copy_now(*k.key())?));
+            }
+            heap.alloc(res)
+        } else if let Some(s) = Struct::from_value(x) {
+            let content = match self.copy_contents(x, s.fields.values().copied())? {
+                Some(content) => content,
+                None => return Ok(None),
+            };
+            let mut res = StructBuilder::with_capacity(heap, s.fields.len());
+            for (k, v) in s.fields.keys().zip(content) {
+                res.add(k.as_str(), v);
+            }
+            heap.alloc(res.build())
+        } else {
+            return Err(DeepCopyError::Unsupported(x.get_type()).into());
+        };
+        self.copied.insert(x.identity(), res);
+        Ok(Some(res))
+    }
+
+    /// Copy the contents of `x`, a tuple or struct, or return `None` if any of them is
+    /// pending, in which case so is `x`, and it is copied again later.
+    fn copy_contents(
+        &mut self,
+        x: Value<'v>,
+        contents: impl Iterator<Item = Value<'v>>,
+    ) -> anyhow::Result<Option<Vec<Value<'v2>>>> {
+        self.in_progress.insert(x.identity());
+        let res = contents.map(|x| self.copy(x)).collect();
+        self.in_progress.remove(&x.identity());
+        res
+    }
+}
+
+impl<'v> Value<'v> {
+    /// Copy this value, and every value it refers to, to `heap`, e.g. to pass the result of
+    /// one evaluation to another [`Module`](crate::environment::Module). Values which are
+    /// shared, or refer to each other in a cycle, are shared and cyclic in the copy too.
+    /// The copy is mutable, even if this value is frozen.
+    ///
+    /// Only the builtin data types can be copied: `None`, booleans, numbers, strings, bytes,
+    /// lists, tuples, dicts, sets, structs, named tuples and their types. Copying any other
+    /// value, e.g. a function, is an error.
+    pub fn deep_copy_to<'v2>(self, heap: &'v2 Heap) -> anyhow::Result<Value<'v2>> {
+        DeepCopier {
+            heap,
+            copied: HashMap::new(),
+            in_progress: HashSet::new(),
+            deferred: Vec::new(),
+        }
+        .copy_all(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        environment::{Globals, Module},
+        eval::Evaluator,
+        syntax::{AstModule, Dialect},
+        values::{list::List, Heap, Value},
+    };
+
+    fn eval(module: &Module, program: &str) {
+        let ast = AstModule::parse("x.star", program.to_owned(), &Dialect::Extended).unwrap();
+        let mut eval = Evaluator::new(module);
+        eval.eval_module(ast, &Globals::extended()).unwrap();
+    }
+
+    #[test]
+    fn test_deep_copy() {
+        let module = Module::new();
+        eval(
+            &module,
+            "\
+shared = [1]
+cycle = {'shared': shared, 'again': shared}
+cycle['self'] = cycle
+x = (cycle, struct(a = 1.5, b = b'x'), 'long string ' * 5, 12345678901234567890)
+t = ([],)
+t[0].append(t)
+s = struct(d = {})
+s.d['s'] = s
+l = []
+u = ((l,),)
+l.append(u)
+Point = namedtuple('x', 'y')
+p = Point([], 2)
+p.x.append(p)
+",
+        );
+        let x = module.get("x").unwrap();
+
+        let heap = Heap::new();
+        let copy = x.deep_copy_to(&heap).unwrap();
+        assert_eq!(x.to_repr(), copy.to_repr());
+
+        let cycle = copy.at(Value::new_int(0), &heap).unwrap();
+        let get = |k: &str| cycle.at(heap.alloc(k), &heap).unwrap();
+        assert!(get("self").ptr_eq(cycle));
+        assert!(get("shared").ptr_eq(get("again")));
+        // The copy is independent of the original.
+        List::from_value_mut(get("shared"))
+            .unwrap()
+            .unwrap()
+            .push(Value::new_int(2), &heap);
+        assert_eq!("[1, 2]", get("shared").to_repr());
+        assert_eq!("[1]", module.get("shared").unwrap().to_repr());
+
+        // Cycles through tuples and structs, which are allocated after their contents.
+        let t = module.get("t").unwrap().deep_copy_to(&heap).unwrap();
+        let zero = Value::new_int(0);
+        let l = t.at(zero, &heap).unwrap();
+        assert!(l.at(zero, &heap).unwrap().ptr_eq(t));
+        let s = module.get("s").unwrap().deep_copy_to(&heap).unwrap();
+        let d = s.get_attr("d", &heap).unwrap().unwrap();
+        assert!(d.at(heap.alloc("s"), &heap).unwrap().ptr_eq(s));
+        // A tuple containing a tuple being copied is copied after the list containing it.
+        let t = module
+            .get("u")
+            .unwrap()
+            .at(zero, module.heap())
+            .unwrap()
+            .deep_copy_to(&heap)
+            .unwrap();
+        let l = t.at(zero, &heap).unwrap();
+        let u = l.at(zero, &heap).unwrap();
+        assert!(u.at(zero, &heap).unwrap().ptr_eq(t));
+        let p = module.get("p").unwrap().deep_copy_to(&heap).unwrap();
+        assert_eq!(
+            p.get_attr("y", &heap).unwrap().unwrap().unpack_int(),
+            Some(2)
+        );
+        let x = p.get_attr("x", &heap).unwrap().unwrap();
+        assert!(x.at(zero, &heap).unwrap().ptr_eq(p));
+        let point = module.get("Point").unwrap().deep_copy_to(&heap).unwrap();
+        assert_eq!("namedtuple(\"x\", \"y\")", point.to_repr());
+    }
+
+    #[test]
+    fn test_deep_copy_frozen() {
+        let module = Module::new();
+        eval(&module, "shared = [1]");
+        let shared = module.freeze().unwrap().get("shared").unwrap();
+
+        // The copy of a frozen value is mutable.
+        let heap = Heap::new();
+        let copy = shared.value().deep_copy_to(&heap).unwrap();
+        List::from_value_mut(copy)
+            .unwrap()
+            .unwrap()
+            .push(Value::new_int(2), &heap);
+        assert_eq!("[1, 2]", copy.to_repr());
+    }
+
+    #[test]
+    fn test_deep_copy_unsupported() {
+        let module = Module::new();
+        eval(&module, "x = [len]");
+        let err = module
+            .get("x")
+            .unwrap()
+            .deep_copy_to(&Heap::new())
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Can't copy a value of type `function`"));
+    }
+}
@@ -0,0 +1,165 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Allocate Starlark values from any `serde` deserializer, e.g. from JSON.
+
+use std::fmt;
+
+use num_bigint::BigInt;
+use serde::{
+    de::{DeserializeSeed, Error, MapAccess, SeqAccess, Visitor},
+    Deserializer,
+};
+
+use crate::{
+    collections::SmallMap,
+    values::{dict::Dict, Heap, Value},
+};
+
+/// A [`DeserializeSeed`] which allocates the deserialized data on a [`Heap`].
+///
+/// Maps become dicts, sequences become lists, and the primitives become the corresponding
+/// Starlark value, with integers too big for `int` becoming big integers.
+#[derive(Clone, Copy)]
+pub struct DeserializeValue<'v> {
+    heap: &'v Heap,
+}
+
+impl<'v> DeserializeValue<'v> {
+    /// Create a seed which allocates on `heap`.
+    pub fn new(heap: &'v Heap) -> Self {
+        Self { heap }
+    }
+}
+
+impl<'de, 'v> DeserializeSeed<'de> for DeserializeValue<'v> {
+    type Value = Value<'v>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value<'v>, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de, 'v> Visitor<'de> for DeserializeValue<'v> {
+    type Value = Value<'v>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a value representable in Starlark")
+    }
+
+    fn visit_bool<E: Error>(self, v: bool) -> Result<Value<'v>, E> {
+        Ok(Value::new_bool(v))
+    }
+
+    fn visit_i64<E: Error>(self, v: i64) -> Result<Value<'v>, E> {
+        Ok(match i32::try_from(v) {
+            Ok(v) => Value::new_int(v),
+            Err(_) => self.heap.alloc(BigInt::from(v)),
+        })
+    }
+
+    fn visit_u64<E: Error>(self, v: u64) -> Result<Value<'v>, E> {
+        Ok(match i32::try_from(v) {
+            Ok(v) => Value::new_int(v),
+            Err(_) => self.heap.alloc(BigInt::from(v)),
+        })
+    }
+
+    fn visit_f64<E: Error>(self, v: f64) -> Result<Value<'v>, E> {
+        Ok(self.heap.alloc(v))
+    }
+
+    fn visit_str<E: Error>(self, v: &str) -> Result<Value<'v>, E> {
+        Ok(self.heap.alloc(v))
+    }
+
+    fn visit_bytes<E: Error>(self, v: &[u8]) -> Result<Value<'v>, E> {
+        Ok(self.heap.alloc(v))
+    }
+
+    fn visit_unit<E: Error>(self) -> Result<Value<'v>, E> {
+        Ok(Value::new_none())
+    }
+
+    fn visit_none<E: Error>(self) -> Result<Value<'v>, E> {
+        Ok(Value::new_none())
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value<'v>, D::Error> {
+        self.deserialize(deserializer)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value<'v>, A::Error> {
+        let mut res = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(x) = seq.next_element_seed(self)? {
+            res.push(x);
+        }
+        Ok(self.heap.alloc(res))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value<'v>, A::Error> {
+        let mut res = SmallMap::with_capacity(map.size_hint().unwrap_or(0));
+        while let Some(k) = map.next_key_seed(self)? {
+            let k = k.get_hashed().map_err(A::Error::custom)?;
+            let v = map.next_value_seed(self)?;
+            res.insert_hashed(k, v);
+        }
+        Ok(self.heap.alloc(Dict::new(res)))
+    }
+}
+
+impl Heap {
+    /// Allocate the value described by a JSON string, with objects becoming dicts
+    /// and arrays becoming lists.
+    ///
+    /// ```
+    /// # use starlark::values::Heap;
+    /// let heap = Heap::new();
+    /// let x = heap.alloc_from_json(r#"{"a": [1, "b", null]}"#).unwrap();
+    /// assert_eq!(x.to_repr(), r#"{"a": [1, "b", None]}"#);
+    /// ```
+    pub fn alloc_from_json<'v>(&'v self, json: &str) -> anyhow::Result<Value<'v>> {
+        let mut deserializer = serde_json::Deserializer::from_str(json);
+        let res = DeserializeValue::new(self).deserialize(&mut deserializer)?;
+        deserializer.end()?;
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::values::Heap;
+
+    #[test]
+    fn test_alloc_from_json() {
+        let heap = Heap::new();
+        let json =
+            r#"{"a": [1, -2, 2.5, true, null, "s"], "b": {"c": {}}, "d": 12345678901234567890}"#;
+        let x = heap.alloc_from_json(json).unwrap();
+        assert_eq!(
+            x.to_repr(),
+            r#"{"a": [1, -2, 2.5, True, None, "s"], "b": {"c": {}}, "d": 12345678901234567890}"#
+        );
+        // Round trips through JSON.
+        assert_eq!(
+            x.to_json().unwrap(),
+            r#"{"a":[1,-2,2.5,true,null,"s"],"b":{"c":{}},"d":12345678901234567890}"#
+        );
+        assert!(heap.alloc_from_json("[1,").is_err());
+        assert!(heap.alloc_from_json("1 2").is_err());
+    }
+}
@@ -32,8 +32,14 @@ pub enum ValueError {
         left: String,
         right: String,
     },
-    #[error("Cannot divide by zero")]
+    #[error("floating-point division by zero")]
     DivisionByZero,
+    #[error("floored division by zero")]
+    FlooredDivisionByZero,
+    #[error("integer modulo by zero")]
+    ModuloByZero,
+    #[error("floating-point modulo by zero")]
+    FloatModuloByZero,
     #[error("Integer overflow")]
     IntegerOverflow,
     #[error("Type of parameters mismatch, expected `{0}`, actual `{1}`")]
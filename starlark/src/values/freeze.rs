@@ -15,7 +15,13 @@
  * limitations under the License.
  */
 
-use std::{cell::RefCell, marker, marker::PhantomData};
+use std::{
+    cell::RefCell,
+    error::Error,
+    fmt::{self, Display},
+    marker,
+    marker::PhantomData,
+};
 
 use gazebo::prelude::*;
 
@@ -24,6 +30,7 @@ use crate::{
         vec_map::{Bucket, VecMap},
         SmallMap,
     },
+    eval::ValueProvenance,
     values::{Freezer, FrozenStringValue, FrozenValue, StringValue, Value},
 };
 
@@ -55,6 +62,143 @@ pub trait Freeze {
     fn freeze(self, freezer: &Freezer) -> anyhow::Result<Self::Frozen>;
 }
 
+/// How a value which failed to freeze was reached from the one containing it,
+/// see [`FreezeError`].
+#[derive(Debug, Clone)]
+pub(crate) enum FreezeStep {
+    /// A module variable.
+    Variable(String),
+    /// An element of a list or tuple.
+    Index(usize),
+    /// The value in a dict, under a key with this `repr`.
+    Key(String),
+    /// A field of a struct or record.
+    Attr(String),
+    /// Somewhere inside a value of this type, which doesn't say where.
+    Within(&'static str),
+}
+
+impl Display for FreezeStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FreezeStep::Variable(x) => write!(f, "{}", x),
+            FreezeStep::Index(x) => write!(f, "[{}]", x),
+            FreezeStep::Key(x) => write!(f, "[{}]", x),
+            FreezeStep::Attr(x) => write!(f, ".{}", x),
+            FreezeStep::Within(x) => write!(f, ".<{}>", x),
+        }
+    }
+}
+
+/// The error when a value can't be frozen, e.g. by
+/// [`Module::freeze`](crate::environment::Module::freeze), saying where the value is.
+///
+/// The path is written as Starlark, e.g. `cfg["handlers"][3].callback`, except that a
+/// step inside a value which doesn't say where it keeps the next is written `.<type>`,
+/// e.g. `rules[0].<function>` for a value captured by a function.
+#[derive(Debug)]
+pub struct FreezeError {
+    // From the value which failed to the module variable, the order they are found in
+    path: Vec<FreezeStep>,
+    // Was the last step added by the value containing the one which failed
+    added_step: bool,
+    type_name: &'static str,
+    // The address of the value on the heap, to find its provenance
+    address: usize,
+    provenance: Option<ValueProvenance>,
+    error: anyhow::Error,
+}
+
+impl FreezeError {
+    /// Wrap the error freezing the value of type `type_name` at `address` on the heap,
+    /// unless the error is from freezing a value it contains.
+    pub(crate) fn new(
+        error: anyhow::Error,
+        type_name: &'static str,
+        address: usize,
+    ) -> anyhow::Error {
+        match error.downcast::<FreezeError>() {
+            Ok(mut e) => {
+                if !e.added_step {
+                    e.path.push(FreezeStep::Within(type_name));
+                }
+                e.added_step = false;
+                e.into()
+            }
+            Err(error) => FreezeError {
+                path: Vec::new(),
+                added_step: false,
+                type_name,
+                address,
+                provenance: None,
+                error,
+            }
+            .into(),
+        }
+    }
+
+    /// Record how the value which failed was reached from the value being frozen.
+    pub(crate) fn add_step<T>(
+        res: anyhow::Result<T>,
+        step: impl FnOnce() -> FreezeStep,
+    ) -> anyhow::Result<T> {
+        res.map_err(|e| Self::with_step(e, step()))
+    }
+
+    fn with_step(error: anyhow::Error, step: FreezeStep) -> anyhow::Error {
+        match error.downcast::<FreezeError>() {
+            Ok(mut e) => {
+                e.path.push(step);
+                e.added_step = true;
+                e.into()
+            }
+            Err(e) => e,
+        }
+    }
+
+    pub(crate) fn address(&self) -> usize {
+        self.address
+    }
+
+    pub(crate) fn set_provenance(&mut self, provenance: ValueProvenance) {
+        self.provenance = Some(provenance);
+    }
+
+    /// The path to the value from the module variable holding it, e.g. `cfg["handlers"][3]`.
+    pub fn path(&self) -> String {
+        self.path.iter().rev().map(|x| x.to_string()).collect()
+    }
+
+    /// The type of the value.
+    pub fn type_name(&self) -> &str {
+        self.type_name
+    }
+
+    /// Where the value was allocated, if
+    /// [`Evaluator::enable_provenance`](crate::eval::Evaluator::enable_provenance)
+    /// was called before it was.
+    pub fn provenance(&self) -> Option<&ValueProvenance> {
+        self.provenance.as_ref()
+    }
+}
+
+impl Display for FreezeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Can't freeze ")?;
+        if !self.path.is_empty() {
+            write!(f, "`{}`, ", self.path())?;
+        }
+        write!(f, "a value of type `{}`: {:#}", self.type_name, self.error)?;
+        if let Some(provenance) = &self.provenance {
+            write!(f, "\nThe value was allocated at {}", provenance)?;
+        }
+        Ok(())
+    }
+}
+
+// The underlying error is part of the message, so isn't also the source
+impl Error for FreezeError {}
+
 impl Freeze for String {
     type Frozen = String;
 
@@ -79,6 +223,14 @@ impl Freeze for usize {
     }
 }
 
+impl Freeze for () {
+    type Frozen = ();
+
+    fn freeze(self, _freezer: &Freezer) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
 impl Freeze for bool {
     type Frozen = bool;
 
@@ -136,9 +288,22 @@ where
     type Frozen = VecMap<K::Frozen, V::Frozen>;
 
     fn freeze(self, freezer: &Freezer) -> anyhow::Result<Self::Frozen> {
+        self.freeze_keyed(freezer, |_| None)
+    }
+}
+
+impl<K: Freeze, V: Freeze> VecMap<K, V> {
+    fn freeze_keyed(
+        self,
+        freezer: &Freezer,
+        step: impl Fn(&K::Frozen) -> Option<FreezeStep>,
+    ) -> anyhow::Result<VecMap<K::Frozen, V::Frozen>> {
         let buckets = self.buckets.into_try_map(|Bucket { hash, key, value }| {
             let key = key.freeze(freezer)?;
-            let value = value.freeze(freezer)?;
+            let value = value.freeze(freezer).map_err(|e| match step(&key) {
+                Some(step) => FreezeError::with_step(e, step),
+                None => e,
+            })?;
             // `freeze` must not change hash.
             Ok::<_, anyhow::Error>(Bucket { hash, key, value })
         })?;
@@ -153,10 +318,22 @@ where
 {
     type Frozen = SmallMap<K::Frozen, V::Frozen>;
 
-    fn freeze(mut self, freezer: &Freezer) -> anyhow::Result<SmallMap<K::Frozen, V::Frozen>> {
+    fn freeze(self, freezer: &Freezer) -> anyhow::Result<SmallMap<K::Frozen, V::Frozen>> {
+        self.freeze_keyed(freezer, |_| None)
+    }
+}
+
+impl<K: Freeze, V: Freeze> SmallMap<K, V> {
+    /// Freeze the map, and if a value fails to freeze, add the [`FreezeStep`]
+    /// `step` returns for its frozen key to the [`FreezeError`].
+    pub(crate) fn freeze_keyed(
+        mut self,
+        freezer: &Freezer,
+        step: impl Fn(&K::Frozen) -> Option<FreezeStep>,
+    ) -> anyhow::Result<SmallMap<K::Frozen, V::Frozen>> {
         self.maybe_drop_index();
         let (entries, index) = self.into_raw_parts();
-        let entries = entries.freeze(freezer)?;
+        let entries = entries.freeze_keyed(freezer, step)?;
         unsafe { Ok(SmallMap::from_raw_parts(entries, index)) }
     }
 }
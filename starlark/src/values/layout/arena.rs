@@ -151,10 +151,11 @@ impl<'v, 'v2, T: AValueDyn<'v2>> Reservation<'v, 'v2, T> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Default)]
 /// Information about the data stored on a heap. Accessible through
-/// the function `allocated_summary` available on [`Heap`](crate::values::Heap)
-/// and [`FrozenHeap`](crate::values::FrozenHeap)
+/// the function `allocated_summary` available on [`Heap`](crate::values::Heap),
+/// [`FrozenHeap`](crate::values::FrozenHeap) and
+/// [`FrozenHeapRef`](crate::values::FrozenHeapRef).
 pub struct HeapSummary {
     /// For each type, give the (number of entries, size of all entries).
     /// The size may be approximate as it includes information from
@@ -162,6 +163,39 @@ pub struct HeapSummary {
     pub summary: HashMap<String, (usize, usize)>,
 }
 
+impl HeapSummary {
+    /// Number of values on the heap.
+    pub fn total_count(&self) -> usize {
+        self.summary.values().map(|(count, _)| count).sum()
+    }
+
+    /// Size of all the values on the heap, in bytes.
+    pub fn total_bytes(&self) -> usize {
+        self.summary.values().map(|(_, bytes)| bytes).sum()
+    }
+
+    /// Add the values summarised by `other` to this summary.
+    pub(crate) fn add(&mut self, other: HeapSummary) {
+        for (name, (count, bytes)) in other.summary {
+            let v = self.summary.entry(name).or_insert((0, 0));
+            v.0 += count;
+            v.1 += bytes;
+        }
+    }
+
+    /// The `(type, count, bytes)` of each type on the heap, the types using the most
+    /// memory first, and types of the same size by name.
+    pub fn by_bytes(&self) -> Vec<(&str, usize, usize)> {
+        let mut res: Vec<_> = self
+            .summary
+            .iter()
+            .map(|(name, (count, bytes))| (name.as_str(), *count, *bytes))
+            .collect();
+        res.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| a.0.cmp(b.0)));
+        res
+    }
+}
+
 impl Arena {
     pub fn allocated_bytes(&self) -> usize {
         self.drop.allocated_bytes() + self.non_drop.allocated_bytes()
@@ -284,6 +318,18 @@ impl Arena {
     }
 
     fn iter_chunk<'a>(chunk: &'a [MaybeUninit<u8>], mut f: impl FnMut(&'a AValueHeader)) {
+        Self::iter_chunk_or_forward(chunk, |x| {
+            if let Either::Left(x) = x.unpack() {
+                f(x)
+            }
+        })
+    }
+
+    // Like `iter_chunk`, but including the values which have been overwritten with a forward.
+    fn iter_chunk_or_forward<'a>(
+        chunk: &'a [MaybeUninit<u8>],
+        mut f: impl FnMut(&'a AValueOrForward),
+    ) {
         unsafe {
             // We only allocate trait ptr then a payload immediately after
             // so find the first trait ptr, see how big it is, and keep skipping.
@@ -291,11 +337,9 @@ impl Arena {
             let end = chunk.as_ptr().add(chunk.len());
             while p < end {
                 let or_forward = &*(p as *const AValueOrForward);
+                f(or_forward);
                 let n = match or_forward.unpack() {
-                    Either::Left(ptr) => {
-                        f(&or_forward.header);
-                        ptr.unpack().memory_size()
-                    }
+                    Either::Left(ptr) => ptr.unpack().memory_size(),
                     Either::Right(forward) => {
                         // Overwritten, so the next word will be the size of the memory
                         forward.object_size
@@ -314,6 +358,25 @@ impl Arena {
     // Iterate over the values in the heap in the order they
     // were added.
     pub fn for_each_ordered<'a>(&'a mut self, mut f: impl FnMut(&'a AValueHeader)) {
+        self.for_each_ordered_or_forward(|x| {
+            if let Either::Left(x) = x.unpack() {
+                f(x)
+            }
+        })
+    }
+
+    // Like `for_each_ordered`, but also passing the values which have been overwritten
+    // with a forward, as `None`, and the address of each value.
+    pub(crate) fn for_each_ordered_address<'a>(
+        &'a mut self,
+        mut f: impl FnMut(usize, Option<&'a AValueHeader>),
+    ) {
+        self.for_each_ordered_or_forward(|x| {
+            f(x as *const AValueOrForward as usize, x.unpack().left())
+        })
+    }
+
+    fn for_each_ordered_or_forward<'a>(&'a mut self, mut f: impl FnMut(&'a AValueOrForward)) {
         // We get the chunks from most newest to oldest as per the bumpalo spec.
         // And within each chunk, the values are filled newest to oldest.
         // So need to do two sets of reversing.
@@ -322,13 +385,29 @@ impl Arena {
             // Use a single buffer to reduce allocations, but clear it after use
             let mut buffer = Vec::new();
             for chunk in chunks.iter().rev() {
-                Self::iter_chunk(chunk, |x| buffer.push(x));
+                Self::iter_chunk_or_forward(chunk, |x| buffer.push(x));
                 buffer.iter().rev().for_each(|x| f(*x));
                 buffer.clear();
             }
         }
     }
 
+    /// The address ranges of the memory allocated by this arena, sorted by start.
+    pub(crate) fn chunk_ranges(&self) -> Vec<(usize, usize)> {
+        let mut res = Vec::new();
+        for bump in [&self.drop, &self.non_drop] {
+            // SAFE: We're consuming the iterator immediately and not allocating from the arena during.
+            unsafe {
+                res.extend(
+                    bump.iter_allocated_chunks_raw()
+                        .map(|(data, len)| (data as usize, data as usize + len)),
+                );
+            }
+        }
+        res.sort_unstable();
+        res
+    }
+
     // Iterate over the values in the drop bump in any order
     pub fn for_each_drop_unordered<'a>(&'a mut self, mut f: impl FnMut(&'a AValueHeader)) {
         self.drop
@@ -576,4 +655,31 @@ mod test {
         assert_eq!(entry.0, 2);
         assert_eq!(entry.1, arena.allocated_bytes());
     }
+
+    #[test]
+    fn test_heap_summary_totals() {
+        use crate::values::{FrozenHeap, Heap};
+
+        let heap = Heap::new();
+        heap.alloc((1, 2, 3));
+        heap.alloc((4, 5));
+        heap.alloc("x".repeat(100).as_str());
+        let summary = heap.allocated_summary();
+        assert_eq!(summary.total_count(), 3);
+        let by_bytes = summary.by_bytes();
+        assert_eq!(by_bytes.len(), 2);
+        assert_eq!(by_bytes[0].0, "string");
+        assert_eq!(by_bytes[1].0, "tuple");
+        assert_eq!(by_bytes[1].1, 2);
+        assert_eq!(
+            by_bytes.iter().map(|x| x.2).sum::<usize>(),
+            summary.total_bytes()
+        );
+
+        let frozen_heap = FrozenHeap::new();
+        frozen_heap.alloc((1, 2));
+        let summary = frozen_heap.allocated_summary();
+        assert_eq!(summary.total_count(), 1);
+        assert_eq!(summary.by_bytes()[0].0, "tuple");
+    }
 }
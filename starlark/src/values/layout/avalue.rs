@@ -27,6 +27,7 @@ use std::{
 };
 
 use derive_more::Display;
+use either::Either;
 use gazebo::{any::AnyLifetime, cast, coerce::Coerce, prelude::*};
 
 use crate::{
@@ -43,13 +44,14 @@ use crate::{
         list::{FrozenList, List, ListGen},
         none::NoneType,
         num::Num,
-        string::StarlarkStr,
+        string::{rope::StarlarkRope, StarlarkStr},
         types::{
             array::Array,
+            shared_slice::SharedSlice,
             tuple::{FrozenTuple, Tuple},
         },
-        ComplexValue, Freezer, FrozenStringValue, FrozenValue, Heap, SimpleValue, StarlarkValue,
-        StarlarkValueDyn, Trace, Tracer, Value, ValueTyped,
+        ComplexValue, FreezeError, FreezeStep, Freezer, FrozenStringValue, FrozenValue, Heap,
+        SimpleValue, StarlarkValue, StarlarkValueDyn, Trace, Tracer, Value, ValueTyped,
     },
 };
 
@@ -163,6 +165,10 @@ pub(crate) trait AValue<'v>: StarlarkValueDyn<'v> + Sized {
 
     unsafe fn heap_copy(me: *mut AValueRepr<Self>, tracer: &Tracer<'v>) -> Value<'v>;
 
+    /// Trace the values referenced by this value, without moving it.
+    /// Used by a minor garbage collection for tenured values which have been mutated.
+    unsafe fn heap_trace(me: *mut AValueRepr<Self>, tracer: &Tracer<'v>);
+
     fn get_hash(&self) -> anyhow::Result<SmallHashResult> {
         let mut hasher = StarlarkHasher::new();
         self.write_hash(&mut hasher)?;
@@ -187,6 +193,8 @@ pub(crate) trait AValueDyn<'v>: StarlarkValueDyn<'v> {
     /// This function is not safe because it overwrites `self` value with forward.
     unsafe fn heap_copy(&self, me: *mut AValueHeader, tracer: &Tracer<'v>) -> Value<'v>;
 
+    unsafe fn heap_trace(&self, me: *mut AValueHeader, tracer: &Tracer<'v>);
+
     fn is_str(&self) -> bool;
 
     fn get_hash(&self) -> anyhow::Result<SmallHashResult>;
@@ -209,6 +217,10 @@ impl<'v, A: AValue<'v>> AValueDyn<'v> for A {
         A::heap_copy((*me).as_repr_mut::<A>(), tracer)
     }
 
+    unsafe fn heap_trace(&self, me: *mut AValueHeader, tracer: &Tracer<'v>) {
+        A::heap_trace((*me).as_repr_mut::<A>(), tracer)
+    }
+
     fn is_str(&self) -> bool {
         A::is_str()
     }
@@ -257,6 +269,12 @@ pub(crate) fn list_avalue<'v>(
     AValueImpl(Direct, ListGen(List::new(content)))
 }
 
+pub(crate) fn shared_list_avalue<'v>(
+    shared: ValueTyped<'v, SharedSlice<'v>>,
+) -> impl AValue<'v, StarlarkValue = ListGen<List<'v>>, ExtraElem = ()> {
+    AValueImpl(Direct, ListGen(List::new_shared(shared)))
+}
+
 pub(crate) fn frozen_list_avalue(len: usize) -> impl AValue<'static, ExtraElem = FrozenValue> {
     AValueImpl(Direct, unsafe { ListGen(FrozenList::new(len)) })
 }
@@ -267,6 +285,12 @@ pub(crate) fn array_avalue<'v>(
     AValueImpl(Direct, unsafe { Array::new(0, cap) })
 }
 
+pub(crate) fn shared_slice_avalue<'v>(
+    x: SharedSlice<'v>,
+) -> impl AValue<'v, StarlarkValue = SharedSlice<'v>, ExtraElem = ()> {
+    AValueImpl(Direct, x)
+}
+
 pub(crate) fn basic_ref<'v, T: StarlarkValueBasic<'v>>(x: &T) -> &dyn AValueDyn<'v> {
     // These are the same representation, so safe to convert
     let x: &AValueImpl<Basic, T> = unsafe { cast::ptr(x) };
@@ -285,6 +309,10 @@ where
     AValueImpl(Complex, x)
 }
 
+pub(crate) fn rope_avalue<'v>(x: StarlarkRope<'v>) -> impl AValue<'v, ExtraElem = ()> {
+    AValueImpl(Direct, x)
+}
+
 pub(crate) fn float_avalue<'v>(x: StarlarkFloat) -> impl AValue<'v, ExtraElem = ()> {
     AValueImpl(Direct, x)
 }
@@ -343,6 +371,9 @@ impl<'v, T: StarlarkValueBasic<'v>> AValue<'v> for AValueImpl<Basic, T> {
     unsafe fn heap_copy(_me: *mut AValueRepr<Self>, _tracer: &Tracer<'v>) -> Value<'v> {
         unreachable!("Basic types don't appear in the heap")
     }
+    unsafe fn heap_trace(_me: *mut AValueRepr<Self>, _tracer: &Tracer<'v>) {
+        unreachable!("Basic types don't appear in the heap")
+    }
 
     fn get_hash(&self) -> anyhow::Result<SmallHashResult> {
         Ok(self.1.get_hash())
@@ -373,6 +404,8 @@ impl<'v> AValue<'v> for AValueImpl<Direct, StarlarkFloat> {
         Self::heap_copy_impl(me, tracer, |_v, _tracer| {})
     }
 
+    unsafe fn heap_trace(_me: *mut AValueRepr<Self>, _tracer: &Tracer<'v>) {}
+
     fn get_hash(&self) -> anyhow::Result<SmallHashResult> {
         Ok(Num::from(self.1.0).get_small_hash_result())
     }
@@ -426,11 +459,103 @@ impl<'v> AValue<'v> for AValueImpl<Direct, StarlarkStr> {
         v
     }
 
+    unsafe fn heap_trace(_me: *mut AValueRepr<Self>, _tracer: &Tracer<'v>) {}
+
     fn get_hash(&self) -> anyhow::Result<SmallHashResult> {
         Ok(self.1.get_small_hash_result())
     }
 }
 
+impl<'v> AValue<'v> for AValueImpl<Direct, StarlarkRope<'v>> {
+    type StarlarkValue = StarlarkRope<'v>;
+
+    type ExtraElem = ();
+
+    fn extra_len(&self) -> usize {
+        0
+    }
+
+    fn offset_of_extra() -> usize {
+        mem::size_of::<Self>()
+    }
+
+    unsafe fn heap_freeze(
+        me: *mut AValueRepr<Self>,
+        freezer: &Freezer,
+    ) -> anyhow::Result<FrozenValue> {
+        let rope = &(*me).payload.1;
+        let fv = match rope.flattened() {
+            Some(flat) => freezer.freeze(flat.to_value())?,
+            None => {
+                let mut s = String::with_capacity(rope.len());
+                // Pieces which are already frozen have been overwritten with a forward,
+                // so read the frozen string instead.
+                rope.for_each_piece(
+                    |x| match x.0.unpack_ptr() {
+                        Some(p) if x.0.is_unfrozen() => match p.unpack_overwrite() {
+                            Either::Left(forward) => {
+                                FrozenValue::new_ptr_usize_with_str_tag(forward).to_value()
+                            }
+                            Either::Right(_) => x,
+                        },
+                        _ => x,
+                    },
+                    |piece| s.push_str(piece),
+                );
+                freezer.alloc(s)
+            }
+        };
+        debug_assert!(fv.is_str());
+        AValueHeader::overwrite_with_forward::<Self>(me, fv.0.ptr_value());
+        Ok(fv)
+    }
+
+    unsafe fn heap_copy(me: *mut AValueRepr<Self>, tracer: &Tracer<'v>) -> Value<'v> {
+        let rope = &(*me).payload.1;
+        let v = match rope.flattened() {
+            Some(flat) => {
+                // Once flattened, the pieces are no longer needed, so replace the rope
+                // with its string.
+                let mut v = flat.to_value();
+                tracer.trace(&mut v);
+                v
+            }
+            None if tracer.is_tenuring() => {
+                // Flattening would store a younger string in a tenured rope, which nothing
+                // remembers, so tenure the string instead. Pieces which have already been
+                // copied have been overwritten with a forward, so read the copy.
+                let mut s = String::with_capacity(rope.len());
+                rope.for_each_piece(
+                    |x| match x.0.unpack_ptr() {
+                        Some(p) if x.0.is_unfrozen() => match p.unpack_overwrite() {
+                            Either::Left(forward) => Value::new_ptr_usize_with_str_tag(forward),
+                            Either::Right(_) => x,
+                        },
+                        _ => x,
+                    },
+                    |piece| s.push_str(piece),
+                );
+                tracer.alloc_str(&s)
+            }
+            None => return Self::heap_copy_impl(me, tracer, Trace::trace),
+        };
+        AValueHeader::overwrite_with_forward::<Self>(me, clear_lsb(v.0.ptr_value()));
+        v
+    }
+
+    unsafe fn heap_trace(me: *mut AValueRepr<Self>, tracer: &Tracer<'v>) {
+        (*me).payload.1.trace(tracer)
+    }
+
+    fn get_hash(&self) -> anyhow::Result<SmallHashResult> {
+        Ok(self
+            .1
+            .flatten()
+            .unpack_starlark_str()
+            .get_small_hash_result())
+    }
+}
+
 impl<'v> AValue<'v> for AValueImpl<Direct, Tuple<'v>> {
     type StarlarkValue = Tuple<'v>;
 
@@ -461,7 +586,11 @@ impl<'v> AValue<'v> for AValueImpl<Direct, Tuple<'v>> {
         AValueHeader::overwrite_with_forward::<Self>(me, fv.0.ptr_value());
 
         // TODO: this allocation is unnecessary
-        let frozen_values = content.try_map(|v| freezer.freeze(*v))?;
+        let frozen_values = content
+            .iter()
+            .enumerate()
+            .map(|(i, v)| FreezeError::add_step(freezer.freeze(*v), || FreezeStep::Index(i)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
         r.fill(AValueImpl(Direct, FrozenTuple::new(content.len())));
         MaybeUninit::write_slice(extra, &frozen_values);
 
@@ -489,6 +618,10 @@ impl<'v> AValue<'v> for AValueImpl<Direct, Tuple<'v>> {
         MaybeUninit::write_slice(extra, content);
         v
     }
+
+    unsafe fn heap_trace(me: *mut AValueRepr<Self>, tracer: &Tracer<'v>) {
+        (*me).payload.1.content_mut().trace(tracer)
+    }
 }
 
 impl<'v> AValue<'v> for AValueImpl<Direct, FrozenTuple> {
@@ -514,6 +647,10 @@ impl<'v> AValue<'v> for AValueImpl<Direct, FrozenTuple> {
     unsafe fn heap_copy(_me: *mut AValueRepr<Self>, _tracer: &Tracer<'v>) -> Value<'v> {
         panic!("shouldn't be copying frozen values");
     }
+
+    unsafe fn heap_trace(_me: *mut AValueRepr<Self>, _tracer: &Tracer<'v>) {
+        panic!("shouldn't be tracing frozen values");
+    }
 }
 
 impl<'v> AValue<'v> for AValueImpl<Direct, ListGen<List<'v>>> {
@@ -546,8 +683,10 @@ impl<'v> AValue<'v> for AValueImpl<Direct, ListGen<List<'v>>> {
         AValueHeader::overwrite_with_forward::<Self>(me, fv.0.ptr_value());
         r.fill(AValueImpl(Direct, ListGen(FrozenList::new(content.len()))));
         assert_eq!(extra.len(), content.len());
-        for (elem_place, elem) in extra.iter_mut().zip(content) {
-            elem_place.write(freezer.freeze(*elem)?);
+        for (i, (elem_place, elem)) in extra.iter_mut().zip(content).enumerate() {
+            elem_place.write(FreezeError::add_step(freezer.freeze(*elem), || {
+                FreezeStep::Index(i)
+            })?);
         }
         Ok(fv)
     }
@@ -555,6 +694,13 @@ impl<'v> AValue<'v> for AValueImpl<Direct, ListGen<List<'v>>> {
     unsafe fn heap_copy(me: *mut AValueRepr<Self>, tracer: &Tracer<'v>) -> Value<'v> {
         Self::heap_copy_impl(me, tracer, Trace::trace)
     }
+
+    unsafe fn heap_trace(me: *mut AValueRepr<Self>, tracer: &Tracer<'v>) {
+        let list = &mut (*me).payload.1.0;
+        // Elements are stored into the array, which isn't remembered itself.
+        tracer.trace_tenured(list.content.get());
+        list.trace(tracer)
+    }
 }
 
 impl<'v> AValue<'v> for AValueImpl<Direct, ListGen<FrozenList>> {
@@ -580,6 +726,10 @@ impl<'v> AValue<'v> for AValueImpl<Direct, ListGen<FrozenList>> {
     unsafe fn heap_copy(_me: *mut AValueRepr<Self>, _tracer: &Tracer<'v>) -> Value<'v> {
         panic!("shouldn't be copying frozen values");
     }
+
+    unsafe fn heap_trace(_me: *mut AValueRepr<Self>, _tracer: &Tracer<'v>) {
+        panic!("shouldn't be tracing frozen values");
+    }
 }
 
 impl<'v> AValue<'v> for AValueImpl<Direct, Array<'v>> {
@@ -631,6 +781,38 @@ impl<'v> AValue<'v> for AValueImpl<Direct, Array<'v>> {
         MaybeUninit::write_slice(extra, content);
         v
     }
+
+    unsafe fn heap_trace(me: *mut AValueRepr<Self>, tracer: &Tracer<'v>) {
+        (*me).payload.1.content_mut().trace(tracer)
+    }
+}
+
+impl<'v> AValue<'v> for AValueImpl<Direct, SharedSlice<'v>> {
+    type StarlarkValue = SharedSlice<'v>;
+
+    type ExtraElem = ();
+
+    fn extra_len(&self) -> usize {
+        0
+    }
+
+    fn offset_of_extra() -> usize {
+        mem::size_of::<Self>()
+    }
+
+    unsafe fn heap_freeze(
+        _me: *mut AValueRepr<Self>,
+        _freezer: &Freezer,
+    ) -> anyhow::Result<FrozenValue> {
+        panic!("shared slices should not be frozen")
+    }
+
+    unsafe fn heap_copy(me: *mut AValueRepr<Self>, tracer: &Tracer<'v>) -> Value<'v> {
+        // The elements are frozen, so there is nothing to trace.
+        Self::heap_copy_impl(me, tracer, |_, _| {})
+    }
+
+    unsafe fn heap_trace(_me: *mut AValueRepr<Self>, _tracer: &Tracer<'v>) {}
 }
 
 impl<Mode, C> AValueImpl<Mode, C> {
@@ -676,6 +858,8 @@ where
     unsafe fn heap_copy(me: *mut AValueRepr<Self>, tracer: &Tracer<'v>) -> Value<'v> {
         Self::heap_copy_impl(me, tracer, |_v, _tracer| {})
     }
+
+    unsafe fn heap_trace(_me: *mut AValueRepr<Self>, _tracer: &Tracer<'v>) {}
 }
 
 impl<Mode, C> AValueImpl<Mode, C> {
@@ -732,6 +916,10 @@ where
     unsafe fn heap_copy(me: *mut AValueRepr<Self>, tracer: &Tracer<'v>) -> Value<'v> {
         Self::heap_copy_impl(me, tracer, Trace::trace)
     }
+
+    unsafe fn heap_trace(me: *mut AValueRepr<Self>, tracer: &Tracer<'v>) {
+        (*me).payload.1.trace(tracer)
+    }
 }
 
 #[derive(Debug, Display)]
@@ -753,6 +941,9 @@ impl<'v> AValueDyn<'v> for BlackHole {
     unsafe fn heap_copy(&self, _me: *mut AValueHeader, _tracer: &Tracer<'v>) -> Value<'v> {
         unreachable!()
     }
+    unsafe fn heap_trace(&self, _me: *mut AValueHeader, _tracer: &Tracer<'v>) {
+        unreachable!()
+    }
 
     fn is_str(&self) -> bool {
         // We don't create reservations for `StarlarkStr`.
@@ -812,6 +1003,12 @@ impl<'v> StarlarkValueDyn<'v> for BlackHole {
     fn collect_repr_cycle(&self, _collector: &mut String) {
         panic!()
     }
+    fn debug_summary(&self) -> Option<String> {
+        panic!()
+    }
+    fn debug_children(&self) -> Vec<(String, Value<'v>)> {
+        panic!()
+    }
     fn collect_json(&self, _collector: &mut String) -> anyhow::Result<()> {
         panic!()
     }
@@ -922,19 +1119,19 @@ impl<'v> StarlarkValueDyn<'v> for BlackHole {
     fn floor_div(&self, _other: Value<'v>, _heap: &'v Heap) -> anyhow::Result<Value<'v>> {
         panic!()
     }
-    fn bit_and(&self, _other: Value<'v>) -> anyhow::Result<Value<'v>> {
+    fn bit_and(&self, _other: Value<'v>, _heap: &'v Heap) -> anyhow::Result<Value<'v>> {
         panic!()
     }
-    fn bit_or(&self, _other: Value<'v>) -> anyhow::Result<Value<'v>> {
+    fn bit_or(&self, _other: Value<'v>, _heap: &'v Heap) -> anyhow::Result<Value<'v>> {
         panic!()
     }
-    fn bit_xor(&self, _other: Value<'v>) -> anyhow::Result<Value<'v>> {
+    fn bit_xor(&self, _other: Value<'v>, _heap: &'v Heap) -> anyhow::Result<Value<'v>> {
         panic!()
     }
-    fn left_shift(&self, _other: Value<'v>) -> anyhow::Result<Value<'v>> {
+    fn left_shift(&self, _other: Value<'v>, _heap: &'v Heap) -> anyhow::Result<Value<'v>> {
         panic!()
     }
-    fn right_shift(&self, _other: Value<'v>) -> anyhow::Result<Value<'v>> {
+    fn right_shift(&self, _other: Value<'v>, _heap: &'v Heap) -> anyhow::Result<Value<'v>> {
         panic!()
     }
     fn export_as(&self, _variable_name: &str, _eval: &mut Evaluator<'v, '_>) {
@@ -991,6 +1188,12 @@ impl<'v, Mode: 'static, T: StarlarkValue<'v>> StarlarkValueDyn<'v> for AValueImp
     fn collect_repr_cycle(&self, collector: &mut String) {
         self.1.collect_repr_cycle(collector)
     }
+    fn debug_summary(&self) -> Option<String> {
+        self.1.debug_summary()
+    }
+    fn debug_children(&self) -> Vec<(String, Value<'v>)> {
+        self.1.debug_children()
+    }
     fn collect_json(&self, collector: &mut String) -> anyhow::Result<()> {
         self.1.collect_json(collector)
     }
@@ -1101,20 +1304,20 @@ impl<'v, Mode: 'static, T: StarlarkValue<'v>> StarlarkValueDyn<'v> for AValueImp
     fn floor_div(&self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
         self.1.floor_div(other, heap)
     }
-    fn bit_and(&self, other: Value<'v>) -> anyhow::Result<Value<'v>> {
-        self.1.bit_and(other)
+    fn bit_and(&self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        self.1.bit_and(other, heap)
     }
-    fn bit_or(&self, other: Value<'v>) -> anyhow::Result<Value<'v>> {
-        self.1.bit_or(other)
+    fn bit_or(&self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        self.1.bit_or(other, heap)
     }
-    fn bit_xor(&self, other: Value<'v>) -> anyhow::Result<Value<'v>> {
-        self.1.bit_xor(other)
+    fn bit_xor(&self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        self.1.bit_xor(other, heap)
     }
-    fn left_shift(&self, other: Value<'v>) -> anyhow::Result<Value<'v>> {
-        self.1.left_shift(other)
+    fn left_shift(&self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        self.1.left_shift(other, heap)
     }
-    fn right_shift(&self, other: Value<'v>) -> anyhow::Result<Value<'v>> {
-        self.1.right_shift(other)
+    fn right_shift(&self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        self.1.right_shift(other, heap)
     }
     fn export_as(&self, variable_name: &str, eval: &mut Evaluator<'v, '_>) {
         self.1.export_as(variable_name, eval)
@@ -240,7 +240,7 @@ impl<'v> StringValue<'v> {
         if value.is_str() {
             Some(StringValue(value))
         } else {
-            None
+            value.unpack_rope().map(|rope| rope.flatten())
         }
     }
 
@@ -0,0 +1,154 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Bookkeeping for generational garbage collection.
+//!
+//! With generational collection enabled, a [`Heap`](crate::values::Heap) allocates into
+//! a nursery, and values which survive a collection are moved to a tenured arena.
+//! A minor collection only copies the nursery, treating the tenured values as roots
+//! without walking them, so long-lived values aren't rescanned by every collection.
+//! That is only sound if no tenured value refers to a nursery value the collection
+//! doesn't otherwise find, so code storing a value into an existing value calls
+//! the write barrier, which remembers the container here, and a minor collection
+//! walks the remembered containers. A major collection copies everything, like a heap
+//! without generations.
+
+use std::{
+    cell::{Cell, RefCell},
+    cmp,
+};
+
+/// Most containers remembered between collections, after which the next collection is
+/// a major one, rather than keep growing the remembered set.
+const MAX_REMEMBERED: usize = 1 << 16;
+
+/// Size of the tenured generation below which a major collection isn't forced by growth.
+const MIN_MAJOR_GC_BYTES: usize = 1 << 20;
+
+#[derive(Default)]
+pub(crate) struct Generations {
+    enabled: Cell<bool>,
+    /// Addresses of the headers of values which may have been given references to
+    /// younger values since the last collection.
+    remembered: RefCell<Vec<usize>>,
+    /// Set when more than `MAX_REMEMBERED` values were mutated since the last collection.
+    overflow: Cell<bool>,
+    /// Size of the tenured generation at which the next collection is a major one.
+    next_major_bytes: Cell<usize>,
+}
+
+impl Generations {
+    pub(crate) fn enable(&self) {
+        self.enabled.set(true);
+    }
+
+    #[inline]
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled.get()
+    }
+
+    pub(crate) fn remember(&self, header: usize) {
+        let mut remembered = self.remembered.borrow_mut();
+        // Loops usually mutate the same container many times in a row.
+        if remembered.last() == Some(&header) || self.overflow.get() {
+            return;
+        }
+        if remembered.len() >= MAX_REMEMBERED {
+            self.overflow.set(true);
+            remembered.clear();
+            return;
+        }
+        remembered.push(header);
+    }
+
+    /// Should the next collection be a minor one, given the current size of the
+    /// tenured generation.
+    pub(crate) fn minor_gc(&self, tenured_bytes: usize) -> bool {
+        self.enabled.get() && !self.overflow.get() && tenured_bytes < self.next_major_bytes.get()
+    }
+
+    /// The remembered containers, which are forgotten, as the collection will either
+    /// walk them, or copy them.
+    pub(crate) fn take_remembered(&self) -> Vec<usize> {
+        self.overflow.set(false);
+        self.remembered.take()
+    }
+
+    /// Called after a major collection, with the size of everything which survived it.
+    pub(crate) fn major_gc_done(&self, tenured_bytes: usize) {
+        self.next_major_bytes
+            .set(cmp::max(tenured_bytes * 2, MIN_MAJOR_GC_BYTES));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::values::{
+        list::List,
+        string::rope::{StarlarkRope, MIN_ROPE_LEN},
+        Heap,
+    };
+
+    #[test]
+    fn test_minor_gc_does_not_move_tenured() {
+        let heap = Heap::new();
+        heap.enable_generational_gc();
+        let mut list = heap.alloc_list(&[heap.alloc("tenured")]);
+        // The first collection is a major one, which tenures the list.
+        unsafe { heap.garbage_collect(|tracer| tracer.trace(&mut list)) };
+        let tenured = list;
+        heap.alloc("garbage");
+        unsafe { heap.garbage_collect(|tracer| tracer.trace(&mut list)) };
+        assert!(list.ptr_eq(tenured));
+        assert_eq!("[\"tenured\"]", list.to_str());
+    }
+
+    #[test]
+    fn test_minor_gc_traces_remembered() {
+        let heap = Heap::new();
+        heap.enable_generational_gc();
+        let mut list = heap.alloc_list(&[]);
+        unsafe { heap.garbage_collect(|tracer| tracer.trace(&mut list)) };
+        for i in 0..10 {
+            // Only the list refers to the new string.
+            heap.write_barrier(list);
+            List::from_value_mut(list)
+                .unwrap()
+                .unwrap()
+                .push(heap.alloc(format!("young{}", i).as_str()), &heap);
+            unsafe { heap.garbage_collect(|tracer| tracer.trace(&mut list)) };
+        }
+        assert_eq!(
+            "[\"young0\", \"young1\", \"young2\", \"young3\", \"young4\", \
+             \"young5\", \"young6\", \"young7\", \"young8\", \"young9\"]",
+            list.to_str()
+        );
+    }
+
+    #[test]
+    fn test_rope_is_flattened_when_tenured() {
+        let heap = Heap::new();
+        heap.enable_generational_gc();
+        let long = "x".repeat(MIN_ROPE_LEN);
+        let mut rope =
+            StarlarkRope::concat(heap.alloc(long.as_str()), heap.alloc("y"), &heap).unwrap();
+        assert!(rope.unpack_rope().is_some());
+        unsafe { heap.garbage_collect(|tracer| tracer.trace(&mut rope)) };
+        assert!(rope.unpack_rope().is_none());
+        assert_eq!(Some(format!("{}y", long).as_str()), rope.unpack_str());
+    }
+}
@@ -18,13 +18,13 @@
 use std::{
     cell::{Cell, RefCell},
     cmp,
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     fmt,
     fmt::{Debug, Display, Formatter},
     hash::{Hash, Hasher},
     intrinsics::copy_nonoverlapping,
     marker::PhantomData,
-    mem::MaybeUninit,
+    mem::{self, MaybeUninit},
     ops::Deref,
     ptr,
     sync::Arc,
@@ -33,10 +33,11 @@ use std::{
 
 use either::Either;
 use gazebo::{cast, prelude::*};
+use thiserror::Error;
 
 use crate::{
     collections::Hashed,
-    eval::FrozenDef,
+    eval::{FrozenDef, HeapSnapshot},
     values::{
         any::StarlarkAny,
         array::Array,
@@ -44,15 +45,21 @@ use crate::{
             arena::{AValueHeader, AValueRepr, Arena, HeapSummary, Reservation},
             avalue::{
                 array_avalue, complex, float_avalue, frozen_list_avalue, frozen_tuple_avalue,
-                list_avalue, simple, starlark_str, tuple_avalue, AValue, VALUE_EMPTY_ARRAY,
-                VALUE_EMPTY_FROZEN_LIST, VALUE_EMPTY_TUPLE,
+                list_avalue, rope_avalue, shared_list_avalue, shared_slice_avalue, simple,
+                starlark_str, tuple_avalue, AValue, VALUE_EMPTY_ARRAY, VALUE_EMPTY_FROZEN_LIST,
+                VALUE_EMPTY_TUPLE,
             },
             constant::constant_string,
+            generation::Generations,
+            interner::{FrozenStrInterner, StrInterner, MAX_INTERNED_STR_LEN},
+            snapshot::SnapshotRecorder,
             value::{FrozenValue, Value},
+            weak::{WeakSlot, WeakTable, WeakValue},
         },
-        string::hash_string_result,
-        types::float::StarlarkFloat,
-        AllocFrozenValue, ComplexValue, FrozenRef, FrozenValueTyped, SimpleValue, ValueTyped,
+        string::{hash_string_result, rope::StarlarkRope},
+        types::{float::StarlarkFloat, shared_slice::SharedSlice},
+        AllocFrozenValue, ComplexValue, FreezeError, FrozenRef, FrozenValueTyped, SimpleValue,
+        ValueTyped,
     },
 };
 
@@ -61,7 +68,25 @@ use crate::{
 pub struct Heap {
     /// Peak memory seen when a garbage collection takes place (may be lower than currently allocated)
     peak_allocated: Cell<usize>,
+    /// Bytes which may be allocated, see `set_allocation_limit`
+    allocation_limit: Cell<Option<usize>>,
+    /// Values allocated since the last garbage collection, or all the values unless
+    /// generational collection is enabled.
     arena: RefCell<Arena>,
+    /// Values which survived a garbage collection, when generational collection is enabled.
+    tenured: RefCell<Arena>,
+    /// Remembered mutations and the policy for generational collection.
+    generations: Generations,
+    /// Targets of the weak references handed out by `weak`, updated by garbage collection.
+    weak: RefCell<WeakTable>,
+    /// Short strings allocated by `alloc_str`.
+    strings: StrInterner,
+}
+
+#[derive(Error, Debug)]
+pub(crate) enum HeapError {
+    #[error("Allocation budget exceeded, the heap is limited to {0} bytes")]
+    AllocationBudgetExceeded(usize),
 }
 
 impl Debug for Heap {
@@ -81,6 +106,7 @@ impl Debug for Heap {
 pub struct FrozenHeap {
     arena: Arena,                          // My memory
     refs: RefCell<HashSet<FrozenHeapRef>>, // Memory I depend on
+    strings: FrozenStrInterner,            // Short strings allocated by `alloc_str`
 }
 
 /// `FrozenHeap` when it is no longer modified and can be share between threads.
@@ -155,6 +181,22 @@ impl FrozenHeapRef {
     pub fn allocated_summary(&self) -> HeapSummary {
         self.0.arena.allocated_summary()
     }
+
+    /// Number of bytes kept alive by this heap, i.e. its own
+    /// [`allocated_bytes`](FrozenHeapRef::allocated_bytes) plus those of the heaps it keeps
+    /// alive by reference, directly or indirectly, each counted once.
+    pub fn retained_bytes(&self) -> usize {
+        let mut seen = HashSet::new();
+        let mut todo = vec![self];
+        let mut bytes = 0;
+        while let Some(heap) = todo.pop() {
+            if seen.insert(heap) {
+                bytes += heap.allocated_bytes();
+                todo.extend(heap.0.refs.iter());
+            }
+        }
+        bytes
+    }
 }
 
 impl FrozenHeap {
@@ -167,7 +209,7 @@ impl FrozenHeap {
     /// [`FrozenHeapRef`] which can be [`clone`](Clone::clone)d, shared between threads,
     /// and ensures the underlying values allocated on the [`FrozenHeap`] remain valid.
     pub fn into_ref(self) -> FrozenHeapRef {
-        let FrozenHeap { arena, refs } = self;
+        let FrozenHeap { arena, refs, .. } = self;
         FrozenHeapRef(Arc::new(FrozenFrozenHeap {
             arena,
             refs: refs.into_inner(),
@@ -191,13 +233,19 @@ impl FrozenHeap {
     pub(crate) fn alloc_str(&self, x: &str) -> FrozenValue {
         if let Some(x) = constant_string(x) {
             x
+        } else if x.len() <= MAX_INTERNED_STR_LEN {
+            self.strings.intern(x, || self.alloc_str_uninterned(x))
         } else {
-            let (v, extra) = self.arena.alloc_extra_non_drop(starlark_str(x.len()));
-            MaybeUninit::write_slice(extra, x.as_bytes());
-            FrozenValue::new_repr(unsafe { cast::ptr_lifetime(&*v) })
+            self.alloc_str_uninterned(x)
         }
     }
 
+    fn alloc_str_uninterned(&self, x: &str) -> FrozenValue {
+        let (v, extra) = self.arena.alloc_extra_non_drop(starlark_str(x.len()));
+        MaybeUninit::write_slice(extra, x.as_bytes());
+        FrozenValue::new_repr(unsafe { cast::ptr_lifetime(&*v) })
+    }
+
     /// Allocate a string on this heap and hash it. Be careful about the warnings
     /// around [`FrozenValue`].
     pub fn alloc_str_hashed(&self, x: &str) -> Hashed<FrozenValue> {
@@ -256,10 +304,11 @@ impl FrozenHeap {
         value.map(|r| &r.0)
     }
 
-    /// Number of bytes allocated on this heap, not including any memory
+    /// Number of bytes allocated on this heap, including the table of interned strings,
+    /// which is dropped by [`into_ref`](FrozenHeap::into_ref), but not including any memory
     /// represented by [`extra_memory`](crate::values::StarlarkValue::extra_memory).
     pub fn allocated_bytes(&self) -> usize {
-        self.arena.allocated_bytes()
+        self.arena.allocated_bytes() + self.strings.allocated_bytes()
     }
 
     /// Number of bytes allocated by the heap but not yet filled.
@@ -333,9 +382,13 @@ impl Freezer {
         let value = value.0.unpack_ptr().unwrap();
         match value.unpack_overwrite() {
             Either::Left(x) => Ok(FrozenValue::new_ptr_usize_with_str_tag(x)),
-            Either::Right(v) => unsafe {
-                v.heap_freeze(value as *const AValueHeader as *mut AValueHeader, self)
-            },
+            Either::Right(v) => {
+                // Freezing overwrites the value, so get its type while we still can
+                let type_name = v.get_type();
+                let address = value as *const AValueHeader;
+                unsafe { v.heap_freeze(address as *mut AValueHeader, self) }
+                    .map_err(|e| FreezeError::new(e, type_name, address as usize))
+            }
         }
     }
 }
@@ -346,10 +399,13 @@ impl Heap {
         Self::default()
     }
 
-    /// Number of bytes allocated on this heap, not including any memory
+    /// Number of bytes allocated on this heap, including the table of interned strings,
+    /// but not including any memory
     /// represented by [`extra_memory`](crate::values::StarlarkValue::extra_memory).
     pub fn allocated_bytes(&self) -> usize {
         self.arena.borrow().allocated_bytes()
+            + self.tenured.borrow().allocated_bytes()
+            + self.strings.allocated_bytes()
     }
 
     /// Peak memory allocated to this heap, even if the value is now lower
@@ -360,7 +416,36 @@ impl Heap {
 
     /// Number of bytes allocated by the heap but not yet filled.
     pub fn available_bytes(&self) -> usize {
-        self.arena.borrow().available_bytes()
+        self.arena.borrow().available_bytes() + self.tenured.borrow().available_bytes()
+    }
+
+    /// Limit the [`allocated_bytes`](Heap::allocated_bytes) of this heap, so a script
+    /// building a huge value fails with an error, rather than using up the memory of the
+    /// process. Allocation itself can't fail, so the limit is checked by
+    /// [`check_allocation_limit`](Heap::check_allocation_limit), which an
+    /// [`Evaluator`](crate::eval::Evaluator) using this heap calls before each statement
+    /// and function call, and operations such as `*` call before allocating their result.
+    /// The usage can go over the limit within a statement, but evaluation stops soon after.
+    /// Must be called _before_ the code is compiled.
+    pub fn set_allocation_limit(&self, bytes: usize) {
+        self.allocation_limit.set(Some(bytes));
+    }
+
+    /// The limit set by [`set_allocation_limit`](Heap::set_allocation_limit), if any.
+    pub fn allocation_limit(&self) -> Option<usize> {
+        self.allocation_limit.get()
+    }
+
+    /// Fail if allocating `bytes` more would go over the
+    /// [`allocation_limit`](Heap::allocation_limit). Call it before allocating a large value,
+    /// e.g. from a native function.
+    pub fn check_allocation_limit(&self, bytes: usize) -> anyhow::Result<()> {
+        match self.allocation_limit.get() {
+            Some(limit) if self.allocated_bytes().saturating_add(bytes) > limit => {
+                Err(HeapError::AllocationBudgetExceeded(limit).into())
+            }
+            _ => Ok(()),
+        }
     }
 
     fn alloc_raw<'v, 'v2: 'v2>(&'v self, x: impl AValue<'v2, ExtraElem = ()>) -> Value<'v> {
@@ -400,17 +485,24 @@ impl Heap {
         unsafe { transmute!(Value, Value, Value::new_repr(&*v)) }
     }
 
-    /// Allocate a string on the heap.
+    /// Allocate a string on the heap. Short strings are interned, so allocating
+    /// the same short string twice gives the same value.
     pub fn alloc_str<'v>(&'v self, x: &str) -> Value<'v> {
         if let Some(x) = constant_string(x) {
             x.to_value()
+        } else if x.len() <= MAX_INTERNED_STR_LEN {
+            self.strings.intern(x, || self.alloc_str_uninterned(x))
         } else {
-            self.alloc_str_init(x.len(), |dest| unsafe {
-                copy_nonoverlapping(x.as_ptr(), dest, x.len())
-            })
+            self.alloc_str_uninterned(x)
         }
     }
 
+    fn alloc_str_uninterned<'v>(&'v self, x: &str) -> Value<'v> {
+        self.alloc_str_init(x.len(), |dest| unsafe {
+            copy_nonoverlapping(x.as_ptr(), dest, x.len())
+        })
+    }
+
     /// Allocate a string on this heap and hash it.
     pub fn alloc_str_hashed<'v>(&'v self, x: &str) -> Hashed<Value<'v>> {
         let h = hash_string_result(x);
@@ -430,6 +522,11 @@ impl Heap {
         }
     }
 
+    /// Allocate a rope, which [`StarlarkRope::concat`] decides when to use.
+    pub(crate) fn alloc_rope<'v>(&'v self, rope: StarlarkRope<'v>) -> Value<'v> {
+        self.alloc_raw(rope_avalue(rope))
+    }
+
     pub(crate) fn alloc_str_concat3<'v>(&'v self, x: &str, y: &str, z: &str) -> Value<'v> {
         if x.is_empty() {
             self.alloc_str_concat(y, z)
@@ -489,6 +586,13 @@ impl Heap {
         list.to_value()
     }
 
+    /// Allocate a list which reads the elements of a frozen list until it is mutated,
+    /// rather than copying them.
+    pub(crate) fn alloc_list_shared<'v>(&'v self, content: &'v [FrozenValue]) -> Value<'v> {
+        let shared = self.alloc_raw_typed(shared_slice_avalue(SharedSlice::new(content, self)));
+        self.alloc_raw(shared_list_avalue(shared))
+    }
+
     /// Allocate a list by concatenating two slices.
     pub(crate) fn alloc_list_concat<'v>(&'v self, a: &[Value<'v>], b: &[Value<'v>]) -> Value<'v> {
         let array = self.alloc_array(a.len() + b.len());
@@ -522,14 +626,33 @@ impl Heap {
     }
 
     pub(crate) fn for_each_ordered<'v>(&'v self, mut f: impl FnMut(Value<'v>)) {
-        self.arena.borrow_mut().for_each_ordered(|x| {
-            // Otherwise the Value is constrainted by the borrow_mut, when
-            // we consider values to be kept alive permanently, other than
-            // when a GC happens
-            f(Value::new_ptr_query_is_str(unsafe {
-                cast::ptr_lifetime(x)
-            }))
-        })
+        // Tenured values are older than the rest.
+        for arena in [&self.tenured, &self.arena] {
+            arena.borrow_mut().for_each_ordered(|x| {
+                // Otherwise the Value is constrainted by the borrow_mut, when
+                // we consider values to be kept alive permanently, other than
+                // when a GC happens
+                f(Value::new_ptr_query_is_str(unsafe {
+                    cast::ptr_lifetime(x)
+                }))
+            })
+        }
+    }
+
+    /// Like `for_each_ordered`, but also passing the values which have been overwritten
+    /// with a forward by freezing, as `None`, and the address of each value.
+    pub(crate) fn for_each_ordered_address<'v>(
+        &'v self,
+        mut f: impl FnMut(usize, Option<Value<'v>>),
+    ) {
+        for arena in [&self.tenured, &self.arena] {
+            arena.borrow_mut().for_each_ordered_address(|address, x| {
+                f(
+                    address,
+                    x.map(|x| Value::new_ptr_query_is_str(unsafe { cast::ptr_lifetime(x) })),
+                )
+            })
+        }
     }
 
     /// Garbage collect any values that are unused. This function is _unsafe_ in
@@ -539,30 +662,144 @@ impl Heap {
     pub(crate) unsafe fn garbage_collect<'v>(&'v self, f: impl FnOnce(&Tracer<'v>)) {
         // Record the highest peak, so it never decreases
         self.peak_allocated.set(self.peak_allocated_bytes());
-        self.garbage_collect_internal(f)
+        self.garbage_collect_internal(f, None);
     }
 
-    fn garbage_collect_internal<'v>(&'v self, f: impl FnOnce(&Tracer<'v>)) {
+    /// Like `garbage_collect`, but also record a snapshot of the values which are kept alive,
+    /// given where each value was allocated, by its address before garbage collection.
+    pub(crate) unsafe fn garbage_collect_snapshot<'v>(
+        &'v self,
+        f: impl FnOnce(&Tracer<'v>),
+        locations: &HashMap<usize, String>,
+    ) -> HeapSnapshot {
+        self.peak_allocated.set(self.peak_allocated_bytes());
+        self.garbage_collect_internal(f, Some(SnapshotRecorder::new()))
+            .unwrap()
+            .finish(locations)
+    }
+
+    fn garbage_collect_internal<'v>(
+        &'v self,
+        f: impl FnOnce(&Tracer<'v>),
+        snapshot: Option<SnapshotRecorder>,
+    ) -> Option<SnapshotRecorder> {
         // Must rewrite all Value's so they point at the new heap
         let mut arena = self.arena.borrow_mut();
+        let mut tenured = self.tenured.borrow_mut();
+        let generational = self.generations.is_enabled();
+        let remembered = self.generations.take_remembered();
 
+        let minor = snapshot.is_none() && self.generations.minor_gc(tenured.allocated_bytes());
         let tracer = Tracer::<'v> {
-            arena: Arena::default(),
+            // A minor collection adds the survivors to the tenured values,
+            // a major collection copies everything.
+            arena: if minor {
+                mem::take(&mut *tenured)
+            } else {
+                Arena::default()
+            },
+            nursery: if minor {
+                Some(arena.chunk_ranges())
+            } else {
+                None
+            },
+            tenuring: generational,
+            snapshot: snapshot.map(RefCell::new),
             phantom: PhantomData,
         };
         f(&tracer);
-        *arena = tracer.arena;
+        if minor {
+            for header in remembered {
+                tracer.trace_tenured_header(header);
+            }
+        }
+        // Weak references and interned strings must be updated while the old arena still holds the forwarding pointers.
+        self.weak.borrow_mut().adjust(&tracer);
+        self.strings.adjust(&tracer);
+        if generational {
+            *tenured = tracer.arena;
+            *arena = Arena::default();
+            if !minor {
+                self.generations.major_gc_done(tenured.allocated_bytes());
+            }
+        } else {
+            *arena = tracer.arena;
+        }
+        tracer.snapshot.map(RefCell::into_inner)
+    }
+
+    /// Whether the next garbage collection only copies the values allocated since
+    /// the previous one.
+    pub(crate) fn next_gc_is_minor(&self) -> bool {
+        self.generations.minor_gc(self.tenured.borrow().allocated_bytes())
+    }
+
+    /// Bytes allocated since the previous garbage collection, if generational collection
+    /// is enabled, otherwise all the bytes allocated.
+    pub(crate) fn young_allocated_bytes(&self) -> usize {
+        self.arena.borrow().allocated_bytes()
+    }
+
+    /// Collect garbage by generations from now on: values which survive a garbage
+    /// collection are tenured, and most collections only copy the values allocated since
+    /// the previous one. That requires the [`write_barrier`](Heap::write_barrier) to be
+    /// called whenever a reference is stored in an existing value.
+    pub(crate) fn enable_generational_gc(&self) {
+        self.generations.enable();
+    }
+
+    /// Record that `container` has been mutated to refer to other values, so a minor
+    /// garbage collection finds them even if `container` is tenured.
+    /// Must be called by native values which store a [`Value`] in themselves after they
+    /// were allocated, e.g. in a [`RefCell`], if generational collection is enabled with
+    /// [`Evaluator::enable_generational_gc`](crate::eval::Evaluator::enable_generational_gc).
+    /// Mutations through [`StarlarkValue::set_at`](crate::values::StarlarkValue::set_at) and
+    /// [`StarlarkValue::set_attr`](crate::values::StarlarkValue::set_attr) by Starlark code
+    /// are recorded by the evaluator.
+    #[inline]
+    pub fn write_barrier<'v>(&'v self, container: Value<'v>) {
+        if self.generations.is_enabled() {
+            if let Some(ptr) = container.0.unpack_ptr() {
+                if container.0.is_unfrozen() {
+                    self.generations.remember(ptr as *const AValueHeader as usize);
+                }
+            }
+        }
+    }
+
+    /// Obtain a weak reference to a value, which does not keep it alive during garbage
+    /// collection. Calling this twice with the same value gives equal [`WeakValue`]s.
+    pub fn weak<'v>(&'v self, value: Value<'v>) -> WeakValue<'v> {
+        WeakValue::new(self, self.weak.borrow_mut().slot(value))
+    }
+
+    pub(crate) fn weak_get<'v>(&'v self, slot: WeakSlot) -> Option<Value<'v>> {
+        self.weak.borrow().get(slot)
+    }
+
+    /// Number of values with weak references which are still alive.
+    pub fn weak_alive_count(&self) -> usize {
+        self.weak.borrow().alive()
     }
 
     /// Obtain a summary of how much memory is currently allocated by this heap.
     pub fn allocated_summary(&self) -> HeapSummary {
-        self.arena.borrow().allocated_summary()
+        let mut summary = self.tenured.borrow().allocated_summary();
+        summary.add(self.arena.borrow().allocated_summary());
+        summary
     }
 }
 
 /// Used to perform garbage collection by [`Trace::trace`](crate::values::Trace::trace).
 pub struct Tracer<'v> {
     arena: Arena,
+    /// During a minor collection, the memory of the values allocated since the previous
+    /// collection, sorted by address. Values elsewhere are tenured, and aren't moved.
+    nursery: Option<Vec<(usize, usize)>>,
+    /// Whether the values copied are tenured.
+    tenuring: bool,
+    /// Set when taking a heap snapshot, to record each value copied and each reference traced.
+    snapshot: Option<RefCell<SnapshotRecorder>>,
     phantom: PhantomData<&'v ()>,
 }
 
@@ -600,18 +837,88 @@ impl<'v> Tracer<'v> {
         unsafe { transmute!(Value, Value, Value::new_repr(&*v)) }
     }
 
+    /// Whether the values copied by this collection become tenured.
+    pub(crate) fn is_tenuring(&self) -> bool {
+        self.tenuring
+    }
+
+    /// Is the value at this address moved by this collection.
+    fn is_young(&self, address: usize) -> bool {
+        match &self.nursery {
+            None => true,
+            Some(nursery) => {
+                let i = nursery.partition_point(|(start, _)| *start <= address);
+                i > 0 && address < nursery[i - 1].1
+            }
+        }
+    }
+
+    /// Trace the references of a tenured value in place, during a minor collection.
+    /// Does nothing for values which are moved by this collection, as they are traced
+    /// when they are copied, if they are still alive.
+    pub(crate) fn trace_tenured(&self, value: Value<'v>) {
+        if value.0.is_unfrozen() {
+            let header = value.0.unpack_ptr().unwrap();
+            self.trace_tenured_header(header as *const AValueHeader as usize);
+        }
+    }
+
+    fn trace_tenured_header(&self, address: usize) {
+        if self.is_young(address) {
+            return;
+        }
+        let header = unsafe { &*(address as *const AValueHeader) };
+        match header.unpack_overwrite() {
+            Either::Left(_) => unreachable!("tenured values are not moved by a minor collection"),
+            Either::Right(v) => unsafe { v.heap_trace(address as *mut AValueHeader, self) },
+        }
+    }
+
+    /// Like `adjust`, but don't copy values which have not been copied already,
+    /// and return `None` for them, as nothing else refers to them.
+    pub(crate) fn adjust_weak(&self, value: Value<'v>) -> Option<Value<'v>> {
+        if !value.0.is_unfrozen() {
+            return Some(value);
+        }
+        let old_val = value.0.unpack_ptr().unwrap();
+        if !self.is_young(old_val as *const AValueHeader as usize) {
+            return Some(value);
+        }
+        match old_val.unpack_overwrite() {
+            Either::Left(x) => Some(Value::new_ptr_usize_with_str_tag(x)),
+            Either::Right(_) => None,
+        }
+    }
+
     fn adjust(&self, value: Value<'v>) -> Value<'v> {
         // Case 1, doesn't point at the old arena
         if !value.0.is_unfrozen() {
             return value;
         }
         let old_val = value.0.unpack_ptr().unwrap();
+        let address = old_val as *const AValueHeader as usize;
 
-        // Case 2: We have already been replaced with a forwarding, or need to freeze
+        // Case 2: Tenured, which a minor collection doesn't move
+        if !self.is_young(address) {
+            return value;
+        }
+
+        // Case 3: We have already been replaced with a forwarding, or need to freeze
         let res = match old_val.unpack_overwrite() {
-            Either::Left(x) => Value::new_ptr_usize_with_str_tag(x),
-            Either::Right(v) => unsafe {
-                v.heap_copy(old_val as *const AValueHeader as *mut AValueHeader, self)
+            Either::Left(x) => {
+                if let Some(snapshot) = &self.snapshot {
+                    snapshot.borrow_mut().reference(address);
+                }
+                Value::new_ptr_usize_with_str_tag(x)
+            }
+            Either::Right(v) => match &self.snapshot {
+                None => unsafe { v.heap_copy(address as *mut AValueHeader, self) },
+                Some(snapshot) => {
+                    snapshot.borrow_mut().copying(address, v);
+                    let res = unsafe { v.heap_copy(address as *mut AValueHeader, self) };
+                    snapshot.borrow_mut().copied();
+                    res
+                }
             },
         };
 
@@ -0,0 +1,193 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Interning of short strings, so that repeated attribute names, labels and the like
+//! share a single allocation, and comparing them is usually a pointer comparison.
+//!
+//! Strings are looked up by their Starlark hash, which is cached in each string, so a
+//! string which is then used as a dict key or attribute name is only hashed once.
+
+use std::{cell::RefCell, mem};
+
+use hashbrown::raw::RawTable;
+
+use crate::{
+    collections::{idhasher::mix_u32, SmallHashResult},
+    values::{layout::heap::Tracer, string::hash_string_result, FrozenValue, StringValue, Value},
+};
+
+/// Strings up to this many bytes are interned. Longer strings are rarely repeated,
+/// and hashing them on every allocation would cost more than sharing them saves.
+pub(crate) const MAX_INTERNED_STR_LEN: usize = 32;
+
+/// A heap interns at most this many strings at once, and allocates any more without
+/// interning them, so the table stays small even if garbage collection never runs.
+pub(crate) const MAX_INTERNED_STRS: usize = 1 << 16;
+
+fn promote_hash(x: SmallHashResult) -> u64 {
+    mix_u32(x.get())
+}
+
+/// The strings interned by a heap. The lifetime is erased, the heap keeps them valid.
+#[derive(Default)]
+struct StrTable(RefCell<RawTable<StringValue<'static>>>);
+
+impl StrTable {
+    fn intern<'v>(&self, x: &str, alloc: impl FnOnce() -> Value<'v>) -> Value<'v> {
+        debug_assert!(x.len() <= MAX_INTERNED_STR_LEN);
+        let hash = hash_string_result(x);
+        if let Some(s) = self.0.borrow().get(promote_hash(hash), |s| s.as_str() == x) {
+            return unsafe { transmute!(Value<'static>, Value<'v>, s.to_value()) };
+        }
+        let v = alloc();
+        let s = unsafe { StringValue::new_unchecked(v) };
+        s.cache_small_hash_result(hash);
+        let mut table = self.0.borrow_mut();
+        if table.len() < MAX_INTERNED_STRS {
+            table.insert(
+                promote_hash(hash),
+                unsafe { transmute!(StringValue<'v>, StringValue<'static>, s) },
+                |s| promote_hash(s.get_small_hash_result()),
+            );
+        }
+        v
+    }
+
+    /// Bytes used by the table, which count towards the heap's usage.
+    fn allocated_bytes(&self) -> usize {
+        let table = self.0.borrow();
+        if table.capacity() == 0 {
+            0
+        } else {
+            // Each bucket holds a pointer and a control byte.
+            table.buckets() * (mem::size_of::<StringValue>() + 1)
+        }
+    }
+}
+
+/// Strings interned in a [`FrozenHeap`](crate::values::FrozenHeap).
+#[derive(Default)]
+pub(crate) struct FrozenStrInterner(StrTable);
+
+impl FrozenStrInterner {
+    pub(crate) fn intern(&self, x: &str, alloc: impl FnOnce() -> FrozenValue) -> FrozenValue {
+        self.0
+            .intern(x, || alloc().to_value())
+            .unpack_frozen()
+            .unwrap()
+    }
+
+    pub(crate) fn allocated_bytes(&self) -> usize {
+        self.0.allocated_bytes()
+    }
+}
+
+/// Strings interned in a [`Heap`](crate::values::Heap). The table does not keep the
+/// strings alive: garbage collection drops the ones nothing else refers to.
+#[derive(Default)]
+pub(crate) struct StrInterner(StrTable);
+
+impl StrInterner {
+    pub(crate) fn intern<'v>(&self, x: &str, alloc: impl FnOnce() -> Value<'v>) -> Value<'v> {
+        self.0.intern(x, alloc)
+    }
+
+    pub(crate) fn allocated_bytes(&self) -> usize {
+        self.0.allocated_bytes()
+    }
+
+    /// Called at the end of the tracing phase of garbage collection, before the old
+    /// arena is released. The table is rebuilt to fit the strings which survived.
+    pub(crate) fn adjust<'v>(&self, tracer: &Tracer<'v>) {
+        let mut table = self.0.0.borrow_mut();
+        let kept: Vec<_> = unsafe {
+            table
+                .iter()
+                .filter_map(|s| {
+                    let v = transmute!(Value<'static>, Value<'v>, s.as_ref().to_value());
+                    let s = StringValue::new_unchecked(tracer.adjust_weak(v)?);
+                    Some(transmute!(StringValue<'v>, StringValue<'static>, s))
+                })
+                .collect()
+        };
+        // Only hash the strings once they have moved, as the old ones are overwritten.
+        *table = RawTable::with_capacity(kept.len());
+        for s in kept {
+            let hash = promote_hash(s.get_small_hash_result());
+            table.insert(hash, s, |s| promote_hash(s.get_small_hash_result()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem;
+
+    use crate::values::{layout::interner::MAX_INTERNED_STRS, FrozenHeap, Heap};
+
+    #[test]
+    fn test_intern_short_strings() {
+        let heap = Heap::new();
+        let a = heap.alloc("name");
+        assert!(a.ptr_eq(heap.alloc("name")));
+        assert!(!a.ptr_eq(heap.alloc("other")));
+        let long = "x".repeat(100);
+        assert!(!heap.alloc(long.as_str()).ptr_eq(heap.alloc(long.as_str())));
+
+        let frozen_heap = FrozenHeap::new();
+        let f = frozen_heap.alloc("name");
+        assert!(f.to_value().ptr_eq(frozen_heap.alloc("name").to_value()));
+    }
+
+    #[test]
+    fn test_intern_across_gc() {
+        let heap = Heap::new();
+        let mut a = heap.alloc("kept");
+        heap.alloc("dropped");
+        unsafe { heap.garbage_collect(|tracer| tracer.trace(&mut a)) };
+        assert!(a.ptr_eq(heap.alloc("kept")));
+        assert_eq!("dropped", heap.alloc("dropped").unpack_str().unwrap());
+    }
+
+    #[test]
+    fn test_intern_allocated_bytes() {
+        // The same strings, interned or not, so only the table differs.
+        let interned = Heap::new();
+        let uninterned = Heap::new();
+        for i in 0..1000 {
+            interned.alloc(format!("x{}", i).as_str());
+            uninterned.alloc_str_concat("x", &i.to_string());
+        }
+        assert!(
+            interned.allocated_bytes()
+                >= uninterned.allocated_bytes() + 1000 * mem::size_of::<usize>()
+        );
+    }
+
+    #[test]
+    fn test_intern_limit() {
+        let heap = Heap::new();
+        let first = heap.alloc("0");
+        for i in 1..MAX_INTERNED_STRS {
+            heap.alloc(i.to_string().as_str());
+        }
+        // Once the table is full, strings are no longer interned, but those already
+        // interned still are.
+        assert!(!heap.alloc("full").ptr_eq(heap.alloc("full")));
+        assert!(first.ptr_eq(heap.alloc("0")));
+    }
+}
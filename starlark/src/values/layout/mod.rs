@@ -18,19 +18,26 @@
 // Possible optimisations:
 // Encoding none, bool etc in the pointer of frozen value
 
+pub use arena::HeapSummary;
 pub(crate) use constant::StringValueLike;
 pub use constant::{FrozenStringValue, StarlarkStrNRepr, StringValue};
+pub(crate) use heap::HeapError;
 pub use heap::{Freezer, FrozenHeap, FrozenHeapRef, Heap, Tracer};
 pub(crate) use pointer_i32::PointerI32;
 pub use value::{FrozenValue, Value, ValueIdentity};
 pub(crate) use value_captured::*;
+pub use weak::WeakValue;
 
 mod arena;
 mod avalue;
 mod constant;
+mod generation;
 mod heap;
+mod interner;
 mod pointer;
 mod pointer_i32;
+mod snapshot;
 pub(crate) mod typed;
 mod value;
 mod value_captured;
+mod weak;
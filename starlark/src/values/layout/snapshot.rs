@@ -0,0 +1,94 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Recording a [`HeapSnapshot`] of the live values on a [`Heap`](crate::values::Heap).
+//!
+//! A value can only list the values it refers to by tracing them, which moves them,
+//! so a snapshot is recorded by the [`Tracer`](crate::values::Tracer) during a garbage
+//! collection: each value copied becomes a node, and each reference traced an edge.
+
+use std::collections::HashMap;
+
+use crate::{
+    eval::{HeapSnapshot, HeapSnapshotNode},
+    values::layout::avalue::AValueDyn,
+};
+
+/// Records a [`HeapSnapshot`] while the [`Tracer`](crate::values::Tracer) copies values.
+pub(crate) struct SnapshotRecorder {
+    nodes: Vec<HeapSnapshotNode>,
+    /// The address each value had before garbage collection.
+    addresses: Vec<usize>,
+    /// The node of each value copied so far, by its address before garbage collection.
+    ids: HashMap<usize, usize>,
+    /// The nodes being copied, whose references are being traced.
+    parents: Vec<usize>,
+}
+
+impl SnapshotRecorder {
+    pub(crate) fn new() -> Self {
+        Self {
+            nodes: vec![HeapSnapshotNode {
+                typ: HeapSnapshot::ROOTS.to_owned(),
+                bytes: 0,
+                references: Vec::new(),
+                location: None,
+            }],
+            addresses: vec![0],
+            ids: HashMap::new(),
+            parents: vec![0],
+        }
+    }
+
+    /// Record a reference to a value which has already been copied.
+    pub(crate) fn reference(&mut self, address: usize) {
+        if let Some(id) = self.ids.get(&address) {
+            let parent = *self.parents.last().unwrap();
+            self.nodes[parent].references.push(*id);
+        }
+    }
+
+    /// Record a reference to a value which is about to be copied. Must be followed by
+    /// [`copied`](SnapshotRecorder::copied) once its references have been traced.
+    pub(crate) fn copying<'v>(&mut self, address: usize, value: &dyn AValueDyn<'v>) {
+        let id = self.nodes.len();
+        self.nodes.push(HeapSnapshotNode {
+            typ: value.get_type().to_owned(),
+            bytes: value.total_memory(),
+            references: Vec::new(),
+            location: None,
+        });
+        self.addresses.push(address);
+        self.ids.insert(address, id);
+        self.reference(address);
+        self.parents.push(id);
+    }
+
+    pub(crate) fn copied(&mut self) {
+        self.parents.pop();
+    }
+
+    /// Finish recording, given where each value was allocated, by its address before
+    /// garbage collection.
+    pub(crate) fn finish(self, locations: &HashMap<usize, String>) -> HeapSnapshot {
+        let mut nodes = self.nodes;
+        for (node, address) in nodes.iter_mut().zip(self.addresses) {
+            node.location = locations.get(&address).cloned();
+        }
+        HeapSnapshot { nodes }
+    }
+}
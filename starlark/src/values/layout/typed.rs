@@ -23,6 +23,7 @@ use std::{
 };
 
 use gazebo::{cast, prelude::*};
+use serde::{Serialize, Serializer};
 
 use crate::{
     gazebo::any::AnyLifetime,
@@ -77,6 +78,34 @@ impl<'v, T: StarlarkValue<'v>> Display for FrozenValueTyped<'v, T> {
     }
 }
 
+impl<'v, T: StarlarkValue<'v>> PartialEq for ValueTyped<'v, T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl<'v, T: StarlarkValue<'v>> Eq for ValueTyped<'v, T> {}
+
+impl<'v, T: StarlarkValue<'v>> PartialEq for FrozenValueTyped<'v, T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl<'v, T: StarlarkValue<'v>> Eq for FrozenValueTyped<'v, T> {}
+
+impl<'v, T: StarlarkValue<'v>> From<ValueTyped<'v, T>> for Value<'v> {
+    fn from(x: ValueTyped<'v, T>) -> Self {
+        x.0
+    }
+}
+
+impl<'v, T: StarlarkValue<'v>> From<FrozenValueTyped<'v, T>> for FrozenValue {
+    fn from(x: FrozenValueTyped<'v, T>) -> Self {
+        x.0
+    }
+}
+
 impl<'v, T: StarlarkValue<'v>> ValueTyped<'v, T> {
     /// Downcast.
     pub fn new(value: Value<'v>) -> Option<ValueTyped<'v, T>> {
@@ -204,6 +233,16 @@ impl<'v, T: StarlarkValue<'v>> UnpackValue<'v> for ValueTyped<'v, T> {
     }
 }
 
+impl<'v, T: StarlarkValue<'v>> UnpackValue<'v> for FrozenValueTyped<'v, T> {
+    fn expected() -> String {
+        format!("frozen {}", T::get_type_value_static().as_str())
+    }
+
+    fn unpack_value(value: Value<'v>) -> Option<Self> {
+        FrozenValueTyped::new(value.unpack_frozen()?)
+    }
+}
+
 impl<'v, T: StarlarkValue<'v>> AllocValue<'v> for ValueTyped<'v, T> {
     fn alloc_value(self, _heap: &'v Heap) -> Value<'v> {
         self.0
@@ -222,13 +261,56 @@ impl<'v, T: StarlarkValue<'v>> AllocFrozenValue for FrozenValueTyped<'v, T> {
     }
 }
 
+impl<'v, T: StarlarkValue<'v>> Serialize for ValueTyped<'v, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'v, T: StarlarkValue<'v>> Serialize for FrozenValueTyped<'v, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use crate::values::{FrozenValue, FrozenValueTyped, PointerI32, StarlarkValue};
+    use crate::values::{
+        list::{FrozenList, List},
+        FrozenHeap, FrozenValue, FrozenValueTyped, Heap, PointerI32, StarlarkValue, UnpackValue,
+        Value, ValueTyped,
+    };
 
     #[test]
     fn int() {
         let v = FrozenValueTyped::<PointerI32>::new(FrozenValue::new_int(17)).unwrap();
         assert_eq!(17, v.as_ref().to_int().unwrap());
     }
+
+    #[test]
+    fn typed_in_collections() {
+        let heap = Heap::new();
+        let lists: Vec<ValueTyped<List>> = vec![
+            ValueTyped::new(heap.alloc_list(&[])).unwrap(),
+            ValueTyped::new(heap.alloc_list(&[])).unwrap(),
+        ];
+        lists[0].push(heap.alloc(1), &heap);
+        assert_eq!(1, lists[0].len());
+        assert_eq!(0, lists[1].len());
+        assert!(lists[0] != lists[1]);
+        let value: Value = lists[1].into();
+        assert_eq!(Some(lists[1]), ValueTyped::<List>::unpack_value(value));
+        assert_eq!("[[1],[]]", serde_json::to_string(&lists).unwrap());
+    }
+
+    #[test]
+    fn frozen_unpack() {
+        let heap = Heap::new();
+        let frozen_heap = FrozenHeap::new();
+        let frozen = frozen_heap.alloc_list(&[]).to_value();
+        let unfrozen = heap.alloc_list(&[]);
+        assert!(FrozenValueTyped::<FrozenList>::unpack_value(frozen).is_some());
+        assert!(FrozenValueTyped::<FrozenList>::unpack_value(unfrozen).is_none());
+        assert!(FrozenValueTyped::<PointerI32>::unpack_value(frozen).is_none());
+    }
 }
@@ -52,7 +52,7 @@ use crate::{
             pointer_i32::PointerI32,
         },
         num::Num,
-        string::StarlarkStr,
+        string::{rope::StarlarkRope, StarlarkStr},
         UnpackValue,
     },
 };
@@ -178,6 +178,17 @@ impl<'v> Value<'v> {
     /// Unstable and likely to be removed in future, as the presence of the `Box` is
     /// not a guaranteed part of the API.
     pub fn unpack_starlark_str(self) -> Option<&'v StarlarkStr> {
+        match self.unpack_flat_starlark_str() {
+            Some(s) => Some(s),
+            None => self
+                .unpack_rope()
+                .map(|rope| rope.flatten().unpack_starlark_str()),
+        }
+    }
+
+    /// Like [`unpack_starlark_str`](Value::unpack_starlark_str), but [`None`] for a rope,
+    /// so never allocates.
+    pub(crate) fn unpack_flat_starlark_str(self) -> Option<&'v StarlarkStr> {
         if self.is_str() {
             unsafe {
                 Some(
@@ -199,6 +210,17 @@ impl<'v> Value<'v> {
         self.unpack_starlark_str().map(|s| s.unpack())
     }
 
+    /// Obtain the rope if this is a string built by concatenation, which may not have
+    /// been flattened yet.
+    pub(crate) fn unpack_rope(self) -> Option<&'v StarlarkRope<'v>> {
+        // Ropes are only allocated on the heap, never frozen.
+        if self.0.is_unfrozen() && !self.is_str() {
+            self.get_ref().downcast_ref::<StarlarkRope<'v>>()
+        } else {
+            None
+        }
+    }
+
     /// Get a pointer to a [`AValue`].
     pub(crate) fn get_ref(self) -> &'v dyn AValueDyn<'v> {
         match self.0.unpack() {
@@ -231,6 +253,8 @@ impl<'v> Value<'v> {
     ///    compare equal.
     /// 2. If two [`Value]` have [`ValueIdentity`]  that compare equal, then [`Value::ptr_eq`] and
     ///    [`Value::equals`]  will also consider them to be equal.
+    ///
+    /// For an identity which survives GC, use [`Heap::weak`](crate::values::Heap::weak).
     pub fn identity(self) -> ValueIdentity<'v> {
         ValueIdentity {
             identity: self.0.ptr_value(),
@@ -0,0 +1,214 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Weak references to values on a [`Heap`].
+//!
+//! Garbage collection moves values, so neither a [`Value`] nor its
+//! [`ValueIdentity`] can be kept across a collection. A [`WeakValue`] can: it names
+//! a slot in a table owned by the heap, which garbage collection rewrites to point
+//! at the moved value, or clears if the value was not reachable. Cleared slots are
+//! reused, with a new generation, so a [`WeakValue`] to the dead value doesn't start
+//! pointing at the unrelated value stored in the slot next.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    fmt::{Debug, Formatter},
+    hash::{Hash, Hasher},
+    ptr,
+};
+
+use gazebo::prelude::*;
+
+use crate::values::{
+    layout::heap::{Heap, Tracer},
+    Value, ValueIdentity,
+};
+
+/// A slot in a [`WeakTable`], and the generation of the slot when it was handed out.
+#[derive(Copy, Clone, Dupe, PartialEq, Eq, Hash)]
+pub(crate) struct WeakSlot {
+    index: usize,
+    generation: u32,
+}
+
+/// The weak slots of a [`Heap`]. A slot is reused once its value dies, with its generation
+/// incremented, so that a [`WeakValue`] to the dead value can't start pointing at an
+/// unrelated live one.
+#[derive(Default)]
+pub(crate) struct WeakTable {
+    slots: Vec<(Option<Value<'static>>, u32)>,
+    /// Slots whose value died, to be reused.
+    free: Vec<usize>,
+    /// Slot of each live value, so asking twice for the same value gives the same slot.
+    index: HashMap<ValueIdentity<'static>, usize>,
+}
+
+impl WeakTable {
+    pub(crate) fn slot<'v>(&mut self, value: Value<'v>) -> WeakSlot {
+        // Values are only stored while their heap is alive, and only handed out again as `Value<'v>`.
+        let value = unsafe { transmute!(Value<'v>, Value<'static>, value) };
+        let Self { slots, free, index } = self;
+        let index = *index.entry(value.identity()).or_insert_with(|| match free.pop() {
+            Some(i) => {
+                slots[i].0 = Some(value);
+                i
+            }
+            None => {
+                slots.push((Some(value), 0));
+                slots.len() - 1
+            }
+        });
+        WeakSlot {
+            index,
+            generation: self.slots[index].1,
+        }
+    }
+
+    pub(crate) fn get<'v>(&self, slot: WeakSlot) -> Option<Value<'v>> {
+        match self.slots[slot.index] {
+            (Some(x), generation) if generation == slot.generation => {
+                Some(unsafe { transmute!(Value<'static>, Value<'v>, x) })
+            }
+            _ => None,
+        }
+    }
+
+    /// Number of slots whose value is still alive.
+    pub(crate) fn alive(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Called at the end of the tracing phase of garbage collection, before the old
+    /// arena is released.
+    pub(crate) fn adjust<'v>(&mut self, tracer: &Tracer<'v>) {
+        self.index.clear();
+        for (i, (slot, generation)) in self.slots.iter_mut().enumerate() {
+            if let Some(x) = *slot {
+                let x = unsafe { transmute!(Value<'static>, Value<'v>, x) };
+                *slot = tracer
+                    .adjust_weak(x)
+                    .map(|x| unsafe { transmute!(Value<'v>, Value<'static>, x) });
+                match *slot {
+                    Some(x) => {
+                        self.index.insert(x.identity(), i);
+                    }
+                    None => {
+                        *generation = generation.wrapping_add(1);
+                        self.free.push(i);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A weak reference to a [`Value`], obtained with [`Heap::weak`].
+///
+/// A [`WeakValue`] does not keep its value alive. After a garbage collection in which
+/// the value was unreachable, [`get`](WeakValue::get) returns [`None`]; otherwise it
+/// returns the value, wherever it has moved to.
+///
+/// Asking for a weak reference to the same value twice gives equal [`WeakValue`]s, and
+/// that remains true across garbage collections, so a [`WeakValue`] can be used as the key
+/// of a side table keyed by value identity, e.g. `HashMap<WeakValue, T>`. Entries
+/// for dead values can be dropped with `retain(|k, _| k.is_alive())`.
+#[derive(Copy, Clone, Dupe)]
+pub struct WeakValue<'v> {
+    heap: &'v Heap,
+    slot: WeakSlot,
+}
+
+impl<'v> WeakValue<'v> {
+    pub(crate) fn new(heap: &'v Heap, slot: WeakSlot) -> Self {
+        Self { heap, slot }
+    }
+
+    /// The value, if it has not been garbage collected.
+    pub fn get(self) -> Option<Value<'v>> {
+        self.heap.weak_get(self.slot)
+    }
+
+    /// Whether the value is still alive.
+    pub fn is_alive(self) -> bool {
+        self.get().is_some()
+    }
+}
+
+impl PartialEq for WeakValue<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        ptr::eq(self.heap, other.heap) && self.slot == other.slot
+    }
+}
+
+impl Eq for WeakValue<'_> {}
+
+impl Hash for WeakValue<'_> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        ptr::hash(self.heap, state);
+        self.slot.hash(state);
+    }
+}
+
+impl Debug for WeakValue<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("WeakValue").field(&self.get()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::values::{Heap, Value};
+
+    #[test]
+    fn test_weak_across_gc() {
+        let heap = Heap::new();
+        let mut live = heap.alloc(vec![1, 2]);
+        let dead = heap.alloc(vec![3]);
+        let live_weak = heap.weak(live);
+        let dead_weak = heap.weak(dead);
+        let int_weak = heap.weak(Value::new_int(3));
+        assert_eq!(live_weak, heap.weak(live));
+        assert_ne!(live_weak, dead_weak);
+
+        let mut table = HashMap::new();
+        table.insert(live_weak, "live");
+        table.insert(dead_weak, "dead");
+
+        unsafe { heap.garbage_collect(|tracer| tracer.trace(&mut live)) };
+
+        assert!(live_weak.get().unwrap().ptr_eq(live));
+        assert_eq!("[1, 2]", live_weak.get().unwrap().to_str());
+        assert!(!dead_weak.is_alive());
+        assert_eq!(Some(3), int_weak.get().and_then(|x| x.unpack_int()));
+        assert_eq!(live_weak, heap.weak(live));
+        assert_eq!(2, heap.weak_alive_count());
+
+        table.retain(|k, _| k.is_alive());
+        assert_eq!(vec!["live"], table.into_values().collect::<Vec<_>>());
+
+        // The slot of the dead value is reused, but not by its weak reference.
+        let other = heap.alloc(vec![4]);
+        let other_weak = heap.weak(other);
+        assert_eq!(dead_weak.slot.index, other_weak.slot.index);
+        assert_ne!(dead_weak, other_weak);
+        assert!(!dead_weak.is_alive());
+        assert!(other_weak.get().unwrap().ptr_eq(other));
+    }
+}
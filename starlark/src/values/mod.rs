@@ -38,18 +38,22 @@ use std::{
 use gazebo::coerce::CoerceKey;
 pub use gazebo::{any::AnyLifetime, cell::ARef, coerce::Coerce, prelude::*};
 use indexmap::Equivalent;
-pub use starlark_derive::{starlark_attrs, Freeze, StarlarkAttrs, Trace};
+pub use starlark_derive::{
+    starlark_attrs, Freeze, StarlarkAttrs, StarlarkEnum, Trace, UnpackValue,
+};
 use types::unbound::MaybeUnboundValue;
 
 pub use crate::values::{
-    alloc_value::*, error::*, freeze::*, frozen_ref::*, layout::*, owned::*, trace::*, traits::*,
-    typed::*, types::*, unpack::*,
+    alloc_value::*, deserialize::*, error::*, freeze::*, frozen_ref::*, layout::*, owned::*,
+    trace::*, traits::*, typed::*, types::*, unpack::*,
 };
 use crate::{
     codemap::Span,
     collections::{Hashed, StarlarkHasher},
     eval::{Arguments, Evaluator, FrozenDef},
     values::{
+        bigint::StarlarkBigInt,
+        bytes::StarlarkBytes,
         dict::FrozenDict,
         docs::DocItem,
         enumeration::{EnumType, FrozenEnumValue},
@@ -57,6 +61,7 @@ use crate::{
         function::{FrozenBoundMethod, NativeFunction, FUNCTION_TYPE},
         record::FrozenRecord,
         recursive_repr_guard::{repr_stack_push, ReprStackReleaseMemoryOnDrop},
+        string::rope::StarlarkRope,
         structs::FrozenStruct,
         tuple::FrozenTuple,
         types::{list::FrozenList, range::Range, record::RecordType},
@@ -69,6 +74,8 @@ mod comparison;
 // Submodules
 mod alloc_value;
 pub(crate) mod basic;
+mod deep_copy;
+mod deserialize;
 pub mod display;
 pub mod docs;
 mod error;
@@ -80,7 +87,8 @@ mod layout;
 pub(crate) mod num;
 mod owned;
 pub(crate) mod recursive_repr_guard;
-mod stack_guard;
+mod serialize;
+pub(crate) mod stack_guard;
 mod trace;
 mod traits;
 pub(crate) mod types;
@@ -327,7 +335,9 @@ impl FrozenValue {
             || self.is_str()
             || self.unpack_bool().is_some()
             || self.unpack_int().is_some()
+            || FrozenValueTyped::<StarlarkBigInt>::new(self).is_some()
             || FrozenValueTyped::<StarlarkFloat>::new(self).is_some()
+            || FrozenValueTyped::<StarlarkBytes>::new(self).is_some()
             || FrozenList::from_frozen_value(&self).is_some()
             || FrozenDict::from_frozen_value(&self).is_some()
             || FrozenValueTyped::<FrozenTuple>::new(self).is_some()
@@ -359,7 +369,9 @@ impl<'v> Value<'v> {
     /// before falling back to [`add`](StarlarkValue::add).
     pub fn add(self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
         let me = self.to_value();
-        if let Some(v) = other.get_ref().radd(me, heap) {
+        if let Some(v) = StarlarkRope::concat(me, other, heap) {
+            Ok(v)
+        } else if let Some(v) = other.get_ref().radd(me, heap) {
             v
         } else {
             self.get_ref().add(other, heap)
@@ -391,6 +403,29 @@ impl<'v> Value<'v> {
         s
     }
 
+    /// A short description of the value for debuggers and error messages, see
+    /// [`StarlarkValue::debug_summary`]. Falls back to the `repr`.
+    pub fn debug_summary(self) -> String {
+        match self.get_ref().debug_summary() {
+            Some(s) => s,
+            None => self.to_repr(),
+        }
+    }
+
+    /// Like [`debug_summary`](Value::debug_summary), but falls back to the `str`,
+    /// for error messages which show strings without quotes.
+    pub(crate) fn debug_summary_or_str(self) -> String {
+        match self.get_ref().debug_summary() {
+            Some(s) => s,
+            None => self.to_str(),
+        }
+    }
+
+    /// Forwards to [`StarlarkValue::debug_children`].
+    pub fn debug_children(self) -> Vec<(String, Value<'v>)> {
+        self.get_ref().debug_children()
+    }
+
     pub fn to_json(self) -> anyhow::Result<String> {
         let mut s = String::new();
         self.collect_json(&mut s)?;
@@ -598,20 +633,20 @@ impl<'v> Value<'v> {
         self.get_ref().floor_div(other, heap)
     }
 
-    pub fn bit_and(self, other: Value<'v>) -> anyhow::Result<Value<'v>> {
-        self.get_ref().bit_and(other)
+    pub fn bit_and(self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        self.get_ref().bit_and(other, heap)
     }
-    pub fn bit_or(self, other: Value<'v>) -> anyhow::Result<Value<'v>> {
-        self.get_ref().bit_or(other)
+    pub fn bit_or(self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        self.get_ref().bit_or(other, heap)
     }
-    pub fn bit_xor(self, other: Value<'v>) -> anyhow::Result<Value<'v>> {
-        self.get_ref().bit_xor(other)
+    pub fn bit_xor(self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        self.get_ref().bit_xor(other, heap)
     }
-    pub fn left_shift(self, other: Value<'v>) -> anyhow::Result<Value<'v>> {
-        self.get_ref().left_shift(other)
+    pub fn left_shift(self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        self.get_ref().left_shift(other, heap)
     }
-    pub fn right_shift(self, other: Value<'v>) -> anyhow::Result<Value<'v>> {
-        self.get_ref().right_shift(other)
+    pub fn right_shift(self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        self.get_ref().right_shift(other, heap)
     }
 
     pub fn invoke(
@@ -19,9 +19,13 @@ use std::{fmt, fmt::Display, ops::Deref};
 
 use gazebo::prelude::*;
 
-use crate::values::{
-    none::NoneType, AllocFrozenValue, FrozenHeap, FrozenHeapRef, FrozenValue, FrozenValueTyped,
-    StarlarkValue, Value,
+use crate::{
+    environment::Module,
+    eval::Evaluator,
+    values::{
+        none::NoneType, AllocFrozenValue, FrozenHeap, FrozenHeapRef, FrozenValue, FrozenValueTyped,
+        StarlarkValue, Value,
+    },
 };
 
 /// A [`FrozenValue`] along with a [`FrozenHeapRef`] that ensures it is kept alive.
@@ -32,6 +36,14 @@ use crate::values::{
 /// [`unchecked_frozen_value`](OwnedFrozenValue::unchecked_frozen_value), that approach
 /// is strongly discouraged. See the other methods which unpack the code, access it as a
 /// [`Value`] (which has a suitable lifetime) or add references to other heaps.
+///
+/// [`OwnedFrozenValue`] is [`Send`] and [`Sync`], as is
+/// [`FrozenModule`](crate::environment::FrozenModule), so the results of an evaluation can
+/// be shared between threads. To use the value on another thread, pass it to
+/// [`owned_value`](OwnedFrozenValue::owned_value) with the
+/// [`frozen_heap`](crate::environment::Module::frozen_heap) of a [`Module`] on that
+/// thread, or call it with [`call`](OwnedFrozenValue::call). Frozen functions may be called
+/// from several threads at once, provided each call uses its own [`Module`].
 #[derive(Debug, Clone, Dupe)]
 pub struct OwnedFrozenValue {
     owner: FrozenHeapRef,
@@ -115,6 +127,30 @@ impl OwnedFrozenValue {
         unsafe { self.owned_frozen_value(heap).to_value() }
     }
 
+    /// Call this value, which should be a function, with a new [`Module`] for the values
+    /// allocated by the call, returning the result frozen. Any thread may do so, and several
+    /// threads can call the same function at the same time, since each has its own heap.
+    /// To keep more of the call's values, or set options on the [`Evaluator`], use
+    /// [`owned_value`](OwnedFrozenValue::owned_value) with a [`Module`] of your own.
+    pub fn call(
+        &self,
+        positional: &[OwnedFrozenValue],
+        named: &[(&str, OwnedFrozenValue)],
+    ) -> anyhow::Result<OwnedFrozenValue> {
+        const RESULT: &str = "result";
+
+        let module = Module::new();
+        let mut eval = Evaluator::new(&module);
+        let heap = module.frozen_heap();
+        let function = self.owned_value(heap);
+        let positional = positional.map(|x| x.owned_value(heap));
+        let named = named.map(|(name, x)| (*name, x.owned_value(heap)));
+        let res = eval.eval_function(function, &positional, &named)?;
+        drop(eval);
+        module.set(RESULT, res);
+        Ok(module.freeze()?.get(RESULT).unwrap())
+    }
+
     /// Operate on the [`FrozenValue`] stored inside.
     /// Safe provided you don't store the argument [`FrozenValue`] after the closure has returned.
     /// Using this function is discouraged when possible.
@@ -240,3 +276,59 @@ impl<T: StarlarkValue<'static>> OwnedFrozenValueTyped<T> {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::{
+        environment::{FrozenModule, Globals},
+        syntax::{AstModule, Dialect},
+    };
+
+    #[test]
+    fn test_send_sync()
+    where
+        OwnedFrozenValue: Send + Sync,
+    {
+    }
+
+    #[test]
+    fn test_call_concurrently() {
+        let module = Module::new();
+        let mut eval = Evaluator::new(&module);
+        let program = "def f(x, suffix = ''):\n  return [str(i) + suffix for i in range(x)]";
+        eval.eval_module(
+            AstModule::parse("f.star", program.to_owned(), &Dialect::Standard).unwrap(),
+            &Globals::standard(),
+        )
+        .unwrap();
+        drop(eval);
+        let module: FrozenModule = module.freeze().unwrap();
+        let f = module.get("f").unwrap();
+
+        let threads = (0..4)
+            .map(|i| {
+                let f = f.dupe();
+                thread::spawn(move || {
+                    f.call(
+                        &[OwnedFrozenValue::alloc(i)],
+                        &[("suffix", OwnedFrozenValue::alloc("!"))],
+                    )
+                    .unwrap()
+                })
+            })
+            .collect::<Vec<_>>();
+        for (i, thread) in threads.into_iter().enumerate() {
+            let res = thread.join().unwrap();
+            assert_eq!(res.value().length().unwrap(), i as i32);
+        }
+        assert_eq!(
+            f.call(&[OwnedFrozenValue::alloc(2)], &[])
+                .unwrap()
+                .to_string(),
+            r#"["0", "1"]"#
+        );
+    }
+}
@@ -0,0 +1,138 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Implementations of [`Serialize`] for [`Value`] and [`FrozenValue`], so the results of
+//! evaluation can be written out with any `serde` serializer, e.g. as JSON or YAML.
+//!
+//! Lists, tuples and sets are serialized as sequences, dicts and structs as maps, and the
+//! other builtin types as the corresponding primitive. Integers too big for 64 bits are
+//! serialized as decimal strings, as most formats can't hold them. Any other type is
+//! serialized via its
+//! [`collect_json`](crate::values::StarlarkValue::collect_json), so implementing that is
+//! enough to make a custom type serializable.
+
+use serde::{ser::Error, Serialize, Serializer};
+
+use crate::values::{
+    bigint::StarlarkBigInt, bytes::StarlarkBytes, dict::Dict, float::StarlarkFloat, list::List,
+    set::Set, stack_guard, structs::Struct, tuple::Tuple, FrozenValue, Value, ValueLike,
+};
+
+impl Serialize for Value<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // Values may be cyclic, or just very deep.
+        let _guard = stack_guard::stack_guard().map_err(S::Error::custom)?;
+        let x = *self;
+        if x.is_none() {
+            serializer.serialize_unit()
+        } else if let Some(b) = x.unpack_bool() {
+            serializer.serialize_bool(b)
+        } else if let Some(i) = x.unpack_int() {
+            serializer.serialize_i32(i)
+        } else if let Some(b) = x.downcast_ref::<StarlarkBigInt>() {
+            let b = b.get();
+            if let Ok(i) = i64::try_from(b) {
+                serializer.serialize_i64(i)
+            } else if let Ok(i) = u64::try_from(b) {
+                serializer.serialize_u64(i)
+            } else {
+                serializer.serialize_str(&b.to_string())
+            }
+        } else if let Some(f) = x.downcast_ref::<StarlarkFloat>() {
+            serializer.serialize_f64(f.0)
+        } else if let Some(s) = x.unpack_str() {
+            serializer.serialize_str(s)
+        } else if let Some(b) = x.downcast_ref::<StarlarkBytes>() {
+            serializer.serialize_bytes(b.as_bytes())
+        } else if let Some(xs) = List::from_value(x) {
+            serializer.collect_seq(xs.iter())
+        } else if let Some(xs) = Tuple::from_value(x) {
+            serializer.collect_seq(xs.iter())
+        } else if let Some(xs) = Set::from_value(x) {
+            serializer.collect_seq(xs.iter())
+        } else if let Some(d) = Dict::from_value(x) {
+            serializer.collect_map(d.iter())
+        } else if let Some(s) = Struct::from_value(x) {
+            serializer.collect_map(s.fields.iter().map(|(k, v)| (k.as_str(), *v)))
+        } else {
+            let json = x.to_json().map_err(S::Error::custom)?;
+            let json: serde_json::Value = serde_json::from_str(&json).map_err(S::Error::custom)?;
+            json.serialize(serializer)
+        }
+    }
+}
+
+impl Serialize for FrozenValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_value().serialize(serializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        environment::{Globals, Module},
+        eval::Evaluator,
+        syntax::{AstModule, Dialect},
+    };
+
+    fn to_json(program: &str) -> Result<String, serde_json::Error> {
+        let module = Module::new();
+        let mut eval = Evaluator::new(&module);
+        let ast = AstModule::parse("test.star", program.to_owned(), &Dialect::Extended).unwrap();
+        let res = eval.eval_module(ast, &Globals::extended()).unwrap();
+        serde_json::to_string(&res)
+    }
+
+    #[test]
+    fn test_serialize() {
+        assert_eq!(
+            to_json("[None, True, 1, 2.5, 'x', (1,), {'a': [1]}]").unwrap(),
+            r#"[null,true,1,2.5,"x",[1],{"a":[1]}]"#
+        );
+        assert_eq!(
+            to_json("struct(a = 1, b = struct(c = 'd'))").unwrap(),
+            r#"{"a":1,"b":{"c":"d"}}"#
+        );
+        assert_eq!(to_json("{1: 2}").unwrap(), r#"{"1":2}"#);
+        assert_eq!(to_json("set([1, 2])").unwrap(), "[1,2]");
+        assert_eq!(to_json("b'ab'").unwrap(), "[97,98]");
+        // Other types go via their JSON representation.
+        assert_eq!(
+            to_json("record(a = int.type)(a = 1)").unwrap(),
+            r#"{"a":1}"#
+        );
+    }
+
+    #[test]
+    fn test_serialize_bigint() {
+        assert_eq!(to_json("1 << 40").unwrap(), "1099511627776");
+        assert_eq!(to_json("-(1 << 63)").unwrap(), "-9223372036854775808");
+        assert_eq!(to_json("(1 << 64) - 1").unwrap(), "18446744073709551615");
+        // Too big for any JSON number to hold exactly.
+        assert_eq!(
+            to_json("1 << 100").unwrap(),
+            r#""1267650600228229401496703205376""#
+        );
+    }
+
+    #[test]
+    fn test_serialize_fails() {
+        assert!(to_json("x = []; x.append(x); x").is_err());
+        assert!(to_json("len").is_err());
+    }
+}
@@ -24,10 +24,10 @@ use crate::values::ControlError;
 // Maximum recursion level for comparison
 // TODO(dmarting): those are rather short, maybe make it configurable?
 #[cfg(debug_assertions)]
-const MAX_RECURSION: u32 = 200;
+pub(crate) const MAX_RECURSION: u32 = 200;
 
 #[cfg(not(debug_assertions))]
-const MAX_RECURSION: u32 = 3000;
+pub(crate) const MAX_RECURSION: u32 = 3000;
 
 // A thread-local counter is used to detect too deep recursion.
 //
@@ -133,6 +133,10 @@ unsafe impl<'v> Trace<'v> for bool {
     fn trace(&mut self, _tracer: &Tracer<'v>) {}
 }
 
+unsafe impl<'v> Trace<'v> for () {
+    fn trace(&mut self, _tracer: &Tracer<'v>) {}
+}
+
 unsafe impl<'v> Trace<'v> for std::time::Instant {
     fn trace(&mut self, _tracer: &Tracer<'v>) {}
 }
@@ -314,6 +314,20 @@ pub trait StarlarkValue<'v>: 'v + AnyLifetime<'v> + Debug + Display {
         write!(collector, "<{}...>", self.get_type()).unwrap()
     }
 
+    /// A short, single line description of the value, used in place of the `repr` by
+    /// debuggers and in error messages, e.g. for a large table, its dimensions rather
+    /// than its contents. The default of [`None`] means use the `repr`.
+    fn debug_summary(&self) -> Option<String> {
+        None
+    }
+
+    /// The components of the value, which a debugger can show beneath the
+    /// [`debug_summary`](StarlarkValue::debug_summary), each with a label.
+    /// The default is that the value has no components.
+    fn debug_children(&self) -> Vec<(String, Value<'v>)> {
+        Vec::new()
+    }
+
     /// Convert the type to a JSON string.
     fn collect_json(&self, _collector: &mut String) -> anyhow::Result<()> {
         ValueError::unsupported(self, "collect_json()")
@@ -661,27 +675,27 @@ pub trait StarlarkValue<'v>: 'v + AnyLifetime<'v> + Debug + Display {
     }
 
     /// Bitwise `&` operator.
-    fn bit_and(&self, other: Value<'v>) -> anyhow::Result<Value<'v>> {
+    fn bit_and(&self, other: Value<'v>, _heap: &'v Heap) -> anyhow::Result<Value<'v>> {
         ValueError::unsupported_with(self, "&", other)
     }
 
     /// Bitwise `|` operator.
-    fn bit_or(&self, other: Value<'v>) -> anyhow::Result<Value<'v>> {
+    fn bit_or(&self, other: Value<'v>, _heap: &'v Heap) -> anyhow::Result<Value<'v>> {
         ValueError::unsupported_with(self, "|", other)
     }
 
     /// Bitwise `^` operator.
-    fn bit_xor(&self, other: Value<'v>) -> anyhow::Result<Value<'v>> {
+    fn bit_xor(&self, other: Value<'v>, _heap: &'v Heap) -> anyhow::Result<Value<'v>> {
         ValueError::unsupported_with(self, "^", other)
     }
 
     /// Bitwise `<<` operator.
-    fn left_shift(&self, other: Value<'v>) -> anyhow::Result<Value<'v>> {
+    fn left_shift(&self, other: Value<'v>, _heap: &'v Heap) -> anyhow::Result<Value<'v>> {
         ValueError::unsupported_with(self, "<<", other)
     }
 
     /// Bitwise `>>` operator.
-    fn right_shift(&self, other: Value<'v>) -> anyhow::Result<Value<'v>> {
+    fn right_shift(&self, other: Value<'v>, _heap: &'v Heap) -> anyhow::Result<Value<'v>> {
         ValueError::unsupported_with(self, ">>", other)
     }
 
@@ -738,6 +752,8 @@ pub(crate) trait StarlarkValueDyn<'v>: 'v {
     fn documentation(&self) -> Option<DocItem>;
     fn collect_repr(&self, _collector: &mut String);
     fn collect_repr_cycle(&self, _collector: &mut String);
+    fn debug_summary(&self) -> Option<String>;
+    fn debug_children(&self) -> Vec<(String, Value<'v>)>;
     fn collect_json(&self, _collector: &mut String) -> anyhow::Result<()>;
     fn to_bool(&self) -> bool;
     fn to_int(&self) -> anyhow::Result<i32>;
@@ -793,11 +809,11 @@ pub(crate) trait StarlarkValueDyn<'v>: 'v {
     fn percent(&self, _other: Value<'v>, _heap: &'v Heap) -> anyhow::Result<Value<'v>>;
     fn div(&self, _other: Value<'v>, _heap: &'v Heap) -> anyhow::Result<Value<'v>>;
     fn floor_div(&self, _other: Value<'v>, _heap: &'v Heap) -> anyhow::Result<Value<'v>>;
-    fn bit_and(&self, _other: Value<'v>) -> anyhow::Result<Value<'v>>;
-    fn bit_or(&self, _other: Value<'v>) -> anyhow::Result<Value<'v>>;
-    fn bit_xor(&self, _other: Value<'v>) -> anyhow::Result<Value<'v>>;
-    fn left_shift(&self, _other: Value<'v>) -> anyhow::Result<Value<'v>>;
-    fn right_shift(&self, _other: Value<'v>) -> anyhow::Result<Value<'v>>;
+    fn bit_and(&self, _other: Value<'v>, _heap: &'v Heap) -> anyhow::Result<Value<'v>>;
+    fn bit_or(&self, _other: Value<'v>, _heap: &'v Heap) -> anyhow::Result<Value<'v>>;
+    fn bit_xor(&self, _other: Value<'v>, _heap: &'v Heap) -> anyhow::Result<Value<'v>>;
+    fn left_shift(&self, _other: Value<'v>, _heap: &'v Heap) -> anyhow::Result<Value<'v>>;
+    fn right_shift(&self, _other: Value<'v>, _heap: &'v Heap) -> anyhow::Result<Value<'v>>;
     fn export_as(&self, _variable_name: &str, _eval: &mut Evaluator<'v, '_>);
     fn set_at(&self, _index: Value<'v>, _new_value: Value<'v>) -> anyhow::Result<()>;
     fn set_attr(&self, _attribute: &str, _new_value: Value<'v>) -> anyhow::Result<()>;
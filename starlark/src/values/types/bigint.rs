@@ -0,0 +1,468 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Integers too large to fit in 32 bits.
+//!
+//! Starlark integers have unbounded magnitude. Those which fit in an `i32` are represented
+//! without allocation (see [`int`](crate::values::int)), and arithmetic on them is promoted
+//! to a [`StarlarkBigInt`] on the heap if the result overflows. A [`StarlarkBigInt`] is never
+//! in the `i32` range, so every integer has exactly one representation.
+
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    fmt::{self, Display, Write},
+    hash::{Hash, Hasher},
+};
+
+use num_bigint::{BigInt, Sign};
+use num_integer::Integer;
+use num_traits::{FromPrimitive, ToPrimitive, Zero};
+use thiserror::Error;
+
+use crate::{
+    collections::StarlarkHasher,
+    values::{
+        float::StarlarkFloat, int::INT_TYPE, num::Num, AllocFrozenValue, AllocValue, FrozenHeap,
+        FrozenValue, Heap, StarlarkValue, UnpackValue, Value, ValueError,
+    },
+};
+
+#[derive(Debug, Error)]
+pub(crate) enum BigIntError {
+    #[error("Integer too large to convert to float")]
+    TooLargeForFloat,
+    #[error("shift count too large: {0}")]
+    ShiftTooLarge(BigInt),
+    #[error("negative shift count: {0}")]
+    NegativeShift(BigInt),
+}
+
+/// Left shifts must be by less than this, as in the Go implementation, so a single
+/// operation can't make an integer big enough to use up the memory of the process.
+const MAX_LEFT_SHIFT: usize = 512;
+
+/// An integer outside the range of `i32`.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct StarlarkBigInt {
+    value: BigInt,
+}
+
+starlark_simple_value!(StarlarkBigInt);
+
+impl StarlarkBigInt {
+    /// Allocate an integer, without using the heap if it fits in an `i32`.
+    pub fn alloc<'v>(value: BigInt, heap: &'v Heap) -> Value<'v> {
+        match value.to_i32() {
+            Some(i) => Value::new_int(i),
+            None => heap.alloc_simple(StarlarkBigInt { value }),
+        }
+    }
+
+    /// Allocate an integer on a [`FrozenHeap`], without using the heap if it fits in an `i32`.
+    pub fn alloc_frozen(value: BigInt, heap: &FrozenHeap) -> FrozenValue {
+        match value.to_i32() {
+            Some(i) => FrozenValue::new_int(i),
+            None => heap.alloc_simple(StarlarkBigInt { value }),
+        }
+    }
+
+    /// The value of this integer.
+    pub fn get(&self) -> &BigInt {
+        &self.value
+    }
+
+    /// Obtain the value of any integer, whether or not it fits in an `i32`.
+    pub fn unpack_integer(value: Value) -> Option<BigInt> {
+        Some(Self::unpack_integer_ref(value)?.into_owned())
+    }
+
+    fn unpack_integer_ref(value: Value) -> Option<Cow<BigInt>> {
+        match value.unpack_int() {
+            Some(i) => Some(Cow::Owned(BigInt::from(i))),
+            None => Some(Cow::Borrowed(&Self::from_value(value)?.value)),
+        }
+    }
+
+    /// The nearest float, or an error if the integer is beyond the range of `f64`.
+    pub(crate) fn to_f64(&self) -> anyhow::Result<f64> {
+        match self.value.to_f64() {
+            Some(f) if f.is_finite() => Ok(f),
+            _ => Err(BigIntError::TooLargeForFloat.into()),
+        }
+    }
+
+    /// Compare exactly against a float, where NaN is greater than any integer.
+    pub(crate) fn compare_f64(&self, f: f64) -> Ordering {
+        if f.is_nan() {
+            Ordering::Less
+        } else if f.is_infinite() {
+            if f > 0.0 {
+                Ordering::Less
+            } else {
+                Ordering::Greater
+            }
+        } else {
+            // Every finite float with no fractional part is an integer, so this is exact.
+            let trunc = f.trunc();
+            match self.value.cmp(&BigInt::from_f64(trunc).unwrap()) {
+                Ordering::Equal => 0f64.partial_cmp(&(f - trunc)).unwrap(),
+                ord => ord,
+            }
+        }
+    }
+
+    fn bin_op<'v>(
+        &self,
+        other: Value<'v>,
+        heap: &'v Heap,
+        op: &'static str,
+        int: impl FnOnce(&BigInt, &BigInt) -> anyhow::Result<BigInt>,
+        float: fn(&StarlarkFloat, Value<'v>, &'v Heap) -> anyhow::Result<Value<'v>>,
+    ) -> anyhow::Result<Value<'v>> {
+        if let Some(other) = Self::unpack_integer_ref(other) {
+            Ok(Self::alloc(int(&self.value, &other)?, heap))
+        } else if other.unpack_num().is_some() {
+            float(&StarlarkFloat(self.to_f64()?), other, heap)
+        } else {
+            ValueError::unsupported_with(self, op, other)
+        }
+    }
+
+    fn bit_op<'v>(
+        &self,
+        other: Value<'v>,
+        heap: &'v Heap,
+        op: &'static str,
+        f: impl FnOnce(&BigInt, &BigInt) -> BigInt,
+    ) -> anyhow::Result<Value<'v>> {
+        match Self::unpack_integer_ref(other) {
+            Some(other) => Ok(Self::alloc(f(&self.value, &other), heap)),
+            None => ValueError::unsupported_with(self, op, other),
+        }
+    }
+
+    fn shift_op<'v>(
+        &self,
+        other: Value<'v>,
+        heap: &'v Heap,
+        op: &'static str,
+        max: usize,
+        f: impl FnOnce(&BigInt, usize) -> BigInt,
+    ) -> anyhow::Result<Value<'v>> {
+        match Self::unpack_integer_ref(other) {
+            // Shift counts can't be negative, or at least `max`.
+            Some(other) => match other.to_usize() {
+                Some(shift) if shift < max => Ok(Self::alloc(f(&self.value, shift), heap)),
+                _ if other.sign() == Sign::Minus => {
+                    Err(BigIntError::NegativeShift(other.into_owned()).into())
+                }
+                _ => Err(BigIntError::ShiftTooLarge(other.into_owned()).into()),
+            },
+            None => ValueError::unsupported_with(self, op, other),
+        }
+    }
+}
+
+impl From<i32> for StarlarkBigInt {
+    /// An integer which is only used for arithmetic, never allocated, since integers in the
+    /// `i32` range must be represented as such.
+    fn from(i: i32) -> Self {
+        StarlarkBigInt {
+            value: BigInt::from(i),
+        }
+    }
+}
+
+impl<'v> AllocValue<'v> for BigInt {
+    fn alloc_value(self, heap: &'v Heap) -> Value<'v> {
+        StarlarkBigInt::alloc(self, heap)
+    }
+}
+
+impl AllocFrozenValue for BigInt {
+    fn alloc_frozen_value(self, heap: &FrozenHeap) -> FrozenValue {
+        StarlarkBigInt::alloc_frozen(self, heap)
+    }
+}
+
+impl<'v> UnpackValue<'v> for BigInt {
+    fn expected() -> String {
+        INT_TYPE.to_owned()
+    }
+
+    fn unpack_value(value: Value<'v>) -> Option<Self> {
+        StarlarkBigInt::unpack_integer(value)
+    }
+}
+
+impl Display for StarlarkBigInt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.value)
+    }
+}
+
+fn floor_div(a: &BigInt, b: &BigInt) -> anyhow::Result<BigInt> {
+    if b.is_zero() {
+        Err(ValueError::FlooredDivisionByZero.into())
+    } else {
+        Ok(a.div_floor(b))
+    }
+}
+
+fn percent(a: &BigInt, b: &BigInt) -> anyhow::Result<BigInt> {
+    if b.is_zero() {
+        Err(ValueError::ModuloByZero.into())
+    } else {
+        Ok(a.mod_floor(b))
+    }
+}
+
+impl<'v> StarlarkValue<'v> for StarlarkBigInt {
+    starlark_type!(INT_TYPE);
+
+    fn equals(&self, other: Value) -> anyhow::Result<bool> {
+        Ok(match Self::unpack_integer_ref(other) {
+            Some(other) => self.value == *other,
+            None => match other.unpack_num() {
+                Some(Num::Float(f)) => self.compare_f64(f) == Ordering::Equal,
+                _ => false,
+            },
+        })
+    }
+
+    fn compare(&self, other: Value) -> anyhow::Result<Ordering> {
+        match Self::unpack_integer_ref(other) {
+            Some(other) => Ok(self.value.cmp(&other)),
+            None => match other.unpack_num() {
+                Some(Num::Float(f)) => Ok(self.compare_f64(f)),
+                // Used for `<`, `sorted` and the like, so we can't name the operator.
+                _ => ValueError::unsupported_with(self, "compare", other),
+            },
+        }
+    }
+
+    fn collect_json(&self, collector: &mut String) -> anyhow::Result<()> {
+        write!(collector, "{}", self.value).unwrap();
+        Ok(())
+    }
+
+    fn to_int(&self) -> anyhow::Result<i32> {
+        Err(ValueError::IntegerOverflow.into())
+    }
+
+    fn to_bool(&self) -> bool {
+        !self.value.is_zero()
+    }
+
+    fn write_hash(&self, hasher: &mut StarlarkHasher) -> anyhow::Result<()> {
+        // Integers which are exactly representable as floats must hash like the float.
+        match self.value.to_f64() {
+            Some(f) if BigInt::from_f64(f).as_ref() == Some(&self.value) => {
+                hasher.write_u64(Num::from(f).get_hash())
+            }
+            _ => self.value.hash(hasher),
+        }
+        Ok(())
+    }
+
+    fn extra_memory(&self) -> usize {
+        (self.value.bits() / 8) as usize
+    }
+
+    fn plus(&self, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        Ok(Self::alloc(self.value.clone(), heap))
+    }
+
+    fn minus(&self, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        Ok(Self::alloc(-&self.value, heap))
+    }
+
+    fn add(&self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        self.bin_op(other, heap, "+", |a, b| Ok(a + b), StarlarkFloat::add)
+    }
+
+    fn sub(&self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        self.bin_op(other, heap, "-", |a, b| Ok(a - b), StarlarkFloat::sub)
+    }
+
+    fn mul(&self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        self.bin_op(other, heap, "*", |a, b| Ok(a * b), StarlarkFloat::mul)
+    }
+
+    fn div(&self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        if other.unpack_num().is_some() || Self::from_value(other).is_some() {
+            StarlarkFloat(self.to_f64()?).div(other, heap)
+        } else {
+            ValueError::unsupported_with(self, "/", other)
+        }
+    }
+
+    fn percent(&self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        self.bin_op(other, heap, "%", percent, StarlarkFloat::percent)
+    }
+
+    fn floor_div(&self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        self.bin_op(other, heap, "//", floor_div, StarlarkFloat::floor_div)
+    }
+
+    fn bit_and(&self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        self.bit_op(other, heap, "&", |a, b| a & b)
+    }
+
+    fn bit_or(&self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        self.bit_op(other, heap, "|", |a, b| a | b)
+    }
+
+    fn bit_xor(&self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        self.bit_op(other, heap, "^", |a, b| a ^ b)
+    }
+
+    fn left_shift(&self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        self.shift_op(other, heap, "<<", MAX_LEFT_SHIFT, |a, b| a << b)
+    }
+
+    fn right_shift(&self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        // Rounds towards negative infinity, so negative numbers end at `-1`.
+        self.shift_op(other, heap, ">>", usize::MAX, |a, b| a >> b)
+    }
+}
+
+/// The `~` operator on an integer, which may be a [`StarlarkBigInt`].
+pub(crate) fn bit_not<'v>(value: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+    match StarlarkBigInt::from_value(value) {
+        Some(x) => Ok(StarlarkBigInt::alloc(!&x.value, heap)),
+        None => Ok(Value::new_int(!value.to_int()?)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assert;
+
+    #[test]
+    fn test_promotion() {
+        assert::all_true(
+            r#"
+2147483647 + 1 == 2147483648
+-2147483647 - 2 == -2147483649
+65536 * 65536 == 4294967296
+-(-2147483647 - 1) == 2147483648
+(-2147483647 - 1) // -1 == 2147483648
+1 << 31 == 2147483648
+1 << 100 == 1267650600228229401496703205376
+(1 << 100) >> 98 == 4
+(1 << 100) - (1 << 100) == 0
+type(1 << 100) == "int"
+type((1 << 40) >> 40) == "int"
+"#,
+        );
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert::pass(
+            r#"
+x = 1 << 70
+y = 12345678901234567890
+assert_eq(x + y - x, y)
+assert_eq(x * x // x, x)
+assert_eq(-x // 3, -393530540239137101142)
+assert_eq(-x % 3, 2)
+assert_eq(x % -3, -2)
+assert_eq(x / (1 << 69), 2.0)
+assert_eq(x + 0.5, 1180591620717411303424.0)
+assert_eq(~x, -x - 1)
+assert_eq(x & (x - 1), 0)
+assert_eq(x | 1, x + 1)
+assert_eq(x ^ x, 0)
+assert_eq(-x >> 200, -1)
+assert_eq(-1 & x, x)
+"#,
+        );
+        assert::fail("(1 << 70) // 0", "floored division by zero");
+        assert::fail("(1 << 70) % 0", "integer modulo by zero");
+        assert::fail(
+            "1 << (1 << 70)",
+            "shift count too large: 1180591620717411303424",
+        );
+        assert::fail("(1 << 70) << -1", "negative shift count: -1");
+        assert::fail(
+            "(1 << 70) >> -(1 << 70)",
+            "negative shift count: -1180591620717411303424",
+        );
+        assert::fail(
+            "float((1 << 511) * (1 << 511) * (1 << 511))",
+            "too large to convert to float",
+        );
+    }
+
+    #[test]
+    fn test_shift_limit() {
+        assert::all_true(
+            r#"
+1 << 511 == 2 * (1 << 510)
+(1 << 511) >> 600 == 0
+-(1 << 511) >> 600 == -1
+"#,
+        );
+        assert::fail("1 << 512", "shift count too large: 512");
+        assert::fail("(1 << 40) << 600", "shift count too large: 600");
+        assert::fail("0 << 512", "shift count too large: 512");
+        assert::fail("(1 << 40) < 'x'", "Operation `compare` not supported");
+    }
+
+    #[test]
+    fn test_comparison_and_hash() {
+        assert::all_true(
+            r#"
+(1 << 40) == 1099511627776.0
+1099511627776.0 == (1 << 40)
+(1 << 60) + 1 != float(1 << 60)
+(1 << 60) + 1 > float(1 << 60)
+(1 << 40) < float("inf")
+-(1 << 40) > float("-inf")
+(1 << 40) < float("nan")
+(1 << 40) > 1
+-(1 << 40) < -1
+sorted([1 << 40, 1, -(1 << 40), 2.5]) == [-(1 << 40), 1, 2.5, 1 << 40]
+{1 << 40: "x"}[1099511627776.0] == "x"
+{1 << 100: "x"}[1 << 100] == "x"
+(1 << 100) in [1 << 100]
+bool(1 << 100)
+"#,
+        );
+    }
+
+    #[test]
+    fn test_literals() {
+        assert::all_true(
+            r#"
+2147483648 == 1 << 31
+0x10000000000000000 == 1 << 64
+-9223372036854775808 == -(1 << 63)
+str(123456789012345678901234567890) == "123456789012345678901234567890"
+repr(-123456789012345678901234567890) == "-123456789012345678901234567890"
+int("123456789012345678901234567890") == 123456789012345678901234567890
+int("-0x10000000000000000", 0) == -(1 << 64)
+int(1e20) == 100000000000000000000
+int(1 << 100) == 1 << 100
+float(1 << 70) == 1180591620717411303424.0
+"#,
+        );
+    }
+}
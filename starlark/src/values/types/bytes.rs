@@ -0,0 +1,339 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! The bytes type, an immutable sequence of bytes, written `b"..."`.
+//!
+//! Unlike strings, bytes need not be valid UTF-8. Indexing a bytes value gives a bytes
+//! value of length one, while the `elems()` method gives the bytes as integers.
+
+use std::{
+    cmp::{self, Ordering},
+    fmt::{self, Display},
+    hash::Hasher,
+    str,
+};
+
+use thiserror::Error;
+
+use crate::{
+    collections::StarlarkHasher,
+    environment::{Methods, MethodsStatic},
+    syntax::ast::fmt_bytes_literal,
+    values::{
+        index::{apply_slice, convert_index},
+        AllocFrozenValue, AllocValue, FrozenHeap, FrozenValue, Heap, StarlarkValue, UnpackValue,
+        Value, ValueError,
+    },
+};
+
+#[derive(Debug, Error)]
+enum BytesError {
+    #[error("Unknown encoding `{0}`, expected one of `utf-8`, `ascii` or `latin-1`")]
+    UnknownEncoding(String),
+    #[error("Bytes can't be decoded as `{0}`")]
+    Decode(&'static str),
+    #[error("String can't be encoded as `{0}`")]
+    Encode(&'static str),
+    #[error("Bytes elements must be integers in the range 0 to 255, got `{0}`")]
+    NotByte(String),
+}
+
+/// The result of calling `type()` on bytes.
+pub const BYTES_TYPE: &str = "bytes";
+
+/// An immutable sequence of bytes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StarlarkBytes(Vec<u8>);
+
+starlark_simple_value!(StarlarkBytes);
+
+/// A text encoding which can convert between bytes and strings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Encoding {
+    Utf8,
+    Ascii,
+    Latin1,
+}
+
+impl Encoding {
+    pub(crate) fn new(name: &str) -> anyhow::Result<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Ok(Encoding::Utf8),
+            "ascii" => Ok(Encoding::Ascii),
+            "latin-1" | "latin1" | "iso-8859-1" => Ok(Encoding::Latin1),
+            _ => Err(BytesError::UnknownEncoding(name.to_owned()).into()),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "utf-8",
+            Encoding::Ascii => "ascii",
+            Encoding::Latin1 => "latin-1",
+        }
+    }
+
+    pub(crate) fn encode(self, s: &str) -> anyhow::Result<Vec<u8>> {
+        match self {
+            Encoding::Ascii if !s.is_ascii() => Err(BytesError::Encode(self.name()).into()),
+            Encoding::Utf8 | Encoding::Ascii => Ok(s.as_bytes().to_vec()),
+            Encoding::Latin1 => s
+                .chars()
+                .map(|c| u8::try_from(u32::from(c)).ok())
+                .collect::<Option<Vec<u8>>>()
+                .ok_or_else(|| BytesError::Encode(self.name()).into()),
+        }
+    }
+
+    pub(crate) fn decode(self, b: &[u8]) -> anyhow::Result<String> {
+        match self {
+            Encoding::Ascii if !b.is_ascii() => Err(BytesError::Decode(self.name()).into()),
+            Encoding::Utf8 | Encoding::Ascii => match str::from_utf8(b) {
+                Ok(s) => Ok(s.to_owned()),
+                Err(_) => Err(BytesError::Decode(self.name()).into()),
+            },
+            Encoding::Latin1 => Ok(b.iter().map(|x| *x as char).collect()),
+        }
+    }
+}
+
+impl StarlarkBytes {
+    /// Create a new bytes value.
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Self(bytes)
+    }
+
+    /// The contents of this bytes value.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Collect an iterable of integers, each in the range 0 to 255.
+    pub(crate) fn collect_ints<'v>(x: Value<'v>, heap: &'v Heap) -> anyhow::Result<Vec<u8>> {
+        x.with_iterator(heap, |it| {
+            it.map(|x| {
+                x.unpack_int()
+                    .and_then(|i| u8::try_from(i).ok())
+                    .ok_or_else(|| BytesError::NotByte(x.debug_summary()).into())
+            })
+            .collect::<anyhow::Result<Vec<u8>>>()
+        })?
+    }
+}
+
+impl Display for StarlarkBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_bytes_literal(f, &self.0)
+    }
+}
+
+impl<'v> AllocValue<'v> for Vec<u8> {
+    fn alloc_value(self, heap: &'v Heap) -> Value<'v> {
+        heap.alloc_simple(StarlarkBytes(self))
+    }
+}
+
+impl<'v> AllocValue<'v> for &'_ [u8] {
+    fn alloc_value(self, heap: &'v Heap) -> Value<'v> {
+        heap.alloc_simple(StarlarkBytes(self.to_vec()))
+    }
+}
+
+impl AllocFrozenValue for Vec<u8> {
+    fn alloc_frozen_value(self, heap: &FrozenHeap) -> FrozenValue {
+        heap.alloc_simple(StarlarkBytes(self))
+    }
+}
+
+impl<'v> UnpackValue<'v> for &'v [u8] {
+    fn expected() -> String {
+        BYTES_TYPE.to_owned()
+    }
+
+    fn unpack_value(value: Value<'v>) -> Option<Self> {
+        StarlarkBytes::from_value(value).map(|x| x.as_bytes())
+    }
+}
+
+impl<'v> UnpackValue<'v> for Vec<u8> {
+    fn expected() -> String {
+        BYTES_TYPE.to_owned()
+    }
+
+    fn unpack_value(value: Value<'v>) -> Option<Self> {
+        StarlarkBytes::from_value(value).map(|x| x.0.clone())
+    }
+}
+
+impl<'v> StarlarkValue<'v> for StarlarkBytes {
+    starlark_type!(BYTES_TYPE);
+
+    fn get_methods(&self) -> Option<&'static Methods> {
+        static RES: MethodsStatic = MethodsStatic::new();
+        RES.methods(crate::stdlib::bytes::bytes_methods)
+    }
+
+    fn to_bool(&self) -> bool {
+        !self.0.is_empty()
+    }
+
+    fn extra_memory(&self) -> usize {
+        self.0.capacity()
+    }
+
+    fn write_hash(&self, hasher: &mut StarlarkHasher) -> anyhow::Result<()> {
+        hasher.write(&self.0);
+        Ok(())
+    }
+
+    fn equals(&self, other: Value) -> anyhow::Result<bool> {
+        match StarlarkBytes::from_value(other) {
+            Some(other) => Ok(self.0 == other.0),
+            None => Ok(false),
+        }
+    }
+
+    fn compare(&self, other: Value) -> anyhow::Result<Ordering> {
+        match StarlarkBytes::from_value(other) {
+            Some(other) => Ok(self.0.cmp(&other.0)),
+            None => ValueError::unsupported_with(self, "cmp()", other),
+        }
+    }
+
+    fn at(&self, index: Value, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        let i = convert_index(index, self.0.len() as i32)? as usize;
+        Ok(heap.alloc(&self.0[i..i + 1]))
+    }
+
+    fn length(&self) -> anyhow::Result<i32> {
+        Ok(self.0.len() as i32)
+    }
+
+    fn is_in(&self, other: Value) -> anyhow::Result<bool> {
+        if let Some(needle) = StarlarkBytes::from_value(other) {
+            Ok(needle.0.is_empty() || self.0.windows(needle.0.len()).any(|x| x == needle.0))
+        } else if let Some(i) = other.unpack_int() {
+            match u8::try_from(i) {
+                Ok(b) => Ok(self.0.contains(&b)),
+                Err(_) => Err(BytesError::NotByte(other.debug_summary()).into()),
+            }
+        } else {
+            ValueError::unsupported_with(self, "in", other)
+        }
+    }
+
+    fn slice(
+        &self,
+        start: Option<Value>,
+        stop: Option<Value>,
+        stride: Option<Value>,
+        heap: &'v Heap,
+    ) -> anyhow::Result<Value<'v>> {
+        Ok(heap.alloc(apply_slice(&self.0, start, stop, stride)?))
+    }
+
+    fn add(&self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        match StarlarkBytes::from_value(other) {
+            Some(other) => Ok(heap.alloc([self.0.as_slice(), &other.0].concat())),
+            None => ValueError::unsupported_with(self, "+", other),
+        }
+    }
+
+    fn mul(&self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        let l = i32::unpack_param(other)?;
+        let len = self.0.len() * cmp::max(0, l) as usize;
+        heap.check_allocation_limit(len)?;
+        Ok(heap.alloc(self.0.repeat(cmp::max(0, l) as usize)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assert;
+
+    #[test]
+    fn test_literals() {
+        assert::all_true(
+            r#"
+b"abc" == b'abc'
+b"\x00\xff" == bytes([0, 255])
+rb"\x00" == b"\\x00"
+br"a\"b" == b'a"b'
+b"é" == "é".encode()
+b"""multi
+line""" == b"multi\nline"
+repr(b"a\"\x00\xff") == 'b"a\\"\\x00\\xff"'
+type(b"") == "bytes"
+"#,
+        );
+        assert::fail(r#"b"\777""#, "invalid string escape sequence");
+    }
+
+    #[test]
+    fn test_operations() {
+        assert::all_true(
+            r#"
+len(b"hello") == 5
+b"hello"[1] == b"e"
+b"hello"[-1] == b"o"
+b"hello"[1:3] == b"el"
+b"hello"[::-1] == b"olleh"
+b"ab" + b"cd" == b"abcd"
+b"ab" * 3 == b"ababab"
+3 * b"ab" == b"ababab"
+b"ell" in b"hello"
+101 in b"hello"
+not (b"x" in b"hello")
+b"a" < b"b"
+not b""
+b"a" != "a"
+{b"a": 1}[b"a"] == 1
+"#,
+        );
+        assert::fail("b'a' + 'a'", "not supported");
+        assert::fail("256 in b'a'", "range 0 to 255");
+    }
+
+    #[test]
+    fn test_conversions() {
+        assert::all_true(
+            r#"
+list(b"ab".elems()) == [97, 98]
+bytes("hé") == b"h\xc3\xa9"
+bytes(b"x") == b"x"
+bytes([104, 105]) == b"hi"
+b"h\xc3\xa9".decode() == "hé"
+b"h\xe9".decode("latin-1") == "hé"
+"hé".encode("latin-1") == b"h\xe9"
+"hi".encode("ascii") == b"hi"
+str(b"hi") == "hi"
+str(b"\xff") == "�"
+"#,
+        );
+        assert::fail("b'\\xff'.decode()", "can't be decoded");
+        assert::fail("'é'.encode('ascii')", "can't be encoded");
+        assert::fail("b'a'.decode('utf-16')", "Unknown encoding");
+        assert::fail("bytes([256])", "range 0 to 255");
+    }
+
+    #[test]
+    fn test_dialect() {
+        let mut a = assert::Assert::new();
+        a.dialect_set(|d| d.enable_bytes = false);
+        a.fail("b'x'", "not allowed in this dialect");
+    }
+}
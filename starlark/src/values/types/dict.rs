@@ -19,6 +19,7 @@
 
 use std::{
     cell::{Ref, RefCell, RefMut},
+    collections::{BTreeMap, HashMap},
     fmt,
     fmt::{Debug, Display},
     hash::{Hash, Hasher},
@@ -41,8 +42,8 @@ use crate::{
     values::{
         comparison::equals_small_map, display::display_keyed_container, error::ValueError,
         iter::ARefIterator, string::hash_string_value, AllocFrozenValue, AllocValue, Freeze,
-        Freezer, FrozenHeap, FrozenStringValue, FrozenValue, Heap, SimpleValue, StarlarkValue,
-        StringValue, Trace, UnpackValue, Value, ValueLike,
+        FreezeStep, Freezer, FrozenHeap, FrozenStringValue, FrozenValue, Heap, SimpleValue,
+        StarlarkValue, StringValue, Trace, UnpackValue, Value, ValueLike,
     },
 };
 
@@ -128,7 +129,54 @@ impl<'v> UnpackValue<'v> for ARef<'v, Dict<'v>> {
     }
 }
 
+/// A read-only view of a dict, either mutable or frozen, which can be obtained
+/// without copying the entries. Use [`DictRef::from_value`] to obtain one, or take
+/// `DictRef` as the parameter type of a native function.
+///
+/// While the view is alive, attempts to mutate the underlying dict will fail
+/// (as they would while iterating over it).
+pub struct DictRef<'v> {
+    aref: ARef<'v, Dict<'v>>,
+}
+
+impl<'v> DictRef<'v> {
+    /// Obtain a view of the dict pointed at by a [`Value`], or [`None`] if it isn't a dict.
+    pub fn from_value(x: Value<'v>) -> Option<DictRef<'v>> {
+        Some(DictRef {
+            aref: Dict::from_value(x)?,
+        })
+    }
+
+    /// Like [`DictRef::from_value`], but returns an error mentioning the type of the value
+    /// if it isn't a dict.
+    pub fn try_from_value(x: Value<'v>) -> anyhow::Result<DictRef<'v>> {
+        DictRef::unpack_param(x)
+    }
+}
+
+impl<'v> Deref for DictRef<'v> {
+    type Target = Dict<'v>;
+
+    fn deref(&self) -> &Dict<'v> {
+        &self.aref
+    }
+}
+
+impl<'v> UnpackValue<'v> for DictRef<'v> {
+    fn expected() -> String {
+        "dict".to_owned()
+    }
+
+    fn unpack_value(value: Value<'v>) -> Option<DictRef<'v>> {
+        DictRef::from_value(value)
+    }
+}
+
 impl FrozenDict {
+    pub(crate) fn new(content: SmallMap<FrozenValue, FrozenValue>) -> Self {
+        Self { content }
+    }
+
     /// Obtain the [`FrozenDict`] pointed at by a [`FrozenValue`].
     #[allow(clippy::trivially_copy_pass_by_ref)]
     // We need a lifetime because FrozenValue doesn't contain the right lifetime
@@ -310,7 +358,11 @@ impl FrozenDict {
 impl<'v> Freeze for DictGen<RefCell<Dict<'v>>> {
     type Frozen = DictGen<FrozenDict>;
     fn freeze(self, freezer: &Freezer) -> anyhow::Result<Self::Frozen> {
-        let content = self.0.into_inner().content.freeze(freezer)?;
+        let content = self
+            .0
+            .into_inner()
+            .content
+            .freeze_keyed(freezer, |k| Some(FreezeStep::Key(k.to_value().debug_summary())))?;
         Ok(DictGen(FrozenDict { content }))
     }
 }
@@ -375,6 +427,14 @@ where
         collector.push_str("{...}");
     }
 
+    fn debug_children(&self) -> Vec<(String, Value<'v>)> {
+        self.0
+            .content()
+            .iter()
+            .map(|(k, v)| (k.to_repr(), *v))
+            .collect()
+    }
+
     fn collect_json(&self, collector: &mut String) -> anyhow::Result<()> {
         collector.push('{');
         for (i, (k, v)) in self.0.content().iter().enumerate() {
@@ -405,7 +465,7 @@ where
     fn at(&self, index: Value<'v>, _heap: &'v Heap) -> anyhow::Result<Value<'v>> {
         match self.0.content().get_hashed(index.get_hashed()?.borrow()) {
             Some(v) => Ok(v.to_value()),
-            None => Err(ValueError::KeyNotFound(index.to_repr()).into()),
+            None => Err(ValueError::KeyNotFound(index.debug_summary()).into()),
         }
     }
 
@@ -465,14 +525,86 @@ impl<'v, K: UnpackValue<'v> + Hash + Eq, V: UnpackValue<'v>> UnpackValue<'v> for
     }
 }
 
+/// Allocate a dict with the given entries, in order.
+/// Panics if a key is not hashable, which is a bug in the Rust code choosing the key type.
+fn alloc_dict_iter<'v, K: AllocValue<'v>, V: AllocValue<'v>>(
+    heap: &'v Heap,
+    entries: impl ExactSizeIterator<Item = (K, V)>,
+) -> Value<'v> {
+    let mut content = SmallMap::with_capacity(entries.len());
+    for (k, v) in entries {
+        let k = k
+            .alloc_value(heap)
+            .get_hashed()
+            .expect("dict key must be hashable");
+        content.insert_hashed(k, v.alloc_value(heap));
+    }
+    heap.alloc(Dict::new(content))
+}
+
+/// Allocated as a dict, in the arbitrary iteration order of the [`HashMap`].
+impl<'v, K: AllocValue<'v>, V: AllocValue<'v>> AllocValue<'v> for HashMap<K, V> {
+    fn alloc_value(self, heap: &'v Heap) -> Value<'v> {
+        alloc_dict_iter(heap, self.into_iter())
+    }
+}
+
+/// Allocated as a dict, in key order.
+impl<'v, K: AllocValue<'v>, V: AllocValue<'v>> AllocValue<'v> for BTreeMap<K, V> {
+    fn alloc_value(self, heap: &'v Heap) -> Value<'v> {
+        alloc_dict_iter(heap, self.into_iter())
+    }
+}
+
+impl<'v, K: UnpackValue<'v> + Hash + Eq, V: UnpackValue<'v>> UnpackValue<'v> for HashMap<K, V> {
+    fn expected() -> String {
+        format!("dict mapping {} to {}", K::expected(), V::expected())
+    }
+
+    fn unpack_value(value: Value<'v>) -> Option<Self> {
+        let dict = Dict::from_value(value)?;
+        let mut r = HashMap::with_capacity(dict.len());
+        for (k, v) in dict.content.iter() {
+            r.insert(K::unpack_value(*k)?, V::unpack_value(*v)?);
+        }
+        Some(r)
+    }
+}
+
+impl<'v, K: UnpackValue<'v> + Ord, V: UnpackValue<'v>> UnpackValue<'v> for BTreeMap<K, V> {
+    fn expected() -> String {
+        format!("dict mapping {} to {}", K::expected(), V::expected())
+    }
+
+    fn unpack_value(value: Value<'v>) -> Option<Self> {
+        let dict = Dict::from_value(value)?;
+        let mut r = BTreeMap::new();
+        for (k, v) in dict.content.iter() {
+            r.insert(K::unpack_value(*k)?, V::unpack_value(*v)?);
+        }
+        Some(r)
+    }
+}
+
 /// Like [`ValueOf`](crate::values::ValueOf), but only validates key and value types; does not construct
-/// or store a map. Use `to_dict` to get at the map.
+/// or store a map. Use `to_dict` or `collect_entries` to get at the entries.
 pub struct DictOf<'v, K: UnpackValue<'v>, V: UnpackValue<'v>> {
     value: Value<'v>,
     phantom: PhantomData<(K, V)>,
 }
 
 impl<'v, K: UnpackValue<'v>, V: UnpackValue<'v>> DictOf<'v, K, V> {
+    /// Number of entries in the dict.
+    pub fn len(&self) -> usize {
+        Dict::from_value(self.value)
+            .expect("already validated as a dict")
+            .len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     // This should return an iterator, but it is not trivial to do with `ARef`.
     pub fn collect_entries(&self) -> Vec<(K, V)> {
         Dict::from_value(self.value)
@@ -522,6 +654,35 @@ impl<'v, K: UnpackValue<'v>, V: UnpackValue<'v>> UnpackValue<'v> for DictOf<'v,
             None
         }
     }
+
+    fn unpack_named_param(value: Value<'v>, param_name: &str) -> anyhow::Result<Self> {
+        if let Some(x) = Self::unpack_value(value) {
+            return Ok(x);
+        }
+        // Point at the offending entry, rather than just saying we got a `dict`.
+        let mut actual = value.get_type().to_owned();
+        if let Some(dict) = Dict::from_value(value) {
+            for (k, v) in dict.iter() {
+                if K::unpack_value(k).is_none() {
+                    actual = format!("dict with key of type {}", k.get_type());
+                    break;
+                } else if V::unpack_value(v).is_none() {
+                    actual = format!(
+                        "dict with value of type {} for key {}",
+                        v.get_type(),
+                        k.debug_summary()
+                    );
+                    break;
+                }
+            }
+        }
+        Err(ValueError::IncorrectParameterTypeNamedWithExpected(
+            param_name.to_owned(),
+            Self::expected(),
+            actual,
+        )
+        .into())
+    }
 }
 
 impl<'v, K: UnpackValue<'v> + Hash, V: UnpackValue<'v>> Deref for DictOf<'v, K, V> {
@@ -569,6 +730,33 @@ b1 and b2 and b3
         Ok(())
     }
 
+    #[test]
+    fn test_dict_ref() -> anyhow::Result<()> {
+        let heap = Heap::new();
+        let d = heap.alloc(Dict::new(SmallMap::new()));
+        Dict::from_value_mut(d)?
+            .unwrap()
+            .insert_hashed(heap.alloc_str_hashed("x"), Value::new_int(1));
+
+        let view = DictRef::try_from_value(d)?;
+        assert_eq!(view.len(), 1);
+        assert_eq!(view.get_str("x").unwrap().unpack_int(), Some(1));
+        // The dict can't be mutated while we are looking at it
+        assert!(Dict::from_value_mut(d).is_err());
+        drop(view);
+        assert!(Dict::from_value_mut(d).is_ok());
+
+        assert!(DictRef::from_value(heap.alloc(vec![1])).is_none());
+        assert!(
+            DictRef::try_from_value(Value::new_none())
+                .err()
+                .unwrap()
+                .to_string()
+                .contains("expected `dict`, actual `NoneType`")
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_repr_cycle() {
         assert::eq("d = {}; d[17] = d; repr(d)", "'{17: {...}}'");
@@ -32,6 +32,7 @@
 //! assert_eq(Colors[0], val)
 //! assert_eq(Colors.type, "Colors")
 //! assert_eq([v.value for v in Colors], ["Red", "Green", "Blue"])
+//! assert_eq(val in Colors, True)
 //! # "#);
 //! ```
 use std::{
@@ -178,7 +179,7 @@ where
             .either(|x| &x.elements, |x| coerce_ref(&x.elements));
         match elements.get_hashed(val.get_hashed()?.borrow()) {
             Some(v) => Ok(*v),
-            None => Err(EnumError::InvalidElement(val.to_str(), this.to_repr()).into()),
+            None => Err(EnumError::InvalidElement(val.debug_summary_or_str(), this.to_repr()).into()),
         }
     }
 
@@ -215,6 +216,13 @@ where
         f(&mut self.elements.values().map(|x| x.to_value()))
     }
 
+    fn is_in(&self, other: Value<'v>) -> anyhow::Result<bool> {
+        match EnumValue::from_value(other) {
+            Some(other) => self.equals(other.typ),
+            None => Ok(false),
+        }
+    }
+
     fn get_methods(&self) -> Option<&'static Methods> {
         static RES: MethodsStatic = MethodsStatic::new();
         RES.methods(enum_type_methods)
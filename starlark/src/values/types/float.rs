@@ -21,6 +21,7 @@ use std::{
     cmp::Ordering,
     fmt::{self, Display, Write},
     hash::Hasher,
+    time::Duration,
 };
 
 use gazebo::{any::AnyLifetime, prelude::*};
@@ -28,8 +29,8 @@ use gazebo::{any::AnyLifetime, prelude::*};
 use crate::{
     collections::StarlarkHasher,
     values::{
-        num::Num, AllocFrozenValue, AllocValue, FrozenHeap, FrozenValue, Heap, StarlarkValue,
-        UnpackValue, Value, ValueError, ValueLike,
+        bigint::StarlarkBigInt, num::Num, AllocFrozenValue, AllocValue, FrozenHeap, FrozenValue,
+        Heap, StarlarkValue, UnpackValue, Value, ValueError, ValueLike,
     },
 };
 
@@ -168,6 +169,30 @@ impl<'v> UnpackValue<'v> for StarlarkFloat {
     }
 }
 
+/// Allocated as a number of seconds.
+impl<'v> AllocValue<'v> for Duration {
+    fn alloc_value(self, heap: &'v Heap) -> Value<'v> {
+        heap.alloc(self.as_secs_f64())
+    }
+}
+
+/// Unpacked from a number of seconds.
+impl<'v> UnpackValue<'v> for Duration {
+    fn expected() -> String {
+        "non-negative number of seconds".to_owned()
+    }
+
+    fn unpack_value(value: Value<'v>) -> Option<Self> {
+        let secs = value.unpack_num()?.as_float();
+        // Checked, since `from_secs_f64` panics on values it can't represent.
+        if secs.is_finite() && secs >= 0.0 && secs < u64::MAX as f64 {
+            Some(Duration::from_secs_f64(secs))
+        } else {
+            None
+        }
+    }
+}
+
 fn f64_arith_bin_op<'v, F>(
     left: f64,
     right: Value,
@@ -180,6 +205,8 @@ where
 {
     if let Some(right) = right.unpack_num().map(|n| n.as_float()) {
         Ok(heap.alloc_float(StarlarkFloat(f(left, right)?)))
+    } else if let Some(right) = StarlarkBigInt::from_value(right) {
+        Ok(heap.alloc_float(StarlarkFloat(f(left, right.to_f64()?)?)))
     } else {
         ValueError::unsupported_with(&StarlarkFloat(left), op, right)
     }
@@ -195,7 +222,7 @@ impl<'v> StarlarkValue<'v> for StarlarkFloat {
     starlark_type!(StarlarkFloat::TYPE);
 
     fn equals(&self, other: Value) -> anyhow::Result<bool> {
-        if other.unpack_num().is_some() {
+        if other.unpack_num().is_some() || StarlarkBigInt::from_value(other).is_some() {
             Ok(self.compare(other)? == Ordering::Equal)
         } else {
             Ok(false)
@@ -259,7 +286,7 @@ impl<'v> StarlarkValue<'v> for StarlarkFloat {
     fn percent(&self, other: Value, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
         f64_arith_bin_op(self.0, other, heap, "%", |a, b| {
             if b == 0.0 {
-                Err(ValueError::DivisionByZero.into())
+                Err(ValueError::FloatModuloByZero.into())
             } else {
                 let r = a % b;
                 if r == 0.0 {
@@ -274,7 +301,7 @@ impl<'v> StarlarkValue<'v> for StarlarkFloat {
     fn floor_div(&self, other: Value, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
         f64_arith_bin_op(self.0, other, heap, "//", |l, r| {
             if r == 0.0 {
-                Err(ValueError::DivisionByZero.into())
+                Err(ValueError::FlooredDivisionByZero.into())
             } else {
                 Ok((l / r).floor())
             }
@@ -282,6 +309,10 @@ impl<'v> StarlarkValue<'v> for StarlarkFloat {
     }
 
     fn compare(&self, other: Value) -> anyhow::Result<Ordering> {
+        if let Some(other) = StarlarkBigInt::from_value(other) {
+            // Compare exactly, rather than rounding the integer to a float.
+            return Ok(other.compare_f64(self.0).reverse());
+        }
         if let Some(other_float) = other.unpack_num().map(|n| n.as_float()) {
             // According to the spec (https://github.com/bazelbuild/starlark/blob/689f54426951638ef5b7c41a14d8fc48e65c5f77/spec.md#floating-point-numbers)
             // All NaN values compare equal to each other, but greater than any non-NaN float value.
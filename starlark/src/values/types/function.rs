@@ -22,6 +22,7 @@ use std::collections::HashMap;
 use derivative::Derivative;
 use derive_more::Display;
 use gazebo::{any::AnyLifetime, coerce::Coerce};
+use thiserror::Error;
 
 use crate as starlark;
 use crate::{
@@ -30,6 +31,8 @@ use crate::{
     values::{
         docs,
         docs::{DocItem, DocStringKind},
+        list::ListRef,
+        tuple::Tuple,
         AllocFrozenValue, AllocValue, Freeze, FrozenHeap, FrozenValue, FrozenValueTyped, Heap,
         SimpleValue, StarlarkValue, Trace, Value, ValueLike,
     },
@@ -198,7 +201,9 @@ impl<'v> StarlarkValue<'v> for NativeFunction {
         args: Arguments<'v, '_>,
         eval: &mut Evaluator<'v, '_>,
     ) -> anyhow::Result<Value<'v>> {
-        eval.with_call_stack(me, location, |eval| (self.function)(eval, args))
+        eval.with_call_hooks(me, location, args, |args, eval| {
+            eval.with_call_stack(me, location, |eval| (self.function)(eval, args))
+        })
     }
 
     fn extra_memory(&self) -> usize {
@@ -229,6 +234,137 @@ impl<'v> StarlarkValue<'v> for NativeFunction {
     }
 }
 
+#[derive(Debug, Error)]
+enum FunctionError {
+    #[error("Batched function `{0}` returned {1} results for {2} calls")]
+    BatchLength(String, usize, usize),
+}
+
+/// A native function whose calls can be batched, for when each call is expensive,
+/// e.g. a remote procedure call. Added with
+/// [`GlobalsBuilder::set_batched_function`](crate::environment::GlobalsBuilder::set_batched_function).
+///
+/// A list comprehension which calls the function with positional arguments, such as
+/// `[fetch(x) for x in xs if x]`, and calls nothing else in its element, conditions or
+/// nested loops, is compiled to evaluate the arguments of every iteration first, then pass
+/// them all to [`call_batch`](BatchedFunction::call_batch) at once. Any other call goes
+/// to [`call`](BatchedFunction::call).
+///
+/// Only list comprehensions are batched: calls in the body of a `for` statement, such as
+/// `for x in xs: results.append(fetch(x))`, are made one at a time, so rewrite such loops
+/// as comprehensions to batch them.
+pub trait BatchedFunction: Send + Sync + 'static {
+    /// Make a single call, with the positional arguments.
+    fn call<'v>(
+        &self,
+        args: &[Value<'v>],
+        eval: &mut Evaluator<'v, '_>,
+    ) -> anyhow::Result<Value<'v>>;
+
+    /// Make several calls, returning one result per set of arguments, in the same order.
+    /// By default, makes the calls one at a time.
+    fn call_batch<'v>(
+        &self,
+        args: &[Vec<Value<'v>>],
+        eval: &mut Evaluator<'v, '_>,
+    ) -> anyhow::Result<Vec<Value<'v>>> {
+        args.iter().map(|args| self.call(args, eval)).collect()
+    }
+}
+
+/// Starlark representation of a [`BatchedFunction`].
+#[derive(Derivative, AnyLifetime, Display)]
+#[derivative(Debug)]
+#[display(fmt = "{}", name)]
+pub(crate) struct NativeBatchedFunction {
+    #[derivative(Debug = "ignore")]
+    pub(crate) function: Box<dyn BatchedFunction>,
+    pub(crate) name: String,
+}
+
+impl AllocFrozenValue for NativeBatchedFunction {
+    fn alloc_frozen_value(self, heap: &FrozenHeap) -> FrozenValue {
+        heap.alloc_simple(self)
+    }
+}
+
+starlark_simple_value!(NativeBatchedFunction);
+
+impl<'v> StarlarkValue<'v> for NativeBatchedFunction {
+    starlark_type!(FUNCTION_TYPE);
+
+    fn invoke(
+        &self,
+        me: Value<'v>,
+        location: Option<Span>,
+        args: Arguments<'v, '_>,
+        eval: &mut Evaluator<'v, '_>,
+    ) -> anyhow::Result<Value<'v>> {
+        eval.with_call_hooks(me, location, args, |args, eval| {
+            eval.with_call_stack(me, location, |eval| {
+                args.no_named_args()?;
+                let args: Vec<Value> = args.positions(eval.heap())?.collect();
+                self.function.call(&args, eval)
+            })
+        })
+    }
+
+    fn extra_memory(&self) -> usize {
+        self.name.capacity()
+    }
+}
+
+/// Created by the compiler for a batched list comprehension, called with the list of
+/// argument tuples of every iteration, and returning the list of results.
+#[derive(Debug, Display)]
+#[display(fmt = "{}", function)]
+pub(crate) struct BatchCall {
+    /// Always a [`NativeBatchedFunction`].
+    pub(crate) function: FrozenValue,
+}
+
+starlark_simple_value!(BatchCall);
+
+impl<'v> StarlarkValue<'v> for BatchCall {
+    starlark_type!(FUNCTION_TYPE);
+
+    fn invoke(
+        &self,
+        _me: Value<'v>,
+        location: Option<Span>,
+        args: Arguments<'v, '_>,
+        eval: &mut Evaluator<'v, '_>,
+    ) -> anyhow::Result<Value<'v>> {
+        let function = self
+            .function
+            .downcast_ref::<NativeBatchedFunction>()
+            .unwrap();
+        let calls = args.positional1(eval.heap())?;
+        let calls: Vec<Vec<Value>> = ListRef::from_value(calls)
+            .unwrap()
+            .iter()
+            .map(|x| Tuple::from_value(x).unwrap().content().to_vec())
+            .collect();
+        if eval.has_call_hooks() {
+            // Call them one at a time, so the hooks see every call
+            let mut res = Vec::with_capacity(calls.len());
+            for args in &calls {
+                res.push(self.function.to_value().invoke_pos(location, args, eval)?);
+            }
+            return Ok(eval.heap().alloc_list(&res));
+        }
+        let res = eval.with_call_stack(self.function.to_value(), location, |eval| {
+            function.function.call_batch(&calls, eval)
+        })?;
+        if res.len() != calls.len() {
+            return Err(
+                FunctionError::BatchLength(function.name.clone(), res.len(), calls.len()).into(),
+            );
+        }
+        Ok(eval.heap().alloc_list(&res))
+    }
+}
+
 #[derive(Derivative, Display)]
 #[derivative(Debug)]
 #[display(fmt = "{}", name)]
@@ -255,7 +391,9 @@ impl<'v> StarlarkValue<'v> for NativeMethod {
         args: Arguments<'v, '_>,
         eval: &mut Evaluator<'v, '_>,
     ) -> anyhow::Result<Value<'v>> {
-        eval.with_call_stack(me, location, |eval| (self.function)(eval, this, args))
+        eval.with_call_hooks(me, location, args, |args, eval| {
+            eval.with_call_stack(me, location, |eval| (self.function)(eval, this, args))
+        })
     }
 
     fn documentation(&self) -> Option<DocItem> {
@@ -15,16 +15,41 @@
  * limitations under the License.
  */
 
-//! The integer type. Currently limited to 32 bit.
+//! The integer type, for integers which fit in 32 bits.
 //!
 //! Can be created with [`new_int`](Value::new_int) and unwrapped with [`unpack_int`](Value::unpack_int).
 //! Unlike most Starlark values, these aren't actually represented on the [`Heap`], but as special values.
-//! At some point in the future we plan to support arbitrary sized integers (as required by the
-//! [Starlark spec](https://github.com/bazelbuild/starlark/blob/master/spec.md#integers)), and those larger
-//! integer values will be stored on the heap.
+//! Integers have unbounded magnitude (as required by the
+//! [Starlark spec](https://github.com/bazelbuild/starlark/blob/master/spec.md#integers)), so results
+//! which don't fit in 32 bits are stored on the heap as a [`StarlarkBigInt`].
+//!
+//! Arithmetic follows the Starlark spec, and agrees with the Go implementation, as checked by
+//! `test_go_int` in `src/eval/tests/go.rs`:
+//!
+//! | Expression | Result | Notes |
+//! |---|---|---|
+//! | `-7 // 2` | `-4` | `//` rounds towards negative infinity |
+//! | `-7 % 2` | `1` | `%` takes the sign of the divisor |
+//! | `7 % -2` | `-1` | so `x == (x // y) * y + x % y` |
+//! | `7 / 2` | `3.5` | `/` always produces a float |
+//! | `7 // 2.0` | `3.0` | mixing in a float produces a float |
+//! | `-7.0 % 2` | `1.0` | floats follow the same rules as integers |
+//! | `1 / 0`, `1.0 / 0.0` | error | `floating-point division by zero` |
+//! | `1 // 0`, `1 // 0.0` | error | `floored division by zero` |
+//! | `1 % 0` | error | `integer modulo by zero` |
+//! | `1.0 % 0`, `1 % 0.0` | error | `floating-point modulo by zero` |
+//! | `(-2147483647 - 1) // -1` | `2147483648` | results outside 32 bits are big integers |
+//! | `(-2147483647 - 1) % -1` | `0` | |
+//! | `1 << 31` | `2147483648` | shifts never lose bits |
+//! | `-1 >> 40` | `-1` | right shifts are arithmetic, for any shift count |
+//! | `1 << -1` | error | `negative shift count: -1` |
+//! | `1 << 512` | error | `shift count too large: 512`, so a shift can't use up all memory |
+//!
+//! None of these can be configured: the spec defines them, and a program behaving differently
+//! depending on the embedder would defeat the point of matching it.
 
 use std::{
-    cmp::Ordering,
+    cmp::{self, Ordering},
     fmt::{self, Display, Write},
     hash::Hasher,
 };
@@ -32,9 +57,14 @@ use std::{
 use crate::{
     collections::{SmallHashResult, StarlarkHasher},
     values::{
-        basic::StarlarkValueBasic, error::ValueError, float::StarlarkFloat, layout::PointerI32,
-        num::Num, AllocFrozenValue, AllocValue, FrozenHeap, FrozenValue, Heap, StarlarkValue,
-        UnpackValue, Value,
+        basic::StarlarkValueBasic,
+        bigint::{BigIntError, StarlarkBigInt},
+        error::ValueError,
+        float::StarlarkFloat,
+        layout::PointerI32,
+        num::Num,
+        AllocFrozenValue, AllocValue, FrozenHeap, FrozenValue, Heap, StarlarkValue, UnpackValue,
+        Value,
     },
 };
 
@@ -62,21 +92,6 @@ impl UnpackValue<'_> for i32 {
     }
 }
 
-fn i64_arith_bin_op<'v, F>(
-    left: i32,
-    right: Value,
-    op: &'static str,
-    f: F,
-) -> anyhow::Result<Value<'v>>
-where
-    F: FnOnce(i32, i32) -> anyhow::Result<i32>,
-{
-    match right.unpack_int() {
-        Some(right) => Ok(Value::new_int(f(left, right)?)),
-        None => ValueError::unsupported_owned(INT_TYPE, op, Some(INT_TYPE)),
-    }
-}
-
 impl Display for PointerI32 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.get())
@@ -91,6 +106,7 @@ impl<'v> StarlarkValue<'v> for PointerI32 {
         Ok(match other.unpack_num() {
             Some(Num::Int(other)) => self.get() == other,
             Some(Num::Float(other)) => self.get() as f64 == other,
+            // Big integers are never in the range of `i32`.
             None => false,
         })
     }
@@ -113,143 +129,176 @@ impl<'v> StarlarkValue<'v> for PointerI32 {
     fn plus(&self, _heap: &'v Heap) -> anyhow::Result<Value<'v>> {
         Ok(Value::new_int(self.get()))
     }
-    fn minus(&self, _heap: &'v Heap) -> anyhow::Result<Value<'v>> {
-        self.get()
-            .checked_neg()
-            .map(Value::new_int)
-            .ok_or_else(|| ValueError::IntegerOverflow.into())
+    fn minus(&self, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        match self.get().checked_neg() {
+            Some(x) => Ok(Value::new_int(x)),
+            None => StarlarkBigInt::from(self.get()).minus(heap),
+        }
     }
     fn add(&self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
         match other.unpack_num() {
-            Some(Num::Int(other)) => self
-                .get()
-                .checked_add(other)
-                .map(Value::new_int)
-                .ok_or_else(|| ValueError::IntegerOverflow.into()),
+            Some(Num::Int(other)) => match self.get().checked_add(other) {
+                Some(x) => Ok(Value::new_int(x)),
+                None => StarlarkBigInt::from(self.get()).add(Value::new_int(other), heap),
+            },
             Some(Num::Float(_)) => StarlarkFloat(self.get() as f64).add(other, heap),
-            None => ValueError::unsupported_with(self, "+", other),
+            None => self.big(other, heap, "+", StarlarkBigInt::add),
         }
     }
     fn sub(&self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
         match other.unpack_num() {
-            Some(Num::Int(other)) => self
-                .get()
-                .checked_sub(other)
-                .map(Value::new_int)
-                .ok_or_else(|| ValueError::IntegerOverflow.into()),
+            Some(Num::Int(other)) => match self.get().checked_sub(other) {
+                Some(x) => Ok(Value::new_int(x)),
+                None => StarlarkBigInt::from(self.get()).sub(Value::new_int(other), heap),
+            },
             Some(Num::Float(_)) => StarlarkFloat(self.get() as f64).sub(other, heap),
-            None => ValueError::unsupported_with(self, "-", other),
+            None => self.big(other, heap, "-", StarlarkBigInt::sub),
         }
     }
     fn mul(&self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
         if let Some(other) = other.unpack_int() {
-            self.get()
-                .checked_mul(other)
-                .ok_or_else(|| ValueError::IntegerOverflow.into())
-                .map(Value::new_int)
+            match self.get().checked_mul(other) {
+                Some(x) => Ok(Value::new_int(x)),
+                None => StarlarkBigInt::from(self.get()).mul(Value::new_int(other), heap),
+            }
         } else {
             other.mul(Value::new_int(self.get()), heap)
         }
     }
     fn div(&self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
-        if other.unpack_num().is_some() {
+        if other.unpack_num().is_some() || StarlarkBigInt::from_value(other).is_some() {
             StarlarkFloat(self.get() as f64).div(other, heap)
         } else {
             ValueError::unsupported_with(self, "/", other)
         }
     }
     fn percent(&self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
-        if let Some(Num::Float(_)) = other.unpack_num() {
-            return StarlarkFloat(self.get() as f64).percent(other, heap);
+        let b = match other.unpack_num() {
+            Some(Num::Int(b)) => b,
+            Some(Num::Float(_)) => return StarlarkFloat(self.get() as f64).percent(other, heap),
+            None => return self.big(other, heap, "%", StarlarkBigInt::percent),
+        };
+        let a = self.get();
+        if b == 0 {
+            return Err(ValueError::ModuloByZero.into());
         }
-        i64_arith_bin_op(self.get(), other, "%", |a, b| {
-            if b == 0 {
-                return Err(ValueError::DivisionByZero.into());
-            }
-            // In Rust `i32::min_value() % -1` is overflow, but we should eval it to zero.
-            if self.get() == i32::min_value() && b == -1 {
-                return Ok(0);
-            }
-            let r = a % b;
-            if r == 0 {
-                Ok(0)
+        // In Rust `i32::min_value() % -1` is overflow, but we should eval it to zero.
+        if a == i32::min_value() && b == -1 {
+            return Ok(Value::new_int(0));
+        }
+        let r = a % b;
+        if r == 0 {
+            Ok(Value::new_int(0))
+        } else {
+            Ok(Value::new_int(if b.signum() != r.signum() {
+                r + b
             } else {
-                Ok(if b.signum() != r.signum() { r + b } else { r })
-            }
-        })
+                r
+            }))
+        }
     }
     fn floor_div(&self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
-        if let Some(Num::Float(_)) = other.unpack_num() {
-            return StarlarkFloat(self.get() as f64).floor_div(other, heap);
+        let b = match other.unpack_num() {
+            Some(Num::Int(b)) => b,
+            Some(Num::Float(_)) => return StarlarkFloat(self.get() as f64).floor_div(other, heap),
+            None => return self.big(other, heap, "//", StarlarkBigInt::floor_div),
+        };
+        let a = self.get();
+        if b == 0 {
+            return Err(ValueError::FlooredDivisionByZero.into());
+        }
+        let sig = b.signum() * a.signum();
+        let offset = if sig < 0 && a % b != 0 { 1 } else { 0 };
+        match a.checked_div(b) {
+            Some(div) => Ok(Value::new_int(div - offset)),
+            None => StarlarkBigInt::from(a).floor_div(other, heap),
         }
-        i64_arith_bin_op(self.get(), other, "//", |a, b| {
-            if b == 0 {
-                return Err(ValueError::DivisionByZero.into());
-            }
-            let sig = b.signum() * a.signum();
-            let offset = if sig < 0 && a % b != 0 { 1 } else { 0 };
-            match a.checked_div(b) {
-                Some(div) => Ok(div - offset),
-                None => Err(ValueError::IntegerOverflow.into()),
-            }
-        })
     }
 
     fn compare(&self, other: Value) -> anyhow::Result<Ordering> {
         match other.unpack_num() {
             Some(Num::Int(other)) => Ok(self.get().cmp(&other)),
             Some(Num::Float(_)) => StarlarkFloat(self.get() as f64).compare(other),
-            None => ValueError::unsupported_with(self, "==", other),
+            None => match StarlarkBigInt::from_value(other) {
+                Some(other) => Ok(StarlarkBigInt::from(self.get()).cmp(other)),
+                None => ValueError::unsupported_with(self, "==", other),
+            },
         }
     }
 
-    fn bit_and(&self, other: Value) -> anyhow::Result<Value<'v>> {
+    fn bit_and(&self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
         if let Some(other) = other.unpack_int() {
             Ok(Value::new_int(self.get() & other))
         } else {
-            ValueError::unsupported_with(self, "&", other)
+            self.big(other, heap, "&", StarlarkBigInt::bit_and)
         }
     }
 
-    fn bit_or(&self, other: Value) -> anyhow::Result<Value<'v>> {
+    fn bit_or(&self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
         if let Some(other) = other.unpack_int() {
             Ok(Value::new_int(self.get() | other))
         } else {
-            ValueError::unsupported_with(self, "|", other)
+            self.big(other, heap, "|", StarlarkBigInt::bit_or)
         }
     }
 
-    fn bit_xor(&self, other: Value) -> anyhow::Result<Value<'v>> {
+    fn bit_xor(&self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
         if let Some(other) = other.unpack_int() {
             Ok(Value::new_int(self.get() ^ other))
         } else {
-            ValueError::unsupported_with(self, "^", other)
+            self.big(other, heap, "^", StarlarkBigInt::bit_xor)
         }
     }
 
-    fn left_shift(&self, other: Value) -> anyhow::Result<Value<'v>> {
+    fn left_shift(&self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
         if let Some(other) = other.unpack_int() {
-            other
-                .try_into()
-                .ok()
-                .and_then(|unsigned_other| self.get().checked_shl(unsigned_other))
-                .map(Value::new_int)
-                .ok_or_else(|| ValueError::IntegerOverflow.into())
+            // Unlike `checked_shl`, promote if any bits (including the sign) are shifted out.
+            let res = other.try_into().ok().and_then(|unsigned_other: u32| {
+                let res = self.get().checked_shl(unsigned_other)?;
+                if res >> unsigned_other == self.get() {
+                    Some(res)
+                } else {
+                    None
+                }
+            });
+            match res {
+                Some(res) => Ok(Value::new_int(res)),
+                None => StarlarkBigInt::from(self.get()).left_shift(Value::new_int(other), heap),
+            }
         } else {
-            ValueError::unsupported_with(self, "<<", other)
+            self.big(other, heap, "<<", StarlarkBigInt::left_shift)
         }
     }
 
-    fn right_shift(&self, other: Value) -> anyhow::Result<Value<'v>> {
+    fn right_shift(&self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
         if let Some(other) = other.unpack_int() {
+            // Shifting right by 32 or more leaves just the sign.
             other
                 .try_into()
                 .ok()
-                .and_then(|unsigned_other| self.get().checked_shr(unsigned_other))
+                .map(|unsigned_other: u32| self.get() >> cmp::min(unsigned_other, 31))
                 .map(Value::new_int)
-                .ok_or_else(|| ValueError::IntegerOverflow.into())
+                .ok_or_else(|| BigIntError::NegativeShift(other.into()).into())
+        } else {
+            self.big(other, heap, ">>", StarlarkBigInt::right_shift)
+        }
+    }
+}
+
+impl PointerI32 {
+    /// Apply an operator whose right operand isn't an `i32`, by treating this as a big integer
+    /// if the right operand is one.
+    fn big<'v>(
+        &self,
+        other: Value<'v>,
+        heap: &'v Heap,
+        op: &'static str,
+        f: fn(&StarlarkBigInt, Value<'v>, &'v Heap) -> anyhow::Result<Value<'v>>,
+    ) -> anyhow::Result<Value<'v>> {
+        if StarlarkBigInt::from_value(other).is_some() {
+            f(&StarlarkBigInt::from(self.get()), other, heap)
         } else {
-            ValueError::unsupported_with(self, ">>", other)
+            ValueError::unsupported_with(self, op, other)
         }
     }
 }
@@ -282,4 +331,53 @@ mod tests {
 "#,
         );
     }
+
+    #[test]
+    fn test_arithmetic_edge_cases() {
+        // Results agree with the Go implementation
+        assert::all_true(
+            r#"
+-7 // 2 == -4
+7 // -2 == -4
+-7 // -2 == 3
+-6 // 2 == -3
+-7 % 2 == 1
+7 % -2 == -1
+-7 % -2 == -1
+-6 % 2 == 0
+7 / 2 == 3.5
+-7 / 2 == -3.5
+7 // 2.0 == 3.0
+-7 // 2.0 == -4.0
+-7.0 % 2 == 1.0
+7.0 % -2 == -1.0
+7.5 % 2 == 1.5
+(-2147483647 - 1) % -1 == 0
+2147483647 // -1 == -2147483647
+1 << 30 == 1073741824
+-1 << 31 == -2147483647 - 1
+-1 >> 40 == -1
+1 >> 40 == 0
+(-2147483647 - 1) // -1 == 2147483648
+-(-2147483647 - 1) == 2147483648
+1 << 31 == 2147483648
+3 << 30 == 3221225472
+1 << 32 == 4294967296
+"#,
+        );
+        for (x, msg) in [
+            ("1 / 0", "floating-point division by zero"),
+            ("1.0 / 0", "floating-point division by zero"),
+            ("1 // 0", "floored division by zero"),
+            ("1 // 0.0", "floored division by zero"),
+            ("1 % 0", "integer modulo by zero"),
+            ("1.5 % 0.0", "floating-point modulo by zero"),
+        ] {
+            assert::fail(x, msg);
+        }
+        for x in &["1 << -1", "1 >> -1"] {
+            assert::fail(x, "negative shift count: -1");
+        }
+        assert::fail("1 << (1 << 70)", "shift count too large");
+    }
 }
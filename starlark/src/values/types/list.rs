@@ -25,12 +25,15 @@ use std::{
     fmt::{self, Debug, Display, Formatter},
     intrinsics::{likely, unlikely},
     marker::PhantomData,
+    mem,
     ops::Deref,
     slice,
 };
 
+use either::Either;
 use gazebo::{
     any::AnyLifetime,
+    cast,
     coerce::{coerce, coerce_ref, Coerce},
     prelude::*,
 };
@@ -43,7 +46,8 @@ use crate::{
         comparison::{compare_slice, equals_slice},
         display::display_container,
         error::ValueError,
-        index::{apply_slice, convert_index},
+        index::{apply_slice, convert_index, convert_slice_indices},
+        shared_slice::{SharedSlice, MIN_SHARED_SLICE_LEN},
         AllocFrozenValue, AllocValue, FrozenHeap, FrozenStringValue, FrozenValue, Heap,
         StarlarkValue, UnpackValue, Value, ValueLike, ValueTyped,
     },
@@ -56,8 +60,9 @@ pub(crate) struct ListGen<T>(pub(crate) T);
 /// Define the list type. See [`List`] and [`FrozenList`] as the two possible representations.
 #[derive(Trace, Debug, AnyLifetime)]
 pub struct List<'v> {
-    /// The data stored by the list.
-    pub(crate) content: Cell<ValueTyped<'v, Array<'v>>>,
+    /// The data stored by the list: an [`Array`], or a [`SharedSlice`] of the elements
+    /// of a frozen list this list was sliced from, until the list is first mutated.
+    pub(crate) content: Cell<Value<'v>>,
 }
 
 /// Define the list type. See [`List`] and [`FrozenList`] as the two possible representations.
@@ -83,6 +88,9 @@ impl ListGen<FrozenList> {
     }
 }
 
+/// A read-only view of a list, either mutable or frozen, which can be obtained
+/// without copying the elements. Use [`ListRef::from_value`] to obtain one, or take
+/// `&ListRef` as the parameter type of a native function.
 #[repr(transparent)]
 #[derive(Coerce)]
 pub struct ListRef<'v> {
@@ -94,6 +102,25 @@ impl<'v> ListRef<'v> {
         coerce(slice)
     }
 
+    /// Obtain a view of the list pointed at by a [`Value`], or [`None`] if it isn't a list.
+    pub fn from_value(x: Value<'v>) -> Option<&'v ListRef<'v>> {
+        List::from_value(x)
+    }
+
+    /// Like [`ListRef::from_value`], but returns an error mentioning the type of the value
+    /// if it isn't a list.
+    pub fn try_from_value(x: Value<'v>) -> anyhow::Result<&'v ListRef<'v>> {
+        <&ListRef>::unpack_param(x)
+    }
+
+    pub fn len(&self) -> usize {
+        self.content.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.content.is_empty()
+    }
+
     pub fn content(&self) -> &[Value<'v>] {
         &self.content
     }
@@ -143,12 +170,45 @@ impl<'v> List<'v> {
 
     /// Return an error if there's at least one iterator over the list.
     fn check_can_mutate(&self) -> anyhow::Result<()> {
-        if unlikely(self.content.get().as_ref().iter_count_is_non_zero()) {
+        let iterating = match self.shared() {
+            None => self.array().iter_count_is_non_zero(),
+            Some(shared) => shared.iter_count_is_non_zero(),
+        };
+        if unlikely(iterating) {
             return Err(ValueError::MutationDuringIteration.into());
         }
         Ok(())
     }
 
+    #[cold]
+    #[inline(never)]
+    fn unshare_slow(&self, shared: &SharedSlice<'v>) {
+        let new_array = shared.heap().alloc_array(shared.len());
+        new_array.extend_from_slice(shared.content());
+        self.content.set(new_array.to_value());
+    }
+
+    /// Copy the shared elements into the list's own array, before it is mutated.
+    #[inline(always)]
+    fn unshare(&self) {
+        if let Some(shared) = self.shared() {
+            self.unshare_slow(shared);
+        }
+    }
+
+    /// The elements of a frozen list, while the list reads them instead of its own array.
+    #[inline(always)]
+    fn shared(&self) -> Option<&'v SharedSlice<'v>> {
+        self.content.get().downcast_ref::<SharedSlice>()
+    }
+
+    /// The list's own array. Must not be called while the list is shared.
+    #[inline(always)]
+    fn array(&self) -> ValueTyped<'v, Array<'v>> {
+        debug_assert!(self.shared().is_none());
+        unsafe { ValueTyped::new_unchecked(self.content.get()) }
+    }
+
     #[cold]
     #[inline(never)]
     fn reserve_additional_slow(&self, additional: usize, heap: &'v Heap) {
@@ -161,12 +221,12 @@ impl<'v> List<'v> {
 
         let new_array = heap.alloc_array(new_cap);
         new_array.extend_from_slice(self.content());
-        self.content.set(new_array);
+        self.content.set(new_array.to_value());
     }
 
     #[inline(always)]
     fn reserve_additional(&self, additional: usize, heap: &'v Heap) {
-        if likely(self.content.get().as_ref().remaining_capacity() >= additional) {
+        if likely(self.array().remaining_capacity() >= additional) {
             return;
         }
 
@@ -174,12 +234,14 @@ impl<'v> List<'v> {
     }
 
     pub(crate) fn double(&self, heap: &'v Heap) {
+        self.unshare();
         self.reserve_additional(self.len(), heap);
-        self.content.get().double();
+        self.array().double();
     }
 
     #[inline]
     pub(crate) fn extend<I: IntoIterator<Item = Value<'v>>>(&self, iter: I, heap: &'v Heap) {
+        self.unshare();
         let iter = iter.into_iter();
         let (lo, hi) = iter.size_hint();
         match hi {
@@ -187,12 +249,12 @@ impl<'v> List<'v> {
                 // Exact size iterator.
                 self.reserve_additional(lo, heap);
                 // Extend will panic if upper bound is provided incorrectly.
-                self.content.get().extend(iter);
+                self.array().extend(iter);
             }
-            Some(hi) if self.content.get().remaining_capacity() >= hi => {
+            Some(hi) if self.array().remaining_capacity() >= hi => {
                 // Enough capacity for upper bound.
                 // Extend will panic if upper bound is provided incorrectly.
-                self.content.get().extend(iter);
+                self.array().extend(iter);
             }
             _ => {
                 // Default slow version.
@@ -205,21 +267,28 @@ impl<'v> List<'v> {
     }
 
     pub(crate) fn push(&self, value: Value<'v>, heap: &'v Heap) {
+        self.unshare();
         self.reserve_additional(1, heap);
-        self.content.get().push(value);
+        self.array().push(value);
     }
 
     pub(crate) fn clear(&self) {
-        self.content.get().clear();
+        match self.shared() {
+            // Nothing to copy, the elements are dropped anyway.
+            Some(shared) => self.content.set(shared.heap().alloc_array(0).to_value()),
+            None => self.array().clear(),
+        }
     }
 
     pub(crate) fn insert(&self, index: usize, value: Value<'v>, heap: &'v Heap) {
+        self.unshare();
         self.reserve_additional(1, heap);
-        self.content.get().insert(index, value);
+        self.array().insert(index, value);
     }
 
     pub(crate) fn remove(&self, index: usize) -> Value<'v> {
-        self.content.get().remove(index)
+        self.unshare();
+        self.array().remove(index)
     }
 }
 
@@ -305,13 +374,22 @@ impl<'v> List<'v> {
 
     pub(crate) fn new(content: ValueTyped<'v, Array<'v>>) -> Self {
         List {
-            content: Cell::new(content),
+            content: Cell::new(content.to_value()),
+        }
+    }
+
+    pub(crate) fn new_shared(shared: ValueTyped<'v, SharedSlice<'v>>) -> Self {
+        List {
+            content: Cell::new(shared.to_value()),
         }
     }
 
     /// Obtain the length of the list.
     pub fn len(&self) -> usize {
-        self.content.get().len()
+        match self.shared() {
+            None => self.array().len(),
+            Some(shared) => shared.len(),
+        }
     }
 
     /// List content.
@@ -319,7 +397,10 @@ impl<'v> List<'v> {
     /// Note this operation does not prevent mutation of this list while
     /// holding the slice. But such mutation does not violate memory-safety.
     pub fn content(&self) -> &[Value<'v>] {
-        self.content.get().as_ref().content()
+        match self.shared() {
+            None => self.array().as_ref().content(),
+            Some(shared) => shared.content(),
+        }
     }
 
     /// Iterate over the elements in the list.
@@ -327,13 +408,16 @@ impl<'v> List<'v> {
     where
         'v: 'a,
     {
-        self.content.get().as_ref().iter()
+        match self.shared() {
+            None => Either::Left(self.array().as_ref().iter()),
+            Some(shared) => Either::Right(shared.iter()),
+        }
     }
 }
 
 impl<'v> Display for List<'v> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        display_list(self.content.get().content(), f)
+        display_list(self.content(), f)
     }
 }
 
@@ -370,16 +454,20 @@ pub(crate) trait ListLike<'v>: Debug {
         &self,
         f: &mut dyn FnMut(&mut dyn Iterator<Item = Value<'v>>) -> anyhow::Result<()>,
     ) -> anyhow::Result<()>;
+    /// The content as elements of a frozen list, if it is known to be one,
+    /// so slices can share it rather than copy.
+    fn frozen_content(&self) -> Option<&[FrozenValue]>;
 }
 
 impl<'v> ListLike<'v> for List<'v> {
     fn content(&self) -> &[Value<'v>] {
-        self.content.get().as_ref().content()
+        List::content(self)
     }
 
     fn set_at(&self, i: usize, v: Value<'v>) -> anyhow::Result<()> {
         self.check_can_mutate()?;
-        self.content.get().set_at(i, v);
+        self.unshare();
+        self.array().set_at(i, v);
         Ok(())
     }
 
@@ -387,14 +475,18 @@ impl<'v> ListLike<'v> for List<'v> {
     where
         'v: 'a,
     {
-        box self.content.get().as_ref().iter()
+        box self.iter()
     }
 
     fn with_iterator(
         &self,
         f: &mut dyn FnMut(&mut dyn Iterator<Item = Value<'v>>) -> anyhow::Result<()>,
     ) -> anyhow::Result<()> {
-        f(&mut self.content.get().iter())
+        f(&mut self.iter())
+    }
+
+    fn frozen_content(&self) -> Option<&[FrozenValue]> {
+        self.shared().map(|shared| shared.frozen_content())
     }
 }
 
@@ -420,6 +512,10 @@ impl<'v> ListLike<'v> for FrozenList {
     ) -> anyhow::Result<()> {
         f(&mut coerce(self.content()).iter().copied())
     }
+
+    fn frozen_content(&self) -> Option<&[FrozenValue]> {
+        Some(self.content())
+    }
 }
 
 impl<T: Display> Display for ListGen<T> {
@@ -459,6 +555,15 @@ where
         collector.push_str("[...]");
     }
 
+    fn debug_children(&self) -> Vec<(String, Value<'v>)> {
+        self.0
+            .content()
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (i.to_string(), *v))
+            .collect()
+    }
+
     fn collect_json(&self, collector: &mut String) -> anyhow::Result<()> {
         collector.push('[');
         for (i, e) in self.0.content().iter().enumerate() {
@@ -519,6 +624,15 @@ where
         heap: &'v Heap,
     ) -> anyhow::Result<Value<'v>> {
         let xs = self.0.content();
+        if let Some(frozen) = self.0.frozen_content() {
+            let (lo, hi, step) = convert_slice_indices(xs.len() as i32, start, stop, stride)?;
+            if step == 1 && hi - lo >= MIN_SHARED_SLICE_LEN as i32 {
+                // Frozen lists live as long as the heap which references them,
+                // so the slice can borrow their elements for `'v`.
+                let frozen = unsafe { cast::ptr_lifetime(&frozen[lo as usize..hi as usize]) };
+                return Ok(heap.alloc_list_shared(frozen));
+            }
+        }
         let res = apply_slice(&*xs, start, stop, stride)?;
         Ok(heap.alloc_list(&res))
     }
@@ -551,7 +665,9 @@ where
 
     fn mul(&self, other: Value, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
         let l = i32::unpack_param(other)?;
-        let mut result = Vec::with_capacity(self.0.content().len() * cmp::max(0, l) as usize);
+        let len = self.0.content().len() * cmp::max(0, l) as usize;
+        heap.check_allocation_limit(len.saturating_mul(mem::size_of::<Value>()))?;
+        let mut result = Vec::with_capacity(len);
         for _ in 0..l {
             result.extend(self.0.content().iter());
         }
@@ -565,19 +681,37 @@ where
 }
 
 /// Like `ValueOf`, but only validates item types; does not construct or store a
-/// vec. Use `to_vec` to get a Vec.
+/// vec. Use `iter` to walk the items as Rust values, or `to_vec` to get a Vec.
 pub struct ListOf<'v, V: UnpackValue<'v>> {
     value: Value<'v>,
     phantom: PhantomData<V>,
 }
 
 impl<'v, V: UnpackValue<'v>> ListOf<'v, V> {
-    pub fn to_vec(&self) -> Vec<V> {
-        List::from_value(self.value)
-            .expect("already validated as a list")
+    fn list(&self) -> &'v ListRef<'v> {
+        List::from_value(self.value).expect("already validated as a list")
+    }
+
+    /// Number of items in the list.
+    pub fn len(&self) -> usize {
+        self.list().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Iterate over the items, unpacking each one as it is reached.
+    /// Items are only validated when the list is unpacked, so the list must not be
+    /// mutated with items of other types while iterating.
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = V> + 'v {
+        self.list()
             .iter()
             .map(|v| V::unpack_value(v).expect("already validated value"))
-            .collect()
+    }
+
+    pub fn to_vec(&self) -> Vec<V> {
+        self.iter().collect()
     }
 }
 
@@ -607,6 +741,28 @@ impl<'v, V: UnpackValue<'v>> UnpackValue<'v> for ListOf<'v, V> {
             None
         }
     }
+
+    fn unpack_named_param(value: Value<'v>, param_name: &str) -> anyhow::Result<Self> {
+        if let Some(x) = Self::unpack_value(value) {
+            return Ok(x);
+        }
+        // Point at the offending item, rather than just saying we got a `list`.
+        let bad_item = List::from_value(value).and_then(|list| {
+            list.iter()
+                .enumerate()
+                .find(|(_, v)| V::unpack_value(*v).is_none())
+        });
+        let actual = match bad_item {
+            Some((i, v)) => format!("list with item {} of type {}", i, v.get_type()),
+            None => value.get_type().to_owned(),
+        };
+        Err(ValueError::IncorrectParameterTypeNamedWithExpected(
+            param_name.to_owned(),
+            Self::expected(),
+            actual,
+        )
+        .into())
+    }
 }
 
 impl<'v, V: UnpackValue<'v>> Deref for ListOf<'v, V> {
@@ -619,7 +775,10 @@ impl<'v, V: UnpackValue<'v>> Deref for ListOf<'v, V> {
 
 #[cfg(test)]
 mod tests {
-    use crate::assert::{self, Assert};
+    use crate::{
+        assert::{self, Assert},
+        values::{list::ListRef, FrozenHeap, Heap, Value},
+    };
 
     #[test]
     fn test_to_str() {
@@ -693,4 +852,102 @@ def list_result():
         );
         a.is_true("load('x','list_result')\nx = list_result()\nx += [8]\nx == [1, 2, 4, 8]");
     }
+
+    #[test]
+    fn test_list_ref() {
+        let heap = Heap::new();
+        let list = heap.alloc(vec![1, 2, 3]);
+        let view = ListRef::try_from_value(list).unwrap();
+        assert_eq!(view.len(), 3);
+        assert_eq!(
+            view.iter().map(|x| x.unpack_int()).collect::<Vec<_>>(),
+            vec![Some(1), Some(2), Some(3)]
+        );
+        assert!(ListRef::from_value(heap.alloc((1, 2))).is_none());
+        assert!(
+            ListRef::try_from_value(Value::new_int(1))
+                .unwrap_err()
+                .to_string()
+                .contains("expected `list`, actual `int`")
+        );
+
+        let frozen = FrozenHeap::new();
+        let list = frozen.alloc(vec![4]).to_value();
+        assert_eq!(
+            ListRef::from_value(list).unwrap().content()[0].unpack_int(),
+            Some(4)
+        );
+    }
+
+    #[test]
+    fn test_slice_frozen_list_shares_content() {
+        let frozen = FrozenHeap::new();
+        let list = frozen.alloc((0..20).collect::<Vec<i32>>()).to_value();
+        let heap = Heap::new();
+        let slice = list.slice(Some(Value::new_int(2)), None, None, &heap).unwrap();
+        assert_eq!(
+            ListRef::from_value(list).unwrap()[2..].as_ptr(),
+            ListRef::from_value(slice).unwrap().as_ptr()
+        );
+        // Short slices are copied.
+        let slice = list.slice(Some(Value::new_int(18)), None, None, &heap).unwrap();
+        assert_ne!(
+            ListRef::from_value(list).unwrap()[18..].as_ptr(),
+            ListRef::from_value(slice).unwrap().as_ptr()
+        );
+    }
+
+    #[test]
+    fn test_slice_frozen_list() {
+        let mut a = Assert::new();
+        a.module("x", "xs = list(range(20))");
+        a.module("y", "load('x', 'xs')\nys = xs[1:]");
+        a.pass(
+            r#"
+load('x', 'xs')
+def test():
+    ys = xs[2:]
+    zs = ys[3:15]
+    ys.append(20)
+    zs[0] = "a"
+    zs.pop()
+    assert_eq(xs, list(range(20)))
+    assert_eq(ys, list(range(2, 21)))
+    assert_eq(zs, ["a"] + list(range(6, 16)))
+    assert_eq(xs[::2], list(range(0, 20, 2)))
+    total = 0
+    ws = xs
+    for _ in range(len(ws)):
+        total += ws[0]
+        ws = ws[1:]
+    assert_eq(total, 190)
+test()
+"#,
+        );
+        a.pass(
+            r#"
+load('x', 'xs')
+def test():
+    ys = xs[1:]
+    ys.clear()
+    assert_eq(ys, [])
+    ys.append(1)
+    assert_eq(ys, [1])
+test()
+"#,
+        );
+        a.fail(
+            r#"
+load('x', 'xs')
+def test():
+    ys = xs[1:]
+    for y in ys:
+        ys.append(y)
+test()
+"#,
+            "mutate an iterable",
+        );
+        a.is_true("load('y', 'ys')\nys == list(range(1, 20))");
+        a.fail("load('y', 'ys')\nys.append(1)", "Immutable");
+    }
 }
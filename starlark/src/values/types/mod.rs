@@ -17,16 +17,21 @@
 
 pub mod any;
 pub mod array;
+pub mod bigint;
 pub mod bool;
+pub mod bytes;
 pub mod dict;
 pub mod enumeration;
 pub mod float;
 pub mod function;
 pub mod int;
 pub mod list;
+pub mod namedtuple;
 pub mod none;
 pub mod range;
 pub mod record;
+pub mod set;
+pub(crate) mod shared_slice;
 pub mod string;
 pub mod structs;
 pub mod tuple;
@@ -0,0 +1,458 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! A `namedtuple` type, a tuple whose elements can also be accessed by name.
+//!
+//! Calling `namedtuple()` produces a [`NamedTupleType`]. Calling [`NamedTupleType`] produces a
+//! [`NamedTuple`], taking the elements either positionally or by name. Like a record, the
+//! field names are only stored once, in the type.
+//!
+//! ```
+//! # starlark::assert::is_true(r#"
+//! Point = namedtuple("x", "y")
+//! p = Point(1, y=2)
+//! p.x == 1 and p[1] == 2 and list(p) == [1, 2]
+//! # "#);
+//! ```
+
+use std::{
+    cell::RefCell,
+    cmp::Ordering,
+    fmt::{self, Debug, Display},
+    mem,
+};
+
+use either::Either;
+use gazebo::{
+    any::AnyLifetime,
+    cell::AsARef,
+    coerce::{coerce_ref, Coerce},
+};
+use thiserror::Error;
+
+use crate::{
+    self as starlark,
+    codemap::Span,
+    collections::{SmallSet, StarlarkHasher},
+    eval::{Arguments, Evaluator, ParametersSpec},
+    values::{
+        comparison::{compare_slice, equals_slice},
+        display::display_container,
+        function::FUNCTION_TYPE,
+        index::convert_index,
+        Freeze, Freezer, FrozenValue, Heap, StarlarkValue, StringValue, Trace, Value, ValueError,
+        ValueLike,
+    },
+};
+
+#[derive(Error, Debug)]
+enum NamedTupleError {
+    #[error("namedtuple field names must all be distinct, but repeated `{0}`")]
+    DuplicateField(String),
+}
+
+/// The result of `namedtuple()`, being the type of named tuples.
+#[derive(Debug, Trace)]
+pub struct NamedTupleTypeGen<V, Typ> {
+    /// The name of this type, e.g. Point
+    /// Either `Option<String>` or a `RefCell` thereof.
+    typ: Typ,
+    /// The field names, all of which are strings.
+    fields: Vec<V>,
+    /// Computed in advance, as for records.
+    parameter_spec: ParametersSpec<FrozenValue>,
+}
+
+impl<V: Display, Typ> Display for NamedTupleTypeGen<V, Typ> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        display_container(f, "namedtuple(", ")", self.fields.iter())
+    }
+}
+
+pub type NamedTupleType<'v> = NamedTupleTypeGen<Value<'v>, RefCell<Option<String>>>;
+pub type FrozenNamedTupleType = NamedTupleTypeGen<FrozenValue, Option<String>>;
+
+/// An actual named tuple.
+#[derive(Clone, Debug, Trace, Coerce, Freeze)]
+#[repr(C)]
+pub struct NamedTupleGen<V> {
+    typ: V, // Must be NamedTupleType
+    values: Vec<V>,
+}
+
+starlark_complex_values!(NamedTupleType);
+starlark_complex_value!(pub NamedTuple);
+
+impl<'v> NamedTupleType<'v> {
+    pub(crate) fn new(fields: Vec<StringValue<'v>>) -> anyhow::Result<Self> {
+        let mut seen = SmallSet::with_capacity(fields.len());
+        let mut parameter_spec =
+            ParametersSpec::with_capacity("namedtuple".to_owned(), fields.len());
+        for field in &fields {
+            if !seen.insert(field.as_str()) {
+                return Err(NamedTupleError::DuplicateField(field.as_str().to_owned()).into());
+            }
+            parameter_spec.required(field.as_str());
+        }
+        Ok(Self {
+            typ: RefCell::new(None),
+            fields: fields.into_iter().map(|x| x.to_value()).collect(),
+            parameter_spec,
+        })
+    }
+}
+
+impl<'v, V: ValueLike<'v>, Typ: AsARef<Option<String>>> NamedTupleTypeGen<V, Typ> {
+    /// A copy of this type allocated on `heap`, equal to it, see
+    /// [`Value::deep_copy_to`](crate::values::Value::deep_copy_to).
+    pub(crate) fn copy_to<'v2>(&self, heap: &'v2 Heap) -> NamedTupleType<'v2> {
+        NamedTupleType {
+            typ: RefCell::new((*self.typ.as_aref()).clone()),
+            fields: self
+                .fields
+                .iter()
+                .map(|x| heap.alloc_str(field_name(*x)))
+                .collect(),
+            parameter_spec: self.parameter_spec.clone(),
+        }
+    }
+}
+
+impl<'v> Freeze for NamedTupleType<'v> {
+    type Frozen = FrozenNamedTupleType;
+    fn freeze(self, freezer: &Freezer) -> anyhow::Result<Self::Frozen> {
+        Ok(FrozenNamedTupleType {
+            typ: self.typ.into_inner(),
+            fields: self.fields.freeze(freezer)?,
+            parameter_spec: self.parameter_spec,
+        })
+    }
+}
+
+fn field_name<'v>(x: impl ValueLike<'v>) -> &'v str {
+    // Safe to unwrap because we always ensure fields are strings
+    x.to_value().unpack_str().unwrap()
+}
+
+impl<'v, Typ, V: ValueLike<'v>> StarlarkValue<'v> for NamedTupleTypeGen<V, Typ>
+where
+    Self: AnyLifetime<'v>,
+    Typ: AsARef<Option<String>> + Debug,
+{
+    starlark_type!(FUNCTION_TYPE);
+
+    fn invoke(
+        &self,
+        me: Value<'v>,
+        _location: Option<Span>,
+        args: Arguments<'v, '_>,
+        eval: &mut Evaluator<'v, '_>,
+    ) -> anyhow::Result<Value<'v>> {
+        self.parameter_spec
+            .parser(args, eval, |mut param_parser, eval| {
+                let mut values = Vec::with_capacity(self.fields.len());
+                for field in &self.fields {
+                    values.push(param_parser.next(field_name(*field))?);
+                }
+                Ok(eval.heap().alloc_complex(NamedTuple { typ: me, values }))
+            })
+    }
+
+    fn extra_memory(&self) -> usize {
+        let typ = self.typ.as_aref();
+        typ.as_ref().map_or(0, |s| s.capacity()) + self.fields.capacity() * mem::size_of::<V>()
+    }
+
+    fn dir_attr(&self) -> Vec<String> {
+        vec!["type".to_owned()]
+    }
+
+    fn has_attr(&self, attribute: &str) -> bool {
+        attribute == "type"
+    }
+
+    fn get_attr(&self, attribute: &str, heap: &'v Heap) -> Option<Value<'v>> {
+        if attribute == "type" {
+            Some(heap.alloc(self.typ.as_aref().as_deref().unwrap_or(NamedTuple::TYPE)))
+        } else {
+            None
+        }
+    }
+
+    fn equals(&self, other: Value<'v>) -> anyhow::Result<bool> {
+        fn eq<'v>(
+            a: &NamedTupleTypeGen<impl ValueLike<'v>, impl AsARef<Option<String>>>,
+            b: &NamedTupleTypeGen<impl ValueLike<'v>, impl AsARef<Option<String>>>,
+        ) -> bool {
+            a.typ.as_aref() == b.typ.as_aref()
+                && a.fields.len() == b.fields.len()
+                && a.fields
+                    .iter()
+                    .zip(&b.fields)
+                    .all(|(x, y)| field_name(*x) == field_name(*y))
+        }
+
+        match NamedTupleType::from_value(other) {
+            Some(Either::Left(other)) => Ok(eq(self, &*other)),
+            Some(Either::Right(other)) => Ok(eq(self, &*other)),
+            _ => Ok(false),
+        }
+    }
+
+    fn export_as(&self, variable_name: &str, _eval: &mut Evaluator<'v, '_>) {
+        if let Some(typ) = self.typ.as_ref_cell() {
+            let mut typ = typ.borrow_mut();
+            if typ.is_none() {
+                *typ = Some(variable_name.to_owned())
+            }
+        }
+    }
+}
+
+impl<'v> NamedTuple<'v> {
+    /// A named tuple of type `typ`, which must be a named tuple type with a field
+    /// for each of `values`.
+    pub(crate) fn new(typ: Value<'v>, values: Vec<Value<'v>>) -> Self {
+        debug_assert!(NamedTupleType::from_value(typ).is_some());
+        Self { typ, values }
+    }
+}
+
+impl<'v, V: ValueLike<'v>> NamedTupleGen<V> {
+    /// The result of calling `type()` on a named tuple.
+    pub const TYPE: &'static str = "namedtuple";
+
+    pub(crate) fn typ(&self) -> Value<'v> {
+        self.typ.to_value()
+    }
+
+    fn get_type_name(&self) -> Option<String> {
+        // Safe to unwrap because we always ensure typ is NamedTupleType
+        match NamedTupleType::from_value(self.typ.to_value()).unwrap() {
+            Either::Left(x) => x.typ.borrow().clone(),
+            Either::Right(x) => x.typ.clone(),
+        }
+    }
+
+    fn get_fields(&self) -> &'v [Value<'v>] {
+        match NamedTupleType::from_value(self.typ.to_value()).unwrap() {
+            Either::Left(x) => &x.fields,
+            Either::Right(x) => coerce_ref(&x.fields),
+        }
+    }
+
+    pub(crate) fn iter<'a>(&'a self) -> impl Iterator<Item = Value<'v>> + 'a
+    where
+        'v: 'a,
+    {
+        self.values.iter().map(|x| x.to_value())
+    }
+}
+
+impl<'v, V: ValueLike<'v>> Display for NamedTupleGen<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}(",
+            self.get_type_name().as_deref().unwrap_or(NamedTuple::TYPE)
+        )?;
+        for (i, (name, v)) in self.get_fields().iter().zip(&self.values).enumerate() {
+            if i != 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}=", field_name(*name))?;
+            Display::fmt(v, f)?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl<'v, V: ValueLike<'v>> StarlarkValue<'v> for NamedTupleGen<V>
+where
+    Self: AnyLifetime<'v>,
+{
+    starlark_type!(NamedTuple::TYPE);
+
+    fn matches_type(&self, ty: &str) -> bool {
+        ty == NamedTuple::TYPE || Some(ty) == self.get_type_name().as_deref()
+    }
+
+    fn to_bool(&self) -> bool {
+        !self.values.is_empty()
+    }
+
+    fn collect_json(&self, collector: &mut String) -> anyhow::Result<()> {
+        collector.push('[');
+        for (i, e) in self.values.iter().enumerate() {
+            if i != 0 {
+                collector.push(',');
+            }
+            e.collect_json(collector)?;
+        }
+        collector.push(']');
+        Ok(())
+    }
+
+    fn equals(&self, other: Value<'v>) -> anyhow::Result<bool> {
+        match NamedTuple::from_value(other) {
+            Some(other) if self.typ.equals(other.typ)? => {
+                equals_slice(&self.values, &other.values, |x, y| x.equals(*y))
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn compare(&self, other: Value<'v>) -> anyhow::Result<Ordering> {
+        match NamedTuple::from_value(other) {
+            Some(other) if self.typ.equals(other.typ)? => {
+                compare_slice(&self.values, &other.values, |x, y| x.compare(*y))
+            }
+            _ => ValueError::unsupported_with(self, "cmp()", other),
+        }
+    }
+
+    fn write_hash(&self, hasher: &mut StarlarkHasher) -> anyhow::Result<()> {
+        for v in &self.values {
+            v.write_hash(hasher)?;
+        }
+        Ok(())
+    }
+
+    fn at(&self, index: Value, _heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        let i = convert_index(index, self.values.len() as i32)? as usize;
+        Ok(self.values[i].to_value())
+    }
+
+    fn length(&self) -> anyhow::Result<i32> {
+        Ok(self.values.len() as i32)
+    }
+
+    fn is_in(&self, other: Value<'v>) -> anyhow::Result<bool> {
+        for x in &self.values {
+            if x.equals(other)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn iterate<'a>(
+        &'a self,
+        _heap: &'v Heap,
+    ) -> anyhow::Result<Box<dyn Iterator<Item = Value<'v>> + 'a>>
+    where
+        'v: 'a,
+    {
+        Ok(box self.iter())
+    }
+
+    fn with_iterator(
+        &self,
+        _heap: &'v Heap,
+        f: &mut dyn FnMut(&mut dyn Iterator<Item = Value<'v>>) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        f(&mut self.iter())
+    }
+
+    fn get_attr(&self, attribute: &str, _heap: &'v Heap) -> Option<Value<'v>> {
+        let i = self
+            .get_fields()
+            .iter()
+            .position(|x| field_name(*x) == attribute)?;
+        Some(self.values[i].to_value())
+    }
+
+    fn has_attr(&self, attribute: &str) -> bool {
+        self.get_fields()
+            .iter()
+            .any(|x| field_name(*x) == attribute)
+    }
+
+    fn dir_attr(&self) -> Vec<String> {
+        self.get_fields()
+            .iter()
+            .map(|x| field_name(*x).to_owned())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assert::{self, Assert};
+
+    #[test]
+    fn test_namedtuple() {
+        assert::all_true(
+            r#"
+Point = namedtuple("x", "y")
+Point(1, 2).x == 1
+Point(1, y=2).y == 2
+Point(y=2, x=1) == Point(1, 2)
+Point(1, 2) != Point(2, 1)
+Point(1, 2)[-1] == 2
+len(Point(1, 2)) == 2
+list(Point(1, 2)) == [1, 2]
+2 in Point(1, 2)
+Point(1, 2) < Point(1, 3)
+repr(Point(1, "a")) == 'Point(x=1, y="a")'
+repr(namedtuple("a")(1)) == "namedtuple(a=1)"
+dir(Point(1, 2)) == ["x", "y"]
+Point.type == "Point"
+type(Point(1, 2)) == "namedtuple"
+json(Point(1, 2)) == "[1,2]"
+"#,
+        );
+        assert::pass(
+            r#"
+Point = namedtuple("x", "y")
+x, y = Point(1, 2)
+assert_eq((x, y), (1, 2))
+def f(p: "Point"):
+    return p.x
+f(Point(3, 4))
+"#,
+        );
+        assert::fail(
+            "namedtuple('x', 'y')(1)",
+            "Missing parameter `y` for call to namedtuple",
+        );
+        assert::fail("namedtuple('x', 'x')", "repeated `x`");
+        assert::fail("namedtuple('x', 'y')(1, 2).z", "z");
+    }
+
+    #[test]
+    fn test_namedtuple_frozen() {
+        let mut a = Assert::new();
+        a.module(
+            "m",
+            r#"
+Point = namedtuple("x", "y")
+origin = Point(0, 0)
+"#,
+        );
+        a.pass(
+            r#"
+load("m", "Point", "origin")
+assert_eq(origin, Point(0, 0))
+assert_eq(origin.y, 0)
+assert_eq(repr(origin), "Point(x=0, y=0)")
+Other = namedtuple("x", "y")
+assert_ne(origin, Other(0, 0))
+"#,
+        );
+    }
+}
@@ -63,8 +63,8 @@ use crate::{
     eval::{Arguments, Evaluator, ParametersSpec},
     values::{
         comparison::equals_slice, display::display_keyed_container, function::FUNCTION_TYPE,
-        typing::TypeCompiled, Freeze, Freezer, FrozenValue, Heap, StarlarkValue, Trace, Value,
-        ValueLike,
+        typing::TypeCompiled, Freeze, FreezeError, FreezeStep, Freezer, FrozenValue, Heap,
+        StarlarkValue, Trace, Value, ValueLike,
     },
 };
 
@@ -119,7 +119,7 @@ pub type RecordType<'v> = RecordTypeGen<Value<'v>, RefCell<Option<String>>>;
 pub type FrozenRecordType = RecordTypeGen<FrozenValue, Option<String>>;
 
 /// An actual record.
-#[derive(Clone, Debug, Trace, Coerce, Freeze)]
+#[derive(Clone, Debug, Trace, Coerce)]
 #[repr(C)]
 pub struct RecordGen<V> {
     typ: V, // Must be RecordType
@@ -145,6 +145,30 @@ impl<'v, V: ValueLike<'v>> Display for RecordGen<V> {
     }
 }
 
+impl<'v> Freeze for RecordGen<Value<'v>> {
+    type Frozen = RecordGen<FrozenValue>;
+    fn freeze(self, freezer: &Freezer) -> anyhow::Result<Self::Frozen> {
+        let typ = self.typ.freeze(freezer)?;
+        let values = self
+            .values
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| {
+                FreezeError::add_step(v.freeze(freezer), || {
+                    // The type may not be frozen yet, if it refers back to this record
+                    match RecordType::from_value(typ.to_value())
+                        .and_then(|t| record_fields(t).get_index(i))
+                    {
+                        Some((name, _)) => FreezeStep::Attr(name.clone()),
+                        None => FreezeStep::Index(i),
+                    }
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(RecordGen { typ, values })
+    }
+}
+
 starlark_complex_value!(pub(crate) Field);
 starlark_complex_values!(RecordType);
 starlark_complex_value!(pub Record);
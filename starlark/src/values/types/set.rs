@@ -0,0 +1,436 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! The set type, a mutable collection of unique hashable values, which iterates in insertion order.
+//! Only available with [`LibraryExtension::SetType`](crate::environment::LibraryExtension::SetType).
+
+use std::{
+    cell::{Ref, RefCell, RefMut},
+    fmt::{self, Debug, Display},
+    intrinsics::unlikely,
+};
+
+use gazebo::{
+    any::AnyLifetime,
+    cell::ARef,
+    coerce::{coerce_ref, Coerce},
+};
+
+use crate::{
+    self as starlark,
+    collections::{Hashed, SmallMap},
+    environment::{Methods, MethodsStatic},
+    values::{
+        display::display_container, error::ValueError, iter::ARefIterator, AllocFrozenValue,
+        AllocValue, Freeze, Freezer, FrozenHeap, FrozenValue, Heap, SimpleValue, StarlarkValue,
+        Trace, UnpackValue, Value,
+    },
+};
+
+#[derive(Clone, Default, Trace, Debug)]
+struct SetGen<T>(T);
+
+impl<'v, T: SetLike<'v>> Display for SetGen<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let content = self.0.content();
+        if content.is_empty() {
+            f.write_str("set()")
+        } else {
+            display_container(f, "set([", "])", content.keys())
+        }
+    }
+}
+
+/// Define the set type. See [`Set`] and [`FrozenSet`] as the two possible representations.
+#[derive(Clone, Default, Trace, Debug)]
+#[repr(transparent)]
+pub struct Set<'v> {
+    /// The elements of the set, which must all be hashable values.
+    content: SmallMap<Value<'v>, ()>,
+}
+
+/// Define the set type. See [`Set`] and [`FrozenSet`] as the two possible representations.
+#[derive(Clone, Default, Debug, AnyLifetime)]
+#[repr(transparent)]
+pub struct FrozenSet {
+    /// The elements of the set, which must all be hashable values.
+    content: SmallMap<FrozenValue, ()>,
+}
+
+unsafe impl<'v> AnyLifetime<'v> for SetGen<RefCell<Set<'v>>> {
+    any_lifetime_body!(SetGen<RefCell<Set<'static>>>);
+}
+any_lifetime!(SetGen<FrozenSet>);
+
+unsafe impl<'v> Coerce<Set<'v>> for FrozenSet {}
+
+impl<'v> AllocValue<'v> for Set<'v> {
+    fn alloc_value(self, heap: &'v Heap) -> Value<'v> {
+        heap.alloc_complex(SetGen(RefCell::new(self)))
+    }
+}
+
+impl AllocFrozenValue for FrozenSet {
+    fn alloc_frozen_value(self, heap: &FrozenHeap) -> FrozenValue {
+        heap.alloc_simple(SetGen(self))
+    }
+}
+
+impl SimpleValue for SetGen<FrozenSet> {}
+
+impl<'v> Set<'v> {
+    /// The result of calling `type()` on sets.
+    pub const TYPE: &'static str = "set";
+
+    pub fn from_value(x: Value<'v>) -> Option<ARef<'v, Self>> {
+        if x.unpack_frozen().is_some() {
+            x.downcast_ref::<SetGen<FrozenSet>>()
+                .map(|x| ARef::new_ptr(coerce_ref(&x.0)))
+        } else {
+            let ptr = x.downcast_ref::<SetGen<RefCell<Set<'v>>>>()?;
+            Some(ARef::new_ref(ptr.0.borrow()))
+        }
+    }
+
+    pub fn from_value_mut(x: Value<'v>) -> anyhow::Result<Option<RefMut<'v, Self>>> {
+        if unlikely(x.unpack_frozen().is_some()) {
+            return Err(ValueError::CannotMutateImmutableValue.into());
+        }
+        let ptr = x.downcast_ref::<SetGen<RefCell<Set<'v>>>>();
+        match ptr {
+            None => Ok(None),
+            Some(ptr) => match ptr.0.try_borrow_mut() {
+                Ok(x) => Ok(Some(x)),
+                Err(_) => Err(ValueError::MutationDuringIteration.into()),
+            },
+        }
+    }
+
+    /// Create a new, empty, [`Set`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.content.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.content.is_empty()
+    }
+
+    /// Iterate through the elements of the set, in insertion order.
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = Value<'v>> + 'a {
+        self.content.keys().copied()
+    }
+
+    /// Iterate through the elements of the set, retaining their hashes.
+    pub fn iter_hashed<'a>(&'a self) -> impl Iterator<Item = Hashed<Value<'v>>> + 'a
+    where
+        'v: 'a,
+    {
+        self.content.iter_hashed().map(|(x, _)| x.unborrow_copy())
+    }
+
+    pub fn contains_hashed(&self, x: Hashed<Value<'v>>) -> bool {
+        self.content.contains_key_hashed(x.borrow())
+    }
+
+    /// Add an element to the set, returning `true` if it was not already present.
+    pub fn insert_hashed(&mut self, x: Hashed<Value<'v>>) -> bool {
+        self.content.insert_hashed(x, ()).is_none()
+    }
+
+    /// Remove an element from the set, returning `true` if it was present.
+    pub fn remove_hashed(&mut self, x: Hashed<Value<'v>>) -> bool {
+        self.content.remove_hashed(x.borrow()).is_some()
+    }
+
+    /// Remove the most recently inserted element from the set.
+    pub fn pop(&mut self) -> Option<Value<'v>> {
+        self.content.pop().map(|(x, ())| x)
+    }
+
+    pub fn clear(&mut self) {
+        self.content.clear();
+    }
+
+    /// Is every element of this set also in `other`.
+    pub fn is_subset(&self, other: &Set<'v>) -> bool {
+        self.len() <= other.len() && self.iter_hashed().all(|x| other.contains_hashed(x))
+    }
+
+    /// The elements which satisfy `keep`, in the order of this set.
+    pub(crate) fn filter(&self, mut keep: impl FnMut(Hashed<Value<'v>>) -> bool) -> Set<'v> {
+        let mut res = Set::new();
+        for x in self.iter_hashed() {
+            if keep(x) {
+                res.insert_hashed(x);
+            }
+        }
+        res
+    }
+}
+
+impl FrozenSet {
+    pub(crate) fn new(content: SmallMap<FrozenValue, ()>) -> Self {
+        Self { content }
+    }
+
+    /// Obtain the [`FrozenSet`] pointed at by a [`FrozenValue`].
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    // We need a lifetime because FrozenValue doesn't contain the right lifetime
+    pub fn from_frozen_value(x: &FrozenValue) -> Option<&FrozenSet> {
+        x.downcast_ref::<SetGen<FrozenSet>>().map(|x| &x.0)
+    }
+
+    /// Iterate through the elements of the set, in insertion order.
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = FrozenValue> + 'a {
+        self.content.keys().copied()
+    }
+}
+
+impl<'v> UnpackValue<'v> for ARef<'v, Set<'v>> {
+    fn expected() -> String {
+        Set::TYPE.to_owned()
+    }
+
+    fn unpack_value(value: Value<'v>) -> Option<ARef<'v, Set<'v>>> {
+        Set::from_value(value)
+    }
+}
+
+impl<'v> Freeze for SetGen<RefCell<Set<'v>>> {
+    type Frozen = SetGen<FrozenSet>;
+    fn freeze(self, freezer: &Freezer) -> anyhow::Result<Self::Frozen> {
+        let content = self.0.into_inner().content.freeze(freezer)?;
+        Ok(SetGen(FrozenSet { content }))
+    }
+}
+
+trait SetLike<'v>: Debug {
+    fn content(&self) -> ARef<SmallMap<Value<'v>, ()>>;
+}
+
+impl<'v> SetLike<'v> for RefCell<Set<'v>> {
+    fn content(&self) -> ARef<SmallMap<Value<'v>, ()>> {
+        ARef::new_ref(Ref::map(self.borrow(), |x| &x.content))
+    }
+}
+
+impl<'v> SetLike<'v> for FrozenSet {
+    fn content(&self) -> ARef<SmallMap<Value<'v>, ()>> {
+        ARef::new_ptr(&coerce_ref::<_, Set<'v>>(self).content)
+    }
+}
+
+impl<'v, T: SetLike<'v>> SetGen<T> {
+    /// Combine with another set, for the binary operators.
+    fn op(
+        &self,
+        other: Value<'v>,
+        op: &str,
+        heap: &'v Heap,
+        f: impl FnOnce(&Set<'v>, &Set<'v>) -> Set<'v>,
+    ) -> anyhow::Result<Value<'v>> {
+        match Set::from_value(other) {
+            Some(other) => {
+                let this = Set {
+                    content: self.0.content().clone(),
+                };
+                Ok(heap.alloc(f(&this, &other)))
+            }
+            None => ValueError::unsupported_with(self, op, other),
+        }
+    }
+}
+
+impl<'v, T: SetLike<'v>> StarlarkValue<'v> for SetGen<T>
+where
+    Self: AnyLifetime<'v>,
+{
+    starlark_type!(Set::TYPE);
+
+    fn get_methods(&self) -> Option<&'static Methods> {
+        static RES: MethodsStatic = MethodsStatic::new();
+        RES.methods(crate::stdlib::set::set_methods)
+    }
+
+    fn collect_repr_cycle(&self, collector: &mut String) {
+        collector.push_str("set(...)");
+    }
+
+    fn debug_children(&self) -> Vec<(String, Value<'v>)> {
+        self.0
+            .content()
+            .keys()
+            .enumerate()
+            .map(|(i, x)| (i.to_string(), *x))
+            .collect()
+    }
+
+    fn collect_json(&self, collector: &mut String) -> anyhow::Result<()> {
+        collector.push('[');
+        for (i, x) in self.0.content().keys().enumerate() {
+            if i != 0 {
+                collector.push_str(", ");
+            }
+            x.collect_json(collector)?;
+        }
+        collector.push(']');
+        Ok(())
+    }
+
+    fn to_bool(&self) -> bool {
+        !self.0.content().is_empty()
+    }
+
+    fn equals(&self, other: Value<'v>) -> anyhow::Result<bool> {
+        match Set::from_value(other) {
+            None => Ok(false),
+            Some(other) => {
+                let content = self.0.content();
+                Ok(content.len() == other.len()
+                    && other
+                        .iter_hashed()
+                        .all(|x| content.contains_key_hashed(x.borrow())))
+            }
+        }
+    }
+
+    fn extra_memory(&self) -> usize {
+        self.0.content().extra_memory()
+    }
+
+    fn length(&self) -> anyhow::Result<i32> {
+        Ok(self.0.content().len() as i32)
+    }
+
+    fn is_in(&self, other: Value<'v>) -> anyhow::Result<bool> {
+        Ok(self
+            .0
+            .content()
+            .contains_key_hashed(other.get_hashed()?.borrow()))
+    }
+
+    fn iterate<'a>(
+        &'a self,
+        _heap: &'v Heap,
+    ) -> anyhow::Result<Box<dyn Iterator<Item = Value<'v>> + 'a>>
+    where
+        'v: 'a,
+    {
+        Ok(box ARefIterator::new(self.0.content(), |x| {
+            x.keys().copied()
+        }))
+    }
+
+    fn with_iterator(
+        &self,
+        _heap: &'v Heap,
+        f: &mut dyn FnMut(&mut dyn Iterator<Item = Value<'v>>) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        f(&mut self.0.content().keys().copied())
+    }
+
+    fn bit_or(&self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        self.op(other, "|", heap, |x, y| {
+            let mut res = x.clone();
+            for v in y.iter_hashed() {
+                res.insert_hashed(v);
+            }
+            res
+        })
+    }
+
+    fn bit_and(&self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        self.op(other, "&", heap, |x, y| x.filter(|v| y.contains_hashed(v)))
+    }
+
+    fn bit_xor(&self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        self.op(other, "^", heap, |x, y| {
+            let mut res = x.filter(|v| !y.contains_hashed(v));
+            for v in y.iter_hashed() {
+                if !x.contains_hashed(v) {
+                    res.insert_hashed(v);
+                }
+            }
+            res
+        })
+    }
+
+    fn sub(&self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        self.op(other, "-", heap, |x, y| x.filter(|v| !y.contains_hashed(v)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert::{self, Assert};
+
+    #[test]
+    fn test_set() {
+        assert::all_true(
+            r#"
+set() == set([])
+set([1, 2, 2, 3]) == set([3, 2, 1])
+len(set([1, 2, 2, 3])) == 3
+list(set([3, 1, 2, 1])) == [3, 1, 2]
+2 in set([1, 2])
+not (4 in set([1, 2]))
+repr(set([1, "x"])) == 'set([1, "x"])'
+repr(set()) == "set()"
+type(set()) == "set"
+not set()
+set([1, 2]) != [1, 2]
+set([1, 2]) | set([2, 3]) == set([1, 2, 3])
+set([1, 2]) & set([2, 3]) == set([2])
+set([1, 2]) - set([2, 3]) == set([1])
+set([1, 2]) ^ set([2, 3]) == set([1, 3])
+list(set([3, 1]) | set([2, 1])) == [3, 1, 2]
+"#,
+        );
+        assert::fail("set([[]])", "not hashable");
+        assert::fail("set([1]) | [1]", "not supported");
+        assert::fail("{set(): 1}", "not hashable");
+    }
+
+    #[test]
+    fn test_set_freeze() {
+        let mut a = Assert::new();
+        a.module("m", "s = set([1])\ns.add(2)");
+        a.pass("load('m', 's')\nassert_eq(s, set([2, 1]))");
+        a.fail("load('m', 's')\ns.add(3)", "Immutable");
+    }
+
+    #[test]
+    fn test_set_api() -> anyhow::Result<()> {
+        let heap = Heap::new();
+        let mut s = Set::new();
+        assert!(s.insert_hashed(Value::new_int(1).get_hashed()?));
+        assert!(!s.insert_hashed(Value::new_int(1).get_hashed()?));
+        let v = heap.alloc(s);
+        assert_eq!(Set::from_value(v).unwrap().len(), 1);
+        Set::from_value_mut(v)?
+            .unwrap()
+            .insert_hashed(heap.alloc_str_hashed("x"));
+        assert_eq!(v.to_repr(), "set([1, \"x\"])");
+        assert!(Set::from_value(heap.alloc(vec![1])).is_none());
+        Ok(())
+    }
+}
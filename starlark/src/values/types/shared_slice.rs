@@ -0,0 +1,185 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Elements of a frozen list shared by a `List` sliced from it.
+//!
+//! This object is used internally, and not visible outside of `starlark` crate.
+
+use std::{
+    cell::Cell,
+    fmt,
+    fmt::{Debug, Display, Formatter},
+};
+
+use gazebo::{any::AnyLifetime, coerce::coerce};
+
+use crate::values::{types::list::display_list, FrozenValue, Heap, StarlarkValue, Value};
+
+/// Slices shorter than this are copied, because a copy is as cheap as sharing.
+pub(crate) const MIN_SHARED_SLICE_LEN: usize = 8;
+
+/// A range of elements of a frozen list, read by a `List` instead of its own array
+/// until the list is first mutated.
+///
+/// Frozen values never move or change, so the elements can be referenced
+/// rather than copied.
+#[derive(AnyLifetime)]
+pub(crate) struct SharedSlice<'v> {
+    /// Elements of the frozen list.
+    content: &'v [FrozenValue],
+    /// The heap to copy the elements to when the list is mutated.
+    heap: &'v Heap,
+    /// Number of active iterators over the list.
+    iter_count: Cell<u32>,
+}
+
+impl<'v> Debug for SharedSlice<'v> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedSlice")
+            .field("iter_count", &self.iter_count.get())
+            .field("content", &self.content)
+            .finish()
+    }
+}
+
+impl<'v> SharedSlice<'v> {
+    pub(crate) fn new(content: &'v [FrozenValue], heap: &'v Heap) -> SharedSlice<'v> {
+        SharedSlice {
+            content,
+            heap,
+            iter_count: Cell::new(0),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.content.len()
+    }
+
+    pub(crate) fn content(&self) -> &'v [Value<'v>] {
+        coerce(self.content)
+    }
+
+    pub(crate) fn frozen_content(&self) -> &'v [FrozenValue] {
+        self.content
+    }
+
+    pub(crate) fn heap(&self) -> &'v Heap {
+        self.heap
+    }
+
+    /// Has at least one iterator over the list.
+    pub(crate) fn iter_count_is_non_zero(&self) -> bool {
+        self.iter_count.get() != 0
+    }
+
+    /// Create an iterator.
+    ///
+    /// Note this operation updates the iterator count of this object.
+    pub(crate) fn iter<'a>(&'a self) -> SharedSliceIter<'a, 'v> {
+        self.iter_count.set(self.iter_count.get() + 1);
+        SharedSliceIter {
+            slice: self,
+            next: 0,
+        }
+    }
+}
+
+/// This type is not visible to user, but still add meaningful `Display` for consistency.
+impl<'v> Display for SharedSlice<'v> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "shared_slice(")?;
+        display_list(self.content(), f)?;
+        write!(f, ")")
+    }
+}
+
+/// Iterator over the shared slice.
+pub(crate) struct SharedSliceIter<'a, 'v> {
+    slice: &'a SharedSlice<'v>,
+    next: usize,
+}
+
+impl<'a, 'v> Iterator for SharedSliceIter<'a, 'v> {
+    type Item = Value<'v>;
+
+    fn next(&mut self) -> Option<Value<'v>> {
+        let r = self.slice.content.get(self.next)?;
+        self.next += 1;
+        Some(r.to_value())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let rem = self.len();
+        (rem, Some(rem))
+    }
+}
+
+impl<'a, 'v> ExactSizeIterator for SharedSliceIter<'a, 'v> {
+    fn len(&self) -> usize {
+        self.slice.len() - self.next
+    }
+}
+
+impl<'a, 'v> Drop for SharedSliceIter<'a, 'v> {
+    fn drop(&mut self) {
+        debug_assert!(self.slice.iter_count.get() >= 1);
+        self.slice.iter_count.set(self.slice.iter_count.get() - 1);
+    }
+}
+
+impl<'v> StarlarkValue<'v> for SharedSlice<'v> {
+    starlark_type!("shared_slice");
+
+    fn length(&self) -> anyhow::Result<i32> {
+        Ok(self.len() as i32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::values::{
+        list::FrozenList, shared_slice::SharedSlice, FrozenHeap, FrozenValue, Heap, Value,
+    };
+
+    #[test]
+    fn display() {
+        let frozen_heap = FrozenHeap::new();
+        let list = frozen_heap.alloc_list(&[FrozenValue::new_int(29), FrozenValue::new_none()]);
+        let heap = Heap::new();
+        let slice = SharedSlice::new(
+            FrozenList::from_frozen_value(&list).unwrap().content(),
+            &heap,
+        );
+        assert_eq!("shared_slice([29, None])", slice.to_string());
+    }
+
+    #[test]
+    fn iter_count() {
+        let frozen_heap = FrozenHeap::new();
+        let list = frozen_heap.alloc_list(&[FrozenValue::new_int(17), FrozenValue::new_int(19)]);
+        let heap = Heap::new();
+        let slice = SharedSlice::new(
+            FrozenList::from_frozen_value(&list).unwrap().content(),
+            &heap,
+        );
+        let mut iter = slice.iter();
+        assert!(slice.iter_count_is_non_zero());
+        assert_eq!(Some(Value::new_int(17)), iter.next());
+        drop(iter);
+        assert!(!slice.iter_count_is_non_zero());
+    }
+}
@@ -17,6 +17,8 @@
 
 //! Implementations of alloc and unpack traits for string.
 
+use std::path::{Path, PathBuf};
+
 use crate::values::{
     AllocFrozenValue, AllocValue, FrozenHeap, FrozenValue, Heap, UnpackValue, Value,
 };
@@ -76,3 +78,25 @@ impl<'v> UnpackValue<'v> for String {
         value.unpack_str().map(ToOwned::to_owned)
     }
 }
+
+impl<'v> AllocValue<'v> for &'_ Path {
+    fn alloc_value(self, heap: &'v Heap) -> Value<'v> {
+        heap.alloc_str(&self.to_string_lossy())
+    }
+}
+
+impl<'v> AllocValue<'v> for PathBuf {
+    fn alloc_value(self, heap: &'v Heap) -> Value<'v> {
+        self.as_path().alloc_value(heap)
+    }
+}
+
+impl<'v> UnpackValue<'v> for PathBuf {
+    fn expected() -> String {
+        "str".to_owned()
+    }
+
+    fn unpack_value(value: Value<'v>) -> Option<Self> {
+        value.unpack_str().map(PathBuf::from)
+    }
+}
@@ -22,13 +22,15 @@ use std::{fmt::Write, str::FromStr};
 
 use anyhow::anyhow;
 use gazebo::{cast, prelude::*};
+use num_bigint::BigInt;
+use num_traits::FromPrimitive;
 use thiserror::Error;
 
 use crate::{
     collections::string_pool::StringPool,
     values::{
-        dict::Dict, float, num, num::Num, tuple::Tuple, Heap, StringValue, UnpackValue, Value,
-        ValueError, ValueLike,
+        bigint::StarlarkBigInt, dict::Dict, float, num, num::Num, tuple::Tuple, Heap, StringValue,
+        UnpackValue, Value, ValueError, ValueLike,
     },
 };
 
@@ -43,6 +45,14 @@ enum StringInterpolationError {
     NotEnoughParameters,
 }
 
+/// The value of an integer (or bool), which may be a big integer.
+fn to_integer(value: Value) -> anyhow::Result<BigInt> {
+    match StarlarkBigInt::from_value(value) {
+        Some(v) => Ok(v.get().clone()),
+        None => Ok(BigInt::from(value.to_int()?)),
+    }
+}
+
 pub(crate) fn percent(format: &str, value: Value) -> anyhow::Result<String> {
     // For performance reasons, we treat format as a list of bytes
     // (which is fine, the only thing we care about are '%' and ASCII digits).
@@ -88,46 +98,22 @@ pub(crate) fn percent(format: &str, value: Value) -> anyhow::Result<String> {
                     b'd' => {
                         let value = next_value()?;
                         if let Some(num::Num::Float(v)) = value.unpack_num() {
-                            match num::Num::Float(v.trunc()).as_int() {
+                            match BigInt::from_f64(v.trunc()) {
                                 None => {
                                     return ValueError::unsupported(&float::StarlarkFloat(v), "%d");
                                 }
                                 Some(v) => write!(out, "{}", v).unwrap(),
                             }
+                        } else if let Some(v) = StarlarkBigInt::from_value(value) {
+                            write!(out, "{}", v).unwrap()
                         } else {
                             write!(out, "{}", value.to_int()?).unwrap()
                         }
                     }
-                    b'o' => {
-                        let v = next_value()?.to_int()?;
-                        write!(
-                            out,
-                            "{}{:o}",
-                            if v < 0 { "-" } else { "" },
-                            v.wrapping_abs() as u64
-                        )
-                        .unwrap();
-                    }
-                    b'x' => {
-                        let v = next_value()?.to_int()?;
-                        write!(
-                            out,
-                            "{}{:x}",
-                            if v < 0 { "-" } else { "" },
-                            v.wrapping_abs() as u64
-                        )
-                        .unwrap();
-                    }
-                    b'X' => {
-                        let v = next_value()?.to_int()?;
-                        write!(
-                            out,
-                            "{}{:X}",
-                            if v < 0 { "-" } else { "" },
-                            v.wrapping_abs() as u64
-                        )
-                        .unwrap()
-                    }
+                    // Big integers format negative numbers with a leading `-`.
+                    b'o' => write!(out, "{:o}", to_integer(next_value()?)?).unwrap(),
+                    b'x' => write!(out, "{:x}", to_integer(next_value()?)?).unwrap(),
+                    b'X' => write!(out, "{:X}", to_integer(next_value()?)?).unwrap(),
                     b'e' => {
                         let v = Num::unpack_param(next_value()?)?.as_float();
                         float::write_scientific(out, v, 'e', false).unwrap()
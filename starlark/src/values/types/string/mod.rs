@@ -50,6 +50,7 @@ pub(crate) mod interpolation;
 pub(crate) mod iter;
 mod json;
 mod repr;
+pub(crate) mod rope;
 pub(crate) mod simd;
 
 /// Index of a char in a string.
@@ -149,6 +150,12 @@ impl StarlarkStr {
         }
     }
 
+    /// Record the hash of this string, computed by [`hash_string_result`], so it isn't
+    /// computed again.
+    pub(crate) fn cache_small_hash_result(&self, hash: SmallHashResult) {
+        self.str.hash.store(hash.get(), atomic::Ordering::Relaxed);
+    }
+
     pub fn as_str_hashed(&self) -> BorrowHashed<str> {
         BorrowHashed::new_unchecked(self.get_small_hash_result(), self.unpack())
     }
@@ -316,7 +323,9 @@ impl<'v> StarlarkValue<'v> for str {
 
     fn mul(&self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
         let l = i32::unpack_param(other)?;
-        let mut result = String::with_capacity(self.len() * cmp::max(0, l) as usize);
+        let len = self.len() * cmp::max(0, l) as usize;
+        heap.check_allocation_limit(len)?;
+        let mut result = String::with_capacity(len);
         for _i in 0..l {
             result.push_str(self)
         }
@@ -0,0 +1,388 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Lazily concatenated strings.
+//!
+//! Building a string with `s += x` in a loop copies `s` every iteration, so is quadratic.
+//! Instead, concatenations producing long strings allocate a [`StarlarkRope`], which
+//! points at its two halves, and copies them into a single string the first time the
+//! contents are needed, e.g. to hash, compare or print it. A rope looks like a string
+//! to Starlark code, to [`Value::unpack_str`] and to [`StringValue`], and is replaced by
+//! a string when frozen, or by garbage collection once flattened.
+
+use std::{
+    cell::Cell,
+    cmp,
+    cmp::Ordering,
+    fmt,
+    fmt::{Debug, Display},
+    ptr::copy_nonoverlapping,
+};
+
+use gazebo::any::AnyLifetime;
+
+use crate::{
+    self as starlark,
+    collections::StarlarkHasher,
+    environment::Methods,
+    values::{string::STRING_TYPE, Heap, StarlarkValue, StringValue, Trace, Value},
+};
+
+/// Concatenations shorter than this copy both strings, as a rope isn't worth it.
+pub(crate) const MIN_ROPE_LEN: usize = 256;
+
+/// Ropes deeper than this are flattened, so garbage collection, which traces
+/// a rope recursively, doesn't run out of stack. Appending to a string in a loop
+/// then copies it once every `MAX_ROPE_DEPTH` iterations, rather than every time.
+pub(crate) const MAX_ROPE_DEPTH: usize = 256;
+
+/// A string formed by concatenating two strings, each a flat string or another rope.
+#[derive(Trace, AnyLifetime)]
+pub(crate) struct StarlarkRope<'v> {
+    left: Value<'v>,
+    right: Value<'v>,
+    /// Length in bytes.
+    len: usize,
+    /// Longest path to a flat string, counting this rope.
+    depth: usize,
+    /// Where to allocate the flattened string.
+    #[trace(unsafe_ignore)]
+    heap: &'v Heap,
+    /// The flattened string, once something has needed it.
+    flat: Cell<Option<StringValue<'v>>>,
+}
+
+impl<'v> StarlarkRope<'v> {
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// The flattened string, copying the pieces into a string on the heap if this is the
+    /// first time it has been needed.
+    pub(crate) fn flatten(&self) -> StringValue<'v> {
+        if let Some(flat) = self.flat.get() {
+            return flat;
+        }
+        let len = self.len;
+        let flat = self.heap.alloc_str_init(len, |dest| {
+            let mut written = 0;
+            self.for_each_piece(
+                |x| x,
+                |piece| unsafe {
+                    copy_nonoverlapping(piece.as_ptr(), dest.add(written), piece.len());
+                    written += piece.len();
+                },
+            );
+            assert_eq!(written, len, "rope length doesn't match its pieces");
+        });
+        let flat = unsafe { StringValue::new_unchecked(flat) };
+        self.flat.set(Some(flat));
+        flat
+    }
+
+    pub(crate) fn flattened(&self) -> Option<StringValue<'v>> {
+        self.flat.get()
+    }
+
+    pub(crate) fn as_str(&self) -> &'v str {
+        self.flatten().as_str()
+    }
+
+    /// Call `f` with each flat string making up this rope, in order, without flattening
+    /// any rope. Each piece is passed through `resolve` before it is looked at, which lets
+    /// freezing see the values pieces have already been frozen to.
+    pub(crate) fn for_each_piece(
+        &self,
+        resolve: impl Fn(Value<'v>) -> Value<'v>,
+        mut f: impl FnMut(&str),
+    ) {
+        // Appending in a loop builds ropes deep on the left, so don't recurse.
+        let mut todo = vec![self.right, self.left];
+        while let Some(x) = todo.pop() {
+            let x = resolve(x);
+            if let Some(s) = x.unpack_flat_starlark_str() {
+                f(s.unpack());
+            } else {
+                let rope = x
+                    .unpack_rope()
+                    .expect("rope pieces must be strings or ropes");
+                match rope.flat.get() {
+                    Some(flat) => f(flat.as_str()),
+                    None => {
+                        todo.push(rope.right);
+                        todo.push(rope.left);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Concatenate two values if they are both strings, without copying them if the
+    /// result is long. Returns [`None`] if either isn't a string.
+    pub(crate) fn concat(x: Value<'v>, y: Value<'v>, heap: &'v Heap) -> Option<Value<'v>> {
+        let x_len = str_len(x)?;
+        let y_len = str_len(y)?;
+        if x_len == 0 {
+            return Some(y);
+        } else if y_len == 0 {
+            return Some(x);
+        }
+
+        let len = x_len + y_len;
+        if len < MIN_ROPE_LEN {
+            // Neither is a rope, as ropes are never shorter than `MIN_ROPE_LEN`.
+            return Some(heap.alloc_str_concat(x.unpack_str()?, y.unpack_str()?));
+        }
+
+        let mut depth = cmp::max(depth(x), depth(y)) + 1;
+        if depth > MAX_ROPE_DEPTH {
+            for rope in [x, y].iter().filter_map(|v| v.unpack_rope()) {
+                rope.flatten();
+            }
+            depth = 1;
+        }
+        Some(heap.alloc_rope(StarlarkRope {
+            left: x,
+            right: y,
+            len,
+            depth,
+            heap,
+            flat: Cell::new(None),
+        }))
+    }
+}
+
+/// Length of a string or rope, without flattening it.
+fn str_len(x: Value) -> Option<usize> {
+    match x.unpack_flat_starlark_str() {
+        Some(s) => Some(s.len()),
+        None => x.unpack_rope().map(StarlarkRope::len),
+    }
+}
+
+/// Depth of a rope, where flat strings, and ropes which have been flattened, are 0.
+fn depth(x: Value) -> usize {
+    match x.unpack_rope() {
+        Some(rope) if rope.flat.get().is_none() => rope.depth,
+        _ => 0,
+    }
+}
+
+impl<'v> Debug for StarlarkRope<'v> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Don't flatten just to debug.
+        f.debug_struct("StarlarkRope")
+            .field("len", &self.len)
+            .field("depth", &self.depth)
+            .field("flat", &self.flat.get().is_some())
+            .finish()
+    }
+}
+
+impl<'v> Display for StarlarkRope<'v> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Display::fmt(self.flatten().unpack_starlark_str(), f)
+    }
+}
+
+impl<'v> StarlarkValue<'v> for StarlarkRope<'v> {
+    starlark_type!(STRING_TYPE);
+
+    fn get_methods(&self) -> Option<&'static Methods> {
+        self.as_str().get_methods()
+    }
+
+    fn collect_repr(&self, collector: &mut String) {
+        self.as_str().collect_repr(collector)
+    }
+
+    fn collect_json(&self, collector: &mut String) -> anyhow::Result<()> {
+        self.as_str().collect_json(collector)
+    }
+
+    fn to_bool(&self) -> bool {
+        self.len != 0
+    }
+
+    fn write_hash(&self, hasher: &mut StarlarkHasher) -> anyhow::Result<()> {
+        self.flatten().unpack_starlark_str().write_hash(hasher)
+    }
+
+    fn equals(&self, other: Value<'v>) -> anyhow::Result<bool> {
+        if str_len(other) != Some(self.len) {
+            return Ok(false);
+        }
+        self.as_str().equals(other)
+    }
+
+    fn compare(&self, other: Value<'v>) -> anyhow::Result<Ordering> {
+        self.as_str().compare(other)
+    }
+
+    fn at(&self, index: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        self.as_str().at(index, heap)
+    }
+
+    fn length(&self) -> anyhow::Result<i32> {
+        self.as_str().length()
+    }
+
+    fn is_in(&self, other: Value<'v>) -> anyhow::Result<bool> {
+        self.as_str().is_in(other)
+    }
+
+    fn slice(
+        &self,
+        start: Option<Value<'v>>,
+        stop: Option<Value<'v>>,
+        stride: Option<Value<'v>>,
+        heap: &'v Heap,
+    ) -> anyhow::Result<Value<'v>> {
+        self.as_str().slice(start, stop, stride, heap)
+    }
+
+    fn add(&self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        self.as_str().add(other, heap)
+    }
+
+    fn mul(&self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        self.as_str().mul(other, heap)
+    }
+
+    fn percent(&self, other: Value<'v>, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
+        self.as_str().percent(other, heap)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        assert,
+        environment::Module,
+        values::{
+            string::rope::{StarlarkRope, MAX_ROPE_DEPTH, MIN_ROPE_LEN},
+            Heap, StringValue,
+        },
+    };
+
+    #[test]
+    fn test_rope_only_for_long_strings() {
+        let heap = Heap::new();
+        let short = StarlarkRope::concat(heap.alloc("abc"), heap.alloc("def"), &heap).unwrap();
+        assert!(short.unpack_rope().is_none());
+        assert_eq!(Some("abcdef"), short.unpack_str());
+
+        let long = "x".repeat(MIN_ROPE_LEN);
+        let rope = StarlarkRope::concat(heap.alloc(long.as_str()), heap.alloc("y"), &heap).unwrap();
+        assert!(rope.unpack_rope().unwrap().flattened().is_none());
+        assert_eq!(Some(format!("{}y", long).as_str()), rope.unpack_str());
+        assert!(rope.unpack_rope().unwrap().flattened().is_some());
+        assert!(StringValue::new(rope).is_some());
+
+        assert!(StarlarkRope::concat(rope, heap.alloc(1), &heap).is_none());
+    }
+
+    #[test]
+    fn test_rope_depth_is_bounded() {
+        let heap = Heap::new();
+        let mut s = heap.alloc("x".repeat(MIN_ROPE_LEN).as_str());
+        for _ in 0..MAX_ROPE_DEPTH * 3 {
+            s = StarlarkRope::concat(s, heap.alloc("y"), &heap).unwrap();
+            assert!(super::depth(s) <= MAX_ROPE_DEPTH);
+        }
+        let expected = format!(
+            "{}{}",
+            "x".repeat(MIN_ROPE_LEN),
+            "y".repeat(MAX_ROPE_DEPTH * 3)
+        );
+        assert_eq!(Some(expected.as_str()), s.unpack_str());
+    }
+
+    #[test]
+    fn test_rope_across_gc() {
+        let heap = Heap::new();
+        let long = "x".repeat(MIN_ROPE_LEN);
+        let mut flat =
+            StarlarkRope::concat(heap.alloc(long.as_str()), heap.alloc("a"), &heap).unwrap();
+        let mut lazy =
+            StarlarkRope::concat(heap.alloc(long.as_str()), heap.alloc("b"), &heap).unwrap();
+        flat.unpack_str();
+        unsafe {
+            heap.garbage_collect(|tracer| {
+                tracer.trace(&mut flat);
+                tracer.trace(&mut lazy);
+            })
+        };
+        // Once flattened, a rope is replaced by its string.
+        assert!(flat.unpack_rope().is_none());
+        assert_eq!(Some(format!("{}a", long).as_str()), flat.unpack_str());
+        assert!(lazy.unpack_rope().is_some());
+        assert_eq!(Some(format!("{}b", long).as_str()), lazy.unpack_str());
+    }
+
+    #[test]
+    fn test_rope_freezes_to_string() {
+        let module = Module::new();
+        let long = module.heap().alloc("x".repeat(MIN_ROPE_LEN).as_str());
+        let rope = StarlarkRope::concat(long, module.heap().alloc("y"), module.heap()).unwrap();
+        // Freeze a piece first, so freezing the rope must look through it.
+        module.set("long", long);
+        module.set("rope", rope);
+        let module = module.freeze().unwrap();
+        let rope = module.get("rope").unwrap();
+        assert!(rope.value().unpack_str().unwrap().ends_with("xy"));
+        assert_eq!(MIN_ROPE_LEN + 1, rope.value().unpack_str().unwrap().len());
+    }
+
+    #[test]
+    fn test_rope_behaves_as_string() {
+        assert::is_true(
+            r#"
+def build(n):
+    s = ""
+    for i in range(n):
+        s += str(i % 10)
+    return s
+s = build(2000)
+t = "0123456789" * 200
+(s == t and t == s and hash(s) == hash(t) and len(s) == 2000 and type(s) == "string" and
+    {t: 1}[s] == 1 and s[1999] == "9" and s.startswith("0123") and s < t + "x" and
+    repr(s) == repr(t) and ("345" + s)[3:] == t and (s + "x")[-1] == "x")
+"#,
+        );
+    }
+
+    #[test]
+    fn test_rope_exported_as_string() {
+        let module = assert::pass_module(
+            r#"
+def build():
+    s = ""
+    for _ in range(1000):
+        s += "ab"
+    return s
+s = build()
+"#,
+        );
+        let s = module.get("s").unwrap();
+        assert_eq!(Some("ab".repeat(1000).as_str()), s.unpack_str());
+        assert_eq!(
+            format!("\"{}\"", "ab".repeat(1000)),
+            s.value().to_json().unwrap()
+        );
+    }
+}
@@ -57,8 +57,8 @@ use crate::{
         docs,
         docs::DocItem,
         error::ValueError,
-        AllocValue, Freeze, FrozenValue, Heap, StarlarkValue, StringValue, StringValueLike, Trace,
-        UnpackValue, Value, ValueLike, ValueOf,
+        AllocValue, Freeze, FreezeStep, Freezer, FrozenValue, Heap, StarlarkValue, StringValue,
+        StringValueLike, Trace, UnpackValue, Value, ValueLike, ValueOf,
     },
 };
 
@@ -78,7 +78,7 @@ impl<'v, V: ValueLike<'v>> StructGen<'v, V> {
 starlark_complex_value!(pub Struct<'v>);
 
 /// The result of calling `struct()`.
-#[derive(Clone, Default, Debug, Trace, Freeze)]
+#[derive(Clone, Default, Debug, Trace)]
 #[repr(C)]
 pub struct StructGen<'v, V: ValueLike<'v>> {
     /// The fields in a struct.
@@ -88,6 +88,19 @@ pub struct StructGen<'v, V: ValueLike<'v>> {
 
 unsafe impl<'v> Coerce<StructGen<'v, Value<'v>>> for StructGen<'static, FrozenValue> {}
 
+impl<'v> Freeze for StructGen<'v, Value<'v>> {
+    type Frozen = StructGen<'static, FrozenValue>;
+    fn freeze(self, freezer: &Freezer) -> anyhow::Result<Self::Frozen> {
+        let fields = self
+            .fields
+            .freeze_keyed(freezer, |k| Some(FreezeStep::Attr(k.as_str().to_owned())))?;
+        Ok(StructGen {
+            fields,
+            _marker: marker::PhantomData,
+        })
+    }
+}
+
 impl<'v, V: ValueLike<'v>> Display for StructGen<'v, V> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         display_keyed_container(
@@ -150,6 +163,13 @@ where
         collector.push_str("struct(...)");
     }
 
+    fn debug_children(&self) -> Vec<(String, Value<'v>)> {
+        self.fields
+            .iter()
+            .map(|(k, v)| (k.to_string_value().as_str().to_owned(), v.to_value()))
+            .collect()
+    }
+
     fn collect_json(&self, collector: &mut String) -> anyhow::Result<()> {
         collector.push('{');
         for (i, (k, v)) in self.fields.iter().enumerate() {
@@ -334,6 +354,29 @@ struct(foo = [struct(bar = "some")]).to_json() == '{"foo":[{"bar":"some"}]}'
         );
     }
 
+    #[test]
+    fn test_frozen() {
+        let mut a = assert::Assert::new();
+        a.module("m", "s = struct(a=1, b=[2])");
+        a.pass(
+            r#"
+load("m", "s")
+assert_eq(s.a, 1)
+assert_eq(s, struct(a=1, b=[2]))
+assert_ne(s, struct(a=1, b=[3]))
+assert_eq(dir(s), ["a", "b", "to_json"])
+assert_eq(s.to_json(), '{"a":1,"b":[2]}')
+"#,
+        );
+        a.fail(
+            r#"
+load("m", "s")
+s.b.append(3)
+"#,
+            "Immutable",
+        );
+    }
+
     #[test]
     fn test_docs() {
         let expected = DocItem::Object(docs::Object {
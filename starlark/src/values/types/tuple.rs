@@ -18,9 +18,11 @@
 //! The list type, an immutable sequence of values.
 
 use std::{
+    cmp,
     cmp::Ordering,
     fmt,
     fmt::{Debug, Display, Formatter},
+    mem,
     slice,
 };
 
@@ -236,7 +238,9 @@ where
 
     fn mul(&self, other: Value, heap: &'v Heap) -> anyhow::Result<Value<'v>> {
         let l = i32::unpack_param(other)?;
-        let mut result = Vec::new();
+        let len = self.content().len() * cmp::max(0, l) as usize;
+        heap.check_allocation_limit(len.saturating_mul(mem::size_of::<Value>()))?;
+        let mut result = Vec::with_capacity(len);
         for _i in 0..l {
             result.extend(self.content().iter().map(|e| e.to_value()));
         }
@@ -246,49 +250,52 @@ where
     fn collect_repr_cycle(&self, collector: &mut String) {
         collector.push_str("(...)");
     }
-}
-
-impl<'v, T1: AllocValue<'v>> AllocValue<'v> for (T1,) {
-    fn alloc_value(self, heap: &'v Heap) -> Value<'v> {
-        heap.alloc_tuple(&[self.0.alloc_value(heap)])
-    }
-}
 
-impl<'v, T1: AllocValue<'v>, T2: AllocValue<'v>> AllocValue<'v> for (T1, T2) {
-    fn alloc_value(self, heap: &'v Heap) -> Value<'v> {
-        heap.alloc_tuple(&[self.0.alloc_value(heap), self.1.alloc_value(heap)])
+    fn debug_children(&self) -> Vec<(String, Value<'v>)> {
+        self.content()
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (i.to_string(), v.to_value()))
+            .collect()
     }
 }
 
-impl<'v, T1: AllocValue<'v>, T2: AllocValue<'v>, T3: AllocValue<'v>> AllocValue<'v>
-    for (T1, T2, T3)
-{
-    fn alloc_value(self, heap: &'v Heap) -> Value<'v> {
-        heap.alloc_tuple(&[
-            self.0.alloc_value(heap),
-            self.1.alloc_value(heap),
-            self.2.alloc_value(heap),
-        ])
-    }
-}
+macro_rules! tuple_impls {
+    ($($t:ident $v:ident $i:tt),+) => {
+        impl<'v, $($t: AllocValue<'v>),+> AllocValue<'v> for ($($t,)+) {
+            fn alloc_value(self, heap: &'v Heap) -> Value<'v> {
+                heap.alloc_tuple(&[$(self.$i.alloc_value(heap)),+])
+            }
+        }
 
-impl<'v, T1: UnpackValue<'v>, T2: UnpackValue<'v>> UnpackValue<'v> for (T1, T2) {
-    fn expected() -> String {
-        format!("tuple ({}, {})", T1::expected(), T2::expected())
-    }
+        impl<'v, $($t: UnpackValue<'v>),+> UnpackValue<'v> for ($($t,)+) {
+            fn expected() -> String {
+                format!("tuple ({})", [$($t::expected()),+].join(", "))
+            }
 
-    fn unpack_value(value: Value<'v>) -> Option<Self> {
-        let t = Tuple::from_value(value)?;
-        if t.len() != 2 {
-            return None;
+            fn unpack_value(value: Value<'v>) -> Option<Self> {
+                match Tuple::from_value(value)?.content() {
+                    [$($v),+] => Some(($($t::unpack_value(*$v)?,)+)),
+                    _ => None,
+                }
+            }
         }
-        Some((
-            T1::unpack_value(t.content()[0])?,
-            T2::unpack_value(t.content()[1])?,
-        ))
-    }
+    };
 }
 
+tuple_impls!(T1 v1 0);
+tuple_impls!(T1 v1 0, T2 v2 1);
+tuple_impls!(T1 v1 0, T2 v2 1, T3 v3 2);
+tuple_impls!(T1 v1 0, T2 v2 1, T3 v3 2, T4 v4 3);
+tuple_impls!(T1 v1 0, T2 v2 1, T3 v3 2, T4 v4 3, T5 v5 4);
+tuple_impls!(T1 v1 0, T2 v2 1, T3 v3 2, T4 v4 3, T5 v5 4, T6 v6 5);
+tuple_impls!(T1 v1 0, T2 v2 1, T3 v3 2, T4 v4 3, T5 v5 4, T6 v6 5, T7 v7 6);
+tuple_impls!(T1 v1 0, T2 v2 1, T3 v3 2, T4 v4 3, T5 v5 4, T6 v6 5, T7 v7 6, T8 v8 7);
+tuple_impls!(T1 v1 0, T2 v2 1, T3 v3 2, T4 v4 3, T5 v5 4, T6 v6 5, T7 v7 6, T8 v8 7, T9 v9 8);
+tuple_impls!(
+    T1 v1 0, T2 v2 1, T3 v3 2, T4 v4 3, T5 v5 4, T6 v6 5, T7 v7 6, T8 v8 7, T9 v9 8, T10 v10 9
+);
+
 #[cfg(test)]
 mod tests {
     use crate::assert;
@@ -209,7 +209,7 @@ impl<'v> Value<'v> {
     #[inline(never)]
     fn check_type_error(value: Value, ty: Value, arg_name: Option<&str>) -> anyhow::Result<()> {
         Err(TypingError::TypeAnnotationMismatch(
-            value.to_str(),
+            value.debug_summary_or_str(),
             value.get_type().to_owned(),
             ty.to_str(),
             match arg_name {
@@ -21,8 +21,23 @@ use std::ops::Deref;
 
 use either::Either;
 use gazebo::prelude::*;
-
-use crate::values::{list::List, tuple::Tuple, Value, ValueError};
+use thiserror::Error;
+
+use crate::values::{dict::Dict, list::List, structs::Struct, tuple::Tuple, Value, ValueError};
+
+#[derive(Debug, Error)]
+enum UnpackFieldsError {
+    #[error("Expected `{0}`, but got `{1}`")]
+    NotDictOrStruct(String, String),
+    #[error("Dict keys must be strings, but got `{0}`")]
+    NonStringKey(String),
+    #[error("Missing field `{0}`")]
+    MissingField(String),
+    #[error("Unknown field `{0}`, expected one of {1}")]
+    UnknownField(String, String),
+    #[error("Type of field `{0}` doesn't match, expected `{1}`, actual `{2}`")]
+    IncorrectFieldType(String, String, String),
+}
 
 /// How to convert a [`Value`] to a Rust type. Required for all arguments in a [`#[starlark_module]`](macro@starlark_module) definition.
 pub trait UnpackValue<'v>: Sized {
@@ -121,6 +136,23 @@ impl<'v, TLeft: UnpackValue<'v>, TRight: UnpackValue<'v>> UnpackValue<'v>
     }
 }
 
+/// Unpacks `None` as [`None`]. Note that in a [`#[starlark_module]`](macro@starlark_module)
+/// signature an [`Option`] parameter means the parameter is optional instead,
+/// so use [`NoneOr`](crate::values::none::NoneOr) there.
+impl<'v, T: UnpackValue<'v>> UnpackValue<'v> for Option<T> {
+    fn expected() -> String {
+        format!("None or {}", T::expected())
+    }
+
+    fn unpack_value(value: Value<'v>) -> Option<Self> {
+        if value.is_none() {
+            Some(None)
+        } else {
+            T::unpack_value(value).map(Some)
+        }
+    }
+}
+
 impl<'v, T: UnpackValue<'v>> UnpackValue<'v> for Vec<T> {
     fn expected() -> String {
         format!("list or tuple of {}", T::expected())
@@ -136,3 +168,68 @@ impl<'v, T: UnpackValue<'v>> UnpackValue<'v> for Vec<T> {
         }
     }
 }
+
+/// The fields of a dict with string keys, or of a struct, being unpacked into a Rust struct.
+/// Used by [`#[derive(UnpackValue)]`](derive@crate::values::UnpackValue), with errors
+/// naming the field which is missing or has the wrong type.
+pub struct UnpackFields<'v> {
+    fields: Vec<(&'v str, Value<'v>)>,
+}
+
+impl<'v> UnpackFields<'v> {
+    /// Collect the fields of `value`, which must be a dict or a struct.
+    /// The `expected` string is only used for error messages.
+    pub fn new(value: Value<'v>, expected: &str) -> anyhow::Result<Self> {
+        let fields = if let Some(dict) = Dict::from_value(value) {
+            dict.iter()
+                .map(|(k, v)| match k.unpack_str() {
+                    Some(k) => Ok((k, v)),
+                    None => Err(UnpackFieldsError::NonStringKey(k.debug_summary()).into()),
+                })
+                .collect::<anyhow::Result<_>>()?
+        } else if let Some(s) = Struct::from_value(value) {
+            s.fields.iter().map(|(k, v)| (k.as_str(), *v)).collect()
+        } else {
+            return Err(UnpackFieldsError::NotDictOrStruct(
+                expected.to_owned(),
+                value.get_type().to_owned(),
+            )
+            .into());
+        };
+        Ok(Self { fields })
+    }
+
+    /// Check there are no fields other than `names`.
+    pub fn check_names(&self, names: &[&str]) -> anyhow::Result<()> {
+        match self.fields.iter().find(|(k, _)| !names.contains(k)) {
+            None => Ok(()),
+            Some((k, _)) => Err(UnpackFieldsError::UnknownField(
+                (*k).to_owned(),
+                names.map(|x| format!("`{}`", x)).join(", "),
+            )
+            .into()),
+        }
+    }
+
+    /// Unpack the field `name`, returning `None` if it is absent or `None`.
+    pub fn optional<T: UnpackValue<'v>>(&self, name: &str) -> anyhow::Result<Option<T>> {
+        match self.fields.iter().find(|(k, _)| *k == name) {
+            Some((_, v)) if !v.is_none() => match T::unpack_value(*v) {
+                Some(x) => Ok(Some(x)),
+                None => Err(UnpackFieldsError::IncorrectFieldType(
+                    name.to_owned(),
+                    T::expected(),
+                    v.get_type().to_owned(),
+                )
+                .into()),
+            },
+            _ => Ok(None),
+        }
+    }
+
+    /// Unpack the field `name`, which must be present.
+    pub fn required<T: UnpackValue<'v>>(&self, name: &str) -> anyhow::Result<T> {
+        self.optional(name)?
+            .ok_or_else(|| UnpackFieldsError::MissingField(name.to_owned()).into())
+    }
+}
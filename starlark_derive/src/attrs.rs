@@ -19,15 +19,15 @@ use proc_macro::TokenStream;
 use proc_macro2::Ident;
 use quote::quote;
 use syn::{
-    parse_macro_input, spanned::Spanned, Attribute, Data, DeriveInput, Error, Meta, NestedMeta,
-    Result, Type,
+    parse::Parser, parse_macro_input, punctuated::Punctuated, spanned::Spanned, Attribute, Data,
+    DeriveInput, Error, Meta, NestedMeta, Result, Token, Type,
 };
 
 pub fn derive_attrs(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
     let data = input.data;
     let name = input.ident;
-    expand_attrs_derive(data, name)
+    expand_attrs_derive(&input.attrs, data, name)
         .unwrap_or_else(|e| e.to_compile_error())
         .into()
 }
@@ -71,23 +71,76 @@ impl Field {
         }
     }
 
-    fn get_attr_match_item(&self) -> proc_macro2::TokenStream {
-        let name = self.name();
+    /// Allocate the field of `this` on a Starlark heap named `heap`.
+    fn alloc(&self, this: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
         let ident = &self.ident;
         match self.should_clone() {
             false => quote! {
-                #name => Some(heap.alloc(&self.#ident))
+                heap.alloc(&#this.#ident)
             },
             true => quote! {
-                #name => Some(heap.alloc(self.#ident.clone()))
+                heap.alloc(#this.#ident.clone())
             },
         }
     }
+
+    fn get_attr_match_item(&self) -> proc_macro2::TokenStream {
+        let name = self.name();
+        let alloc = self.alloc(quote! { self });
+        quote! {
+            #name => Some(#alloc)
+        }
+    }
+
+    fn equals_item(&self) -> proc_macro2::TokenStream {
+        let this = self.alloc(quote! { self });
+        let other = self.alloc(quote! { other });
+        quote! {
+            if !#this.equals(#other)? {
+                return Ok(false);
+            }
+        }
+    }
+
+    fn display_item(&self, first: bool) -> proc_macro2::TokenStream {
+        let prefix = format!("{}{}=", if first { "" } else { ", " }, self.name());
+        let alloc = self.alloc(quote! { self });
+        quote! {
+            write!(f, "{}{}", #prefix, #alloc.to_repr())?;
+        }
+    }
 }
 
 static STARLARK_ATTR_ERR_MSG: &str = "valid starlark attributes are {skip}";
+static STARLARK_STRUCT_ATTR_ERR_MSG: &str = "valid starlark attributes on a struct are {display}";
 
-fn expand_attrs_derive(data: Data, name: Ident) -> Result<proc_macro2::TokenStream> {
+/// Parse the `#[starlark(...)]` attribute on the struct itself, returning whether
+/// `display` was requested.
+fn struct_display(attrs: &[Attribute]) -> Result<bool> {
+    let attr = match attrs.iter().find(|a| a.path.is_ident("starlark")) {
+        None => return Ok(false),
+        Some(attr) => attr,
+    };
+    match attr.parse_meta()? {
+        Meta::List(lst) => {
+            for m in &lst.nested {
+                match m {
+                    NestedMeta::Meta(Meta::Path(p)) if p.is_ident("display") => {}
+                    _ => return Err(Error::new(m.span(), STARLARK_STRUCT_ATTR_ERR_MSG)),
+                }
+            }
+            Ok(true)
+        }
+        _ => Err(Error::new(attr.span(), "starlark attr must parse as list")),
+    }
+}
+
+fn expand_attrs_derive(
+    attrs: &[Attribute],
+    data: Data,
+    name: Ident,
+) -> Result<proc_macro2::TokenStream> {
+    let display = struct_display(attrs)?;
     let fields: Vec<_> = match data {
         Data::Struct(s) => Ok(s.fields.iter().cloned().collect()),
         Data::Enum(e) => Err(Error::new(
@@ -166,7 +219,22 @@ fn expand_attrs_derive(data: Data, name: Ident) -> Result<proc_macro2::TokenStre
         }
     };
 
-    let expanded = quote! {
+    let equals_items = expose_fields.iter().map(|f| f.equals_item());
+    let equals = quote! {
+        #[allow(unused_variables)]
+        pub(crate) fn attrs_equals<'v>(&self, other: starlark::values::Value<'v>) -> anyhow::Result<bool> {
+            match other.downcast_ref::<Self>() {
+                None => Ok(false),
+                Some(other) => {
+                    let heap = starlark::values::Heap::new();
+                    #(#equals_items)*
+                    Ok(true)
+                }
+            }
+        }
+    };
+
+    let mut expanded = quote! {
         // Unfortunately, we can't actually implement the direct methods for
         // `StarlarkValue`, because then we would have conflicting
         // implementations. However, we can implement wrappers in another
@@ -175,9 +243,30 @@ fn expand_attrs_derive(data: Data, name: Ident) -> Result<proc_macro2::TokenStre
             #has_attr
             #get_attr
             #dir_attr
+            #equals
         }
     };
 
+    if display {
+        // Render each field with its Starlark `repr`, e.g. `Example(hello="world")`.
+        let name_str = name.to_string();
+        let display_items = expose_fields
+            .iter()
+            .enumerate()
+            .map(|(i, f)| f.display_item(i == 0));
+        expanded.extend(quote! {
+            impl std::fmt::Display for #name {
+                fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                    #[allow(unused_variables)]
+                    let heap = starlark::values::Heap::new();
+                    write!(f, "{}(", #name_str)?;
+                    #(#display_items)*
+                    write!(f, ")")
+                }
+            }
+        });
+    }
+
     Ok(expanded)
 }
 
@@ -188,10 +277,28 @@ where
     field.attrs.iter().find(|a| a.path.is_ident(path))
 }
 
-pub fn starlark_attrs() -> TokenStream {
+pub fn starlark_attrs(input: TokenStream) -> TokenStream {
     // Clearly, this could be a regular macro, but since it is required by the
     // derive macro, export it as a proc-macro anyway so that it can come from
     // the same crate.
+    let args = match Punctuated::<Ident, Token![,]>::parse_terminated.parse(input) {
+        Ok(args) => args,
+        Err(e) => return e.to_compile_error().into(),
+    };
+    let mut equals = quote! {};
+    for arg in args {
+        if arg == "equals" {
+            equals = quote! {
+                fn equals(&self, other: starlark::values::Value<'v>) -> anyhow::Result<bool> {
+                    self.attrs_equals(other)
+                }
+            };
+        } else {
+            return Error::new(arg.span(), "valid starlark_attrs! arguments are {equals}")
+                .to_compile_error()
+                .into();
+        }
+    }
     let expanded = quote! {
         // proxy all attr methods to the implementations generated by
         // derive(Attrs)
@@ -204,6 +311,7 @@ pub fn starlark_attrs() -> TokenStream {
         fn dir_attr(&self) -> Vec<String> {
             self.attrs_dir_attr()
         }
+        #equals
     };
     expanded.into()
 }
@@ -30,8 +30,10 @@ mod bc;
 mod freeze;
 mod parse;
 mod render;
+mod starlark_enum;
 mod trace;
 mod typ;
+mod unpack_value;
 mod util;
 
 /// Write Starlark modules concisely in Rust syntax.
@@ -78,6 +80,11 @@ mod util;
 ///   is considered safe to execute speculatively: the function should have
 ///   no global side effects, should not panic, and should finish in reasonable time.
 ///   The evaluator may invoke such functions early to generate more efficient code.
+/// * A function can be an `async fn`, with its body run by `Evaluator::await_native`,
+///   which suspends the evaluation while awaiting when it was started with
+///   `Evaluator::eval_module_async`. The body can't use `eval` or `heap`, and the parameters
+///   must have owned types (e.g. `String` rather than `&str`), since they are moved into
+///   the future.
 ///
 /// All these functions interoperate properly with `dir()`, `getattr()` and `hasattr()`.
 ///
@@ -123,16 +130,38 @@ pub fn derive_freeze(input: proc_macro::TokenStream) -> proc_macro::TokenStream
 /// Derive accessor methods that are designed to be used from {has,get,dir}_attr
 /// in an `impl StarlarkValue` block. All fields in the struct that are not
 /// marked with #[starlark(skip)] are exported to Starlark code as attributes.
+/// Marking the struct itself with `#[starlark(display)]` also derives `Display`,
+/// rendering the exported fields like `Example(hello="world")`.
 /// NOTE: Any usage must also call `starlark_attrs!()` in the impl block for
 /// `StarlarkValue`, otherwise the generated attr methods will not be used.
+/// Use `starlark_attrs!(equals)` to also compare values by their exported fields.
 #[proc_macro_derive(StarlarkAttrs, attributes(starlark))]
 pub fn derive_starlark_attrs(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     attrs::derive_attrs(input)
 }
 
+/// Derive conversions between a Rust enum whose variants have no fields and Starlark
+/// strings, implementing `UnpackValue` and `AllocValue`, plus `values()` and `as_str()`
+/// helpers. Each variant is represented by its name in `snake_case`, unless it is
+/// annotated with `#[starlark(rename = "...")]`.
+#[proc_macro_derive(StarlarkEnum, attributes(starlark))]
+pub fn derive_starlark_enum(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    starlark_enum::derive_starlark_enum(input)
+}
+
+/// Derive `UnpackValue` for a struct with named fields, unpacking it from a dict with
+/// string keys or a struct. Fields of type `Option` may be absent, as may fields annotated
+/// with `#[starlark(default)]`, which take their `Default` value. A field which is `None`
+/// is treated as absent. Errors name the field which is missing, unknown or ill-typed.
+#[proc_macro_derive(UnpackValue, attributes(starlark))]
+pub fn derive_unpack_value(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    unpack_value::derive_unpack_value(input)
+}
+
 /// Generate `{has,get,dir}_attr` in the `StarlarkValue` impl block that proxy
-/// to the ones generated by `derive(StarlarkAttrs)`
+/// to the ones generated by `derive(StarlarkAttrs)`, and `equals` if given
+/// as `starlark_attrs!(equals)`.
 #[proc_macro]
-pub fn starlark_attrs(_: proc_macro::TokenStream) -> proc_macro::TokenStream {
-    attrs::starlark_attrs()
+pub fn starlark_attrs(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    attrs::starlark_attrs(input)
 }
@@ -228,7 +228,11 @@ fn parse_fun(func: ItemFn) -> syn::Result<StarStmt> {
         .map(parse_arg)
         .collect::<Result<_, _>>()?;
 
+    let is_async = func.sig.asyncness.is_some();
     if is_attribute {
+        if is_async {
+            return Err(syn::Error::new(sig_span, "Attribute function can't be async"));
+        }
         if args.len() != 1 {
             return Err(syn::Error::new(
                 sig_span,
@@ -265,6 +269,7 @@ fn parse_fun(func: ItemFn) -> syn::Result<StarStmt> {
             args,
             return_type: *return_type,
             speculative_exec_safe,
+            is_async,
             body: *func.block,
             source: StarFunSource::Unknown,
             docstring,
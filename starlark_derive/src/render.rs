@@ -131,11 +131,19 @@ fn render_fun(x: StarFun) -> TokenStream {
         args: _,
         return_type,
         speculative_exec_safe,
+        is_async,
         body,
         source: _,
         docstring: _,
     } = x;
 
+    // An async body can't borrow from the evaluator, so is run with the arguments moved into it
+    let body = if is_async {
+        quote_spanned! {span=> eval.await_native(async move #body) }
+    } else {
+        quote_spanned! {span=> #body }
+    };
+
     let typ = match type_attribute {
         Some(x) => quote_spanned! {
             span=>
@@ -317,7 +325,7 @@ fn render_binding_arg(arg: &StarArg) -> TokenStream {
             "Can't have Option argument with a default, for `{}`",
             name_str
         );
-        quote_spanned! { span=> starlark::eval::Arguments::check_optional(#name_str, #source)? }
+        quote_spanned! { span=> starlark::eval::Arguments::check_optional_coerced(eval, #name_str, #source)? }
     } else if !arg.is_value() && arg.default.is_some() {
         let default = arg
             .default
@@ -329,12 +337,12 @@ fn render_binding_arg(arg: &StarArg) -> TokenStream {
                 #[allow(clippy::manual_unwrap_or)]
                 #[allow(clippy::unnecessary_lazy_evaluations)]
                 #[allow(clippy::redundant_closure)]
-                let x = starlark::eval::Arguments::check_optional(#name_str, #source)?.unwrap_or_else(|| #default);
+                let x = starlark::eval::Arguments::check_optional_coerced(eval, #name_str, #source)?.unwrap_or_else(|| #default);
                 x
             }
         }
     } else {
-        quote_spanned! { span=> starlark::eval::Arguments::check_required(#name_str, #source)? }
+        quote_spanned! { span=> starlark::eval::Arguments::check_required_coerced(eval, #name_str, #source)? }
     };
 
     let mutability = mut_token(arg.mutable);
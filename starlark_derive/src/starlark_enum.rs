@@ -0,0 +1,168 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use proc_macro::TokenStream;
+use proc_macro2::Ident;
+use quote::quote;
+use syn::{
+    parse_macro_input, spanned::Spanned, Attribute, Data, DeriveInput, Error, Fields, Lit, Meta,
+    NestedMeta, Result,
+};
+
+pub fn derive_starlark_enum(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let data = input.data;
+    let name = input.ident;
+    expand_enum_derive(data, name)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+struct Variant {
+    ident: Ident,
+    /// The Starlark string this variant is represented by.
+    value: String,
+}
+
+static STARLARK_ENUM_ERR_MSG: &str = "valid starlark attributes are {rename = \"...\"}";
+
+/// Convert `CamelCase` to `snake_case`.
+fn snake(x: &str) -> String {
+    let mut res = String::new();
+    for c in x.chars() {
+        if c.is_uppercase() {
+            if !res.is_empty() {
+                res.push('_');
+            }
+            res.extend(c.to_lowercase());
+        } else {
+            res.push(c);
+        }
+    }
+    res
+}
+
+/// The value of `#[starlark(rename = "...")]`, if present.
+fn rename(attrs: &[Attribute]) -> Result<Option<String>> {
+    let attr = match attrs.iter().find(|a| a.path.is_ident("starlark")) {
+        None => return Ok(None),
+        Some(attr) => attr,
+    };
+    match attr.parse_meta()? {
+        Meta::List(lst) => match lst.nested.iter().collect::<Vec<_>>().as_slice() {
+            [NestedMeta::Meta(Meta::NameValue(nv))] if nv.path.is_ident("rename") => {
+                match &nv.lit {
+                    Lit::Str(s) => Ok(Some(s.value())),
+                    _ => Err(Error::new(nv.lit.span(), STARLARK_ENUM_ERR_MSG)),
+                }
+            }
+            _ => Err(Error::new(lst.span(), STARLARK_ENUM_ERR_MSG)),
+        },
+        _ => Err(Error::new(attr.span(), "starlark attr must parse as list")),
+    }
+}
+
+fn expand_enum_derive(data: Data, name: Ident) -> Result<proc_macro2::TokenStream> {
+    let variants = match data {
+        Data::Enum(e) => Ok(e.variants),
+        Data::Struct(s) => Err(Error::new(
+            s.struct_token.span(),
+            "#[derive(StarlarkEnum)] does not support structs",
+        )),
+        Data::Union(u) => Err(Error::new(
+            u.union_token.span(),
+            "#[derive(StarlarkEnum)] does not support unions",
+        )),
+    }?;
+
+    let variants: Vec<Variant> = variants
+        .into_iter()
+        .map(|v| {
+            if !matches!(v.fields, Fields::Unit) {
+                return Err(Error::new(
+                    v.fields.span(),
+                    "#[derive(StarlarkEnum)] only supports variants without fields",
+                ));
+            }
+            let value = match rename(&v.attrs)? {
+                Some(value) => value,
+                None => snake(&v.ident.to_string()),
+            };
+            Ok(Variant {
+                ident: v.ident,
+                value,
+            })
+        })
+        .collect::<Result<_>>()?;
+
+    for (i, x) in variants.iter().enumerate() {
+        if variants[..i].iter().any(|y| y.value == x.value) {
+            return Err(Error::new(
+                x.ident.span(),
+                format!("duplicate Starlark value `{}`", x.value),
+            ));
+        }
+    }
+
+    let idents = variants.iter().map(|v| &v.ident).collect::<Vec<_>>();
+    let values = variants.iter().map(|v| &v.value).collect::<Vec<_>>();
+    let expected = format!(
+        "one of {}",
+        values
+            .iter()
+            .map(|x| format!("`{}`", x))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    let expanded = quote! {
+        impl #name {
+            /// All the Starlark strings this type can be converted from.
+            pub fn values() -> &'static [&'static str] {
+                &[#(#values),*]
+            }
+
+            /// The Starlark string representing this value.
+            pub fn as_str(&self) -> &'static str {
+                match self {
+                    #(Self::#idents => #values),*
+                }
+            }
+        }
+
+        impl<'v> starlark::values::UnpackValue<'v> for #name {
+            fn expected() -> String {
+                #expected.to_owned()
+            }
+
+            fn unpack_value(value: starlark::values::Value<'v>) -> Option<Self> {
+                match value.unpack_str()? {
+                    #(#values => Some(Self::#idents),)*
+                    _ => None,
+                }
+            }
+        }
+
+        impl<'v> starlark::values::AllocValue<'v> for #name {
+            fn alloc_value(self, heap: &'v starlark::values::Heap) -> starlark::values::Value<'v> {
+                heap.alloc_str(self.as_str())
+            }
+        }
+    };
+
+    Ok(expanded)
+}
@@ -85,6 +85,8 @@ pub(crate) struct StarFun {
     pub args: Vec<StarArg>,
     pub return_type: Type,
     pub speculative_exec_safe: bool,
+    /// Is this an `async fn`, whose body is awaited with `Evaluator::await_native`.
+    pub is_async: bool,
     pub body: Block,
     pub source: StarFunSource,
     pub docstring: Option<String>,
@@ -0,0 +1,143 @@
+/*
+ * Copyright 2019 The Starlark in Rust Authors.
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     https://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use proc_macro::TokenStream;
+use proc_macro2::Ident;
+use quote::quote;
+use syn::{
+    parse_macro_input, spanned::Spanned, Attribute, Data, DeriveInput, Error, Fields, GenericParam,
+    Generics, Meta, NestedMeta, Result, Type,
+};
+
+pub fn derive_unpack_value(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_unpack_value_derive(input.data, input.ident, input.generics)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+static STARLARK_UNPACK_ERR_MSG: &str = "valid starlark attributes are {default}";
+
+/// Whether the field is marked `#[starlark(default)]`.
+fn has_default(attrs: &[Attribute]) -> Result<bool> {
+    let attr = match attrs.iter().find(|a| a.path.is_ident("starlark")) {
+        None => return Ok(false),
+        Some(attr) => attr,
+    };
+    match attr.parse_meta()? {
+        Meta::List(lst) => match lst.nested.iter().collect::<Vec<_>>().as_slice() {
+            [NestedMeta::Meta(Meta::Path(p))] if p.is_ident("default") => Ok(true),
+            _ => Err(Error::new(lst.span(), STARLARK_UNPACK_ERR_MSG)),
+        },
+        _ => Err(Error::new(attr.span(), "starlark attr must parse as list")),
+    }
+}
+
+fn is_option(ty: &Type) -> bool {
+    match ty {
+        Type::Path(p) => p
+            .path
+            .segments
+            .last()
+            .map_or(false, |x| x.ident == "Option"),
+        _ => false,
+    }
+}
+
+fn expand_unpack_value_derive(
+    data: Data,
+    name: Ident,
+    generics: Generics,
+) -> Result<proc_macro2::TokenStream> {
+    let fields = match data {
+        Data::Struct(s) => match s.fields {
+            Fields::Named(fields) => Ok(fields.named),
+            fields => Err(Error::new(
+                fields.span(),
+                "#[derive(UnpackValue)] only supports structs with named fields",
+            )),
+        },
+        Data::Enum(e) => Err(Error::new(
+            e.enum_token.span(),
+            "#[derive(UnpackValue)] does not support enums",
+        )),
+        Data::Union(u) => Err(Error::new(
+            u.union_token.span(),
+            "#[derive(UnpackValue)] does not support unions",
+        )),
+    }?;
+
+    // The struct may borrow from the heap, in which case its lifetime is the one we unpack with.
+    let (lifetime, ty) = match generics.params.iter().collect::<Vec<_>>().as_slice() {
+        [] => (quote! { 'v }, quote! { #name }),
+        [GenericParam::Lifetime(l)] => {
+            let l = &l.lifetime;
+            (quote! { #l }, quote! { #name<#l> })
+        }
+        _ => {
+            return Err(Error::new(
+                generics.span(),
+                "#[derive(UnpackValue)] only supports structs with at most one lifetime parameter",
+            ));
+        }
+    };
+
+    let mut names = Vec::with_capacity(fields.len());
+    let mut inits = Vec::with_capacity(fields.len());
+    for field in &fields {
+        let ident = field.ident.as_ref().unwrap();
+        let name = ident.to_string();
+        let init = if has_default(&field.attrs)? {
+            quote! { fields.optional(#name)?.unwrap_or_default() }
+        } else if is_option(&field.ty) {
+            quote! { fields.optional(#name)? }
+        } else {
+            quote! { fields.required(#name)? }
+        };
+        inits.push(quote! { #ident: #init });
+        names.push(name);
+    }
+
+    let expanded = quote! {
+        impl<#lifetime> starlark::values::UnpackValue<#lifetime> for #ty {
+            fn expected() -> String {
+                "dict or struct".to_owned()
+            }
+
+            fn unpack_value(value: starlark::values::Value<#lifetime>) -> Option<Self> {
+                Self::unpack_param(value).ok()
+            }
+
+            fn unpack_param(value: starlark::values::Value<#lifetime>) -> anyhow::Result<Self> {
+                let fields = starlark::values::UnpackFields::new(value, &Self::expected())?;
+                fields.check_names(&[#(#names),*])?;
+                Ok(Self {
+                    #(#inits),*
+                })
+            }
+
+            fn unpack_named_param(
+                value: starlark::values::Value<#lifetime>,
+                _param_name: &str,
+            ) -> anyhow::Result<Self> {
+                Self::unpack_param(value)
+            }
+        }
+    };
+
+    Ok(expanded)
+}
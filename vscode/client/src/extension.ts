@@ -15,7 +15,7 @@
  * limitations under the License.
  */
 
-import { ExtensionContext } from 'vscode';
+import { commands, debug, DebugSession, ExtensionContext, Uri, window, workspace } from 'vscode';
 import {
     LanguageClient,
     LanguageClientOptions,
@@ -24,7 +24,78 @@ import {
 
 let client: LanguageClient;
 
+// Run a file with the interpreter, or call a function in it.
+// Invoked by the code lenses the server provides.
+// The interpreter is started directly with an argument list, not through a shell,
+// so nothing in the path or function name is interpreted by a shell.
+function run(uri: string, func?: string) {
+    const path = Uri.parse(uri).fsPath;
+    const args = func === undefined ? [path] : ['--prelude', path, '-e', func + '()'];
+    const terminal = window.createTerminal({
+        name: 'Starlark',
+        shellPath: 'starlark',
+        shellArgs: args,
+    });
+    terminal.show();
+}
+
+// Debug a file, or a function in it, using our debug adapter.
+function debugFile(uri: string, func?: string) {
+    debug.startDebugging(undefined, {
+        type: 'starlark',
+        request: 'launch',
+        name: func === undefined ? 'Debug file' : `Debug ${func}`,
+        program: Uri.parse(uri).fsPath,
+        function: func,
+    });
+}
+
+// The breakpoints live in the debug adapter, so need a session to export or import them.
+function starlarkSession(): DebugSession | undefined {
+    const session = debug.activeDebugSession;
+    if (session === undefined || session.type !== 'starlark') {
+        window.showErrorMessage('There is no Starlark debug session');
+        return undefined;
+    }
+    return session;
+}
+
+// Save the breakpoints of the debug session to a JSON file, to import later.
+async function exportBreakpoints() {
+    const session = starlarkSession();
+    if (session === undefined) {
+        return;
+    }
+    const uri = await window.showSaveDialog({ filters: { 'Breakpoints': ['json'] } });
+    if (uri === undefined) {
+        return;
+    }
+    const body = await session.customRequest('exportBreakpoints');
+    await workspace.fs.writeFile(uri, Buffer.from(JSON.stringify(body, null, 2)));
+}
+
+// Replace the breakpoints of the debug session with those from a file saved by `exportBreakpoints`.
+async function importBreakpoints() {
+    const session = starlarkSession();
+    if (session === undefined) {
+        return;
+    }
+    const uris = await window.showOpenDialog({ filters: { 'Breakpoints': ['json'] } });
+    if (uris === undefined || uris.length === 0) {
+        return;
+    }
+    const body = JSON.parse(Buffer.from(await workspace.fs.readFile(uris[0])).toString());
+    await session.customRequest('importBreakpoints', body);
+}
+
 export function activate(context: ExtensionContext) {
+    context.subscriptions.push(
+        commands.registerCommand('starlark.run', run),
+        commands.registerCommand('starlark.debug', debugFile),
+        commands.registerCommand('starlark.exportBreakpoints', exportBreakpoints),
+        commands.registerCommand('starlark.importBreakpoints', importBreakpoints),
+    );
+
     // Otherwise to spawn the server
     let serverOptions: ServerOptions = { command: "starlark", args: ["--lsp"] };
 
@@ -32,6 +103,8 @@ export function activate(context: ExtensionContext) {
     let clientOptions: LanguageClientOptions = {
         // Register the server for Starlark documents
         documentSelector: [{ scheme: 'file', language: 'starlark' }],
+        // Send the server our settings, and any changes to them
+        synchronize: { configurationSection: 'starlark' },
     };
 
     // Create the language client and start the client.